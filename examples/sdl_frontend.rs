@@ -0,0 +1,341 @@
+//! Minimal SDL2 frontend.
+//!
+//! Usage: cargo run --example sdl_frontend --features sdl2 -- <bios> <cue>
+//!
+//! Use `hle` as the BIOS to run the experimental HLE BIOS instead of
+//! a BIOS image.
+//!
+//! Uses the `SoftwareRenderer` for the video output and maps the
+//! keyboard and the first game controller to a digital pad in port
+//! 1. The SPU doesn't mix any samples yet so the audio device only
+//! receives silence, it's there to show where the sound output
+//! plugs in.
+//!
+//! Keys:
+//!
+//! * Arrows: D-pad
+//! * X, C, S, D: Cross, Circle, Square, Triangle
+//! * Q, W, A, E: L1, R1, L2, R2
+//! * Return, Backspace: Start, Select
+//! * F1, F3: save and load the state in `rustation.state`
+//! * F5: reset button
+//! * Tab (held): fast forward
+//! * Escape: quit
+
+extern crate rustation;
+extern crate sdl2;
+
+use std::env;
+use std::fs::File;
+use std::path::Path;
+use std::process;
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::Button as SdlButton;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+use rustation::audio::{SPU_SAMPLE_RATE, Frame};
+use rustation::audio::resampler::AudioOutput;
+use rustation::bios::Bios;
+use rustation::cdrom::disc::Disc;
+use rustation::gpu::VideoClock;
+use rustation::gpu::software::SoftwareRenderer;
+use rustation::machine::Machine;
+use rustation::padmemcard::gamepad::{Button, ButtonState, DigitalProfile};
+use rustation::savestate::SaveState;
+
+/// Path of the savestate file used by the hotkeys
+const STATE_PATH: &'static str = "rustation.state";
+
+/// Number of frames run per displayed frame when fast forwarding
+const FAST_FORWARD_FRAMES: u32 = 4;
+
+/// Audio latency in milliseconds
+const AUDIO_LATENCY: u32 = 64;
+
+type SdlMachine = Machine<(), SoftwareRenderer>;
+
+fn keyboard_button(key: Keycode) -> Option<Button> {
+    let b =
+        match key {
+            Keycode::Up => Button::DUp,
+            Keycode::Down => Button::DDown,
+            Keycode::Left => Button::DLeft,
+            Keycode::Right => Button::DRight,
+            Keycode::X => Button::Cross,
+            Keycode::C => Button::Circle,
+            Keycode::S => Button::Square,
+            Keycode::D => Button::Triangle,
+            Keycode::Q => Button::L1,
+            Keycode::W => Button::R1,
+            Keycode::A => Button::L2,
+            Keycode::E => Button::R2,
+            Keycode::Return => Button::Start,
+            Keycode::Backspace => Button::Select,
+            _ => return None,
+        };
+
+    Some(b)
+}
+
+fn controller_button(button: SdlButton) -> Option<Button> {
+    let b =
+        match button {
+            SdlButton::DPadUp => Button::DUp,
+            SdlButton::DPadDown => Button::DDown,
+            SdlButton::DPadLeft => Button::DLeft,
+            SdlButton::DPadRight => Button::DRight,
+            SdlButton::A => Button::Cross,
+            SdlButton::B => Button::Circle,
+            SdlButton::X => Button::Square,
+            SdlButton::Y => Button::Triangle,
+            SdlButton::LeftShoulder => Button::L1,
+            SdlButton::RightShoulder => Button::R1,
+            SdlButton::LeftStick => Button::L3,
+            SdlButton::RightStick => Button::R3,
+            SdlButton::Start => Button::Start,
+            SdlButton::Back => Button::Select,
+            _ => return None,
+        };
+
+    Some(b)
+}
+
+fn set_button(machine: &mut SdlMachine, button: Button, state: ButtonState) {
+    machine.cpu_mut()
+        .interconnect_mut()
+        .pad_memcard_mut()
+        .gamepad_mut(0, 0)
+        .set_button_state(button, state);
+}
+
+fn save_state(machine: &SdlMachine) -> Result<(), String> {
+    let state = try!(machine.save_state().map_err(|e| format!("{:?}", e)));
+    let mut f = try!(File::create(STATE_PATH).map_err(|e| e.to_string()));
+
+    state.write(&mut f).map_err(|e| format!("{:?}", e))
+}
+
+fn load_state(machine: &mut SdlMachine) -> Result<(), String> {
+    let mut f = try!(File::open(STATE_PATH).map_err(|e| e.to_string()));
+    let state = try!(SaveState::read(&mut f).map_err(|e| format!("{:?}", e)));
+
+    machine.load_state(&state).map_err(|e| format!("{:?}", e))
+}
+
+/// Feed one video frame worth of samples to the audio device
+fn queue_audio(output: &mut AudioOutput,
+               queue: &AudioQueue<i16>,
+               fps: u32) {
+    // The SPU doesn't produce any sound yet, send silence
+    let silence = vec![(0, 0); (SPU_SAMPLE_RATE / fps) as usize];
+
+    output.push(&silence);
+
+    let mut frames: Vec<Frame> = vec![(0, 0); silence.len()];
+    let n = output.pull(&mut frames);
+
+    let mut samples = Vec::with_capacity(n * 2);
+
+    for &(l, r) in &frames[..n] {
+        samples.push(l);
+        samples.push(r);
+    }
+
+    queue.queue(&samples);
+}
+
+fn run(bios: &str, cue: &str) -> Result<(), String> {
+    let disc =
+        try!(Disc::from_path(Path::new(cue))
+             .map_err(|e| format!("Can't load disc {}: {}", cue, e)));
+
+    let bios =
+        if bios == "hle" {
+            // Experimental, see `rustation::bios::hle`
+            Bios::hle(disc.region())
+        } else {
+            try!(Bios::load_file(Path::new(bios))
+                 .map_err(|e| format!("Can't load BIOS {}: {:?}", bios, e)))
+        };
+
+    let video_clock = bios.video_clock();
+
+    let mut machine = Machine::new(bios,
+                                   video_clock,
+                                   Some(disc),
+                                   (),
+                                   SoftwareRenderer::new());
+
+    machine.cpu_mut()
+        .interconnect_mut()
+        .pad_memcard_mut()
+        .gamepad_mut(0, 0)
+        .set_profile(Box::new(DigitalProfile::new()));
+
+    let fps =
+        match video_clock {
+            VideoClock::Ntsc => 60,
+            VideoClock::Pal => 50,
+        };
+
+    let sdl = try!(sdl2::init());
+    let video = try!(sdl.video());
+    let audio = try!(sdl.audio());
+    let controllers = try!(sdl.game_controller());
+
+    let window =
+        try!(video.window("Rustation", 640, 480)
+             .position_centered()
+             .resizable()
+             .build()
+             .map_err(|e| e.to_string()));
+
+    let mut canvas =
+        try!(window.into_canvas()
+             .present_vsync()
+             .build()
+             .map_err(|e| e.to_string()));
+
+    let creator = canvas.texture_creator();
+
+    let spec = AudioSpecDesired {
+        freq: Some(SPU_SAMPLE_RATE as i32),
+        channels: Some(2),
+        samples: None,
+    };
+
+    let queue = try!(audio.open_queue::<i16, _>(None, &spec));
+    let mut output = AudioOutput::new(SPU_SAMPLE_RATE, AUDIO_LATENCY);
+
+    queue.resume();
+
+    // Keep the first game controller open, SDL only sends the events
+    // of the opened controllers
+    let _controller =
+        (0..try!(controllers.num_joysticks()))
+        .find(|&i| controllers.is_game_controller(i))
+        .and_then(|i| controllers.open(i).ok());
+
+    let mut events = try!(sdl.event_pump());
+    let mut texture = None;
+    let mut pixels = Vec::new();
+    let mut bytes = Vec::new();
+    let mut fast_forward = false;
+
+    'running: loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. } |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } =>
+                    break 'running,
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } =>
+                    match key {
+                        Keycode::F1 =>
+                            if let Err(e) = save_state(&machine) {
+                                println!("Can't save state: {}", e);
+                            },
+                        Keycode::F3 =>
+                            if let Err(e) = load_state(&mut machine) {
+                                println!("Can't load state: {}", e);
+                            },
+                        Keycode::F5 => machine.set_reset_button(true),
+                        Keycode::Tab => fast_forward = true,
+                        k => if let Some(b) = keyboard_button(k) {
+                            set_button(&mut machine, b, ButtonState::Pressed);
+                        },
+                    },
+                Event::KeyUp { keycode: Some(key), .. } =>
+                    match key {
+                        Keycode::F5 => machine.set_reset_button(false),
+                        Keycode::Tab => fast_forward = false,
+                        k => if let Some(b) = keyboard_button(k) {
+                            set_button(&mut machine, b, ButtonState::Released);
+                        },
+                    },
+                Event::ControllerButtonDown { button, .. } =>
+                    if let Some(b) = controller_button(button) {
+                        set_button(&mut machine, b, ButtonState::Pressed);
+                    },
+                Event::ControllerButtonUp { button, .. } =>
+                    if let Some(b) = controller_button(button) {
+                        set_button(&mut machine, b, ButtonState::Released);
+                    },
+                _ => (),
+            }
+        }
+
+        let frames = if fast_forward { FAST_FORWARD_FRAMES } else { 1 };
+
+        for _ in 0..frames {
+            machine.run_frame();
+        }
+
+        // Don't let the audio queue grow while fast forwarding
+        if !fast_forward {
+            queue_audio(&mut output, &queue, fps);
+        }
+
+        let (width, height) = machine.renderer().frame(&mut pixels);
+
+        if width == 0 || height == 0 {
+            continue;
+        }
+
+        let resolution = (width as u32, height as u32);
+
+        let resized =
+            match texture {
+                Some((_, r)) => r != resolution,
+                None => true,
+            };
+
+        if resized {
+            let t =
+                try!(creator.create_texture_streaming(PixelFormatEnum::RGB888,
+                                                      resolution.0,
+                                                      resolution.1)
+                     .map_err(|e| e.to_string()));
+
+            texture = Some((t, resolution));
+        }
+
+        if let Some((ref mut t, _)) = texture {
+            // RGB888 pixels are 32bit words, this assumes a little
+            // endian host
+            bytes.clear();
+
+            for &p in &pixels {
+                bytes.extend_from_slice(&[p as u8,
+                                          (p >> 8) as u8,
+                                          (p >> 16) as u8,
+                                          0]);
+            }
+
+            try!(t.update(None, &bytes, width as usize * 4)
+                 .map_err(|e| e.to_string()));
+
+            canvas.clear();
+            try!(canvas.copy(t, None, None));
+            canvas.present();
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 3 {
+        println!("Usage: {} <bios> <cue>", args[0]);
+        process::exit(2);
+    }
+
+    if let Err(e) = run(&args[1], &args[2]) {
+        println!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,115 @@
+//! Minimal web frontend, exported as plain C functions to avoid
+//! depending on a JavaScript binding generator.
+//!
+//! Build with:
+//!
+//! cargo build --release --example web --target wasm32-unknown-unknown
+//!
+//! then copy `target/wasm32-unknown-unknown/release/examples/web.wasm`
+//! next to `examples/web.html` and open the page through a web
+//! server. Browsers don't give access to the filesystem so no disc
+//! can be loaded, the BIOS shell (memory card manager and CD player)
+//! runs instead.
+
+extern crate rustation;
+
+use std::mem;
+use std::slice;
+use std::str;
+
+use rustation::bios::Bios;
+use rustation::web::WebFrontend;
+
+/// Allocate `len` bytes in the wasm memory, used by the page to pass
+/// the BIOS image and the key codes
+#[no_mangle]
+pub extern "C" fn rustation_alloc(len: u32) -> *mut u8 {
+    let mut buf = Vec::with_capacity(len as usize);
+    let ptr = buf.as_mut_ptr();
+
+    mem::forget(buf);
+
+    ptr
+}
+
+/// Free a buffer returned by `rustation_alloc`
+#[no_mangle]
+pub unsafe extern "C" fn rustation_free(ptr: *mut u8, len: u32) {
+    drop(Vec::from_raw_parts(ptr, 0, len as usize));
+}
+
+/// Create a frontend from the BIOS image at `bios`. Returns null if
+/// the image is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn rustation_new(bios: *const u8,
+                                       bios_len: u32,
+                                       sample_rate: u32)
+                                       -> *mut WebFrontend {
+    let bios = slice::from_raw_parts(bios, bios_len as usize);
+
+    match Bios::from_slice(bios) {
+        Ok(bios) => {
+            let web = WebFrontend::new(bios, None, sample_rate);
+
+            Box::into_raw(Box::new(web))
+        }
+        Err(_) => 0 as *mut WebFrontend,
+    }
+}
+
+/// `unix_time` is a double since JavaScript numbers can't be passed
+/// as 64bit integers
+#[no_mangle]
+pub unsafe extern "C" fn rustation_set_date(web: *mut WebFrontend,
+                                            unix_time: f64) {
+    (*web).set_date(unix_time as u64);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rustation_run_frame(web: *mut WebFrontend) {
+    (*web).run_frame();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rustation_width(web: *const WebFrontend) -> u32 {
+    (*web).resolution().0 as u32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rustation_height(web: *const WebFrontend) -> u32 {
+    (*web).resolution().1 as u32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rustation_rgba(web: *const WebFrontend)
+                                        -> *const u8 {
+    (*web).rgba().as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rustation_audio(web: *const WebFrontend)
+                                         -> *const f32 {
+    (*web).audio().as_ptr()
+}
+
+/// Number of `f32` values (not stereo frames) in the audio buffer
+#[no_mangle]
+pub unsafe extern "C" fn rustation_audio_len(web: *const WebFrontend)
+                                             -> u32 {
+    (*web).audio().len() as u32
+}
+
+/// Forward a keyboard event, `code` is the UTF-8 encoded
+/// `KeyboardEvent.code`. Returns 1 if the key is mapped.
+#[no_mangle]
+pub unsafe extern "C" fn rustation_key(web: *mut WebFrontend,
+                                       code: *const u8,
+                                       code_len: u32,
+                                       pressed: u32) -> u32 {
+    let code = slice::from_raw_parts(code, code_len as usize);
+
+    match str::from_utf8(code) {
+        Ok(code) => (*web).key_event(code, pressed != 0) as u32,
+        Err(_) => 0,
+    }
+}
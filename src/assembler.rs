@@ -78,6 +78,7 @@ pub mod syntax {
         // Coprocessor opcodes
         Mfc0(Register, u8),
         Mtc0(Register, u8),
+        Rfe,
 
         /// Global labels: can't be redefined
         Global(&'static str),
@@ -671,6 +672,11 @@ impl Assembler {
                                .t(r0)
                                .cop_r(cop_r))
             }
+            Rfe => {
+                self.emit_code(MachineCode::op(0b010000)
+                               .cop_opcode(0b10000)
+                               .imm(0b010000))
+            }
 
             /// Alignment padding
             Align(o) =>
@@ -824,6 +830,7 @@ fn opcodes() {
         (Ori(T5, T5, 0xbeef),         [0xef, 0xbe, 0xad, 0x35]),
         (Break(0x1234),               [0x0d, 0x8d, 0x04, 0x00]),
         (Jal(Label::Absolute(0xabc)), [0xaf, 0x02, 0x00, 0x0c]),
+        (Rfe,                         [0x10, 0x00, 0x00, 0x42]),
     ];
 
     for &(instruction, ref expected) in &tests {
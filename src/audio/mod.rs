@@ -0,0 +1,17 @@
+//! Audio output helpers for frontends.
+//!
+//! The SPU generates stereo samples at 44.1kHz, in lockstep with the
+//! emulated CPU clock. The host's audio device generally runs at a
+//! different rate and its clock isn't synchronized with the video
+//! refresh used to pace the emulator so the samples have to be
+//! resampled and the resampling ratio adjusted on the fly to avoid
+//! buffer underruns (crackling) and overruns (increasing latency).
+
+pub mod resampler;
+pub mod wav;
+
+/// Native sample rate of the SPU output
+pub const SPU_SAMPLE_RATE: u32 = 44_100;
+
+/// Stereo sample: (left, right)
+pub type Frame = (i16, i16);
@@ -0,0 +1,211 @@
+//! Resampling and dynamic rate control.
+//!
+//! The dynamic rate control works by slightly adjusting the
+//! resampling ratio depending on how full the output buffer is: when
+//! the buffer runs low we generate a few more samples, when it fills
+//! up we generate a few less. With a small enough maximum deviation
+//! the pitch change is inaudible. See "Dynamic Rate Control for
+//! Retro Game Emulators" by Hans-Kristian Arntzen for the details.
+
+use std::collections::VecDeque;
+
+use super::{Frame, SPU_SAMPLE_RATE};
+
+/// Linear interpolation resampler
+pub struct Resampler {
+    /// Position of the next output sample between `prev` and the
+    /// next input sample, in the [0, 1[ range
+    pos: f64,
+    /// Last input sample
+    prev: Frame,
+}
+
+impl Resampler {
+    pub fn new() -> Resampler {
+        Resampler {
+            pos: 0.,
+            prev: (0, 0),
+        }
+    }
+
+    /// Resample `input` and append the result to `output`. `ratio`
+    /// is the number of output samples generated per input sample.
+    pub fn process(&mut self,
+                   input: &[Frame],
+                   ratio: f64,
+                   output: &mut VecDeque<Frame>) {
+        let step = 1. / ratio;
+
+        for &sample in input {
+            while self.pos < 1. {
+                output.push_back(lerp(self.prev, sample, self.pos));
+                self.pos += step;
+            }
+
+            self.pos -= 1.;
+            self.prev = sample;
+        }
+    }
+}
+
+fn lerp(a: Frame, b: Frame, t: f64) -> Frame {
+    let l = a.0 as f64 + (b.0 as f64 - a.0 as f64) * t;
+    let r = a.1 as f64 + (b.1 as f64 - a.1 as f64) * t;
+
+    (l.round() as i16, r.round() as i16)
+}
+
+/// Output buffer sitting between the emulator and the host's audio
+/// device
+pub struct AudioOutput {
+    resampler: Resampler,
+    /// Resampled samples waiting to be played
+    buffer: VecDeque<Frame>,
+    /// Host device sample rate
+    host_rate: u32,
+    /// Target buffer size in frames
+    capacity: usize,
+    /// Maximum relative deviation of the resampling ratio used by
+    /// the dynamic rate control
+    max_deviation: f64,
+}
+
+impl AudioOutput {
+    /// Create an output for a host device running at `host_rate`Hz
+    /// with a buffer holding `latency_ms` milliseconds of audio
+    pub fn new(host_rate: u32, latency_ms: u32) -> AudioOutput {
+        let mut output = AudioOutput {
+            resampler: Resampler::new(),
+            buffer: VecDeque::new(),
+            host_rate: host_rate,
+            capacity: 0,
+            max_deviation: DEFAULT_MAX_DEVIATION,
+        };
+
+        output.set_latency(latency_ms);
+
+        output
+    }
+
+    /// Set the size of the output buffer in milliseconds. Bigger
+    /// buffers are less likely to underrun but increase the delay
+    /// between the emulation and the sound.
+    pub fn set_latency(&mut self, latency_ms: u32) {
+        let capacity = (self.host_rate as u64 * latency_ms as u64) / 1000;
+
+        self.capacity = ::std::cmp::max(capacity as usize, 1);
+        self.buffer.reserve(self.capacity * 2);
+    }
+
+    /// Return the configured buffer latency in milliseconds
+    pub fn latency(&self) -> u32 {
+        ((self.capacity as u64 * 1000) / self.host_rate as u64) as u32
+    }
+
+    /// Set the maximum resampling ratio deviation used by the
+    /// dynamic rate control. 0 disables the rate control.
+    pub fn set_max_deviation(&mut self, deviation: f64) {
+        self.max_deviation = deviation;
+    }
+
+    /// Buffer fill level, 0.0 is empty and 1.0 is full
+    pub fn fill_level(&self) -> f64 {
+        self.buffer.len() as f64 / self.capacity as f64
+    }
+
+    /// Return true if the buffer is full. Frontends which use the
+    /// audio to pace the emulation should wait before running the
+    /// next frame in this situation.
+    pub fn buffer_full(&self) -> bool {
+        self.buffer.len() >= self.capacity
+    }
+
+    /// Current resampling ratio, including the dynamic rate control
+    /// adjustment
+    pub fn ratio(&self) -> f64 {
+        let base = self.host_rate as f64 / SPU_SAMPLE_RATE as f64;
+
+        // Generate more samples when the buffer runs low and fewer
+        // samples when it fills up
+        let fill = self.fill_level().min(1.);
+
+        base * (1. + self.max_deviation * (1. - 2. * fill))
+    }
+
+    /// Push samples generated by the SPU, typically once per frame
+    pub fn push(&mut self, samples: &[Frame]) {
+        let ratio = self.ratio();
+
+        self.resampler.process(samples, ratio, &mut self.buffer);
+
+        // If we're really late drop the oldest samples to keep the
+        // latency under control
+        let max_len = self.capacity * 2;
+
+        while self.buffer.len() > max_len {
+            self.buffer.pop_front();
+        }
+    }
+
+    /// Fill `out` with resampled samples, called from the host's
+    /// audio callback. Returns the number of samples actually
+    /// available, the rest of `out` is filled with silence.
+    pub fn pull(&mut self, out: &mut [Frame]) -> usize {
+        let mut n = 0;
+
+        for s in out.iter_mut() {
+            match self.buffer.pop_front() {
+                Some(f) => {
+                    *s = f;
+                    n += 1;
+                }
+                None => *s = (0, 0),
+            }
+        }
+
+        n
+    }
+}
+
+/// Default maximum ratio deviation for the dynamic rate control. A
+/// 0.5% pitch change should be inaudible.
+pub const DEFAULT_MAX_DEVIATION: f64 = 0.005;
+
+#[test]
+fn resample_ratio() {
+    let mut resampler = Resampler::new();
+    let mut out = VecDeque::new();
+
+    let input: Vec<Frame> = vec![(1000, -1000); 44100];
+
+    resampler.process(&input, 48000. / 44100., &mut out);
+
+    let len = out.len() as i64;
+
+    assert!((len - 48000).abs() <= 1);
+    // Once the resampler has settled the signal must be unchanged
+    assert_eq!(out[100], (1000, -1000));
+}
+
+#[test]
+fn dynamic_rate_control() {
+    let mut output = AudioOutput::new(48000, 100);
+
+    assert_eq!(output.latency(), 100);
+
+    // Empty buffer: we should generate more samples than nominal
+    let nominal = 48000. / 44100.;
+
+    assert!(output.ratio() > nominal);
+
+    let silence: Vec<Frame> = vec![(0, 0); 44100];
+
+    output.push(&silence);
+
+    assert!(output.buffer_full());
+    assert!(output.ratio() < nominal);
+
+    let mut out: Vec<Frame> = vec![(1, 1); 16];
+
+    assert_eq!(output.pull(&mut out), 16);
+}
@@ -0,0 +1,203 @@
+//! WAV audio capture, used to dump the SPU output (or individual
+//! voices) to disc for offline analysis.
+
+use std::fs::File;
+use std::io::{self, Write, Seek, SeekFrom, BufWriter};
+use std::path::Path;
+
+use super::{Frame, SPU_SAMPLE_RATE};
+use logging;
+
+/// Streaming 16bit stereo WAV writer. The header is updated with the
+/// final size when the writer is finalized (or dropped).
+pub struct WavWriter {
+    w: BufWriter<File>,
+    /// Number of frames written so far
+    frames: u32,
+    /// Set once the header has been updated
+    finalized: bool,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path) -> io::Result<WavWriter> {
+        let f = try!(File::create(path));
+
+        let mut writer = WavWriter {
+            w: BufWriter::new(f),
+            frames: 0,
+            finalized: false,
+        };
+
+        // Write a placeholder header, the sizes will be fixed in
+        // `finalize`
+        try!(writer.write_header());
+
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let data_len = self.frames * BYTES_PER_FRAME;
+        let rate = SPU_SAMPLE_RATE;
+
+        try!(self.w.write_all(b"RIFF"));
+        try!(write_u32(&mut self.w, 36 + data_len));
+        try!(self.w.write_all(b"WAVE"));
+
+        try!(self.w.write_all(b"fmt "));
+        // Chunk size
+        try!(write_u32(&mut self.w, 16));
+        // PCM
+        try!(write_u16(&mut self.w, 1));
+        // Channels
+        try!(write_u16(&mut self.w, 2));
+        try!(write_u32(&mut self.w, rate));
+        // Byte rate
+        try!(write_u32(&mut self.w, rate * BYTES_PER_FRAME));
+        // Block align
+        try!(write_u16(&mut self.w, BYTES_PER_FRAME as u16));
+        // Bits per sample
+        try!(write_u16(&mut self.w, 16));
+
+        try!(self.w.write_all(b"data"));
+        write_u32(&mut self.w, data_len)
+    }
+
+    /// Append `frames` to the file
+    pub fn write(&mut self, frames: &[Frame]) -> io::Result<()> {
+        for &(l, r) in frames {
+            try!(write_u16(&mut self.w, l as u16));
+            try!(write_u16(&mut self.w, r as u16));
+        }
+
+        self.frames += frames.len() as u32;
+
+        Ok(())
+    }
+
+    /// Number of frames written so far
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Update the header with the final size and flush the file
+    pub fn finalize(mut self) -> io::Result<()> {
+        self.do_finalize()
+    }
+
+    fn do_finalize(&mut self) -> io::Result<()> {
+        if self.finalized {
+            return Ok(());
+        }
+
+        self.finalized = true;
+
+        try!(self.w.seek(SeekFrom::Start(0)));
+        try!(self.write_header());
+        try!(self.w.seek(SeekFrom::End(0)));
+
+        self.w.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.do_finalize() {
+            error!(target: logging::SPU,
+                   "Couldn't finalize WAV file: {}", e);
+        }
+    }
+}
+
+/// Audio capture state: the main SPU mix and/or any of the 24
+/// voices can be recorded to separate files. The SPU feeds the
+/// capture with its output and the frontend or debugger decides what
+/// should be recorded.
+pub struct AudioCapture {
+    /// Capture of the final mix
+    mix: Option<WavWriter>,
+    /// Per-voice captures
+    voices: Vec<Option<WavWriter>>,
+}
+
+impl AudioCapture {
+    pub fn new() -> AudioCapture {
+        AudioCapture {
+            mix: None,
+            voices: (0..VOICE_COUNT).map(|_| None).collect(),
+        }
+    }
+
+    /// Start recording the SPU output to `path`
+    pub fn start_mix(&mut self, path: &Path) -> io::Result<()> {
+        self.mix = Some(try!(WavWriter::create(path)));
+
+        Ok(())
+    }
+
+    /// Start recording voice `voice` (in the range 0...23) to `path`
+    pub fn start_voice(&mut self,
+                       voice: usize,
+                       path: &Path) -> io::Result<()> {
+        self.voices[voice] = Some(try!(WavWriter::create(path)));
+
+        Ok(())
+    }
+
+    /// Stop all the captures, finalizing the files
+    pub fn stop(&mut self) -> io::Result<()> {
+        if let Some(w) = self.mix.take() {
+            try!(w.finalize());
+        }
+
+        for v in self.voices.iter_mut() {
+            if let Some(w) = v.take() {
+                try!(w.finalize());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return true if anything is being recorded
+    pub fn active(&self) -> bool {
+        self.mix.is_some() || self.voices.iter().any(|v| v.is_some())
+    }
+
+    /// Return true if `voice` is being recorded. Lets the SPU skip
+    /// the per-voice output when it's not needed.
+    pub fn voice_active(&self, voice: usize) -> bool {
+        self.voices[voice].is_some()
+    }
+
+    /// Record frames from the final mix
+    pub fn push_mix(&mut self, frames: &[Frame]) -> io::Result<()> {
+        match self.mix {
+            Some(ref mut w) => w.write(frames),
+            None => Ok(()),
+        }
+    }
+
+    /// Record frames from the output of `voice`
+    pub fn push_voice(&mut self,
+                      voice: usize,
+                      frames: &[Frame]) -> io::Result<()> {
+        match self.voices[voice] {
+            Some(ref mut w) => w.write(frames),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Number of voices in the SPU
+pub const VOICE_COUNT: usize = 24;
+
+/// 2 channels, 16bits per sample
+const BYTES_PER_FRAME: u32 = 4;
+
+fn write_u16<W: Write>(w: &mut W, v: u16) -> io::Result<()> {
+    w.write_all(&[v as u8, (v >> 8) as u8])
+}
+
+fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8])
+}
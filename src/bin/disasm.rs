@@ -0,0 +1,84 @@
+//! Objdump-style MIPS disassembler.
+//!
+//! Usage: disasm <file> [--base <addr>] [--symbols <map>]
+//!
+//! Disassembles the raw machine code in `file`, the first instruction
+//! being located at `addr` (hexadecimal, 0 by default). If a symbol
+//! map is provided (see `SymbolTable::load_file` for the supported
+//! formats) the branch targets are replaced by the symbol names and
+//! each symbol gets a label line.
+
+extern crate rustation;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process;
+
+use rustation::cpu::asm::decode_range;
+use rustation::debugger::symbols::{SymbolTable, parse_hex};
+
+fn usage(program: &str) -> ! {
+    println!("Usage: {} <file> [--base <addr>] [--symbols <map>]", program);
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let mut path = None;
+    let mut base = 0;
+    let mut symbols = SymbolTable::new();
+
+    let mut i = 1;
+
+    while i < args.len() {
+        match &args[i][..] {
+            "--base" if i + 1 < args.len() => {
+                base =
+                    match parse_hex(&args[i + 1]) {
+                        Some(b) => b,
+                        None => usage(&args[0]),
+                    };
+                i += 1;
+            }
+            "--symbols" if i + 1 < args.len() => {
+                let map = Path::new(&args[i + 1]);
+
+                if let Err(e) = symbols.load_file(map) {
+                    println!("Can't load {}: {}", map.display(), e);
+                    process::exit(1);
+                }
+                i += 1;
+            }
+            p if path.is_none() && !p.starts_with("--") => path = Some(p),
+            _ => usage(&args[0]),
+        }
+
+        i += 1;
+    }
+
+    let path =
+        match path {
+            Some(p) => p,
+            None => usage(&args[0]),
+        };
+
+    let mut code = Vec::new();
+
+    let r = File::open(path).and_then(|mut f| f.read_to_end(&mut code));
+
+    if let Err(e) = r {
+        println!("Can't read {}: {}", path, e);
+        process::exit(1);
+    }
+
+    for insn in decode_range(&code, base) {
+        if let Some((s, 0)) = symbols.lookup(insn.addr) {
+            println!("\n{:08x} <{}>:", insn.addr, s.name);
+        }
+
+        println!("{}", insn.to_string_with_symbols(&symbols));
+    }
+}
@@ -0,0 +1,122 @@
+//! Headless runner.
+//!
+//! Usage: headless --report <bios> <cue> [frames]
+//!
+//! Boots the disc for `frames` frames (3600 by default, one minute of
+//! NTSC video) without any output and prints a JSON compatibility
+//! report on stdout. See `rustation::report` for its contents. Use
+//! `hle` as the BIOS to run the experimental HLE BIOS instead of a
+//! BIOS image.
+
+extern crate rustation;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use rustation::bios::Bios;
+use rustation::cdrom::disc::Disc;
+use rustation::gpu::renderer::{Renderer, PrimitiveAttributes, Vertex};
+use rustation::machine::Machine;
+use rustation::report::{self, BootTracker};
+
+/// Renderer discarding everything
+struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn set_draw_offset(&mut self, _: i16, _: i16) {
+    }
+
+    fn set_draw_area(&mut self, _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn set_display_mode(&mut self, _: (u16, u16), _: (u16, u16), _: bool) {
+    }
+
+    fn push_line(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 2]) {
+    }
+
+    fn push_triangle(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 3]) {
+    }
+
+    fn push_quad(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 4]) {
+    }
+
+    fn fill_rect(&mut self, _: [u8; 3], _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn load_image(&mut self, _: (u16, u16), _: (u16, u16), _: &[u16]) {
+    }
+
+    fn copy_rect(&mut self, _: (u16, u16), _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn store_image(&mut self,
+                   _: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]) {
+        let len = dimensions.0 as usize * dimensions.1 as usize;
+
+        for p in pixel_buffer[..len].iter_mut() {
+            *p = 0;
+        }
+    }
+}
+
+fn report(bios: &str, cue: &str, frames: u32) {
+    let mut disc =
+        match Disc::from_path(Path::new(cue)) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("Can't load disc {}: {}", cue, e);
+                process::exit(2);
+            }
+        };
+
+    let bios =
+        if bios == "hle" {
+            Bios::hle(disc.region())
+        } else {
+            match Bios::load_file(Path::new(bios)) {
+                Ok(b) => b,
+                Err(e) => {
+                    println!("Can't load BIOS {}: {:?}", bios, e);
+                    process::exit(2);
+                }
+            }
+        };
+
+    // Without the entry point we can still tell if the BIOS boots
+    let entry = disc.boot_entry_point().ok();
+
+    let video_clock = bios.video_clock();
+
+    let mut machine = Machine::new(bios,
+                                   video_clock,
+                                   Some(disc),
+                                   BootTracker::new(entry),
+                                   NullRenderer);
+
+    let report = report::run(&mut machine, frames);
+
+    println!("{}", report.to_json());
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let frames =
+        match args.get(4) {
+            Some(f) => f.parse::<u32>().ok(),
+            None => Some(3600),
+        };
+
+    match (args.len(), args.get(1), frames) {
+        (4...5, Some(mode), Some(frames)) if mode == "--report" =>
+            report(&args[2], &args[3], frames),
+        _ => {
+            println!("Usage: {} --report <bios> <cue> [frames]", args[0]);
+            process::exit(2);
+        }
+    }
+}
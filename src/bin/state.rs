@@ -0,0 +1,49 @@
+//! Savestate inspection tool.
+//!
+//! Usage: state inspect <savestate>
+//!
+//! Prints the inventory of the chunks in the savestate along with
+//! their version and size. Chunks unknown to this version of
+//! rustation or using an unsupported version are flagged, they'd be
+//! skipped when loading the state.
+
+extern crate rustation;
+
+use std::env;
+use std::fs::File;
+use std::process;
+
+use rustation::savestate::{SaveState, FORMAT_VERSION};
+
+fn inspect(path: &str) {
+    let r = File::open(path)
+        .map_err(|e| e.into())
+        .and_then(|mut f| SaveState::read(&mut f));
+
+    let state =
+        match r {
+            Ok(s) => s,
+            Err(e) => {
+                println!("{}: {}", path, e);
+                process::exit(1);
+            }
+        };
+
+    println!("{}: format version {}, {} chunks",
+             path, FORMAT_VERSION, state.chunks().len());
+
+    for c in state.chunks() {
+        println!("  {}", c);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 3 || args[1] != "inspect" {
+        println!("Usage: {} inspect <savestate>", args[0]);
+        process::exit(2);
+    }
+
+    inspect(&args[2]);
+}
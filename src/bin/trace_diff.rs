@@ -0,0 +1,53 @@
+//! Compare a golden trace generated by rustation with a reference
+//! trace and report the first divergence. See
+//! `rustation::debugger::golden` for the trace format.
+//!
+//! Usage: trace_diff <reference trace> <rustation trace>
+
+extern crate rustation;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+use rustation::debugger::golden::{parse_trace, first_divergence, TraceEntry};
+
+fn load_trace(path: &str) -> Vec<TraceEntry> {
+    let mut s = String::new();
+
+    let r = File::open(path).and_then(|mut f| f.read_to_string(&mut s));
+
+    if let Err(e) = r {
+        println!("Can't read {}: {}", path, e);
+        process::exit(2);
+    }
+
+    match parse_trace(&s) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("{}: {}", path, e);
+            process::exit(2);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 3 {
+        println!("Usage: {} <reference trace> <rustation trace>", args[0]);
+        process::exit(2);
+    }
+
+    let expected = load_trace(&args[1]);
+    let actual = load_trace(&args[2]);
+
+    match first_divergence(&expected, &actual) {
+        Some(d) => {
+            println!("{}", d);
+            process::exit(1);
+        }
+        None => println!("Traces match ({} entries)", expected.len()),
+    }
+}
@@ -48,7 +48,11 @@ impl fmt::Debug for Metadata {
 /// Attempt to find the metadata for the given BIOS binary blob.
 /// Returns None if this BIOS is not part of the database.
 pub fn lookup_blob(binary: &[u8; BIOS_SIZE]) -> Option<&'static Metadata> {
+    lookup_sha256(&sha256(binary))
+}
 
+/// Compute the SHA-256 hash of a binary blob
+pub fn sha256(binary: &[u8]) -> [u8; 32] {
     let mut hasher = Sha256::new();
 
     hasher.input(binary);
@@ -57,7 +61,7 @@ pub fn lookup_blob(binary: &[u8; BIOS_SIZE]) -> Option<&'static Metadata> {
 
     hasher.result(&mut sha256);
 
-    lookup_sha256(&sha256)
+    sha256
 }
 
 /// Attempt to find the metadata for the given BIOS SHA-256
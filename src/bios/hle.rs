@@ -0,0 +1,116 @@
+//! ROM image for the high-level BIOS emulation.
+//!
+//! The kernel itself is implemented in Rust in `cpu::hle`, the ROM
+//! only contains a few placeholder instructions at addresses
+//! intercepted by the CPU:
+//!
+//! * The reset vector where the kernel is initialized.
+//!
+//! * A hook slot where a real BIOS would jump to the boot animation,
+//!   so that `ExeLoader::patch_bios` works with the HLE BIOS.
+//!
+//! * The boot slot where the executable named in the disc's
+//!   SYSTEM.CNF is loaded and started.
+//!
+//! If there's nothing to boot the CPU ends up in an infinite loop at
+//! `HALT_OFFSET`.
+
+use cdrom::disc::Region;
+use assembler::Assembler;
+use assembler::syntax::*;
+
+use super::db::Metadata;
+
+macro_rules! metadata {
+    ($name:ident, $checksum:expr, $region:expr) => (
+        pub static $name: Metadata =
+            Metadata {
+                sha256: [$checksum; 32],
+                version_major: 0,
+                version_minor: 0,
+                region: $region,
+                known_bad: false,
+                animation_jump_hook: Some(HOOK_OFFSET),
+                patch_debug_uart: None,
+            };
+    )
+}
+
+// Metadata for the HLE BIOS, one per region since it determines the
+// video standard of the console. Like the shim they get fake
+// checksums.
+metadata!(METADATA_JAPAN, 0xfd, Region::Japan);
+metadata!(METADATA_NORTH_AMERICA, 0xfc, Region::NorthAmerica);
+metadata!(METADATA_EUROPE, 0xfb, Region::Europe);
+
+/// Return the metadata for an HLE BIOS of region `region`
+pub fn metadata_for_region(region: Region) -> &'static Metadata {
+    match region {
+        Region::Japan => &METADATA_JAPAN,
+        Region::NorthAmerica => &METADATA_NORTH_AMERICA,
+        Region::Europe => &METADATA_EUROPE,
+    }
+}
+
+/// Return true if `metadata` belongs to an HLE BIOS. Only compares
+/// the addresses since it's called for every instruction.
+pub fn is_hle_metadata(metadata: &'static Metadata) -> bool {
+    let m = metadata as *const Metadata;
+
+    m == &METADATA_JAPAN as *const Metadata ||
+        m == &METADATA_NORTH_AMERICA as *const Metadata ||
+        m == &METADATA_EUROPE as *const Metadata
+}
+
+/// Return the region of the HLE BIOS with checksum `sha256` or `None`
+/// if it's not one of ours
+pub fn lookup_sha256(sha256: &[u8; 32]) -> Option<Region> {
+    [&METADATA_JAPAN, &METADATA_NORTH_AMERICA, &METADATA_EUROPE]
+        .iter()
+        .find(|m| &m.sha256 == sha256)
+        .map(|m| m.region)
+}
+
+/// Generate the ROM's machine code, to be placed at the start of the
+/// BIOS ROM
+pub fn assemble() -> Vec<u8> {
+    let mut asm = Assembler::from_base(ROM_BASE);
+
+    asm.assemble(&[
+        // Reset vector, the kernel is initialized here
+        Nop,
+        Nop,
+
+        // Hook slot, at `HOOK_OFFSET`. Replaced by a jump to the
+        // executable loader.
+        Nop,
+        Nop,
+
+        // Boot slot, at `BOOT_OFFSET`. The disc's executable is
+        // started from here.
+        Nop,
+        Nop,
+
+        // At `HALT_OFFSET`
+        Global("halt"),
+        B(Label::Global("halt")),
+        Nop,
+    ]).unwrap();
+
+    let (mc, _) = asm.machine_code();
+
+    mc
+}
+
+/// Address of the BIOS ROM as seen by the CPU at reset
+pub const ROM_BASE: u32 = 0xbfc00000;
+
+/// ROM offset of the hook slot
+const HOOK_OFFSET: u32 = 8;
+
+/// ROM offset of the boot slot
+pub const BOOT_OFFSET: u32 = 0x10;
+
+/// ROM offset of the infinite loop reached when there's nothing to
+/// boot
+pub const HALT_OFFSET: u32 = 0x18;
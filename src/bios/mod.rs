@@ -1,11 +1,20 @@
+use std::path::Path;
+use std::fs::File;
+use std::io::{self, Read};
+
 use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
 use memory::Addressable;
 use cdrom::disc::Region;
+use gpu::VideoClock;
+use host::BiosSource;
 
 use self::db::Metadata;
 
 pub mod db;
+pub mod hle;
+pub mod patch;
+pub mod shim;
 
 /// BIOS image
 pub struct Bios {
@@ -31,6 +40,54 @@ impl Bios {
         }
     }
 
+    /// Create a BIOS image from an arbitrary binary blob. Returns an
+    /// error if the blob doesn't have the right size or isn't in the
+    /// database.
+    pub fn from_slice(binary: &[u8]) -> Result<Bios, Error> {
+        if binary.len() != BIOS_SIZE {
+            return Err(Error::BadSize(binary.len()));
+        }
+
+        let sha256 = db::sha256(binary);
+
+        let metadata =
+            match db::lookup_sha256(&sha256) {
+                Some(m) => m,
+                None => return Err(Error::UnknownImage(sha256)),
+            };
+
+        let mut data = box_array![0; BIOS_SIZE];
+
+        data.copy_from_slice(binary);
+
+        Ok(Bios {
+            data: data,
+            metadata: metadata,
+        })
+    }
+
+    /// Load a BIOS image provided by the frontend and validate it
+    /// against the database
+    pub fn from_source(source: &mut BiosSource) -> Result<Bios, Error> {
+        match source.read_bios() {
+            Ok(binary) => Bios::from_slice(&binary),
+            Err(e) => Err(Error::Source(e)),
+        }
+    }
+
+    /// Load a BIOS image from a file and validate it against the
+    /// database
+    pub fn load_file(path: &Path) -> Result<Bios, Error> {
+        let mut f = try!(File::open(path));
+
+        // Read one more byte than needed to detect oversized images
+        let mut binary = Vec::with_capacity(BIOS_SIZE + 1);
+
+        try!(f.by_ref().take(BIOS_SIZE as u64 + 1).read_to_end(&mut binary));
+
+        Bios::from_slice(&binary)
+    }
+
     /// Generate a dummy BIOS that won't work, used for
     /// deserialization and running unit tests
     pub fn dummy() -> Bios {
@@ -49,6 +106,46 @@ impl Bios {
         bios
     }
 
+    /// Generate a minimal kernel able to run bare-metal test
+    /// executables without a real BIOS image, see the `shim` module
+    pub fn shim() -> Bios {
+        let mut bios =
+            Bios {
+                data: box_array![0; BIOS_SIZE],
+                metadata: &shim::METADATA,
+            };
+
+        let code = shim::assemble();
+
+        bios.data[..code.len()].copy_from_slice(&code);
+
+        bios
+    }
+
+    /// Generate the ROM for the high-level BIOS emulation, used to
+    /// run games and homebrew without a BIOS image. `region`
+    /// determines the video standard of the console. See
+    /// `cpu::hle` for the kernel itself.
+    pub fn hle(region: Region) -> Bios {
+        let mut bios =
+            Bios {
+                data: box_array![0; BIOS_SIZE],
+                metadata: hle::metadata_for_region(region),
+            };
+
+        let code = hle::assemble();
+
+        bios.data[..code.len()].copy_from_slice(&code);
+
+        bios
+    }
+
+    /// Return true if this is the ROM of the high-level BIOS
+    /// emulation
+    pub fn is_hle(&self) -> bool {
+        hle::is_hle_metadata(self.metadata)
+    }
+
     /// Attempt to modify the BIOS ROM to remove the call to the code
     /// responsible for the boot logo animations (SCEx/PS) and
     /// directly boot the game. This can break some games!  Returns
@@ -104,10 +201,39 @@ impl Bios {
         r
     }
 
+    /// Store the little endian value `val` at `offset`. Only used
+    /// when the BIOS is made writable for runtime patches.
+    pub fn store<T: Addressable>(&mut self, offset: u32, val: u32) {
+        let offset = offset as usize;
+
+        for i in 0..T::size() as usize {
+            self.data[offset + i] = (val >> (8 * i)) as u8;
+        }
+    }
+
     /// Return a static pointer to the BIOS's Metadata
     pub fn metadata(&self) -> &'static Metadata {
         self.metadata
     }
+
+    /// Return the region of the console this BIOS was dumped from
+    pub fn region(&self) -> Region {
+        self.metadata.region
+    }
+
+    /// Return the BIOS version as a `(major, minor)` pair
+    pub fn version(&self) -> (u8, u8) {
+        (self.metadata.version_major, self.metadata.version_minor)
+    }
+
+    /// Return the video standard used by consoles running this
+    /// BIOS: PAL for European BIOSes, NTSC otherwise.
+    pub fn video_clock(&self) -> VideoClock {
+        match self.metadata.region {
+            Region::Europe => VideoClock::Pal,
+            Region::Japan | Region::NorthAmerica => VideoClock::Ntsc,
+        }
+    }
 }
 
 impl Encodable for Bios {
@@ -142,6 +268,17 @@ impl Decodable for Bios {
                 *b = try!(d.read_seq_elt(i, |d| Decodable::decode(d)))
             }
 
+            if sha256 == shim::METADATA.sha256 {
+                // The shim doesn't depend on any external file, we can
+                // rebuild it directly
+                return Ok(Bios::shim());
+            }
+
+            if let Some(region) = hle::lookup_sha256(&sha256) {
+                // Same thing for the HLE BIOS
+                return Ok(Bios::hle(region));
+            }
+
             let meta =
                 match db::lookup_sha256(&sha256) {
                     Some(m) => m,
@@ -159,6 +296,28 @@ impl Decodable for Bios {
     }
 }
 
+/// Error returned when loading a BIOS image fails
+#[derive(Debug)]
+pub enum Error {
+    /// Error while reading the BIOS file
+    IoError(io::Error),
+    /// Error reported by a `BiosSource`
+    Source(String),
+    /// BIOS images must be exactly `BIOS_SIZE` bytes long, this
+    /// contains the actual size of the image (possibly truncated to
+    /// `BIOS_SIZE + 1` for oversized files).
+    BadSize(usize),
+    /// The image is not in the BIOS database, this contains its
+    /// SHA-256 hash.
+    UnknownImage([u8; 32]),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
 /// Dummy metadata used as a placeholder for dummy BIOS instances
 static DUMMY_METADATA: Metadata =
     Metadata {
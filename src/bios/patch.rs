@@ -0,0 +1,62 @@
+//! Optional modifications applied in-memory to the BIOS image. The
+//! offsets to patch depend on the BIOS version and are stored in the
+//! database `Metadata`.
+
+use super::Bios;
+
+/// Set of patches to apply to a BIOS image
+#[derive(Clone, Copy, Debug)]
+pub struct Patcher {
+    /// Skip the boot logo animation and jump straight into the game
+    fast_boot: bool,
+    /// Force-enable the BIOS TTY output on the debug UART
+    enable_tty: bool,
+}
+
+impl Patcher {
+    /// Create a patcher that doesn't modify anything
+    pub fn new() -> Patcher {
+        Patcher {
+            fast_boot: false,
+            enable_tty: false,
+        }
+    }
+
+    /// Enable or disable the fast boot patch
+    pub fn fast_boot(mut self, enable: bool) -> Patcher {
+        self.fast_boot = enable;
+        self
+    }
+
+    /// Enable or disable the TTY patch
+    pub fn enable_tty(mut self, enable: bool) -> Patcher {
+        self.enable_tty = enable;
+        self
+    }
+
+    /// Apply the selected patches to `bios`. If one of the patches is
+    /// not available for this BIOS version an error is returned,
+    /// however the other patches are still applied.
+    pub fn apply(&self, bios: &mut Bios) -> Result<(), Error> {
+        let mut res = Ok(());
+
+        if self.enable_tty && bios.enable_debug_uart().is_err() {
+            res = Err(Error::TtyUnsupported);
+        }
+
+        if self.fast_boot && bios.patch_boot_animation().is_err() {
+            res = Err(Error::FastBootUnsupported);
+        }
+
+        res
+    }
+}
+
+/// Error returned when a patch can't be applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// We don't know how to skip the boot animation for this BIOS
+    FastBootUnsupported,
+    /// We don't know how to enable the TTY for this BIOS
+    TtyUnsupported,
+}
@@ -0,0 +1,304 @@
+//! Minimal replacement kernel used to run bare-metal test programs
+//! without a BIOS image.
+//!
+//! The shim only provides what a self-contained test executable
+//! expects to find when it's started:
+//!
+//! * The exception vector. Syscalls implement EnterCriticalSection
+//!   and ExitCriticalSection, interrupts are acknowledged and
+//!   ignored, any other exception hangs the CPU in `fatal`.
+//!
+//! * The A0, B0 and C0 kernel call vectors. `putchar` writes to the
+//!   debug UART, `puts` and `printf` print the string one `putchar`
+//!   at a time through the B0 vector so the TTY capture in the
+//!   debugger works as with a real BIOS. Note that `printf` prints the
+//!   format string verbatim, the conversions are not expanded. The
+//!   event functions pretend to succeed immediately, everything else
+//!   returns 0.
+//!
+//! * A hook slot where a real BIOS would jump to the boot animation,
+//!   so that `ExeLoader::patch_bios` works with the shim.
+//!
+//! Everything is generated with our assembler so the shim is
+//! deterministic and doesn't depend on any copyrighted code.
+
+use cdrom::disc::Region;
+use assembler::Assembler;
+use assembler::syntax::*;
+use memory::map;
+
+use super::db::Metadata;
+
+/// Metadata for the shim. It's not a real BIOS so it gets its own
+/// fake checksum.
+pub static METADATA: Metadata =
+    Metadata {
+        sha256: [0xfe; 32],
+        version_major: 0,
+        version_minor: 0,
+        region: Region::NorthAmerica,
+        known_bad: false,
+        animation_jump_hook: Some(HOOK_OFFSET),
+        patch_debug_uart: None,
+    };
+
+/// Generate the shim's machine code, to be placed at the start of
+/// the BIOS ROM
+pub fn assemble() -> Vec<u8> {
+    let mut asm = Assembler::from_base(ROM_BASE);
+
+    let irq_base = map::IRQ_CONTROL.0;
+    let uart_base = map::EXPANSION_2.0;
+
+    asm.assemble(&[
+        // Reset vector
+        Jal(Label::Global("init")),
+        Nop,
+
+        // Hook slot, at `HOOK_OFFSET`. Replaced by a jump to the
+        // executable loader.
+        Nop,
+        Nop,
+
+        Global("halt"),
+        B(Label::Global("halt")),
+        Nop,
+
+        Global("init"),
+        // Mask and acknowledge all interrupts
+        Li(T0, irq_base),
+        Sh(R0, T0, 4),
+        Sh(R0, T0, 0),
+
+        // Clear BEV to use the exception vector in RAM, disable the
+        // interrupts
+        Mtc0(R0, 12),
+        Mtc0(R0, 13),
+
+        Li(SP, KERNEL_SP),
+
+        // Copy the vectors to RAM
+        La(T0, Label::Global("vectors")),
+        Li(T1, VECTORS_ADDRESS),
+        Li(T2, VECTORS_LEN),
+
+        Local("copy_vectors"),
+        Lw(T3, T0, 0),
+        Addiu(T0, T0, 4),
+        Addiu(T2, T2, -4),
+        Sw(T3, T1, 0),
+        Bnez(T2, Label::Local("copy_vectors", 'b')),
+        Addiu(T1, T1, 4),
+
+        Jr(RA),
+        Nop,
+
+        // Vectors copied to `VECTORS_ADDRESS`, each one is 16 bytes
+        // long
+        Global("vectors"),
+        // 0x80: exception vector
+        La(K0, Label::Global("exception_handler")),
+        Jr(K0),
+        Nop,
+        // 0x90: unused
+        Nop,
+        Nop,
+        Nop,
+        Nop,
+        // 0xa0: A functions
+        La(T0, Label::Global("a_functions")),
+        Jr(T0),
+        Nop,
+        // 0xb0: B functions
+        La(T0, Label::Global("b_functions")),
+        Jr(T0),
+        Nop,
+        // 0xc0: C functions
+        La(T0, Label::Global("c_functions")),
+        Jr(T0),
+        Nop,
+
+        Global("exception_handler"),
+        // Only K0 and K1 can be used here
+        Mfc0(K0, 13),
+        Nop,
+        Andi(K0, K0, 0x7c),
+        Beqz(K0, Label::Local("interrupt", 'f')),
+        // Syscall exception code
+        Li(K1, 8 << 2),
+        Bne(K0, K1, Label::Global("fatal")),
+        Nop,
+
+        // Syscall: the function number is in A0
+        Mfc0(K1, 12),
+        Li(K0, 1),
+        Bne(A0, K0, Label::Local("not_enter", 'f')),
+        Li(K0, 2),
+        // EnterCriticalSection: clear the interrupt enable bit that
+        // RFE will restore
+        Addiu(K0, R0, -5),
+        B(Label::Local("syscall_done", 'f')),
+        And(K1, K1, K0),
+
+        Local("not_enter"),
+        Bne(A0, K0, Label::Local("syscall_done", 'f')),
+        Nop,
+        // ExitCriticalSection: set the interrupt enable bit and
+        // unmask the hardware interrupts
+        Ori(K1, K1, 0x404),
+
+        Local("syscall_done"),
+        Mtc0(K1, 12),
+        // Return after the syscall instruction
+        Mfc0(K0, 14),
+        Nop,
+        Addiu(K0, K0, 4),
+        Jr(K0),
+        Rfe,
+
+        Local("interrupt"),
+        // We don't support the event handlers, acknowledge everything
+        Li(K0, irq_base),
+        Sh(R0, K0, 0),
+        Mfc0(K0, 14),
+        Nop,
+        Jr(K0),
+        Rfe,
+
+        // Unhandled exception, hang here
+        Global("fatal"),
+        B(Label::Global("fatal")),
+        Nop,
+
+        // The function number is in T1, T0 is free to use
+        Global("a_functions"),
+        Li(T0, 0x3c),
+        Beq(T1, T0, Label::Global("putchar")),
+        Li(T0, 0x3e),
+        Beq(T1, T0, Label::Global("puts")),
+        Li(T0, 0x3f),
+        Beq(T1, T0, Label::Global("print")),
+        Nop,
+        B(Label::Global("return_zero")),
+        Nop,
+
+        Global("b_functions"),
+        Li(T0, 0x3d),
+        Beq(T1, T0, Label::Global("putchar")),
+        Li(T0, 0x3f),
+        Beq(T1, T0, Label::Global("puts")),
+        Li(T0, 0x08),
+        Beq(T1, T0, Label::Global("open_event")),
+        // Other event functions: 0x07 to 0x0d
+        Addiu(T0, T1, -0x07),
+        Sltiu(T0, T0, 0x07),
+        Bnez(T0, Label::Global("return_one")),
+        Nop,
+        B(Label::Global("return_zero")),
+        Nop,
+
+        Global("c_functions"),
+        B(Label::Global("return_zero")),
+        Nop,
+
+        Global("return_zero"),
+        Jr(RA),
+        Move(V0, R0),
+
+        Global("return_one"),
+        Jr(RA),
+        Li(V0, 1),
+
+        Global("open_event"),
+        // Event descriptor
+        Li(V0, 0xf1000000),
+        Jr(RA),
+        Nop,
+
+        Global("putchar"),
+        Li(T0, uart_base),
+        // Debug UART TX register
+        Sb(A0, T0, 0x23),
+        Jr(RA),
+        Move(V0, A0),
+
+        // Print the string in A0 without a trailing newline
+        Global("print"),
+        Addiu(SP, SP, -8),
+        Sw(RA, SP, 4),
+        Move(T2, A0),
+
+        Local("print_loop"),
+        Lbu(A0, T2, 0),
+        Nop,
+        Beqz(A0, Label::Local("print_done", 'f')),
+        Addiu(T2, T2, 1),
+        // Call putchar through the B0 vector
+        Li(T0, 0xb0),
+        Jalr(RA, T0),
+        Li(T1, 0x3d),
+        B(Label::Local("print_loop", 'b')),
+        Nop,
+
+        Local("print_done"),
+        Lw(RA, SP, 4),
+        Nop,
+        Jr(RA),
+        Addiu(SP, SP, 8),
+
+        Global("puts"),
+        Addiu(SP, SP, -8),
+        Sw(RA, SP, 4),
+        Jal(Label::Global("print")),
+        Nop,
+        Li(A0, b'\n' as u32),
+        Li(T0, 0xb0),
+        Jalr(RA, T0),
+        Li(T1, 0x3d),
+        Lw(RA, SP, 4),
+        Nop,
+        Jr(RA),
+        Addiu(SP, SP, 8),
+    ]).unwrap();
+
+    let (mc, _) = asm.machine_code();
+
+    mc
+}
+
+/// Address of the BIOS ROM as seen by the CPU at reset
+const ROM_BASE: u32 = 0xbfc00000;
+
+/// ROM offset of the hook slot
+const HOOK_OFFSET: u32 = 8;
+
+/// Where the vectors are copied in RAM
+const VECTORS_ADDRESS: u32 = 0x80000080;
+
+/// Length of the vector area in bytes
+const VECTORS_LEN: u32 = 0x50;
+
+/// Stack used by the shim before the executable takes over
+const KERNEL_SP: u32 = 0x801fff00;
+
+#[test]
+fn shim_boot() {
+    use cpu::test_machine::TestMachine;
+    use memory::Word;
+
+    use super::Bios;
+
+    let mut m = TestMachine::new();
+
+    m.cpu.interconnect_mut().set_bios(Bios::shim());
+    m.cpu.set_pc(ROM_BASE);
+
+    // Run the init code up to the hook slot
+    m.run_until(0x0fc00000 + HOOK_OFFSET);
+
+    // The vectors must have been copied to RAM: lui t0, 0xbfc0
+    assert_eq!(m.read::<Word>(0xb0), 0x3c08bfc0);
+    assert_eq!(m.read::<Word>(0xc0), 0x3c08bfc0);
+
+    assert_eq!(m.cpu.regs()[29], KERNEL_SP);
+}
@@ -1,13 +1,24 @@
 use std::fmt;
+use std::path::Path;
 
 use cdimage::{Image, CdError};
 use cdimage::msf::Msf;
 use cdimage::bcd::Bcd;
 use cdimage::sector::Sector;
+use cdimage::cue::Cue;
 
 use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
 use super::iso9660;
+use super::pbp::Pbp;
+use logging;
+
+/// Container-agnostic disc image interface. The CD-ROM controller
+/// only ever deals with `Disc`s built on top of this trait so new
+/// container formats only need to implement it. BIN/CUE images are
+/// handled by the `cdimage` crate, PBP files by `pbp::Pbp`. CHD isn't
+/// supported yet.
+pub use cdimage::Image as DiscImage;
 
 /// PlayStation disc.
 ///
@@ -15,14 +26,17 @@ use super::iso9660;
 /// be very hard either. We need to support audio tracks anyway...
 pub struct Disc {
     /// Image file
-    image: Box<Image>,
+    image: Box<DiscImage>,
     /// Disc serial number
     serial: SerialNumber,
+    /// Absolute position of the first sector (index 01) of each
+    /// track, track 01 first
+    tracks: Vec<Msf>,
 }
 
 impl Disc {
     /// Reify a disc using `image` as a backend.
-    pub fn new(mut image: Box<Image>) -> Result<Disc, String> {
+    pub fn new(mut image: Box<DiscImage>) -> Result<Disc, String> {
         let serial =
             match extract_serial_number(&mut *image) {
                 Some(s) => s,
@@ -31,14 +45,41 @@ impl Disc {
                 }
             };
 
+        let tracks = read_track_list(&mut *image);
+
         let disc = Disc {
             image: image,
             serial: serial,
+            tracks: tracks,
         };
 
         Ok(disc)
     }
 
+    /// Load a disc from a CUE sheet or a PBP file
+    pub fn from_path(path: &Path) -> Result<Disc, String> {
+        let pbp =
+            match path.extension() {
+                Some(e) => e.to_string_lossy().to_lowercase() == "pbp",
+                None => false,
+            };
+
+        if pbp {
+            let image = try!(Pbp::new(path));
+
+            return Disc::new(Box::new(image));
+        }
+
+        let image =
+            match Cue::new(path) {
+                Ok(c) => c,
+                Err(e) => return Err(format!("Couldn't load {}: {:?}",
+                                             path.display(), e)),
+            };
+
+        Disc::new(Box::new(image))
+    }
+
     pub fn region(&self) -> Region {
         // For now I prefer to panic to catch potential issues with
         // the serial number handling code, alternatively we could
@@ -53,9 +94,154 @@ impl Disc {
         self.serial
     }
 
-    pub fn image(&mut self) -> &mut Image {
+    pub fn image(&mut self) -> &mut DiscImage {
         &mut*self.image
     }
+
+    /// Number of tracks on the disc. Mixed-mode discs have a data
+    /// track followed by one or more audio tracks.
+    pub fn track_count(&self) -> u8 {
+        self.tracks.len() as u8
+    }
+
+    /// Absolute position of the start (index 01) of `track`. Tracks
+    /// are numbered from 1.
+    pub fn track_start(&self, track: u8) -> Option<Msf> {
+        if track == 0 {
+            None
+        } else {
+            self.tracks.get(track as usize - 1).cloned()
+        }
+    }
+
+    /// Position of `msf` relative to the start of its track as
+    /// reported by the Q subchannel. If `msf` is in a pregap (index
+    /// 00) the relative position counts down towards the start of the
+    /// track instead of up.
+    pub fn track_relative_msf(&self, msf: Msf, pregap: bool) -> Option<Msf> {
+        if pregap {
+            // The pregap belongs to the next track
+            self.tracks.iter()
+                .find(|&&start| start > msf)
+                .and_then(|&start| msf_distance(msf, start))
+        } else {
+            self.tracks.iter()
+                .rev()
+                .find(|&&start| start <= msf)
+                .and_then(|&start| msf_distance(start, msf))
+        }
+    }
+
+    /// Read and parse the disc's SYSTEM.CNF
+    pub fn system_cnf(&mut self) -> Result<SystemCnf, iso9660::Error> {
+        let raw = try!(read_system_cnf(&mut *self.image));
+
+        Ok(SystemCnf::parse(&raw))
+    }
+
+    /// List all the files in the disc's filesystem. Meant for
+    /// debugging.
+    pub fn list_files(&mut self)
+                      -> Result<Vec<iso9660::FileInfo>, iso9660::Error> {
+        iso9660::list_files(&mut *self.image)
+    }
+
+    /// Return the entry point of the executable started by the BIOS
+    /// (the BOOT entry of SYSTEM.CNF)
+    pub fn boot_entry_point(&mut self) -> Result<u32, iso9660::Error> {
+        let exe = try!(self.read_boot_executable());
+
+        // The entry point is at offset 0x10 in the header
+        let pc = exe[0x10] as u32 |
+                 (exe[0x11] as u32) << 8 |
+                 (exe[0x12] as u32) << 16 |
+                 (exe[0x13] as u32) << 24;
+
+        Ok(pc)
+    }
+
+    /// Read the PS-X EXE executable started by the BIOS (the BOOT
+    /// entry of SYSTEM.CNF)
+    pub fn read_boot_executable(&mut self)
+                                -> Result<Vec<u8>, iso9660::Error> {
+        let cnf = try!(self.system_cnf());
+
+        let boot =
+            match cnf.boot() {
+                Some(b) => b.to_uppercase(),
+                None => {
+                    let desc = "SYSTEM.CNF has no BOOT entry".into();
+                    return Err(iso9660::Error::BadFormat(desc));
+                }
+            };
+
+        read_executable(&mut *self.image, &boot)
+    }
+}
+
+/// Parsed contents of the SYSTEM.CNF file found at the root of
+/// PlayStation discs
+#[derive(Clone, Debug)]
+pub struct SystemCnf {
+    /// List of `KEY = VALUE` pairs in the order they appear in the
+    /// file
+    entries: Vec<(String, String)>,
+}
+
+impl SystemCnf {
+    pub fn parse(raw: &[u8]) -> SystemCnf {
+        let mut entries = Vec::new();
+
+        for line in raw.split(|&b| b == b'\n') {
+            let words: Vec<_> = line
+                .split(|&b| b == b' ' || b == b'\t' || b == b'=' || b == b'\r')
+                .filter(|w| !w.is_empty())
+                .collect();
+
+            if words.len() == 2 {
+                let key = String::from_utf8_lossy(words[0]).into_owned();
+                let val = String::from_utf8_lossy(words[1]).into_owned();
+
+                entries.push((key, val));
+            }
+        }
+
+        SystemCnf {
+            entries: entries,
+        }
+    }
+
+    /// Return the value associated with `key`, if any
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|&&(ref k, _)| k == key)
+            .map(|&(_, ref v)| v.as_ref())
+    }
+
+    /// Path of the boot executable, should look like
+    /// `cdrom:\FOO\BAR\...\aaaa_ddd.dd;1`
+    pub fn boot(&self) -> Option<&str> {
+        self.get("BOOT")
+    }
+
+    /// Name of the boot executable file, without the path and
+    /// version number
+    pub fn boot_executable(&self) -> Option<&str> {
+        let boot = match self.boot() {
+            Some(b) => b,
+            None => return None,
+        };
+
+        let path: Vec<_> = boot
+            .split(|c| c == ':' || c == ';' || c == '\\')
+            .collect();
+
+        if path.len() < 2 {
+            None
+        } else {
+            Some(path[path.len() - 2])
+        }
+    }
 }
 
 impl Encodable for Disc {
@@ -73,6 +259,7 @@ impl Decodable for Disc {
         Ok(Disc {
             image: Box::new(MissingImage),
             serial: serial,
+            tracks: Vec::new(),
         })
     }
 }
@@ -175,6 +362,12 @@ impl fmt::Display for SerialNumber {
     }
 }
 
+impl fmt::Debug for SerialNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 /// Attempt to discover the region of the disc using the license
 /// string stored in the system area of the official PlayStation
 /// ISO filesystem.
@@ -217,7 +410,8 @@ pub fn extract_system_region(image: &mut Image) -> Result<Region, CdError> {
             "LicensedbySonyComputerEntertainmentEurope"
                 => Region::Europe,
             _ => {
-                warn!("Couldn't identify disc region string: {}", license);
+                warn!(target: logging::CDROM,
+                      "Couldn't identify disc region string: {}", license);
                 return Err(CdError::BadFormat);
             }
         };
@@ -233,57 +427,74 @@ fn extract_serial_number(image: &mut Image) -> Option<SerialNumber> {
         match read_system_cnf(image) {
             Ok(c) => c,
             Err(e) => {
-                warn!("Couldn't read SYSTEM.CNF: {:?}", e);
+                warn!(target: logging::CDROM,
+                      "Couldn't read SYSTEM.CNF: {:?}", e);
                 return None;
             }
         };
 
-    // Now we need to parse the SYSTEM.CNF file to get the content of
-    // the "BOOT" line
-    let mut boot_path = None;
-
-    for line in system_cnf.split(|&b| b == b'\n') {
-        let words: Vec<_> = line
-            .split(|&b| b == b' ' || b == b'\t' || b == b'=')
-            .filter(|w| !w.is_empty())
-            .collect();
-
-        if words.len() == 2 {
-            if words[0] == b"BOOT" {
-                boot_path = Some(words[1]);
-                break;
-            }
-        }
-    }
+    let system_cnf = SystemCnf::parse(&system_cnf);
 
-    let boot_path =
-        match boot_path {
+    let bin_name =
+        match system_cnf.boot_executable() {
             Some(b) => b,
             None => {
-                warn!("Couldn't find BOOT line in SYSTEM.CNF");
+                warn!(target: logging::CDROM,
+                      "Unexpected boot path in SYSTEM.CNF: {:?}",
+                      system_cnf.boot());
                 return None;
             }
         };
 
-    // boot_path should look like "cdrom:\FOO\BAR\...\aaaa_ddd.dd;1"
-    let path: Vec<_> = boot_path
-        .split(|&b| b == b':' || b == b';' || b == b'\\')
-        .collect();
+    let serial = SerialNumber::from_bin_name(bin_name.as_bytes());
 
-    if path.len() < 2 {
-        warn!("Unexpected boot path: {}", String::from_utf8_lossy(boot_path));
-        return None;
+    if serial.is_none() {
+        warn!(target: logging::CDROM,
+              "Unexpected bin name: {}", bin_name);
     }
 
-    let bin_name = path[path.len() - 2];
+    serial
+}
 
-    let serial = SerialNumber::from_bin_name(&bin_name);
+/// Build the list of track start positions. The cue sheet can spread
+/// the tracks across several files and each track can have a pregap
+/// (index 00) which may or may not be stored in the image, the
+/// `Image` takes care of all that and only returns the absolute
+/// position of index 01.
+fn read_track_list(image: &mut Image) -> Vec<Msf> {
+    let mut tracks = Vec::new();
+
+    for track in 1..100 {
+        let bcd =
+            match Bcd::from_bcd(::rtc::to_bcd(track)) {
+                Some(b) => b,
+                None => break,
+            };
 
-    if serial.is_none() {
-        warn!("Unexpected bin name: {}", String::from_utf8_lossy(bin_name));
+        match image.track_msf(bcd, Msf::zero()) {
+            Ok(msf) => tracks.push(msf),
+            // No more tracks
+            Err(_) => break,
+        }
     }
 
-    serial
+    if tracks.is_empty() {
+        warn!(target: logging::CDROM, "Couldn't find any track on the disc");
+    }
+
+    tracks
+}
+
+/// Return `to - from` as an MSF or None if `to` is before `from`
+pub fn msf_distance(from: Msf, to: Msf) -> Option<Msf> {
+    let from = from.sector_index();
+    let to = to.sector_index();
+
+    if to < from {
+        None
+    } else {
+        Msf::from_sector_index(to - from)
+    }
 }
 
 fn read_system_cnf(image: &mut Image) -> Result<Vec<u8>, iso9660::Error> {
@@ -302,3 +513,63 @@ fn read_system_cnf(image: &mut Image) -> Result<Vec<u8>, iso9660::Error> {
 
     system_cnf.read_file(image)
 }
+
+/// Read the executable at `boot` (a path like `cdrom:\FOO\BAR.EXE;1`)
+/// and check that it looks like a PS-X EXE
+fn read_executable(image: &mut Image,
+                   boot: &str) -> Result<Vec<u8>, iso9660::Error> {
+    // Strip the device name
+    let path =
+        match boot.find(':') {
+            Some(p) => &boot[p + 1..],
+            None => boot,
+        };
+
+    let mut components: Vec<_> =
+        path.split('\\').filter(|c| !c.is_empty()).collect();
+
+    let file =
+        match components.pop() {
+            Some(f) => f,
+            None => {
+                let desc = format!("Invalid BOOT path: {}", boot);
+                return Err(iso9660::Error::BadFormat(desc));
+            }
+        };
+
+    let mut dir = try!(iso9660::open_image(image));
+
+    for c in components {
+        dir = try!(dir.cd(image, c.as_bytes()));
+    }
+
+    // The version number is sometimes omitted
+    let name =
+        if file.contains(';') {
+            file.to_string()
+        } else {
+            format!("{};1", file)
+        };
+
+    let exe = try!(try!(dir.entry_by_name(name.as_bytes())).read_file(image));
+
+    if exe.len() < 0x800 || &exe[0..8] != b"PS-X EXE" {
+        let desc = format!("{} is not a PlayStation executable", boot);
+        return Err(iso9660::Error::BadFormat(desc));
+    }
+
+    Ok(exe)
+}
+
+#[test]
+fn pregap_distance() {
+    let msf = |m, s, f| Msf::from_bcd(m, s, f).unwrap();
+
+    // Track 01 starts at 00:02:00, the pregap counts down towards it
+    assert_eq!(msf_distance(msf(0x00, 0x01, 0x25), msf(0x00, 0x02, 0x00)),
+               Some(msf(0x00, 0x00, 0x50)));
+    assert_eq!(msf_distance(msf(0x00, 0x02, 0x00), msf(0x01, 0x00, 0x10)),
+               Some(msf(0x00, 0x58, 0x10)));
+    assert_eq!(msf_distance(msf(0x00, 0x02, 0x01), msf(0x00, 0x02, 0x00)),
+               None);
+}
@@ -136,6 +136,14 @@ impl Entry {
         &self.0[33..name_end]
     }
 
+    /// Return true if this is the "." or ".." entry. Those are
+    /// encoded as a single 0 or 1 byte.
+    pub fn is_special(&self) -> bool {
+        let name = self.name();
+
+        name == b"\0" || name == b"\x01"
+    }
+
     pub fn is_dir(&self) -> bool {
         let flags = self.0[25];
 
@@ -272,6 +280,59 @@ pub fn open_image(image: &mut Image) -> Result<Directory, Error> {
     Directory::new(image, &root_dir)
 }
 
+/// Description of a file or directory returned by `list_files`
+#[derive(Clone, Debug)]
+pub struct FileInfo {
+    /// Full path of the file, using the PlayStation syntax
+    /// `\DIR\FILE;1`
+    pub path: String,
+    /// Size of the file in bytes
+    pub size: u32,
+    /// True if the entry is a directory
+    pub is_dir: bool,
+}
+
+/// Recursively list all the files and directories in the image's
+/// filesystem. Meant for debugging.
+pub fn list_files(image: &mut Image) -> Result<Vec<FileInfo>, Error> {
+    let root = try!(open_image(image));
+
+    let mut files = Vec::new();
+
+    try!(list_dir(image, &root, "", &mut files));
+
+    Ok(files)
+}
+
+fn list_dir(image: &mut Image,
+            dir: &Directory,
+            prefix: &str,
+            files: &mut Vec<FileInfo>) -> Result<(), Error> {
+    for entry in dir.ls() {
+        if entry.is_special() {
+            continue;
+        }
+
+        let path = format!("{}\\{}",
+                           prefix,
+                           String::from_utf8_lossy(entry.name()));
+
+        files.push(FileInfo {
+            path: path.clone(),
+            size: entry.extent_len(),
+            is_dir: entry.is_dir(),
+        });
+
+        if entry.is_dir() {
+            let subdir = try!(Directory::new(image, entry));
+
+            try!(list_dir(image, &subdir, &path, files));
+        }
+    }
+
+    Ok(())
+}
+
 /// Read a 32bit number stored in "both byte order" format
 fn read_u32(v: &[u8]) -> u32 {
     // Only use the little endian representation. Should we bother
@@ -0,0 +1,82 @@
+//! Game identification.
+//!
+//! Frontends usually want to display the name of the running game in
+//! the window title or publish it as "rich presence" for chat
+//! clients. The serial number and boot executable come from the disc
+//! itself (SYSTEM.CNF and the license area), the human readable title
+//! isn't stored anywhere on the disc so it's looked up in the game
+//! `Database`.
+
+use std::fmt;
+
+use config::Database;
+use logging;
+
+use super::disc::{Disc, Region, SerialNumber};
+
+/// Identification of a game disc
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameInfo {
+    /// Serial number, for instance "SCUS-94900"
+    pub serial: SerialNumber,
+    /// Region deduced from the serial number
+    pub region: Option<Region>,
+    /// Title of the game if it's in the database
+    pub title: Option<String>,
+    /// Name of the boot executable found in SYSTEM.CNF. It's usually
+    /// derived from the serial number but not always.
+    pub boot: Option<String>,
+}
+
+impl GameInfo {
+    /// Identify `disc`, using `db` to find the title
+    pub fn from_disc(disc: &mut Disc, db: &Database) -> GameInfo {
+        let serial = disc.serial_number();
+
+        let boot =
+            match disc.system_cnf() {
+                Ok(cnf) => cnf.boot_executable().map(|b| b.to_owned()),
+                Err(e) => {
+                    warn!(target: logging::CDROM,
+                          "Couldn't read SYSTEM.CNF: {:?}", e);
+                    None
+                }
+            };
+
+        let title = db.lookup(serial).and_then(|c| c.title.clone());
+
+        GameInfo {
+            serial: serial,
+            region: serial.region(),
+            title: title,
+            boot: boot,
+        }
+    }
+}
+
+impl fmt::Display for GameInfo {
+    /// Format the game name suitably for a window title: "Title
+    /// [SERIAL]" or only the serial number if the title is unknown
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.title {
+            Some(ref t) => write!(f, "{} [{}]", t, self.serial),
+            None => write!(f, "{}", self.serial),
+        }
+    }
+}
+
+#[test]
+fn display() {
+    let mut info = GameInfo {
+        serial: SerialNumber::dummy(),
+        region: None,
+        title: None,
+        boot: None,
+    };
+
+    assert_eq!(info.to_string(), "UNKN-00000");
+
+    info.title = Some("Unknown Game".into());
+
+    assert_eq!(info.to_string(), "Unknown Game [UNKN-00000]");
+}
@@ -15,6 +15,8 @@
 //! based on No$'s specs, mednafen's source code and some educated
 //! guesses.
 
+use std::cmp;
+
 use memory::Addressable;
 use timekeeper::{Peripheral, Cycles};
 use interrupt::Interrupt;
@@ -22,14 +24,17 @@ use shared::SharedState;
 use arrayvec::ArrayVec;
 use cdimage::sector::Sector;
 use cdimage::msf::Msf;
+use logging;
+use rtc::to_bcd;
+use events::Event;
 
 use self::disc::{Disc, Region};
-use self::simple_rand::SimpleRand;
 
 pub mod disc;
 pub mod iso9660;
+pub mod metadata;
+pub mod pbp;
 
-mod simple_rand;
 
 /// CDROM drive, controller and decoder.
 #[derive(RustcDecodable, RustcEncodable)]
@@ -70,10 +75,26 @@ pub struct CdRom {
     read_pending: bool,
     /// Currently loaded disc or None if no disc is present
     disc: Option<Disc>,
+    /// True while the drive's lid is open
+    lid_open: bool,
+    /// Latched "shell open" status bit. Set when the lid is opened,
+    /// only cleared by a GetStat command once the lid is closed again.
+    shell_opened: bool,
+    /// True if the lid has been opened and the host hasn't been
+    /// notified yet
+    lid_irq_pending: bool,
+    /// Disc waiting to be inserted when the lid closes
+    pending_disc: Option<Disc>,
+    /// If the lid is open and a disc swap is in progress: number of
+    /// CPU cycles before it closes again
+    lid_close_timer: Option<u32>,
     /// Target of the next seek command
     seek_target: Msf,
     /// True if `seek_target` has been set but no seek took place
     seek_target_pending: bool,
+    /// Random delay added to the next seek, drawn when the command
+    /// starts
+    seek_jitter: u32,
     /// Current read position
     position: Msf,
     /// If true the drive is in double speed mode (2x, 150 sectors per
@@ -108,9 +129,6 @@ pub struct CdRom {
 
     /// CDROM audio mixer connected to the SPU
     mixer: Mixer,
-    /// PRNG to simulate the pseudo-random CD controller timings (from
-    /// the host's perspective)
-    rand: SimpleRand,
 }
 
 impl CdRom {
@@ -131,8 +149,14 @@ impl CdRom {
             read_state: ReadState::Idle,
             read_pending: false,
             disc: disc,
+            lid_open: false,
+            shell_opened: false,
+            lid_irq_pending: false,
+            pending_disc: None,
+            lid_close_timer: None,
             seek_target: Msf::zero(),
             seek_target_pending: false,
+            seek_jitter: 0,
             position: Msf::zero(),
             double_speed: false,
             xa_adpcm_to_spu: false,
@@ -145,7 +169,6 @@ impl CdRom {
             filter_file: 0,
             filter_channel: 0,
             mixer: Mixer::new(),
-            rand: SimpleRand::new(),
         }
     }
 
@@ -154,6 +177,18 @@ impl CdRom {
 
         let mut remaining_cycles = delta as u32;
 
+        if let Some(timer) = self.lid_close_timer {
+            if timer > remaining_cycles {
+                self.lid_close_timer = Some(timer - remaining_cycles);
+            } else {
+                let disc = self.pending_disc.take();
+
+                self.close_lid(disc);
+
+                shared.push_event(Event::LidClosed);
+            }
+        }
+
         while remaining_cycles > 0 {
             let elapsed =
                 if self.sub_cpu.in_command() {
@@ -192,6 +227,9 @@ impl CdRom {
             if let ReadState::Reading(delay) = self.read_state {
                 if delay > elapsed {
                     self.read_state = ReadState::Reading(delay - elapsed);
+                } else if self.disc_missing() {
+                    // The disc went away in the middle of the read
+                    self.read_state = ReadState::Idle;
                 } else {
                     let leftover = elapsed - delay;
 
@@ -212,6 +250,25 @@ impl CdRom {
         self.predict_next_sync(shared);
     }
 
+    /// Reset the controller to its power-on state. The disc (if any)
+    /// remains in the drive.
+    pub fn reset(&mut self) {
+        // If we're in the middle of a disc swap the new disc is
+        // inserted right away
+        let disc = self.remove_disc().or(self.pending_disc.take());
+
+        *self = CdRom::new(disc);
+    }
+
+    /// Return the disc currently in the drive or, during a disc
+    /// swap, the one about to be inserted
+    pub fn disc_mut(&mut self) -> Option<&mut Disc> {
+        match self.disc {
+            Some(ref mut d) => Some(d),
+            None => self.pending_disc.as_mut(),
+        }
+    }
+
     // Remove the disc. Returns the disc instance, if any.
     pub fn remove_disc(&mut self) -> Option<Disc> {
         self.set_disc(None)
@@ -226,9 +283,74 @@ impl CdRom {
         disc
     }
 
+    /// Open the drive's lid. Any read in progress is interrupted and
+    /// the disc is removed and returned.
+    pub fn open_lid(&mut self, shared: &mut SharedState) -> Option<Disc> {
+        if !self.lid_open {
+            info!(target: logging::CDROM, "Lid opened");
+
+            self.lid_open = true;
+            self.shell_opened = true;
+            self.read_state = ReadState::Idle;
+            self.read_pending = false;
+
+            // The controller notifies the host with an error
+            // interrupt
+            self.lid_irq_pending = true;
+            self.maybe_notify_lid(shared);
+
+            shared.push_event(Event::LidOpened);
+        }
+
+        self.remove_disc()
+    }
+
+    /// Insert `disc` (if any) and close the lid. The "shell open"
+    /// status bit remains set until the next GetStat command.
+    pub fn close_lid(&mut self, disc: Option<Disc>) {
+        info!(target: logging::CDROM, "Lid closed");
+
+        self.set_disc(disc);
+        self.lid_open = false;
+        self.lid_close_timer = None;
+        self.position = Msf::zero();
+        self.seek_target_pending = false;
+    }
+
+    /// Current value of the index register
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Return true if the lid is currently open
+    pub fn lid_open(&self) -> bool {
+        self.lid_open
+    }
+
+    /// Emulate a disc swap by opening the lid, waiting long enough
+    /// for the game to notice and then closing it again with `disc`
+    /// inside. Returns the previous disc.
+    pub fn swap_disc(&mut self,
+                     shared: &mut SharedState,
+                     disc: Option<Disc>) -> Option<Disc> {
+        let old = self.open_lid(shared);
+
+        self.pending_disc = disc;
+        self.lid_close_timer = Some(timings::DISC_SWAP);
+
+        self.predict_next_sync(shared);
+
+        old
+    }
+
     fn predict_next_sync(&mut self, shared: &mut SharedState) {
         shared.tk().no_sync_needed(Peripheral::CdRom);
 
+        if let Some(timer) = self.lid_close_timer {
+            shared.tk().set_next_sync_delta(Peripheral::CdRom,
+                                            timer as Cycles);
+        }
+
         if self.sub_cpu.in_command() {
             // Force a sync at the next step. If we wanted to optimize
             // that some more we could compute the delay till the next
@@ -364,7 +486,8 @@ impl CdRom {
                     // ATV1 register
                     2 => self.mixer.cd_left_to_spu_right = val,
                     // ADPCTL register
-                    3 => debug!("CDROM Mixer apply {:02x}", val),
+                    3 => debug!(target: logging::CDROM,
+                                "CDROM Mixer apply {:02x}", val),
                     _ => unimplemented(),
                 },
             _ => unimplemented(),
@@ -439,7 +562,8 @@ impl CdRom {
     fn set_host_interrupt_mask(&mut self, val: u8) {
         // We only support the 3 bit sub-CPU interrupt code for now.
         if val & 0x18 != 0 {
-            warn!("CDROM: unhandled IRQ mask: {:02x}", val);
+            warn!(target: logging::CDROM,
+                  "CDROM: unhandled IRQ mask: {:02x}", val);
         }
 
         self.irq_mask = val & 0x1f;
@@ -495,6 +619,7 @@ impl CdRom {
         self.maybe_start_command(shared);
         self.maybe_process_async_response(shared);
         self.maybe_notify_read(shared);
+        self.maybe_notify_lid(shared);
     }
 
     /// Start the command sequence if a command is pending and the
@@ -508,8 +633,13 @@ impl CdRom {
                 // We're good to go. Let's begin by computing the
                 // seemingly pseudo-random command pending delay.
 
-                let variation = self.rand.next() as u32
-                    % timings::COMMAND_PENDING_VARIATION;
+                let variation =
+                    shared.rng().below(timings::COMMAND_PENDING_VARIATION);
+
+                // The seek time also varies a bit depending on the
+                // exact position of the disc
+                self.seek_jitter =
+                    shared.rng().below(timings::SEEK_JITTER);
 
                 let timer = timings::COMMAND_PENDING + variation;
 
@@ -567,6 +697,31 @@ impl CdRom {
         }
     }
 
+    /// Send the "shell opened" error interrupt if the lid has been
+    /// opened and the preconditions are met
+    fn maybe_notify_lid(&mut self, shared: &mut SharedState) {
+        if self.lid_irq_pending {
+            if self.irq_flags == 0 && !self.sub_cpu.in_command() {
+                self.sub_cpu.response.clear();
+
+                self.sub_cpu.irq_code = IrqCode::Error;
+
+                let status = self.drive_status();
+
+                // Status with the error bit set followed by the "door
+                // open" error code
+                self.sub_cpu.response.push_slice(&[status | 1, 0x08]);
+
+                self.sub_cpu.sequence = SubCpuSequence::AsyncRxPush;
+                self.sub_cpu.timer = timings::READ_RX_PUSH;
+
+                self.lid_irq_pending = false;
+
+                self.predict_next_sync(shared);
+            }
+        }
+    }
+
     /// Called when it's time to advance in the sub-CPU execution
     /// sequence
     fn next_sub_cpu_step(&mut self, shared: &mut SharedState) {
@@ -692,16 +847,20 @@ impl CdRom {
     }
 
     /// Execute a pending seek (if any). On the real console that
-    /// would mean physically moving the read head.
-    fn do_seek(&mut self) {
-        // Make sure we don't end up in track1's pregap, I don't know
-        // if it's ever useful? Needs special handling at least...
-        if self.seek_target < Msf::from_bcd(0x00, 0x02, 0x00).unwrap() {
-            panic!("Seek to track. 1 pregap: {}", self.seek_target);
-        }
+    /// would mean physically moving the read head. Returns the number
+    /// of CPU cycles taken by the seek.
+    fn do_seek(&mut self) -> u32 {
+        // Seeking into a pregap (including the 2 seconds before track
+        // 01) is allowed, the image returns the index 00 sectors even
+        // if they're not stored in the file.
+        let delay = seek_time(self.position,
+                              self.seek_target,
+                              self.double_speed) + self.seek_jitter;
 
         self.position = self.seek_target;
         self.seek_target_pending = false;
+
+        delay
     }
 
     /// Called when a new sector must be read
@@ -753,7 +912,8 @@ impl CdRom {
                         // data and no error correction. It probably
                         // shouldn't be read without
                         // `read_whole_sector` being set.
-                        warn!("Form 2 sector partial read");
+                        warn!(target: logging::CDROM,
+                              "Form 2 sector partial read");
                     }
 
                     &data[0..2048]
@@ -778,8 +938,29 @@ impl CdRom {
         self.read_pending = true;
     }
 
+    /// Return true if the disc can't be accessed: the lid is open
+    /// (for instance during a disc swap) or the drive is empty
+    fn disc_missing(&self) -> bool {
+        self.lid_open || self.disc.is_none()
+    }
+
+    /// Error response of the commands accessing the disc when
+    /// `disc_missing` is true
+    fn push_disc_missing_error(&mut self) {
+        let status = self.drive_status();
+
+        self.sub_cpu.response.push_slice(&[status | 1, 0x80]);
+
+        self.sub_cpu.irq_code = IrqCode::Error;
+    }
+
     /// Assembles the first status byte returned by many commands
     fn drive_status(&self) -> u8 {
+        if self.lid_open {
+            // Shell open, motor off
+            return 0x10;
+        }
+
         match self.disc {
             // XXX on the real hardware bit 4 is always set the first time
             // this command is called even if the console is booted with
@@ -792,6 +973,7 @@ impl CdRom {
 
                 // Motor on
                 r |= 1 << 1;
+                r |= (self.shell_opened as u8) << 4;
                 r |= (reading as u8) << 5;
 
                 r
@@ -819,6 +1001,8 @@ impl CdRom {
                 0x0e => (1, 1, CdRom::cmd_set_mode),
                 0x0f => (0, 0, CdRom::cmd_get_param),
                 0x11 => (0, 0, CdRom::cmd_get_loc_p),
+                0x13 => (0, 0, CdRom::cmd_get_tn),
+                0x14 => (1, 1, CdRom::cmd_get_td),
                 0x15 => (0, 0, CdRom::cmd_seek_l),
                 0x19 => (1, 1, CdRom::cmd_test),
                 0x1a => (0, 0, CdRom::cmd_get_id),
@@ -844,6 +1028,12 @@ impl CdRom {
         let status = self.drive_status();
 
         self.sub_cpu.response.push(status);
+
+        // GetStat acknowledges the "shell opened" bit once the lid is
+        // closed
+        if !self.lid_open {
+            self.shell_opened = false;
+        }
     }
 
     /// Tell the CDROM controller where the next seek should take us
@@ -877,16 +1067,26 @@ impl CdRom {
     /// audio/movies). In our emulator we'll just pretend no error
     /// ever occurs.
     fn cmd_read(&mut self) {
-        if !self.read_state.is_idle() {
-            warn!("CDROM READ while we're already reading");
+        if self.disc_missing() {
+            self.push_disc_missing_error();
+            return;
         }
 
-        if self.seek_target_pending {
-            // XXX That should take some time...
-            self.do_seek();
+        if !self.read_state.is_idle() {
+            warn!(target: logging::CDROM,
+                  "CDROM READ while we're already reading");
         }
 
-        let read_delay = self.cycles_per_sector();
+        let seek_delay =
+            if self.seek_target_pending {
+                self.do_seek()
+            } else {
+                0
+            };
+
+        // The first sector is ready once the head reached the target
+        // position
+        let read_delay = seek_delay + self.cycles_per_sector();
 
         self.read_state = ReadState::Reading(read_delay);
 
@@ -903,19 +1103,18 @@ impl CdRom {
 
         self.sub_cpu.response.push(status);
 
-        self.read_state = ReadState::Idle;
-
         let async_delay =
             if self.read_state.is_idle() {
-                warn!("Pause when we're not reading");
-                9000
+                warn!(target: logging::CDROM, "Pause when we're not reading");
+                timings::PAUSE_IDLE_ASYNC
             } else {
-                // XXX Very very rough approximation, can change based
-                // on many factors. Need to come up with a more
-                // accurate heuristic
-                1_000_000
+                // The drive has to finish reading the current sector
+                // and spin down
+                timings::PAUSE_ASYNC >> (self.double_speed as u32)
             };
 
+        self.read_state = ReadState::Idle;
+
         self.sub_cpu.schedule_async_response(async_delay, CdRom::async_pause);
     }
 
@@ -1036,16 +1235,6 @@ impl CdRom {
     /// Get the current position of the drive head by returning the
     /// contents of the Q subchannel
     fn cmd_get_loc_p(&mut self) {
-        if self.position < Msf::from_bcd(0x00, 0x02, 0x00).unwrap() {
-            // The values returned in the track 01 pregap are strange,
-            // The absolute MSF seems correct but the track MSF looks
-            // like garbage.
-            //
-            // For instance after seeking at 00:01:25 the track MSF
-            // returned by GetLocP is 00:00:49 with my PAL Spyro disc.
-            panic!("GetLocP while in track1 pregap");
-        }
-
         // Fixme: All this data should be extracted from the
         // subchannel Q (when available in cdimage).
 
@@ -1057,12 +1246,23 @@ impl CdRom {
         // it for now.
         let abs_msf = metadata.msf;
 
-        // Position within the current track
-        let track_msf = metadata.track_msf;
-
         let track = metadata.track;
         let index = metadata.index;
 
+        // Position within the current track. In a pregap (index 00)
+        // it counts down towards the start of the track: after
+        // seeking at 00:01:25 in track 01's pregap the track MSF
+        // returned by my PAL Spyro disc is 00:00:49.
+        let pregap = index.bcd() == 0;
+
+        let track_msf =
+            match self.disc {
+                Some(ref d) => d.track_relative_msf(abs_msf, pregap),
+                None => None,
+            };
+
+        let track_msf = track_msf.unwrap_or(metadata.track_msf);
+
         let (track_m, track_s, track_f) = track_msf.into_bcd();
 
         let (abs_m, abs_s, abs_f) = abs_msf.into_bcd();
@@ -1077,27 +1277,83 @@ impl CdRom {
         self.sub_cpu.response.push_slice(&response_bcd);
     }
 
+    /// Return the first and last track numbers. Mixed-mode discs use
+    /// this to locate their CD-DA tracks.
+    fn cmd_get_tn(&mut self) {
+        let last =
+            match self.disc {
+                Some(ref d) => d.track_count(),
+                None => 0,
+            };
+
+        let status = self.drive_status();
+
+        self.sub_cpu.response.push_slice(&[status, 0x01, to_bcd(last)]);
+    }
+
+    /// Return the absolute position (minutes and seconds) of the
+    /// start of the track given in parameter
+    fn cmd_get_td(&mut self) {
+        let param = self.sub_cpu.params.pop();
+
+        let track = (param >> 4) * 10 + (param & 0xf);
+
+        // XXX track 0 should return the position of the lead-out
+        // but the image doesn't give us the size of the disc.
+        let start =
+            match self.disc {
+                Some(ref d) if param & 0xf < 10 => d.track_start(track),
+                _ => None,
+            };
+
+        let status = self.drive_status();
+
+        match start {
+            Some(msf) => {
+                let (m, s, _) = msf.into_bcd();
+
+                self.sub_cpu.response.push_slice(&[status, m.bcd(), s.bcd()]);
+            }
+            None => {
+                warn!(target: logging::CDROM,
+                      "GetTD for invalid track {:02x}", param);
+
+                // Invalid parameter
+                self.sub_cpu.response.push_slice(&[status | 1, 0x10]);
+
+                self.sub_cpu.irq_code = IrqCode::Error;
+            }
+        }
+    }
+
     /// Execute seek. Target is given by previous "set loc" command.
     fn cmd_seek_l(&mut self) {
-        self.do_seek();
+        if self.disc_missing() {
+            self.push_disc_missing_error();
+            return;
+        }
+
+        // The async response is sent once the reading head reached
+        // the target
+        let seek_delay = self.do_seek();
 
         let status = self.drive_status();
 
         self.sub_cpu.response.push(status);
 
-        // XXX the delay for the async response is tied to the time it
-        // takes for the reading head to physically seek on the
-        // disc. We probably need a heuristic based on the current
-        // head position, target position and probably a bunch of
-        // other factors. For now hardcode a dumb value and hope for
-        // the best.
-        self.sub_cpu.schedule_async_response(1_000_000, CdRom::async_seek_l);
+        self.sub_cpu.schedule_async_response(seek_delay,
+                                             CdRom::async_seek_l);
     }
 
     fn async_seek_l(&mut self) -> u32 {
-        let status = self.drive_status();
+        // The lid may have been opened since the command
+        if self.disc_missing() {
+            self.push_disc_missing_error();
+        } else {
+            let status = self.drive_status();
 
-        self.sub_cpu.response.push(status);
+            self.sub_cpu.response.push(status);
+        }
 
         timings::SEEK_L_RX_PUSH
     }
@@ -1118,6 +1374,11 @@ impl CdRom {
 
     /// Instruct the CD drive to read the table of contents
     fn cmd_read_toc(&mut self) {
+        if self.disc_missing() {
+            self.push_disc_missing_error();
+            return;
+        }
+
         let status = self.drive_status();
 
         self.sub_cpu.response.push(status);
@@ -1129,9 +1390,13 @@ impl CdRom {
     }
 
     fn async_read_toc(&mut self) -> u32 {
-        let status = self.drive_status();
+        if self.disc_missing() {
+            self.push_disc_missing_error();
+        } else {
+            let status = self.drive_status();
 
-        self.sub_cpu.response.push(status);
+            self.sub_cpu.response.push(status);
+        }
 
         timings::READ_TOC_RX_PUSH
     }
@@ -1140,28 +1405,35 @@ impl CdRom {
     /// checks that the disc is an official PlayStation disc (and not
     /// a copy) and handles region locking.
     fn cmd_get_id(&mut self) {
+        if self.disc_missing() {
+            self.push_disc_missing_error();
+            return;
+        }
 
-        match self.disc {
-            Some(_) => {
-                let status = self.drive_status();
-
-                self.sub_cpu.response.push(status);
+        let status = self.drive_status();
 
-                self.sub_cpu.schedule_async_response(timings::GET_ID_ASYNC,
-                                                     CdRom::async_get_id);
-            }
-            None => {
-                // Pretend the shell is open
-                self.sub_cpu.response.push_slice(&[0x11, 0x80]);
+        self.sub_cpu.response.push(status);
 
-                self.sub_cpu.irq_code = IrqCode::Error;
-            }
-        }
+        self.sub_cpu.schedule_async_response(timings::GET_ID_ASYNC,
+                                             CdRom::async_get_id);
     }
 
     fn async_get_id(&mut self) -> u32 {
-        // If we're here we must have a disc
-        let disc = self.disc.as_ref().unwrap();
+        // The lid may have been opened since the command
+        let region =
+            match self.disc {
+                Some(ref d) if !self.lid_open => Some(d.region()),
+                _ => None,
+            };
+
+        let region =
+            match region {
+                Some(r) => r,
+                None => {
+                    self.push_disc_missing_error();
+                    return timings::GET_ID_RX_PUSH;
+                }
+            };
 
         let response = [
             // Status + bit 3 if unlicensed/audio
@@ -1178,7 +1450,7 @@ impl CdRom {
             // Region string: "SCEI" for japan, "SCEE" for
             // Europe and "SCEA" for US.
             b'S', b'C', b'E',
-            match disc.region() {
+            match region {
                 Region::Japan => b'I',
                 Region::NorthAmerica => b'A',
                 Region::Europe => b'E',
@@ -1273,6 +1545,37 @@ impl Fifo {
     }
 }
 
+/// Estimate the number of CPU cycles needed to move the reading head
+/// from `from` to `to`. Short distances are covered by reading ahead
+/// (or waiting for the disc to spin back), longer ones need to
+/// physically move the sled which takes up to about a second for a
+/// full stroke.
+fn seek_time(from: Msf, to: Msf, double_speed: bool) -> u32 {
+    let from = from.sector_index();
+    let to = to.sector_index();
+
+    let distance =
+        if to > from {
+            to - from
+        } else {
+            from - to
+        };
+
+    let speed = 1 + double_speed as u32;
+
+    if distance <= timings::SEEK_READ_AHEAD_SECTORS {
+        // Small adjustment, the head doesn't need to move much but we
+        // have to wait for the right sector to come by
+        let sector = ::cpu::CPU_FREQ_HZ / (75 * speed);
+
+        cmp::max(timings::SEEK_MIN, distance * sector)
+    } else {
+        let sled = distance * timings::SEEK_SLED_PER_SECTOR;
+
+        cmp::min(timings::SEEK_MIN + sled, timings::SEEK_MAX)
+    }
+}
+
 /// RX buffer serializable container
 buffer!(struct RxBuffer([u8; 2352]));
 
@@ -1516,6 +1819,39 @@ mod timings {
     /// for the asynchronous SeekL response
     pub const SEEK_L_RX_PUSH: u32 = 1_700;
 
+    /// Shortest seek time, even when the head is already at the
+    /// right place
+    pub const SEEK_MIN: u32 = 20_000;
+
+    /// Longest seek time, roughly what it takes to move from one end
+    /// of the disc to the other
+    pub const SEEK_MAX: u32 = 33_868_800;
+
+    /// Maximum random delay added to each seek
+    pub const SEEK_JITTER: u32 = 2_000;
+
+    /// Seeks shorter than this number of sectors don't need to move
+    /// the sled
+    pub const SEEK_READ_AHEAD_SECTORS: u32 = 16;
+
+    /// Time taken to move the sled by one sector: a full stroke over
+    /// a 72 minute disc takes about a second
+    pub const SEEK_SLED_PER_SECTOR: u32 = SEEK_MAX / (72 * 60 * 75);
+
+    /// Delay between a Pause command and its asynchronous response
+    /// when reading at 1x. The drive has to finish the current sector
+    /// and slow down, at 2x it takes about half as long.
+    pub const PAUSE_ASYNC: u32 = 2_200_000;
+
+    /// Delay between a Pause command and its asynchronous response
+    /// when the drive is already paused
+    pub const PAUSE_IDLE_ASYNC: u32 = 9_000;
+
+    /// How long the lid stays open during a disc swap. Games poll
+    /// the drive status so this has to be long enough for them to
+    /// notice, ~1 second seems to work.
+    pub const DISC_SWAP: u32 = 33_868_800;
+
     /// Delay between the asynchronous RX_CLEAR and first param push
     /// for the asynchronous Read(S/N) response
     pub const READ_RX_PUSH: u32 = 1_800;
@@ -1528,3 +1864,65 @@ mod timings {
     /// for the asynchronous Init response
     pub const INIT_RX_PUSH: u32 = 1_700;
 }
+
+#[test]
+fn seek_distance() {
+    let msf = |m, s, f| Msf::from_bcd(m, s, f).unwrap();
+
+    let start = msf(0x00, 0x02, 0x00);
+
+    let short = seek_time(start, msf(0x00, 0x02, 0x01), false);
+    let medium = seek_time(start, msf(0x10, 0x00, 0x00), false);
+    let long = seek_time(start, msf(0x70, 0x00, 0x00), false);
+
+    assert_eq!(short, timings::SEEK_MIN);
+    assert!(medium > short);
+    assert!(long > medium);
+    assert!(long <= timings::SEEK_MAX);
+
+    // Seeking backwards takes as long as seeking forward
+    assert_eq!(seek_time(msf(0x10, 0x00, 0x00), start, false), medium);
+}
+
+#[test]
+fn disc_missing_errors() {
+    let mut cdrom = CdRom::new(None);
+
+    // Commands accessing the disc return an error instead of
+    // starting, including the async handlers reached after the lid
+    // was opened
+    let handlers: [fn(&mut CdRom); 4] = [CdRom::cmd_read,
+                                         CdRom::cmd_seek_l,
+                                         CdRom::cmd_read_toc,
+                                         CdRom::cmd_get_id];
+
+    for handler in handlers.iter() {
+        cdrom.sub_cpu.start_command(0);
+
+        handler(&mut cdrom);
+
+        assert_eq!(cdrom.sub_cpu.irq_code as u8, IrqCode::Error as u8);
+        assert_eq!(cdrom.sub_cpu.response.len(), 2);
+        assert_eq!(cdrom.sub_cpu.response.pop(), 0x11);
+        assert_eq!(cdrom.sub_cpu.response.pop(), 0x80);
+        assert!(cdrom.read_state.is_idle());
+        assert!(!cdrom.sub_cpu.async_command_pending());
+
+        cdrom.sub_cpu.sequence = SubCpuSequence::Idle;
+    }
+
+    let asyncs: [fn(&mut CdRom) -> u32; 3] = [CdRom::async_seek_l,
+                                              CdRom::async_read_toc,
+                                              CdRom::async_get_id];
+
+    for handler in asyncs.iter() {
+        cdrom.sub_cpu.response.clear();
+        cdrom.sub_cpu.irq_code = IrqCode::AsyncOk;
+
+        handler(&mut cdrom);
+
+        assert_eq!(cdrom.sub_cpu.irq_code as u8, IrqCode::Error as u8);
+        assert_eq!(cdrom.sub_cpu.response.pop(), 0x11);
+        assert_eq!(cdrom.sub_cpu.response.pop(), 0x80);
+    }
+}
@@ -0,0 +1,526 @@
+//! PlayStation Portable "eboot" (PBP) disc images.
+//!
+//! PBP files are the format used by the PSP's PlayStation emulator
+//! and are a popular way to store compressed disc collections. The
+//! disc data lives in the DATA.PSAR section:
+//!
+//! * A `PSISOIMG0000` header for single disc images. Multi disc
+//!   images start with `PSTITLEIMG000000` instead, followed by the
+//!   offsets of one `PSISOIMG0000` header per disc at +0x200.
+//! * The table of contents at +0x800, in the same format as the
+//!   Q subchannel of the lead-in (10 bytes per entry).
+//! * The block index at +0x4000: 32 bytes per entry, the offset
+//!   (relative to +0x100000) and the length of each block.
+//! * The data itself at +0x100000: blocks of 16 raw 2352 byte
+//!   sectors, compressed with raw deflate unless the block is stored
+//!   full size.
+//!
+//! Sectors can only be built by the `cdimage` backends so the disc
+//! is served through the CUE backend, using a temporary CUE sheet
+//! and a sparse BIN file of the size of the image. Blocks are only
+//! decompressed when one of their sectors is read, and the sector is
+//! written to the BIN file just before the CUE backend reads it back.
+//! The last few decompressed blocks are kept in memory since sectors
+//! are mostly read sequentially. The files are removed when the image
+//! is dropped.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use std::process;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use cdimage::{Image, CdError};
+use cdimage::msf::Msf;
+use cdimage::bcd::Bcd;
+use cdimage::sector::Sector;
+use cdimage::cue::Cue;
+
+use flate2::read::DeflateDecoder;
+
+pub struct Pbp {
+    /// CUE backend reading the sparse BIN file
+    cue: Cue,
+    /// Temporary directory containing the CUE sheet and BIN file
+    dir: PathBuf,
+    /// The compressed disc
+    disc: Disc,
+    /// Recently decompressed blocks, most recently used first
+    cache: Vec<(usize, Vec<u8>)>,
+}
+
+impl Pbp {
+    /// Open the first disc of the PBP file at `path`
+    pub fn new(path: &Path) -> Result<Pbp, String> {
+        let stem =
+            match path.file_stem() {
+                Some(s) => s.to_string_lossy().into_owned(),
+                None => "disc".into(),
+            };
+
+        let dir = try!(temp_dir(&stem));
+
+        let cue_path = dir.join("disc.cue");
+
+        let opened =
+            fs::create_dir_all(&dir)
+            .and_then(|_| Disc::open(path, &dir.join("disc.bin"), &cue_path));
+
+        let disc =
+            match opened {
+                Ok(d) => d,
+                Err(e) => {
+                    let _ = fs::remove_dir_all(&dir);
+
+                    return Err(format!("Couldn't load {}: {}",
+                                       path.display(), e));
+                }
+            };
+
+        match Cue::new(&cue_path) {
+            Ok(cue) => Ok(Pbp {
+                cue: cue,
+                dir: dir,
+                disc: disc,
+                cache: Vec::with_capacity(CACHE_BLOCKS),
+            }),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+
+                Err(format!("Couldn't load {}: {:?}", path.display(), e))
+            }
+        }
+    }
+
+    /// Write the sector at `msf` to the BIN file, decompressing its
+    /// block if it's not in the cache. Positions outside of the image
+    /// are left to the CUE backend.
+    fn fill_sector(&mut self, msf: Msf) -> io::Result<()> {
+        let index = msf.sector_index();
+
+        if index < LEAD_IN || index - LEAD_IN >= self.disc.sectors {
+            return Ok(());
+        }
+
+        let sector = (index - LEAD_IN) as usize;
+        let block = sector / BLOCK_SECTORS as usize;
+
+        let cached = self.cache.iter().position(|c| c.0 == block);
+
+        let entry =
+            match cached {
+                Some(i) => self.cache.remove(i),
+                None => {
+                    // Reuse the buffer of the least recently used
+                    // block once the cache is full
+                    let mut data =
+                        if self.cache.len() < CACHE_BLOCKS {
+                            vec![0; BLOCK_SIZE]
+                        } else {
+                            self.cache.pop().unwrap().1
+                        };
+
+                    try!(self.disc.read_block(block, &mut data));
+
+                    (block, data)
+                }
+            };
+
+        self.cache.insert(0, entry);
+
+        let offset = (sector % BLOCK_SECTORS as usize) * SECTOR_SIZE;
+        let data = &self.cache[0].1[offset..offset + SECTOR_SIZE];
+        let bin = &mut self.disc.bin;
+
+        try!(bin.seek(SeekFrom::Start((sector * SECTOR_SIZE) as u64)));
+
+        bin.write_all(data)
+    }
+}
+
+impl Image for Pbp {
+    fn image_format(&self) -> String {
+        "PBP".into()
+    }
+
+    fn read_sector(&mut self, sector: &mut Sector, msf: Msf)
+                   -> Result<(), CdError> {
+        if let Err(e) = self.fill_sector(msf) {
+            return Err(CdError::IoError(e));
+        }
+
+        self.cue.read_sector(sector, msf)
+    }
+
+    fn track_msf(&self, track: Bcd, track_msf: Msf) -> Result<Msf, CdError> {
+        self.cue.track_msf(track, track_msf)
+    }
+}
+
+impl Drop for Pbp {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Track entry of the table of contents
+struct Track {
+    /// True for CD-DA tracks
+    audio: bool,
+    /// Absolute position of index 01, in sectors
+    start: u32,
+}
+
+/// First disc of a PBP file
+struct Disc {
+    /// The PBP file
+    pbp: File,
+    /// Sparse raw image read by the CUE backend
+    bin: File,
+    /// Absolute offset and stored length of each block
+    index: Vec<(u64, usize)>,
+    /// Length of the image in sectors
+    sectors: u32,
+}
+
+impl Disc {
+    /// Open the first disc of the PBP file at `path`, create the
+    /// empty raw image `bin` and write the matching CUE sheet in
+    /// `cue`
+    fn open(path: &Path, bin: &Path, cue: &Path) -> io::Result<Disc> {
+        let mut pbp = try!(File::open(path));
+
+        let mut header = [0; 0x28];
+
+        try!(pbp.read_exact(&mut header));
+
+        if &header[0..4] != b"\0PBP" {
+            return Err(bad_format("not a PBP file"));
+        }
+
+        let psar = read_u32(&header[0x24..]) as u64;
+
+        let mut magic = [0; 12];
+
+        try!(read_at(&mut pbp, psar, &mut magic));
+
+        let iso =
+            if &magic == b"PSISOIMG0000" {
+                psar
+            } else if &magic == b"PSTITLEIMG00" {
+                // Multi disc image, use the first one
+                let mut offset = [0; 4];
+
+                try!(read_at(&mut pbp, psar + 0x200, &mut offset));
+
+                let iso = psar + read_u32(&offset) as u64;
+
+                try!(read_at(&mut pbp, iso, &mut magic));
+
+                if &magic != b"PSISOIMG0000" {
+                    return Err(bad_format("bad disc header"));
+                }
+
+                iso
+            } else {
+                return Err(bad_format("no PlayStation disc image"));
+            };
+
+        let (tracks, sectors) = try!(read_toc(&mut pbp, iso));
+
+        let blocks = (sectors + BLOCK_SECTORS - 1) / BLOCK_SECTORS;
+
+        if blocks > INDEX_ENTRIES {
+            return Err(bad_format("disc too big"));
+        }
+
+        let mut entries = vec![0; blocks as usize * 32];
+
+        try!(read_at(&mut pbp, iso + 0x4000, &mut entries));
+
+        let index =
+            entries.chunks(32)
+            .map(|e| {
+                let offset = read_u32(e) as u64;
+                let len = (e[4] as usize) | ((e[5] as usize) << 8);
+
+                (iso + 0x100000 + offset, len)
+            })
+            .collect();
+
+        try!(write_cue(cue, bin, &tracks));
+
+        let image = try!(File::create(bin));
+
+        // Nothing is written yet, the file stays sparse
+        try!(image.set_len(sectors as u64 * SECTOR_SIZE as u64));
+
+        Ok(Disc {
+            pbp: pbp,
+            bin: image,
+            index: index,
+            sectors: sectors,
+        })
+    }
+
+    /// Decompress `block` into `data`
+    fn read_block(&mut self, block: usize, data: &mut [u8])
+                  -> io::Result<()> {
+        let (offset, len) = self.index[block];
+
+        try!(self.pbp.seek(SeekFrom::Start(offset)));
+
+        let mut stored = (&mut self.pbp).take(len as u64);
+
+        // Full size blocks are stored uncompressed, shorter ones are
+        // deflated
+        if len == BLOCK_SIZE {
+            stored.read_exact(data)
+        } else {
+            DeflateDecoder::new(stored).read_exact(data)
+        }
+    }
+}
+
+/// Parse the table of contents of the disc image at `iso`. Returns
+/// the tracks and the length of the image in sectors.
+fn read_toc(pbp: &mut File, iso: u64) -> io::Result<(Vec<Track>, u32)> {
+    let mut toc = [0; 10 * 102];
+
+    try!(read_at(pbp, iso + 0x800, &mut toc));
+
+    let position = |e: &[u8]| {
+        let m = from_bcd(e[7]) as u32;
+        let s = from_bcd(e[8]) as u32;
+        let f = from_bcd(e[9]) as u32;
+
+        (m * 60 + s) * 75 + f
+    };
+
+    // The first three entries contain the first and last track
+    // numbers and the position of the lead-out
+    let last_track = from_bcd(toc[10 + 7]) as usize;
+    let lead_out = position(&toc[20..30]);
+
+    if last_track == 0 || last_track > 99 || lead_out <= LEAD_IN {
+        return Err(bad_format("bad table of contents"));
+    }
+
+    let tracks: Vec<Track> =
+        toc[30..].chunks(10)
+        .take(last_track)
+        .map(|e| Track {
+            audio: e[0] & 0x40 == 0,
+            start: position(e),
+        })
+        .collect();
+
+    if tracks.iter().any(|t| t.start < LEAD_IN || t.start >= lead_out) {
+        return Err(bad_format("bad track position"));
+    }
+
+    Ok((tracks, lead_out - LEAD_IN))
+}
+
+/// Write a CUE sheet for the raw image `bin` containing `tracks`
+fn write_cue(path: &Path, bin: &Path, tracks: &[Track]) -> io::Result<()> {
+    let mut cue = try!(File::create(path));
+
+    let name =
+        match bin.file_name() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None => return Err(bad_format("bad image name")),
+        };
+
+    try!(writeln!(cue, "FILE \"{}\" BINARY", name));
+
+    for (i, track) in tracks.iter().enumerate() {
+        // The image starts at the end of the lead-in
+        let start = track.start - LEAD_IN;
+
+        let format = if track.audio { "AUDIO" } else { "MODE2/2352" };
+
+        try!(writeln!(cue, "  TRACK {:02} {}", i + 1, format));
+        try!(writeln!(cue, "    INDEX 01 {:02}:{:02}:{:02}",
+                      start / (60 * 75),
+                      (start / 75) % 60,
+                      start % 75));
+    }
+
+    Ok(())
+}
+
+fn read_at(f: &mut File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    try!(f.seek(SeekFrom::Start(offset)));
+
+    f.read_exact(buf)
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    (b[0] as u32) |
+    ((b[1] as u32) << 8) |
+    ((b[2] as u32) << 16) |
+    ((b[3] as u32) << 24)
+}
+
+fn from_bcd(b: u8) -> u8 {
+    (b >> 4) * 10 + (b & 0xf)
+}
+
+fn bad_format(desc: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, desc)
+}
+
+/// Return a new temporary directory for the image `stem`
+#[cfg(not(target_arch = "wasm32"))]
+fn temp_dir(stem: &str) -> Result<PathBuf, String> {
+    // The same file can be opened several times, for instance when
+    // swapping discs
+    let instance = INSTANCES.fetch_add(1, Ordering::SeqCst);
+
+    Ok(env::temp_dir().join(format!("rustation-pbp-{}-{}-{}",
+                                    process::id(), instance, stem)))
+}
+
+/// There's no filesystem on wasm32 (`env::temp_dir` panics)
+#[cfg(target_arch = "wasm32")]
+fn temp_dir(_stem: &str) -> Result<PathBuf, String> {
+    Err("PBP images aren't supported on wasm32".into())
+}
+
+/// Number of images opened so far, used to give each of them its own
+/// temporary directory
+#[cfg(not(target_arch = "wasm32"))]
+static INSTANCES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Size of a raw CD sector
+const SECTOR_SIZE: usize = 2352;
+
+/// Number of sectors in a block
+const BLOCK_SECTORS: u32 = 16;
+
+/// Size of a decompressed block
+const BLOCK_SIZE: usize = SECTOR_SIZE * BLOCK_SECTORS as usize;
+
+/// Number of decompressed blocks kept in memory
+const CACHE_BLOCKS: usize = 4;
+
+/// Maximum number of entries in the block index
+const INDEX_ENTRIES: u32 = (0x100000 - 0x4000) / 32;
+
+/// Length of the lead-in (2 seconds) which isn't part of the image
+const LEAD_IN: u32 = 150;
+
+#[test]
+fn pbp_image() {
+    use flate2::Compression;
+    use flate2::write::DeflateEncoder;
+    use rtc::to_bcd;
+
+    // Image with 20 sectors: one compressed block and one stored
+    // block, the last 12 sectors are padding
+    let sectors = 20;
+
+    let mut raw = vec![0; BLOCK_SIZE * 2];
+
+    for (i, s) in raw.chunks_mut(SECTOR_SIZE).enumerate() {
+        let msf = i as u32 + LEAD_IN;
+
+        // Sync pattern
+        for b in &mut s[1..11] {
+            *b = 0xff;
+        }
+
+        s[12] = to_bcd((msf / (60 * 75)) as u8);
+        s[13] = to_bcd(((msf / 75) % 60) as u8);
+        s[14] = to_bcd((msf % 75) as u8);
+        // Mode 2
+        s[15] = 2;
+
+        for b in &mut s[16..] {
+            *b = i as u8;
+        }
+    }
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+
+    encoder.write_all(&raw[..BLOCK_SIZE]).unwrap();
+
+    let compressed = encoder.finish().unwrap();
+
+    let psar = 0x28;
+
+    let mut pbp = vec![0; psar + 0x100000];
+
+    pbp[0..4].copy_from_slice(b"\0PBP");
+    pbp[0x24] = psar as u8;
+
+    pbp[psar..psar + 12].copy_from_slice(b"PSISOIMG0000");
+
+    {
+        let toc = &mut pbp[psar + 0x800..];
+
+        // First track, last track, lead-out at 00:02:20 and track 1
+        // at 00:02:00
+        toc[0..10].copy_from_slice(&[0x41, 0, 0xa0, 0, 0, 0, 0, 1, 0, 0]);
+        toc[10..20].copy_from_slice(&[0x41, 0, 0xa1, 0, 0, 0, 0, 1, 0, 0]);
+        toc[20..30]
+            .copy_from_slice(&[0x41, 0, 0xa2, 0, 0, 0, 0, 0, 2, 0x20]);
+        toc[30..40].copy_from_slice(&[0x41, 0, 1, 0, 0, 0, 0, 0, 2, 0]);
+    }
+
+    {
+        let index = &mut pbp[psar + 0x4000..];
+        let len = compressed.len();
+
+        index[4] = len as u8;
+        index[5] = (len >> 8) as u8;
+
+        index[32] = len as u8;
+        index[33] = (len >> 8) as u8;
+        index[34] = (len >> 16) as u8;
+        index[36] = BLOCK_SIZE as u8;
+        index[37] = (BLOCK_SIZE >> 8) as u8;
+    }
+
+    pbp.extend_from_slice(&compressed);
+    pbp.extend_from_slice(&raw[BLOCK_SIZE..]);
+
+    let path = env::temp_dir()
+        .join(format!("rustation-test-{}.pbp", process::id()));
+
+    File::create(&path).and_then(|mut f| f.write_all(&pbp)).unwrap();
+
+    let mut image = Pbp::new(&path).unwrap();
+
+    let start = image.track_msf(Bcd::one(), Msf::zero()).unwrap();
+
+    assert_eq!(start, Msf::from_bcd(0, 2, 0).unwrap());
+
+    let mut sector = Sector::empty();
+
+    // Sector 17 is in the stored block
+    for &i in &[0, 5, 16, 17, sectors - 1] {
+        let msf = Msf::from_sector_index(i as u32 + LEAD_IN).unwrap();
+
+        image.read_sector(&mut sector, msf).unwrap();
+
+        let data = sector.data_2352().unwrap();
+
+        assert_eq!(&data[12..16], &raw[i * SECTOR_SIZE + 12..][..4]);
+        assert_eq!(data[100], i as u8);
+    }
+
+    let dir = image.dir.clone();
+
+    drop(image);
+
+    // The PBP file is kept open by the image
+    fs::remove_file(&path).unwrap();
+
+    assert!(!dir.exists());
+}
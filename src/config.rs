@@ -0,0 +1,293 @@
+//! Per-game configuration overrides.
+//!
+//! Some games need specific settings or hacks in order to run
+//! properly. The overrides are loaded from a TOML file containing one
+//! table per disc serial number (as found in SYSTEM.CNF), for
+//! instance:
+//!
+//! ```toml
+//! [SCUS-94900]
+//! title = "Crash Bandicoot"
+//! region = "NorthAmerica"
+//! renderer = "software"
+//! hle_bios = false
+//! unhandled_access = "OpenBus"
+//! widescreen = true
+//! pipeline_timing = true
+//! write_buffer = true
+//! true_color = false
+//! hacks = [ "skip_intro" ]
+//! ```
+//!
+//! Missing entries fall back to the global configuration.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use rustc_serialize::Decodable;
+use toml;
+
+use cdrom::disc::{Region, SerialNumber};
+use cpu::Cpu;
+use memory::UnhandledAccessPolicy;
+use logging;
+
+/// Settings overridden for a single game. `None` means that the
+/// global setting should be used.
+#[derive(Clone, Debug, Default)]
+pub struct GameConfig {
+    /// Human readable name of the game, meant to be displayed by the
+    /// frontend
+    pub title: Option<String>,
+    /// Force the console region, used to select the BIOS
+    pub region: Option<Region>,
+    /// Renderer mode. The core doesn't do any rendering so this is
+    /// only meaningful to the frontend.
+    pub renderer: Option<String>,
+    /// Enable or disable the HLE fast paths for the BIOS calls
+    pub hle_bios: Option<bool>,
+    /// What to do when the game accesses an unemulated address
+    pub unhandled_access: Option<UnhandledAccessPolicy>,
+    /// Enable or disable the GTE widescreen hack
+    pub widescreen: Option<bool>,
+    /// Enable or disable the slower pipeline timing model
+    pub pipeline_timing: Option<bool>,
+    /// Enable or disable the CPU write buffer model
+    pub write_buffer: Option<bool>,
+    /// Output 24bit colors instead of dithering down to 15bits
+    pub true_color: Option<bool>,
+    /// Game-specific hacks, looked up by name where they're
+    /// implemented
+    pub hacks: Vec<String>,
+}
+
+impl GameConfig {
+    fn from_raw(raw: RawGameConfig) -> Result<GameConfig, String> {
+        let region =
+            match raw.region {
+                Some(r) => match r.as_ref() {
+                    "Japan" => Some(Region::Japan),
+                    "NorthAmerica" => Some(Region::NorthAmerica),
+                    "Europe" => Some(Region::Europe),
+                    _ => return Err(format!("Unknown region {}", r)),
+                },
+                None => None,
+            };
+
+        let unhandled_access =
+            match raw.unhandled_access {
+                Some(p) => match p.as_ref() {
+                    "Panic" => Some(UnhandledAccessPolicy::Panic),
+                    "OpenBus" => Some(UnhandledAccessPolicy::OpenBus),
+                    "BusError" => Some(UnhandledAccessPolicy::BusError),
+                    _ => return Err(format!("Unknown access policy {}", p)),
+                },
+                None => None,
+            };
+
+        Ok(GameConfig {
+            title: raw.title,
+            region: region,
+            renderer: raw.renderer,
+            hle_bios: raw.hle_bios,
+            unhandled_access: unhandled_access,
+            widescreen: raw.widescreen,
+            pipeline_timing: raw.pipeline_timing,
+            write_buffer: raw.write_buffer,
+            true_color: raw.true_color,
+            hacks: raw.hacks.unwrap_or(Vec::new()),
+        })
+    }
+
+    /// Return true if the hack `name` is enabled for this game
+    pub fn has_hack(&self, name: &str) -> bool {
+        self.hacks.iter().any(|h| h == name)
+    }
+
+    /// Apply the overrides handled by the emulator core. The region
+    /// and renderer settings are left to the frontend.
+    pub fn apply(&self, cpu: &mut Cpu) {
+        if let Some(hle) = self.hle_bios {
+            cpu.set_hle_bios(hle);
+        }
+
+        if let Some(policy) = self.unhandled_access {
+            cpu.interconnect_mut().set_unhandled_access_policy(policy);
+        }
+
+        if let Some(widescreen) = self.widescreen {
+            cpu.set_widescreen_hack(widescreen);
+        }
+
+        if let Some(timing) = self.pipeline_timing {
+            cpu.set_pipeline_timing(timing);
+        }
+
+        if let Some(write_buffer) = self.write_buffer {
+            cpu.set_write_buffer(write_buffer);
+        }
+
+        if let Some(true_color) = self.true_color {
+            cpu.interconnect_mut().gpu_mut().set_true_color(true_color);
+        }
+    }
+}
+
+/// Representation of a game entry in the TOML file
+#[derive(RustcDecodable)]
+struct RawGameConfig {
+    title: Option<String>,
+    region: Option<String>,
+    renderer: Option<String>,
+    hle_bios: Option<bool>,
+    unhandled_access: Option<String>,
+    widescreen: Option<bool>,
+    pipeline_timing: Option<bool>,
+    write_buffer: Option<bool>,
+    true_color: Option<bool>,
+    hacks: Option<Vec<String>>,
+}
+
+/// Database of per-game overrides indexed by serial number
+pub struct Database {
+    games: HashMap<String, GameConfig>,
+}
+
+impl Database {
+    /// Create an empty database
+    pub fn new() -> Database {
+        Database {
+            games: HashMap::new(),
+        }
+    }
+
+    /// Parse a database from the contents of a TOML file
+    pub fn from_str(s: &str) -> Result<Database, Error> {
+        let mut parser = toml::Parser::new(s);
+
+        let table =
+            match parser.parse() {
+                Some(t) => t,
+                None => {
+                    let errors: Vec<String> =
+                        parser.errors.iter()
+                        .map(|e| format!("{}", e))
+                        .collect();
+
+                    return Err(Error::ParseError(errors.join("; ")));
+                }
+            };
+
+        let mut db = Database::new();
+
+        for (serial, value) in table {
+            let mut decoder = toml::Decoder::new(value);
+
+            let raw =
+                match RawGameConfig::decode(&mut decoder) {
+                    Ok(r) => r,
+                    Err(e) => return Err(Error::BadEntry(serial,
+                                                         format!("{}", e))),
+                };
+
+            let config =
+                match GameConfig::from_raw(raw) {
+                    Ok(c) => c,
+                    Err(e) => return Err(Error::BadEntry(serial, e)),
+                };
+
+            db.games.insert(serial.to_uppercase(), config);
+        }
+
+        info!(target: logging::CDROM,
+              "Loaded {} game configuration entries", db.games.len());
+
+        Ok(db)
+    }
+
+    /// Load a database from a TOML file
+    pub fn load_file(path: &Path) -> Result<Database, Error> {
+        let mut f = try!(File::open(path));
+
+        let mut s = String::new();
+
+        try!(f.read_to_string(&mut s));
+
+        Database::from_str(&s)
+    }
+
+    /// Return the overrides for the game with serial number `serial`
+    /// if there are any
+    pub fn lookup(&self, serial: SerialNumber) -> Option<&GameConfig> {
+        self.games.get(&serial.to_string())
+    }
+
+    /// Return the overrides for `serial` or the default (empty)
+    /// configuration if the game isn't in the database
+    pub fn config_for(&self, serial: SerialNumber) -> GameConfig {
+        match self.lookup(serial) {
+            Some(c) => c.clone(),
+            None => GameConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error while reading the configuration file
+    IoError(io::Error),
+    /// The file is not valid TOML
+    ParseError(String),
+    /// The entry for the given serial number is invalid
+    BadEntry(String, String),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+#[test]
+fn parse_database() {
+    let db = Database::from_str(r#"
+[SCUS-94900]
+title = "Crash Bandicoot"
+region = "NorthAmerica"
+hle_bios = true
+write_buffer = true
+hacks = [ "skip_intro" ]
+
+[slps-01234]
+unhandled_access = "OpenBus"
+widescreen = true
+true_color = true
+"#).unwrap();
+
+    let crash = &db.games["SCUS-94900"];
+
+    assert_eq!(crash.title, Some("Crash Bandicoot".into()));
+    assert_eq!(crash.region, Some(Region::NorthAmerica));
+    assert_eq!(crash.hle_bios, Some(true));
+    assert_eq!(crash.write_buffer, Some(true));
+    assert!(crash.has_hack("skip_intro"));
+    assert!(!crash.has_hack("other"));
+
+    let other = &db.games["SLPS-01234"];
+
+    assert_eq!(other.region, None);
+    assert_eq!(other.unhandled_access, Some(UnhandledAccessPolicy::OpenBus));
+    assert_eq!(other.widescreen, Some(true));
+    assert_eq!(crash.widescreen, None);
+    assert_eq!(other.true_color, Some(true));
+}
+
+#[test]
+fn bad_region() {
+    match Database::from_str("[SCUS-94900]\nregion = \"Mars\"\n") {
+        Err(Error::BadEntry(_, _)) => (),
+        _ => panic!("Bad region accepted"),
+    }
+}
@@ -0,0 +1,292 @@
+//! MIPS disassembler.
+//!
+//! Decodes the R3000A instruction set (including the COP0 and GTE
+//! instructions) into structured records so that external tools
+//! can analyze code without reimplementing the decoder. The
+//! `Display` implementations produce an objdump-like syntax.
+
+use std::fmt;
+
+use debugger::breakpoints::REGISTER_NAMES;
+use debugger::symbols::SymbolTable;
+
+use super::Instruction;
+use super::op::Op;
+
+/// A single decoded instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    /// Address of the instruction
+    pub addr: u32,
+    /// Raw instruction word
+    pub raw: u32,
+    /// Decoded operation
+    pub op: Op,
+    /// Lowercase mnemonic, "illegal" if the opcode is invalid
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    /// Destination of branches and jumps if it can be computed
+    /// statically (i.e. not for `jr` and `jalr`)
+    pub target: Option<u32>,
+    /// True for instructions followed by a delay slot
+    pub has_delay_slot: bool,
+}
+
+impl DecodedInstruction {
+    /// Format the instruction, replacing the branch targets with
+    /// the symbol covering them (if any)
+    pub fn to_string_with_symbols(&self, symbols: &SymbolTable) -> String {
+        let mut s = format!("{:08x}:  {:08x}  {}",
+                            self.addr, self.raw, self.mnemonic);
+
+        for (i, o) in self.operands.iter().enumerate() {
+            s.push_str(if i == 0 { " " } else { ", " });
+
+            match *o {
+                Operand::Target(t) => s.push_str(&symbols.describe(t)),
+                ref o => s.push_str(&o.to_string()),
+            }
+        }
+
+        s
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:08x}:  {:08x}  {}",
+                    self.addr, self.raw, self.mnemonic));
+
+        for (i, o) in self.operands.iter().enumerate() {
+            try!(write!(f, "{}{}", if i == 0 { " " } else { ", " }, o));
+        }
+
+        Ok(())
+    }
+}
+
+/// Instruction operand
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// General purpose register
+    Register(u8),
+    /// COP0 register
+    Cop0Register(u8),
+    /// GTE data register
+    GteData(u8),
+    /// GTE control register
+    GteControl(u8),
+    /// Signed immediate value
+    Signed(i32),
+    /// Unsigned immediate value (logical operations, shift amounts,
+    /// GTE command parameters...)
+    Unsigned(u32),
+    /// Memory operand `offset(base)`
+    Memory { base: u8, offset: i16 },
+    /// Branch or jump target
+    Target(u32),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Register(r) =>
+                write!(f, "${}", REGISTER_NAMES[r as usize]),
+            Operand::Cop0Register(r) => write!(f, "$cop0_{}", r),
+            Operand::GteData(r) => write!(f, "$gte_d{}", r),
+            Operand::GteControl(r) => write!(f, "$gte_c{}", r),
+            Operand::Signed(v) =>
+                if v < 0 {
+                    write!(f, "-0x{:x}", -(v as i64))
+                } else {
+                    write!(f, "0x{:x}", v)
+                },
+            Operand::Unsigned(v) => write!(f, "0x{:x}", v),
+            Operand::Memory { base, offset } =>
+                if offset < 0 {
+                    write!(f, "-0x{:x}(${})",
+                           -(offset as i32), REGISTER_NAMES[base as usize])
+                } else {
+                    write!(f, "0x{:x}(${})",
+                           offset, REGISTER_NAMES[base as usize])
+                },
+            Operand::Target(t) => write!(f, "0x{:08x}", t),
+        }
+    }
+}
+
+/// Decode the instruction `raw` located at `addr`
+pub fn decode(raw: u32, addr: u32) -> DecodedInstruction {
+    let i = Instruction(raw);
+
+    let s = Operand::Register(i.s().0 as u8);
+    let t = Operand::Register(i.t().0 as u8);
+    let d = Operand::Register(i.d().0 as u8);
+    let imm_se = Operand::Signed(i.imm_se() as i32);
+    let imm = Operand::Unsigned(i.imm());
+    let shift = Operand::Unsigned(i.shift());
+    let mem = Operand::Memory {
+        base: i.s().0 as u8,
+        offset: i.imm_se() as i16,
+    };
+
+    // Branch target relative to the delay slot
+    let branch = addr.wrapping_add(4).wrapping_add(i.imm_se() << 2);
+    // Jump target within the current 256MB segment
+    let jump = (addr.wrapping_add(4) & 0xf0000000) | (i.imm_jump() << 2);
+
+    let op = i.op();
+
+    let target =
+        match op {
+            Op::J | Op::Jal => Some(jump),
+            op if op.is_branch() => Some(branch),
+            _ => None,
+        };
+
+    let operands =
+        match op {
+            Op::Sll | Op::Srl | Op::Sra => vec![d, t, shift],
+            Op::Sllv | Op::Srlv | Op::Srav => vec![d, t, s],
+            Op::Jr | Op::Mthi | Op::Mtlo => vec![s],
+            Op::Jalr => vec![d, s],
+            Op::Syscall | Op::Break => vec![Operand::Unsigned(raw >> 6)],
+            Op::Mfhi | Op::Mflo => vec![d],
+            Op::Mult | Op::Multu | Op::Div | Op::Divu => vec![s, t],
+            Op::Add | Op::Addu | Op::Sub | Op::Subu |
+            Op::And | Op::Or | Op::Xor | Op::Nor |
+            Op::Slt | Op::Sltu => vec![d, s, t],
+            Op::Bltz | Op::Bgez | Op::Bltzal | Op::Bgezal |
+            Op::Blez | Op::Bgtz => vec![s, Operand::Target(branch)],
+            Op::J | Op::Jal => vec![Operand::Target(jump)],
+            Op::Beq | Op::Bne => vec![s, t, Operand::Target(branch)],
+            Op::Addi | Op::Addiu | Op::Slti | Op::Sltiu =>
+                vec![t, s, imm_se],
+            Op::Andi | Op::Ori | Op::Xori => vec![t, s, imm],
+            Op::Lui => vec![t, imm],
+            Op::Mfc0 | Op::Mtc0 =>
+                vec![t, Operand::Cop0Register(i.d().0 as u8)],
+            Op::Mfc2 | Op::Mtc2 => vec![t, Operand::GteData(i.d().0 as u8)],
+            Op::Cfc2 | Op::Ctc2 =>
+                vec![t, Operand::GteControl(i.d().0 as u8)],
+            // The parameters (shift, saturation, MVMVA matrix and
+            // vector selection) are shown as a single operand
+            // containing the raw bits [24:10].
+            Op::Gte => vec![Operand::Unsigned((raw >> 10) & 0x7fff)],
+            Op::Lb | Op::Lh | Op::Lwl | Op::Lw | Op::Lbu | Op::Lhu |
+            Op::Lwr | Op::Sb | Op::Sh | Op::Swl | Op::Sw | Op::Swr =>
+                vec![t, mem],
+            Op::Lwc2 | Op::Swc2 =>
+                vec![Operand::GteData(i.t().0 as u8), mem],
+            // COP1 and COP3 don't exist on the PlayStation, the other
+            // coprocessor loads and stores can't be used either
+            _ => vec![],
+        };
+
+    let mnemonic =
+        match op {
+            Op::Sll if raw == 0 => "nop",
+            Op::Gte => gte_command(raw),
+            op => op.mnemonic(),
+        };
+
+    DecodedInstruction {
+        addr: addr,
+        raw: raw,
+        op: op,
+        mnemonic: mnemonic,
+        operands: operands,
+        target: target,
+        has_delay_slot: op.has_delay_slot(),
+    }
+}
+
+/// Decode the little-endian machine code in `code`, the first
+/// instruction being located at `base_addr`. Trailing bytes which
+/// don't make a full instruction are ignored.
+pub fn decode_range(code: &[u8], base_addr: u32) -> Vec<DecodedInstruction> {
+    code.chunks(4)
+        .take_while(|c| c.len() == 4)
+        .enumerate()
+        .map(|(n, c)| {
+            let raw = (c[0] as u32) |
+                      ((c[1] as u32) << 8) |
+                      ((c[2] as u32) << 16) |
+                      ((c[3] as u32) << 24);
+
+            decode(raw, base_addr.wrapping_add(n as u32 * 4))
+        })
+        .collect()
+}
+
+/// Return the mnemonic of a GTE command
+fn gte_command(raw: u32) -> &'static str {
+    match raw & 0x3f {
+        0x01 => "rtps",
+        0x06 => "nclip",
+        0x0c => "op",
+        0x10 => "dpcs",
+        0x11 => "intpl",
+        0x12 => "mvmva",
+        0x13 => "ncds",
+        0x14 => "cdp",
+        0x16 => "ncdt",
+        0x1b => "nccs",
+        0x1c => "cc",
+        0x1e => "ncs",
+        0x20 => "nct",
+        0x28 => "sqr",
+        0x29 => "dcpl",
+        0x2a => "dpct",
+        0x2d => "avsz3",
+        0x2e => "avsz4",
+        0x30 => "rtpt",
+        0x3d => "gpf",
+        0x3e => "gpl",
+        0x3f => "ncct",
+        _ => "cop2",
+    }
+}
+
+#[test]
+fn disassemble() {
+    let code = [
+        // lui $t0, 0x8001
+        0x01, 0x80, 0x08, 0x3c,
+        // lw $v0, -4($sp)
+        0xfc, 0xff, 0xa2, 0x8f,
+        // bne $v0, $zero, -2
+        0xfe, 0xff, 0x40, 0x14,
+        // nop
+        0x00, 0x00, 0x00, 0x00,
+        // jal 0x80010000
+        0x00, 0x40, 0x00, 0x0c,
+        // rtpt
+        0x30, 0x00, 0x28, 0x4a,
+    ];
+
+    let insns = decode_range(&code, 0x80020000);
+
+    assert_eq!(insns.len(), 6);
+
+    assert_eq!(insns[0].to_string(), "80020000:  3c088001  lui $t0, 0x8001");
+    assert_eq!(insns[1].operands,
+               vec![Operand::Register(2),
+                    Operand::Memory { base: 29, offset: -4 }]);
+    assert_eq!(insns[1].to_string(),
+               "80020004:  8fa2fffc  lw $v0, -0x4($sp)");
+    assert_eq!(insns[2].mnemonic, "bne");
+    assert_eq!(insns[2].op, Op::Bne);
+    assert_eq!(insns[2].target, Some(0x80020004));
+    assert!(insns[2].has_delay_slot);
+    assert_eq!(insns[3].mnemonic, "nop");
+    assert_eq!(insns[4].target, Some(0x80010000));
+    assert_eq!(insns[5].mnemonic, "rtpt");
+
+    let mut symbols = SymbolTable::new();
+
+    symbols.add(0x80010000, 0, "main".into());
+
+    assert_eq!(insns[4].to_string_with_symbols(&symbols),
+               "80020010:  0c004000  jal main");
+}
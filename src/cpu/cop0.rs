@@ -3,6 +3,22 @@ use interrupt::InterruptState;
 /// Coprocessor 0: System control
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct Cop0 {
+    /// Cop0 register 3: Breakpoint on execute address (BPC)
+    bpc: u32,
+    /// Cop0 register 5: Breakpoint on data access address (BDA)
+    bda: u32,
+    /// Cop0 register 6: Target of the last jump taken before an
+    /// exception (JUMPDEST)
+    jump_dest: u32,
+    /// Cop0 register 7: Breakpoint control (DCIC)
+    dcic: u32,
+    /// Cop0 register 8: Address latched on address error exceptions
+    /// (BAD_VADDR)
+    bad_vaddr: u32,
+    /// Cop0 register 9: Breakpoint on data access mask (BDAM)
+    bdam: u32,
+    /// Cop0 register 11: Breakpoint on execute mask (BPCM)
+    bpcm: u32,
     /// Cop0 register 12: Status register
     sr: u32,
     /// Cop0 register 13: Cause register
@@ -15,6 +31,13 @@ impl Cop0 {
 
     pub fn new() -> Cop0 {
         Cop0 {
+            bpc:   0,
+            bda:   0,
+            jump_dest: 0,
+            dcic:  0,
+            bad_vaddr: 0,
+            bdam:  0,
+            bpcm:  0,
             sr:    0,
             cause: 0,
             epc:   0,
@@ -48,6 +71,116 @@ impl Cop0 {
         self.epc
     }
 
+    pub fn bpc(&self) -> u32 {
+        self.bpc
+    }
+
+    pub fn set_bpc(&mut self, bpc: u32) {
+        self.bpc = bpc;
+    }
+
+    pub fn bda(&self) -> u32 {
+        self.bda
+    }
+
+    pub fn set_bda(&mut self, bda: u32) {
+        self.bda = bda;
+    }
+
+    pub fn jump_dest(&self) -> u32 {
+        self.jump_dest
+    }
+
+    pub fn set_jump_dest(&mut self, jump_dest: u32) {
+        self.jump_dest = jump_dest;
+    }
+
+    pub fn bad_vaddr(&self) -> u32 {
+        self.bad_vaddr
+    }
+
+    pub fn set_bad_vaddr(&mut self, bad_vaddr: u32) {
+        self.bad_vaddr = bad_vaddr;
+    }
+
+    pub fn dcic(&self) -> u32 {
+        self.dcic
+    }
+
+    pub fn set_dcic(&mut self, dcic: u32) {
+        // Bits [22:6] are hardwired to 0 except for the jump
+        // redirection enable in bits [13:12]
+        self.dcic = dcic & 0xff80303f;
+    }
+
+    pub fn bdam(&self) -> u32 {
+        self.bdam
+    }
+
+    pub fn set_bdam(&mut self, bdam: u32) {
+        self.bdam = bdam;
+    }
+
+    pub fn bpcm(&self) -> u32 {
+        self.bpcm
+    }
+
+    pub fn set_bpcm(&mut self, bpcm: u32) {
+        self.bpcm = bpcm;
+    }
+
+    /// Return true if all the `enable` bits are set in DCIC along
+    /// with the two master enables shared by all breakpoints.
+    fn breakpoint_armed(&self, enable: u32) -> bool {
+        let enable = enable | DCIC_MASTER_ENABLE | DCIC_SUPER_MASTER_ENABLE1;
+
+        self.dcic & enable == enable
+    }
+
+    /// Check if executing the instruction at `pc` hits the execution
+    /// breakpoint. The hit is latched in DCIC's status bits. Returns
+    /// true if the breakpoint exception must be triggered.
+    pub fn code_breakpoint(&mut self, pc: u32) -> bool {
+        if !self.breakpoint_armed(1 << 24) {
+            return false;
+        }
+
+        if (pc ^ self.bpc) & self.bpcm != 0 {
+            return false;
+        }
+
+        // "Any break" and "BPC code break" status flags
+        self.dcic |= 0b11;
+
+        self.dcic & DCIC_SUPER_MASTER_ENABLE2 != 0
+    }
+
+    /// Check if a memory access at `addr` hits the data access
+    /// breakpoint. The hit is latched in DCIC's status bits. Returns
+    /// true if the breakpoint exception must be triggered.
+    pub fn data_breakpoint(&mut self, addr: u32, is_write: bool) -> bool {
+        // Bit 25 enables data breakpoints, bits 26 and 27 select
+        // respectively read and write accesses.
+        let (enable, status) =
+            match is_write {
+                false => ((1 << 25) | (1 << 26), 1 << 3),
+                true  => ((1 << 25) | (1 << 27), 1 << 4),
+            };
+
+        if !self.breakpoint_armed(enable) {
+            return false;
+        }
+
+        if (addr ^ self.bda) & self.bdam != 0 {
+            return false;
+        }
+
+        // "Any break", "BDA data break" and read/write status flags
+        self.dcic |= 0b101 | status;
+
+        self.dcic & DCIC_SUPER_MASTER_ENABLE2 != 0
+    }
+
     pub fn cache_isolated(&self) -> bool {
         self.sr & 0x10000 != 0
     }
@@ -93,6 +226,20 @@ impl Cop0 {
         }
     }
 
+    /// Enter a breakpoint exception triggered by the DCIC
+    /// hardware. Those use a dedicated "debug" vector instead of the
+    /// common exception handler. Returns the address of the handler.
+    pub fn enter_debug_exception(&mut self,
+                                 pc: u32,
+                                 in_delay_slot: bool) -> u32 {
+        self.enter_exception(Exception::Break, pc, in_delay_slot);
+
+        match self.sr & (1 << 22) != 0 {
+            true  => 0xbfc00140,
+            false => 0x80000040,
+        }
+    }
+
     /// The counterpart to "enter_exception": shift SR's mode back
     /// into place. Doesn't touch CAUSE or EPC however.
     pub fn return_from_exception(&mut self) {
@@ -137,6 +284,10 @@ pub enum Exception {
     LoadAddressError = 0x4,
     /// Address error on store
     StoreAddressError = 0x5,
+    /// Bus error on instruction fetch
+    BusErrorInstruction = 0x6,
+    /// Bus error on data load or store
+    BusErrorData = 0x7,
     /// System call (caused by the SYSCALL opcode)
     SysCall = 0x8,
     /// Breakpoint (caused by the BREAK opcode)
@@ -148,3 +299,11 @@ pub enum Exception {
     /// Arithmetic overflow
     Overflow = 0xc,
 }
+
+/// DCIC bit 23: "super-master" enable for all breakpoints
+const DCIC_SUPER_MASTER_ENABLE1: u32 = 1 << 23;
+/// DCIC bit 30: master enable for the execution and data breakpoints
+const DCIC_MASTER_ENABLE: u32 = 1 << 30;
+/// DCIC bit 31: when set breakpoint hits trigger an exception,
+/// otherwise they're only latched in the status bits
+const DCIC_SUPER_MASTER_ENABLE2: u32 = 1 << 31;
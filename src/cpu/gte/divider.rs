@@ -2,8 +2,20 @@
 /// by 0 shouldn't occur since we clip against the near plane but they
 /// would saturate to 0x1ffff anyway.
 ///
-/// The algorithm is based on Newton–Raphson, I copied mednafen's
-/// implementation.
+/// This is the hardware's algorithm, using only integer arithmetic so
+/// that the results match bit-for-bit (including the rounding errors,
+/// which are not the same as an exact division):
+///
+/// 1. The divisor is normalized so that its MSB is set and the
+///    numerator is shifted by the same amount.
+/// 2. An initial approximation of the reciprocal is looked up in
+///    `UNR_TABLE` using the 8 bits following the MSB (rounded).
+/// 3. A single Newton-Raphson iteration refines it to 17 bits.
+/// 4. The numerator is multiplied by the reciprocal, rounded and
+///    saturated.
+///
+/// I copied mednafen's implementation which matches No$'s description
+/// of the UNR division.
 pub fn divide(numerator: u16, divisor: u16) -> u32 {
     let shift = divisor.leading_zeros();
 
@@ -21,6 +33,8 @@ pub fn divide(numerator: u16, divisor: u16) -> u32 {
     }
 }
 
+/// Compute the reciprocal of the normalized divisor `d` as a 0.17
+/// fixed point value
 fn reciprocal(d: u16) -> u32 {
     let index = ((d & 0x7fff) + 0x40) >> 7;
 
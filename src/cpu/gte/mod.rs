@@ -1,6 +1,8 @@
 //! Geometry Transform Engine (Coprocessor 2) emulation
 
 use std::{i16, u16};
+use logging;
+use pgxp::PreciseVertex;
 
 mod divider;
 
@@ -61,6 +63,12 @@ pub struct Gte {
     lzcr: u8,
     /// Register 23: 32bit read/write but not used for anything
     reg_23: u32,
+    /// Widescreen hack: when true the projected X coordinates are
+    /// scaled by 3/4 to render 4:3 games in 16:9
+    widescreen: bool,
+    /// Full precision values of the XY FIFO entries computed by the
+    /// RTPS/RTPT commands for PGXP
+    precise_xy_fifo: [PreciseVertex; 4],
 }
 
 impl Gte {
@@ -92,9 +100,36 @@ impl Gte {
             // to 0.
             lzcr: 32,
             reg_23: 0,
+            widescreen: false,
+            precise_xy_fifo: [PreciseVertex {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+                value: 0,
+            }; 4],
         }
     }
 
+    pub fn widescreen(&self) -> bool {
+        self.widescreen
+    }
+
+    /// Enable or disable the widescreen hack. This is not accurate:
+    /// 2D elements drawn without the GTE (HUD, menus...) won't be
+    /// affected and the game will still cull the polygons outside of
+    /// its original 4:3 field of view.
+    pub fn set_widescreen(&mut self, widescreen: bool) {
+        self.widescreen = widescreen;
+    }
+
+    /// Return the full precision value of the XY FIFO entry `index`
+    /// (SXY0, SXY1 or SXY2) if it's still valid
+    pub fn precise_sxy(&self, index: usize) -> Option<PreciseVertex> {
+        let value = self.data(12 + index as u32);
+
+        self.precise_xy_fifo[index].validate(value)
+    }
+
     /// Execute GTE command
     pub fn command(&mut self, command: u32) {
         let opcode = command & 0x3f;
@@ -637,7 +672,8 @@ impl Gte {
 
                 self.lzcr = tmp.leading_zeros() as u8;
             }
-            31 => warn!("Write to read-only GTE data register 31"),
+            31 => warn!(target: logging::GTE,
+                        "Write to read-only GTE data register 31"),
             _  => unreachable!(),
         }
     }
@@ -1300,6 +1336,14 @@ impl Gte {
         let ofx = self.ofx as i64;
         let ofy = self.ofy as i64;
 
+        let x =
+            if self.widescreen {
+                // Squeeze the X axis to fit a 16:9 display
+                (x * 3) / 4
+            } else {
+                x
+            };
+
         // Project X and Y onto the plane
         let screen_x = x * factor + ofx;
         let screen_y = y * factor + ofy;
@@ -1307,6 +1351,9 @@ impl Gte {
         self.check_mac_overflow(screen_x);
         self.check_mac_overflow(screen_y);
 
+        let precise_x = screen_x as f64 / 65536.;
+        let precise_y = screen_y as f64 / 65536.;
+
         let screen_x = (screen_x >> 16) as i32;
         let screen_y = (screen_y >> 16) as i32;
 
@@ -1318,6 +1365,21 @@ impl Gte {
         self.xy_fifo[1] = self.xy_fifo[2];
         self.xy_fifo[2] = self.xy_fifo[3];
 
+        // Keep the unrounded values around for PGXP, clamped to the
+        // same range as the integer coordinates
+        let clamp = |v: f32| v.max(-0x400 as f32).min(0x3ff as f32);
+
+        self.precise_xy_fifo[3] = PreciseVertex {
+            x: clamp(precise_x as f32),
+            y: clamp(precise_y as f32),
+            z: z_shifted as f32,
+            value: self.data(15),
+        };
+
+        self.precise_xy_fifo[0] = self.precise_xy_fifo[1];
+        self.precise_xy_fifo[1] = self.precise_xy_fifo[2];
+        self.precise_xy_fifo[2] = self.precise_xy_fifo[3];
+
         // return projection factor
         projection_factor
     }
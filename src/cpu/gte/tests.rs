@@ -60,67 +60,335 @@ struct Config {
 
 impl Config {
     fn make_gte(&self) -> Gte {
-        let mut gte = Gte::new();
+        make_gte(self.controls, self.data)
+    }
+
+    fn validate(&self, gte: Gte) {
+        validate(gte, self.controls, self.data)
+    }
+}
+
+/// Build a GTE with the given register values, missing registers are
+/// set to 0
+fn make_gte(controls: &[(u8, u32)], data: &[(u8, u32)]) -> Gte {
+    let mut gte = Gte::new();
+
+    for &(reg, val) in controls {
+        gte.set_control(reg as u32, val);
+    }
 
-        for &(reg, val) in self.controls {
-            gte.set_control(reg as u32, val);
+    for &(reg, val) in data {
+        if reg == 15 {
+            // Writing to 14 should set this register and writing
+            // here will push a new entry onto the XY_FIFO which
+            // will change the previous values.
+            continue;
         }
 
-        for &(reg, val) in self.data {
-            if reg == 15 {
-                // Writing to 14 should set this register and writing
-                // here will push a new entry onto the XY_FIFO which
-                // will change the previous values.
-                continue;
-            }
-
-            if reg == 28 {
-                // This sets the IR1...3 registers MSB but those
-                // values should have been set through registers
-                // 9...11
-                continue;
-            }
-
-            if reg == 29 {
-                // This register is read only
-                continue;
-            }
-
-            gte.set_data(reg as u32, val);
+        if reg == 28 {
+            // This sets the IR1...3 registers MSB but those
+            // values should have been set through registers
+            // 9...11
+            continue;
         }
 
-        gte
+        if reg == 29 {
+            // This register is read only
+            continue;
+        }
+
+        gte.set_data(reg as u32, val);
     }
 
-    fn validate(&self, gte: Gte) {
-        let mut error_count = 0u32;
+    gte
+}
 
-        for &(reg, val) in self.controls {
-            let v = gte.control(reg as u32);
+/// Check that the registers listed have the expected values, the
+/// other registers are ignored
+fn validate(gte: Gte, controls: &[(u8, u32)], data: &[(u8, u32)]) {
+    let mut error_count = 0u32;
 
-            if v != val {
-                println!("Control register {}: expected 0x{:08x} got 0x{:08x}",
-                         reg, val, v);
-                error_count += 1;
-            }
+    for &(reg, val) in controls {
+        let v = gte.control(reg as u32);
+
+        if v != val {
+            println!("Control register {}: expected 0x{:08x} got 0x{:08x}",
+                     reg, val, v);
+            error_count += 1;
         }
+    }
 
-        for &(reg, val) in self.data {
-            let v = gte.data(reg as u32);
+    for &(reg, val) in data {
+        let v = gte.data(reg as u32);
 
-            if v != val {
-                println!("Data register {}: expected 0x{:08x} got 0x{:08x}",
-                         reg, val, v);
-                error_count += 1;
-            }
+        if v != val {
+            println!("Data register {}: expected 0x{:08x} got 0x{:08x}",
+                     reg, val, v);
+            error_count += 1;
         }
+    }
+
+    if error_count > 0 {
+        panic!("{} registers errors", error_count);
+    }
+}
+
+#[test]
+fn gte_edge_cases() {
+    for test in EDGE_CASES {
+        println!("Test: '{}'", test.desc);
+        println!("Command: 0x{:08x}", test.command);
+
+        let mut gte = test.initial.make_gte();
+
+        gte.command(test.command);
+
+        test.result.validate(gte);
+    }
+}
+
+#[test]
+fn gte_divide() {
+    use super::divider::divide;
+
+    // 0.5 in 1.16 fixed point
+    assert_eq!(divide(0x1000, 0x2000), 0x8000);
+    assert_eq!(divide(0, 0x1234), 0);
+    // Results of 2.0 and above saturate
+    assert_eq!(divide(0x2000, 0x1000), 0x1ffff);
+    assert_eq!(divide(0xffff, 1), 0x1ffff);
+}
+
+/// Run the test vectors from the file pointed to by the
+/// `RUSTATION_GTE_VECTORS` environment variable. This lets us
+/// validate large sets of captures from real hardware without
+/// including them in the source. The format is:
+///
+/// ```text
+/// # Comment
+/// command 0x00080001
+/// in c26=0x1000 d0=0x00010001
+/// out c31=0x00020000 d19=0x1
+/// ```
+///
+/// `c` registers are control registers and `d` data registers. Only
+/// the registers listed in `out` are validated.
+#[test]
+#[ignore]
+fn gte_vectors_file() {
+    use std::env;
+    use std::fs::File;
+    use std::io::Read;
+
+    let path =
+        match env::var_os("RUSTATION_GTE_VECTORS") {
+            Some(p) => p,
+            None => panic!("RUSTATION_GTE_VECTORS is not set"),
+        };
+
+    let mut vectors = String::new();
+
+    File::open(path).unwrap().read_to_string(&mut vectors).unwrap();
+
+    let mut count = 0;
+
+    for v in parse_vectors(&vectors) {
+        println!("Vector at line {}: command 0x{:08x}", v.line, v.command);
+
+        let mut gte = make_gte(&v.controls_in, &v.data_in);
+
+        gte.command(v.command);
+
+        validate(gte, &v.controls_out, &v.data_out);
+
+        count += 1;
+    }
+
+    println!("{} vectors passed", count);
+}
+
+/// Test vector loaded from a file
+struct Vector {
+    /// Line where the vector starts, for error reporting
+    line: usize,
+    command: u32,
+    controls_in: Vec<(u8, u32)>,
+    data_in: Vec<(u8, u32)>,
+    controls_out: Vec<(u8, u32)>,
+    data_out: Vec<(u8, u32)>,
+}
+
+fn parse_vectors(s: &str) -> Vec<Vector> {
+    let mut vectors: Vec<Vector> = Vec::new();
+
+    for (n, line) in s.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+
+        let kind = words.next().unwrap();
+
+        if kind == "command" {
+            let command = parse_u32(words.next().unwrap());
+
+            vectors.push(Vector {
+                line: n + 1,
+                command: command,
+                controls_in: Vec::new(),
+                data_in: Vec::new(),
+                controls_out: Vec::new(),
+                data_out: Vec::new(),
+            });
+
+            continue;
+        }
+
+        let v =
+            match vectors.last_mut() {
+                Some(v) => v,
+                None => panic!("line {}: no command", n + 1),
+            };
+
+        for reg in words {
+            let (name, val) = reg.split_at(reg.find('=').unwrap());
+
+            let index = name[1..].parse::<u8>().unwrap();
+            let val = parse_u32(&val[1..]);
 
-        if error_count > 0 {
-            panic!("{} registers errors", error_count);
+            let regs =
+                match (kind, &name[0..1]) {
+                    ("in", "c") => &mut v.controls_in,
+                    ("in", "d") => &mut v.data_in,
+                    ("out", "c") => &mut v.controls_out,
+                    ("out", "d") => &mut v.data_out,
+                    _ => panic!("line {}: bad register '{}'", n + 1, reg),
+                };
+
+            regs.push((index, val));
         }
     }
+
+    vectors
+}
+
+fn parse_u32(s: &str) -> u32 {
+    if s.starts_with("0x") {
+        u32::from_str_radix(&s[2..], 16).unwrap()
+    } else if s.starts_with('-') {
+        s.parse::<i32>().unwrap() as u32
+    } else {
+        s.parse::<u32>().unwrap()
+    }
+}
+
+#[test]
+fn vector_parser() {
+    let v = parse_vectors("# RTPS\n\
+                           command 0x00080001\n\
+                           in c26=0x1000 d9=-1\n\
+                           out c31=0x00020000\n");
+
+    assert_eq!(v.len(), 1);
+    assert_eq!(v[0].command, 0x00080001);
+    assert_eq!(v[0].controls_in, vec![(26, 0x1000)]);
+    assert_eq!(v[0].data_in, vec![(9, 0xffffffff)]);
+    assert_eq!(v[0].controls_out, vec![(31, 0x00020000)]);
 }
 
+/// Hand-written vectors targeting the saturation and overflow flags,
+/// the expected values come from the No$ specification. Only the
+/// relevant registers are checked.
+static EDGE_CASES: &'static [Test] = &[
+    Test {
+        desc: "GTE_RTPS divide overflow (H >= 2 * SZ3)",
+        initial: Config {
+            controls: &[
+                // TRZ
+                (7, 0x00000001),
+                // H
+                (26, 0x00001000),
+                ],
+            data: &[],
+        },
+        command: 0x00080001,
+        result: Config {
+            controls: &[
+                (31, 0x00020000),
+                ],
+            data: &[
+                // SZ3
+                (19, 0x00000001),
+                ],
+        },
+    },
+    Test {
+        desc: "GTE_GPF IR1 and color saturation, lm=0, sf=0",
+        initial: Config {
+            controls: &[],
+            data: &[
+                // IR0
+                (8, 0x00001000),
+                // IR1
+                (9, 0x00007fff),
+                ],
+        },
+        command: 0x0000003d,
+        result: Config {
+            controls: &[
+                (31, 0x81200000),
+                ],
+            data: &[
+                (9, 0x00007fff),
+                (25, 0x07fff000),
+                ],
+        },
+    },
+    Test {
+        desc: "GTE_GPF negative IR1 clamped to 0, lm=1, sf=1",
+        initial: Config {
+            controls: &[],
+            data: &[
+                (8, 0x00001000),
+                (9, 0xfffff000),
+                ],
+        },
+        command: 0x0008043d,
+        result: Config {
+            controls: &[
+                (31, 0x81200000),
+                ],
+            data: &[
+                (9, 0x00000000),
+                (25, 0xfffff000),
+                ],
+        },
+    },
+    Test {
+        desc: "GTE_NCLIP MAC0 positive overflow",
+        initial: Config {
+            controls: &[],
+            data: &[
+                (12, 0x00007fff),
+                (13, 0x7fff0000),
+                (14, 0x80008000),
+                ],
+        },
+        command: 0x00000006,
+        result: Config {
+            controls: &[
+                (31, 0x80010000),
+                ],
+            data: &[
+                (24, 0xbffe0001),
+                ],
+        },
+    },
+];
+
 /// Reference data generated using tests/gte_commands/main.s in
 /// https://github.com/simias/psx-hardware-tests and running it on the
 /// real console.
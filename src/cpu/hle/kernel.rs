@@ -0,0 +1,1075 @@
+//! Kernel used by the HLE BIOS (`Bios::hle`) to boot without a BIOS
+//! image.
+//!
+//! The kernel state lives here instead of in the kernel RAM area, so
+//! software poking directly at the BIOS tables (event control blocks,
+//! TCBs, the function tables at 0x200...) won't work. What's
+//! implemented:
+//!
+//! * Boot: the executable named in the disc's SYSTEM.CNF is loaded
+//!   and started.
+//! * Events: OpenEvent, CloseEvent, WaitEvent, TestEvent,
+//!   EnableEvent, DisableEvent, DeliverEvent and UnDeliverEvent. The
+//!   root counter events (VBlank included) are delivered by the
+//!   interrupt handler.
+//! * Threads: OpenThread, CloseThread and ChangeThread.
+//! * Exceptions: the interrupt priority chains (SysEnqIntRP and
+//!   SysDeqIntRP), the custom exit from exception and
+//!   ReturnFromException. The EnterCriticalSection and
+//!   ExitCriticalSection syscalls.
+//!
+//! The BIOS controller and memory card drivers and the file functions
+//! are missing, the calls to unimplemented functions return 0.
+//!
+//! Guest callbacks (event handlers and priority chain functions) are
+//! called with their return address set to `CALLBACK_RETURN` where
+//! the CPU is intercepted to resume the dispatch.
+
+use shared::SharedState;
+use interrupt::Interrupt;
+use bios::hle::{ROM_BASE, HALT_OFFSET};
+use parallel_io::exe_loader::ExeLoader;
+use logging;
+
+use super::super::{Cpu, RegisterIndex, Instruction};
+use super::super::cop0::Exception;
+use super::{load_byte, store_byte, jump, V0, A0, A1, A2, RA};
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Kernel {
+    /// Event control blocks
+    events: Vec<Event>,
+    /// Thread control blocks, thread 0 is the one started at boot
+    threads: Vec<Option<Thread>>,
+    /// Index of the running thread
+    current_thread: usize,
+    /// Heads of the interrupt priority chains set up by SysEnqIntRP
+    chains: [u32; 4],
+    /// Address of the `jmp_buf` used to exit from exceptions, 0 to
+    /// return to the interrupted code
+    custom_exit: u32,
+    /// Root counter interrupts acknowledged by the kernel, changed
+    /// with ChangeClearRCnt
+    clear_rcnt: [bool; 4],
+    /// Guest callbacks being dispatched. Kernel calls can be
+    /// interrupted while calling back into the guest so this is a
+    /// stack.
+    dispatch: Vec<Dispatch>,
+}
+
+impl Kernel {
+    pub fn new() -> Kernel {
+        let mut threads = vec![None; MAX_THREADS];
+
+        threads[0] = Some(Thread::new());
+
+        Kernel {
+            events: vec![Event::free(); MAX_EVENTS],
+            threads: threads,
+            current_thread: 0,
+            chains: [0; 4],
+            custom_exit: 0,
+            clear_rcnt: [true; 4],
+            dispatch: Vec::new(),
+        }
+    }
+
+    fn thread_mut(&mut self) -> &mut Thread {
+        self.threads[self.current_thread]
+            .as_mut()
+            .expect("No current thread")
+    }
+
+    fn event_index(&self, handle: u32) -> Option<usize> {
+        if handle & 0xffff0000 != EVENT_HANDLE {
+            return None;
+        }
+
+        let index = (handle & 0xffff) as usize;
+
+        match self.events.get(index) {
+            Some(e) if e.status != EVENT_FREE => Some(index),
+            _ => None,
+        }
+    }
+
+    fn thread_index(&self, handle: u32) -> Option<usize> {
+        if handle & 0xffff0000 != THREAD_HANDLE {
+            return None;
+        }
+
+        let index = (handle & 0xffff) as usize;
+
+        match self.threads.get(index) {
+            Some(&Some(_)) => Some(index),
+            _ => None,
+        }
+    }
+
+    fn open_event(&mut self,
+                  class: u32,
+                  spec: u32,
+                  mode: u32,
+                  handler: u32) -> u32 {
+        match self.events.iter().position(|e| e.status == EVENT_FREE) {
+            Some(i) => {
+                self.events[i] = Event {
+                    class: class,
+                    spec: spec,
+                    mode: mode,
+                    status: EVENT_DISABLED,
+                    handler: handler,
+                };
+
+                EVENT_HANDLE | i as u32
+            }
+            None => {
+                warn!(target: logging::HLE, "No free event");
+                !0
+            }
+        }
+    }
+
+    /// Set the status of event `handle`. Returns 1 on success, 0 if
+    /// the handle is invalid.
+    fn set_event_status(&mut self, handle: u32, status: u32) -> u32 {
+        match self.event_index(handle) {
+            Some(i) => {
+                self.events[i].status = status;
+                1
+            }
+            None => 0,
+        }
+    }
+
+    /// Returns 1 if the event was ready, in which case it's re-armed
+    fn test_event(&mut self, handle: u32) -> u32 {
+        match self.event_index(handle) {
+            Some(i) if self.events[i].status == EVENT_READY => {
+                self.events[i].status = EVENT_ENABLED;
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Signal the enabled events matching `class` and `spec`. Returns
+    /// the handlers to call for the events in callback mode.
+    fn deliver_event(&mut self, class: u32, spec: u32) -> Vec<GuestCall> {
+        let mut calls = Vec::new();
+
+        for e in self.events.iter_mut() {
+            if e.status != EVENT_ENABLED || e.class != class ||
+                e.spec != spec {
+                continue;
+            }
+
+            match e.mode {
+                EVENT_MODE_CALLBACK =>
+                    calls.push(GuestCall::Event(e.handler)),
+                EVENT_MODE_READY => e.status = EVENT_READY,
+                _ => (),
+            }
+        }
+
+        calls
+    }
+
+    fn undeliver_event(&mut self, class: u32, spec: u32) {
+        for e in self.events.iter_mut() {
+            if e.status == EVENT_READY && e.mode == EVENT_MODE_READY &&
+                e.class == class && e.spec == spec {
+                e.status = EVENT_ENABLED;
+            }
+        }
+    }
+
+    fn open_thread(&mut self, pc: u32, sp: u32, gp: u32) -> u32 {
+        match self.threads.iter().position(|t| t.is_none()) {
+            Some(i) => {
+                let mut thread = Thread::new();
+
+                thread.pc = pc;
+                thread.regs[GP.0 as usize] = gp;
+                thread.regs[SP.0 as usize] = sp;
+                thread.regs[FP.0 as usize] = sp;
+
+                self.threads[i] = Some(thread);
+
+                THREAD_HANDLE | i as u32
+            }
+            None => {
+                warn!(target: logging::HLE, "No free thread");
+                !0
+            }
+        }
+    }
+
+    fn close_thread(&mut self, handle: u32) -> u32 {
+        match self.thread_index(handle) {
+            // The running thread can't be closed
+            Some(i) if i != self.current_thread => {
+                self.threads[i] = None;
+                1
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Event control block
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+struct Event {
+    class: u32,
+    spec: u32,
+    mode: u32,
+    status: u32,
+    /// Function called on delivery in `EVENT_MODE_CALLBACK`
+    handler: u32,
+}
+
+impl Event {
+    fn free() -> Event {
+        Event {
+            class: 0,
+            spec: 0,
+            mode: 0,
+            status: EVENT_FREE,
+            handler: 0,
+        }
+    }
+}
+
+/// Thread control block
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+struct Thread {
+    regs: [u32; 32],
+    hi: u32,
+    lo: u32,
+    /// Where to resume the thread
+    pc: u32,
+}
+
+impl Thread {
+    fn new() -> Thread {
+        Thread {
+            regs: [0; 32],
+            hi: 0,
+            lo: 0,
+            pc: 0,
+        }
+    }
+}
+
+/// Guest function called by the kernel
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+enum GuestCall {
+    /// Handler of an event in callback mode
+    Event(u32),
+    /// First function of the priority chain entry at this address.
+    /// If it returns a non-0 value the second function is called
+    /// with that value as argument.
+    ChainFirst(u32),
+    /// Function and argument
+    ChainSecond(u32, u32),
+}
+
+/// Guest callbacks pending for an exception or kernel call
+#[derive(RustcDecodable, RustcEncodable)]
+struct Dispatch {
+    calls: Vec<GuestCall>,
+    /// Callback currently running
+    current: Option<GuestCall>,
+    /// What to do once all the callbacks have returned
+    then: Continuation,
+}
+
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+enum Continuation {
+    /// Leave the exception handler
+    Exception,
+    /// Return `v0` to the kernel call's caller at `ra`
+    Return(u32, u32),
+}
+
+/// Result of a kernel function
+pub enum Outcome {
+    /// Return this value to the caller
+    Return(u32),
+    /// The kernel took care of moving the PC
+    Jumped,
+    /// The function is waiting for an interrupt, the CPU must run the
+    /// vector's idle loop
+    Wait,
+}
+
+/// Called at reset, before the hook slot
+pub fn init(cpu: &mut Cpu, shared: &mut SharedState) {
+    info!(target: logging::HLE, "Starting the HLE kernel");
+
+    cpu.hle.kernel = Kernel::new();
+
+    // Kernel mode, interrupts disabled, exception vector in RAM
+    cpu.cop0.set_sr(0);
+
+    {
+        let irq = shared.irq_state_mut();
+
+        irq.set_mask(0);
+        irq.ack(0);
+    }
+
+    // The vectors are intercepted before they run. The only exception
+    // is WaitEvent which lets the CPU spin there until the event
+    // fires, giving the interrupts a chance to be serviced.
+    for &addr in &[0x80, 0xa0, 0xb0, 0xc0, CALLBACK_RETURN] {
+        store_word(cpu, addr, SPIN);
+        store_word(cpu, addr + 4, 0);
+    }
+
+    cpu.set_reg(SP, KERNEL_SP);
+}
+
+/// Called from the boot slot: load the disc's executable and jump to
+/// its entry point
+pub fn boot(cpu: &mut Cpu) {
+    let exe =
+        cpu.inter.cdrom_mut().disc_mut().map(|d| d.read_boot_executable());
+
+    let exe =
+        match exe {
+            Some(e) => e,
+            None => {
+                error!(target: logging::HLE, "No disc to boot, halting");
+                return halt(cpu);
+            }
+        };
+
+    let exe =
+        match exe {
+            Ok(e) => e,
+            Err(e) => {
+                error!(target: logging::HLE,
+                       "Couldn't read the boot executable: {:?}", e);
+                return halt(cpu);
+            }
+        };
+
+    match ExeLoader::load(&mut &exe[..]) {
+        Ok(loader) => start_executable(cpu, &loader),
+        Err(e) => {
+            error!(target: logging::HLE,
+                   "Invalid boot executable: {:?}", e);
+            halt(cpu);
+        }
+    }
+}
+
+/// Copy `loader`'s executable in RAM and jump to its entry point
+fn start_executable(cpu: &mut Cpu, loader: &ExeLoader) {
+    let (base, len) = loader.memfill();
+
+    for i in 0..len {
+        store_byte(cpu, base.wrapping_add(i), 0);
+    }
+
+    let (base, text) = loader.text();
+
+    for (i, &b) in text.iter().enumerate() {
+        store_byte(cpu, base.wrapping_add(i as u32), b);
+    }
+
+    cpu.icache.invalidate_all();
+
+    let sp =
+        match loader.initial_sp() {
+            0 => KERNEL_SP,
+            sp => sp,
+        };
+
+    cpu.set_reg(GP, loader.initial_gp());
+    cpu.set_reg(SP, sp);
+    cpu.set_reg(FP, sp);
+    cpu.set_reg(RA, ROM_BASE + HALT_OFFSET);
+
+    // Interrupts enabled
+    cpu.cop0.set_sr(0x401);
+
+    info!(target: logging::HLE,
+          "Starting executable at 0x{:08x}", loader.entry());
+
+    jump(cpu, loader.entry());
+}
+
+/// Hang the CPU in the ROM's infinite loop
+fn halt(cpu: &mut Cpu) {
+    jump(cpu, ROM_BASE + HALT_OFFSET);
+}
+
+/// Called from the exception vector
+pub fn exception(cpu: &mut Cpu, shared: &mut SharedState) {
+    let cause = cpu.cop0.cause(*shared.irq_state());
+    let code = (cause >> 2) & 0x1f;
+
+    if code == Exception::SysCall as u32 {
+        return syscall(cpu);
+    }
+
+    if code != Exception::Interrupt as u32 {
+        error!(target: logging::HLE,
+               "Unhandled exception {} at 0x{:08x}, halting",
+               code, cpu.cop0.epc());
+        return halt(cpu);
+    }
+
+    save_context(cpu);
+
+    let pending = {
+        let irq = *shared.irq_state();
+
+        irq.status() & irq.mask()
+    };
+
+    let mut calls = Vec::new();
+
+    // Root counters, the 4th one is the VBlank
+    for (n, &it) in RCNT_INTERRUPTS.iter().enumerate() {
+        let bit = 1 << (it as u16);
+
+        if pending & bit == 0 {
+            continue;
+        }
+
+        let class = RCNT_CLASS + n as u32;
+
+        calls.extend(cpu.hle.kernel.deliver_event(class, RCNT_SPEC));
+
+        if cpu.hle.kernel.clear_rcnt[n] {
+            shared.irq_state_mut().ack(!bit);
+        }
+    }
+
+    for prio in 0..4 {
+        let mut node = cpu.hle.kernel.chains[prio];
+        let mut len = 0;
+
+        while node != 0 && len < MAX_CHAIN_LEN {
+            calls.push(GuestCall::ChainFirst(node));
+
+            node = load_word(cpu, node);
+            len += 1;
+        }
+    }
+
+    start_calls(cpu, calls, Continuation::Exception);
+}
+
+/// Save the interrupted code's registers in the current thread
+fn save_context(cpu: &mut Cpu) {
+    let mut pc = cpu.cop0.epc();
+
+    // GTE commands are executed even if an interrupt occurs, we must
+    // not run them twice
+    if Instruction(load_word(cpu, pc)).is_gte_op() {
+        pc = pc.wrapping_add(4);
+    }
+
+    let regs = cpu.regs;
+    let hi = cpu.hi;
+    let lo = cpu.lo;
+
+    let thread = cpu.hle.kernel.thread_mut();
+
+    thread.regs = regs;
+    thread.hi = hi;
+    thread.lo = lo;
+    thread.pc = pc;
+}
+
+/// Restore the current thread and leave the exception handler
+fn return_from_exception(cpu: &mut Cpu) {
+    let (regs, hi, lo, pc) = {
+        let thread = cpu.hle.kernel.thread_mut();
+
+        (thread.regs, thread.hi, thread.lo, thread.pc)
+    };
+
+    cpu.regs = regs;
+    cpu.regs[0] = 0;
+    cpu.hi = hi;
+    cpu.lo = lo;
+
+    rfe(cpu);
+    jump(cpu, pc);
+}
+
+/// Pop the SR mode stack like the RFE instruction
+fn rfe(cpu: &mut Cpu) {
+    let old_sr = cpu.cop0.sr();
+
+    cpu.cop0.return_from_exception();
+
+    cpu.check_mode_stack(old_sr, false);
+}
+
+fn syscall(cpu: &mut Cpu) {
+    let sr = cpu.cop0.sr();
+
+    // The interrupted code's interrupt enable is in the "previous"
+    // entry of the mode stack
+    let (sr, v0) =
+        match cpu.reg(A0) {
+            // EnterCriticalSection, returns 1 if the interrupts were
+            // enabled
+            1 => (sr & !IRQ_ENABLE, (sr & IRQ_ENABLE == IRQ_ENABLE) as u32),
+            // ExitCriticalSection
+            2 => (sr | IRQ_ENABLE, 0),
+            n => {
+                warn!(target: logging::HLE, "Unhandled syscall {}", n);
+                (sr, 0)
+            }
+        };
+
+    cpu.cop0.set_sr(sr);
+    cpu.set_reg(V0, v0);
+
+    // Return after the syscall instruction
+    let epc = cpu.cop0.epc();
+
+    rfe(cpu);
+    jump(cpu, epc.wrapping_add(4));
+}
+
+/// Call the guest functions in `calls` one after the other, then
+/// carry on with `then`
+fn start_calls(cpu: &mut Cpu, calls: Vec<GuestCall>, then: Continuation) {
+    cpu.hle.kernel.dispatch.push(Dispatch {
+        calls: calls,
+        current: None,
+        then: then,
+    });
+
+    next_call(cpu);
+}
+
+/// Called when a guest callback returns to `CALLBACK_RETURN`
+pub fn callback_return(cpu: &mut Cpu) {
+    let v0 = cpu.reg(V0);
+
+    let current = cpu.hle.kernel.dispatch.last_mut().map(|d| d.current.take());
+
+    let current =
+        match current {
+            Some(c) => c,
+            None => {
+                error!(target: logging::HLE,
+                       "Unexpected callback return, halting");
+                return halt(cpu);
+            }
+        };
+
+    if let Some(GuestCall::ChainFirst(node)) = current {
+        if v0 != 0 {
+            let second = load_word(cpu, node.wrapping_add(4));
+            let d = cpu.hle.kernel.dispatch.last_mut().unwrap();
+
+            d.calls.insert(0, GuestCall::ChainSecond(second, v0));
+        }
+    }
+
+    next_call(cpu);
+}
+
+/// Run the next callback of the innermost dispatch, or finish it if
+/// there's none left
+fn next_call(cpu: &mut Cpu) {
+    loop {
+        let (call, then) = {
+            let d = cpu.hle.kernel.dispatch.last_mut().unwrap();
+
+            if d.calls.is_empty() {
+                break;
+            }
+
+            (d.calls.remove(0), d.then)
+        };
+
+        let (func, arg) =
+            match call {
+                GuestCall::Event(f) => (f, 0),
+                GuestCall::ChainFirst(node) =>
+                    (load_word(cpu, node.wrapping_add(8)), 0),
+                GuestCall::ChainSecond(f, arg) => (f, arg),
+            };
+
+        if func == 0 {
+            continue;
+        }
+
+        cpu.hle.kernel.dispatch.last_mut().unwrap().current = Some(call);
+
+        if let Continuation::Exception = then {
+            cpu.set_reg(SP, EXCEPTION_SP);
+        }
+
+        cpu.set_reg(A0, arg);
+        cpu.set_reg(RA, CALLBACK_RETURN);
+
+        return jump(cpu, func);
+    }
+
+    let d = cpu.hle.kernel.dispatch.pop().unwrap();
+
+    match d.then {
+        Continuation::Exception => {
+            let custom_exit = cpu.hle.kernel.custom_exit;
+
+            if custom_exit != 0 {
+                long_jump(cpu, custom_exit);
+            } else {
+                return_from_exception(cpu);
+            }
+        }
+        Continuation::Return(ra, v0) => {
+            cpu.set_reg(V0, v0);
+            jump(cpu, ra);
+        }
+    }
+}
+
+/// Restore the registers saved by `setjmp` in `buf` and return 1
+/// from it
+fn long_jump(cpu: &mut Cpu, buf: u32) {
+    let mut saved = [0; 12];
+
+    for (i, w) in saved.iter_mut().enumerate() {
+        *w = load_word(cpu, buf.wrapping_add(i as u32 * 4));
+    }
+
+    // RA, SP, FP, S0-S7 and GP
+    cpu.set_reg(SP, saved[1]);
+    cpu.set_reg(FP, saved[2]);
+
+    for i in 0..8 {
+        cpu.set_reg(RegisterIndex(16 + i), saved[3 + i as usize]);
+    }
+
+    cpu.set_reg(GP, saved[11]);
+    cpu.set_reg(V0, 1);
+
+    jump(cpu, saved[0]);
+}
+
+/// Run kernel function `function` of table `table`. Returns `None` if
+/// it's not implemented.
+pub fn function(cpu: &mut Cpu,
+                shared: &mut SharedState,
+                table: u32,
+                function: u32) -> Option<Outcome> {
+    let a0 = cpu.reg(A0);
+    let a1 = cpu.reg(A1);
+    let a2 = cpu.reg(A2);
+    let a3 = cpu.reg(A3);
+
+    let v0 =
+        match (table, function) {
+            (0xb0, 0x04) => set_rcnt_irq(shared, a0, true),
+            (0xb0, 0x05) => set_rcnt_irq(shared, a0, false),
+            (0xb0, 0x07) => {
+                let calls = cpu.hle.kernel.deliver_event(a0, a1);
+                let ra = cpu.reg(RA);
+
+                start_calls(cpu, calls, Continuation::Return(ra, 0));
+
+                return Some(Outcome::Jumped);
+            }
+            (0xb0, 0x08) => cpu.hle.kernel.open_event(a0, a1, a2, a3),
+            (0xb0, 0x09) => cpu.hle.kernel.set_event_status(a0, EVENT_FREE),
+            (0xb0, 0x0a) => {
+                let kernel = &mut cpu.hle.kernel;
+
+                match kernel.event_index(a0) {
+                    Some(i) if kernel.events[i].status == EVENT_ENABLED =>
+                        return Some(Outcome::Wait),
+                    _ => kernel.test_event(a0),
+                }
+            }
+            (0xb0, 0x0b) => cpu.hle.kernel.test_event(a0),
+            (0xb0, 0x0c) =>
+                cpu.hle.kernel.set_event_status(a0, EVENT_ENABLED),
+            (0xb0, 0x0d) =>
+                cpu.hle.kernel.set_event_status(a0, EVENT_DISABLED),
+            (0xb0, 0x0e) => cpu.hle.kernel.open_thread(a0, a1, a2),
+            (0xb0, 0x0f) => cpu.hle.kernel.close_thread(a0),
+            (0xb0, 0x10) => {
+                if change_thread(cpu, a0) {
+                    return Some(Outcome::Jumped);
+                }
+
+                0
+            }
+            (0xb0, 0x17) => {
+                return_from_exception(cpu);
+
+                return Some(Outcome::Jumped);
+            }
+            (0xb0, 0x18) => {
+                cpu.hle.kernel.custom_exit = 0;
+                0
+            }
+            (0xb0, 0x19) => {
+                cpu.hle.kernel.custom_exit = a0;
+                0
+            }
+            (0xb0, 0x20) => {
+                cpu.hle.kernel.undeliver_event(a0, a1);
+                0
+            }
+            (0xc0, 0x02) => enqueue_int_rp(cpu, a0, a1),
+            (0xc0, 0x03) => dequeue_int_rp(cpu, a0, a1),
+            (0xc0, 0x0a) => {
+                match cpu.hle.kernel.clear_rcnt.get_mut(a0 as usize) {
+                    Some(clear) => {
+                        let old = *clear;
+
+                        *clear = a1 != 0;
+
+                        old as u32
+                    }
+                    None => 0,
+                }
+            }
+            _ => return None,
+        };
+
+    Some(Outcome::Return(v0))
+}
+
+/// Unmask or mask root counter `rcnt`'s interrupt
+fn set_rcnt_irq(shared: &mut SharedState, rcnt: u32, enable: bool) -> u32 {
+    let it =
+        match RCNT_INTERRUPTS.get(rcnt as usize) {
+            Some(&it) => it,
+            None => return 0,
+        };
+
+    let bit = 1 << (it as u16);
+    let irq = shared.irq_state_mut();
+    let mask = irq.mask();
+
+    if enable {
+        irq.set_mask(mask | bit);
+    } else {
+        irq.set_mask(mask & !bit);
+    }
+
+    1
+}
+
+/// Switch to thread `handle`. The current thread resumes by returning
+/// 1 to the caller. Returns false if the handle is invalid.
+fn change_thread(cpu: &mut Cpu, handle: u32) -> bool {
+    let index =
+        match cpu.hle.kernel.thread_index(handle) {
+            Some(i) => i,
+            None => return false,
+        };
+
+    let mut regs = cpu.regs;
+    let hi = cpu.hi;
+    let lo = cpu.lo;
+
+    regs[V0.0 as usize] = 1;
+
+    {
+        let thread = cpu.hle.kernel.thread_mut();
+
+        thread.regs = regs;
+        thread.hi = hi;
+        thread.lo = lo;
+        thread.pc = regs[RA.0 as usize];
+    }
+
+    cpu.hle.kernel.current_thread = index;
+
+    let (regs, hi, lo, pc) = {
+        let thread = cpu.hle.kernel.thread_mut();
+
+        (thread.regs, thread.hi, thread.lo, thread.pc)
+    };
+
+    cpu.regs = regs;
+    cpu.regs[0] = 0;
+    cpu.hi = hi;
+    cpu.lo = lo;
+
+    jump(cpu, pc);
+
+    true
+}
+
+/// SysEnqIntRP: insert the entry at `node` at the head of priority
+/// chain `prio`
+fn enqueue_int_rp(cpu: &mut Cpu, prio: u32, node: u32) -> u32 {
+    let head =
+        match cpu.hle.kernel.chains.get(prio as usize) {
+            Some(&h) => h,
+            None => return 0,
+        };
+
+    store_word(cpu, node, head);
+
+    cpu.hle.kernel.chains[prio as usize] = node;
+
+    0
+}
+
+/// SysDeqIntRP: remove the entry at `node` from priority chain `prio`
+fn dequeue_int_rp(cpu: &mut Cpu, prio: u32, node: u32) -> u32 {
+    let mut cur =
+        match cpu.hle.kernel.chains.get(prio as usize) {
+            Some(&h) => h,
+            None => return 0,
+        };
+
+    let mut prev = None;
+    let mut len = 0;
+
+    while cur != 0 && len < MAX_CHAIN_LEN {
+        let next = load_word(cpu, cur);
+
+        if cur == node {
+            match prev {
+                Some(p) => store_word(cpu, p, next),
+                None => cpu.hle.kernel.chains[prio as usize] = next,
+            }
+
+            break;
+        }
+
+        prev = Some(cur);
+        cur = next;
+        len += 1;
+    }
+
+    0
+}
+
+fn load_word(cpu: &Cpu, addr: u32) -> u32 {
+    let mut w = 0;
+
+    for i in 0..4 {
+        w |= (load_byte(cpu, addr.wrapping_add(i)) as u32) << (i * 8);
+    }
+
+    w
+}
+
+fn store_word(cpu: &mut Cpu, addr: u32, val: u32) {
+    for i in 0..4 {
+        store_byte(cpu, addr.wrapping_add(i), (val >> (i * 8)) as u8);
+    }
+}
+
+/// Handles returned by OpenEvent
+const EVENT_HANDLE: u32 = 0xf1000000;
+/// Handles returned by OpenThread
+const THREAD_HANDLE: u32 = 0xff000000;
+
+const MAX_EVENTS: usize = 16;
+const MAX_THREADS: usize = 4;
+
+/// Event statuses
+const EVENT_FREE: u32 = 0;
+const EVENT_DISABLED: u32 = 0x1000;
+const EVENT_ENABLED: u32 = 0x2000;
+const EVENT_READY: u32 = 0x4000;
+
+/// Event modes
+const EVENT_MODE_CALLBACK: u32 = 0x1000;
+const EVENT_MODE_READY: u32 = 0x2000;
+
+/// Class of the root counter events, plus the counter number
+const RCNT_CLASS: u32 = 0xf2000000;
+/// Spec of the root counter events: interrupt
+const RCNT_SPEC: u32 = 0x0002;
+
+/// Interrupts of the 4 root counters
+const RCNT_INTERRUPTS: [Interrupt; 4] = [Interrupt::Timer0,
+                                         Interrupt::Timer1,
+                                         Interrupt::Timer2,
+                                         Interrupt::VBlank];
+
+/// Limit on the length of the priority chains, in case the guest
+/// corrupts them
+const MAX_CHAIN_LEN: u32 = 64;
+
+/// Where the guest callbacks return
+pub const CALLBACK_RETURN: u32 = 0x80000f00;
+
+/// `beq $0, $0, .`
+const SPIN: u32 = 0x1000ffff;
+
+/// SR interrupt enable: IEp and the hardware interrupt mask
+const IRQ_ENABLE: u32 = 0x404;
+
+/// Initial stack
+const KERNEL_SP: u32 = 0x801fff00;
+/// Stack used by the guest callbacks called from the exception
+/// handler
+const EXCEPTION_SP: u32 = 0x8000e000;
+
+const A3: RegisterIndex = RegisterIndex(7);
+const GP: RegisterIndex = RegisterIndex(28);
+const SP: RegisterIndex = RegisterIndex(29);
+const FP: RegisterIndex = RegisterIndex(30);
+
+#[test]
+fn hle_boot() {
+    use cdrom::disc::Region;
+    use cpu::test_machine::TestMachine;
+    use memory::Word;
+    use bios::Bios;
+
+    let mut m = TestMachine::new();
+
+    m.cpu.interconnect_mut().set_bios(Bios::hle(Region::NorthAmerica));
+    m.cpu.reset();
+
+    // No disc, the kernel gives up after the init
+    m.run_until(0x0fc00000 + HALT_OFFSET);
+
+    assert_eq!(m.read::<Word>(0xb0), SPIN);
+    assert_eq!(m.cpu.regs()[29], KERNEL_SP);
+
+    let mut exe = vec![0; 0x800];
+
+    exe[..8].copy_from_slice(b"PS-X EXE");
+
+    // Entry point, GP, load address, text length, SP base and offset
+    for &(offset, v) in &[(0x10, 0x80100000u32),
+                          (0x14, 0x80123456),
+                          (0x18, 0x80100000),
+                          (0x1c, 8),
+                          (0x30, 0x801ff000),
+                          (0x34, 0x100)] {
+        for i in 0..4 {
+            exe[offset + i] = (v >> (i * 8)) as u8;
+        }
+    }
+
+    // j 0xeadbee0
+    exe.extend_from_slice(&[0xb8, 0x6f, 0xab, 0x0b, 0, 0, 0, 0]);
+
+    let loader = ExeLoader::load(&mut &exe[..]).unwrap();
+
+    start_executable(&mut m.cpu, &loader);
+
+    m.run_until(0xeadbee0);
+
+    m.assert_regs(&[(28, 0x80123456),
+                    (29, 0x801ff100),
+                    (30, 0x801ff100),
+                    (31, ROM_BASE + HALT_OFFSET)]);
+}
+
+#[test]
+fn hle_wait_event() {
+    use cdrom::disc::Region;
+    use cpu::test_machine::TestMachine;
+    use bios::Bios;
+
+    let mut m = TestMachine::new()
+        .with_blob(0x80100000, &[0x3c04f200, // lui   $a0, 0xf200
+                                 0x34840003, // ori   $a0, $a0, 3
+                                 0x34050002, // ori   $a1, $0, 2
+                                 0x34062000, // ori   $a2, $0, 0x2000
+                                 0x34070000, // ori   $a3, $0, 0
+                                 0x0c00002c, // jal   0xb0
+                                 0x34090008, // ori   $t1, $0, 8
+                                 0x00408025, // or    $s0, $v0, $0
+                                 0x02002025, // or    $a0, $s0, $0
+                                 0x0c00002c, // jal   0xb0
+                                 0x3409000c, // ori   $t1, $0, 0xc
+                                 0x34040003, // ori   $a0, $0, 3
+                                 0x0c00002c, // jal   0xb0
+                                 0x34090004, // ori   $t1, $0, 4
+                                 0x02002025, // or    $a0, $s0, $0
+                                 0x0c00002c, // jal   0xb0
+                                 0x3409000a, // ori   $t1, $0, 0xa
+                                 0x00408825, // or    $s1, $v0, $0
+                                 0x0bab6fb8, // j     0xeadbee0
+                                 0x00000000]);
+
+    m.cpu.interconnect_mut().set_bios(Bios::hle(Region::NorthAmerica));
+    init(&mut m.cpu, &mut m.shared);
+    m.cpu.cop0.set_sr(0x401);
+
+    // OpenEvent(RCNT3, interrupt, ready mode), EnableEvent,
+    // StartRCnt(3) then WaitEvent spins until the VBlank
+    for _ in 0..200 {
+        m.step();
+    }
+
+    assert_eq!(m.cpu.pc() & 0xfffffff8, 0x800000b0);
+    assert_eq!(m.shared.irq_state().mask(), 1);
+
+    m.shared.irq_state_mut().assert(Interrupt::VBlank);
+    m.run_until(0xeadbee0);
+
+    m.assert_regs(&[(16, EVENT_HANDLE), (17, 1)]);
+
+    // Acknowledged by the kernel
+    assert_eq!(m.shared.irq_state().status(), 0);
+    assert_eq!(m.cpu.cop0.sr(), 0x401);
+}
+
+#[test]
+fn hle_interrupt_chain() {
+    use cdrom::disc::Region;
+    use cpu::test_machine::TestMachine;
+    use memory::Word;
+    use bios::Bios;
+
+    let mut m = TestMachine::new()
+        // Second function: stores its argument
+        .with_blob(0x80120000, &[0xac040100, // sw    $a0, 0x100($0)
+                                 0x03e00008, // jr    $ra
+                                 0x00000000])
+        // First function
+        .with_blob(0x80120010, &[0x03e00008, // jr    $ra
+                                 0x34020055]) // ori  $v0, $0, 0x55
+        // Priority chain entry
+        .with_blob(0x80110000, &[0xffffffff,
+                                 0x80120000,
+                                 0x80120010,
+                                 0x00000000])
+        .with_blob(0x80100000, &[0x3c058011, // lui   $a1, 0x8011
+                                 0x00002025, // or    $a0, $0, $0
+                                 0x0c000030, // jal   0xc0
+                                 0x34090002, // ori   $t1, $0, 2
+                                 0x1000ffff, // b     .
+                                 0x00000000])
+        .with_pc(0x80100000);
+
+    m.cpu.interconnect_mut().set_bios(Bios::hle(Region::NorthAmerica));
+    init(&mut m.cpu, &mut m.shared);
+    m.cpu.cop0.set_sr(0x401);
+    m.shared.irq_state_mut().set_mask(1);
+
+    // SysEnqIntRP(0, entry)
+    m.run_until(0x00100010);
+
+    assert_eq!(m.read::<Word>(0x110000), 0);
+
+    m.shared.irq_state_mut().assert(Interrupt::VBlank);
+
+    for _ in 0..50 {
+        m.step();
+    }
+
+    assert_eq!(m.read::<Word>(0x100), 0x55);
+    assert_eq!(m.cpu.pc() & 0xfffffff8, 0x80100010);
+    assert_eq!(m.cpu.cop0.sr(), 0x401);
+    assert_eq!(m.shared.irq_state().status(), 0);
+}
@@ -0,0 +1,316 @@
+//! High-level fast paths for some of the BIOS kernel calls.
+//!
+//! The PlayStation kernel exposes its API through three function
+//! tables reached by jumping to 0xa0, 0xb0 or 0xc0 with the function
+//! number in register `t1`. When HLE is enabled we intercept those
+//! jumps and implement the function in Rust before returning directly
+//! to the caller.
+//!
+//! With a real BIOS image only some of the standard library and TTY
+//! functions are implemented, everything else (events, threads,
+//! exception dispatch, the boot sequence) still runs the BIOS code.
+//!
+//! With the HLE BIOS (`Bios::hle`) there's no BIOS code to fall back
+//! to: the kernel in the `kernel` module takes over the boot
+//! sequence, the exception vector and the event, thread and interrupt
+//! functions. It's enabled automatically and is experimental, see the
+//! `kernel` module for what's supported.
+
+use memory::{Byte, map};
+use bios::hle::{ROM_BASE, BOOT_OFFSET};
+use shared::SharedState;
+use logging;
+
+use super::{Cpu, RegisterIndex};
+
+use self::kernel::{Kernel, Outcome, CALLBACK_RETURN};
+
+mod kernel;
+
+/// HLE BIOS state
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Hle {
+    /// True if the kernel calls should be intercepted
+    enabled: bool,
+    /// Line buffer for the TTY output
+    tty: String,
+    /// State of the kernel used with the HLE BIOS
+    kernel: Kernel,
+}
+
+impl Hle {
+    pub fn new() -> Hle {
+        Hle {
+            enabled: false,
+            tty: String::new(),
+            kernel: Kernel::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Output a character on the TTY
+    fn putchar(&mut self, c: u8) {
+        match c {
+            b'\n' => {
+                info!(target: logging::HLE, "TTY: {}", self.tty);
+                self.tty.clear();
+            }
+            b'\r' => (),
+            _ => self.tty.push(c as char),
+        }
+    }
+}
+
+/// Called before each instruction when HLE is enabled or when running
+/// the HLE BIOS. If the CPU is about to enter one of the kernel call
+/// vectors and we know how to handle the requested function we run it
+/// and return directly to the caller. Returns `true` if the call was
+/// handled.
+pub fn kernel_call(cpu: &mut Cpu, shared: &mut SharedState) -> bool {
+    let table = map::mask_region(cpu.current_pc);
+    let hle_bios = cpu.inter.bios().is_hle();
+
+    if hle_bios {
+        let rom = map::mask_region(ROM_BASE);
+
+        if table == rom {
+            // Reset, the ROM code carries on with the hook slot
+            kernel::init(cpu, shared);
+            return false;
+        }
+
+        let boot = rom + BOOT_OFFSET;
+        let callback_return = map::mask_region(CALLBACK_RETURN);
+
+        if table == EXCEPTION_VECTOR ||
+            table == boot ||
+            table == callback_return {
+            cpu.delayed_load();
+
+            if table == EXCEPTION_VECTOR {
+                kernel::exception(cpu, shared);
+            } else if table == boot {
+                kernel::boot(cpu);
+            } else {
+                kernel::callback_return(cpu);
+            }
+
+            // XXX Same as the kernel calls
+            shared.tk().cpu_tick(10);
+
+            return true;
+        }
+    }
+
+    if table != 0xa0 && table != 0xb0 && table != 0xc0 {
+        return false;
+    }
+
+    // Take care of any pending load before we look at the arguments
+    cpu.delayed_load();
+
+    let function = cpu.reg(T1) & 0xff;
+
+    let a0 = cpu.reg(A0);
+    let a1 = cpu.reg(A1);
+    let a2 = cpu.reg(A2);
+
+    let ret =
+        match (table, function) {
+            (0xa0, 0x17) => strcmp(cpu, a0, a1),
+            (0xa0, 0x19) => strcpy(cpu, a0, a1),
+            (0xa0, 0x1b) => strlen(cpu, a0),
+            (0xa0, 0x25) => toupper(a0 as u8) as u32,
+            (0xa0, 0x26) => tolower(a0 as u8) as u32,
+            (0xa0, 0x28) => memset(cpu, a0, 0, a1),
+            (0xa0, 0x2a) => memcpy(cpu, a0, a1, a2),
+            (0xa0, 0x2b) => memset(cpu, a0, a1 as u8, a2),
+            (0xa0, 0x3c) | (0xb0, 0x3d) => putchar(cpu, a0),
+            (0xa0, 0x3e) | (0xb0, 0x3f) => puts(cpu, a0),
+            (0xa0, 0x44) => flush_cache(cpu),
+            _ if hle_bios =>
+                match kernel::function(cpu, shared, table, function) {
+                    Some(Outcome::Return(v)) => v,
+                    Some(Outcome::Jumped) => {
+                        shared.tk().cpu_tick(10);
+                        return true;
+                    }
+                    // Let the CPU spin in the vector
+                    Some(Outcome::Wait) => return false,
+                    None => {
+                        warn!(target: logging::HLE,
+                              "Unimplemented kernel call {:02x}({:02x})",
+                              table, function);
+                        0
+                    }
+                },
+            _ => {
+                debug!(target: logging::HLE,
+                       "Unimplemented kernel call {:02x}({:02x}), \
+                        using the BIOS",
+                       table, function);
+                return false;
+            }
+        };
+
+    cpu.set_reg(V0, ret);
+
+    // Return to the caller
+    let ra = cpu.reg(RA);
+
+    jump(cpu, ra);
+
+    // XXX The real functions obviously take a lot longer than that
+    shared.tk().cpu_tick(10);
+
+    true
+}
+
+/// Continue execution at `addr`, outside of any branch delay slot
+fn jump(cpu: &mut Cpu, addr: u32) {
+    cpu.pc         = addr;
+    cpu.next_pc    = addr.wrapping_add(4);
+    cpu.branch     = false;
+    cpu.delay_slot = false;
+}
+
+/// Read a byte from RAM. The kernel functions are only expected to
+/// deal with RAM buffers.
+fn load_byte(cpu: &Cpu, addr: u32) -> u8 {
+    match map::RAM.contains(map::PhysAddr::from_cpu(addr)) {
+        Some(offset) => cpu.inter.ram().load::<Byte>(offset) as u8,
+        None => {
+            warn!(target: logging::HLE,
+                  "Kernel call read outside of RAM: {:08x}", addr);
+            0
+        }
+    }
+}
+
+/// Write a byte to RAM
+fn store_byte(cpu: &mut Cpu, addr: u32, val: u8) {
+    match map::RAM.contains(map::PhysAddr::from_cpu(addr)) {
+        Some(offset) =>
+            cpu.inter.ram_mut().store::<Byte>(offset, val as u32),
+        None => warn!(target: logging::HLE,
+                      "Kernel call write outside of RAM: {:08x}", addr),
+    }
+}
+
+fn strcmp(cpu: &mut Cpu, a: u32, b: u32) -> u32 {
+    for i in 0..MAX_STRING_LEN {
+        let ca = load_byte(cpu, a.wrapping_add(i)) as i32;
+        let cb = load_byte(cpu, b.wrapping_add(i)) as i32;
+
+        if ca != cb || ca == 0 {
+            return (ca - cb) as u32;
+        }
+    }
+
+    0
+}
+
+fn strcpy(cpu: &mut Cpu, dst: u32, src: u32) -> u32 {
+    for i in 0..MAX_STRING_LEN {
+        let c = load_byte(cpu, src.wrapping_add(i));
+
+        store_byte(cpu, dst.wrapping_add(i), c);
+
+        if c == 0 {
+            break;
+        }
+    }
+
+    dst
+}
+
+fn strlen(cpu: &mut Cpu, s: u32) -> u32 {
+    for i in 0..MAX_STRING_LEN {
+        if load_byte(cpu, s.wrapping_add(i)) == 0 {
+            return i;
+        }
+    }
+
+    MAX_STRING_LEN
+}
+
+fn toupper(c: u8) -> u8 {
+    match c {
+        b'a'...b'z' => c - b'a' + b'A',
+        _ => c,
+    }
+}
+
+fn tolower(c: u8) -> u8 {
+    match c {
+        b'A'...b'Z' => c - b'A' + b'a',
+        _ => c,
+    }
+}
+
+fn memset(cpu: &mut Cpu, dst: u32, val: u8, len: u32) -> u32 {
+    for i in 0..len {
+        store_byte(cpu, dst.wrapping_add(i), val);
+    }
+
+    dst
+}
+
+fn memcpy(cpu: &mut Cpu, dst: u32, src: u32, len: u32) -> u32 {
+    for i in 0..len {
+        let b = load_byte(cpu, src.wrapping_add(i));
+
+        store_byte(cpu, dst.wrapping_add(i), b);
+    }
+
+    dst
+}
+
+fn putchar(cpu: &mut Cpu, c: u32) -> u32 {
+    cpu.hle.putchar(c as u8);
+
+    c
+}
+
+fn puts(cpu: &mut Cpu, s: u32) -> u32 {
+    for i in 0..MAX_STRING_LEN {
+        let c = load_byte(cpu, s.wrapping_add(i));
+
+        if c == 0 {
+            break;
+        }
+
+        cpu.hle.putchar(c);
+    }
+
+    cpu.hle.putchar(b'\n');
+
+    1
+}
+
+fn flush_cache(cpu: &mut Cpu) -> u32 {
+    cpu.icache.invalidate_all();
+
+    0
+}
+
+/// Exception vector in RAM, the HLE BIOS never sets BEV
+const EXCEPTION_VECTOR: u32 = 0x80;
+
+/// Maximum length of the strings handled by the kernel functions,
+/// prevents us from looping forever on unterminated strings
+const MAX_STRING_LEN: u32 = 0x10000;
+
+const V0: RegisterIndex = RegisterIndex(2);
+const A0: RegisterIndex = RegisterIndex(4);
+const A1: RegisterIndex = RegisterIndex(5);
+const A2: RegisterIndex = RegisterIndex(6);
+const T1: RegisterIndex = RegisterIndex(9);
+const RA: RegisterIndex = RegisterIndex(31);
@@ -0,0 +1,211 @@
+//! Instruction cache.
+//!
+//! The R3000A has a 4KB direct mapped instruction cache made of 256
+//! lines of four words. Each line has a tag (bits [30:12] of the
+//! address, the MSB is ignored so KUSEG and KSEG0 share the lines)
+//! and each word has a valid bit.
+//!
+//! Instead of an array of lines the cache is split in a tag array, a
+//! validity bitmap with one bit per word and a flat array of
+//! instructions so that a hit only costs a tag compare, a bit test
+//! and an array index. On top of that we remember the last fetch that
+//! hit: as long as the CPU keeps running sequentially within the same
+//! line and the cache isn't modified the lookup is skipped entirely.
+
+use init_policy::Filler;
+
+use super::Instruction;
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct ICache {
+    /// Tag of each line
+    tags: Tags,
+    /// One bit per word, set if the word is valid
+    valid: ValidBitmap,
+    /// Cached instructions
+    words: Words,
+    /// Address of the last fetch that hit the cache. The words
+    /// following it in the same line are guaranteed to be valid.
+    last_hit: Option<u32>,
+}
+
+impl ICache {
+    pub fn new() -> ICache {
+        // Tags are 0 and all the lines are invalid. If the lines were
+        // valid with a tag of 0 the exception handler the BIOS copies
+        // at 0x80000080 would hit the garbage in the cache if it runs
+        // before the first cache flush.
+        ICache {
+            tags: Tags::new(),
+            valid: ValidBitmap::new(),
+            words: Words::new(),
+            last_hit: None,
+        }
+    }
+
+    /// Overwrite the tags, valid bits and contents of the cache with
+    /// values generated by `filler`
+    pub fn fill(&mut self, filler: &mut Filler) {
+        for line in 0..LINE_COUNT {
+            // The legacy policy keeps the lines invalid like `new`
+            let tag_valid = filler.word(LEGACY_TAG_VALID);
+            let base = line << 2;
+
+            self.tags[line] = tag_valid & 0xfffff000;
+
+            // The first valid word index is in bits [4:2], values
+            // above 3 mean that the whole line is invalid
+            let first_valid = (tag_valid >> 2) & 7;
+
+            for i in 0..4 {
+                self.set_valid(base + i, (i as u32) >= first_valid);
+                self.words[base + i] = filler.word(0);
+            }
+        }
+
+        self.last_hit = None;
+    }
+
+    /// Return the cached instruction at `pc` or `None` if it's not in
+    /// the cache
+    #[inline(always)]
+    pub fn lookup(&mut self, pc: u32) -> Option<Instruction> {
+        let word = word_index(pc);
+
+        if let Some(last) = self.last_hit {
+            if pc & !0xf == last & !0xf && pc >= last {
+                // Fast path: same line as the last hit, further down
+                return Some(Instruction(self.words[word]));
+            }
+        }
+
+        if self.tags[word >> 2] == pc & TAG_MASK && self.is_valid(word) {
+            self.last_hit = Some(pc);
+
+            Some(Instruction(self.words[word]))
+        } else {
+            None
+        }
+    }
+
+    /// Store `instruction` in the word for `addr` without changing
+    /// the tags or valid bits
+    pub fn set_word(&mut self, addr: u32, instruction: Instruction) {
+        self.words[word_index(addr)] = instruction.0;
+        self.last_hit = None;
+    }
+
+    /// Set the tag of the line containing `pc` and mark the words
+    /// starting from `pc` as valid. The words before it in the line
+    /// are invalidated.
+    pub fn validate_line(&mut self, pc: u32) {
+        let word = word_index(pc);
+        let base = word & !3;
+
+        self.tags[word >> 2] = pc & TAG_MASK;
+
+        for i in 0..4 {
+            self.set_valid(base + i, base + i >= word);
+        }
+
+        self.last_hit = Some(pc);
+    }
+
+    /// Invalidate the entire line containing `addr`. Doesn't change
+    /// the tag or contents of the line.
+    pub fn invalidate_line(&mut self, addr: u32) {
+        let base = word_index(addr) & !3;
+
+        for i in 0..4 {
+            self.set_valid(base + i, false);
+        }
+
+        self.last_hit = None;
+    }
+
+    /// Invalidate every line in the cache
+    pub fn invalidate_all(&mut self) {
+        for w in self.valid.iter_mut() {
+            *w = 0;
+        }
+
+        self.last_hit = None;
+    }
+
+    fn is_valid(&self, word: usize) -> bool {
+        self.valid[word >> 5] & (1 << (word & 0x1f)) != 0
+    }
+
+    fn set_valid(&mut self, word: usize, valid: bool) {
+        let bit = 1 << (word & 0x1f);
+
+        if valid {
+            self.valid[word >> 5] |= bit;
+        } else {
+            self.valid[word >> 5] &= !bit;
+        }
+    }
+}
+
+/// Index of the cache word for address `addr`
+fn word_index(addr: u32) -> usize {
+    ((addr >> 2) & 0x3ff) as usize
+}
+
+/// Number of cache lines
+const LINE_COUNT: usize = 0x100;
+
+/// Address bits used for the tag
+const TAG_MASK: u32 = 0x7ffff000;
+
+buffer!(struct Tags([u32; 0x100]));
+buffer!(struct ValidBitmap([u32; 0x20]));
+buffer!(struct Words([u32; 0x400]));
+
+/// Tag and valid word used by `fill` for the `Legacy` policy: tag 0
+/// with the first valid word index out of range, i.e. all the words
+/// are invalid
+const LEGACY_TAG_VALID: u32 = 0x10;
+
+#[test]
+fn partial_line() {
+    let mut icache = ICache::new();
+
+    assert!(icache.lookup(0x80001008).is_none());
+
+    // Refill starting in the middle of the line
+    icache.set_word(0x80001008, Instruction(0x1234));
+    icache.set_word(0x8000100c, Instruction(0x5678));
+    icache.validate_line(0x80001008);
+
+    assert_eq!(icache.lookup(0x80001008).map(|i| i.0), Some(0x1234));
+    assert_eq!(icache.lookup(0x8000100c).map(|i| i.0), Some(0x5678));
+    // The MSB is ignored
+    assert_eq!(icache.lookup(0x0000100c).map(|i| i.0), Some(0x5678));
+
+    // The beginning of the line is still invalid
+    assert!(icache.lookup(0x80001004).is_none());
+    // Different tag
+    assert!(icache.lookup(0x80002008).is_none());
+
+    icache.lookup(0x80001008);
+    icache.invalidate_line(0x80001000);
+
+    assert!(icache.lookup(0x8000100c).is_none());
+}
+
+#[test]
+fn legacy_fill() {
+    use init_policy::InitPolicy;
+
+    let mut icache = ICache::new();
+
+    icache.fill(&mut InitPolicy::Legacy.filler());
+
+    // Nothing hits after a reset, even at address 0
+    for line in 0..LINE_COUNT as u32 {
+        for word in 0..4 {
+            assert!(icache.lookup((line << 4) | (word << 2)).is_none());
+        }
+    }
+}
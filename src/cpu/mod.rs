@@ -1,21 +1,44 @@
 mod cop0;
 mod gte;
+mod hle;
+mod icache;
+mod timing;
+mod write_buffer;
+
+pub mod asm;
+pub mod op;
 
 #[cfg(test)]
 mod tests;
+#[cfg(test)]
+pub mod test_machine;
 
 use std::fmt::{Display, Formatter, Error};
-use std::default::Default;
 
 use memory::{Interconnect, Addressable, Byte, HalfWord, Word};
+use memory::map::Segment;
 use shared::SharedState;
+use timekeeper::Cycles;
 use gpu::renderer::Renderer;
 use interrupt::InterruptState;
 use debugger::Debugger;
+use init_policy::{InitPolicy, Filler};
+use pgxp::PreciseVertex;
 use tracer::module_tracer;
+use savestate::{self, SaveState};
+use crash::{PcRing, DEFAULT_PC_RING_SIZE};
+use instrumentation::Instrumentation;
+#[cfg(feature = "instrumentation")]
+use instrumentation::Instrument;
+use logging;
 
 use self::cop0::{Cop0, Exception};
 use self::gte::Gte;
+use self::hle::Hle;
+use self::icache::ICache;
+use self::timing::PipelineTiming;
+use self::write_buffer::WriteBuffer;
+use self::op::Op;
 
 /// This struct contains the CPU state, including the `Interconnect`
 /// instance which owns most of the peripherals.
@@ -36,8 +59,12 @@ pub struct Cpu {
     /// LO register for division quotient and multiplication low
     /// result
     lo: u32,
+    /// Date (in CPU cycles) at which the result of the last
+    /// multiplication or division will be available in HI/LO. Reading
+    /// those registers before that stalls the CPU.
+    mult_div_end: Cycles,
     /// Instruction Cache (256 4-word cachelines)
-    icache: ICacheLines,
+    icache: ICache,
     /// Memory interface
     inter: Interconnect,
     /// Coprocessor 0: System control
@@ -45,7 +72,11 @@ pub struct Cpu {
     /// Coprocessor 2: Geometry Transform Engine
     gte: Gte,
     /// Load initiated by the current instruction (will take effect
-    /// after the load delay slot)
+    /// after the load delay slot). Memory loads (LB, LH, LW...) and
+    /// coprocessor register moves (MFC0, MFC2, CFC2) all go through
+    /// it. Only one load can be pending at a time so tracking the
+    /// single delayed register is enough, there's no need for a copy
+    /// of the register file.
     load: (RegisterIndex, u32),
     /// Set by the current instruction if a branch occured and the
     /// next instruction will be in the delay slot.
@@ -55,6 +86,19 @@ pub struct Cpu {
     /// If `true` break instructions will trigger the debugger instead
     /// of generating an exception.
     debug_on_break: bool,
+    /// High-level emulation of the BIOS kernel calls
+    hle: Hle,
+    /// Policy used to fill the registers and caches on reset
+    init_policy: InitPolicy,
+    /// Optional pipeline timing model
+    timing: PipelineTiming,
+    /// Optional write buffer model
+    write_buffer: WriteBuffer,
+    /// Addresses of the last instructions executed, for crash
+    /// reports
+    pc_ring: PcRing,
+    /// Hooks for external coverage and analysis tools
+    instrumentation: Instrumentation,
 }
 
 impl Cpu {
@@ -76,7 +120,8 @@ impl Cpu {
             regs:           regs,
             hi:             0xdeadbeef,
             lo:             0xdeadbeef,
-            icache:         ICacheLines::new(),
+            mult_div_end:   0,
+            icache:         ICache::new(),
             inter:          inter,
             cop0:           Cop0::new(),
             gte:            Gte::new(),
@@ -84,13 +129,226 @@ impl Cpu {
             branch:         false,
             delay_slot:     false,
             debug_on_break: false,
+            hle:            Hle::new(),
+            init_policy:    InitPolicy::Legacy,
+            timing:         PipelineTiming::new(),
+            write_buffer:   WriteBuffer::new(),
+            pc_ring:        PcRing::new(DEFAULT_PC_RING_SIZE),
+            instrumentation: Instrumentation::new(),
         }
     }
 
+    /// Create a new CPU instance, using `policy` to fill the
+    /// registers, caches, RAM and ScratchPad
+    pub fn with_init_policy(inter: Interconnect,
+                            policy: InitPolicy) -> Cpu {
+        let mut cpu = Cpu::new(inter);
+
+        cpu.set_init_policy(policy);
+
+        cpu
+    }
+
+    /// Change the policy used to fill the uninitialized state and
+    /// apply it immediately. Meant to be called before the emulation
+    /// starts since it overwrites the RAM and resets the CPU.
+    pub fn set_init_policy(&mut self, policy: InitPolicy) {
+        self.init_policy = policy;
+
+        let mut filler = policy.filler();
+
+        self.fill_uninitialized(&mut filler);
+        self.inter.fill_uninitialized(&mut filler);
+    }
+
+    pub fn init_policy(&self) -> InitPolicy {
+        self.init_policy
+    }
+
+    /// Fill the registers and instruction cache using `filler`
+    fn fill_uninitialized(&mut self, filler: &mut Filler) {
+        for r in self.regs.iter_mut() {
+            *r = filler.word(0xdeadbeef);
+        }
+
+        // ... but R0 is hardwired to 0
+        self.regs[0] = 0;
+
+        self.hi = filler.word(0xdeadbeef);
+        self.lo = filler.word(0xdeadbeef);
+
+        self.icache.fill(filler);
+    }
+
+    /// Put the CPU back in its power-on state: the registers and
+    /// instruction cache are reset and execution restarts at the
+    /// beginning of the BIOS. The peripherals are left untouched.
+    pub fn reset(&mut self) {
+        let pc = 0xbfc00000;
+        // The widescreen hack is a setting, not part of the GTE state
+        let widescreen = self.gte.widescreen();
+
+        self.pc           = pc;
+        self.next_pc      = pc.wrapping_add(4);
+        self.current_pc   = 0;
+        self.mult_div_end = 0;
+        self.icache       = ICache::new();
+        self.cop0         = Cop0::new();
+        self.gte          = Gte::new();
+        self.load         = (RegisterIndex(0), 0);
+        self.branch       = false;
+        self.delay_slot   = false;
+
+        self.gte.set_widescreen(widescreen);
+
+        let pipeline_timing = self.timing.enabled();
+
+        self.timing.set_enabled(pipeline_timing);
+
+        let write_buffer = self.write_buffer.enabled();
+
+        self.write_buffer.set_enabled(write_buffer);
+
+        let mut filler = self.init_policy.filler();
+
+        self.fill_uninitialized(&mut filler);
+    }
+
+    /// Enable or disable the HLE fast paths for the BIOS stdlib and
+    /// TTY calls. The other calls still go through the BIOS code.
+    pub fn set_hle_bios(&mut self, enabled: bool) {
+        self.hle.set_enabled(enabled)
+    }
+
+    /// Enable or disable the GTE widescreen hack
+    pub fn set_widescreen_hack(&mut self, enabled: bool) {
+        self.gte.set_widescreen(enabled)
+    }
+
+    /// Enable or disable the PGXP precise vertex tracking
+    pub fn set_pgxp(&mut self, enabled: bool) {
+        self.inter.pgxp_mut().set_enabled(enabled)
+    }
+
+    /// Enable or disable the pipeline timing model (load-use stalls
+    /// and branch costs). More accurate but slower.
+    pub fn set_pipeline_timing(&mut self, enabled: bool) {
+        self.timing.set_enabled(enabled)
+    }
+
+    pub fn pipeline_timing(&self) -> bool {
+        self.timing.enabled()
+    }
+
+    /// Enable or disable the write buffer model: stores are queued
+    /// and loads wait for the pending stores they depend on
+    pub fn set_write_buffer(&mut self, enabled: bool) {
+        self.write_buffer.set_enabled(enabled)
+    }
+
+    pub fn write_buffer(&self) -> bool {
+        self.write_buffer.enabled()
+    }
+
     pub fn set_debug_on_break(&mut self, enabled: bool) {
         self.debug_on_break = enabled
     }
 
+    /// Store the CPU and peripherals state in `state`
+    pub fn save_state(&self,
+                      state: &mut SaveState) -> Result<(), savestate::Error> {
+        let regs = (self.pc,
+                    self.next_pc,
+                    self.current_pc,
+                    self.regs,
+                    self.hi,
+                    self.lo,
+                    self.mult_div_end,
+                    &self.load,
+                    self.branch,
+                    self.delay_slot);
+
+        try!(state.put(&savestate::CPU, &regs));
+        try!(state.put(&savestate::PIPELINE, &self.timing));
+        try!(state.put(&savestate::WRITE_BUFFER, &self.write_buffer));
+        try!(state.put(&savestate::ICACHE, &self.icache));
+        try!(state.put(&savestate::COP0, &self.cop0));
+        try!(state.put(&savestate::GTE, &self.gte));
+        try!(state.put(&savestate::HLE, &self.hle));
+
+        self.inter.save_state(state)
+    }
+
+    /// Restore the CPU and peripherals state from `state`. Missing
+    /// chunks leave the corresponding state untouched. The settings
+    /// (widescreen hack, pipeline timing, write buffer) are kept.
+    pub fn load_state(&mut self,
+                      state: &SaveState) -> Result<(), savestate::Error> {
+        if let Some(regs) = try!(state.get(&savestate::CPU)) {
+            let (pc, next_pc, current_pc, r, hi, lo, mult_div_end,
+                 load, branch, delay_slot) = regs;
+
+            self.pc = pc;
+            self.next_pc = next_pc;
+            self.current_pc = current_pc;
+            self.regs = r;
+            self.hi = hi;
+            self.lo = lo;
+            self.mult_div_end = mult_div_end;
+            self.load = load;
+            self.branch = branch;
+            self.delay_slot = delay_slot;
+        }
+
+        if let Some(timing) = try!(state.get(&savestate::PIPELINE)) {
+            self.timing.restore(timing);
+        }
+
+        if let Some(buffer) = try!(state.get(&savestate::WRITE_BUFFER)) {
+            self.write_buffer.restore(buffer);
+        }
+
+        if let Some(icache) = try!(state.get(&savestate::ICACHE)) {
+            self.icache = icache;
+        }
+
+        if let Some(cop0) = try!(state.get(&savestate::COP0)) {
+            self.cop0 = cop0;
+        }
+
+        if let Some(gte) = try!(state.get(&savestate::GTE)) {
+            let widescreen = self.gte.widescreen();
+
+            self.gte = gte;
+            self.gte.set_widescreen(widescreen);
+        }
+
+        if let Some(hle) = try!(state.get(&savestate::HLE)) {
+            self.hle = hle;
+        }
+
+        self.inter.load_state(state)
+    }
+
+    /// Return the addresses of the last instructions executed
+    pub fn pc_ring(&self) -> &PcRing {
+        &self.pc_ring
+    }
+
+    pub fn pc_ring_mut(&mut self) -> &mut PcRing {
+        &mut self.pc_ring
+    }
+
+    /// Attach an instrument receiving all the instructions executed
+    /// and memory accesses, returns the previous one. Only available
+    /// with the "instrumentation" feature.
+    #[cfg(feature = "instrumentation")]
+    pub fn set_instrumentation(&mut self,
+                               instrument: Option<Box<Instrument>>)
+                               -> Option<Box<Instrument>> {
+        self.instrumentation.set(instrument)
+    }
+
     /// Return a reference to the interconnect
     pub fn interconnect(&self) -> &Interconnect {
         &self.inter
@@ -112,6 +370,9 @@ impl Cpu {
         while frame == shared.counters().frame.get() {
             self.run_next_instruction(debugger, shared, renderer);
         }
+
+        // Send whatever is left of the frame to the renderer
+        self.inter.gpu_mut().flush(renderer);
     }
 
     /// Run a single CPU instruction and return
@@ -121,9 +382,11 @@ impl Cpu {
                                    renderer: &mut Renderer)
         where D: Debugger {
 
+        self.check_invariants();
+
         // Synchronize the peripherals
         if shared.tk().sync_pending() {
-            self.inter.sync(shared);
+            self.inter.sync(shared, renderer);
             shared.tk().update_sync_pending();
         }
 
@@ -131,12 +394,45 @@ impl Cpu {
         // `EPC` in case of an exception.
         self.current_pc = self.pc;
 
+        self.pc_ring.push(self.current_pc);
+        self.instrumentation.instruction(self.current_pc);
+
+        shared.stats_mut().cpu_instructions += 1;
+
+        // Debugger entrypoint: used for profiling
+        debugger.instruction_start(self.current_pc, shared.tk().now());
+
         // Debugger entrypoint: used for code breakpoints and stepping
         debugger.pc_change(self);
 
-        if self.current_pc % 4 != 0 {
-            // PC is not correctly aligned!
-            self.exception(Exception::LoadAddressError);
+        // If the last instruction was a branch then we're in the
+        // delay slot. Must be updated before raising any exception
+        // for this instruction.
+        self.delay_slot = self.branch;
+        self.branch     = false;
+
+        // The PC must be aligned and user mode code can only run
+        // from KUSEG
+        let user_mode = self.cop0.sr() & 2 != 0;
+
+        if self.current_pc % 4 != 0 ||
+            (user_mode && self.current_pc >= 0x80000000) {
+            let pc = self.current_pc;
+            self.address_exception(Exception::LoadAddressError, pc);
+            return;
+        }
+
+        // The HLE BIOS has no code of its own, it always needs the
+        // kernel
+        let hle = self.hle.enabled() || self.inter.bios().is_hle();
+
+        if hle && hle::kernel_call(self, shared) {
+            return;
+        }
+
+        // Cop0 hardware execution breakpoint
+        if self.cop0.code_breakpoint(self.current_pc) {
+            self.debug_exception();
             return;
         }
 
@@ -150,14 +446,16 @@ impl Cpu {
         self.pc         = self.next_pc;
         self.next_pc    = self.pc.wrapping_add(4);
 
-        // If the last instruction was a branch then we're in the
-        // delay slot
-        self.delay_slot = self.branch;
-        self.branch     = false;
+        if self.inter.take_bus_error() {
+            // Instruction fetch failed
+            self.exception(Exception::BusErrorInstruction);
+            return;
+        }
 
         // Check for pending interrupts
         if self.cop0.irq_active(*shared.irq_state()) {
             shared.counters_mut().cpu_interrupt.increment();
+            shared.stats_mut().interrupts += 1;
 
             module_tracer("CPU", |m| {
                 let now = shared.tk().now();
@@ -181,7 +479,7 @@ impl Cpu {
 
             // XXX No idea how long the interrupt switch takes on the
             // real hardware?
-            shared.tk().tick(1);
+            shared.tk().cpu_tick(1);
 
             self.exception(Exception::Interrupt);
         } else {
@@ -190,6 +488,69 @@ impl Cpu {
         }
     }
 
+    /// Sanity checks run before every instruction in debug builds
+    /// to catch emulator bugs as early as possible
+    #[cfg(debug_assertions)]
+    fn check_invariants(&self) {
+        if self.regs[0] != 0 {
+            panic!("R0 contains 0x{:08x}", self.regs[0]);
+        }
+
+        let (RegisterIndex(target), _) = self.load;
+
+        if target > 31 {
+            panic!("Pending load targets invalid register {}", target);
+        }
+
+        // Only a jump can change the sequential flow of the PC
+        if !self.branch && self.next_pc != self.pc.wrapping_add(4) {
+            panic!("Unexpected next PC: 0x{:08x} -> 0x{:08x}",
+                   self.pc, self.next_pc);
+        }
+
+        // Exception handlers are aligned, which means that a
+        // misaligned PC can only come from a jump target, right after
+        // the delay slot
+        if self.pc % 4 != 0 && !self.delay_slot {
+            panic!("Misaligned PC 0x{:08x} outside of a jump", self.pc);
+        }
+    }
+
+    /// Check that an exception entry (`entering`) or an RFE moved
+    /// the SR mode stack (bits [5:0]) by one level without touching
+    /// the other bits
+    #[cfg(debug_assertions)]
+    fn check_mode_stack(&self, old_sr: u32, entering: bool) {
+        let sr = self.cop0.sr();
+
+        let expected =
+            if entering {
+                // Push kernel mode with the interrupts disabled, the
+                // oldest entry is lost
+                (old_sr & !0x3f) | ((old_sr << 2) & 0x3c)
+            } else {
+                // Pop, the oldest entry is duplicated
+                (old_sr & !0xf) | ((old_sr >> 2) & 0xf)
+            };
+
+        if sr != expected {
+            panic!("Bad SR mode stack update: 0x{:08x} -> 0x{:08x}",
+                   old_sr, sr);
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline(always)]
+    fn check_mode_stack(&self, _: u32, _: bool) {
+        // NOP
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline(always)]
+    fn check_invariants(&self) {
+        // NOP
+    }
+
     /// Force the value of the PC
     pub fn set_pc(&mut self, pc: u32) {
         self.pc = pc;
@@ -204,7 +565,7 @@ impl Cpu {
 
         // KUSEG and KSEG0 regions are cached. KSEG1 is uncached and
         // KSEG2 doesn't contain any code
-        let cached = pc < 0xa0000000;
+        let cached = Segment::from_address(pc).is_cached();
 
         if cached && cc.icache_enabled() {
             // The MSB is ignored: running from KUSEG or KSEG0 hits
@@ -213,45 +574,36 @@ impl Cpu {
             // jump from one to the other without having to reload the
             // cache.
 
-            // Cache tag: bits [30:12]
-            let tag  = pc & 0x7ffff000;
-            // Cache line "bucket": bits [11:4]
-            let line = (pc >> 4) & 0xff;
-            // Index in the cache line: bits [3:2]
-            let index = (pc >> 2) & 3;
-
-            // Fetch the cacheline for this address
-            let line = &mut self.icache[line as usize];
-
-            // Check the tag and validity
-            if line.tag() != tag || line.valid_index() > index {
-                // Cache miss. Fetch the cacheline starting at the
-                // current index. If the index is not 0 then some
-                // words are going to remain invalid in the cacheline.
-                let mut cpc = pc;
+            if let Some(instruction) = self.icache.lookup(pc) {
+                return instruction;
+            }
 
-                // XXX Fetch timings from mednafen, on my console it
-                // seems a bit faster than that, need to review those
-                // timings when I decide to implement CPU pipelining
-                // and whatnot
-                shared.tk().tick(3);
+            // Cache miss. Fetch the cacheline starting at the current
+            // index. If the index is not 0 then some words are going
+            // to remain invalid in the cacheline.
+            let index = (pc >> 2) & 3;
+            let mut cpc = pc;
 
-                for i in index..4 {
-                    shared.tk().tick(1);
+            // XXX Fetch timings from mednafen, on my console it seems
+            // a bit faster than that, need to review those timings
+            // when I decide to implement CPU pipelining and whatnot
+            shared.tk().cpu_tick(3);
 
-                    let instruction =
-                        Instruction(self.inter.load_instruction(shared, cpc));
+            for _ in index..4 {
+                shared.tk().cpu_tick(1);
 
-                    line.set_instruction(i, instruction);
-                    cpc += 4;
-                }
+                let instruction =
+                    Instruction(self.inter.load_instruction(shared, cpc));
 
-                // Set the tag and valid bits
-                line.set_tag_valid(pc);
+                self.icache.set_word(cpc, instruction);
+                cpc += 4;
             }
 
+            // Set the tag and valid bits
+            self.icache.validate_line(pc);
+
             // Cache line is now guaranteed to be valid
-            line.instruction(index)
+            self.icache.lookup(pc).unwrap()
         } else {
             // XXX Apparently pointing the PC to KSEG2 causes a bus
             // error no matter what, even if you point it at some
@@ -262,28 +614,58 @@ impl Cpu {
 
             // Cache disabled, fetch directly from memory. Takes 4 to
             // 5 cycles on average.
-            shared.tk().tick(4);
+            shared.tk().cpu_tick(4);
 
             Instruction(self.inter.load_instruction(shared, pc))
         }
     }
 
-    /// Memory read
+    /// Memory read. Returns `None` if the access was aborted by an
+    /// exception, in which case the target register must not be
+    /// modified.
     fn load<A, D>(&mut self,
                   debugger: &mut D,
                   shared: &mut SharedState,
-                  addr: u32) -> u32
+                  addr: u32) -> Option<u32>
     where A: Addressable, D: Debugger {
         debugger.memory_read(self, addr);
+        self.instrumentation.memory_read(self.current_pc, addr, A::size());
 
-        self.inter.load::<A>(shared, addr)
+        if self.cop0.data_breakpoint(addr, false) {
+            // The exception is taken on the faulting instruction
+            // before the access takes place
+            self.debug_exception();
+            return None;
+        }
+
+        if self.write_buffer.enabled() {
+            self.write_buffer.load(shared.tk(), addr);
+        }
+
+        if self.timing.enabled() {
+            self.timing.load(shared.tk(), addr);
+        }
+
+        let v = self.inter.load::<A>(shared, addr);
+
+        if self.inter.take_bus_error() {
+            self.exception(Exception::BusErrorData);
+            return None;
+        }
+
+        Some(v)
     }
 
     /// Memory read with as little side-effect as possible. Used for
     /// debugging.
     pub fn examine<A: Addressable>(&mut self, addr: u32) -> u32 {
 
-        self.inter.load::<A>(&mut SharedState::new(), addr)
+        let v = self.inter.load::<A>(&mut SharedState::new(), addr);
+
+        // Don't let the debugger trigger bus errors
+        self.inter.take_bus_error();
+
+        v
     }
 
     /// Memory write
@@ -304,11 +686,29 @@ impl Cpu {
                    val: u32)
     where A: Addressable, D: Debugger {
         debugger.memory_write(self, addr);
+        self.instrumentation.memory_write(self.current_pc,
+                                          addr,
+                                          A::size(),
+                                          val);
+
+        if self.cop0.data_breakpoint(addr, true) {
+            // The write is aborted
+            self.debug_exception();
+            return;
+        }
 
         if self.cop0.cache_isolated() {
             self.cache_maintenance::<A>(addr, val);
         } else {
+            if self.write_buffer.enabled() {
+                self.write_buffer.store(shared.tk(), addr);
+            }
+
             self.inter.store::<A>(shared, renderer, addr, val);
+
+            if self.inter.take_bus_error() {
+                self.exception(Exception::BusErrorData);
+            }
         }
     }
 
@@ -330,22 +730,13 @@ impl Cpu {
                    val);
         }
 
-        let line = (addr >> 4) & 0xff;
-
-        // Fetch the cacheline for this address
-        let line = &mut self.icache[line as usize];
-
         if cc.tag_test_mode() {
             // In tag test mode the write invalidates the entire
             // targeted cacheline
-            line.invalidate();
+            self.icache.invalidate_line(addr);
         } else {
             // Otherwise the write ends up directly in the cache.
-            let index = (addr >> 2) & 3;
-
-            let instruction = Instruction(val);
-
-            line.set_instruction(index, instruction);
+            self.icache.set_word(addr, Instruction(val));
         }
     }
 
@@ -359,23 +750,59 @@ impl Cpu {
         self.next_pc = self.pc.wrapping_add(offset);
 
         self.branch = true;
+
+        if self.timing.enabled() {
+            self.timing.branch_taken();
+        }
     }
 
     /// Trigger an exception
     fn exception(&mut self, cause: Exception) {
 
+        if self.delay_slot {
+            // When an exception occurs in a branch delay slot the
+            // target of the branch is latched in JUMPDEST
+            self.cop0.set_jump_dest(self.pc);
+        }
+
+        let old_sr = self.cop0.sr();
+
         // Update the status register
         let handler_addr =
             self.cop0.enter_exception(cause,
                                       self.current_pc,
                                       self.delay_slot);
 
+        self.check_mode_stack(old_sr, true);
+
         // Exceptions don't have a branch delay, we jump directly into
         // the handler
         self.pc      = handler_addr;
         self.next_pc = self.pc.wrapping_add(4);
     }
 
+    /// Trigger an address error exception and latch the offending
+    /// address in BAD_VADDR
+    fn address_exception(&mut self, cause: Exception, addr: u32) {
+        self.cop0.set_bad_vaddr(addr);
+
+        self.exception(cause);
+    }
+
+    /// Trigger a cop0 hardware breakpoint exception
+    fn debug_exception(&mut self) {
+        let old_sr = self.cop0.sr();
+
+        let handler_addr =
+            self.cop0.enter_debug_exception(self.current_pc,
+                                            self.delay_slot);
+
+        self.check_mode_stack(old_sr, true);
+
+        self.pc      = handler_addr;
+        self.next_pc = self.pc.wrapping_add(4);
+    }
+
     /// Retrieve the value of a general purpose register
     fn reg(&self, index: RegisterIndex) -> u32 {
         self.regs[index.0 as usize]
@@ -389,6 +816,29 @@ impl Cpu {
         self.regs[0] = 0;
     }
 
+    /// Track the precise vertex values moved from `s` or `t` into
+    /// `d` by `or` and `addu` (used by the `move` pseudo-instruction)
+    fn pgxp_move(&mut self,
+                 d: RegisterIndex,
+                 s: RegisterIndex,
+                 t: RegisterIndex) {
+        if !self.inter.pgxp().enabled() {
+            return;
+        }
+
+        let precise =
+            match (s.0, t.0) {
+                (0, src) | (src, 0) => {
+                    let v = self.regs[src as usize];
+
+                    self.inter.pgxp().reg(src, v)
+                }
+                _ => None,
+            };
+
+        self.inter.pgxp_mut().set_reg(d.0, precise);
+    }
+
     /// Execute any pending delayed load. Should be called *after* the
     /// input registers are read but *before* the output registers are
     /// written
@@ -405,7 +855,8 @@ impl Cpu {
     /// Execute the pending delayed and setup the next one. If the new
     /// load targets the same register as the current one then the
     /// older one is cancelled (i.e. it never makes it to the
-    /// register).
+    /// register). This applies to any combination of memory loads and
+    /// coprocessor moves.
     ///
     /// This method should be used instead of `delayed_load` for
     /// instructions that setup a delayed load.
@@ -417,6 +868,20 @@ impl Cpu {
         }
 
         self.load = (reg, val);
+
+        self.timing.load_target(reg.0);
+    }
+
+    /// Return the value of `reg` including the pending delayed load,
+    /// if any. Used by LWL and LWR which bypass the load delay.
+    fn reg_with_pending_load(&self, reg: RegisterIndex) -> u32 {
+        let (pending_reg, pending_val) = self.load;
+
+        if pending_reg == reg {
+            pending_val
+        } else {
+            self.reg(reg)
+        }
     }
 
     /// Get the value of all general purpose registers
@@ -445,9 +910,7 @@ impl Cpu {
     }
 
     pub fn bad(&self) -> u32 {
-        // XXX we don't emulate the "BAD" cop0 register yet. It's
-        // almost useless in the PSX anyway since there's no MMU.
-        0
+        self.cop0.bad_vaddr()
     }
 
     /// Force PC address. Meant to be used from the debugger. Use at
@@ -466,80 +929,93 @@ impl Cpu {
                              renderer: &mut Renderer)
         where D: Debugger {
         // Simulate instruction execution time.
-        shared.tk().tick(1);
-
-        match instruction.function() {
-            0b000000 => match instruction.subfunction() {
-                0b000000 => self.op_sll(instruction),
-                0b000010 => self.op_srl(instruction),
-                0b000011 => self.op_sra(instruction),
-                0b000100 => self.op_sllv(instruction),
-                0b000110 => self.op_srlv(instruction),
-                0b000111 => self.op_srav(instruction),
-                0b001000 => self.op_jr(instruction),
-                0b001001 => self.op_jalr(instruction),
-                0b001100 => self.op_syscall(instruction),
-                0b001101 => self.op_break(instruction, debugger),
-                0b010000 => self.op_mfhi(instruction),
-                0b010001 => self.op_mthi(instruction),
-                0b010010 => self.op_mflo(instruction),
-                0b010011 => self.op_mtlo(instruction),
-                0b011000 => self.op_mult(instruction),
-                0b011001 => self.op_multu(instruction),
-                0b011010 => self.op_div(instruction),
-                0b011011 => self.op_divu(instruction),
-                0b100000 => self.op_add(instruction),
-                0b100001 => self.op_addu(instruction),
-                0b100010 => self.op_sub(instruction),
-                0b100011 => self.op_subu(instruction),
-                0b100100 => self.op_and(instruction),
-                0b100101 => self.op_or(instruction),
-                0b100110 => self.op_xor(instruction),
-                0b100111 => self.op_nor(instruction),
-                0b101010 => self.op_slt(instruction),
-                0b101011 => self.op_sltu(instruction),
-                _        => self.op_illegal(instruction),
-            },
-            0b000001 => self.op_bxx(instruction),
-            0b000010 => self.op_j(instruction),
-            0b000011 => self.op_jal(instruction),
-            0b000100 => self.op_beq(instruction),
-            0b000101 => self.op_bne(instruction),
-            0b000110 => self.op_blez(instruction),
-            0b000111 => self.op_bgtz(instruction),
-            0b001000 => self.op_addi(instruction),
-            0b001001 => self.op_addiu(instruction),
-            0b001010 => self.op_slti(instruction),
-            0b001011 => self.op_sltiu(instruction),
-            0b001100 => self.op_andi(instruction),
-            0b001101 => self.op_ori(instruction),
-            0b001110 => self.op_xori(instruction),
-            0b001111 => self.op_lui(instruction),
-            0b010000 => self.op_cop0(instruction, shared),
-            0b010001 => self.op_cop1(instruction),
-            0b010010 => self.op_cop2(instruction),
-            0b010011 => self.op_cop3(instruction),
-            0b100000 => self.op_lb(instruction, debugger, shared),
-            0b100001 => self.op_lh(instruction, debugger, shared),
-            0b100010 => self.op_lwl(instruction, debugger, shared),
-            0b100011 => self.op_lw(instruction, debugger, shared),
-            0b100100 => self.op_lbu(instruction, debugger, shared),
-            0b100101 => self.op_lhu(instruction, debugger, shared),
-            0b100110 => self.op_lwr(instruction, debugger, shared),
-            0b101000 => self.op_sb(instruction, debugger, shared, renderer),
-            0b101001 => self.op_sh(instruction, debugger, shared, renderer),
-            0b101010 => self.op_swl(instruction, debugger, shared, renderer),
-            0b101011 => self.op_sw(instruction, debugger, shared, renderer),
-            0b101110 => self.op_swr(instruction, debugger, shared, renderer),
-            0b110000 => self.op_lwc0(instruction),
-            0b110001 => self.op_lwc1(instruction),
-            0b110010 => self.op_lwc2(instruction, debugger, shared),
-            0b110011 => self.op_lwc3(instruction),
-            0b111000 => self.op_swc0(instruction),
-            0b111001 => self.op_swc1(instruction),
-            0b111010 => self.op_swc2(instruction, debugger, shared, renderer),
-            0b111011 => self.op_swc3(instruction),
-            _        => self.op_illegal(instruction),
+        shared.tk().cpu_tick(1);
+
+        if self.timing.enabled() {
+            self.timing.issue(shared.tk(), instruction.0);
+        }
+
+        match instruction.op() {
+            Op::Illegal => self.op_illegal(instruction),
+            Op::Sll     => self.op_sll(instruction),
+            Op::Srl     => self.op_srl(instruction),
+            Op::Sra     => self.op_sra(instruction),
+            Op::Sllv    => self.op_sllv(instruction),
+            Op::Srlv    => self.op_srlv(instruction),
+            Op::Srav    => self.op_srav(instruction),
+            Op::Jr      => self.op_jr(instruction),
+            Op::Jalr    => self.op_jalr(instruction),
+            Op::Syscall => self.op_syscall(instruction),
+            Op::Break   => self.op_break(instruction, debugger),
+            Op::Mfhi    => self.op_mfhi(instruction, shared),
+            Op::Mthi    => self.op_mthi(instruction),
+            Op::Mflo    => self.op_mflo(instruction, shared),
+            Op::Mtlo    => self.op_mtlo(instruction),
+            Op::Mult    => self.op_mult(instruction, shared),
+            Op::Multu   => self.op_multu(instruction, shared),
+            Op::Div     => self.op_div(instruction, shared),
+            Op::Divu    => self.op_divu(instruction, shared),
+            Op::Add     => self.op_add(instruction),
+            Op::Addu    => self.op_addu(instruction),
+            Op::Sub     => self.op_sub(instruction),
+            Op::Subu    => self.op_subu(instruction),
+            Op::And     => self.op_and(instruction),
+            Op::Or      => self.op_or(instruction),
+            Op::Xor     => self.op_xor(instruction),
+            Op::Nor     => self.op_nor(instruction),
+            Op::Slt     => self.op_slt(instruction),
+            Op::Sltu    => self.op_sltu(instruction),
+            Op::Bltz    => self.op_bxx(instruction, false, false),
+            Op::Bgez    => self.op_bxx(instruction, true, false),
+            Op::Bltzal  => self.op_bxx(instruction, false, true),
+            Op::Bgezal  => self.op_bxx(instruction, true, true),
+            Op::J       => self.op_j(instruction),
+            Op::Jal     => self.op_jal(instruction),
+            Op::Beq     => self.op_beq(instruction),
+            Op::Bne     => self.op_bne(instruction),
+            Op::Blez    => self.op_blez(instruction),
+            Op::Bgtz    => self.op_bgtz(instruction),
+            Op::Addi    => self.op_addi(instruction),
+            Op::Addiu   => self.op_addiu(instruction),
+            Op::Slti    => self.op_slti(instruction),
+            Op::Sltiu   => self.op_sltiu(instruction),
+            Op::Andi    => self.op_andi(instruction),
+            Op::Ori     => self.op_ori(instruction),
+            Op::Xori    => self.op_xori(instruction),
+            Op::Lui     => self.op_lui(instruction),
+            Op::Mfc0    => self.op_mfc0(instruction, shared),
+            Op::Mtc0    => self.op_mtc0(instruction),
+            Op::Rfe     => self.op_rfe(instruction),
+            Op::Cop0    => panic!("unhandled cop0 instruction {}", instruction),
+            Op::Cop1    => self.op_cop1(instruction),
+            Op::Mfc2    => self.op_mfc2(instruction),
+            Op::Cfc2    => self.op_cfc2(instruction),
+            Op::Mtc2    => self.op_mtc2(instruction),
+            Op::Ctc2    => self.op_ctc2(instruction),
+            Op::Gte     => self.op_gte(instruction),
+            Op::Cop2    => panic!("unhandled GTE instruction {}", instruction),
+            Op::Cop3    => self.op_cop3(instruction),
+            Op::Lb      => self.op_lb(instruction, debugger, shared),
+            Op::Lh      => self.op_lh(instruction, debugger, shared),
+            Op::Lwl     => self.op_lwl(instruction, debugger, shared),
+            Op::Lw      => self.op_lw(instruction, debugger, shared),
+            Op::Lbu     => self.op_lbu(instruction, debugger, shared),
+            Op::Lhu     => self.op_lhu(instruction, debugger, shared),
+            Op::Lwr     => self.op_lwr(instruction, debugger, shared),
+            Op::Sb      => self.op_sb(instruction, debugger, shared, renderer),
+            Op::Sh      => self.op_sh(instruction, debugger, shared, renderer),
+            Op::Swl     => self.op_swl(instruction, debugger, shared, renderer),
+            Op::Sw      => self.op_sw(instruction, debugger, shared, renderer),
+            Op::Swr     => self.op_swr(instruction, debugger, shared, renderer),
+            Op::Lwc0    => self.op_lwc0(instruction),
+            Op::Lwc1    => self.op_lwc1(instruction),
+            Op::Lwc2    => self.op_lwc2(instruction, debugger, shared),
+            Op::Lwc3    => self.op_lwc3(instruction),
+            Op::Swc0    => self.op_swc0(instruction),
+            Op::Swc1    => self.op_swc1(instruction),
+            Op::Swc2    =>
+                self.op_swc2(instruction, debugger, shared, renderer),
+            Op::Swc3    => self.op_swc3(instruction),
         }
     }
 
@@ -547,7 +1023,7 @@ impl Cpu {
     fn op_illegal(&mut self, instruction: Instruction) {
         self.delayed_load();
 
-        warn!("Illegal instruction {} at PC 0x{:08x}!",
+        warn!(target: logging::CPU, "Illegal instruction {} at PC 0x{:08x}!",
               instruction,
               self.current_pc);
 
@@ -635,20 +1111,15 @@ impl Cpu {
         self.set_reg(d, v as u32);
     }
 
-    /// Various branch instructions: BGEZ, BLTZ, BGEZAL, BLTZAL. Bits
-    /// [20:16] are used to figure out which one to use
-    fn op_bxx(&mut self, instruction: Instruction) {
+    /// Various branch instructions: BGEZ, BLTZ, BGEZAL, BLTZAL. The
+    /// decoder figures out which one to use
+    fn op_bxx(&mut self,
+              instruction: Instruction,
+              is_bgez: bool,
+              is_link: bool) {
         let i = instruction.imm_se();
         let s = instruction.s();
 
-        let instruction = instruction.0;
-
-        let is_bgez = (instruction >> 16) & 1;
-        // It's not enough to test for bit 20 to see if we're supposed
-        // to link, if any bit in the range [19:17] is set the link
-        // doesn't take place and RA is left untouched.
-        let is_link = (instruction >> 17) & 0xf == 0x8;
-
         let v = self.reg(s) as i32;
 
         // Test "less than zero"
@@ -657,7 +1128,7 @@ impl Cpu {
         // If the test is "greater than or equal to zero" we need to
         // negate the comparison above ("a >= 0" <=> "!(a < 0)"). The
         // xor takes care of that.
-        let test = test ^ is_bgez;
+        let test = test ^ (is_bgez as u32);
 
         self.delayed_load();
 
@@ -717,17 +1188,42 @@ impl Cpu {
         self.delayed_load();
 
         if self.debug_on_break {
-            info!("BREAK instruction while debug_on_break is active");
+            info!(target: logging::CPU,
+                  "BREAK instruction while debug_on_break is active");
             debugger.trigger_break();
         } else {
             self.exception(Exception::Break);
         }
     }
 
+    /// Start a multiplication or division that will take `duration`
+    /// cycles to complete
+    fn mult_div_start(&mut self,
+                      shared: &mut SharedState,
+                      duration: Cycles) {
+        let duration = shared.tk().cpu_cycles(duration);
+
+        self.mult_div_end = shared.tk().now() + duration;
+    }
+
+    /// Stall the CPU until the result of the pending multiplication
+    /// or division (if any) is available in HI/LO
+    fn mult_div_wait(&mut self, shared: &mut SharedState) {
+        let now = shared.tk().now();
+
+        if now < self.mult_div_end {
+            shared.tk().tick(self.mult_div_end - now);
+        }
+    }
+
     /// Move From HI
-    fn op_mfhi(&mut self, instruction: Instruction) {
+    fn op_mfhi(&mut self,
+               instruction: Instruction,
+               shared: &mut SharedState) {
         let d = instruction.d();
 
+        self.mult_div_wait(shared);
+
         let hi = self.hi;
 
         self.delayed_load();
@@ -745,9 +1241,13 @@ impl Cpu {
     }
 
     /// Move From LO
-    fn op_mflo(&mut self, instruction: Instruction) {
+    fn op_mflo(&mut self,
+               instruction: Instruction,
+               shared: &mut SharedState) {
         let d = instruction.d();
 
+        self.mult_div_wait(shared);
+
         let lo = self.lo;
 
         self.delayed_load();
@@ -765,7 +1265,9 @@ impl Cpu {
     }
 
     /// Multiply (signed)
-    fn op_mult(&mut self, instruction: Instruction) {
+    fn op_mult(&mut self,
+               instruction: Instruction,
+               shared: &mut SharedState) {
         let s = instruction.s();
         let t = instruction.t();
 
@@ -774,6 +1276,17 @@ impl Cpu {
 
         self.delayed_load();
 
+        // The multiplier exits early if the upper bits of the first
+        // operand are all equal to the sign bit
+        let duration =
+            match a {
+                -0x800...0x7ff => MULT_FAST_CYCLES,
+                -0x100000...0xfffff => MULT_MEDIUM_CYCLES,
+                _ => MULT_SLOW_CYCLES,
+            };
+
+        self.mult_div_start(shared, duration);
+
         let v = (a * b) as u64;
 
         self.hi = (v >> 32) as u32;
@@ -781,7 +1294,9 @@ impl Cpu {
     }
 
     /// Multiply Unsigned
-    fn op_multu(&mut self, instruction: Instruction) {
+    fn op_multu(&mut self,
+                instruction: Instruction,
+                shared: &mut SharedState) {
         let s = instruction.s();
         let t = instruction.t();
 
@@ -790,6 +1305,15 @@ impl Cpu {
 
         self.delayed_load();
 
+        let duration =
+            match a {
+                0...0x7ff => MULT_FAST_CYCLES,
+                0x800...0xfffff => MULT_MEDIUM_CYCLES,
+                _ => MULT_SLOW_CYCLES,
+            };
+
+        self.mult_div_start(shared, duration);
+
         let v = a * b;
 
         self.hi = (v >> 32) as u32;
@@ -797,7 +1321,9 @@ impl Cpu {
     }
 
     /// Divide (signed)
-    fn op_div(&mut self, instruction: Instruction) {
+    fn op_div(&mut self,
+              instruction: Instruction,
+              shared: &mut SharedState) {
         let s = instruction.s();
         let t = instruction.t();
 
@@ -806,6 +1332,8 @@ impl Cpu {
 
         self.delayed_load();
 
+        self.mult_div_start(shared, DIV_CYCLES);
+
         if d == 0 {
             // Division by zero, results are bogus
             self.hi = n as u32;
@@ -826,7 +1354,9 @@ impl Cpu {
     }
 
     /// Divide Unsigned
-    fn op_divu(&mut self, instruction: Instruction) {
+    fn op_divu(&mut self,
+               instruction: Instruction,
+               shared: &mut SharedState) {
         let s = instruction.s();
         let t = instruction.t();
 
@@ -835,6 +1365,8 @@ impl Cpu {
 
         self.delayed_load();
 
+        self.mult_div_start(shared, DIV_CYCLES);
+
         if d == 0 {
             // Division by zero, results are bogus
             self.hi = n;
@@ -870,6 +1402,8 @@ impl Cpu {
 
         let v = self.reg(s).wrapping_add(self.reg(t));
 
+        self.pgxp_move(d, s, t);
+
         self.delayed_load();
 
         self.set_reg(d, v);
@@ -926,6 +1460,8 @@ impl Cpu {
 
         let v = self.reg(s) | self.reg(t);
 
+        self.pgxp_move(d, s, t);
+
         self.delayed_load();
 
         self.set_reg(d, v);
@@ -1170,49 +1706,37 @@ impl Cpu {
         self.set_reg(t, v);
     }
 
-    /// Coprocessor 0 opcode
-    fn op_cop0(&mut self, instruction: Instruction, shared: &mut SharedState) {
-        match instruction.cop_opcode() {
-            0b00000 => self.op_mfc0(instruction, shared),
-            0b00100 => self.op_mtc0(instruction),
-            0b10000 => self.op_rfe(instruction),
-            _       => panic!("unhandled cop0 instruction {}", instruction)
-        }
-    }
-
     /// Move From Coprocessor 0
     fn op_mfc0(&mut self, instruction: Instruction, shared: &mut SharedState) {
         let cpu_r = instruction.t();
         let cop_r = instruction.d().0;
 
         let v = match cop_r {
-            6 => {
-                // No$ says this register "randomly" memorizes a jump
-                // target after certain exceptions occur. Doesn't seem
-                // very useful and would require a lot more testing to
-                // implement accurately.
-                warn!("Unhandled read from JUMP_DEST (cop0r6)");
-                0
-            }
-            7 => {
-                // DCIC: breakpoint control
-                warn!("Unhandled read from DCIC (cop0r7)");
-                0
-            }
-            8 => {
-                // This register should be mostly useless on the
-                // PlayStation since it doesn't have virtual memory,
-                // however some exceptions do write to this register
-                // so maybe we'll have to implement this correctly
-                // some day.
-                warn!("Unhandled read from BAD_VADDR (cop0r8)");
-                0
-            }
+            // No$ says this register "randomly" memorizes a jump
+            // target after certain exceptions occur. We only latch
+            // the branch target when an exception occurs in a delay
+            // slot which seems to be the common case.
+            6 => self.cop0.jump_dest(),
+            3 => self.cop0.bpc(),
+            5 => self.cop0.bda(),
+            7 => self.cop0.dcic(),
+            // The PlayStation doesn't have virtual memory but
+            // address errors still latch the faulty address here
+            8 => self.cop0.bad_vaddr(),
+            9 => self.cop0.bdam(),
+            11 => self.cop0.bpcm(),
             12 => self.cop0.sr(),
             13 => self.cop0.cause(*shared.irq_state()),
             14 => self.cop0.epc(),
             15 => PROCESSOR_ID,
-            _  => panic!("Unhandled read from cop0r{}", cop_r),
+            // Registers 0, 1, 2, 4 and 10 are virtual memory related
+            // and not present on the PlayStation, 16 and above don't
+            // exist. They return garbage on the real hardware.
+            _  => {
+                warn!(target: logging::CPU,
+                      "Read from unimplemented cop0r{}", cop_r);
+                0
+            }
         };
 
         self.delayed_load_chain(cpu_r, v);
@@ -1228,10 +1752,13 @@ impl Cpu {
         self.delayed_load();
 
         match cop_r {
-            3 | 5 | 6 | 7 | 9 | 11  => // Breakpoints registers
-                if v != 0 {
-                    panic!("Unhandled write to cop0r{}: {:08x}", cop_r, v)
-                },
+            3  => self.cop0.set_bpc(v),
+            5  => self.cop0.set_bda(v),
+            // JUMPDEST is read-only
+            6  => (),
+            7  => self.cop0.set_dcic(v),
+            9  => self.cop0.set_bdam(v),
+            11 => self.cop0.set_bpcm(v),
             12 => self.cop0.set_sr(v),
             13 => self.cop0.set_cause(v),
             _  => panic!("Unhandled cop0 register {}", cop_r),
@@ -1239,18 +1766,14 @@ impl Cpu {
     }
 
     /// Return From Exception
-    fn op_rfe(&mut self, instruction: Instruction) {
+    fn op_rfe(&mut self, _: Instruction) {
         self.delayed_load();
 
-        // There are other instructions with the same encoding but all
-        // are virtual memory related and the PlayStation doesn't
-        // implement them. Still, let's make sure we're not running
-        // buggy code.
-        if instruction.0 & 0x3f != 0b010000 {
-            panic!("Invalid cop0 instruction: {}", instruction);
-        }
+        let old_sr = self.cop0.sr();
 
         self.cop0.return_from_exception();
+
+        self.check_mode_stack(old_sr, false);
     }
 
     /// Coprocessor 1 opcode (does not exist on the PlayStation)
@@ -1260,29 +1783,21 @@ impl Cpu {
         self.exception(Exception::CoprocessorError);
     }
 
-    /// Coprocessor 2 opcode (GTE)
-    fn op_cop2(&mut self, instruction: Instruction) {
+    /// GTE command
+    fn op_gte(&mut self, instruction: Instruction) {
         // XXX: we should check that the GTE is enabled in cop0's
         // status register, otherwise the cop2 instructions seem to
         // freeze the CPU (or maybe raise an exception?). Furthermore
         // it seems that one has to wait at least two cycles (tested
         // with two nops) after raising the flag in the status
         // register before the GTE can be accessed.
-        let cop_opcode = instruction.cop_opcode();
 
-        if cop_opcode & 0x10 != 0 {
-            // GTE command
-            // XXX handle GTE command duration
-            self.gte.command(instruction.0);
-        } else {
-            match cop_opcode {
-                0b00000 => self.op_mfc2(instruction),
-                0b00010 => self.op_cfc2(instruction),
-                0b00100 => self.op_mtc2(instruction),
-                0b00110 => self.op_ctc2(instruction),
-                _       => panic!("unhandled GTE instruction {}", instruction),
-            }
-        }
+        // GTE commands don't touch the CPU registers but we still
+        // have to retire the pending load in time
+        self.delayed_load();
+
+        // XXX handle GTE command duration
+        self.gte.command(instruction.0);
     }
 
     /// Move From Coprocessor 2 Data register
@@ -1292,9 +1807,24 @@ impl Cpu {
 
         let v = self.gte.data(cop_r);
 
+        if self.inter.pgxp().enabled() {
+            let precise = self.gte_precise_sxy(cop_r);
+
+            self.inter.pgxp_mut().set_reg(cpu_r.0, precise);
+        }
+
         self.delayed_load_chain(cpu_r, v);
     }
 
+    /// Return the precise value of GTE data register `cop_r` if it's
+    /// one of SXY0, SXY1 or SXY2
+    fn gte_precise_sxy(&self, cop_r: u32) -> Option<PreciseVertex> {
+        match cop_r {
+            12...14 => self.gte.precise_sxy((cop_r - 12) as usize),
+            _ => None,
+        }
+    }
+
     /// Move From Coprocessor 2 Control register
     fn op_cfc2(&mut self, instruction: Instruction) {
         let cpu_r = instruction.t();
@@ -1349,8 +1879,16 @@ impl Cpu {
 
         let addr = self.reg(s).wrapping_add(i);
 
-        // Cast as i8 to force sign extension
-        let v = self.load::<Byte, D>(debugger, shared, addr) as i8;
+        let v =
+            match self.load::<Byte, D>(debugger, shared, addr) {
+                // Cast as i8 to force sign extension
+                Some(v) => v as i8,
+                None => {
+                    // The load was aborted by an exception
+                    self.delayed_load();
+                    return;
+                }
+            };
 
         self.delayed_load_chain(t, v as u32);
     }
@@ -1369,13 +1907,21 @@ impl Cpu {
 
         // Address must be 16bit aligned
         if addr % 2 == 0 {
-            // Cast as i16 to force sign extension
-            let v = self.load::<HalfWord, D>(debugger, shared, addr) as i16;
+            let v =
+                match self.load::<HalfWord, D>(debugger, shared, addr) {
+                    // Cast as i16 to force sign extension
+                    Some(v) => v as i16,
+                    None => {
+                        // The load was aborted by an exception
+                        self.delayed_load();
+                        return;
+                    }
+                };
 
             self.delayed_load_chain(t, v as u32);
         } else {
             self.delayed_load();
-            self.exception(Exception::LoadAddressError);
+            self.address_exception(Exception::LoadAddressError, addr);
         }
     }
 
@@ -1394,19 +1940,20 @@ impl Cpu {
         // This instruction bypasses the load delay restriction: this
         // instruction will merge the new contents with the value
         // currently being loaded if need be.
-        let (pending_reg, pending_value) = self.load;
-
-        let cur_v =
-            if pending_reg == t {
-                pending_value
-            } else {
-                self.reg(t)
-            };
+        let cur_v = self.reg_with_pending_load(t);
 
         // Next we load the *aligned* word containing the first
         // addressed byte
         let aligned_addr = addr & !3;
-        let aligned_word = self.load::<Word, D>(debugger, shared, aligned_addr);
+        let aligned_word =
+            match self.load::<Word, D>(debugger, shared, aligned_addr) {
+                Some(v) => v,
+                None => {
+                    // The load was aborted by an exception
+                    self.delayed_load();
+                    return;
+                }
+            };
 
         // Depending on the address alignment we fetch the 1, 2, 3 or
         // 4 *most* significant bytes and put them in the target
@@ -1436,12 +1983,26 @@ impl Cpu {
 
         // Address must be 32bit aligned
         if addr % 4 == 0 {
-            let v = self.load::<Word, D>(debugger, shared, addr);
+            let v =
+                match self.load::<Word, D>(debugger, shared, addr) {
+                    Some(v) => v,
+                    None => {
+                        // The load was aborted by an exception
+                        self.delayed_load();
+                        return;
+                    }
+                };
+
+            if self.inter.pgxp().enabled() {
+                let precise = self.inter.pgxp().load(addr, v);
+
+                self.inter.pgxp_mut().set_reg(t.0, precise);
+            }
 
             self.delayed_load_chain(t, v);
         } else {
             self.delayed_load();
-            self.exception(Exception::LoadAddressError);
+            self.address_exception(Exception::LoadAddressError, addr);
         }
     }
 
@@ -1457,7 +2018,15 @@ impl Cpu {
 
         let addr = self.reg(s).wrapping_add(i);
 
-        let v = self.load::<Byte, D>(debugger, shared, addr);
+        let v =
+            match self.load::<Byte, D>(debugger, shared, addr) {
+                Some(v) => v,
+                None => {
+                    // The load was aborted by an exception
+                    self.delayed_load();
+                    return;
+                }
+            };
 
         self.delayed_load_chain(t, v as u32);
     }
@@ -1476,12 +2045,20 @@ impl Cpu {
 
         // Address must be 16bit aligned
         if addr % 2 == 0 {
-            let v = self.load::<HalfWord, D>(debugger, shared, addr);
+            let v =
+                match self.load::<HalfWord, D>(debugger, shared, addr) {
+                    Some(v) => v,
+                    None => {
+                        // The load was aborted by an exception
+                        self.delayed_load();
+                        return;
+                    }
+                };
 
             self.delayed_load_chain(t, v);
         } else {
             self.delayed_load();
-            self.exception(Exception::LoadAddressError);
+            self.address_exception(Exception::LoadAddressError, addr);
         }
     }
 
@@ -1500,19 +2077,20 @@ impl Cpu {
         // This instruction bypasses the load delay restriction: this
         // instruction will merge the new contents with the value
         // currently being loaded if need be.
-        let (pending_reg, pending_value) = self.load;
-
-        let cur_v =
-            if pending_reg == t {
-                pending_value
-            } else {
-                self.reg(t)
-            };
+        let cur_v = self.reg_with_pending_load(t);
 
         // Next we load the *aligned* word containing the first
         // addressed byte
         let aligned_addr = addr & !3;
-        let aligned_word = self.load::<Word, D>(debugger, shared, aligned_addr);
+        let aligned_word =
+            match self.load::<Word, D>(debugger, shared, aligned_addr) {
+                Some(v) => v,
+                None => {
+                    // The load was aborted by an exception
+                    self.delayed_load();
+                    return;
+                }
+            };
 
         // Depending on the address alignment we fetch the 1, 2, 3 or
         // 4 *least* significant bytes and put them in the target
@@ -1568,7 +2146,7 @@ impl Cpu {
         if addr % 2 == 0 {
             self.store::<HalfWord, D>(debugger, shared, renderer, addr, v);
         } else {
-            self.exception(Exception::StoreAddressError);
+            self.address_exception(Exception::StoreAddressError, addr);
         }
     }
 
@@ -1587,9 +2165,7 @@ impl Cpu {
         let v    = self.reg(t);
 
         let aligned_addr = addr & !3;
-        // Load the current value for the aligned word at the target
-        // address
-        let cur_mem = self.load::<Word, D>(debugger, shared, aligned_addr);
+        let cur_mem = self.unaligned_store_base(aligned_addr);
 
         let mem =
             match addr & 3 {
@@ -1623,12 +2199,39 @@ impl Cpu {
 
         // Address must be 32bit aligned
         if addr % 4 == 0 {
+            if self.inter.pgxp().enabled() {
+                let precise = self.inter.pgxp().reg(t.0, v);
+
+                self.inter.pgxp_mut().store(addr, v, precise);
+            }
+
             self.store::<Word, D>(debugger, shared, renderer, addr, v);
         } else {
-            self.exception(Exception::StoreAddressError);
+            self.address_exception(Exception::StoreAddressError, addr);
         }
     }
 
+    /// Return the current value of the aligned word targeted by SWL or
+    /// SWR. On the real hardware these instructions don't read the
+    /// memory, they only enable the bus lanes of the bytes being
+    /// written. Our interconnect doesn't support partial writes so we
+    /// peek at the memory to merge the word with the new bytes,
+    /// without any load side effect. These instructions never raise
+    /// an address error since they're designed to handle unaligned
+    /// addresses.
+    fn unaligned_store_base(&self, aligned_addr: u32) -> u32 {
+        if self.cop0.cache_isolated() {
+            // The store is going to end up in the cache, the
+            // contents of the memory don't matter
+            return 0;
+        }
+
+        // The devices see the shifted register value, like on the
+        // real hardware where they get the register on the whole bus
+        // with only some of the byte lanes enabled
+        self.inter.peek::<Word>(aligned_addr).unwrap_or(0)
+    }
+
     /// Store Word Right (little-endian only implementation)
     fn op_swr<D: Debugger>(&mut self,
                            instruction: Instruction,
@@ -1644,9 +2247,7 @@ impl Cpu {
         let v    = self.reg(t);
 
         let aligned_addr = addr & !3;
-        // Load the current value for the aligned word at the target
-        // address
-        let cur_mem = self.load::<Word, D>(debugger, shared, aligned_addr);
+        let cur_mem = self.unaligned_store_base(aligned_addr);
 
         let mem =
             match addr & 3 {
@@ -1694,12 +2295,19 @@ impl Cpu {
 
         // Address must be 32bit aligned
         if addr % 4 == 0 {
-            let v = self.load::<Word, D>(debugger, shared, addr);
-
-            // Send to coprocessor
+            let v =
+                match self.load::<Word, D>(debugger, shared, addr) {
+                    Some(v) => v,
+                    // The load was aborted by an exception
+                    None => return,
+                };
+
+            // Send to coprocessor. Unlike the CPU registers there's
+            // no load delay slot here: the GTE interlocks and the
+            // value is available to the next instruction.
             self.gte.set_data(cop_r, v);
         } else {
-            self.exception(Exception::LoadAddressError);
+            self.address_exception(Exception::LoadAddressError, addr);
         }
     }
 
@@ -1744,9 +2352,15 @@ impl Cpu {
 
         // Address must be 32bit aligned
         if addr % 4 == 0 {
+            if self.inter.pgxp().enabled() {
+                let precise = self.gte_precise_sxy(cop_r);
+
+                self.inter.pgxp_mut().store(addr, v, precise);
+            }
+
             self.store::<Word, D>(debugger, shared, renderer, addr, v);
         } else {
-            self.exception(Exception::LoadAddressError);
+            self.address_exception(Exception::StoreAddressError, addr);
         }
     }
 
@@ -1837,6 +2451,11 @@ impl Instruction {
         op & 0x3ffffff
     }
 
+    /// Decode the operation encoded in the instruction
+    fn op(self) -> Op {
+        Op::decode(self.0)
+    }
+
     /// Return true if the instruction contains a GTE/COP2 opcode
     fn is_gte_op(self) -> bool {
         // XXX This will match all GTE instructions including mfc/mtc
@@ -1855,78 +2474,364 @@ impl Display for Instruction {
 #[derive(Clone, Copy, RustcDecodable, RustcEncodable, PartialEq, Eq)]
 struct RegisterIndex(u32);
 
-/// Instruction cache line
-#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
-struct ICacheLine {
-    /// Tag: high 22bits of the address associated with this cacheline
-    /// Valid bits: 3 bit index of the first valid word in line.
-    tag_valid: u32,
-    /// Four words per line
-    line: [Instruction; 4],
+/// Value of the "Processor ID" register (Cop0r15). This is the value
+/// returned by my SCPH-7502.
+pub const PROCESSOR_ID: u32 = 0x00000002;
+
+/// PlayStation CPU clock in Hz
+pub const CPU_FREQ_HZ: u32 = 33_868_500;
+
+/// Duration of a multiplication when the first operand fits in 12
+/// bits (including the sign for MULT)
+const MULT_FAST_CYCLES: Cycles = 6;
+
+/// Duration of a multiplication when the first operand fits in 21
+/// bits (including the sign for MULT)
+const MULT_MEDIUM_CYCLES: Cycles = 9;
+
+/// Duration of a multiplication with a "large" first operand
+const MULT_SLOW_CYCLES: Cycles = 13;
+
+/// Duration of a division, regardless of the operands
+const DIV_CYCLES: Cycles = 36;
+
+#[test]
+fn exception_vector_in_ram() {
+    use self::test_machine::TestMachine;
+
+    let mut m = TestMachine::new()
+        .with_blob(0x80100000, &[0x3c01fffe, // lui   $1, 0xfffe
+                                 0x34020800, // ori   $2, $0, 0x800
+                                 0xac220130, // sw    $2, 0x130($1)
+                                 0x0000000c]) // syscall
+        .with_blob(0x80000080, &[0x24020042, // addiu $2, $0, 0x42
+                                 0x0bab6fb8, // j     0xeadbee0
+                                 0x00000000]);
+
+    // The icache is enabled before the exception, the handler must
+    // be fetched from RAM
+    m.run_until(0xeadbee0);
+
+    m.assert_reg(2, 0x42);
+    assert_eq!(m.cpu.cop0.epc(), 0x8010000c);
+    assert_eq!((m.cpu.cause(InterruptState::new()) >> 2) & 0x1f,
+               Exception::SysCall as u32);
+
+    // The cache doesn't see the writes to RAM, the stale handler
+    // runs until the cache is flushed
+    m.cpu.interconnect_mut().ram_mut().store::<Word>(0x80, 0x24020043);
+
+    write_code(&mut m, 0x80100100, &[0x0000000c]); // syscall
+    m.run_until(0xeadbee0);
+
+    m.assert_reg(2, 0x42);
+
+    // Flush the cacheline the same way the BIOS does: isolate the
+    // cache and write 0 in tag test mode
+    write_code(&mut m, 0x80100200,
+               &[0x3c01fffe, // lui   $1, 0xfffe
+                 0x34020804, // ori   $2, $0, 0x804
+                 0xac220130, // sw    $2, 0x130($1)
+                 0x3c030001, // lui   $3, 1
+                 0x40836000, // mtc0  $3, $12
+                 0x00000000, // nop
+                 0xac000080, // sw    $0, 0x80($0)
+                 0x40806000, // mtc0  $0, $12
+                 0x00000000, // nop
+                 0x34020800, // ori   $2, $0, 0x800
+                 0xac220130, // sw    $2, 0x130($1)
+                 0x0000000c]); // syscall
+    m.run_until(0xeadbee0);
+
+    m.assert_reg(2, 0x43);
+    // The RAM wasn't modified by the isolated write
+    assert_eq!(m.read::<Word>(0x80), 0x24020043);
 }
 
-impl ICacheLine {
-    fn new() -> ICacheLine {
-        // The cache starts in a random state. In order to catch
-        // missbehaving software we fill them with "trap" values
-        ICacheLine {
-            // Tag is 0, all line valid
-            tag_valid: 0x0,
-            // BREAK opcode
-            //line: [Instruction(0xbadc0de5); 4],
-            line: [Instruction(0); 4],
-        }
-    }
+#[test]
+fn exception_vector_bev() {
+    use self::test_machine::TestMachine;
 
-    /// Return the cacheline's tag
-    fn tag(&self) -> u32 {
-        self.tag_valid & 0xfffff000
-    }
+    let mut m = TestMachine::new()
+        .with_blob(0x80100000, &[0x3c010040, // lui   $1, 0x40
+                                 0x40816000, // mtc0  $1, $12
+                                 0x00000000, // nop
+                                 0x0000000c]); // syscall
 
-    /// Return the cacheline's first valid word
-    fn valid_index(&self) -> u32 {
-        // We store the valid bits in bits [4:2], this way we can just
-        // mask the PC value in `set_tag_valid` without having to
-        // shuffle the bits around
-        (self.tag_valid >> 2) & 0x7
+    for _ in 0..4 {
+        m.step();
     }
 
-    /// Set the cacheline's tag and valid bits. `pc` is the first
-    /// valid PC in the cacheline.
-    fn set_tag_valid(&mut self, pc: u32) {
-        self.tag_valid =  pc & 0x7ffff00c;
-    }
+    // BEV is set, the handler is in the BIOS
+    assert_eq!(m.cpu.pc(), 0xbfc00180);
+}
 
-    /// Invalidate the entire cacheline by pushing the index out of
-    /// range. Doesn't change the tag or contents of the line.
-    fn invalidate(&mut self) {
-        // Setting bit 4 means that the value returned by valid_index
-        // will be in the range [4, 7] which is outside the valid
-        // cacheline index range [0, 3].
-        self.tag_valid |= 0x10;
-    }
+#[test]
+fn cop0_breakpoints() {
+    use self::test_machine::TestMachine;
+
+    let code = [0x24020001, // addiu $2, $0, 1
+                0x8c230000, // lw    $3, 0($1)
+                0x24040001, // addiu $4, $0, 1
+                0x0bab6fb8, // j     0xeadbee0
+                0x00000000];
+    // Debug exception vector
+    let handler = [0x0bab6fb8, // j     0xeadbee0
+                   0x00000000];
+
+    // Data read breakpoint
+    let mut m = TestMachine::new()
+        .with_reg(1, 0x80110000)
+        .with_blob(0x80100000, &code)
+        .with_blob(0x80000040, &handler);
+
+    m.cpu.interconnect_mut().ram_mut().store::<Word>(0x110000, 0x1234);
+
+    m.cpu.cop0.set_bda(0x80110000);
+    m.cpu.cop0.set_bdam(0xffffffff);
+    m.cpu.cop0.set_dcic(0xc6800000);
+
+    m.run_until(0xeadbee0);
+
+    // EPC points at the faulting load which didn't complete
+    assert_eq!(m.cpu.cop0.epc(), 0x80100004);
+    m.assert_regs(&[(2, 1), (3, 0), (4, 0)]);
+    assert_eq!((m.cpu.cause(InterruptState::new()) >> 2) & 0x1f,
+               Exception::Break as u32);
+    // "Any break", "data break" and "data read" status bits
+    assert_eq!(m.cpu.cop0.dcic() & 0x3f, 0b001101);
+
+    // Execution breakpoint
+    let mut m = TestMachine::new()
+        .with_blob(0x80100000, &code)
+        .with_blob(0x80000040, &handler);
+
+    m.cpu.cop0.set_bpc(0x80100008);
+    m.cpu.cop0.set_bpcm(0xffffffff);
+    m.cpu.cop0.set_dcic(0xc1800000);
+
+    m.run_until(0xeadbee0);
+
+    assert_eq!(m.cpu.cop0.epc(), 0x80100008);
+    m.assert_regs(&[(2, 1), (4, 0)]);
+    assert_eq!(m.cpu.cop0.dcic() & 0x3f, 0b000011);
+
+    // Bits [22:14] and [11:6] are hardwired to 0
+    m.cpu.cop0.set_dcic(0xffffffff);
+    assert_eq!(m.cpu.cop0.dcic(), 0xff80303f);
+}
 
-    fn instruction(&self, index: u32) -> Instruction {
-        self.line[index as usize]
-    }
+#[test]
+fn mult_div_stall() {
+    use std::cmp;
+    use self::test_machine::TestMachine;
+
+    let mut m = TestMachine::new()
+        .with_reg(1, 0x7fffffff)
+        .with_reg(2, 3)
+        .with_reg(6, 0x10)
+        .with_blob(0x80100000, &[0x00000000, // nop
+                                 0x00220018, // mult  $1, $2
+                                 0x00001812, // mflo  $3
+                                 0x00c20018, // mult  $6, $2
+                                 0x00003812, // mflo  $7
+                                 0x0022001a, // div   $1, $2
+                                 0x00002010, // mfhi  $4
+                                 0x00220019, // multu $1, $2
+                                 0x00002812]); // mflo $5
+
+    fn step(m: &mut TestMachine) -> Cycles {
+        let start = m.shared.tk().now();
+
+        m.step();
+
+        m.shared.tk().now() - start
+    }
+
+    // Duration of an instruction without any stall
+    let nop = step(&mut m);
+
+    // MFLO and MFHI wait for the result
+    step(&mut m);
+    assert_eq!(step(&mut m), cmp::max(nop, MULT_SLOW_CYCLES));
+    step(&mut m);
+    assert_eq!(step(&mut m), cmp::max(nop, MULT_FAST_CYCLES));
+    step(&mut m);
+    assert_eq!(step(&mut m), cmp::max(nop, DIV_CYCLES));
+
+    // No stall once the operation is over
+    step(&mut m);
+    m.shared.tk().tick(DIV_CYCLES);
+    assert_eq!(step(&mut m), nop);
+
+    m.assert_regs(&[(3, 0x7ffffffd),
+                    (4, 1),
+                    (5, 0x7ffffffd),
+                    (7, 0x30)]);
+}
 
-    fn set_instruction(&mut self, index: u32, instruction: Instruction) {
-        self.line[index as usize] = instruction;
-    }
+#[test]
+fn bus_error_aborts_load() {
+    use memory::UnhandledAccessPolicy;
+    use self::test_machine::TestMachine;
+
+    let mut m = TestMachine::new()
+        .with_reg(1, 0xbfa00000)
+        .with_reg(3, 0x55)
+        .with_blob(0x80100000, &[0x8c230000, // lw    $3, 0($1)
+                                 0x00000000]) // nop
+        .with_blob(0x80000080, &[0x0bab6fb8, // j     0xeadbee0
+                                 0x00000000]);
+
+    m.cpu.interconnect_mut()
+        .set_unhandled_access_policy(UnhandledAccessPolicy::BusError);
+
+    m.run_until(0xeadbee0);
+
+    // The target register isn't modified
+    m.assert_reg(3, 0x55);
+    assert_eq!(m.cpu.cop0.epc(), 0x80100000);
+    assert_eq!((m.cpu.cause(InterruptState::new()) >> 2) & 0x1f,
+               Exception::BusErrorData as u32);
 }
 
-impl Default for ICacheLine {
-    fn default() -> ICacheLine {
-        ICacheLine::new()
-    }
+#[test]
+fn software_interrupt() {
+    use self::test_machine::TestMachine;
+
+    let mut m = TestMachine::new()
+        // Software interrupt 0
+        .with_reg(1, 0x100)
+        // IEc + IM0
+        .with_reg(2, 0x101)
+        .with_blob(0x80100000, &[0x40816800, // mtc0  $1, $13
+                                 0x00000000, // nop
+                                 0x40826000, // mtc0  $2, $12
+                                 0x00000000, // nop
+                                 0x0bab6fb8, // j     0xeadbee0
+                                 0x00000000]);
+
+    m.run_until(0x80);
+
+    // The interrupt is taken as soon as SR unmasks it
+    let cause = m.cpu.cause(*m.shared.irq_state());
+
+    assert_eq!(cause & 0x7c, 0);
+    assert_eq!(cause & 0x300, 0x100);
+    assert_eq!(m.cpu.cop0.epc(), 0x8010000c);
 }
 
-/// Serializable container for the cachelines
-buffer!(struct ICacheLines([ICacheLine; 0x100]));
+#[test]
+fn user_mode_fetch_kernel_code() {
+    use self::test_machine::TestMachine;
+
+    let mut m = TestMachine::new()
+        .with_reg(2, 0x80100100)
+        .with_blob(0x00100000, &[0x34010002, // ori   $1, $0, 2
+                                 0x40816000, // mtc0  $1, $12
+                                 0x00000000, // nop
+                                 0x00400008, // jr    $2
+                                 0x00000000]) // nop
+        .with_blob(0x80000080, &[0x0bab6fb8, // j     0xeadbee0
+                                 0x00000000]);
+
+    // Fetching from KSEG0 in user mode raises an address error. The
+    // jump target isn't in a delay slot.
+    m.run_until(0xeadbee0);
+
+    let cause = m.cpu.cause(InterruptState::new());
+
+    assert_eq!(m.cpu.cop0.epc(), 0x80100100);
+    assert_eq!(m.cpu.cop0.bad_vaddr(), 0x80100100);
+    assert_eq!((cause >> 2) & 0x1f, Exception::LoadAddressError as u32);
+    assert_eq!(cause >> 31, 0);
+
+    // Same thing for a misaligned jump target in kernel mode
+    let mut m = TestMachine::new()
+        .with_reg(2, 0x80100102)
+        .with_blob(0x80100000, &[0x00400008, // jr    $2
+                                 0x00000000]) // nop
+        .with_blob(0x80000080, &[0x0bab6fb8, // j     0xeadbee0
+                                 0x00000000]);
+
+    m.run_until(0xeadbee0);
+
+    let cause = m.cpu.cause(InterruptState::new());
+
+    assert_eq!(m.cpu.cop0.epc(), 0x80100102);
+    assert_eq!(m.cpu.cop0.bad_vaddr(), 0x80100102);
+    assert_eq!((cause >> 2) & 0x1f, Exception::LoadAddressError as u32);
+    assert_eq!(cause >> 31, 0);
+}
 
-/// Value of the "Processor ID" register (Cop0r15). This is the value
-/// returned by my SCPH-7502.
-pub const PROCESSOR_ID: u32 = 0x00000002;
+#[test]
+fn unaligned_store_to_io() {
+    use self::test_machine::TestMachine;
+
+    let mut m = TestMachine::new()
+        .with_reg(2, 0x1)
+        .with_reg(4, 0x1f801074)
+        .with_blob(0x80100000, &[0x3403000d, // ori   $3, $0, 0xd
+                                 0xac830000, // sw    $3, 0($4)
+                                 0xb8820001, // swr   $2, 1($4)
+                                 0x0bab6fb8, // j     0xeadbee0
+                                 0x00000000]);
+
+    m.run_until(0xeadbee0);
+
+    // The register isn't read back, the IRQ mask receives the shifted
+    // value without the previous low byte
+    assert_eq!(m.shared.irq_state().mask(), 0x100);
+
+    // The I/O registers can't be peeked at, plain memory can
+    assert_eq!(m.cpu.interconnect().peek::<Word>(0x1f801074), None);
+    assert_eq!(m.cpu.interconnect().peek::<Word>(0x80100000),
+               Some(0x3403000d));
+}
 
-/// PlayStation CPU clock in Hz
-pub const CPU_FREQ_HZ: u32 = 33_868_500;
+#[test]
+fn timing_state_round_trip() {
+    use self::test_machine::TestMachine;
+
+    let mut m = TestMachine::new();
+
+    m.cpu.set_pipeline_timing(true);
+    m.cpu.set_write_buffer(true);
+
+    m.cpu.timing.load(m.shared.tk(), 0x80000000);
+    m.cpu.timing.load_target(2);
+    m.cpu.write_buffer.store(m.shared.tk(), 0x80000000);
+
+    let mut state = SaveState::new();
+
+    m.cpu.save_state(&mut state).unwrap();
+
+    let mut m2 = TestMachine::new();
+
+    m2.cpu.set_pipeline_timing(true);
+    m2.cpu.set_write_buffer(true);
+    m2.cpu.load_state(&state).unwrap();
+
+    let now = m2.shared.tk().now();
+
+    assert_eq!(m2.cpu.write_buffer.pending(now), 1);
+
+    // addu $3, $2, $0 waits for the restored load
+    m2.cpu.timing.issue(m2.shared.tk(), 0x00401821);
+
+    assert_eq!(m2.shared.tk().now(), now + 3);
+}
+
+/// Copy `code` in RAM at `addr` and jump there
+#[cfg(test)]
+fn write_code(m: &mut test_machine::TestMachine, addr: u32, code: &[u32]) {
+    {
+        let ram = m.cpu.interconnect_mut().ram_mut();
+
+        for (i, &w) in code.iter().enumerate() {
+            ram.store::<Word>(addr + (i * 4) as u32, w);
+        }
+    }
+
+    m.cpu.set_pc(addr);
+}
@@ -0,0 +1,340 @@
+//! Instruction decoder.
+//!
+//! The interpreter, the disassembler and the pipeline timing model
+//! all need to know which instruction a word encodes. They used to
+//! each match on the opcode fields and disagreed on some of the
+//! corner cases (the weird BXX encodings, the COP0 opcodes...). Now
+//! the word is decoded once into an `Op` and the consumers only look
+//! at the operand fields. `Op` is a small `Copy` value so it can be
+//! cached alongside the instruction word.
+
+use super::Instruction;
+
+/// The operation encoded by an instruction word. The operands are
+/// not included, they're extracted from the word by the consumers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Reserved opcode, raises an exception
+    Illegal,
+    Sll,
+    Srl,
+    Sra,
+    Sllv,
+    Srlv,
+    Srav,
+    Jr,
+    Jalr,
+    Syscall,
+    Break,
+    Mfhi,
+    Mthi,
+    Mflo,
+    Mtlo,
+    Mult,
+    Multu,
+    Div,
+    Divu,
+    Add,
+    Addu,
+    Sub,
+    Subu,
+    And,
+    Or,
+    Xor,
+    Nor,
+    Slt,
+    Sltu,
+    Bltz,
+    Bgez,
+    Bltzal,
+    Bgezal,
+    J,
+    Jal,
+    Beq,
+    Bne,
+    Blez,
+    Bgtz,
+    Addi,
+    Addiu,
+    Slti,
+    Sltiu,
+    Andi,
+    Ori,
+    Xori,
+    Lui,
+    Mfc0,
+    Mtc0,
+    Rfe,
+    /// COP0 opcode not implemented by the PlayStation (the virtual
+    /// memory instructions)
+    Cop0,
+    /// COP1 opcode, there's no COP1 on the PlayStation
+    Cop1,
+    Mfc2,
+    Cfc2,
+    Mtc2,
+    Ctc2,
+    /// GTE command
+    Gte,
+    /// Invalid COP2 register transfer
+    Cop2,
+    /// COP3 opcode, there's no COP3 on the PlayStation
+    Cop3,
+    Lb,
+    Lh,
+    Lwl,
+    Lw,
+    Lbu,
+    Lhu,
+    Lwr,
+    Sb,
+    Sh,
+    Swl,
+    Sw,
+    Swr,
+    Lwc0,
+    Lwc1,
+    Lwc2,
+    Lwc3,
+    Swc0,
+    Swc1,
+    Swc2,
+    Swc3,
+}
+
+impl Op {
+    /// Decode the instruction word `raw`
+    pub fn decode(raw: u32) -> Op {
+        let instruction = Instruction(raw);
+
+        match instruction.function() {
+            0b000000 => match instruction.subfunction() {
+                0b000000 => Op::Sll,
+                0b000010 => Op::Srl,
+                0b000011 => Op::Sra,
+                0b000100 => Op::Sllv,
+                0b000110 => Op::Srlv,
+                0b000111 => Op::Srav,
+                0b001000 => Op::Jr,
+                0b001001 => Op::Jalr,
+                0b001100 => Op::Syscall,
+                0b001101 => Op::Break,
+                0b010000 => Op::Mfhi,
+                0b010001 => Op::Mthi,
+                0b010010 => Op::Mflo,
+                0b010011 => Op::Mtlo,
+                0b011000 => Op::Mult,
+                0b011001 => Op::Multu,
+                0b011010 => Op::Div,
+                0b011011 => Op::Divu,
+                0b100000 => Op::Add,
+                0b100001 => Op::Addu,
+                0b100010 => Op::Sub,
+                0b100011 => Op::Subu,
+                0b100100 => Op::And,
+                0b100101 => Op::Or,
+                0b100110 => Op::Xor,
+                0b100111 => Op::Nor,
+                0b101010 => Op::Slt,
+                0b101011 => Op::Sltu,
+                _        => Op::Illegal,
+            },
+            0b000001 => {
+                let is_bgez = (raw >> 16) & 1 != 0;
+                // It's not enough to test for bit 20 to see if we're
+                // supposed to link, if any bit in the range [19:17] is
+                // set the link doesn't take place and RA is left
+                // untouched.
+                let is_link = (raw >> 17) & 0xf == 0x8;
+
+                match (is_bgez, is_link) {
+                    (false, false) => Op::Bltz,
+                    (true, false) => Op::Bgez,
+                    (false, true) => Op::Bltzal,
+                    (true, true) => Op::Bgezal,
+                }
+            }
+            0b000010 => Op::J,
+            0b000011 => Op::Jal,
+            0b000100 => Op::Beq,
+            0b000101 => Op::Bne,
+            0b000110 => Op::Blez,
+            0b000111 => Op::Bgtz,
+            0b001000 => Op::Addi,
+            0b001001 => Op::Addiu,
+            0b001010 => Op::Slti,
+            0b001011 => Op::Sltiu,
+            0b001100 => Op::Andi,
+            0b001101 => Op::Ori,
+            0b001110 => Op::Xori,
+            0b001111 => Op::Lui,
+            0b010000 => match instruction.cop_opcode() {
+                0b00000 => Op::Mfc0,
+                0b00100 => Op::Mtc0,
+                // There are other instructions with the same encoding
+                // but all are virtual memory related and the
+                // PlayStation doesn't implement them.
+                0b10000 if instruction.subfunction() == 0b010000 => Op::Rfe,
+                _       => Op::Cop0,
+            },
+            0b010001 => Op::Cop1,
+            0b010010 => match instruction.cop_opcode() {
+                0b00000 => Op::Mfc2,
+                0b00010 => Op::Cfc2,
+                0b00100 => Op::Mtc2,
+                0b00110 => Op::Ctc2,
+                n if n & 0x10 != 0 => Op::Gte,
+                _       => Op::Cop2,
+            },
+            0b010011 => Op::Cop3,
+            0b100000 => Op::Lb,
+            0b100001 => Op::Lh,
+            0b100010 => Op::Lwl,
+            0b100011 => Op::Lw,
+            0b100100 => Op::Lbu,
+            0b100101 => Op::Lhu,
+            0b100110 => Op::Lwr,
+            0b101000 => Op::Sb,
+            0b101001 => Op::Sh,
+            0b101010 => Op::Swl,
+            0b101011 => Op::Sw,
+            0b101110 => Op::Swr,
+            0b110000 => Op::Lwc0,
+            0b110001 => Op::Lwc1,
+            0b110010 => Op::Lwc2,
+            0b110011 => Op::Lwc3,
+            0b111000 => Op::Swc0,
+            0b111001 => Op::Swc1,
+            0b111010 => Op::Swc2,
+            0b111011 => Op::Swc3,
+            _        => Op::Illegal,
+        }
+    }
+
+    /// Lowercase mnemonic of the operation. All the GTE commands
+    /// share the "cop2" mnemonic.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Op::Illegal => "illegal",
+            Op::Sll => "sll",
+            Op::Srl => "srl",
+            Op::Sra => "sra",
+            Op::Sllv => "sllv",
+            Op::Srlv => "srlv",
+            Op::Srav => "srav",
+            Op::Jr => "jr",
+            Op::Jalr => "jalr",
+            Op::Syscall => "syscall",
+            Op::Break => "break",
+            Op::Mfhi => "mfhi",
+            Op::Mthi => "mthi",
+            Op::Mflo => "mflo",
+            Op::Mtlo => "mtlo",
+            Op::Mult => "mult",
+            Op::Multu => "multu",
+            Op::Div => "div",
+            Op::Divu => "divu",
+            Op::Add => "add",
+            Op::Addu => "addu",
+            Op::Sub => "sub",
+            Op::Subu => "subu",
+            Op::And => "and",
+            Op::Or => "or",
+            Op::Xor => "xor",
+            Op::Nor => "nor",
+            Op::Slt => "slt",
+            Op::Sltu => "sltu",
+            Op::Bltz => "bltz",
+            Op::Bgez => "bgez",
+            Op::Bltzal => "bltzal",
+            Op::Bgezal => "bgezal",
+            Op::J => "j",
+            Op::Jal => "jal",
+            Op::Beq => "beq",
+            Op::Bne => "bne",
+            Op::Blez => "blez",
+            Op::Bgtz => "bgtz",
+            Op::Addi => "addi",
+            Op::Addiu => "addiu",
+            Op::Slti => "slti",
+            Op::Sltiu => "sltiu",
+            Op::Andi => "andi",
+            Op::Ori => "ori",
+            Op::Xori => "xori",
+            Op::Lui => "lui",
+            Op::Mfc0 => "mfc0",
+            Op::Mtc0 => "mtc0",
+            Op::Rfe => "rfe",
+            Op::Cop0 => "cop0",
+            Op::Cop1 => "cop1",
+            Op::Mfc2 => "mfc2",
+            Op::Cfc2 => "cfc2",
+            Op::Mtc2 => "mtc2",
+            Op::Ctc2 => "ctc2",
+            Op::Gte | Op::Cop2 => "cop2",
+            Op::Cop3 => "cop3",
+            Op::Lb => "lb",
+            Op::Lh => "lh",
+            Op::Lwl => "lwl",
+            Op::Lw => "lw",
+            Op::Lbu => "lbu",
+            Op::Lhu => "lhu",
+            Op::Lwr => "lwr",
+            Op::Sb => "sb",
+            Op::Sh => "sh",
+            Op::Swl => "swl",
+            Op::Sw => "sw",
+            Op::Swr => "swr",
+            Op::Lwc0 => "lwc0",
+            Op::Lwc1 => "lwc1",
+            Op::Lwc2 => "lwc2",
+            Op::Lwc3 => "lwc3",
+            Op::Swc0 => "swc0",
+            Op::Swc1 => "swc1",
+            Op::Swc2 => "swc2",
+            Op::Swc3 => "swc3",
+        }
+    }
+
+    /// True for the conditional branches, their target is relative
+    /// to the delay slot
+    pub fn is_branch(self) -> bool {
+        match self {
+            Op::Bltz | Op::Bgez | Op::Bltzal | Op::Bgezal |
+            Op::Beq | Op::Bne | Op::Blez | Op::Bgtz => true,
+            _ => false,
+        }
+    }
+
+    /// True for the jumps and branches, which are followed by a delay
+    /// slot
+    pub fn has_delay_slot(self) -> bool {
+        match self {
+            Op::J | Op::Jal | Op::Jr | Op::Jalr => true,
+            op => op.is_branch(),
+        }
+    }
+}
+
+#[test]
+fn decoder() {
+    // addu $3, $2, $0
+    assert_eq!(Op::decode(0x00401821), Op::Addu);
+    // bltzal with a bit set in [19:17]: no link
+    assert_eq!(Op::decode(0x04120004), Op::Bltz);
+    assert_eq!(Op::decode(0x04100004), Op::Bltzal);
+    assert_eq!(Op::decode(0x04110004), Op::Bgezal);
+    // rfe and the unsupported TLB instructions
+    assert_eq!(Op::decode(0x42000010), Op::Rfe);
+    assert_eq!(Op::decode(0x42000002), Op::Cop0);
+    // rtpt
+    assert_eq!(Op::decode(0x4a280030), Op::Gte);
+    assert_eq!(Op::decode(0x48420000), Op::Cfc2);
+    assert_eq!(Op::decode(0xcba00000), Op::Lwc2);
+    assert_eq!(Op::decode(0xfc000000), Op::Illegal);
+    assert_eq!(Op::decode(0x00000001), Op::Illegal);
+
+    assert!(Op::Jr.has_delay_slot());
+    assert!(Op::Bgezal.is_branch());
+    assert!(!Op::Syscall.has_delay_slot());
+}
@@ -0,0 +1,205 @@
+//! Helpers to write CPU tests without the boilerplate of the
+//! generated tests in `tests.rs`:
+//!
+//! ```ignore
+//! let mut m = TestMachine::new()
+//!     .with_reg(1, 0x10)
+//!     .with_blob(0x80100000, &[0x24220001, // addiu $2, $1, 1
+//!                              0x0bab6fb8, // j 0xeadbee0
+//!                              0x00000000]);
+//!
+//! m.run_until(0xeadbee0);
+//! m.assert_reg(2, 0x11);
+//! ```
+//!
+//! Execution starts at the address of the first blob unless
+//! `with_pc` is used.
+
+use gpu::{Gpu, VideoClock};
+use gpu::renderer::{Renderer, PrimitiveAttributes, Vertex};
+use memory::{Interconnect, Addressable, Word};
+use shared::SharedState;
+use bios::Bios;
+
+use super::{Cpu, RegisterIndex};
+
+pub struct TestMachine {
+    pub cpu: Cpu,
+    pub shared: SharedState,
+    pub renderer: NullRenderer,
+    /// Set by the first call to `with_blob` or by `with_pc`
+    pc: Option<u32>,
+}
+
+impl TestMachine {
+    /// Build a machine with a dummy BIOS and all the general purpose
+    /// registers set to 0
+    pub fn new() -> TestMachine {
+        let gpu = Gpu::new(VideoClock::Ntsc);
+        let inter = Interconnect::new(Bios::dummy(), gpu, None);
+        let mut cpu = Cpu::new(inter);
+
+        for r in 1..32 {
+            cpu.set_reg(RegisterIndex(r), 0);
+        }
+
+        TestMachine {
+            cpu: cpu,
+            shared: SharedState::new(),
+            renderer: NullRenderer,
+            pc: None,
+        }
+    }
+
+    /// Copy `blob` in RAM at `addr`
+    pub fn with_blob(mut self, addr: u32, blob: &[u32]) -> TestMachine {
+        {
+            let ram = self.cpu.interconnect_mut().ram_mut();
+
+            for (i, &w) in blob.iter().enumerate() {
+                ram.store::<Word>(addr + (i * 4) as u32, w);
+            }
+        }
+
+        if self.pc.is_none() {
+            self.pc = Some(addr);
+        }
+
+        self
+    }
+
+    /// Set general purpose register `reg` to `val`
+    pub fn with_reg(mut self, reg: u32, val: u32) -> TestMachine {
+        self.cpu.set_reg(RegisterIndex(reg), val);
+
+        self
+    }
+
+    /// Start execution at `pc`
+    pub fn with_pc(mut self, pc: u32) -> TestMachine {
+        self.pc = Some(pc);
+
+        self
+    }
+
+    /// Execute a single instruction
+    pub fn step(&mut self) {
+        if let Some(pc) = self.pc.take() {
+            self.cpu.set_pc(pc);
+        }
+
+        self.cpu.run_next_instruction(&mut (),
+                                      &mut self.shared,
+                                      &mut self.renderer);
+    }
+
+    /// Run until the CPU reaches `pc`. The region bits are ignored
+    /// for the comparison. Panics if it takes more than `TIMEOUT`
+    /// instructions.
+    pub fn run_until(&mut self, pc: u32) {
+        for _ in 0..TIMEOUT {
+            if self.pc.is_none() && self.cpu.pc() & 0x0fffffff == pc {
+                return;
+            }
+
+            self.step();
+        }
+
+        panic!("Timeout while waiting for PC 0x{:08x}, PC is 0x{:08x}",
+               pc, self.cpu.pc());
+    }
+
+    pub fn reg(&self, reg: u32) -> u32 {
+        self.cpu.reg(RegisterIndex(reg))
+    }
+
+    /// Read from RAM at `addr`
+    pub fn read<T: Addressable>(&self, addr: u32) -> u32 {
+        self.cpu.interconnect().ram().load::<T>(addr)
+    }
+
+    pub fn assert_reg(&self, reg: u32, val: u32) {
+        let v = self.reg(reg);
+
+        if v != val {
+            panic!("Register ${} is 0x{:08x}, expected 0x{:08x}",
+                   reg, v, val);
+        }
+    }
+
+    /// Check several registers at once, `expected` contains `(reg,
+    /// val)` pairs
+    pub fn assert_regs(&self, expected: &[(u32, u32)]) {
+        for &(reg, val) in expected {
+            self.assert_reg(reg, val);
+        }
+    }
+}
+
+/// Renderer discarding everything
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn set_draw_offset(&mut self, _: i16, _: i16) {
+    }
+
+    fn set_draw_area(&mut self, _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn set_display_mode(&mut self,
+                        _: (u16, u16),
+                        _: (u16, u16),
+                        _: bool) {
+    }
+
+    fn push_line(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 2]) {
+    }
+
+    fn push_triangle(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 3]) {
+    }
+
+    fn push_quad(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 4]) {
+    }
+
+    fn fill_rect(&mut self,
+                 _: [u8; 3],
+                 _: (u16, u16),
+                 _: (u16, u16)) {
+    }
+
+    fn load_image(&mut self,
+                  _: (u16, u16),
+                  _: (u16, u16),
+                  _: &[u16]) {
+    }
+
+    fn copy_rect(&mut self,
+                 _: (u16, u16),
+                 _: (u16, u16),
+                 _: (u16, u16)) {
+    }
+
+    fn store_image(&mut self,
+                   _: (u16, u16),
+                   _: (u16, u16),
+                   _: &mut [u16]) {
+    }
+}
+
+/// Maximum number of instructions executed by `run_until`
+const TIMEOUT: usize = 1_000_000;
+
+#[test]
+fn builder() {
+    let mut m = TestMachine::new()
+        .with_reg(1, 0x10)
+        .with_blob(0x80100000, &[0x24220001, // addiu $2, $1, 1
+                                 0xac020010, // sw    $2, 0x10($0)
+                                 0x0bab6fb8, // j     0xeadbee0
+                                 0x00000000]);
+
+    m.run_until(0xeadbee0);
+
+    m.assert_regs(&[(1, 0x10), (2, 0x11)]);
+    assert_eq!(m.read::<Word>(0x10), 0x11);
+}
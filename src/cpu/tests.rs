@@ -48,6 +48,18 @@ impl Renderer for DummyRenderer {
                   _: (u16, u16),
                   _: &[u16]) {
     }
+
+    fn copy_rect(&mut self,
+                 _: (u16, u16),
+                 _: (u16, u16),
+                 _: (u16, u16)) {
+    }
+
+    fn store_image(&mut self,
+                   _: (u16, u16),
+                   _: (u16, u16),
+                   _: &mut [u16]) {
+    }
 }
 
 fn write_blob(cpu: &mut Cpu,
@@ -739,6 +751,208 @@ fn test_lh_and_lb_sign_extension() {
     assert!(cpu.regs[4] == 0x80);
 }
 
+#[test]
+fn test_load_cancelled_by_mfc0() {
+    let bios = Bios::dummy();
+    let gpu = Gpu::new(VideoClock::Ntsc);
+    let inter = Interconnect::new(bios, gpu, None);
+    let mut cpu = Cpu::new(inter);
+    let mut shared = SharedState::new();
+    let mut renderer = DummyRenderer;
+
+    for r in 0..31 {
+        cpu.set_reg(RegisterIndex(r), 0);
+    }
+
+    cpu.set_reg(RegisterIndex(1), 0x1234);
+    cpu.set_reg(RegisterIndex(2), 0x80110000);
+    write::<memory::Word>(&mut cpu, 0x80110000, 0xdeadbeef);
+
+    // lw    $1, 0($2)
+    // mfc0  $1, $12
+    // addu  $3, $1, $zero
+    // addu  $4, $1, $zero
+    // j     0x0eadbee0
+    // nop
+    write_blob(&mut cpu, 0x80100000,
+               &[0x8c410000,
+                 0x40016000,
+                 0x00201821,
+                 0x00202021,
+                 0x0bab6fb8,
+                 0x00000000]);
+
+    cpu.set_pc(0x80100000);
+
+    let mut timeout = true;
+    for _ in 0..TIMEOUT {
+        if (cpu.pc & 0x0fffffff) == 0xeadbee0 {
+            timeout = false;
+            break;
+        }
+        cpu.run_next_instruction(&mut (), &mut shared, &mut renderer);
+    }
+    assert!(timeout == false);
+
+    // The LW is cancelled by the MFC0 targeting the same register
+    assert!(cpu.regs[3] == 0x1234);
+    assert!(cpu.regs[4] == cpu.sr());
+    assert!(cpu.regs[1] == cpu.sr());
+}
+
+#[test]
+fn test_load_delay_across_gte_command() {
+    let bios = Bios::dummy();
+    let gpu = Gpu::new(VideoClock::Ntsc);
+    let inter = Interconnect::new(bios, gpu, None);
+    let mut cpu = Cpu::new(inter);
+    let mut shared = SharedState::new();
+    let mut renderer = DummyRenderer;
+
+    for r in 0..31 {
+        cpu.set_reg(RegisterIndex(r), 0);
+    }
+
+    cpu.set_reg(RegisterIndex(2), 0x80110000);
+    write::<memory::Word>(&mut cpu, 0x80110000, 0xdeadbeef);
+
+    // lw    $1, 0($2)
+    // nclip
+    // addu  $3, $1, $zero
+    // j     0x0eadbee0
+    // nop
+    write_blob(&mut cpu, 0x80100000,
+               &[0x8c410000,
+                 0x4b400006,
+                 0x00201821,
+                 0x0bab6fb8,
+                 0x00000000]);
+
+    cpu.set_pc(0x80100000);
+
+    let mut timeout = true;
+    for _ in 0..TIMEOUT {
+        if (cpu.pc & 0x0fffffff) == 0xeadbee0 {
+            timeout = false;
+            break;
+        }
+        cpu.run_next_instruction(&mut (), &mut shared, &mut renderer);
+    }
+    assert!(timeout == false);
+
+    assert!(cpu.regs[3] == 0xdeadbeef);
+}
+
+#[test]
+fn test_lwc2_and_swc2() {
+    let bios = Bios::dummy();
+    let gpu = Gpu::new(VideoClock::Ntsc);
+    let inter = Interconnect::new(bios, gpu, None);
+    let mut cpu = Cpu::new(inter);
+    let mut shared = SharedState::new();
+    let mut renderer = DummyRenderer;
+
+    for r in 0..31 {
+        cpu.set_reg(RegisterIndex(r), 0);
+    }
+
+    cpu.set_reg(RegisterIndex(1), 0x80110000);
+    write::<memory::Word>(&mut cpu, 0x110000, 0x12345678);
+
+    // lwc2  $0, 0($1)
+    // swc2  $0, 4($1)
+    // swc2  $0, 6($1)
+    // j     0x0eadbee0
+    // nop
+    write_blob(&mut cpu, 0x80100000,
+               &[0xc8200000,
+                 0xe8200004,
+                 0xe8200006,
+                 0x0bab6fb8,
+                 0x00000000]);
+
+    cpu.set_pc(0x80100000);
+
+    let mut timeout = true;
+    for _ in 0..TIMEOUT {
+        if cpu.pc == 0x80000080 {
+            timeout = false;
+            break;
+        }
+        cpu.run_next_instruction(&mut (), &mut shared, &mut renderer);
+    }
+    assert!(timeout == false);
+
+    // No load delay for the GTE registers
+    assert!(read::<memory::Word>(&mut cpu, 0x110004) == 0x12345678);
+
+    // The unaligned SWC2 raises a store address error
+    let cause = cpu.cause(*shared.irq_state());
+
+    assert!(cause & 0x7c == 5 << 2);
+    assert!(cpu.cop0.epc() == 0x80100008);
+    assert!(cpu.cop0.bad_vaddr() == 0x80110006);
+}
+
+#[test]
+fn test_swl_and_swr_cache_isolated() {
+    let bios = Bios::dummy();
+    let gpu = Gpu::new(VideoClock::Ntsc);
+    let inter = Interconnect::new(bios, gpu, None);
+    let mut cpu = Cpu::new(inter);
+    let mut shared = SharedState::new();
+    let mut renderer = DummyRenderer;
+
+    for r in 0..31 {
+        cpu.set_reg(RegisterIndex(r), 0);
+    }
+
+    // Isolate the cache
+    cpu.set_reg(RegisterIndex(1), 0x10000);
+    cpu.set_reg(RegisterIndex(2), 0x80110100);
+    write::<memory::Word>(&mut cpu, 0x110100, 0xdeadbeef);
+
+    // Enable the instruction cache
+    cpu.interconnect_mut().store::<memory::Word>(&mut shared,
+                                                 &mut renderer,
+                                                 0xfffe0130,
+                                                 0x804);
+
+    // mtc0  $1, $12
+    // nop
+    // swl   $0, 1($2)
+    // swr   $0, 2($2)
+    // mtc0  $0, $12
+    // nop
+    // j     0x0eadbee0
+    // nop
+    write_blob(&mut cpu, 0x80100000,
+               &[0x40816000,
+                 0x00000000,
+                 0xa8400001,
+                 0xb8400002,
+                 0x40806000,
+                 0x00000000,
+                 0x0bab6fb8,
+                 0x00000000]);
+
+    cpu.set_pc(0x80100000);
+
+    let mut timeout = true;
+    for _ in 0..TIMEOUT {
+        if (cpu.pc & 0x0fffffff) == 0xeadbee0 {
+            timeout = false;
+            break;
+        }
+        cpu.run_next_instruction(&mut (), &mut shared, &mut renderer);
+    }
+    assert!(timeout == false);
+
+    // The stores only invalidated the cache, the memory contents
+    // weren't merged in
+    assert!(read::<memory::Word>(&mut cpu, 0x110100) == 0xdeadbeef);
+}
+
 /// Number of CPU cycles after which we consider the test to be a
 /// failure
 const TIMEOUT: usize = 1_000_000;
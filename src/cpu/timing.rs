@@ -0,0 +1,183 @@
+//! Optional pipeline timing model.
+//!
+//! By default every instruction takes one cycle plus the cost of the
+//! instruction fetch and a flat memory access cost. That's fast and
+//! good enough for most games but it doesn't reproduce the race
+//! conditions some games rely on. This model refines it with:
+//!
+//! * Load-use stalls: the value of a memory load reaches the
+//!   register after the memory latency, an instruction using it
+//!   before that stalls the pipeline.
+//! * A small penalty for taken branches.
+//!
+//! The write buffer is modeled separately in `write_buffer`. The
+//! latencies are approximations. The model is a bit slower so it's
+//! disabled by default.
+
+use memory::map;
+use timekeeper::{TimeKeeper, Cycles};
+
+use super::{Instruction, RegisterIndex};
+use super::op::Op;
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct PipelineTiming {
+    enabled: bool,
+    /// Index of the register targeted by the last memory load
+    load_reg: u32,
+    /// Date at which the value of `load_reg` becomes available
+    load_ready: Cycles,
+    /// Date at which the value of the load issued by the current
+    /// instruction becomes available, until we know its target
+    /// register
+    pending_load: Option<Cycles>,
+    /// Set by taken branches, the penalty is applied to the next
+    /// instruction
+    branch_taken: bool,
+}
+
+impl PipelineTiming {
+    pub fn new() -> PipelineTiming {
+        PipelineTiming {
+            enabled: false,
+            load_reg: 0,
+            load_ready: 0,
+            pending_load: None,
+            branch_taken: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        *self = PipelineTiming::new();
+
+        self.enabled = enabled;
+    }
+
+    /// Replace the state with `saved`, loaded from a savestate. The
+    /// current enable setting is kept.
+    pub fn restore(&mut self, saved: PipelineTiming) {
+        let enabled = self.enabled;
+
+        *self = saved;
+        self.enabled = enabled;
+    }
+
+    /// Called before `instruction` executes, stalls if it needs a
+    /// value which isn't available yet
+    pub fn issue(&mut self, tk: &mut TimeKeeper, instruction: u32) {
+        self.pending_load = None;
+
+        if self.branch_taken {
+            self.branch_taken = false;
+            tk.cpu_tick(BRANCH_TAKEN_CYCLES);
+        }
+
+        let reg = RegisterIndex(self.load_reg);
+
+        if reg.0 != 0 && reads_register(Instruction(instruction), reg) {
+            let now = tk.now();
+
+            if now < self.load_ready {
+                tk.tick(self.load_ready - now);
+            }
+
+            self.load_reg = 0;
+        }
+    }
+
+    /// Called when the CPU reads from `addr`
+    pub fn load(&mut self, tk: &mut TimeKeeper, addr: u32) {
+        let abs_addr = map::PhysAddr::from_cpu(addr);
+
+        self.pending_load = Some(tk.now() + load_latency(abs_addr));
+    }
+
+    /// Called when the load issued by the current instruction targets
+    /// register `reg`
+    pub fn load_target(&mut self, reg: u32) {
+        if let Some(ready) = self.pending_load.take() {
+            self.load_reg = reg;
+            self.load_ready = ready;
+        }
+    }
+
+    pub fn branch_taken(&mut self) {
+        self.branch_taken = true;
+    }
+}
+
+/// Return true if `instruction` reads general purpose register `reg`
+fn reads_register(instruction: Instruction, reg: RegisterIndex) -> bool {
+    let s = instruction.s() == reg;
+    let t = instruction.t() == reg;
+
+    match instruction.op() {
+        Op::Sll | Op::Srl | Op::Sra => t,
+        Op::Sllv | Op::Srlv | Op::Srav |
+        Op::Mult | Op::Multu | Op::Div | Op::Divu |
+        Op::Add | Op::Addu | Op::Sub | Op::Subu |
+        Op::And | Op::Or | Op::Xor | Op::Nor |
+        Op::Slt | Op::Sltu |
+        Op::Beq | Op::Bne => s || t,
+        Op::Jr | Op::Jalr | Op::Mthi | Op::Mtlo |
+        Op::Bltz | Op::Bgez | Op::Bltzal | Op::Bgezal |
+        Op::Blez | Op::Bgtz |
+        Op::Addi | Op::Addiu | Op::Slti | Op::Sltiu |
+        Op::Andi | Op::Ori | Op::Xori => s,
+        Op::Mtc0 | Op::Mtc2 | Op::Ctc2 => t,
+        // LWL and LWR merge the loaded value with the target register
+        Op::Lwl | Op::Lwr => s || t,
+        Op::Lb | Op::Lh | Op::Lw | Op::Lbu | Op::Lhu => s,
+        Op::Sb | Op::Sh | Op::Swl | Op::Sw | Op::Swr => s || t,
+        // The coprocessor loads and stores only use `s` for the
+        // address, the data goes straight to the coprocessor
+        Op::Lwc0 | Op::Lwc1 | Op::Lwc2 | Op::Lwc3 |
+        Op::Swc0 | Op::Swc1 | Op::Swc2 | Op::Swc3 => s,
+        _ => false,
+    }
+}
+
+/// Cycles between a load and the availability of its value
+fn load_latency(abs_addr: map::PhysAddr) -> Cycles {
+    if map::SCRATCH_PAD.contains(abs_addr).is_some() {
+        0
+    } else if map::RAM.contains(abs_addr).is_some() {
+        3
+    } else if map::BIOS.contains(abs_addr).is_some() {
+        // 8bit bus
+        20
+    } else {
+        2
+    }
+}
+
+/// Penalty for taken branches
+const BRANCH_TAKEN_CYCLES: Cycles = 1;
+
+#[test]
+fn load_use_stall() {
+    let mut tk = TimeKeeper::new();
+    let mut timing = PipelineTiming::new();
+
+    timing.set_enabled(true);
+
+    // ScratchPad loads are available immediately
+    timing.load(&mut tk, 0x1f800000);
+    timing.load_target(2);
+
+    // addu $3, $2, $0
+    timing.issue(&mut tk, 0x00401821);
+
+    assert_eq!(tk.now(), 0);
+
+    timing.load(&mut tk, 0x80000000);
+    timing.load_target(2);
+
+    timing.issue(&mut tk, 0x00401821);
+
+    assert_eq!(tk.now(), 3);
+}
@@ -0,0 +1,205 @@
+//! Optional CPU write buffer model.
+//!
+//! The R3000A doesn't wait for stores to complete: they're queued in
+//! a 4 entry write buffer and committed to the bus in the background
+//! while the CPU keeps running. The CPU only stalls when:
+//!
+//! * A store is issued while the buffer is full, it waits for the
+//!   oldest entry to be committed.
+//! * A load targets a word which is still sitting in the buffer, it
+//!   waits for that store (and the ones queued before it) to be
+//!   committed so that it reads the new value.
+//! * A load targets a device register, the buffer is drained
+//!   completely so that the device sees the accesses in program
+//!   order.
+//! * Any other load outside of the ScratchPad has to wait for the
+//!   store currently using the bus.
+//!
+//! The stores themselves still take effect immediately, only their
+//! cost is modeled. The ScratchPad is accessed directly and never goes
+//! through the buffer. The latencies are approximations.
+
+use std::cmp;
+
+use memory::map;
+use timekeeper::{TimeKeeper, Cycles};
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct WriteBuffer {
+    enabled: bool,
+    /// Word address and commit date of the stores sitting in the
+    /// buffer, oldest first
+    entries: Vec<(u32, Cycles)>,
+}
+
+impl WriteBuffer {
+    pub fn new() -> WriteBuffer {
+        WriteBuffer {
+            enabled: false,
+            entries: Vec::with_capacity(WRITE_BUFFER_DEPTH),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enable or disable the model, the buffer is emptied
+    pub fn set_enabled(&mut self, enabled: bool) {
+        *self = WriteBuffer::new();
+
+        self.enabled = enabled;
+    }
+
+    /// Replace the state with `saved`, loaded from a savestate. The
+    /// current enable setting is kept.
+    pub fn restore(&mut self, saved: WriteBuffer) {
+        let enabled = self.enabled;
+
+        *self = saved;
+        self.enabled = enabled;
+    }
+
+    /// Return the number of stores waiting to be committed at `now`
+    pub fn pending(&mut self, now: Cycles) -> usize {
+        self.retire(now);
+
+        self.entries.len()
+    }
+
+    /// Called when the CPU writes to `addr`
+    pub fn store(&mut self, tk: &mut TimeKeeper, addr: u32) {
+        let abs_addr = map::PhysAddr::from_cpu(addr);
+
+        if map::SCRATCH_PAD.contains(abs_addr).is_some() {
+            return;
+        }
+
+        self.retire(tk.now());
+
+        if self.entries.len() == WRITE_BUFFER_DEPTH {
+            // Buffer full, wait for the oldest entry
+            let (_, oldest) = self.entries.remove(0);
+
+            wait_until(tk, oldest);
+        }
+
+        let start =
+            match self.entries.last() {
+                Some(&(_, last)) => last,
+                None => tk.now(),
+            };
+
+        let word = abs_addr.get() & !3;
+
+        self.entries.push((word, start + store_latency(abs_addr)));
+    }
+
+    /// Called when the CPU reads from `addr`, stalls until the load
+    /// can be issued on the bus
+    pub fn load(&mut self, tk: &mut TimeKeeper, addr: u32) {
+        let abs_addr = map::PhysAddr::from_cpu(addr);
+
+        if map::SCRATCH_PAD.contains(abs_addr).is_some() {
+            return;
+        }
+
+        self.retire(tk.now());
+
+        let is_memory = map::RAM.contains(abs_addr).is_some() ||
+                        map::BIOS.contains(abs_addr).is_some();
+
+        let word = abs_addr.get() & !3;
+
+        // Number of entries which have to be committed before the
+        // load can proceed
+        let flush =
+            if !is_memory {
+                self.entries.len()
+            } else {
+                match self.entries.iter().rposition(|e| e.0 == word) {
+                    Some(pos) => pos + 1,
+                    // Wait for the store in progress, if any
+                    None => cmp::min(self.entries.len(), 1),
+                }
+            };
+
+        if flush > 0 {
+            let (_, date) = self.entries[flush - 1];
+
+            wait_until(tk, date);
+
+            self.entries.drain(..flush);
+        }
+    }
+
+    /// Remove the stores committed by `now`
+    fn retire(&mut self, now: Cycles) {
+        self.entries.retain(|&(_, date)| date > now);
+    }
+}
+
+/// Stall the CPU until `date`
+fn wait_until(tk: &mut TimeKeeper, date: Cycles) {
+    let now = tk.now();
+
+    if now < date {
+        tk.tick(date - now);
+    }
+}
+
+/// Cycles taken to commit a store
+fn store_latency(abs_addr: map::PhysAddr) -> Cycles {
+    if map::RAM.contains(abs_addr).is_some() {
+        2
+    } else {
+        4
+    }
+}
+
+/// Number of stores the write buffer can hold
+const WRITE_BUFFER_DEPTH: usize = 4;
+
+#[test]
+fn write_buffer() {
+    let mut tk = TimeKeeper::new();
+    let mut buffer = WriteBuffer::new();
+
+    buffer.set_enabled(true);
+
+    for i in 0..WRITE_BUFFER_DEPTH as u32 {
+        buffer.store(&mut tk, 0x80000000 + i * 4);
+    }
+
+    // Not full yet, no stall
+    assert_eq!(tk.now(), 0);
+
+    buffer.store(&mut tk, 0x80000010);
+
+    assert_eq!(tk.now(), 2);
+    assert_eq!(buffer.pending(tk.now()), 4);
+
+    // ScratchPad loads don't wait for the buffer
+    buffer.load(&mut tk, 0x1f800000);
+
+    assert_eq!(tk.now(), 2);
+
+    // Unrelated RAM load, waits for the store in progress
+    buffer.load(&mut tk, 0x80001000);
+
+    assert_eq!(tk.now(), 4);
+    assert_eq!(buffer.pending(tk.now()), 3);
+
+    // Reading back a buffered word waits for it to be committed
+    buffer.load(&mut tk, 0x8000000e);
+
+    assert_eq!(tk.now(), 8);
+    assert_eq!(buffer.pending(tk.now()), 1);
+
+    // Device loads drain the buffer
+    buffer.store(&mut tk, 0x1f801070);
+    buffer.load(&mut tk, 0x1f801074);
+
+    assert_eq!(tk.now(), 14);
+    assert_eq!(buffer.pending(tk.now()), 0);
+}
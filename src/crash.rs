@@ -0,0 +1,207 @@
+//! Automatic savestates and crash reports.
+//!
+//! `AutoSave` periodically saves the machine state to a set of
+//! rotating slot files so that the player can go back a few minutes
+//! when something goes wrong. When a crash dump directory is
+//! configured with `Machine::set_crash_dump_dir` and the emulator
+//! panics (including on unhandled memory accesses with the default
+//! `UnhandledAccessPolicy::Panic`), the last state of the machine
+//! and the addresses of the last instructions executed are written to
+//! disk so that they can be attached to a bug report.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+use savestate::{self, SaveState};
+
+/// Number of instructions kept in the `PcRing` by default
+pub const DEFAULT_PC_RING_SIZE: usize = 256;
+
+/// Ring buffer containing the addresses of the last instructions
+/// executed by the CPU
+pub struct PcRing {
+    entries: Vec<u32>,
+    /// Index of the next entry to be written
+    pos: usize,
+    /// Total number of instructions recorded
+    count: u64,
+}
+
+impl PcRing {
+    pub fn new(capacity: usize) -> PcRing {
+        PcRing {
+            entries: vec![0; capacity],
+            pos: 0,
+            count: 0,
+        }
+    }
+
+    /// Change the number of entries kept, 0 disables the recording.
+    /// The current entries are lost.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        *self = PcRing::new(capacity);
+    }
+
+    pub fn push(&mut self, pc: u32) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        self.entries[self.pos] = pc;
+        self.pos = (self.pos + 1) % self.entries.len();
+        self.count += 1;
+    }
+
+    /// Return the recorded addresses along with their instruction
+    /// number, oldest first
+    pub fn entries(&self) -> Vec<(u64, u32)> {
+        let len = self.entries.len();
+        let stored = if self.count < len as u64 {
+            self.count as usize
+        } else {
+            len
+        };
+
+        let first = self.count - stored as u64;
+
+        (0..stored).map(|i| {
+            let index = (self.pos + len - stored + i) % len;
+
+            (first + i as u64, self.entries[index])
+        }).collect()
+    }
+
+    /// Write the entries, one per line
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for (n, pc) in self.entries() {
+            try!(writeln!(w, "{} 0x{:08x}", n, pc));
+        }
+
+        Ok(())
+    }
+}
+
+impl Encodable for PcRing {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Debugging aid, not part of the console state
+        s.emit_nil()
+    }
+}
+
+impl Decodable for PcRing {
+    fn decode<D: Decoder>(d: &mut D) -> Result<PcRing, D::Error> {
+        try!(d.read_nil());
+
+        Ok(PcRing::new(DEFAULT_PC_RING_SIZE))
+    }
+}
+
+/// Periodic savestates in rotating slots
+pub struct AutoSave {
+    /// Directory containing the slot files
+    dir: PathBuf,
+    /// Number of frames between two saves
+    interval: u32,
+    /// Number of slots
+    slots: u32,
+    /// Slot used by the next save
+    next_slot: u32,
+    /// Frames elapsed since the last save
+    frames: u32,
+}
+
+impl AutoSave {
+    /// Save every `interval` frames in `slots` files in `dir`, the
+    /// oldest slot is overwritten when they're all used
+    pub fn new<P: AsRef<Path>>(dir: P, interval: u32, slots: u32) -> AutoSave {
+        assert!(interval > 0 && slots > 0);
+
+        AutoSave {
+            dir: dir.as_ref().to_path_buf(),
+            interval: interval,
+            slots: slots,
+            next_slot: 0,
+            frames: 0,
+        }
+    }
+
+    /// Path of the file used for `slot`
+    pub fn slot_path(&self, slot: u32) -> PathBuf {
+        self.dir.join(format!("auto-{}.state", slot))
+    }
+
+    /// Called at the end of each frame, returns true when a new state
+    /// should be saved
+    pub fn frame_complete(&mut self) -> bool {
+        self.frames += 1;
+
+        if self.frames >= self.interval {
+            self.frames = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Write `state` in the next slot and return its path
+    pub fn save(&mut self,
+                state: &SaveState) -> Result<PathBuf, savestate::Error> {
+        let path = self.slot_path(self.next_slot);
+
+        let mut f = try!(File::create(&path));
+
+        try!(state.write(&mut f));
+
+        self.next_slot = (self.next_slot + 1) % self.slots;
+
+        Ok(path)
+    }
+}
+
+/// Write a crash report in `dir`: `crash.txt` containing `message`
+/// and the last instructions executed, and `crash.state` containing
+/// `state` if it could be captured. Returns the path of the report.
+pub fn write_crash_dump(dir: &Path,
+                        message: &str,
+                        state: Option<&SaveState>,
+                        pc_ring: &PcRing) -> Result<PathBuf,
+                                                    savestate::Error> {
+    if let Some(state) = state {
+        let mut f = try!(File::create(dir.join("crash.state")));
+
+        try!(state.write(&mut f));
+    }
+
+    let path = dir.join("crash.txt");
+
+    let mut f = try!(File::create(&path));
+
+    try!(writeln!(f, "# {}", message));
+    try!(writeln!(f, "# Last instructions executed:"));
+    try!(pc_ring.write(&mut f));
+
+    Ok(path)
+}
+
+#[test]
+fn pc_ring() {
+    let mut ring = PcRing::new(3);
+
+    ring.push(0x10);
+    ring.push(0x14);
+
+    assert_eq!(ring.entries(), vec![(0, 0x10), (1, 0x14)]);
+
+    ring.push(0x18);
+    ring.push(0x1c);
+
+    assert_eq!(ring.entries(), vec![(1, 0x14), (2, 0x18), (3, 0x1c)]);
+
+    ring.set_capacity(0);
+    ring.push(0x20);
+
+    assert!(ring.entries().is_empty());
+}
@@ -6,6 +6,7 @@
 
 use memory::Addressable;
 use shared::SharedState;
+use logging;
 
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct DebugUart {
@@ -75,7 +76,8 @@ impl DebugUart {
             }
             // Boot status register, is incremented by the BIOS during
             // bootup
-            0x41 => debug!("BIOS boot status: {}", val),
+            0x41 => debug!(target: logging::DEBUG_UART,
+                           "BIOS boot status: {}", val),
             _ => panic!("Unhandled debug UART store: {:x} {:02x}",
                         offset, val),
         }
@@ -92,7 +94,8 @@ impl DebugUart {
                     _ => unreachable!(),
                 };
 
-            debug!("Debug UART {}: {}", uart, buffer);
+            debug!(target: logging::DEBUG_UART,
+                   "Debug UART {}: {}", uart, buffer);
             buffer.clear();
         } else {
             buffer.push(c);
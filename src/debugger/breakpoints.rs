@@ -0,0 +1,427 @@
+//! Code breakpoint storage for debugger implementations.
+//!
+//! Breakpoints can be conditional (`$v0 == 0 && word[$a0] > 4`, see
+//! the `expression` module for the syntax), temporary (removed the
+//! first time they trigger), can ignore their first hits and can be
+//! disabled without being removed. A `Debugger` implementation is
+//! expected to call `Breakpoints::check` from its `pc_change` method.
+
+use std::collections::HashMap;
+
+use cpu::Cpu;
+
+use super::symbols::{parse_hex, SymbolTable};
+use super::expression::{Expression, BinaryOp};
+
+pub struct Breakpoint {
+    addr: u32,
+    enabled: bool,
+    /// Temporary breakpoints are removed once they trigger
+    temporary: bool,
+    /// Optional condition, the breakpoint only triggers when it
+    /// evaluates to a non-zero value
+    condition: Option<Expression>,
+    /// Number of times the breakpoint was reached with its condition
+    /// true
+    hits: u64,
+    /// Number of hits to ignore before actually triggering
+    ignore: u64,
+}
+
+impl Breakpoint {
+    fn new(addr: u32) -> Breakpoint {
+        Breakpoint {
+            addr: addr,
+            enabled: true,
+            temporary: false,
+            condition: None,
+            hits: 0,
+            ignore: 0,
+        }
+    }
+
+    pub fn addr(&self) -> u32 {
+        self.addr
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn temporary(&self) -> bool {
+        self.temporary
+    }
+
+    pub fn set_temporary(&mut self, temporary: bool) {
+        self.temporary = temporary;
+    }
+
+    pub fn condition(&self) -> Option<&Expression> {
+        self.condition.as_ref()
+    }
+
+    pub fn set_condition(&mut self, condition: Option<Expression>) {
+        self.condition = condition;
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn reset_hits(&mut self) {
+        self.hits = 0;
+    }
+
+    /// Don't trigger until the breakpoint has been hit more than
+    /// `ignore` times
+    pub fn set_ignore_count(&mut self, ignore: u64) {
+        self.ignore = ignore;
+    }
+
+    pub fn ignore_count(&self) -> u64 {
+        self.ignore
+    }
+}
+
+pub struct Breakpoints {
+    /// Breakpoints indexed by address
+    breakpoints: HashMap<u32, Breakpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            breakpoints: HashMap::new(),
+        }
+    }
+
+    /// Add a breakpoint at `addr` and return it so that it can be
+    /// configured. If a breakpoint already exists at this address
+    /// it's replaced.
+    pub fn add(&mut self, addr: u32) -> &mut Breakpoint {
+        self.breakpoints.insert(addr, Breakpoint::new(addr));
+
+        self.breakpoints.get_mut(&addr).unwrap()
+    }
+
+    /// Add a breakpoint at `location`: an address, a symbol name or a
+    /// symbol with an offset ("main+0x10"), see
+    /// `SymbolTable::resolve`. That's what a "break main" command
+    /// would use.
+    pub fn add_at(&mut self,
+                  location: &str,
+                  symbols: Option<&SymbolTable>)
+                  -> Result<&mut Breakpoint, String> {
+        let addr =
+            match symbols {
+                Some(s) => s.resolve(location),
+                None => parse_hex(location.trim()),
+            };
+
+        match addr {
+            Some(a) => Ok(self.add(a)),
+            None => Err(format!("Unknown location '{}'", location)),
+        }
+    }
+
+    /// Add a temporary breakpoint at `addr`, it will be removed the
+    /// first time it triggers
+    pub fn add_temporary(&mut self, addr: u32) -> &mut Breakpoint {
+        let b = self.add(addr);
+
+        b.set_temporary(true);
+
+        b
+    }
+
+    /// Remove the breakpoint at `addr`. Returns `false` if there was
+    /// no breakpoint there.
+    pub fn remove(&mut self, addr: u32) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn get(&self, addr: u32) -> Option<&Breakpoint> {
+        self.breakpoints.get(&addr)
+    }
+
+    pub fn get_mut(&mut self, addr: u32) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&addr)
+    }
+
+    /// Enable or disable the breakpoint at `addr`. Returns `false` if
+    /// there's no breakpoint there.
+    pub fn set_enabled(&mut self, addr: u32, enabled: bool) -> bool {
+        match self.breakpoints.get_mut(&addr) {
+            Some(b) => {
+                b.set_enabled(enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return all the breakpoints sorted by address
+    pub fn list(&self) -> Vec<&Breakpoint> {
+        let mut list: Vec<&Breakpoint> = self.breakpoints.values().collect();
+
+        list.sort_by_key(|b| b.addr);
+
+        list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    /// Called before each instruction, returns `true` if the debugger
+    /// should break.
+    pub fn check(&mut self, cpu: &mut Cpu) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+
+        let pc = cpu.pc();
+
+        let remove =
+            match self.breakpoints.get_mut(&pc) {
+                Some(b) => {
+                    if !b.enabled {
+                        return false;
+                    }
+
+                    if let Some(ref c) = b.condition {
+                        // Stop if the condition can't be evaluated so
+                        // that it can be fixed
+                        match c.is_true(cpu) {
+                            Ok(true) => (),
+                            Ok(false) => return false,
+                            Err(e) => warn!("Breakpoint condition at \
+                                             0x{:08x}: {}", pc, e),
+                        }
+                    }
+
+                    b.hits += 1;
+
+                    if b.hits <= b.ignore {
+                        return false;
+                    }
+
+                    b.temporary
+                }
+                None => return false,
+            };
+
+        if remove {
+            self.breakpoints.remove(&pc);
+        }
+
+        true
+    }
+}
+
+/// Comparison operators usable in breakpoint conditions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Value in a breakpoint condition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// General purpose register
+    Register(usize),
+    Pc,
+    Hi,
+    Lo,
+    Constant(u32),
+}
+
+impl Operand {
+    fn parse(s: &str) -> Result<Operand, String> {
+        let s = s.trim();
+        let name = s.trim_left_matches('$');
+
+        if let Some(r) = register_index(name) {
+            return Ok(Operand::Register(r));
+        }
+
+        let op =
+            match name {
+                "pc" => Operand::Pc,
+                "hi" => Operand::Hi,
+                "lo" => Operand::Lo,
+                _ => {
+                    let v =
+                        if s.starts_with("0x") || s.starts_with("0X") {
+                            parse_hex(s)
+                        } else if s.starts_with('-') {
+                            s.parse::<i32>().ok().map(|v| v as u32)
+                        } else {
+                            s.parse::<u32>().ok()
+                        };
+
+                    match v {
+                        Some(v) => Operand::Constant(v),
+                        None =>
+                            return Err(format!("Invalid operand '{}'", s)),
+                    }
+                }
+            };
+
+        Ok(op)
+    }
+
+    fn value(self, cpu: &Cpu) -> u32 {
+        match self {
+            Operand::Register(r) => cpu.regs()[r],
+            Operand::Pc => cpu.pc(),
+            Operand::Hi => cpu.hi(),
+            Operand::Lo => cpu.lo(),
+            Operand::Constant(v) => v,
+        }
+    }
+}
+
+impl From<Operand> for Expression {
+    fn from(op: Operand) -> Expression {
+        match op {
+            Operand::Register(r) => Expression::Register(r),
+            Operand::Pc => Expression::Pc,
+            Operand::Hi => Expression::Hi,
+            Operand::Lo => Expression::Lo,
+            Operand::Constant(v) => Expression::Constant(v),
+        }
+    }
+}
+
+/// Simple condition comparing two operands. Comparisons are
+/// unsigned. `Expression` supports more complex conditions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub cmp: Comparison,
+    pub rhs: Operand,
+}
+
+impl Condition {
+    /// Parse a condition like "v0 == 0" or "$a0 >= 0x80010000"
+    pub fn parse(s: &str) -> Result<Condition, String> {
+        // Two-character operators must be tested first
+        let operators = [("==", Comparison::Eq),
+                         ("!=", Comparison::Ne),
+                         ("<=", Comparison::Le),
+                         (">=", Comparison::Ge),
+                         ("<", Comparison::Lt),
+                         (">", Comparison::Gt)];
+
+        for &(op, cmp) in &operators {
+            if let Some(pos) = s.find(op) {
+                let lhs = try!(Operand::parse(&s[..pos]));
+                let rhs = try!(Operand::parse(&s[pos + op.len()..]));
+
+                return Ok(Condition {
+                    lhs: lhs,
+                    cmp: cmp,
+                    rhs: rhs,
+                });
+            }
+        }
+
+        Err(format!("Invalid condition '{}'", s))
+    }
+
+    pub fn eval(&self, cpu: &Cpu) -> bool {
+        let a = self.lhs.value(cpu);
+        let b = self.rhs.value(cpu);
+
+        match self.cmp {
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+        }
+    }
+}
+
+impl From<Condition> for Expression {
+    fn from(c: Condition) -> Expression {
+        let op =
+            match c.cmp {
+                Comparison::Eq => BinaryOp::Eq,
+                Comparison::Ne => BinaryOp::Ne,
+                Comparison::Lt => BinaryOp::Lt,
+                Comparison::Le => BinaryOp::Le,
+                Comparison::Gt => BinaryOp::Gt,
+                Comparison::Ge => BinaryOp::Ge,
+            };
+
+        Expression::Binary(op,
+                           Box::new(c.lhs.into()),
+                           Box::new(c.rhs.into()))
+    }
+}
+
+/// Conventional names of the general purpose registers
+pub const REGISTER_NAMES: [&'static str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3",
+    "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7",
+    "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
+];
+
+/// Return the index of the register called `name`. Accepts the
+/// conventional names as well as "r0" to "r31".
+pub fn register_index(name: &str) -> Option<usize> {
+    if let Some(i) = REGISTER_NAMES.iter().position(|&r| r == name) {
+        return Some(i);
+    }
+
+    match name {
+        "s8" => Some(30),
+        _ if name.starts_with('r') =>
+            name[1..].parse::<usize>().ok().and_then(|i| {
+                if i < 32 { Some(i) } else { None }
+            }),
+        _ => None,
+    }
+}
+
+#[test]
+fn parse_condition() {
+    let c = Condition::parse("v0 == 0").unwrap();
+
+    assert_eq!(c, Condition {
+        lhs: Operand::Register(2),
+        cmp: Comparison::Eq,
+        rhs: Operand::Constant(0),
+    });
+
+    let c = Condition::parse("$ra>=0x80010000").unwrap();
+
+    assert_eq!(c, Condition {
+        lhs: Operand::Register(31),
+        cmp: Comparison::Ge,
+        rhs: Operand::Constant(0x80010000),
+    });
+
+    assert_eq!(Condition::parse("r12 < lo").unwrap().lhs,
+               Operand::Register(12));
+
+    assert!(Condition::parse("v0").is_err());
+    assert!(Condition::parse("foo == 1").is_err());
+}
@@ -0,0 +1,206 @@
+//! Call stack reconstruction for the debugger.
+//!
+//! MIPS code doesn't keep frame pointers reliably so we use two
+//! heuristics: `CallStack` tracks the `jal`/`jalr` and `jr ra`
+//! instructions as they're executed, `scan_stack` looks for return
+//! addresses on the stack when the call history is not available
+//! (for instance after loading a savestate).
+
+use cpu::Cpu;
+use memory::Word;
+use memory::map::mask_region;
+
+use super::symbols::SymbolTable;
+
+/// A function call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Address of the call instruction
+    pub call_site: u32,
+    /// Address of the called function
+    pub target: u32,
+    /// Value of SP when the call was made
+    pub sp: u32,
+}
+
+impl Frame {
+    /// Address where the function is expected to return
+    pub fn return_address(&self) -> u32 {
+        self.call_site.wrapping_add(8)
+    }
+}
+
+pub struct CallStack {
+    /// Active calls, innermost last
+    frames: Vec<Frame>,
+    /// Maximum number of frames tracked. Code that doesn't return
+    /// through `jr ra` (exception handlers, longjmp, tail calls using
+    /// other registers...) would otherwise make the stack grow
+    /// without bound.
+    max_depth: usize,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack {
+            frames: Vec::new(),
+            max_depth: 256,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Should be called from `Debugger::pc_change` for each
+    /// instruction about to be executed
+    pub fn instruction_start(&mut self, cpu: &mut Cpu) {
+        let pc = cpu.pc();
+        let instruction = cpu.examine::<Word>(pc);
+
+        self.track(pc, instruction, cpu.regs());
+    }
+
+    /// Update the call stack with the instruction `instruction` at
+    /// `pc` about to be executed with the register values `regs`
+    pub fn track(&mut self, pc: u32, instruction: u32, regs: &[u32]) {
+        let opcode = instruction >> 26;
+        let function = instruction & 0x3f;
+        let rs = ((instruction >> 21) & 0x1f) as usize;
+
+        let target =
+            match (opcode, function) {
+                // JAL
+                (0x03, _) => {
+                    let imm = instruction & 0x3ffffff;
+
+                    (pc.wrapping_add(4) & 0xf0000000) | (imm << 2)
+                }
+                // JALR
+                (0x00, 0x09) => regs[rs],
+                // JR
+                (0x00, 0x08) => {
+                    if rs == 31 {
+                        self.ret(regs[31]);
+                    }
+                    return;
+                }
+                _ => return,
+            };
+
+        if self.frames.len() >= self.max_depth {
+            self.frames.remove(0);
+        }
+
+        self.frames.push(Frame {
+            call_site: pc,
+            target: target,
+            sp: regs[29],
+        });
+    }
+
+    /// Handle a return to `addr`. We unwind up to the matching call,
+    /// if there's none the return is ignored.
+    fn ret(&mut self, addr: u32) {
+        let pos = self.frames.iter().rposition(|f| f.return_address() == addr);
+
+        if let Some(pos) = pos {
+            self.frames.truncate(pos);
+        }
+    }
+
+    /// Return a description of the call chain, innermost first,
+    /// starting with the current `pc`
+    pub fn backtrace(&self,
+                     pc: u32,
+                     symbols: Option<&SymbolTable>) -> Vec<String> {
+        let mut bt = Vec::with_capacity(self.frames.len() + 1);
+
+        bt.push(format!("#0  {}", describe(pc, symbols)));
+
+        for (i, f) in self.frames.iter().rev().enumerate() {
+            bt.push(format!("#{:<2} {} (called from {})",
+                            i + 1,
+                            describe(f.target, symbols),
+                            describe(f.call_site, symbols)));
+        }
+
+        bt
+    }
+}
+
+/// Scan `max_words` words of the stack starting at the current SP for
+/// values that look like return addresses: pointers into RAM just
+/// after a `jal` or `jalr` and its delay slot. This is unreliable
+/// (stale return addresses and data can look legitimate) but works
+/// without any call history.
+pub fn scan_stack(cpu: &mut Cpu, max_words: u32) -> Vec<u32> {
+    let sp = cpu.regs()[29];
+
+    let mut addresses = Vec::new();
+
+    for i in 0..max_words {
+        let addr = sp.wrapping_add(i * 4);
+
+        if !is_ram(addr) {
+            break;
+        }
+
+        let ra = cpu.examine::<Word>(addr);
+
+        if ra & 3 != 0 || ra < 8 || !is_ram(ra) {
+            continue;
+        }
+
+        let call = cpu.examine::<Word>(ra - 8);
+
+        let is_jal = call >> 26 == 0x03;
+        let is_jalr = call >> 26 == 0 && call & 0x3f == 0x09;
+
+        if is_jal || is_jalr {
+            addresses.push(ra);
+        }
+    }
+
+    addresses
+}
+
+/// Return true if `addr` is in main RAM (or one of its mirrors)
+fn is_ram(addr: u32) -> bool {
+    mask_region(addr) < 0x00800000
+}
+
+fn describe(addr: u32, symbols: Option<&SymbolTable>) -> String {
+    match symbols {
+        Some(s) => s.describe(addr),
+        None => format!("0x{:08x}", addr),
+    }
+}
+
+#[test]
+fn track_calls() {
+    let mut stack = CallStack::new();
+    let mut regs = [0u32; 32];
+
+    regs[29] = 0x801ffff0;
+
+    // jal 0x80020000
+    stack.track(0x80010000, 0x0c008000, &regs);
+    // jalr t0
+    regs[8] = 0x80030000;
+    stack.track(0x80020010, 0x0100f809, &regs);
+
+    assert_eq!(stack.frames().len(), 2);
+    assert_eq!(stack.frames()[0].target, 0x80020000);
+    assert_eq!(stack.frames()[1].target, 0x80030000);
+
+    // Return from the outer function directly, unwinding both frames
+    regs[31] = 0x80010008;
+    stack.track(0x80020100, 0x03e00008, &regs);
+
+    assert!(stack.frames().is_empty());
+}
@@ -0,0 +1,532 @@
+//! Expressions used by the debugger for conditional breakpoints,
+//! print commands and watches.
+//!
+//! The syntax is C-like, all values are 32bit unsigned integers:
+//!
+//! * Numbers: `1234`, `0x80010000`
+//! * Registers: `$t0`, `$r8`, `$pc`, `$hi`, `$lo`. The `$` can be
+//!   omitted.
+//! * Symbols, when a `SymbolTable` is provided: `main`, evaluates to
+//!   the symbol's address. Register names take precedence.
+//! * Memory: `byte[addr]`, `half[addr]` and `word[addr]`
+//! * Operators, by increasing precedence: `||`, `&&`, `|`, `^`, `&`,
+//!   `==` `!=`, `<` `<=` `>` `>=`, `<<` `>>`, `+` `-`, `*` `/` `%` and
+//!   the unary `-`, `~` and `!`. Comparisons are unsigned and
+//!   evaluate to 0 or 1.
+
+use cpu::Cpu;
+use memory::{Byte, HalfWord, Word};
+
+use super::breakpoints::register_index;
+use super::symbols::SymbolTable;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expression {
+    Constant(u32),
+    /// General purpose register
+    Register(usize),
+    Pc,
+    Hi,
+    Lo,
+    /// Memory read of the given width at the computed address
+    Memory(Width, Box<Expression>),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    HalfWord,
+    Word,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    LogicalNot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+}
+
+impl Expression {
+    pub fn parse(s: &str) -> Result<Expression, String> {
+        Expression::parse_with_symbols(s, None)
+    }
+
+    /// Parse `s`, looking up unknown identifiers in `symbols`. The
+    /// symbols are resolved immediately.
+    pub fn parse_with_symbols(s: &str,
+                              symbols: Option<&SymbolTable>)
+                              -> Result<Expression, String> {
+        let tokens = try!(tokenize(s));
+
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            symbols: symbols,
+        };
+
+        let e = try!(parser.expression(0));
+
+        match parser.peek() {
+            None => Ok(e),
+            Some(t) => Err(format!("Unexpected '{}' in '{}'", t, s)),
+        }
+    }
+
+    /// Evaluate the expression. Memory is read through
+    /// `Interconnect::peek` so that evaluating an expression never
+    /// has side effects, reading the I/O registers is an error.
+    pub fn eval(&self, cpu: &Cpu) -> Result<u32, String> {
+        let v =
+            match *self {
+                Expression::Constant(v) => v,
+                Expression::Register(r) => cpu.regs()[r],
+                Expression::Pc => cpu.pc(),
+                Expression::Hi => cpu.hi(),
+                Expression::Lo => cpu.lo(),
+                Expression::Memory(w, ref addr) => {
+                    let addr = try!(addr.eval(cpu));
+                    let inter = cpu.interconnect();
+
+                    let v =
+                        match w {
+                            Width::Byte => inter.peek::<Byte>(addr),
+                            Width::HalfWord => inter.peek::<HalfWord>(addr),
+                            Width::Word => inter.peek::<Word>(addr),
+                        };
+
+                    match v {
+                        Some(v) => v,
+                        None => return Err(format!("Address 0x{:08x} is \
+                                                    unreadable", addr)),
+                    }
+                }
+                Expression::Unary(op, ref e) => {
+                    let v = try!(e.eval(cpu));
+
+                    match op {
+                        UnaryOp::Neg => 0u32.wrapping_sub(v),
+                        UnaryOp::Not => !v,
+                        UnaryOp::LogicalNot => (v == 0) as u32,
+                    }
+                }
+                Expression::Binary(op, ref lhs, ref rhs) => {
+                    let a = try!(lhs.eval(cpu));
+
+                    // Short-circuit the logical operators so that
+                    // `$a0 != 0 && word[$a0] == 1` doesn't read
+                    // address 0
+                    match op {
+                        BinaryOp::LogicalAnd if a == 0 => return Ok(0),
+                        BinaryOp::LogicalOr if a != 0 => return Ok(1),
+                        _ => (),
+                    }
+
+                    let b = try!(rhs.eval(cpu));
+
+                    op.apply(a, b)
+                }
+            };
+
+        Ok(v)
+    }
+        }
+    }
+
+    /// Evaluate the expression as a condition
+    pub fn is_true(&self, cpu: &Cpu) -> Result<bool, String> {
+        self.eval(cpu).map(|v| v != 0)
+    }
+}
+
+impl BinaryOp {
+    fn apply(self, a: u32, b: u32) -> u32 {
+        match self {
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+            BinaryOp::Mul => a.wrapping_mul(b),
+            // Same results as the CPU's DIVU
+            BinaryOp::Div => a.checked_div(b).unwrap_or(0xffffffff),
+            BinaryOp::Rem => a.checked_rem(b).unwrap_or(a),
+            BinaryOp::And => a & b,
+            BinaryOp::Or => a | b,
+            BinaryOp::Xor => a ^ b,
+            BinaryOp::Shl => a.wrapping_shl(b),
+            BinaryOp::Shr => a.wrapping_shr(b),
+            BinaryOp::Eq => (a == b) as u32,
+            BinaryOp::Ne => (a != b) as u32,
+            BinaryOp::Lt => (a < b) as u32,
+            BinaryOp::Le => (a <= b) as u32,
+            BinaryOp::Gt => (a > b) as u32,
+            BinaryOp::Ge => (a >= b) as u32,
+            BinaryOp::LogicalAnd => (a != 0 && b != 0) as u32,
+            BinaryOp::LogicalOr => (a != 0 || b != 0) as u32,
+        }
+    }
+}
+
+/// Binary operators grouped by precedence, lowest first
+const BINARY_OPERATORS: [&'static [(&'static str, BinaryOp)]; 10] = [
+    &[("||", BinaryOp::LogicalOr)],
+    &[("&&", BinaryOp::LogicalAnd)],
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[("<", BinaryOp::Lt), ("<=", BinaryOp::Le),
+      (">", BinaryOp::Gt), (">=", BinaryOp::Ge)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)],
+];
+
+/// Operators and punctuation, longest first so that `<=` isn't
+/// tokenized as `<` followed by `=`
+const SYMBOLS: [&'static str; 22] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "~", "!",
+    "(", ")",
+];
+
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_left();
+
+    while !rest.is_empty() {
+        let len =
+            if let Some(sym) = SYMBOLS.iter().find(|&&o| rest.starts_with(o)) {
+                sym.len()
+            } else if rest.starts_with('[') || rest.starts_with(']') {
+                1
+            } else {
+                // Identifier or number
+                let end =
+                    rest.find(|c: char| !(c.is_alphanumeric()
+                                          || c == '_'
+                                          || c == '$'))
+                    .unwrap_or(rest.len());
+
+                if end == 0 {
+                    return Err(format!("Unexpected character in '{}'", rest));
+                }
+
+                end
+            };
+
+        tokens.push(rest[..len].to_owned());
+        rest = rest[len..].trim_left();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|t| t.as_ref())
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        match self.peek() {
+            Some(t) => {
+                self.pos += 1;
+                Ok(t)
+            }
+            None => Err("Unexpected end of expression".into()),
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        let t = try!(self.next());
+
+        if t == token {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}', got '{}'", token, t))
+        }
+    }
+
+    /// Parse an expression containing binary operators of precedence
+    /// `level` or higher
+    fn expression(&mut self, level: usize) -> Result<Expression, String> {
+        if level >= BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = try!(self.expression(level + 1));
+
+        loop {
+            let op =
+                match self.peek() {
+                    Some(t) => BINARY_OPERATORS[level].iter()
+                        .find(|&&(o, _)| o == t)
+                        .map(|&(_, op)| op),
+                    None => None,
+                };
+
+            match op {
+                Some(op) => {
+                    self.pos += 1;
+
+                    let rhs = try!(self.expression(level + 1));
+
+                    lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+                }
+                None => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        let op =
+            match self.peek() {
+                Some("-") => Some(UnaryOp::Neg),
+                Some("~") => Some(UnaryOp::Not),
+                Some("!") => Some(UnaryOp::LogicalNot),
+                _ => None,
+            };
+
+        match op {
+            Some(op) => {
+                self.pos += 1;
+
+                let e = try!(self.unary());
+
+                Ok(Expression::Unary(op, Box::new(e)))
+            }
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let t = try!(self.next());
+
+        if t == "(" {
+            let e = try!(self.expression(0));
+
+            try!(self.expect(")"));
+
+            return Ok(e);
+        }
+
+        let width =
+            match t {
+                "byte" => Some(Width::Byte),
+                "half" => Some(Width::HalfWord),
+                "word" => Some(Width::Word),
+                _ => None,
+            };
+
+        if let Some(w) = width {
+            try!(self.expect("["));
+
+            let addr = try!(self.expression(0));
+
+            try!(self.expect("]"));
+
+            return Ok(Expression::Memory(w, Box::new(addr)));
+        }
+
+        if t.starts_with(|c: char| c.is_digit(10)) {
+            let v =
+                if t.starts_with("0x") || t.starts_with("0X") {
+                    u32::from_str_radix(&t[2..], 16).ok()
+                } else {
+                    t.parse::<u32>().ok()
+                };
+
+            return match v {
+                Some(v) => Ok(Expression::Constant(v)),
+                None => Err(format!("Invalid number '{}'", t)),
+            };
+        }
+
+        let name = t.trim_left_matches('$');
+
+        if let Some(r) = register_index(name) {
+            return Ok(Expression::Register(r));
+        }
+
+        match name {
+            "pc" => return Ok(Expression::Pc),
+            "hi" => return Ok(Expression::Hi),
+            "lo" => return Ok(Expression::Lo),
+            _ => (),
+        }
+
+        let symbol =
+            if t.starts_with('$') {
+                None
+            } else {
+                self.symbols.and_then(|s| s.by_name(t))
+            };
+
+        match symbol {
+            Some(s) => Ok(Expression::Constant(s.addr)),
+            None => Err(format!("Unknown identifier '{}'", t)),
+        }
+    }
+}
+
+/// Expression evaluated each time the debugger stops, to follow how
+/// a value changes
+pub struct Watch {
+    /// Expression as entered by the user
+    source: String,
+    expression: Expression,
+    /// Value at the last update
+    value: Option<u32>,
+}
+
+impl Watch {
+    pub fn new(source: &str) -> Result<Watch, String> {
+        let expression = try!(Expression::parse(source));
+
+        Ok(Watch {
+            source: source.trim().to_owned(),
+            expression: expression,
+            value: None,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Value at the last update, `None` if it hasn't been evaluated
+    /// yet
+    pub fn value(&self) -> Option<u32> {
+        self.value
+    }
+
+    /// Re-evaluate the expression, returns true if the value
+    /// changed. The value is left untouched if the evaluation fails.
+    pub fn update(&mut self, cpu: &Cpu) -> Result<bool, String> {
+        let v = Some(try!(self.expression.eval(cpu)));
+        let changed = v != self.value;
+
+        self.value = v;
+
+        Ok(changed)
+    }
+}
+
+#[test]
+fn parse() {
+    use self::Expression::*;
+
+    let e = Expression::parse("$t0 + 4 * 2").unwrap();
+
+    assert_eq!(e, Binary(BinaryOp::Add,
+                         Box::new(Register(8)),
+                         Box::new(Binary(BinaryOp::Mul,
+                                         Box::new(Constant(4)),
+                                         Box::new(Constant(2))))));
+
+    let e = Expression::parse("word[$sp + 0x10] != 0 && v0").unwrap();
+
+    match e {
+        Binary(BinaryOp::LogicalAnd, lhs, _) =>
+            match *lhs {
+                Binary(BinaryOp::Ne, mem, _) =>
+                    assert_eq!(*mem, Memory(Width::Word,
+                                            Box::new(Binary(
+                                                BinaryOp::Add,
+                                                Box::new(Register(29)),
+                                                Box::new(Constant(0x10)))))),
+                e => panic!("Unexpected {:?}", e),
+            },
+        e => panic!("Unexpected {:?}", e),
+    }
+
+    assert_eq!(Expression::parse("-(1 << 4)").unwrap(),
+               Unary(UnaryOp::Neg,
+                     Box::new(Binary(BinaryOp::Shl,
+                                     Box::new(Constant(1)),
+                                     Box::new(Constant(4))))));
+
+    assert_eq!(Expression::parse("$pc<=$ra").unwrap(),
+               Binary(BinaryOp::Le, Box::new(Pc), Box::new(Register(31))));
+
+    assert!(Expression::parse("1 +").is_err());
+    assert!(Expression::parse("(1").is_err());
+    assert!(Expression::parse("foo").is_err());
+    assert!(Expression::parse("word[4").is_err());
+    assert!(Expression::parse("1 2").is_err());
+}
+
+#[test]
+fn symbols() {
+    let mut symbols = SymbolTable::new();
+
+    symbols.add(0x80010000, 0, "main".into());
+
+    assert_eq!(Expression::parse_with_symbols("main + 4",
+                                              Some(&symbols)).unwrap(),
+               Expression::Binary(BinaryOp::Add,
+                                  Box::new(Expression::Constant(0x80010000)),
+                                  Box::new(Expression::Constant(4))));
+
+    assert!(Expression::parse("main").is_err());
+    assert!(Expression::parse_with_symbols("$main", Some(&symbols)).is_err());
+}
+
+#[test]
+fn binary_ops() {
+    assert_eq!(BinaryOp::Sub.apply(0, 1), 0xffffffff);
+    assert_eq!(BinaryOp::Div.apply(7, 0), 0xffffffff);
+    assert_eq!(BinaryOp::Rem.apply(7, 0), 7);
+    assert_eq!(BinaryOp::Lt.apply(0xffffffff, 1), 0);
+    assert_eq!(BinaryOp::LogicalOr.apply(0, 3), 1);
+}
+
+#[test]
+fn eval_memory() {
+    use cpu::test_machine::TestMachine;
+
+    let m = TestMachine::new()
+        .with_blob(0x80001000, &[0x12345678])
+        .with_reg(8, 0x80001000);
+
+    let e = Expression::parse("half[$t0 + 2]").unwrap();
+
+    assert_eq!(e.eval(&m.cpu), Ok(0x1234));
+
+    // I/O registers aren't read
+    let e = Expression::parse("word[0x1f801070]").unwrap();
+
+    assert!(e.eval(&m.cpu).is_err());
+
+    // Short-circuited
+    let e = Expression::parse("0 && word[0x1f801070]").unwrap();
+
+    assert_eq!(e.eval(&m.cpu), Ok(0));
+}
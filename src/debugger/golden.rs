@@ -0,0 +1,261 @@
+//! Golden traces for differential testing against other emulators.
+//!
+//! A golden trace samples the CPU state every `interval` instructions
+//! and stores it in a canonical text format, one entry per line:
+//!
+//! ```text
+//! <instruction count> <pc> <digest>
+//! ```
+//!
+//! The instruction count is in decimal, the PC and digest are 8 digit
+//! hexadecimal values. The PC is the address of the instruction about
+//! to be executed and the digest is the 32bit FNV-1a hash of the
+//! little endian bytes of R0 to R31 followed by HI and LO. Traces
+//! captured from a reference emulator just have to be converted to
+//! this format in order to be compared with `first_divergence` (or
+//! the `trace_diff` tool). Lines starting with `#` are comments.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use cpu::Cpu;
+
+pub struct GoldenTrace {
+    /// Number of instructions between two samples
+    interval: u64,
+    /// Number of instructions executed so far
+    instructions: u64,
+    entries: Vec<TraceEntry>,
+}
+
+impl GoldenTrace {
+    pub fn new(interval: u64) -> GoldenTrace {
+        assert!(interval > 0);
+
+        GoldenTrace {
+            interval: interval,
+            instructions: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Should be called from `Debugger::pc_change`
+    pub fn pc_change(&mut self, cpu: &Cpu) {
+        if self.instructions % self.interval == 0 {
+            let entry = TraceEntry {
+                instruction: self.instructions,
+                pc: cpu.pc(),
+                digest: regs_digest(cpu.regs(), cpu.hi(), cpu.lo()),
+            };
+
+            self.entries.push(entry);
+        }
+
+        self.instructions += 1;
+    }
+
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    /// Dump the trace in the canonical format
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for e in &self.entries {
+            try!(writeln!(w, "{}", e));
+        }
+
+        Ok(())
+    }
+}
+
+/// Single sample of the CPU state
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TraceEntry {
+    /// Number of instructions executed before this sample
+    pub instruction: u64,
+    pub pc: u32,
+    /// Digest of the general purpose registers, HI and LO
+    pub digest: u32,
+}
+
+impl TraceEntry {
+    /// Parse a single line in the canonical format
+    pub fn parse(line: &str) -> Option<TraceEntry> {
+        let fields: Vec<_> = line.split_whitespace().collect();
+
+        if fields.len() != 3 {
+            return None;
+        }
+
+        let instruction = fields[0].parse::<u64>();
+        let pc = u32::from_str_radix(fields[1], 16);
+        let digest = u32::from_str_radix(fields[2], 16);
+
+        match (instruction, pc, digest) {
+            (Ok(i), Ok(p), Ok(d)) => Some(TraceEntry {
+                instruction: i,
+                pc: p,
+                digest: d,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:08x} {:08x}", self.instruction, self.pc, self.digest)
+    }
+}
+
+/// Compute the digest of the CPU registers used in the traces
+pub fn regs_digest(regs: &[u32], hi: u32, lo: u32) -> u32 {
+    let mut hash = FNV_OFFSET_BASIS;
+
+    for &r in regs.iter().chain([hi, lo].iter()) {
+        for i in 0..4 {
+            hash ^= (r >> (i * 8)) & 0xff;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    hash
+}
+
+/// Parse a complete trace. Empty lines and comments are ignored.
+pub fn parse_trace(trace: &str) -> Result<Vec<TraceEntry>, String> {
+    let mut entries = Vec::new();
+
+    for (n, line) in trace.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match TraceEntry::parse(line) {
+            Some(e) => entries.push(e),
+            None => return Err(format!("Invalid trace entry line {}: {}",
+                                       n + 1, line)),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Description of the first difference between two traces
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Divergence {
+    /// The entries at the same position differ
+    Mismatch {
+        expected: TraceEntry,
+        actual: TraceEntry,
+    },
+    /// One trace ends before the other, `last_common` is the last
+    /// entry found in both traces (if any)
+    Truncated {
+        last_common: Option<TraceEntry>,
+        expected_len: usize,
+        actual_len: usize,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Divergence::Mismatch { expected, actual } => {
+                try!(writeln!(f, "Traces diverge:"));
+                try!(writeln!(f, "  expected: {}", expected));
+                write!(f, "  actual:   {}", actual)
+            }
+            Divergence::Truncated { last_common,
+                                    expected_len,
+                                    actual_len } => {
+                try!(writeln!(f, "Trace length mismatch: expected {} \
+                                  entries, got {}",
+                              expected_len, actual_len));
+                match last_common {
+                    Some(e) => write!(f, "  last common entry: {}", e),
+                    None => write!(f, "  no common entry"),
+                }
+            }
+        }
+    }
+}
+
+/// Compare `actual` against the reference trace `expected`. Returns
+/// `None` if they're identical.
+pub fn first_divergence(expected: &[TraceEntry],
+                        actual: &[TraceEntry]) -> Option<Divergence> {
+    for (&e, &a) in expected.iter().zip(actual.iter()) {
+        if e != a {
+            return Some(Divergence::Mismatch {
+                expected: e,
+                actual: a,
+            });
+        }
+    }
+
+    if expected.len() != actual.len() {
+        let common = ::std::cmp::min(expected.len(), actual.len());
+
+        let last_common =
+            if common > 0 {
+                Some(expected[common - 1])
+            } else {
+                None
+            };
+
+        return Some(Divergence::Truncated {
+            last_common: last_common,
+            expected_len: expected.len(),
+            actual_len: actual.len(),
+        });
+    }
+
+    None
+}
+
+const FNV_OFFSET_BASIS: u32 = 0x811c9dc5;
+const FNV_PRIME: u32 = 0x01000193;
+
+#[test]
+fn divergence() {
+    let reference = "\
+# Reference trace
+0 bfc00000 12345678
+1000 bfc00150 9abcdef0
+
+2000 80010000 0badf00d
+";
+
+    let expected = parse_trace(reference).unwrap();
+
+    assert_eq!(expected.len(), 3);
+    assert_eq!(expected[2].to_string(), "2000 80010000 0badf00d");
+
+    assert_eq!(first_divergence(&expected, &expected), None);
+
+    let mut actual = expected.clone();
+
+    actual[1].digest = 0;
+
+    match first_divergence(&expected, &actual) {
+        Some(Divergence::Mismatch { expected: e, .. }) =>
+            assert_eq!(e.instruction, 1000),
+        d => panic!("Unexpected divergence {:?}", d),
+    }
+
+    match first_divergence(&expected, &expected[..2]) {
+        Some(Divergence::Truncated { last_common: Some(e), .. }) =>
+            assert_eq!(e.instruction, 1000),
+        d => panic!("Unexpected divergence {:?}", d),
+    }
+
+    assert!(parse_trace("0 bfc00000").is_err());
+
+    let regs = [0; 32];
+
+    assert!(regs_digest(&regs, 0, 0) != regs_digest(&regs, 0, 1));
+    assert!(regs_digest(&regs, 0, 1) != regs_digest(&regs, 1, 0));
+}
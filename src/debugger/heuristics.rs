@@ -0,0 +1,185 @@
+//! Heuristics catching common bugs in the emulated software.
+//!
+//! Games and homebrew programs don't crash on a null pointer
+//! dereference or a stack overflow, they silently corrupt the memory
+//! and break much later in unrelated code. `Heuristics` flags the
+//! usual culprits when called from the debugger's `memory_write`
+//! hook:
+//!
+//! * Writes to the first 64KB of RAM by code running outside of the
+//!   kernel. The BIOS keeps its variables there so a game has no
+//!   business writing to it, it's most likely a write through a null
+//!   (or almost null) pointer.
+//!
+//! * Writes while the stack pointer is below a user-configured floor,
+//!   usually a stack overflow or a function popping more than it
+//!   pushed.
+//!
+//! Both checks are disabled by default.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use cpu::Cpu;
+use memory::map::mask_region;
+
+/// Suspicious behaviour detected by the heuristics
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// The instruction at `pc` wrote to `addr` in the low 64KB of RAM
+    LowMemoryWrite { pc: u32, addr: u32 },
+    /// The instruction at `pc` wrote to memory while SP was below the
+    /// stack floor
+    StackOverflow { pc: u32, sp: u32 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::LowMemoryWrite { pc, addr } =>
+                write!(f, "0x{:08x}: write to low memory at 0x{:08x} \
+                           (null pointer?)", pc, addr),
+            Warning::StackOverflow { pc, sp } =>
+                write!(f, "0x{:08x}: SP is below the stack floor: 0x{:08x}",
+                       pc, sp),
+        }
+    }
+}
+
+pub struct Heuristics {
+    /// True if the low memory writes should be reported
+    low_memory_writes: bool,
+    /// Lowest valid value for SP, if any
+    stack_floor: Option<u32>,
+    /// Set while SP is below the floor so that an overflow is only
+    /// reported once
+    stack_overflowed: bool,
+    /// Instructions already reported for a low memory write
+    reported: HashSet<u32>,
+    /// Warnings not yet returned by `take_warnings`
+    warnings: Vec<Warning>,
+}
+
+impl Heuristics {
+    pub fn new() -> Heuristics {
+        Heuristics {
+            low_memory_writes: false,
+            stack_floor: None,
+            stack_overflowed: false,
+            reported: HashSet::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn set_low_memory_writes(&mut self, enabled: bool) {
+        self.low_memory_writes = enabled;
+    }
+
+    /// Report when SP goes below `floor`. `None` disables the check.
+    pub fn set_stack_floor(&mut self, floor: Option<u32>) {
+        self.stack_floor = floor;
+        self.stack_overflowed = false;
+    }
+
+    /// Should be called from `Debugger::memory_write`. Returns true if
+    /// a new warning has been raised, the debugger can use that to
+    /// break.
+    pub fn memory_write(&mut self, cpu: &Cpu, addr: u32) -> bool {
+        self.check(cpu.pc(), cpu.regs()[29], addr)
+    }
+
+    /// Check a write to `addr` by the instruction at `pc` with SP set
+    /// to `sp`
+    pub fn check(&mut self, pc: u32, sp: u32, addr: u32) -> bool {
+        // The kernel is free to do whatever it wants
+        if !is_user_code(pc) {
+            return false;
+        }
+
+        let mut raised = false;
+
+        if self.low_memory_writes && is_low_memory(addr) &&
+            self.reported.insert(pc) {
+            self.warnings.push(Warning::LowMemoryWrite { pc: pc, addr: addr });
+            raised = true;
+        }
+
+        if let Some(floor) = self.stack_floor {
+            let below = mask_region(sp) < mask_region(floor);
+
+            if below && !self.stack_overflowed {
+                self.warnings.push(Warning::StackOverflow { pc: pc, sp: sp });
+                raised = true;
+            }
+
+            self.stack_overflowed = below;
+        }
+
+        raised
+    }
+
+    /// Return the warnings raised since the last call
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        ::std::mem::replace(&mut self.warnings, Vec::new())
+    }
+}
+
+/// Return true if `pc` is in RAM, outside of the kernel area
+fn is_user_code(pc: u32) -> bool {
+    let pc = mask_region(pc);
+
+    pc < RAM_END && pc & RAM_MASK >= LOW_MEMORY_END
+}
+
+/// Return true if `addr` targets the kernel area in RAM (or one of
+/// its mirrors)
+fn is_low_memory(addr: u32) -> bool {
+    let addr = mask_region(addr);
+
+    addr < RAM_END && addr & RAM_MASK < LOW_MEMORY_END
+}
+
+/// End of the RAM mirrors
+const RAM_END: u32 = 0x800000;
+
+/// The 2MB of RAM are mirrored four times
+const RAM_MASK: u32 = 0x1fffff;
+
+/// End of the area reserved for the kernel
+const LOW_MEMORY_END: u32 = 0x10000;
+
+#[test]
+fn low_memory_and_stack() {
+    let mut h = Heuristics::new();
+
+    // Disabled by default
+    assert!(!h.check(0x80010000, 0x801fff00, 0x00000004));
+
+    h.set_low_memory_writes(true);
+    h.set_stack_floor(Some(0x801f0000));
+
+    // Kernel code is ignored
+    assert!(!h.check(0x80001000, 0x8000d000, 0x80000100));
+
+    assert!(h.check(0x80010000, 0x801fff00, 0x00000004));
+    // Only reported once per instruction
+    assert!(!h.check(0x80010000, 0x801fff00, 0x00000008));
+    // Mirror of the kernel area
+    assert!(h.check(0x80010004, 0x801fff00, 0xa0200010));
+
+    assert!(h.check(0x80010008, 0x801efff0, 0x801efff0));
+    assert!(!h.check(0x8001000c, 0x801effe0, 0x801effe0));
+    // SP recovers, then overflows again
+    assert!(!h.check(0x80010010, 0x801fff00, 0x801fff00));
+    assert!(h.check(0x80010014, 0x801efff0, 0x801efff0));
+
+    assert_eq!(h.take_warnings(),
+               vec![Warning::LowMemoryWrite { pc: 0x80010000, addr: 4 },
+                    Warning::LowMemoryWrite { pc: 0x80010004,
+                                              addr: 0xa0200010 },
+                    Warning::StackOverflow { pc: 0x80010008,
+                                             sp: 0x801efff0 },
+                    Warning::StackOverflow { pc: 0x80010014,
+                                             sp: 0x801efff0 }]);
+    assert!(h.take_warnings().is_empty());
+}
@@ -1,5 +1,15 @@
 use cpu::Cpu;
 
+pub mod symbols;
+pub mod breakpoints;
+pub mod expression;
+pub mod callstack;
+pub mod scanner;
+pub mod tty;
+pub mod profiler;
+pub mod golden;
+pub mod heuristics;
+
 /// Trait defining the debugger interface
 pub trait Debugger {
     /// Signal a "break" which will put the emulator in debug mode at
@@ -11,6 +21,12 @@ pub trait Debugger {
     /// instructions so it needs to be as fast as possible.
     fn pc_change(&mut self, cpu: &mut Cpu);
 
+    /// Called by the CPU before each instruction with the address of
+    /// the instruction and the current date in CPU cycles. Meant for
+    /// profiling, the default implementation does nothing.
+    fn instruction_start(&mut self, _pc: u32, _cycles: u64) {
+    }
+
     /// Called by the CPU when it's about to load a value from memory.
     fn memory_read(&mut self, cpu: &mut Cpu, addr: u32);
 
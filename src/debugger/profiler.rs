@@ -0,0 +1,158 @@
+//! Profiler for the emulated code.
+//!
+//! The profiler is fed by the `Debugger::instruction_start` hook and
+//! accounts the CPU cycles spent between two instructions to the
+//! address range (bucket) containing the first one. The results can
+//! be symbolized and exported in the "folded stacks" format used by
+//! flamegraph.pl and compatible tools.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use super::symbols::SymbolTable;
+
+pub struct Profiler {
+    /// log2 of the size of the address buckets
+    granularity: u32,
+    /// Number of cycles spent in each bucket, indexed by the bucket's
+    /// base address
+    buckets: HashMap<u32, u64>,
+    /// Address and date of the previous instruction
+    last: Option<(u32, u64)>,
+    /// True if the profiler is recording
+    enabled: bool,
+}
+
+impl Profiler {
+    /// Create a new profiler. Addresses are grouped in buckets of
+    /// `1 << granularity` bytes, use 2 to get per-instruction
+    /// results.
+    pub fn new(granularity: u32) -> Profiler {
+        Profiler {
+            granularity: granularity,
+            buckets: HashMap::new(),
+            last: None,
+            enabled: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Start or stop recording. The results are kept until `clear`
+    /// is called.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.last = None;
+    }
+
+    /// Discard all the results
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.last = None;
+    }
+
+    /// Called for each instruction with its address and the current
+    /// date
+    pub fn record(&mut self, pc: u32, now: u64) {
+        if !self.enabled {
+            return;
+        }
+
+        if let Some((last_pc, last_date)) = self.last {
+            let bucket = self.bucket(last_pc);
+
+            *self.buckets.entry(bucket).or_insert(0) += now - last_date;
+        }
+
+        self.last = Some((pc, now));
+    }
+
+    /// Total number of cycles recorded
+    pub fn total_cycles(&self) -> u64 {
+        self.buckets.values().fold(0, |acc, &c| acc + c)
+    }
+
+    fn bucket(&self, pc: u32) -> u32 {
+        // Mask the region bits so that KUSEG, KSEG0 and KSEG1 accesses
+        // to the same code end up in the same bucket
+        let pc = ::memory::map::mask_region(pc);
+
+        (pc >> self.granularity) << self.granularity
+    }
+
+    /// Return the results sorted by decreasing cycle count. If
+    /// `symbols` is provided the buckets are merged per function.
+    pub fn report(&self,
+                  symbols: Option<&SymbolTable>) -> Vec<(String, u64)> {
+        let mut merged: HashMap<String, u64> = HashMap::new();
+
+        for (&addr, &cycles) in &self.buckets {
+            let name =
+                match symbols.and_then(|s| lookup(s, addr)) {
+                    Some(n) => n,
+                    None => format!("0x{:08x}", addr),
+                };
+
+            *merged.entry(name).or_insert(0) += cycles;
+        }
+
+        let mut report: Vec<(String, u64)> = merged.into_iter().collect();
+
+        report.sort_by(|a, b| {
+            match b.1.cmp(&a.1) {
+                Ordering::Equal => a.0.cmp(&b.0),
+                o => o,
+            }
+        });
+
+        report
+    }
+
+    /// Write the results in the "folded stacks" format: one line per
+    /// function with the name followed by the cycle count.
+    pub fn write_folded<W: Write>(&self,
+                                  w: &mut W,
+                                  symbols: Option<&SymbolTable>)
+                                  -> io::Result<()> {
+        for (name, cycles) in self.report(symbols) {
+            try!(writeln!(w, "{} {}", name, cycles));
+        }
+
+        Ok(())
+    }
+}
+
+/// Look up the symbol for a bucket. Symbols are usually in KSEG0 while
+/// the buckets use physical addresses so we try both.
+fn lookup(symbols: &SymbolTable, addr: u32) -> Option<String> {
+    symbols.lookup(addr)
+        .or_else(|| symbols.lookup(addr | 0x80000000))
+        .map(|(s, _)| s.name.clone())
+}
+
+#[test]
+fn profile() {
+    let mut profiler = Profiler::new(2);
+
+    profiler.set_enabled(true);
+
+    profiler.record(0x80010000, 100);
+    profiler.record(0x80010004, 102);
+    profiler.record(0x80020000, 110);
+    profiler.record(0x80010000, 111);
+
+    assert_eq!(profiler.total_cycles(), 11);
+
+    let mut symbols = SymbolTable::new();
+
+    symbols.add(0x80010000, 0x100, "main".into());
+    symbols.add(0x80020000, 0x100, "slow".into());
+
+    let report = profiler.report(Some(&symbols));
+
+    assert_eq!(report, vec![("main".to_string(), 10),
+                            ("slow".to_string(), 1)]);
+}
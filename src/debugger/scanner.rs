@@ -0,0 +1,237 @@
+//! RAM scanner and value freezing, used to find the location of game
+//! variables (for cheat creation or reverse engineering) and to force
+//! their values.
+//!
+//! The scanner works on successive snapshots of the RAM: the first
+//! scan considers every aligned address, each following scan only
+//! keeps the candidates matching the filter.
+
+use memory::{Byte, HalfWord, Word};
+use memory::ram::Ram;
+
+/// Size of the values being searched
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    HalfWord,
+    Word,
+}
+
+impl Width {
+    pub fn bytes(self) -> u32 {
+        match self {
+            Width::Byte => 1,
+            Width::HalfWord => 2,
+            Width::Word => 4,
+        }
+    }
+
+    fn load(self, ram: &Ram, offset: u32) -> u32 {
+        match self {
+            Width::Byte => ram.load::<Byte>(offset),
+            Width::HalfWord => ram.load::<HalfWord>(offset),
+            Width::Word => ram.load::<Word>(offset),
+        }
+    }
+
+    fn store(self, ram: &mut Ram, offset: u32, val: u32) {
+        match self {
+            Width::Byte => ram.store::<Byte>(offset, val),
+            Width::HalfWord => ram.store::<HalfWord>(offset, val),
+            Width::Word => ram.store::<Word>(offset, val),
+        }
+    }
+
+    /// Read a value from a RAM snapshot
+    fn read(self, data: &[u8], offset: u32) -> u32 {
+        let offset = offset as usize;
+
+        (0..self.bytes() as usize).fold(0, |v, i| {
+            v | ((data[offset + i] as u32) << (i * 8))
+        })
+    }
+}
+
+/// Scan filter. Comparisons are unsigned, `Changed`, `Unchanged`,
+/// `Increased` and `Decreased` compare against the value at the
+/// previous scan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Equal(u32),
+    NotEqual(u32),
+    /// Value in the inclusive range
+    Range(u32, u32),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Filter {
+    fn matches(self, previous: u32, current: u32) -> bool {
+        match self {
+            Filter::Equal(v) => current == v,
+            Filter::NotEqual(v) => current != v,
+            Filter::Range(min, max) => current >= min && current <= max,
+            Filter::Changed => current != previous,
+            Filter::Unchanged => current == previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+        }
+    }
+}
+
+pub struct Scanner {
+    width: Width,
+    /// RAM offsets of the remaining candidates. `None` before the
+    /// first scan, in which case all the aligned offsets are
+    /// candidates.
+    candidates: Option<Vec<u32>>,
+    /// RAM contents at the previous scan
+    snapshot: Vec<u8>,
+}
+
+impl Scanner {
+    pub fn new(width: Width) -> Scanner {
+        Scanner {
+            width: width,
+            candidates: None,
+            snapshot: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    /// Restart the search from scratch with all the addresses as
+    /// candidates. The current RAM contents are used as reference for
+    /// the relative filters.
+    pub fn reset(&mut self, ram: &Ram) {
+        self.candidates = None;
+        self.snapshot = ram.data().to_vec();
+    }
+
+    /// Filter the candidates against the current RAM contents and
+    /// return the number of remaining candidates
+    pub fn scan(&mut self, ram: &Ram, filter: Filter) -> usize {
+        let data = ram.data();
+
+        if self.snapshot.len() != data.len() {
+            // No reference, the relative filters will compare each
+            // value against itself
+            self.snapshot = data.to_vec();
+        }
+
+        let width = self.width;
+        let step = width.bytes();
+
+        let candidates =
+            match self.candidates.take() {
+                Some(c) => c,
+                None =>
+                    (0..(data.len() as u32) / step).map(|i| i * step).collect(),
+            };
+
+        let snapshot = &self.snapshot;
+
+        let candidates: Vec<u32> =
+            candidates.into_iter()
+            .filter(|&o| filter.matches(width.read(snapshot, o),
+                                        width.read(data, o)))
+            .collect();
+
+        let count = candidates.len();
+
+        self.candidates = Some(candidates);
+        self.snapshot = data.to_vec();
+
+        count
+    }
+
+    /// Return the addresses (in KSEG0) of the remaining candidates.
+    /// Returns an empty list if no scan has been made yet.
+    pub fn results(&self) -> Vec<u32> {
+        match self.candidates {
+            Some(ref c) => c.iter().map(|&o| 0x80000000 | o).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return the current value at `addr`
+    pub fn value(&self, ram: &Ram, addr: u32) -> u32 {
+        self.width.load(ram, addr)
+    }
+}
+
+/// Set of RAM locations whose values are forced
+pub struct Freezer {
+    /// Frozen locations: address, width and value
+    frozen: Vec<(u32, Width, u32)>,
+}
+
+impl Freezer {
+    pub fn new() -> Freezer {
+        Freezer {
+            frozen: Vec::new(),
+        }
+    }
+
+    /// Force the value at `addr` to `val`. Replaces any previous
+    /// freeze at the same address.
+    pub fn freeze(&mut self, addr: u32, width: Width, val: u32) {
+        self.unfreeze(addr);
+        self.frozen.push((addr, width, val));
+    }
+
+    /// Returns `false` if `addr` wasn't frozen
+    pub fn unfreeze(&mut self, addr: u32) -> bool {
+        let len = self.frozen.len();
+
+        self.frozen.retain(|&(a, _, _)| a != addr);
+
+        self.frozen.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.frozen.clear();
+    }
+
+    pub fn frozen(&self) -> &[(u32, Width, u32)] {
+        &self.frozen
+    }
+
+    /// Write the frozen values back to RAM. Should be called
+    /// periodically, typically once per frame.
+    pub fn apply(&self, ram: &mut Ram) {
+        for &(addr, width, val) in &self.frozen {
+            width.store(ram, addr, val);
+        }
+    }
+}
+
+#[test]
+fn scan() {
+    let mut ram = Ram::new();
+
+    ram.store::<Word>(0x1000, 100);
+    ram.store::<Word>(0x2000, 100);
+
+    let mut scanner = Scanner::new(Width::Word);
+
+    scanner.reset(&ram);
+
+    assert_eq!(scanner.scan(&ram, Filter::Equal(100)), 2);
+
+    ram.store::<Word>(0x2000, 99);
+
+    assert_eq!(scanner.scan(&ram, Filter::Decreased), 1);
+    assert_eq!(scanner.results(), vec![0x80002000]);
+
+    let mut freezer = Freezer::new();
+
+    freezer.freeze(0x80002000, Width::Word, 1000);
+    freezer.apply(&mut ram);
+
+    assert_eq!(scanner.value(&ram, 0x80002000), 1000);
+}
@@ -0,0 +1,287 @@
+//! Symbol tables used to give names to addresses in the debugger,
+//! profiler and backtraces.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// A named address range
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    /// Address of the first byte
+    pub addr: u32,
+    /// Size in bytes, 0 if unknown
+    pub size: u32,
+    pub name: String,
+}
+
+/// Set of symbols sorted by address
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Add a symbol to the table. If a symbol already exists at the
+    /// same address it's replaced.
+    pub fn add(&mut self, addr: u32, size: u32, name: String) {
+        let symbol = Symbol {
+            addr: addr,
+            size: size,
+            name: name,
+        };
+
+        match self.symbols.binary_search_by_key(&addr, |s| s.addr) {
+            Ok(i) => self.symbols[i] = symbol,
+            Err(i) => self.symbols.insert(i, symbol),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<Symbol> {
+        self.symbols.iter()
+    }
+
+    /// Find the symbol containing `addr` and return it along with the
+    /// offset of `addr` within the symbol. If the symbol's size is
+    /// unknown we assume that it extends up to the next symbol.
+    pub fn lookup(&self, addr: u32) -> Option<(&Symbol, u32)> {
+        let i =
+            match self.symbols.binary_search_by_key(&addr, |s| s.addr) {
+                Ok(i) => i,
+                // `addr` is before the first symbol
+                Err(0) => return None,
+                Err(i) => i - 1,
+            };
+
+        let symbol = &self.symbols[i];
+        let offset = addr - symbol.addr;
+
+        if symbol.size != 0 && offset >= symbol.size {
+            None
+        } else {
+            Some((symbol, offset))
+        }
+    }
+
+    /// Find a symbol by name
+    pub fn by_name(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// Format `addr` as `symbol+offset` if it's covered by a symbol,
+    /// otherwise return the raw address
+    pub fn describe(&self, addr: u32) -> String {
+        match self.lookup(addr) {
+            Some((s, 0)) => s.name.clone(),
+            Some((s, off)) => format!("{}+0x{:x}", s.name, off),
+            None => format!("0x{:08x}", addr),
+        }
+    }
+
+    /// Parse a simple text symbol list: one symbol per line with an
+    /// hexadecimal address and a name, for instance the output of
+    /// `nm` ("80010000 T main") or no$psx text symbol files
+    /// ("80010000 main"). Lines that can't be parsed are ignored.
+    pub fn parse_map(&mut self, map: &str) -> usize {
+        let mut count = 0;
+
+        for line in map.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            if words.len() < 2 {
+                continue;
+            }
+
+            let addr =
+                match parse_hex(words[0]) {
+                    Some(a) => a,
+                    None => continue,
+                };
+
+            let name = words[words.len() - 1];
+
+            self.add(addr, 0, name.into());
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Parse a linker map generated by GNU ld. Only the symbol lines
+    /// ("0x80010000    main") are used, the section, object file and
+    /// assignment lines are ignored.
+    pub fn parse_gnu_map(&mut self, map: &str) -> usize {
+        let mut count = 0;
+
+        for line in map.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            if words.len() != 2 || !words[0].starts_with("0x") {
+                continue;
+            }
+
+            let addr =
+                match parse_hex(words[0]) {
+                    Some(a) => a,
+                    None => continue,
+                };
+
+            let name = words[1];
+
+            if !is_identifier(name) {
+                continue;
+            }
+
+            self.add(addr, 0, name.into());
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Load a symbol list file, see `parse_map`. Returns the number of
+    /// symbols loaded.
+    pub fn load_map(&mut self, path: &Path) -> io::Result<usize> {
+        let s = try!(read_file(path));
+
+        Ok(self.parse_map(&s))
+    }
+
+    /// Load a symbol file, the format is selected using the file
+    /// extension: GNU linker map for `.map` files, `parse_map` for
+    /// anything else (including no$psx `.sym` files). Returns the
+    /// number of symbols loaded.
+    pub fn load_file(&mut self, path: &Path) -> io::Result<usize> {
+        let s = try!(read_file(path));
+
+        let gnu_map =
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(e) => e.eq_ignore_ascii_case("map"),
+                None => false,
+            };
+
+        if gnu_map {
+            Ok(self.parse_gnu_map(&s))
+        } else {
+            Ok(self.parse_map(&s))
+        }
+    }
+
+    /// Convert a location to an address. The location can be a raw
+    /// hexadecimal address, a symbol name or a symbol name with an
+    /// offset ("main+0x10").
+    pub fn resolve(&self, location: &str) -> Option<u32> {
+        let location = location.trim();
+
+        let (name, offset) =
+            match location.find('+') {
+                Some(i) => {
+                    let offset = location[i + 1..].trim();
+
+                    match parse_hex(offset) {
+                        Some(o) => (location[..i].trim(), o),
+                        None => return None,
+                    }
+                }
+                None => (location, 0),
+            };
+
+        if let Some(s) = self.by_name(name) {
+            return Some(s.addr.wrapping_add(offset));
+        }
+
+        if offset == 0 {
+            parse_hex(name)
+        } else {
+            None
+        }
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut f = try!(File::open(path));
+
+    let mut s = String::new();
+
+    try!(f.read_to_string(&mut s));
+
+    Ok(s)
+}
+
+/// Return true if `name` looks like a C symbol name
+fn is_identifier(name: &str) -> bool {
+    let valid = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+
+    match name.chars().next() {
+        Some(c) if c.is_digit(10) => false,
+        Some(_) => name.chars().all(valid),
+        None => false,
+    }
+}
+
+/// Parse an hexadecimal number with an optional "0x" prefix
+pub fn parse_hex(s: &str) -> Option<u32> {
+    let s =
+        if s.starts_with("0x") || s.starts_with("0X") {
+            &s[2..]
+        } else {
+            s
+        };
+
+    u32::from_str_radix(s, 16).ok()
+}
+
+#[test]
+fn lookup() {
+    let mut table = SymbolTable::new();
+
+    let count = table.parse_map("80010000 T main\n\
+                                 0x80010100 foo\n\
+                                 garbage\n");
+
+    assert_eq!(count, 2);
+
+    table.add(0x80020000, 0x10, "sized".into());
+
+    assert_eq!(table.describe(0x80010000), "main");
+    assert_eq!(table.describe(0x80010004), "main+0x4");
+    assert_eq!(table.describe(0x80010104), "foo+0x4");
+    assert_eq!(table.describe(0x80020010), "0x80020010");
+    assert_eq!(table.describe(0x8000fffc), "0x8000fffc");
+    assert_eq!(table.by_name("foo").unwrap().addr, 0x80010100);
+
+    assert_eq!(table.resolve("main"), Some(0x80010000));
+    assert_eq!(table.resolve("foo + 0x8"), Some(0x80010108));
+    assert_eq!(table.resolve("80030000"), Some(0x80030000));
+    assert_eq!(table.resolve("bar"), None);
+}
+
+#[test]
+fn gnu_map() {
+    let mut table = SymbolTable::new();
+
+    let count = table.parse_gnu_map("\
+ .text          0x80010000      0x1a4 main.o
+                0x80010000                main
+                0x80010080                update_pad
+                0x80010100                . = ALIGN (0x4)
+ *(.text.*)
+                0x80010200                PROVIDE (_etext, .)
+");
+
+    assert_eq!(count, 2);
+    assert_eq!(table.describe(0x80010084), "update_pad+0x4");
+}
@@ -0,0 +1,75 @@
+//! Capture of the TTY output of the emulated software.
+//!
+//! Programs print through the BIOS `putchar` kernel functions (A(3Ch)
+//! and B(3Dh)), all the higher level functions (`puts`, `printf`...)
+//! end up calling them. We intercept the calls from the debugger's
+//! `pc_change` hook so this works with the real BIOS, without the
+//! debug UART patch.
+
+use cpu::Cpu;
+use memory::map::mask_region;
+
+pub struct TtyCapture {
+    /// Everything printed so far
+    output: String,
+    /// Offset of the first character in `output` not yet returned
+    /// by `take_lines`
+    consumed: usize,
+}
+
+impl TtyCapture {
+    pub fn new() -> TtyCapture {
+        TtyCapture {
+            output: String::new(),
+            consumed: 0,
+        }
+    }
+
+    /// Should be called from `Debugger::pc_change`
+    pub fn pc_change(&mut self, cpu: &Cpu) {
+        let pc = mask_region(cpu.pc());
+
+        // Function number is in T1, the character in A0
+        let function = cpu.regs()[9];
+        let c = cpu.regs()[4] as u8;
+
+        match (pc, function) {
+            (0xa0, 0x3c) | (0xb0, 0x3d) => self.putchar(c),
+            _ => (),
+        }
+    }
+
+    fn putchar(&mut self, c: u8) {
+        match c {
+            b'\r' => (),
+            _ => self.output.push(c as char),
+        }
+    }
+
+    /// Return the complete output
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Return the complete lines printed since the last call
+    pub fn take_lines(&mut self) -> Vec<String> {
+        let pending = &self.output[self.consumed..];
+
+        let end =
+            match pending.rfind('\n') {
+                Some(e) => e + 1,
+                None => return Vec::new(),
+            };
+
+        let lines = pending[..end].lines().map(|l| l.into()).collect();
+
+        self.consumed += end;
+
+        lines
+    }
+
+    pub fn clear(&mut self) {
+        self.output.clear();
+        self.consumed = 0;
+    }
+}
@@ -0,0 +1,109 @@
+//! Notifications sent by the emulator core to the frontend.
+//!
+//! The peripherals push an `Event` in the `SharedState` when
+//! something the user might want to know about happens. Frontends
+//! either poll them with `SharedState::poll_event` (typically once
+//! per frame) or register a callback with `SharedState::set_on_event`
+//! to receive them as soon as they're emitted. That way popups and
+//! activity indicators don't have to dig into the peripherals'
+//! internals.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Start of the vertical blanking period, with the frame number
+    VBlank(u32),
+    /// A savestate has been written at this location
+    SaveStateComplete(PathBuf),
+    /// The drive's lid has been opened, the game will probably ask
+    /// for a disc change
+    LidOpened,
+    /// The drive's lid has been closed
+    LidClosed,
+    /// The game wrote to the memory card in this port
+    MemoryCardWrite(usize),
+    /// The memory card in this port has been saved, it's safe to
+    /// quit
+    MemoryCardFlushed(usize),
+    /// The CPU took that many interrupts during the last frame. That
+    /// usually means an interrupt is never acknowledged because the
+    /// game waits for a feature we don't emulate properly.
+    IrqStorm(u32),
+    /// The game attempted to write to the BIOS ROM at this address,
+    /// the write has been ignored. Only the first write is reported.
+    BiosWrite(u32),
+}
+
+/// Events waiting to be polled by the frontend. If the frontend
+/// doesn't poll them the oldest ones are dropped.
+pub struct EventQueue {
+    events: VecDeque<Event>,
+}
+
+impl EventQueue {
+    pub fn new() -> EventQueue {
+        EventQueue {
+            events: VecDeque::with_capacity(EVENT_QUEUE_CAPACITY),
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        if self.events.len() >= EVENT_QUEUE_CAPACITY {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(event);
+    }
+
+    /// Return the oldest pending event, if any
+    pub fn pop(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl Encodable for EventQueue {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Frontend notifications, not part of the console state
+        s.emit_nil()
+    }
+}
+
+impl Decodable for EventQueue {
+    fn decode<D: Decoder>(d: &mut D) -> Result<EventQueue, D::Error> {
+        try!(d.read_nil());
+
+        Ok(EventQueue::new())
+    }
+}
+
+/// Maximum number of events kept in the queue
+pub const EVENT_QUEUE_CAPACITY: usize = 64;
+
+/// Number of interrupts per frame above which we report an
+/// `IrqStorm`. Games normally take a few hundred at most.
+pub const IRQ_STORM_THRESHOLD: u32 = 10_000;
+
+#[test]
+fn queue_overflow() {
+    let mut queue = EventQueue::new();
+
+    for frame in 0..(EVENT_QUEUE_CAPACITY as u32 + 2) {
+        queue.push(Event::VBlank(frame));
+    }
+
+    // The two oldest events have been dropped
+    assert_eq!(queue.pop(), Some(Event::VBlank(2)));
+
+    while let Some(_) = queue.pop() {
+    }
+
+    assert!(queue.is_empty());
+}
@@ -0,0 +1,143 @@
+//! CLUT (palette) cache emulation.
+//!
+//! When drawing a 4 or 8bpp textured primitive the GPU loads the
+//! palette into an internal cache and only fetches it again from the
+//! VRAM when a primitive uses a different CLUT location or texture
+//! depth, or when the cache is flushed with GP0(0x01). Writing to the
+//! palette in VRAM without flushing the cache means that the following
+//! primitives keep using the stale palette, some games rely on that
+//! for special effects.
+//!
+//! Like the `TextureCache` this works on the VRAM contents held by
+//! the renderer: the renderer looks up the palette of each paletted
+//! primitive through `ClutCache::palette` instead of reading the VRAM
+//! directly, and calls `invalidate` from `Renderer::clear_cache`.
+
+use super::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+use super::renderer::{PrimitiveAttributes, TextureDepth};
+
+pub struct ClutCache {
+    /// When false the palette is fetched from VRAM every time, which
+    /// is faster when nothing relies on the cache behaviour
+    enabled: bool,
+    /// Location and depth of the palette currently in the cache
+    key: Option<([u16; 2], TextureDepth)>,
+    /// Cached palette entries, only the first 16 are used in 4bpp
+    /// mode
+    entries: [u16; 256],
+}
+
+impl ClutCache {
+    pub fn new() -> ClutCache {
+        ClutCache {
+            enabled: false,
+            key: None,
+            entries: [0; 256],
+        }
+    }
+
+    /// Enable or disable the cache emulation
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.key = None;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Flush the cache, the next paletted primitive reloads it from
+    /// VRAM
+    pub fn invalidate(&mut self) {
+        self.key = None;
+    }
+
+    /// Return the palette used by a primitive with `attributes`,
+    /// `None` if the primitive doesn't use a palette. `vram` is the
+    /// current content of the VRAM, line by line.
+    pub fn palette(&mut self,
+                   vram: &[u16],
+                   attributes: &PrimitiveAttributes) -> Option<&[u16]> {
+        let len =
+            match attributes.texture_depth {
+                TextureDepth::T4Bpp => 16,
+                TextureDepth::T8Bpp => 256,
+                TextureDepth::T16Bpp => return None,
+            };
+
+        let key = (attributes.clut, attributes.texture_depth);
+
+        if !self.enabled || self.key != Some(key) {
+            self.load(vram, attributes.clut, len);
+            self.key = Some(key);
+        }
+
+        Some(&self.entries[..len])
+    }
+
+    fn load(&mut self, vram: &[u16], clut: [u16; 2], len: usize) {
+        let x = clut[0] as usize;
+        let y = clut[1] as usize;
+
+        let line = (y % VRAM_HEIGHT as usize) * VRAM_WIDTH_PIXELS as usize;
+
+        for (i, e) in self.entries[..len].iter_mut().enumerate() {
+            // The palette wraps around horizontally
+            let x = (x + i) % VRAM_WIDTH_PIXELS as usize;
+
+            *e = vram[line + x];
+        }
+    }
+}
+
+#[test]
+fn stale_palette() {
+    use super::renderer::{BlendMode, SemiTransparencyMode};
+
+    let size = VRAM_WIDTH_PIXELS as usize * VRAM_HEIGHT as usize;
+    let mut vram = vec![0u16; size];
+
+    let clut_offset = 480 * VRAM_WIDTH_PIXELS as usize;
+
+    vram[clut_offset] = 0x1234;
+
+    let mut attributes = PrimitiveAttributes {
+        semi_transparent: false,
+        semi_transparency_mode: SemiTransparencyMode::Average,
+        blend_mode: BlendMode::Raw,
+        texture_page: [960, 256],
+        texture_depth: TextureDepth::T4Bpp,
+        clut: [0, 480],
+        dither: false,
+        true_color: false,
+        flip_x: false,
+        flip_y: false,
+    };
+
+    let mut cache = ClutCache::new();
+
+    cache.set_enabled(true);
+
+    assert_eq!(cache.palette(&vram, &attributes).unwrap()[0], 0x1234);
+
+    // The cache isn't updated when the VRAM changes
+    vram[clut_offset] = 0x5678;
+    assert_eq!(cache.palette(&vram, &attributes).unwrap()[0], 0x1234);
+
+    // Changing the depth reloads the palette
+    attributes.texture_depth = TextureDepth::T8Bpp;
+    assert_eq!(cache.palette(&vram, &attributes).unwrap().len(), 256);
+    assert_eq!(cache.palette(&vram, &attributes).unwrap()[0], 0x5678);
+
+    vram[clut_offset] = 0x9abc;
+    cache.invalidate();
+    assert_eq!(cache.palette(&vram, &attributes).unwrap()[0], 0x9abc);
+
+    // Without the emulation the VRAM is always used
+    cache.set_enabled(false);
+    vram[clut_offset] = 0x4321;
+    assert_eq!(cache.palette(&vram, &attributes).unwrap()[0], 0x4321);
+
+    attributes.texture_depth = TextureDepth::T16Bpp;
+    assert!(cache.palette(&vram, &attributes).is_none());
+}
@@ -0,0 +1,302 @@
+//! Frame dumping and video recording.
+//!
+//! The emulator core doesn't have access to the rendered frames (the
+//! renderer lives in the frontend) so these helpers work on RGB888
+//! buffers provided by the frontend. Frames can be saved as PNG
+//! images (for screenshots and regression tests) or streamed in the
+//! YUV4MPEG2 format to an external encoder like ffmpeg.
+
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::path::Path;
+#[cfg(not(target_arch = "wasm32"))]
+use std::process::{Command, Child, ChildStdin, Stdio};
+
+/// Save an RGB888 frame as a PNG image. The image data is not
+/// compressed to avoid pulling a zlib implementation, the files are
+/// big but any PNG decoder can read them.
+pub fn write_png(path: &Path,
+                 width: u32,
+                 height: u32,
+                 rgb: &[u8]) -> io::Result<()> {
+    check_frame_size(width, height, rgb);
+
+    let mut w = BufWriter::new(try!(File::create(path)));
+
+    try!(w.write_all(b"\x89PNG\r\n\x1a\n"));
+
+    let mut ihdr = Vec::with_capacity(13);
+
+    push_u32_be(&mut ihdr, width);
+    push_u32_be(&mut ihdr, height);
+    // 8 bits per channel, RGB, deflate compression, default filters,
+    // no interlacing
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    try!(write_png_chunk(&mut w, b"IHDR", &ihdr));
+
+    // Each row starts with the filter type (0: None)
+    let row_len = (width * 3) as usize;
+    let mut raw = Vec::with_capacity((row_len + 1) * height as usize);
+
+    for row in rgb.chunks(row_len) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    try!(write_png_chunk(&mut w, b"IDAT", &zlib_stored(&raw)));
+    try!(write_png_chunk(&mut w, b"IEND", &[]));
+
+    w.flush()
+}
+
+fn write_png_chunk<W: Write>(w: &mut W,
+                             kind: &[u8; 4],
+                             data: &[u8]) -> io::Result<()> {
+    let mut len = Vec::with_capacity(4);
+
+    push_u32_be(&mut len, data.len() as u32);
+
+    try!(w.write_all(&len));
+    try!(w.write_all(kind));
+    try!(w.write_all(data));
+
+    let crc = crc32(crc32(0, kind), data);
+
+    let mut crc_be = Vec::with_capacity(4);
+
+    push_u32_be(&mut crc_be, crc);
+
+    w.write_all(&crc_be)
+}
+
+/// Wrap `data` in a zlib stream made of uncompressed "stored" deflate
+/// blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let nblocks = data.len() / 0xffff + 1;
+    let mut out = Vec::with_capacity(data.len() + nblocks * 5 + 6);
+
+    // CMF/FLG: deflate with a 32K window, no dictionary, fastest
+    // compression
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(0xffff).peekable();
+
+    if blocks.peek().is_none() {
+        // Empty final block
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8,
+                                !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+
+    push_u32_be(&mut out, adler32(data));
+
+    out
+}
+
+/// Update the CRC-32 (as used by PNG and zlib) `crc` with `data`
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+
+    for &b in data {
+        crc ^= b as u32;
+
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    for &v in data {
+        a = (a + v as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+fn push_u32_be(v: &mut Vec<u8>, w: u32) {
+    v.extend_from_slice(&[(w >> 24) as u8, (w >> 16) as u8,
+                          (w >> 8) as u8, w as u8]);
+}
+
+fn check_frame_size(width: u32, height: u32, rgb: &[u8]) {
+    let expected = width as usize * height as usize * 3;
+
+    if rgb.len() != expected {
+        panic!("Bad frame size: expected {}x{}x3={} bytes, got {}",
+               width, height, expected, rgb.len());
+    }
+}
+
+/// YUV4MPEG2 stream writer. The frames are converted to 4:4:4 YCbCr
+/// (BT.601) to avoid losing any chroma resolution.
+pub struct Y4mWriter<W: Write> {
+    w: W,
+    width: u32,
+    height: u32,
+    /// Conversion buffer for the three planes
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Create a new stream of `width`x`height` frames at
+    /// `fps_num`/`fps_den` frames per second. The PlayStation's NTSC
+    /// refresh rate is close to 60000/1003 and PAL is close to
+    /// 50000/1005.
+    pub fn new(mut w: W,
+               width: u32,
+               height: u32,
+               fps_num: u32,
+               fps_den: u32) -> io::Result<Y4mWriter<W>> {
+        try!(write!(w, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444\n",
+                    width, height, fps_num, fps_den));
+
+        Ok(Y4mWriter {
+            w: w,
+            width: width,
+            height: height,
+            planes: Vec::new(),
+        })
+    }
+
+    /// Append an RGB888 frame to the stream. The frame must have the
+    /// dimensions given when the stream was created.
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        check_frame_size(self.width, self.height, rgb);
+
+        let npixels = (self.width * self.height) as usize;
+
+        self.planes.clear();
+        self.planes.resize(npixels * 3, 0);
+
+        {
+            let (y, uv) = self.planes.split_at_mut(npixels);
+            let (u, v) = uv.split_at_mut(npixels);
+
+            for (i, p) in rgb.chunks(3).enumerate() {
+                let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+
+                let luma = (66 * r + 129 * g + 25 * b + 128) >> 8;
+                let cb = (-38 * r - 74 * g + 112 * b + 128) >> 8;
+                let cr = (112 * r - 94 * g - 18 * b + 128) >> 8;
+
+                y[i] = (16 + luma) as u8;
+                u[i] = (128 + cb) as u8;
+                v[i] = (128 + cr) as u8;
+            }
+        }
+
+        try!(self.w.write_all(b"FRAME\n"));
+
+        self.w.write_all(&self.planes)
+    }
+
+    /// Return the underlying writer
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// Video recording through an external ffmpeg process. The frames are
+/// piped as a YUV4MPEG2 stream, audio can be dumped separately using
+/// `audio::wav` and muxed afterwards. Not available on wasm32 since
+/// there are no processes to spawn.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FfmpegRecorder {
+    child: Child,
+    stream: Y4mWriter<ChildStdin>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FfmpegRecorder {
+    /// Spawn `ffmpeg` to encode the frames to `output`. The codec is
+    /// chosen by ffmpeg from the file extension.
+    pub fn spawn(output: &Path,
+                 width: u32,
+                 height: u32,
+                 fps_num: u32,
+                 fps_den: u32) -> io::Result<FfmpegRecorder> {
+        let mut child = try!(Command::new("ffmpeg")
+                             .arg("-y")
+                             .arg("-loglevel").arg("error")
+                             .arg("-f").arg("yuv4mpegpipe")
+                             .arg("-i").arg("-")
+                             .arg(output)
+                             .stdin(Stdio::piped())
+                             .spawn());
+
+        let stdin =
+            match child.stdin.take() {
+                Some(s) => s,
+                None => return Err(io::Error::new(io::ErrorKind::Other,
+                                                  "No ffmpeg stdin")),
+            };
+
+        let stream = try!(Y4mWriter::new(stdin,
+                                         width, height,
+                                         fps_num, fps_den));
+
+        Ok(FfmpegRecorder {
+            child: child,
+            stream: stream,
+        })
+    }
+
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        self.stream.write_frame(rgb)
+    }
+
+    /// Close the stream and wait for ffmpeg to finish encoding
+    pub fn finish(self) -> io::Result<()> {
+        let mut child = self.child;
+
+        // Closing stdin signals the end of the stream
+        drop(self.stream.into_inner());
+
+        let status = try!(child.wait());
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::Other,
+                               format!("ffmpeg failed: {}", status)))
+        }
+    }
+}
+
+#[test]
+fn checksums() {
+    assert_eq!(crc32(0, b"123456789"), 0xcbf43926);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+}
+
+#[test]
+fn stored_blocks() {
+    let data = vec![0x55u8; 0x20000];
+
+    let z = zlib_stored(&data);
+
+    // Header + 3 blocks + checksum
+    assert_eq!(z.len(), 2 + 3 * 5 + data.len() + 4);
+    // Only the last block has BFINAL set
+    assert_eq!(z[2], 0);
+    assert_eq!(z[2 + 5 + 0xffff], 0);
+    assert_eq!(z[2 + 2 * (5 + 0xffff)], 1);
+}
@@ -1,3 +1,5 @@
+use std::cmp;
+
 use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
 use memory::Addressable;
@@ -5,11 +7,23 @@ use memory::timers::Timers;
 use shared::SharedState;
 use interrupt::Interrupt;
 use timekeeper::{Peripheral, Cycles, FracCycles};
+use logging;
+use pgxp::{Pgxp, PreciseVertex};
+use stats::FrameStats;
 
 use self::renderer::{Renderer, Vertex, PrimitiveAttributes};
+use self::renderer::{PrimitiveBuffer, PrimitiveKind};
+use self::renderer::{InterlacedField, DisplayArea, DisplayLine, read_back};
 use self::renderer::{BlendMode, SemiTransparencyMode, TextureDepth};
+use self::timing::Blanking;
 
 pub mod renderer;
+pub mod clut;
+pub mod dump;
+pub mod textures;
+pub mod timing;
+pub mod render_queue;
+pub mod software;
 
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct Gpu {
@@ -46,8 +60,13 @@ pub struct Gpu {
     /// Currently displayed field. For progressive output this is
     /// always Top.
     field: Field,
-    /// When true all textures are disabled
-    texture_disable: bool,
+    /// Set when a new field starts in 480 line interlaced mode, the
+    /// renderer is notified at the next `update_field`
+    field_changed: bool,
+    /// Set by GP1(0x09): when true the "texture disable" bit of the
+    /// draw mode can be used to draw textured primitives without
+    /// their texture
+    allow_texture_disable: bool,
     /// Video output horizontal resolution
     hres: HorizontalRes,
     /// Video output vertical resolution
@@ -105,9 +124,113 @@ pub struct Gpu {
     polyline_prev: ([i16; 2], [u8; 3]),
     /// Image buffer for texture uploads
     load_buffer: ImageBuffer,
+    /// Buffer containing the image being read back through GPUREAD
+    store_buffer: ImageBuffer,
+    /// Number of words remaining to be read from `store_buffer`
+    store_words_remaining: u32,
+    /// Number of GPU clock ticks before the GPU is done processing
+    /// the commands it has received so far
+    busy_ticks: u32,
+    /// Drawing statistics for the current frame
+    stats: FrameStats,
+    /// If false the GPU completes every command instantly, faster but
+    /// breaks games relying on the drawing time
+    draw_timing: bool,
+    /// If true the GPU is synchronized at the start of every line of
+    /// the active display and the display area is latched for each
+    /// line. Otherwise the last display area set during the frame is
+    /// used for the whole frame.
+    scanline_timing: bool,
+    /// Display areas latched during the current frame when
+    /// `scanline_timing` is enabled
+    display_latches: DisplayLatches,
+    /// Keep the full 24bit color precision when drawing instead of
+    /// dithering and truncating to 15bits. This is an enhancement,
+    /// the real console can't do that.
+    true_color: bool,
+    /// Set when `true_color` changed, the renderer is notified before
+    /// the next GP0 command
+    true_color_changed: bool,
+    /// Emulate the CLUT cache in the renderer
+    clut_cache: bool,
+    /// Set when `clut_cache` changed, the renderer is notified before
+    /// the next GP0 command
+    clut_cache_changed: bool,
+    /// Precise value associated with the next GP0 word, if any
+    pgxp_pending: Option<PreciseVertex>,
+    /// Precise values received for the current GP0 command
+    pgxp_vertices: Vec<PreciseVertex>,
+    /// Beam position where a lightpen interrupt is triggered every
+    /// frame, set by lightguns like the Konami Justifier
+    lightpen: Option<BeamPosition>,
+    /// Primitives not yet sent to the renderer
+    primitives: PrimitiveBuffer,
 }
 
 impl Gpu {
+    /// Return the video standard this GPU has been configured for
+    pub fn video_clock(&self) -> VideoClock {
+        self.standard
+    }
+
+    /// Enable or disable the emulation of the time taken by the GPU
+    /// to process the draw commands
+    pub fn set_draw_timing(&mut self, enabled: bool) {
+        self.draw_timing = enabled;
+
+        if !enabled {
+            // Flush whatever is in the pipeline
+            self.busy_ticks = 0;
+        }
+    }
+
+    pub fn draw_timing(&self) -> bool {
+        self.draw_timing
+    }
+
+    /// Enable or disable the per-line display timing, needed by the
+    /// games changing the display registers mid-frame for raster
+    /// effects. Costs one GPU synchronization per line.
+    pub fn set_scanline_timing(&mut self, enabled: bool) {
+        self.scanline_timing = enabled;
+
+        if !enabled {
+            self.display_latches = DisplayLatches::new();
+        }
+    }
+
+    pub fn scanline_timing(&self) -> bool {
+        self.scanline_timing
+    }
+
+    /// Enable or disable the 24bit color output. When enabled the
+    /// renderer is told not to dither or truncate the colors of the
+    /// primitives.
+    pub fn set_true_color(&mut self, enabled: bool) {
+        if enabled != self.true_color {
+            self.true_color = enabled;
+            self.true_color_changed = true;
+        }
+    }
+
+    pub fn true_color(&self) -> bool {
+        self.true_color
+    }
+
+    /// Enable or disable the CLUT cache emulation. It's only needed
+    /// by the few games that modify a palette without flushing the
+    /// cache.
+    pub fn set_clut_cache(&mut self, enabled: bool) {
+        if enabled != self.clut_cache {
+            self.clut_cache = enabled;
+            self.clut_cache_changed = true;
+        }
+    }
+
+    pub fn clut_cache(&self) -> bool {
+        self.clut_cache
+    }
+
     pub fn new(standard: VideoClock) -> Gpu {
         let dummy_gp0 =
             Gp0Attributes::new(Gpu::gp0_nop, false, BlendMode::None, false);
@@ -129,7 +252,8 @@ impl Gpu {
             drawing_area_bottom: 0,
             drawing_offset: (0, 0),
             field: Field::Top,
-            texture_disable: false,
+            field_changed: false,
+            allow_texture_disable: false,
             hres: HorizontalRes::from_fields(0, 0),
             vres: VerticalRes::Y240Lines,
             vmode: VMode::Ntsc,
@@ -156,6 +280,21 @@ impl Gpu {
             read_word: 0,
             polyline_prev: ([0; 2], [0; 3]),
             load_buffer: ImageBuffer::new(),
+            store_buffer: ImageBuffer::new(),
+            store_words_remaining: 0,
+            busy_ticks: 0,
+            stats: FrameStats::new(),
+            draw_timing: true,
+            scanline_timing: false,
+            display_latches: DisplayLatches::new(),
+            true_color: false,
+            true_color_changed: false,
+            clut_cache: false,
+            clut_cache_changed: false,
+            pgxp_pending: None,
+            pgxp_vertices: Vec::new(),
+            lightpen: None,
+            primitives: PrimitiveBuffer::new(),
         }
     }
 
@@ -172,16 +311,20 @@ impl Gpu {
         }
     }
 
+    /// Return the GPU clock frequency in Hz
+    fn gpu_clock(&self) -> f32 {
+        match self.standard {
+            VideoClock::Ntsc => 53_690_000.,
+            VideoClock::Pal  => 53_222_000.,
+        }
+    }
+
     /// Return the GPU to CPU clock ratio. The value is multiplied by
     /// CLOCK_RATIO_FRAC to get a precise fixed point value.
     fn gpu_to_cpu_clock_ratio(&self) -> FracCycles {
         // First we convert the delta into GPU clock periods.
         // GPU clock in Hz
-        let gpu_clock =
-            match self.standard {
-                VideoClock::Ntsc => 53_690_000.,
-                VideoClock::Pal  => 53_222_000.,
-            };
+        let gpu_clock = self.gpu_clock();
 
         // CPU clock in Hz
         let cpu_clock = ::cpu::CPU_FREQ_HZ as f32;
@@ -232,6 +375,53 @@ impl Gpu {
         phase.multiply(self.gpu_to_cpu_clock_ratio())
     }
 
+    /// Return the current state of the HBLANK signal. The GPU must be
+    /// synchronized.
+    pub fn hblank(&self) -> Blanking {
+        let (ticks_per_line, _) = self.vmode_timings();
+
+        let (active_start, active_end) =
+            match self.vmode {
+                VMode::Ntsc => (timing::NTSC_HBLANK_END,
+                                timing::NTSC_HBLANK_START),
+                VMode::Pal => (timing::PAL_HBLANK_END,
+                               timing::PAL_HBLANK_START),
+            };
+
+        let position =
+            FracCycles::from_cycles(self.display_line_tick as Cycles);
+
+        Blanking::new(ticks_per_line as Cycles,
+                      active_start,
+                      active_end,
+                      position.add(self.gpu_clock_frac()),
+                      self.gpu_to_cpu_clock_ratio())
+    }
+
+    /// Return the current state of the VBLANK signal. The GPU must be
+    /// synchronized.
+    pub fn vblank(&self) -> Blanking {
+        let (ticks_per_line, lines_per_frame) = self.vmode_timings();
+
+        let ticks_per_line = ticks_per_line as Cycles;
+
+        let position =
+            FracCycles::from_cycles(self.display_line as Cycles *
+                                    ticks_per_line +
+                                    self.display_line_tick as Cycles);
+
+        Blanking::new(lines_per_frame as Cycles * ticks_per_line,
+                      self.display_line_start as Cycles * ticks_per_line,
+                      self.display_line_end as Cycles * ticks_per_line,
+                      position.add(self.gpu_clock_frac()),
+                      self.gpu_to_cpu_clock_ratio())
+    }
+
+    /// Fractional part of the current GPU clock tick
+    fn gpu_clock_frac(&self) -> FracCycles {
+        FracCycles::from_fp(self.gpu_clock_phase as Cycles)
+    }
+
     /// Update the GPU state to its current status
     pub fn sync(&mut self,
                 shared: &mut SharedState) {
@@ -249,6 +439,20 @@ impl Gpu {
         // Conwert delta back to integer
         let delta = delta >> 16;
 
+        if let Some(target) = self.lightpen {
+            if delta >= self.ticks_until(target) {
+                shared.irq_state_mut().assert(Interrupt::LightPen);
+            }
+        }
+
+        // Advance the command processing
+        self.busy_ticks =
+            if delta >= self.busy_ticks as Cycles {
+                0
+            } else {
+                self.busy_ticks - delta as u32
+            };
+
         // Compute the current line and position within the line.
 
         let (ticks_per_line, lines_per_frame) = self.vmode_timings();
@@ -256,13 +460,15 @@ impl Gpu {
         let ticks_per_line = ticks_per_line as Cycles;
         let lines_per_frame = lines_per_frame as Cycles;
 
+        let prev_line = self.display_line;
+
         let line_tick = self.display_line_tick as Cycles + delta;
         let line      = self.display_line as Cycles +
                         line_tick / ticks_per_line;
 
         self.display_line_tick = (line_tick % ticks_per_line) as u16;
 
-        if line > lines_per_frame {
+        if line >= lines_per_frame {
             // New frame
 
             if self.interlaced {
@@ -273,7 +479,9 @@ impl Gpu {
                     match (nframes + self.field as Cycles) & 1 != 0 {
                         true  => Field::Top,
                         false => Field::Bottom,
-                    }
+                    };
+
+                self.field_changed = self.interlaced_480();
             }
 
             self.display_line = (line % lines_per_frame) as u16;
@@ -283,14 +491,31 @@ impl Gpu {
 
         let vblank_interrupt = self.in_vblank();
 
+        if self.scanline_timing && self.display_line != prev_line &&
+            !vblank_interrupt {
+            // We're at the start of a new line of the active display
+            let line = self.display_line - self.display_line_start;
+            let area = self.display_area();
+
+            self.display_latches.latch(line, area);
+        }
+
         if !self.vblank_interrupt && vblank_interrupt {
             // Rising edge of the vblank interrupt
+            if self.scanline_timing {
+                self.display_latches.end_frame();
+            }
+
             shared.irq_state_mut().assert(Interrupt::VBlank);
+            shared.vblank();
         }
 
         if self.vblank_interrupt && !vblank_interrupt {
             // End of vertical blanking, we're starting a new frame
-            shared.counters_mut().frame.increment();
+            shared.stats_mut().merge(&self.stats);
+            self.stats = FrameStats::new();
+
+            shared.end_frame();
         }
 
         self.vblank_interrupt = vblank_interrupt;
@@ -335,11 +560,17 @@ impl Gpu {
             // blanking for the current rame
 
             delta += (display_line_start - 1 - cur_line) * ticks_per_line;
-        } else {
+        } else if !self.scanline_timing {
             // We're in the active video, we want to synchronize at
             // the beginning of the vertical blanking period
             delta += (display_line_end - 1 - cur_line) * ticks_per_line;
         }
+        // Otherwise we synchronize at the start of the next line to
+        // latch the display registers
+
+        if let Some(target) = self.lightpen {
+            delta = cmp::min(delta, self.ticks_until(target));
+        }
 
         // Convert delta in CPU clock periods.
         delta <<= FracCycles::frac_bits();
@@ -354,6 +585,67 @@ impl Gpu {
         shared.tk().set_next_sync_delta(Peripheral::Gpu, delta);
     }
 
+    /// Return the number of GPU clock ticks until the beam reaches
+    /// `target`. If we're exactly at `target` we return the duration
+    /// of a full frame.
+    fn ticks_until(&self, target: BeamPosition) -> Cycles {
+        let (ticks_per_line, lines_per_frame) = self.vmode_timings();
+
+        let ticks_per_line = ticks_per_line as Cycles;
+        let frame = ticks_per_line * lines_per_frame as Cycles;
+
+        let cur = self.display_line as Cycles * ticks_per_line +
+                  self.display_line_tick as Cycles;
+        let target = target.line as Cycles * ticks_per_line +
+                     cmp::min(target.tick as Cycles, ticks_per_line - 1);
+
+        // `cur` can be slightly past the end of the frame
+        match (target + 2 * frame - cur) % frame {
+            0 => frame,
+            n => n,
+        }
+    }
+
+    /// Return the beam position when it reaches the point at `x`, `y`
+    /// of the displayed picture (from 0.0 for the top-left corner to
+    /// 1.0 for the bottom-right). Used to emulate lightguns, returns
+    /// `None` if the point is off-screen.
+    pub fn beam_position(&self, x: f32, y: f32) -> Option<BeamPosition> {
+        if x < 0. || x >= 1. || y < 0. || y >= 1. || self.display_disabled {
+            return None;
+        }
+
+        let hstart = self.display_horiz_start as f32;
+        let hend = self.display_horiz_end as f32;
+        let lstart = self.display_line_start as f32;
+        let lend = self.display_line_end as f32;
+
+        if hend <= hstart || lend <= lstart {
+            return None;
+        }
+
+        let tick = hstart + x * (hend - hstart);
+        let line = lstart + y * (lend - lstart);
+
+        Some(BeamPosition {
+            line: line as u16,
+            tick: tick as u16,
+            x_8mhz: (tick * 8_000_000. / self.gpu_clock()) as u16,
+        })
+    }
+
+    /// Trigger a lightpen interrupt every frame when the beam reaches
+    /// `target`, or disable the interrupt if it's `None`
+    pub fn set_lightpen(&mut self,
+                        shared: &mut SharedState,
+                        target: Option<BeamPosition>) {
+        if self.lightpen != target {
+            self.sync(shared);
+            self.lightpen = target;
+            self.predict_next_sync(shared);
+        }
+    }
+
     pub fn display_vram_start(&self) -> (u16, u16) {
         (self.display_vram_x_start, self.display_vram_y_start)
     }
@@ -364,17 +656,67 @@ impl Gpu {
         self.display_line >= self.display_line_end
     }
 
-    /// Return the index of the currently displayed VRAM line
-    fn displayed_vram_line(&self) -> u16 {
-        let offset =
-            match self.interlaced {
-                true  => self.display_line * 2 + self.field as u16,
-                false => self.display_line,
+    /// Return true if the output is 480 line interlaced video. In
+    /// 240 line interlaced mode both fields display the same lines.
+    fn interlaced_480(&self) -> bool {
+        match self.vres {
+            VerticalRes::Y480Lines => self.interlaced,
+            VerticalRes::Y240Lines => false,
+        }
+    }
+
+    /// Return the field currently displayed, `None` if the output
+    /// isn't 480 line interlaced video
+    pub fn interlaced_field(&self) -> Option<InterlacedField> {
+        if !self.interlaced_480() {
+            return None;
+        }
+
+        let field =
+            match self.field {
+                Field::Top => InterlacedField::Odd,
+                Field::Bottom => InterlacedField::Even,
             };
 
-        // The VRAM "wraps around" so we in case of an overflow we
-        // simply truncate to 9bits
-        (self.display_vram_y_start + offset) & 0x1ff
+        Some(field)
+    }
+
+    /// Notify the renderer if a new field started since the last
+    /// call
+    pub fn update_field(&mut self, renderer: &mut Renderer) {
+        if !self.field_changed {
+            return;
+        }
+
+        self.field_changed = false;
+
+        self.flush(renderer);
+
+        if let Some(field) = self.interlaced_field() {
+            renderer.set_field(field);
+        }
+    }
+
+    /// Send the display areas latched during the last frame to the
+    /// renderer if they changed mid-frame
+    pub fn update_display_lines(&mut self, renderer: &mut Renderer) {
+        if let Some(lines) = self.display_latches.take_frame() {
+            self.flush(renderer);
+            renderer.set_display_lines(&lines);
+        }
+    }
+
+    /// Return the index of the currently displayed VRAM line
+    fn displayed_vram_line(&self) -> u16 {
+        let line = self.display_line.wrapping_sub(self.display_line_start);
+
+        match self.interlaced_field() {
+            Some(field) =>
+                field.vram_line(self.display_vram_y_start, line),
+            // The VRAM "wraps around" so we in case of an overflow we
+            // simply truncate to 9bits
+            None => (self.display_vram_y_start + line) & 0x1ff,
+        }
     }
 
     pub fn load<T: Addressable>(&mut self,
@@ -419,9 +761,59 @@ impl Gpu {
 
     /// Dispatch to the current GP0 handler method
     pub fn gp0(&mut self, renderer: &mut Renderer, val: u32) {
+        if self.clut_cache_changed {
+            self.flush(renderer);
+            self.clut_cache_changed = false;
+            renderer.set_clut_cache(self.clut_cache);
+        }
+
+        if self.true_color_changed {
+            self.flush(renderer);
+            self.true_color_changed = false;
+            renderer.set_true_color(self.true_color);
+        }
+
+        if let Some(p) = self.pgxp_pending.take() {
+            // Polylines can be arbitrarily long, only keep the most
+            // recent values
+            if self.pgxp_vertices.len() >= PGXP_MAX_VERTICES {
+                self.pgxp_vertices.remove(0);
+            }
+
+            self.pgxp_vertices.push(p);
+        }
+
         (self.gp0_handler)(self, renderer, val);
     }
 
+    /// Must be called before `gp0` with the `addr` the GP0 word `val`
+    /// is read from in order to retrieve its precise value (if PGXP
+    /// is enabled)
+    pub fn pgxp_prepare(&mut self, pgxp: &Pgxp, addr: u32, val: u32) {
+        if pgxp.enabled() {
+            self.pgxp_pending = pgxp.load(addr, val);
+        }
+    }
+
+    /// Fill the precise coordinates of `vertices` using the values
+    /// received alongside the current GP0 command
+    fn pgxp_apply(&self, vertices: &mut [Vertex]) {
+        if self.pgxp_vertices.is_empty() {
+            return;
+        }
+
+        for v in vertices.iter_mut() {
+            // Look for the most recent match
+            let precise =
+                self.pgxp_vertices.iter().rev()
+                .find(|p| gp0_position(p.value) == v.position);
+
+            if let Some(p) = precise {
+                v.precise = Some([p.x, p.y, p.z]);
+            }
+        }
+    }
+
     /// Retrieve value of the status register
     fn status(&self) -> u32 {
         let mut r = 0u32;
@@ -433,7 +825,9 @@ impl Gpu {
 
         r |= (self.force_set_mask_bit as u32) << 11;
         r |= (self.preserve_masked_pixels as u32) << 12;
-        r |= (self.field as u32) << 13;
+        // Bit 13 is always set when the output is not interlaced
+        let field = !self.interlaced || self.field == Field::Top;
+        r |= (field as u32) << 13;
         // Bit 14: not supported
         r |= self.hres.into_status();
         r |= (self.vres as u32) << 19;
@@ -443,13 +837,18 @@ impl Gpu {
         r |= (self.display_disabled as u32) << 23;
         r |= (self.gp0_interrupt as u32) << 24;
 
-        // For now we pretend that the GPU is always ready:
+        // We don't emulate the FIFO itself, the GPU simply reports
+        // not being ready while it's processing the previous
+        // commands.
+        let idle = self.busy_ticks == 0;
+
         // Ready to receive command
-        r |= 1 << 26;
-        // Ready to send VRAM to CPU
-        r |= 1 << 27;
+        r |= (idle as u32) << 26;
+        // Ready to send VRAM to CPU, only set while an image store is
+        // in progress
+        r |= ((self.store_words_remaining > 0) as u32) << 27;
         // Ready to receive DMA block
-        r |= 1 << 28;
+        r |= (idle as u32) << 28;
 
         r |= (self.dma_direction as u32) << 29;
 
@@ -467,8 +866,8 @@ impl Gpu {
             match self.dma_direction {
                 // Always 0
                 DmaDirection::Off => 0,
-                // Should be 0 if FIFO is full, 1 otherwise
-                DmaDirection::Fifo => 1,
+                // 0 if FIFO is full, 1 otherwise
+                DmaDirection::Fifo => (!self.fifo_full()) as u32,
                 // Should be the same as status bit 28
                 DmaDirection::CpuToGp0 => (r >> 28) & 1,
                 // Should be the same as status bit 27
@@ -481,9 +880,17 @@ impl Gpu {
     }
 
     /// Retrieve value of the "read" register
-    fn read(&self) -> u32 {
-        debug!("GPUREAD");
-        // XXX framebuffer read not supported
+    fn read(&mut self) -> u32 {
+        if self.store_words_remaining > 0 {
+            // Image store in progress
+            self.read_word = self.store_buffer.pop_gpuread_word();
+            self.store_words_remaining -= 1;
+
+            if self.store_words_remaining == 0 {
+                self.store_buffer.clear();
+            }
+        }
+
         self.read_word
     }
 
@@ -494,6 +901,7 @@ impl Gpu {
         self.gp0_words_remaining = len;
         self.gp0_attributes = attributes;
         self.gp0_command.clear();
+        self.pgxp_vertices.clear();
 
         *self.gp0_handler = Gpu::gp0_handle_parameter;
 
@@ -513,13 +921,81 @@ impl Gpu {
             // drawing the first triangle before the last vertex is
             // received by the GPU
 
+            // Only the draw commands are queued, everything else
+            // must take place after the previous primitives are drawn
+            match self.gp0_command[0] >> 24 {
+                0x20...0x7f => (),
+                _ => self.flush(renderer),
+            }
+
             // Reset GP0 handler. Can be overriden by the callback in
             // certain cases, for instance for image load commands.
             *self.gp0_handler = Gpu::gp0_handle_command;
             (self.gp0_attributes.callback)(self, renderer);
+
+            self.count_command();
+
+            self.add_busy_ticks(GP0_COMMAND_TICKS);
         }
     }
 
+    /// Update the statistics after the execution of the command in
+    /// `gp0_command`. Polyline segments past the first one are
+    /// counted as they're received.
+    fn count_command(&mut self) {
+        let opcode = self.gp0_command[0] >> 24;
+
+        match opcode {
+            0x02 => (),
+            0x20...0x3f => {
+                let quad = opcode & 8 != 0;
+
+                self.stats.triangles += if quad { 2 } else { 1 };
+            }
+            0x40...0x5f => self.stats.lines += 1,
+            0x60...0x7f => self.stats.rects += 1,
+            _ => return,
+        }
+
+        self.stats.draw_calls += 1;
+    }
+
+    /// We don't emulate the command FIFO itself, instead we consider
+    /// it full when too much work is pending
+    fn fifo_full(&self) -> bool {
+        self.busy_ticks > GP0_FIFO_FULL_TICKS
+    }
+
+    /// Return true if the GPU asserts its DMA request signal (GPUSTAT
+    /// bit 25)
+    pub fn dma_request(&self) -> bool {
+        self.status() & (1 << 25) != 0
+    }
+
+    /// Return an estimate of the number of CPU cycles before the GPU
+    /// asserts its DMA request signal again
+    pub fn dma_request_delay(&self) -> Cycles {
+        let threshold =
+            match self.dma_direction {
+                DmaDirection::Fifo => GP0_FIFO_FULL_TICKS,
+                _ => 0,
+            };
+
+        let ticks = self.busy_ticks.saturating_sub(threshold) as Cycles;
+
+        // Convert to CPU cycles, rounding up
+        (ticks << 16) / self.gpu_to_cpu_clock_ratio().get_fp() + 1
+    }
+
+    /// Increase the time the GPU will spend processing the commands
+    fn add_busy_ticks(&mut self, ticks: u32) {
+        if !self.draw_timing {
+            return;
+        }
+
+        self.busy_ticks = self.busy_ticks.saturating_add(ticks);
+    }
+
     /// GP0 handler method: handle shaded polyline color word
     fn gp0_handle_shaded_polyline_color(&mut self, _: &mut Renderer, val: u32) {
         *self.gp0_handler =
@@ -537,7 +1013,7 @@ impl Gpu {
 
     /// GP0 handler method: handle shaded polyline vertex word
     fn gp0_handle_shaded_polyline_vertex(&mut self,
-                                         renderer: &mut Renderer,
+                                         _: &mut Renderer,
                                          val: u32) {
         // We don't test for the end-of-polyline marker here because
         // it only works in color words for shaded polylines.
@@ -549,13 +1025,16 @@ impl Gpu {
         let end_color = gp0_color(self.gp0_command[0]);
         let end_pos = gp0_position(val);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new(start_pos, start_color),
             Vertex::new(end_pos, end_color),
             ];
 
-        renderer.push_line(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_line(&vertices);
+
+        self.stats.lines += 1;
 
         // Store the new ending position for the next segment (if any)
         self.polyline_prev = (end_pos, end_color);
@@ -566,7 +1045,7 @@ impl Gpu {
 
     /// GP0 handler method: handle monochrome polyline position word
     fn gp0_handle_monochrome_polyline_vertex(&mut self,
-                                             renderer: &mut Renderer,
+                                             _: &mut Renderer,
                                              val: u32) {
         if is_polyline_end_marker(val) {
             // We found the end-of-polyline marker, we're done.
@@ -579,13 +1058,16 @@ impl Gpu {
 
         let end_pos = gp0_position(val);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new(start_pos, color),
             Vertex::new(end_pos, color),
             ];
 
-        renderer.push_line(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_line(&vertices);
+
+        self.stats.lines += 1;
 
         // Store the new ending position for the next segment (if any)
         self.polyline_prev = (end_pos, color);
@@ -596,7 +1078,9 @@ impl Gpu {
     fn gp0_parse_command(&self, gp0: u32) -> (u32, Gp0Attributes) {
         let opcode = gp0 >> 24;
 
-        let dither = self.dither();
+        // There's no point in dithering if we don't truncate the
+        // colors
+        let dither = self.dither() && !self.true_color;
 
         let (len, cback, dither): (u32, fn(&mut Gpu, &mut Renderer), bool) =
             match opcode {
@@ -676,12 +1160,14 @@ impl Gpu {
 
         let semi_transparent = opcode & 2 != 0;
 
-        let attr =
+        let mut attr =
             Gp0Attributes::new(cback,
                                semi_transparent,
                                blend_mode,
                                dither);
 
+        attr.primitive_attributes.true_color = self.true_color;
+
         (len, attr)
     }
 
@@ -689,14 +1175,28 @@ impl Gpu {
         (self.draw_mode >> 9) & 1 != 0
     }
 
+    /// Load the texture parameters of a textured polygon. The texpage
+    /// attribute of the polygon also replaces the texture page,
+    /// semi-transparency, depth and texture disable bits of the draw
+    /// mode (GP0(0xE1)), the following rectangles will use them.
+    fn polygon_texpage(&mut self, texpage: u32) {
+        let mask = 0x9ff;
+
+        self.draw_mode = (self.draw_mode & !mask) | (texpage as u16 & mask);
+
+        self.gp0_attributes.set_draw_params(self.draw_mode,
+                                            self.allow_texture_disable,
+                                            false);
+    }
+
     /// GP0(0x00): No operation
     fn gp0_nop(&mut self, _: &mut Renderer) {
         // NOP
     }
 
     /// GP0(0x01): Clear cache
-    fn gp0_clear_cache(&mut self, _: &mut Renderer) {
-        // XXX Not implemented
+    fn gp0_clear_cache(&mut self, renderer: &mut Renderer) {
+        renderer.clear_cache();
     }
 
     /// GP0(0x02): Fill rectangle
@@ -718,89 +1218,86 @@ impl Gpu {
             match size[0] & 0x7ff {
                 0x400 => 0,
                 // round up to the next multiple of 0x10
-                n => ((n + 0xf) & 0x3f0) as u16,
+                n => (((n & 0x3ff) + 0xf) & 0x7f0) as u16,
             };
 
         let height = (size[1] & 0x1ff) as u16;
 
-        let mut bottom = top + height;
-        let mut right = left + width;
+        // Timing formula from mednafen
+        let ticks = 46 + (width as u32 / 8 + 9) * height as u32;
 
-        // XXX Normally the fill rect wraps around: if the x or y
-        // coordinates overflow we should wrap around the image.
-        if right > 0x400 {
-            warn!("Fill rect X overflow: {}", right);
-            right = 0x400;
-        }
+        self.add_busy_ticks(ticks);
 
-        if bottom > 0x200 {
-            warn!("Fill rect Y overflow: {}", bottom);
-            bottom = 0x200;
+        // The fill wraps around the VRAM if it overflows, in this
+        // case we split it in up to 4 rectangles
+        for &(x, w) in &vram_wrap_spans(left, width, VRAM_WIDTH_PIXELS) {
+            for &(y, h) in &vram_wrap_spans(top, height, VRAM_HEIGHT) {
+                if w > 0 && h > 0 {
+                    renderer.fill_rect(color, (x, y), (w, h));
+                }
+            }
         }
-
-        let width = right - left;
-        let height = bottom - top;
-
-        renderer.fill_rect(color,
-                           (left, top),
-                           (width, height));
     }
 
     /// Gp0(0x80): Copy rectangle
-    fn gp0_copy_rect(&mut self, _: &mut Renderer) {
-        let size = gp0_position(self.gp0_command[3]);
-        let src_top_left = gp0_position(self.gp0_command[1]);
-        let dst_top_left = gp0_position(self.gp0_command[2]);
+    fn gp0_copy_rect(&mut self, renderer: &mut Renderer) {
+        let src_top_left = gp0_vram_position(self.gp0_command[1]);
+        let dst_top_left = gp0_vram_position(self.gp0_command[2]);
+        let size = gp0_vram_size(self.gp0_command[3]);
+
+        // Each pixel is read then written back
+        self.add_busy_ticks(size.0 as u32 * size.1 as u32 * 2);
 
-        // XXX Implement me
-        debug!("Copy Rectangle {:?} {:?} {:?}",
-               size, src_top_left, dst_top_left);
+        renderer.copy_rect(src_top_left, dst_top_left, size);
     }
 
     /// Draw an untextured unshaded triangle
-    fn gp0_monochrome_triangle(&mut self, renderer: &mut Renderer) {
+    fn gp0_monochrome_triangle(&mut self, _: &mut Renderer) {
         let color = gp0_color(self.gp0_command[0]);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new(gp0_position(self.gp0_command[1]), color),
             Vertex::new(gp0_position(self.gp0_command[2]), color),
             Vertex::new(gp0_position(self.gp0_command[3]), color),
             ];
 
-        renderer.push_triangle(self.gp0_attributes.primitive_attributes(),
-                               &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_triangle(&vertices);
     }
 
     /// Draw an untextured unshaded quad
-    fn gp0_monochrome_quad(&mut self, renderer: &mut Renderer) {
+    fn gp0_monochrome_quad(&mut self, _: &mut Renderer) {
         let color = gp0_color(self.gp0_command[0]);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new(gp0_position(self.gp0_command[1]), color),
             Vertex::new(gp0_position(self.gp0_command[2]), color),
             Vertex::new(gp0_position(self.gp0_command[3]), color),
             Vertex::new(gp0_position(self.gp0_command[4]), color),
             ];
 
-        renderer.push_quad(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_quad(&vertices);
     }
 
     /// Draw a monochrome line
-    fn gp0_monochrome_line(&mut self, renderer: &mut Renderer) {
+    fn gp0_monochrome_line(&mut self, _: &mut Renderer) {
         let color = gp0_color(self.gp0_command[0]);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new(gp0_position(self.gp0_command[1]), color),
             Vertex::new(gp0_position(self.gp0_command[2]), color),
             ];
 
-        renderer.push_line(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_line(&vertices);
     }
 
     /// Draw a monochrome polyline
-    fn gp0_monochrome_polyline(&mut self, renderer: &mut Renderer) {
+    fn gp0_monochrome_polyline(&mut self, _: &mut Renderer) {
         // Start with the first segment. The end-of-polyline marker is
         // ignored for the first two vertices.
 
@@ -809,13 +1306,14 @@ impl Gpu {
 
         let end_pos = gp0_position(self.gp0_command[2]);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new(start_pos, color),
             Vertex::new(end_pos, color),
             ];
 
-        renderer.push_line(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_line(&vertices);
 
         // Store the end point to continue the polyline when we get
         // the next vertex
@@ -826,13 +1324,13 @@ impl Gpu {
 
 
     /// Draw a textured unshaded triangle
-    fn gp0_textured_triangle(&mut self, renderer: &mut Renderer) {
+    fn gp0_textured_triangle(&mut self, _: &mut Renderer) {
         let color = gp0_color(self.gp0_command[0]);
 
         self.gp0_attributes.set_clut(self.gp0_command[2] >> 16);
-        self.gp0_attributes.set_draw_params(self.gp0_command[4] >> 16);
+        self.polygon_texpage(self.gp0_command[4] >> 16);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new_textured(gp0_position(self.gp0_command[1]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[2])),
@@ -844,18 +1342,19 @@ impl Gpu {
                                  gp0_texture_coordinates(self.gp0_command[6])),
             ];
 
-        renderer.push_triangle(self.gp0_attributes.primitive_attributes(),
-                               &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_triangle(&vertices);
     }
 
     /// Draw a textured unshaded quad
-    fn gp0_textured_quad(&mut self, renderer: &mut Renderer) {
+    fn gp0_textured_quad(&mut self, _: &mut Renderer) {
         let color = gp0_color(self.gp0_command[0]);
 
         self.gp0_attributes.set_clut(self.gp0_command[2] >> 16);
-        self.gp0_attributes.set_draw_params(self.gp0_command[4] >> 16);
+        self.polygon_texpage(self.gp0_command[4] >> 16);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new_textured(gp0_position(self.gp0_command[1]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[2])),
@@ -870,13 +1369,14 @@ impl Gpu {
                                  gp0_texture_coordinates(self.gp0_command[8])),
             ];
 
-        renderer.push_quad(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_quad(&vertices);
     }
 
     /// Draw an untextured shaded triangle
-    fn gp0_shaded_triangle(&mut self, renderer: &mut Renderer) {
-        let vertices = [
+    fn gp0_shaded_triangle(&mut self, _: &mut Renderer) {
+        let mut vertices = [
             Vertex::new(gp0_position(self.gp0_command[1]),
                         gp0_color(self.gp0_command[0])),
             Vertex::new(gp0_position(self.gp0_command[3]),
@@ -885,13 +1385,14 @@ impl Gpu {
                         gp0_color(self.gp0_command[4])),
             ];
 
-        renderer.push_triangle(self.gp0_attributes.primitive_attributes(),
-                               &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_triangle(&vertices);
     }
 
     /// Draw an untextured shaded quad
-    fn gp0_shaded_quad(&mut self, renderer: &mut Renderer) {
-        let vertices = [
+    fn gp0_shaded_quad(&mut self, _: &mut Renderer) {
+        let mut vertices = [
             Vertex::new(gp0_position(self.gp0_command[1]),
                         gp0_color(self.gp0_command[0])),
             Vertex::new(gp0_position(self.gp0_command[3]),
@@ -902,25 +1403,27 @@ impl Gpu {
                         gp0_color(self.gp0_command[6])),
             ];
 
-        renderer.push_quad(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_quad(&vertices);
     }
 
     /// Draw a shaded line
-    fn gp0_shaded_line(&mut self, renderer: &mut Renderer) {
-        let vertices = [
+    fn gp0_shaded_line(&mut self, _: &mut Renderer) {
+        let mut vertices = [
             Vertex::new(gp0_position(self.gp0_command[1]),
                         gp0_color(self.gp0_command[0])),
             Vertex::new(gp0_position(self.gp0_command[3]),
                         gp0_color(self.gp0_command[2])),
             ];
 
-        renderer.push_line(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_line(&vertices);
     }
 
     /// Draw a shaded polyline
-    fn gp0_shaded_polyline(&mut self, renderer: &mut Renderer) {
+    fn gp0_shaded_polyline(&mut self, _: &mut Renderer) {
         // Start with the first segment. We cannot have an
         // end-of-polyline marker in any of these vertice's color code
         // (if you put the marker in the 2nd vertex color word it's
@@ -933,13 +1436,14 @@ impl Gpu {
         let end_color = gp0_color(self.gp0_command[2]);
         let end_pos = gp0_position(self.gp0_command[3]);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new(start_pos, start_color),
             Vertex::new(end_pos, end_color),
             ];
 
-        renderer.push_line(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_line(&vertices);
 
         // Store the end point to continue the polyline when we get
         // the next vertex
@@ -949,12 +1453,12 @@ impl Gpu {
     }
 
     /// Draw a textured shaded triangle
-    fn gp0_textured_shaded_triangle(&mut self, renderer: &mut Renderer) {
+    fn gp0_textured_shaded_triangle(&mut self, _: &mut Renderer) {
 
         self.gp0_attributes.set_clut(self.gp0_command[2] >> 16);
-        self.gp0_attributes.set_draw_params(self.gp0_command[5] >> 16);
+        self.polygon_texpage(self.gp0_command[5] >> 16);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new_textured(gp0_position(self.gp0_command[1]),
                                  gp0_color(self.gp0_command[0]),
                                  gp0_texture_coordinates(self.gp0_command[2])),
@@ -966,17 +1470,18 @@ impl Gpu {
                                  gp0_texture_coordinates(self.gp0_command[8])),
             ];
 
-        renderer.push_triangle(self.gp0_attributes.primitive_attributes(),
-                               &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_triangle(&vertices);
     }
 
     /// Draw a textured shaded quad
-    fn gp0_textured_shaded_quad(&mut self, renderer: &mut Renderer) {
+    fn gp0_textured_shaded_quad(&mut self, _: &mut Renderer) {
 
         self.gp0_attributes.set_clut(self.gp0_command[2] >> 16);
-        self.gp0_attributes.set_draw_params(self.gp0_command[5] >> 16);
+        self.polygon_texpage(self.gp0_command[5] >> 16);
 
-        let vertices = [
+        let mut vertices = [
             Vertex::new_textured(gp0_position(self.gp0_command[1]),
                                  gp0_color(self.gp0_command[0]),
                                  gp0_texture_coordinates(self.gp0_command[2])),
@@ -991,15 +1496,13 @@ impl Gpu {
                                  gp0_texture_coordinates(self.gp0_command[11])),
             ];
 
-        renderer.push_quad(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.pgxp_apply(&mut vertices);
+
+        self.push_quad(&vertices);
     }
 
 
-    fn gp0_rect_sized(&mut self,
-                      renderer: &mut Renderer,
-                      width: i16,
-                      height: i16) {
+    fn gp0_rect_sized(&mut self, width: i16, height: i16) {
 
         let top_left = gp0_position(self.gp0_command[1]);
         let color = gp0_color(self.gp0_command[0]);
@@ -1011,17 +1514,15 @@ impl Gpu {
             Vertex::new([top_left[0] + width, top_left[1] + height], color),
         ];
 
-        renderer.push_quad(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.push_quad(&vertices);
     }
 
-    fn gp0_rect_sized_textured(&mut self,
-                               renderer: &mut Renderer,
-                               width: i16,
-                               height: i16) {
+    fn gp0_rect_sized_textured(&mut self, width: i16, height: i16) {
 
         // Rectangles draw params are set with the "Draw Mode" command
-        self.gp0_attributes.set_draw_params(self.draw_mode as u32);
+        self.gp0_attributes.set_draw_params(self.draw_mode,
+                                            self.allow_texture_disable,
+                                            true);
 
         self.gp0_attributes.set_clut(self.gp0_command[2] >> 16);
 
@@ -1049,86 +1550,68 @@ impl Gpu {
                                   tex_top_left[1] + height as u16]),
         ];
 
-        renderer.push_quad(self.gp0_attributes.primitive_attributes(),
-                           &vertices);
+        self.push_quad(&vertices);
     }
 
     /// Draw a textured rectangle
-    fn gp0_textured_rect(&mut self, renderer: &mut Renderer) {
-        let size = gp0_position(self.gp0_command[3]);
+    fn gp0_textured_rect(&mut self, _: &mut Renderer) {
+        let size = gp0_rect_size(self.gp0_command[3]);
 
-        self.gp0_rect_sized_textured(renderer, size[0], size[1]);
+        self.gp0_rect_sized_textured(size[0], size[1]);
     }
 
     /// Draw a monochrome rectangle
-    fn gp0_monochrome_rect(&mut self, renderer: &mut Renderer) {
-        let size = gp0_position(self.gp0_command[2]);
+    fn gp0_monochrome_rect(&mut self, _: &mut Renderer) {
+        let size = gp0_rect_size(self.gp0_command[2]);
 
-        self.gp0_rect_sized(renderer, size[0], size[1]);
+        self.gp0_rect_sized(size[0], size[1]);
     }
 
     /// Draw a 1x1 monochrome rectangle (point)
-    fn gp0_monochrome_rect_1x1(&mut self, renderer: &mut Renderer) {
-        self.gp0_rect_sized(renderer, 1, 1);
+    fn gp0_monochrome_rect_1x1(&mut self, _: &mut Renderer) {
+        self.gp0_rect_sized(1, 1);
     }
 
     /// Draw a 16x16 monochrome rectangle
-    fn gp0_monochrome_rect_16x16(&mut self, renderer: &mut Renderer) {
-        self.gp0_rect_sized(renderer, 16, 16);
+    fn gp0_monochrome_rect_16x16(&mut self, _: &mut Renderer) {
+        self.gp0_rect_sized(16, 16);
     }
 
 
     /// Draw a 8x8 textured rectangle
-    fn gp0_textured_rect_8x8(&mut self, renderer: &mut Renderer) {
-        self.gp0_rect_sized_textured(renderer, 8, 8);
+    fn gp0_textured_rect_8x8(&mut self, _: &mut Renderer) {
+        self.gp0_rect_sized_textured(8, 8);
     }
 
     /// Draw a 16x16 textured rectangle
-    fn gp0_textured_rect_16x16(&mut self, renderer: &mut Renderer) {
-        self.gp0_rect_sized_textured(renderer, 16, 16);
+    fn gp0_textured_rect_16x16(&mut self, _: &mut Renderer) {
+        self.gp0_rect_sized_textured(16, 16);
     }
 
     /// GP0(0xA0): Image Load
     fn gp0_image_load(&mut self, _: &mut Renderer) {
         // Parameter 1 contains the location of the target location's
         // top-left corner in VRAM
-        let pos = self.gp0_command[1];
-
-        let x = pos as u16;
-        let y = (pos >> 16) as u16;
+        let (x, y) = gp0_vram_position(self.gp0_command[1]);
 
         // Parameter 2 contains the image resolution
-        let res = self.gp0_command[2];
-
-        let width  = res & 0xffff;
-        let height = res >> 16;
-
-        // Size of the image in 16bit pixels
-        let imgsize = width * height;
-
-        // If we have an odd number of pixels we must round up since
-        // we transfer 32bits at a time. There'll be 16bits of padding
-        // in the last word.
-        let imgsize = (imgsize + 1) & !1;
+        let (width, height) = gp0_vram_size(self.gp0_command[2]);
 
         // Store number of 32bit words expected for this image
-        self.gp0_words_remaining = imgsize / 2;
+        self.gp0_words_remaining = image_size_words(width, height);
 
-        if self.gp0_words_remaining > 0 {
-            self.load_buffer.reset(x, y, width as u16, height as u16);
-
-            // Use a custom GP0 handler to handle the GP0 image load
-            *self.gp0_handler = Gpu::gp0_handle_image_load;
-        } else {
-            warn!("GPU: 0-sized image load");
-        }
+        self.load_buffer.reset(x, y, width, height);
 
+        // Use a custom GP0 handler to handle the GP0 image load
+        *self.gp0_handler = Gpu::gp0_handle_image_load;
     }
 
     /// GP0 handler method: handle image load
     fn gp0_handle_image_load(&mut self, renderer: &mut Renderer, word: u32) {
         self.load_buffer.push_gp0_word(word);
 
+        self.stats.vram_upload_words += 1;
+
         self.gp0_words_remaining -= 1;
 
         if self.gp0_words_remaining == 0 {
@@ -1146,14 +1629,46 @@ impl Gpu {
     }
 
     /// GP0(0xC0): Image Store
-    fn gp0_image_store(&mut self, _: &mut Renderer) {
+    fn gp0_image_store(&mut self, renderer: &mut Renderer) {
+        // Parameter 1 contains the location of the source's top-left
+        // corner in VRAM
+        let (x, y) = gp0_vram_position(self.gp0_command[1]);
+
         // Parameter 2 contains the image resolution
-        let res = self.gp0_command[2];
+        let (width, height) = gp0_vram_size(self.gp0_command[2]);
+
+        self.store_buffer.reset(x, y, width, height);
+
+        let mut read_back_done = false;
+
+        if self.true_color {
+            // The renderer might keep more color precision than the
+            // real VRAM, the game must still see 15bit values
+            let len = width as usize * height as usize;
+            let mut pixels = vec![0u32; len];
+
+            if renderer.store_image_true_color((x, y),
+                                               (width, height),
+                                               &mut pixels) {
+                for (p, &v) in self.store_buffer
+                    .buffer_mut()
+                    .iter_mut()
+                    .zip(pixels.iter()) {
+                    *p = read_back(v);
+                }
 
-        let width  = res & 0xffff;
-        let height = res >> 16;
+                read_back_done = true;
+            }
+        }
 
-        warn!("Unhandled image store: {}x{}", width, height);
+        if !read_back_done {
+            renderer.store_image((x, y),
+                                 (width, height),
+                                 self.store_buffer.buffer_mut());
+        }
+
+        // The image is then read 32bits at a time through GPUREAD
+        self.store_words_remaining = image_size_words(width, height);
     }
 
     /// GP0(0xE1): Draw Mode
@@ -1191,6 +1706,91 @@ impl Gpu {
         self.update_draw_area(renderer);
     }
 
+    /// Return true if the GPU draws the primitive made of
+    /// `vertices`. Primitives with an edge longer than 1023 pixels
+    /// horizontally or 511 pixels vertically are silently dropped by
+    /// the hardware, as are the ones we know to be entirely outside
+    /// of the drawing area.
+    fn accept_primitive(&self, vertices: &[Vertex]) -> bool {
+        if primitive_too_big(vertices) {
+            return false;
+        }
+
+        let (off_x, off_y) = self.drawing_offset;
+
+        let mut min_x = i32::max_value();
+        let mut max_x = i32::min_value();
+        let mut min_y = i32::max_value();
+        let mut max_y = i32::min_value();
+
+        for v in vertices {
+            let x = v.position[0] as i32 + off_x as i32;
+            let y = v.position[1] as i32 + off_y as i32;
+
+            min_x = cmp::min(min_x, x);
+            max_x = cmp::max(max_x, x);
+            min_y = cmp::min(min_y, y);
+            max_y = cmp::max(max_y, y);
+        }
+
+        let left = self.drawing_area_left as i32;
+        let right = self.drawing_area_right as i32;
+        let top = self.drawing_area_top as i32;
+        let bottom = self.drawing_area_bottom as i32;
+
+        // The drawing area is inclusive, if it's empty nothing is
+        // drawn
+        left <= right && top <= bottom &&
+            max_x >= left && min_x <= right &&
+            max_y >= top && min_y <= bottom
+    }
+
+    /// Queue a primitive for the renderer
+    fn push_primitive(&mut self, kind: PrimitiveKind, vertices: &[Vertex]) {
+        self.primitives.push(kind,
+                             self.gp0_attributes.primitive_attributes(),
+                             vertices);
+    }
+
+    fn push_line(&mut self, vertices: &[Vertex; 2]) {
+        if self.accept_primitive(vertices) {
+            self.push_primitive(PrimitiveKind::Line, vertices);
+        }
+    }
+
+    fn push_triangle(&mut self, vertices: &[Vertex; 3]) {
+        if self.accept_primitive(vertices) {
+            self.push_primitive(PrimitiveKind::Triangle, vertices);
+        }
+    }
+
+    /// Quads are drawn as two triangles (0, 1, 2) and (1, 2, 3) which
+    /// are accepted or rejected independently
+    fn push_quad(&mut self, vertices: &[Vertex; 4]) {
+        let first = [vertices[0], vertices[1], vertices[2]];
+        let second = [vertices[1], vertices[2], vertices[3]];
+
+        match (self.accept_primitive(&first),
+               self.accept_primitive(&second)) {
+            (true, true) => self.push_primitive(PrimitiveKind::Quad, vertices),
+            (true, false) =>
+                self.push_primitive(PrimitiveKind::Triangle, &first),
+            (false, true) =>
+                self.push_primitive(PrimitiveKind::Triangle, &second),
+            (false, false) => (),
+        }
+    }
+
+    /// Send the queued primitives to the renderer. Must be called
+    /// before any other renderer method so that the drawing order is
+    /// preserved, and at the end of the frame.
+    pub fn flush(&mut self, renderer: &mut Renderer) {
+        if !self.primitives.is_empty() {
+            renderer.push_primitives(&self.primitives);
+            self.primitives.clear();
+        }
+    }
+
     // Called when the drawing area changes to notify the renderer
     fn update_draw_area(&mut self, renderer: &mut Renderer) {
         renderer.set_draw_area((self.drawing_area_left,
@@ -1230,7 +1830,11 @@ impl Gpu {
                val: u32,
                timers: &mut Timers) {
 
-        let opcode = (val >> 24) & 0xff;
+        self.flush(renderer);
+
+        // Only the low 6 bits of the opcode are decoded, 0x40 to 0xff
+        // mirror the first 64 commands
+        let opcode = (val >> 24) & 0x3f;
 
         match opcode {
             0x00 => {
@@ -1250,15 +1854,26 @@ impl Gpu {
                 // that the game is done rendering the previous frame.
                 shared.counters_mut().framebuffer_swap.increment();
                 self.gp1_display_vram_start(val);
+                self.update_display_mode(renderer);
+            }
+            0x06 => {
+                self.gp1_display_horizontal_range(val);
+                self.update_display_mode(renderer);
+            }
+            0x07 => {
+                self.gp1_display_vertical_range(shared,val);
+                // The VBLANK timings changed
+                timers.video_timings_changed(shared, self);
+                self.update_display_mode(renderer);
             }
-            0x06 => self.gp1_display_horizontal_range(val),
-            0x07 => self.gp1_display_vertical_range(shared,val),
             0x08 => {
                 self.gp1_display_mode(shared, val);
                 timers.video_timings_changed(shared, self);
                 self.update_display_mode(renderer);
             }
-            0x10 => self.gp1_get_info(val),
+            0x09 => self.gp1_allow_texture_disable(val),
+            // All of 0x10...0x1f are "Get GPU Info"
+            0x10...0x1f => self.gp1_get_info(val),
             _    => panic!("Unhandled GP1 command {:08x}", val),
         }
     }
@@ -1270,6 +1885,47 @@ impl Gpu {
         let depth_24bpp = self.display_depth == DisplayDepth::D24Bits;
 
         renderer.set_display_mode(top_left, resolution, depth_24bpp);
+        renderer.set_display_area(&self.display_area());
+    }
+
+    /// Compute the visible part of the display area from the display
+    /// range registers
+    pub fn display_area(&self) -> DisplayArea {
+        let divider = self.hres.dotclock_divider() as u16;
+
+        let (std_hstart, std_hend) = STANDARD_HORIZ_RANGE;
+        let (std_lstart, std_lend) =
+            match self.vmode {
+                VMode::Ntsc => STANDARD_NTSC_LINE_RANGE,
+                VMode::Pal => STANDARD_PAL_LINE_RANGE,
+            };
+
+        let hstart = self.display_horiz_start;
+        let hend = cmp::max(self.display_horiz_end, hstart);
+        let lstart = self.display_line_start;
+        let lend = cmp::max(self.display_line_end, lstart);
+
+        // The real hardware rounds the width to a multiple of 4
+        // pixels
+        let width = (((hend - hstart) / divider + 2) & !3) as u16;
+        let mut height = lend - lstart;
+        let mut frame_height = std_lend - std_lstart;
+        let mut y_offset = lstart as i16 - std_lstart as i16;
+
+        if self.interlaced_480() {
+            height *= 2;
+            frame_height *= 2;
+            y_offset *= 2;
+        }
+
+        DisplayArea {
+            top_left: (self.display_vram_x_start, self.display_vram_y_start),
+            visible: (width, height),
+            frame_offset: ((hstart as i16 - std_hstart as i16) /
+                           divider as i16,
+                           y_offset),
+            frame: ((std_hend - std_hstart) / divider, frame_height),
+        }
     }
 
     /// GP1(0x00): Soft Reset
@@ -1283,7 +1939,7 @@ impl Gpu {
         self.texture_window_y_offset = 0;
         self.dithering = false;
         self.draw_to_display = false;
-        self.texture_disable = false;
+        self.allow_texture_disable = false;
         self.drawing_area_left = 0;
         self.drawing_area_top = 0;
         self.drawing_area_right = 0;
@@ -1301,8 +1957,9 @@ impl Gpu {
         self.vres = VerticalRes::Y240Lines;
         self.field = Field::Top;
 
+        // Same as GP1(0x08) with a value of 0
         self.vmode = VMode::Ntsc;
-        self.interlaced = true;
+        self.interlaced = false;
         self.display_horiz_start = 0x200;
         self.display_horiz_end = 0xc00;
         self.display_line_start = 0x10;
@@ -1325,6 +1982,14 @@ impl Gpu {
         self.gp0_command.clear();
         self.gp0_words_remaining = 0;
         *self.gp0_handler = Gpu::gp0_handle_command;
+        self.pgxp_vertices.clear();
+
+        // This also aborts any image load in progress. The real GPU
+        // would have already written the pixels received so far to
+        // the VRAM but we only upload complete images so they're
+        // lost.
+        self.load_buffer.clear();
+
         // XXX should also clear the command FIFO when we implement it
     }
 
@@ -1444,6 +2109,11 @@ impl Gpu {
 
         self.sync(shared);
     }
+
+    /// GP1(0x09): Allow texture disable
+    fn gp1_allow_texture_disable(&mut self, val: u32) {
+        self.allow_texture_disable = val & 1 != 0;
+    }
 }
 
 /// Wrapper around the `gp0_handler` function pointer in order to be
@@ -1459,7 +2129,7 @@ callback!(struct Gp0Handler(fn (&mut Gpu, &mut Renderer, u32)) {
 });
 
 /// Interlaced output splits each frame in two fields
-#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+#[derive(Clone, Copy, PartialEq, Eq, RustcDecodable, RustcEncodable)]
 enum Field {
     /// Top field (odd lines).
     Top = 1,
@@ -1661,6 +2331,9 @@ impl Gp0Attributes {
                 texture_depth: TextureDepth::T4Bpp,
                 clut: [0, 0],
                 dither: dither,
+                true_color: false,
+                flip_x: false,
+                flip_y: false,
             }
         }
     }
@@ -1677,7 +2350,13 @@ impl Gp0Attributes {
         self.primitive_attributes.clut = [x as u16, y as u16];
     }
 
-    fn set_draw_params(&mut self, params: u32) {
+    /// Load the texture parameters from the draw mode (GP0(0xE1)
+    /// format). The x/y flip bits only apply to rectangles.
+    fn set_draw_params(&mut self,
+                       draw_mode: u16,
+                       allow_texture_disable: bool,
+                       rectangle: bool) {
+        let params = draw_mode as u32;
 
         // Texture page coordinates
         let x = (params & 0xf) << 6;
@@ -1705,10 +2384,20 @@ impl Gp0Attributes {
                 // Not sure what this does, No$ says it's
                 // "reserved". More testing required...
                 _ => {
-                    warn!("Invalid texture depth");
+                    warn!(target: logging::GPU, "Invalid texture depth");
                     TextureDepth::T16Bpp
                 }
             };
+
+        // The texture disable bit is ignored unless it's been
+        // allowed with GP1(0x09). The primitive is then drawn using
+        // only its vertex colors.
+        if allow_texture_disable && (params >> 11) & 1 != 0 {
+            attrs.blend_mode = BlendMode::None;
+        }
+
+        attrs.flip_x = rectangle && (params >> 12) & 1 != 0;
+        attrs.flip_y = rectangle && (params >> 13) & 1 != 0;
     }
 }
 
@@ -1750,12 +2439,41 @@ callback!(struct Callback(fn(&mut Gpu, &mut Renderer)) {
 /// Parse a position as written in the GP0 register and return it as
 /// an array of two `i16`
 fn gp0_position(pos: u32) -> [i16; 2] {
-    let x = pos as i16;
-    let y = (pos >> 16) as i16;
+    // Vertex coordinates are 11bit two's complement signed values,
+    // the upper bits are ignored
+    let x = ((pos << 5) as i16) >> 5;
+    let y = (((pos >> 16) << 5) as i16) >> 5;
 
     [x, y]
 }
 
+/// Parse the size of a variable-size rectangle: up to 1023x511
+/// pixels
+fn gp0_rect_size(size: u32) -> [i16; 2] {
+    let w = size & 0x3ff;
+    let h = (size >> 16) & 0x1ff;
+
+    [w as i16, h as i16]
+}
+
+/// Return true if `vertices` contains an edge which is too long for
+/// the GPU to draw: more than 1023 pixels horizontally or 511
+/// vertically.
+fn primitive_too_big(vertices: &[Vertex]) -> bool {
+    for (i, a) in vertices.iter().enumerate() {
+        for b in &vertices[i + 1..] {
+            let dx = (a.position[0] as i32 - b.position[0] as i32).abs();
+            let dy = (a.position[1] as i32 - b.position[1] as i32).abs();
+
+            if dx > 1023 || dy > 511 {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
 /// Parse a color as written in the GP0 register and return it as
 /// an array of 3 `u8`
 fn gp0_color(col: u32) -> [u8; 3] {
@@ -1783,6 +2501,108 @@ fn is_polyline_end_marker(val: u32) -> bool {
     val & 0xf000f000 == 0x50005000
 }
 
+/// Decode VRAM coordinates used by the transfer commands, wrapping
+/// around the VRAM size
+fn gp0_vram_position(pos: u32) -> (u16, u16) {
+    let x = pos & 0x3ff;
+    let y = (pos >> 16) & 0x1ff;
+
+    (x as u16, y as u16)
+}
+
+/// Decode rectangle dimensions used by the transfer commands. A value
+/// of 0 means the full VRAM width/height.
+fn gp0_vram_size(size: u32) -> (u16, u16) {
+    let width = ((size & 0xffff).wrapping_sub(1) & 0x3ff) + 1;
+    let height = ((size >> 16).wrapping_sub(1) & 0x1ff) + 1;
+
+    (width as u16, height as u16)
+}
+
+/// Number of 32bit words needed to transfer an image of the given
+/// dimensions. If we have an odd number of pixels we must round up
+/// since we transfer 32bits at a time, there'll be 16bits of padding
+/// in the last word.
+fn image_size_words(width: u16, height: u16) -> u32 {
+    let imgsize = width as u32 * height as u32;
+
+    (imgsize + 1) / 2
+}
+
+/// Split the span `[start, start + len)` in two if it overflows
+/// `size`. The second span is empty if there's no overflow.
+fn vram_wrap_spans(start: u16, len: u16, size: u16) -> [(u16, u16); 2] {
+    let end = start + len;
+
+    if end > size {
+        [(start, size - start), (0, end - size)]
+    } else {
+        [(start, len), (0, 0)]
+    }
+}
+
+/// Display areas latched line by line when the scanline timing is
+/// enabled
+struct DisplayLatches {
+    /// Changes of the display area in the current frame
+    lines: Vec<DisplayLine>,
+    /// Last complete frame with a mid-frame change, waiting to be
+    /// sent to the renderer
+    frame: Option<Vec<DisplayLine>>,
+}
+
+impl DisplayLatches {
+    fn new() -> DisplayLatches {
+        DisplayLatches {
+            lines: Vec::new(),
+            frame: None,
+        }
+    }
+
+    /// Latch `area` for `line`. Only the changes are recorded.
+    fn latch(&mut self, line: u16, area: DisplayArea) {
+        if self.lines.last().map(|l| l.area) != Some(area) {
+            self.lines.push(DisplayLine {
+                line: line,
+                area: area,
+            });
+        }
+    }
+
+    /// Called at the end of the active display
+    fn end_frame(&mut self) {
+        if self.lines.len() > 1 {
+            let lines = ::std::mem::replace(&mut self.lines, Vec::new());
+
+            self.frame = Some(lines);
+        } else {
+            // No mid-frame change, the renderer already knows the
+            // display area
+            self.lines.clear();
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<Vec<DisplayLine>> {
+        self.frame.take()
+    }
+}
+
+impl Encodable for DisplayLatches {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Only relevant for the frame being output, rebuilt on the
+        // next one
+        s.emit_nil()
+    }
+}
+
+impl Decodable for DisplayLatches {
+    fn decode<D: Decoder>(d: &mut D) -> Result<DisplayLatches, D::Error> {
+        try!(d.read_nil());
+
+        Ok(DisplayLatches::new())
+    }
+}
+
 /// Buffer holding a portion of the VRAM while it's being transfered
 struct ImageBuffer {
     /// Coordinates of the top-left corner in VRAM
@@ -1834,6 +2654,32 @@ impl ImageBuffer {
         self.index = 0;
     }
 
+    fn buffer_mut(&mut self) -> &mut [u16] {
+        let len = self.resolution.0 as usize * self.resolution.1 as usize;
+
+        &mut self.buffer[0..len]
+    }
+
+    /// Return the next two pixels for GPUREAD. If the image has an
+    /// odd number of pixels the last word is padded with 0.
+    fn pop_gpuread_word(&mut self) -> u32 {
+        let len = self.resolution.0 as u32 * self.resolution.1 as u32;
+
+        let index = self.index;
+
+        let lo = self.buffer[index as usize] as u32;
+        let hi =
+            if index + 1 < len {
+                self.buffer[index as usize + 1] as u32
+            } else {
+                0
+            };
+
+        self.index += 2;
+
+        lo | (hi << 16)
+    }
+
     fn push_gp0_word(&mut self, word: u32) {
         // No bound checks: I trust the caller not to send more
         // pixels than fits the buffer. I already have a state
@@ -1918,6 +2764,32 @@ impl Decodable for ImageBuffer {
     }
 }
 
+/// Rough estimate of the number of GPU clock ticks it takes to
+/// process a GP0 command (excluding the VRAM transfers and fills
+/// which are accounted for separately). Rendering time depends on
+/// the size of the primitives but we don't take that into account
+/// for now.
+const GP0_COMMAND_TICKS: u32 = 64;
+
+/// Amount of pending work (in GPU clock ticks) above which we
+/// consider the 16 word command FIFO to be full
+const GP0_FIFO_FULL_TICKS: u32 = GP0_COMMAND_TICKS * 4;
+
+/// Maximum number of PGXP values kept for a single GP0 command. The
+/// longest commands (shaded textured quads) have 4 vertices, only
+/// polylines can have more.
+const PGXP_MAX_VERTICES: usize = 16;
+
+/// Horizontal display range (in GPU clock ticks relative to HSYNC)
+/// covering a standard TV screen
+const STANDARD_HORIZ_RANGE: (u16, u16) = (0x260, 0xc60);
+
+/// Display line range covering a standard NTSC TV screen (240 lines)
+const STANDARD_NTSC_LINE_RANGE: (u16, u16) = (0x10, 0x100);
+
+/// Display line range covering a standard PAL TV screen (288 lines)
+const STANDARD_PAL_LINE_RANGE: (u16, u16) = (0x23, 0x13b);
+
 // Width of the VRAM in 16bit pixels
 pub const VRAM_WIDTH_PIXELS: u16 = 1024;
 // Height of the VRAM in lines
@@ -1927,6 +2799,18 @@ pub const VRAM_HEIGHT: u16 = 512;
 pub const VRAM_SIZE_PIXELS: usize =
     VRAM_WIDTH_PIXELS as usize * VRAM_HEIGHT as usize;
 
+/// Position of the video beam, used to emulate lightguns
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcDecodable, RustcEncodable)]
+pub struct BeamPosition {
+    /// Line number relative to VSYNC
+    pub line: u16,
+    /// GPU clock ticks relative to HSYNC
+    pub tick: u16,
+    /// Horizontal position relative to HSYNC in 8MHz clock periods
+    /// (as reported by the Namco GunCon)
+    pub x_8mhz: u16,
+}
+
 /// The are a few hardware differences between PAL and NTSC consoles,
 /// in particular the pixelclock runs slightly slower on PAL consoles.
 #[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
@@ -1934,3 +2818,171 @@ pub enum VideoClock {
     Ntsc,
     Pal,
 }
+
+#[test]
+fn primitive_size_limits() {
+    let v = |x, y| Vertex::new([x, y], [0; 3]);
+
+    // Coordinates are 11bit signed
+    assert_eq!(gp0_position(0x07ff_0400), [-1024, -1]);
+    assert_eq!(gp0_position(0xf800_03ff), [1023, 0]);
+
+    assert!(!primitive_too_big(&[v(-512, 0), v(511, 511), v(0, 0)]));
+    assert!(primitive_too_big(&[v(-512, 0), v(512, 0), v(0, 0)]));
+    assert!(primitive_too_big(&[v(0, 0), v(10, 10), v(0, 512)]));
+    assert!(primitive_too_big(&[v(0, -256), v(0, 256)]));
+}
+
+#[test]
+fn gp1_status() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+
+    // Bit 31 depends on the current line
+    let status = |gpu: &Gpu| gpu.status() & 0x7fffffff;
+
+    // Value after reset: display disabled, ready to receive commands
+    // and DMA blocks. Bit 13 is set since we're not interlaced.
+    assert_eq!(status(&gpu), 0x14802000);
+
+    // The DMA request (bit 25) depends on the DMA direction
+    gpu.gp1_dma_direction(1);
+    assert_eq!(status(&gpu), 0x36802000);
+    gpu.gp1_dma_direction(2);
+    assert_eq!(status(&gpu), 0x56802000);
+    // No image store in progress: not ready to send VRAM to CPU
+    gpu.gp1_dma_direction(3);
+    assert_eq!(status(&gpu), 0x74802000);
+
+    gpu.gp1_display_enable(0);
+    assert_eq!(status(&gpu), 0x74002000);
+
+    gpu.gp1_dma_direction(0);
+    gpu.gp1_display_enable(1);
+    assert_eq!(status(&gpu), 0x14802000);
+}
+
+#[test]
+fn display_latches() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut latches = DisplayLatches::new();
+
+    let area = gpu.display_area();
+
+    // No mid-frame change
+    latches.latch(0, area);
+    latches.latch(1, area);
+    latches.end_frame();
+    assert!(latches.take_frame().is_none());
+
+    latches.latch(0, area);
+    latches.latch(1, area);
+    gpu.gp1_display_vram_start(0x400);
+    let sheared = gpu.display_area();
+    latches.latch(2, sheared);
+    latches.latch(3, sheared);
+    latches.end_frame();
+
+    assert_eq!(latches.take_frame(),
+               Some(vec![DisplayLine { line: 0, area: area },
+                         DisplayLine { line: 2, area: sheared }]));
+    assert!(latches.take_frame().is_none());
+}
+
+#[test]
+fn vram_rect_commands() {
+    use self::software::SoftwareRenderer;
+
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    let pixel = |r: &SoftwareRenderer, x: usize, y: usize| {
+        r.vram()[y * VRAM_WIDTH_PIXELS as usize + x]
+    };
+
+    // The spans are split at the edge of the VRAM
+    assert_eq!(vram_wrap_spans(1008, 32, 1024), [(1008, 16), (0, 16)]);
+    assert_eq!(vram_wrap_spans(500, 12, 512), [(500, 12), (0, 0)]);
+
+    // Fill a 32x24 rectangle at (1008, 500), it wraps around both
+    // edges of the VRAM
+    for &w in &[0x020000ff, (500 << 16) | 1008, (24 << 16) | 32] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for &(x, y) in &[(1008, 500), (1023, 511), (0, 0), (15, 11)] {
+        assert_eq!(pixel(&renderer, x, y), 0x001f);
+    }
+
+    for &(x, y) in &[(1007, 500), (1008, 499), (16, 0), (0, 12)] {
+        assert_eq!(pixel(&renderer, x, y), 0);
+    }
+
+    // Copy 16x16 pixels from (1016, 508) to (100, 100), the source
+    // wraps around
+    for &w in &[0x80000000, (508 << 16) | 1016, (100 << 16) | 100,
+                (16 << 16) | 16] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert_eq!(pixel(&renderer, 100, 100), 0x001f);
+    assert_eq!(pixel(&renderer, 115, 115), 0x001f);
+    assert_eq!(pixel(&renderer, 116, 100), 0);
+
+    // Read back 4 pixels across the right edge of the copy
+    for &w in &[0xc0000000, (100 << 16) | 114, (1 << 16) | 4] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert_eq!(gpu.read(), 0x001f001f);
+    assert_eq!(gpu.read(), 0);
+    assert_eq!(gpu.store_words_remaining, 0);
+}
+
+#[test]
+fn busy_status() {
+    use self::software::SoftwareRenderer;
+
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut shared = SharedState::new();
+    let mut renderer = SoftwareRenderer::new();
+
+    // Ready to receive commands (bit 26) and DMA blocks (bit 28)
+    let ready = |gpu: &Gpu| gpu.status() & (5 << 26);
+
+    assert_eq!(ready(&gpu), 5 << 26);
+
+    // 32x24 fill
+    for &w in &[0x020000ff, 0, (24 << 16) | 32] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    let fill = GP0_COMMAND_TICKS + 46 + (32 / 8 + 9) * 24;
+
+    assert_eq!(gpu.busy_ticks, fill);
+    assert_eq!(ready(&gpu), 0);
+
+    // 16x16 copy
+    for &w in &[0x80000000, 0, (100 << 16) | 100, (16 << 16) | 16] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert_eq!(gpu.busy_ticks, fill + GP0_COMMAND_TICKS + 16 * 16 * 2);
+
+    // The GPU becomes ready again once it had the time to process
+    // the commands
+    shared.tk().tick(100_000);
+    gpu.sync(&mut shared);
+
+    assert_eq!(gpu.busy_ticks, 0);
+    assert_eq!(ready(&gpu), 5 << 26);
+
+    // Without the draw timing the GPU is never busy
+    gpu.set_draw_timing(false);
+
+    for &w in &[0x020000ff, 0, (24 << 16) | 32] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert_eq!(gpu.busy_ticks, 0);
+    assert_eq!(ready(&gpu), 5 << 26);
+}
@@ -0,0 +1,434 @@
+//! Renderer running on an other thread.
+//!
+//! The GPU calls the `Renderer` methods synchronously while the CPU
+//! runs, which means that the renderer must live on the emulation
+//! thread. GUI toolkits usually want all the drawing to happen on the
+//! main thread though. `render_queue` splits the renderer in two: the
+//! `RenderQueue` half is given to the `Machine` and records the
+//! commands, the `RenderReceiver` half is kept on the UI thread and
+//! replays them on the actual renderer.
+//!
+//! The emulation thread calls `RenderQueue::flush` once per frame
+//! (after `Machine::run_frame`) to hand the recorded commands over.
+//! VRAM reads can't be deferred: `store_image` flushes the queue and
+//! blocks until the UI thread has replayed it and sent the pixels
+//! back. The UI thread must keep calling `RenderReceiver::render` or
+//! `RenderReceiver::render_pending` for that to complete, and the two
+//! halves must never be used from the same thread.
+
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+use super::renderer::{Renderer, PrimitiveAttributes, PrimitiveBuffer,
+                      PrimitiveKind, Vertex, DisplayArea, DisplayLine,
+                      InterlacedField};
+
+/// A `Renderer` call recorded by the `RenderQueue`
+pub enum RenderCommand {
+    SetDrawOffset(i16, i16),
+    SetDrawArea((u16, u16), (u16, u16)),
+    SetDisplayMode((u16, u16), (u16, u16), bool),
+    SetDisplayArea(DisplayArea),
+    SetDisplayLines(Vec<DisplayLine>),
+    /// Consecutive `push_*` and `push_primitives` calls are merged
+    /// into a single buffer
+    Primitives(PrimitiveBuffer),
+    FillRect([u8; 3], (u16, u16), (u16, u16)),
+    LoadImage((u16, u16), (u16, u16), Vec<u16>),
+    CopyRect((u16, u16), (u16, u16), (u16, u16)),
+    /// The pixels are sent back through the channel
+    StoreImage((u16, u16), (u16, u16), Sender<Vec<u16>>),
+    /// The pixels are sent back through the channel, `None` if the
+    /// renderer doesn't support it
+    StoreImageTrueColor((u16, u16), (u16, u16), Sender<Option<Vec<u32>>>),
+    SetField(InterlacedField),
+    ClearCache,
+    SetClutCache(bool),
+    SetTrueColor(bool),
+}
+
+impl RenderCommand {
+    /// Execute the command on `renderer`
+    pub fn replay(self, renderer: &mut Renderer) {
+        match self {
+            RenderCommand::SetDrawOffset(x, y) =>
+                renderer.set_draw_offset(x, y),
+            RenderCommand::SetDrawArea(top_left, bottom_right) =>
+                renderer.set_draw_area(top_left, bottom_right),
+            RenderCommand::SetDisplayMode(top_left, resolution, depth_24) =>
+                renderer.set_display_mode(top_left, resolution, depth_24),
+            RenderCommand::SetDisplayArea(area) =>
+                renderer.set_display_area(&area),
+            RenderCommand::SetDisplayLines(lines) =>
+                renderer.set_display_lines(&lines),
+            RenderCommand::Primitives(buffer) =>
+                renderer.push_primitives(&buffer),
+            RenderCommand::FillRect(color, top_left, dimensions) =>
+                renderer.fill_rect(color, top_left, dimensions),
+            RenderCommand::LoadImage(top_left, dimensions, pixels) =>
+                renderer.load_image(top_left, dimensions, &pixels),
+            RenderCommand::CopyRect(src, dst, dimensions) =>
+                renderer.copy_rect(src, dst, dimensions),
+            RenderCommand::StoreImage(top_left, dimensions, reply) => {
+                let mut pixels = vec![0; pixel_count(dimensions)];
+
+                renderer.store_image(top_left, dimensions, &mut pixels);
+
+                // The emulation thread might be gone, nothing to do
+                let _ = reply.send(pixels);
+            }
+            RenderCommand::StoreImageTrueColor(top_left, dimensions, reply) => {
+                let mut pixels = vec![0; pixel_count(dimensions)];
+
+                let supported =
+                    renderer.store_image_true_color(top_left,
+                                                    dimensions,
+                                                    &mut pixels);
+
+                let _ = reply.send(if supported { Some(pixels) } else { None });
+            }
+            RenderCommand::SetField(field) => renderer.set_field(field),
+            RenderCommand::ClearCache => renderer.clear_cache(),
+            RenderCommand::SetClutCache(enabled) =>
+                renderer.set_clut_cache(enabled),
+            RenderCommand::SetTrueColor(enabled) =>
+                renderer.set_true_color(enabled),
+        }
+    }
+}
+
+/// Create a connected `RenderQueue` and `RenderReceiver`
+pub fn render_queue() -> (RenderQueue, RenderReceiver) {
+    let (sender, receiver) = channel();
+
+    let queue = RenderQueue {
+        commands: Vec::new(),
+        sender: sender,
+    };
+
+    let receiver = RenderReceiver {
+        receiver: receiver,
+    };
+
+    (queue, receiver)
+}
+
+/// Emulation thread half: records the `Renderer` calls
+pub struct RenderQueue {
+    /// Commands recorded since the last flush
+    commands: Vec<RenderCommand>,
+    sender: Sender<Vec<RenderCommand>>,
+}
+
+impl RenderQueue {
+    /// Send the commands recorded so far to the `RenderReceiver`.
+    /// Should be called at the end of every frame.
+    pub fn flush(&mut self) {
+        if self.commands.is_empty() {
+            return;
+        }
+
+        let commands = ::std::mem::replace(&mut self.commands, Vec::new());
+
+        // If the receiver has been dropped there's nobody left to
+        // draw anything
+        let _ = self.sender.send(commands);
+    }
+
+    fn push(&mut self, command: RenderCommand) {
+        self.commands.push(command);
+    }
+
+    /// Return the primitive buffer at the end of the queue, creating
+    /// it if the last command is not a `Primitives`
+    fn primitives(&mut self) -> &mut PrimitiveBuffer {
+        let append =
+            match self.commands.last() {
+                Some(&RenderCommand::Primitives(_)) => false,
+                _ => true,
+            };
+
+        if append {
+            let buffer = PrimitiveBuffer::with_capacity(0);
+
+            self.commands.push(RenderCommand::Primitives(buffer));
+        }
+
+        match self.commands.last_mut() {
+            Some(&mut RenderCommand::Primitives(ref mut b)) => b,
+            _ => unreachable!(),
+        }
+    }
+
+    fn push_primitive(&mut self,
+                      kind: PrimitiveKind,
+                      attributes: &PrimitiveAttributes,
+                      vertices: &[Vertex]) {
+        self.primitives().push(kind, attributes, vertices);
+    }
+}
+
+impl Renderer for RenderQueue {
+    fn set_draw_offset(&mut self, x: i16, y: i16) {
+        self.push(RenderCommand::SetDrawOffset(x, y));
+    }
+
+    fn set_draw_area(&mut self,
+                     top_left: (u16, u16),
+                     bottom_right: (u16, u16)) {
+        self.push(RenderCommand::SetDrawArea(top_left, bottom_right));
+    }
+
+    fn set_display_mode(&mut self,
+                        top_left: (u16, u16),
+                        resolution: (u16, u16),
+                        depth_24bpp: bool) {
+        self.push(RenderCommand::SetDisplayMode(top_left,
+                                                resolution,
+                                                depth_24bpp));
+    }
+
+    fn set_display_area(&mut self, area: &DisplayArea) {
+        self.push(RenderCommand::SetDisplayArea(*area));
+    }
+
+    fn set_display_lines(&mut self, lines: &[DisplayLine]) {
+        self.push(RenderCommand::SetDisplayLines(lines.to_vec()));
+    }
+
+    fn push_line(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 2]) {
+        self.push_primitive(PrimitiveKind::Line, attributes, vertices);
+    }
+
+    fn push_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     vertices: &[Vertex; 3]) {
+        self.push_primitive(PrimitiveKind::Triangle, attributes, vertices);
+    }
+
+    fn push_quad(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 4]) {
+        self.push_primitive(PrimitiveKind::Quad, attributes, vertices);
+    }
+
+    fn push_primitives(&mut self, buffer: &PrimitiveBuffer) {
+        let primitives = self.primitives();
+
+        for p in buffer.iter() {
+            primitives.push(p.kind, p.attributes, p.vertices);
+        }
+    }
+
+    fn fill_rect(&mut self,
+                 color: [u8; 3],
+                 top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        self.push(RenderCommand::FillRect(color, top_left, dimensions));
+    }
+
+    fn load_image(&mut self,
+                  top_left: (u16, u16),
+                  dimensions: (u16, u16),
+                  pixel_buffer: &[u16]) {
+        let pixels = pixel_buffer[..pixel_count(dimensions)].to_vec();
+
+        self.push(RenderCommand::LoadImage(top_left, dimensions, pixels));
+    }
+
+    fn copy_rect(&mut self,
+                 src_top_left: (u16, u16),
+                 dst_top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        self.push(RenderCommand::CopyRect(src_top_left,
+                                          dst_top_left,
+                                          dimensions));
+    }
+
+    fn store_image(&mut self,
+                   top_left: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]) {
+        let (reply, pixels) = channel();
+
+        self.push(RenderCommand::StoreImage(top_left, dimensions, reply));
+        self.flush();
+
+        let len = pixel_count(dimensions);
+
+        match pixels.recv() {
+            Ok(p) => pixel_buffer[..len].copy_from_slice(&p),
+            Err(_) => {
+                // The UI thread is gone
+                for p in pixel_buffer[..len].iter_mut() {
+                    *p = 0;
+                }
+            }
+        }
+    }
+
+    fn set_field(&mut self, field: InterlacedField) {
+        self.push(RenderCommand::SetField(field));
+    }
+
+    fn clear_cache(&mut self) {
+        self.push(RenderCommand::ClearCache);
+    }
+
+    fn set_clut_cache(&mut self, enabled: bool) {
+        self.push(RenderCommand::SetClutCache(enabled));
+    }
+
+    fn set_true_color(&mut self, enabled: bool) {
+        self.push(RenderCommand::SetTrueColor(enabled));
+    }
+
+    fn store_image_true_color(&mut self,
+                              top_left: (u16, u16),
+                              dimensions: (u16, u16),
+                              pixel_buffer: &mut [u32]) -> bool {
+        let (reply, pixels) = channel();
+
+        self.push(RenderCommand::StoreImageTrueColor(top_left,
+                                                     dimensions,
+                                                     reply));
+        self.flush();
+
+        let len = pixel_count(dimensions);
+
+        match pixels.recv() {
+            Ok(Some(p)) => {
+                pixel_buffer[..len].copy_from_slice(&p);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// UI thread half: replays the commands recorded by the `RenderQueue`
+pub struct RenderReceiver {
+    receiver: Receiver<Vec<RenderCommand>>,
+}
+
+impl RenderReceiver {
+    /// Wait for the next batch of commands (usually a frame) and
+    /// replay it on `renderer`. Returns false if the `RenderQueue` has
+    /// been dropped.
+    pub fn render(&self, renderer: &mut Renderer) -> bool {
+        match self.receiver.recv() {
+            Ok(commands) => {
+                replay(commands, renderer);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Replay all the commands received so far without blocking.
+    /// Returns the number of batches replayed.
+    pub fn render_pending(&self, renderer: &mut Renderer) -> usize {
+        let mut batches = 0;
+
+        while let Ok(commands) = self.receiver.try_recv() {
+            replay(commands, renderer);
+            batches += 1;
+        }
+
+        batches
+    }
+}
+
+fn replay(commands: Vec<RenderCommand>, renderer: &mut Renderer) {
+    for c in commands {
+        c.replay(renderer);
+    }
+}
+
+fn pixel_count(dimensions: (u16, u16)) -> usize {
+    dimensions.0 as usize * dimensions.1 as usize
+}
+
+#[test]
+fn render_thread() {
+    use std::thread;
+
+    /// Renderer counting the rectangles and returning a fixed pixel
+    /// value for VRAM reads
+    struct TestRenderer {
+        fills: u32,
+    }
+
+    impl Renderer for TestRenderer {
+        fn set_draw_offset(&mut self, _: i16, _: i16) {
+        }
+
+        fn set_draw_area(&mut self, _: (u16, u16), _: (u16, u16)) {
+        }
+
+        fn set_display_mode(&mut self,
+                            _: (u16, u16),
+                            _: (u16, u16),
+                            _: bool) {
+        }
+
+        fn push_line(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 2]) {
+        }
+
+        fn push_triangle(&mut self,
+                         _: &PrimitiveAttributes,
+                         _: &[Vertex; 3]) {
+        }
+
+        fn push_quad(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 4]) {
+        }
+
+        fn fill_rect(&mut self, _: [u8; 3], _: (u16, u16), _: (u16, u16)) {
+            self.fills += 1;
+        }
+
+        fn load_image(&mut self, _: (u16, u16), _: (u16, u16), _: &[u16]) {
+        }
+
+        fn copy_rect(&mut self,
+                     _: (u16, u16),
+                     _: (u16, u16),
+                     _: (u16, u16)) {
+        }
+
+        fn store_image(&mut self,
+                       _: (u16, u16),
+                       dimensions: (u16, u16),
+                       pixel_buffer: &mut [u16]) {
+            for p in pixel_buffer[..pixel_count(dimensions)].iter_mut() {
+                *p = 0x1234;
+            }
+        }
+    }
+
+    let (mut queue, receiver) = render_queue();
+
+    let ui = thread::spawn(move || {
+        let mut renderer = TestRenderer { fills: 0 };
+
+        while receiver.render(&mut renderer) {
+        }
+
+        renderer.fills
+    });
+
+    queue.fill_rect([0; 3], (0, 0), (16, 16));
+
+    // Blocks until the UI thread replied
+    let mut pixels = [0; 4];
+    queue.store_image((0, 0), (2, 2), &mut pixels);
+    assert_eq!(pixels, [0x1234; 4]);
+
+    queue.fill_rect([0; 3], (0, 0), (16, 16));
+    queue.flush();
+
+    drop(queue);
+
+    assert_eq!(ui.join().unwrap(), 2);
+}
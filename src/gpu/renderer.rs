@@ -1,16 +1,64 @@
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
 pub trait Renderer {
     fn set_draw_offset(&mut self, x: i16, y: i16);
-    fn set_draw_area(&mut self, top_left: (u16, u16), dimensions: (u16, u16));
+
+    /// Set the clipping rectangle. Both corners are inclusive: a
+    /// drawing area going from (0, 0) to (319, 239) is 320x240
+    /// pixels. If `top_left` is below or to the right of
+    /// `bottom_right` nothing gets drawn, the GPU won't even send the
+    /// primitives.
+    fn set_draw_area(&mut self, top_left: (u16, u16), bottom_right: (u16, u16));
 
     fn set_display_mode(&mut self,
                         top_left: (u16, u16),
                         resolution: (u16, u16),
                         depth_24bpp: bool);
 
+    /// Called alongside `set_display_mode` and whenever the display
+    /// range registers change, describes the part of the display
+    /// area actually visible on a TV screen
+    fn set_display_area(&mut self, _area: &DisplayArea) {
+    }
+
+    /// Called once per frame when the scanline timing is enabled (see
+    /// `Gpu::set_scanline_timing`) and the game changed the display
+    /// area while the frame was being output, for instance to shear
+    /// the picture. Each entry gives the area displayed from its line
+    /// up to the next entry. Renderers that don't implement it show
+    /// the entire frame with the last area received by
+    /// `set_display_area`.
+    fn set_display_lines(&mut self, _lines: &[DisplayLine]) {
+    }
+
     fn push_line(&mut self, &PrimitiveAttributes, &[Vertex; 2]);
     fn push_triangle(&mut self, &PrimitiveAttributes, &[Vertex; 3]);
     fn push_quad(&mut self, &PrimitiveAttributes, &[Vertex; 4]);
 
+    /// Draw all the primitives in `buffer`, in order. The GPU doesn't
+    /// call `push_line`, `push_triangle` and `push_quad` directly:
+    /// it accumulates the primitives and only sends them when the
+    /// renderer is about to be called for something else (state
+    /// change, VRAM transfer, end of frame...). Renderers which
+    /// override this method can upload `buffer.vertices()` in one go
+    /// instead of handling the primitives one at a time. The default
+    /// implementation forwards each primitive to the `push_*`
+    /// methods.
+    fn push_primitives(&mut self, buffer: &PrimitiveBuffer) {
+        for p in buffer.iter() {
+            let v = p.vertices;
+
+            match p.kind {
+                PrimitiveKind::Line =>
+                    self.push_line(p.attributes, &[v[0], v[1]]),
+                PrimitiveKind::Triangle =>
+                    self.push_triangle(p.attributes, &[v[0], v[1], v[2]]),
+                PrimitiveKind::Quad =>
+                    self.push_quad(p.attributes, &[v[0], v[1], v[2], v[3]]),
+            }
+        }
+    }
+
     fn fill_rect(&mut self,
                  color: [u8; 3],
                  top_left: (u16, u16),
@@ -20,12 +68,74 @@ pub trait Renderer {
                   top_left: (u16, u16),
                   dimensions: (u16, u16),
                   pixel_buffer: &[u16]);
+
+    /// Copy a rectangle of VRAM to an other location. Both rectangles
+    /// can overflow the VRAM in which case the coordinates wrap
+    /// around.
+    fn copy_rect(&mut self,
+                 src_top_left: (u16, u16),
+                 dst_top_left: (u16, u16),
+                 dimensions: (u16, u16));
+
+    /// Read a rectangle of VRAM into `pixel_buffer` (line by line,
+    /// `dimensions.0 * dimensions.1` pixels). The rectangle can
+    /// overflow the VRAM in which case the coordinates wrap around.
+    fn store_image(&mut self,
+                   top_left: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]);
+
+    /// Called at the start of each field in 480 line interlaced mode
+    /// with the field about to be displayed. The renderer can either
+    /// weave the last two fields together or bob the current one, see
+    /// `deinterlace`.
+    fn set_field(&mut self, _field: InterlacedField) {
+    }
+
+    /// GP0(0x01): flush the texture and CLUT caches. Renderers using
+    /// a `ClutCache` must invalidate it here.
+    fn clear_cache(&mut self) {
+    }
+
+    /// Enable or disable the CLUT cache emulation (see `ClutCache`).
+    /// Called before the next GP0 command whenever the setting
+    /// changes.
+    fn set_clut_cache(&mut self, _enabled: bool) {
+    }
+
+    /// Enable or disable the true color mode (see
+    /// `PrimitiveAttributes::true_color`). Called before the next GP0
+    /// command whenever the setting changes. Renderers can then keep
+    /// a 32bit framebuffer internally and blend at full precision, as
+    /// long as the game still reads 15bit values back (see
+    /// `store_image_true_color`).
+    fn set_true_color(&mut self, _enabled: bool) {
+    }
+
+    /// Used instead of `store_image` in true color mode: read a
+    /// rectangle of the 32bit framebuffer into `pixel_buffer`, one
+    /// `0x0MBBGGRR` value per pixel where `M` is the mask bit. The GPU
+    /// converts them to the 15bit VRAM format with `read_back`.
+    /// Returns false if the renderer doesn't keep a 32bit framebuffer
+    /// (the default), `store_image` is then called instead.
+    fn store_image_true_color(&mut self,
+                              _top_left: (u16, u16),
+                              _dimensions: (u16, u16),
+                              _pixel_buffer: &mut [u32]) -> bool {
+        false
+    }
 }
 
+#[derive(Clone, Copy)]
 pub struct Vertex {
     pub position: [i16; 2],
     pub color: [u8; 3],
     pub texture_coord: [u16; 2],
+    /// Full precision screen coordinates and depth `[x, y, z]` when
+    /// PGXP is enabled and the vertex could be traced back to the
+    /// GTE. The renderer can use them instead of `position` to avoid
+    /// vertex wobble and for depth testing.
+    pub precise: Option<[f32; 3]>,
 }
 
 impl Vertex {
@@ -35,6 +145,7 @@ impl Vertex {
             color: color,
             // Unused
             texture_coord: [0, 0],
+            precise: None,
         }
     }
 
@@ -45,11 +156,12 @@ impl Vertex {
             position: position,
             color: color,
             texture_coord: texture_coord,
+            precise: None,
         }
     }
 }
 
-#[derive(RustcDecodable, RustcEncodable)]
+#[derive(Clone, PartialEq, RustcDecodable, RustcEncodable)]
 pub struct PrimitiveAttributes {
     /// If true then the equation defined by `semi_transparency_mode`
     /// is applied to semi-transparent pixels.
@@ -76,8 +188,170 @@ pub struct PrimitiveAttributes {
     pub clut: [u16; 2],
     /// True if the primitive is dithered.
     pub dither: bool,
+    /// True if the frontend asked for 24bit output: the renderer
+    /// should keep the full color precision instead of truncating to
+    /// 15bits. `dither` is always false in this mode.
+    pub true_color: bool,
+    /// For textured rectangles: the texture is mirrored horizontally,
+    /// the texture coordinates decrement from the left edge instead
+    /// of incrementing. Always false for other primitives.
+    pub flip_x: bool,
+    /// Same as `flip_x` but vertically
+    pub flip_y: bool,
+}
+
+/// Type of a primitive in a `PrimitiveBuffer`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PrimitiveKind {
+    Line = 2,
+    Triangle = 3,
+    Quad = 4,
 }
 
+impl PrimitiveKind {
+    /// Number of vertices of the primitive
+    pub fn vertex_count(self) -> usize {
+        self as usize
+    }
+}
+
+/// Primitive stored in a `PrimitiveBuffer`
+pub struct Primitive<'a> {
+    pub kind: PrimitiveKind,
+    pub attributes: &'a PrimitiveAttributes,
+    /// `kind.vertex_count()` vertices
+    pub vertices: &'a [Vertex],
+}
+
+/// List of primitives waiting to be drawn. The GPU keeps the same
+/// buffer from one frame to the next, once it has grown to the size
+/// of a typical frame pushing primitives doesn't allocate anymore.
+pub struct PrimitiveBuffer {
+    /// Vertices of all the primitives, back to back
+    vertices: Vec<Vertex>,
+    /// Attributes of the primitives. Consecutive primitives usually
+    /// share the same attributes so they're only stored once.
+    attributes: Vec<PrimitiveAttributes>,
+    /// Kind of each primitive and index of its attributes
+    primitives: Vec<(PrimitiveKind, u32)>,
+}
+
+impl PrimitiveBuffer {
+    pub fn new() -> PrimitiveBuffer {
+        PrimitiveBuffer::with_capacity(PRIMITIVE_BUFFER_CAPACITY)
+    }
+
+    /// Create a buffer with room for `primitives` quads
+    pub fn with_capacity(primitives: usize) -> PrimitiveBuffer {
+        PrimitiveBuffer {
+            vertices: Vec::with_capacity(primitives * 4),
+            attributes: Vec::with_capacity(primitives),
+            primitives: Vec::with_capacity(primitives),
+        }
+    }
+
+    /// Append a primitive. `vertices` must contain
+    /// `kind.vertex_count()` vertices.
+    pub fn push(&mut self,
+                kind: PrimitiveKind,
+                attributes: &PrimitiveAttributes,
+                vertices: &[Vertex]) {
+        debug_assert!(vertices.len() == kind.vertex_count());
+
+        if self.attributes.last() != Some(attributes) {
+            self.attributes.push(attributes.clone());
+        }
+
+        let index = self.attributes.len() as u32 - 1;
+
+        self.primitives.push((kind, index));
+        self.vertices.extend_from_slice(vertices);
+    }
+
+    /// Remove all the primitives, the memory is kept for reuse
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.attributes.clear();
+        self.primitives.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    /// Number of primitives in the buffer
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    /// Vertices of all the primitives in drawing order
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    /// Iterate over the primitives in drawing order
+    pub fn iter(&self) -> Primitives {
+        Primitives {
+            buffer: self,
+            index: 0,
+            vertex: 0,
+        }
+    }
+}
+
+impl Encodable for PrimitiveBuffer {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // The buffer is flushed at the end of every frame, no need to
+        // store it
+        s.emit_nil()
+    }
+}
+
+impl Decodable for PrimitiveBuffer {
+    fn decode<D: Decoder>(d: &mut D) -> Result<PrimitiveBuffer, D::Error> {
+        try!(d.read_nil());
+
+        Ok(PrimitiveBuffer::new())
+    }
+}
+
+/// Iterator over the contents of a `PrimitiveBuffer`
+pub struct Primitives<'a> {
+    buffer: &'a PrimitiveBuffer,
+    /// Index of the next primitive
+    index: usize,
+    /// Index of the first vertex of the next primitive
+    vertex: usize,
+}
+
+impl<'a> Iterator for Primitives<'a> {
+    type Item = Primitive<'a>;
+
+    fn next(&mut self) -> Option<Primitive<'a>> {
+        let (kind, attributes) =
+            match self.buffer.primitives.get(self.index) {
+                Some(&p) => p,
+                None => return None,
+            };
+
+        let start = self.vertex;
+        let end = start + kind.vertex_count();
+
+        self.index += 1;
+        self.vertex = end;
+
+        Some(Primitive {
+            kind: kind,
+            attributes: &self.buffer.attributes[attributes as usize],
+            vertices: &self.buffer.vertices[start..end],
+        })
+    }
+}
+
+/// Number of quads a `PrimitiveBuffer` can hold before it needs to
+/// grow
+const PRIMITIVE_BUFFER_CAPACITY: usize = 1024;
+
 /// Primitive texturing methods
 #[derive(Clone, Copy, PartialEq, Eq, RustcDecodable, RustcEncodable)]
 pub enum BlendMode {
@@ -103,7 +377,7 @@ pub enum SemiTransparencyMode {
 }
 
 /// Depth of the pixel values in a texture page
-#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+#[derive(Clone, Copy, PartialEq, Eq, RustcDecodable, RustcEncodable)]
 pub enum TextureDepth {
     /// 4 bits per pixel, paletted
     T4Bpp = 0,
@@ -112,3 +386,291 @@ pub enum TextureDepth {
     /// 16 bits per pixel, truecolor
     T16Bpp = 2,
 }
+
+/// Visible portion of the display area, computed from the display
+/// range registers (GP1(0x06) and GP1(0x07))
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DisplayArea {
+    /// Top left corner of the display area in VRAM
+    pub top_left: (u16, u16),
+    /// Dimensions of the visible part of the display area in VRAM
+    /// pixels, starting at `top_left`
+    pub visible: (u16, u16),
+    /// Position of the visible part within the standard TV frame, in
+    /// output pixels. Can be negative if the game starts displaying
+    /// before the standard frame.
+    pub frame_offset: (i16, i16),
+    /// Dimensions of the standard TV frame in output pixels for the
+    /// current horizontal resolution
+    pub frame: (u16, u16),
+}
+
+impl DisplayArea {
+    /// Return the aspect ratio (width / height) to use to display the
+    /// `visible` area so that the full TV frame is 4:3 whatever the
+    /// horizontal resolution. Use it when cropping the overscan.
+    pub fn cropped_aspect_ratio(&self) -> f32 {
+        let (vw, vh) = self.visible;
+        let (fw, fh) = self.frame;
+
+        if vw == 0 || vh == 0 {
+            return 4. / 3.;
+        }
+
+        let w = vw as f32 / fw as f32;
+        let h = vh as f32 / fh as f32;
+
+        4. / 3. * w / h
+    }
+}
+
+/// Display area latched at the start of a line of the output
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DisplayLine {
+    /// Line number counted from the first displayed line. In 480 line
+    /// interlaced mode each line covers two lines of the display
+    /// area, one per field.
+    pub line: u16,
+    pub area: DisplayArea,
+}
+
+/// Field displayed in 480 line interlaced mode
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InterlacedField {
+    /// Even VRAM lines relative to the start of the display area
+    Even = 0,
+    /// Odd VRAM lines
+    Odd = 1,
+}
+
+impl InterlacedField {
+    /// Return the VRAM line displayed as line `line` of the field
+    /// when the display area starts at `y_start`
+    pub fn vram_line(self, y_start: u16, line: u16) -> u16 {
+        (y_start + line * 2 + self as u16) & 0x1ff
+    }
+}
+
+/// How to display interlaced video
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Deinterlacing {
+    /// Show both fields at once. Full vertical resolution but moving
+    /// objects get "combed".
+    Weave,
+    /// Only show the current field, each line is doubled. Halves the
+    /// vertical resolution but doesn't comb.
+    Bob,
+}
+
+/// Deinterlace the 480 line frame `pixels` (line by line, `width`
+/// pixels per line) in place. `field` is the field currently
+/// displayed.
+pub fn deinterlace<T: Copy>(mode: Deinterlacing,
+                            field: InterlacedField,
+                            pixels: &mut [T],
+                            width: usize) {
+    if mode == Deinterlacing::Weave || width == 0 {
+        // The frame already contains both fields
+        return;
+    }
+
+    let lines = pixels.len() / width;
+    let first = field as usize;
+
+    for y in (0..lines).filter(|y| y & 1 == first) {
+        // Copy the line over the neighbouring line of the other field
+        let other = if first == 0 { y + 1 } else { y - 1 };
+
+        if other >= lines {
+            continue;
+        }
+
+        for x in 0..width {
+            pixels[other * width + x] = pixels[y * width + x];
+        }
+    }
+}
+
+/// The 4x4 ordered dithering matrix used by the GPU, indexed by
+/// `[y & 3][x & 3]`. The offset is added to each 8bit color component
+/// before it's truncated to 5 bits.
+pub const DITHER_MATRIX: [[i8; 4]; 4] = [
+    [-4,  0, -3,  1],
+    [ 2, -2,  3, -1],
+    [-3,  1, -4,  0],
+    [ 3, -1,  2, -2],
+    ];
+
+/// Convert the 24bit `color` of the pixel at `(x, y)` in VRAM into
+/// the 15bit value written to the framebuffer (without the mask
+/// bit), dithering it if `dither` is true. The coordinates are
+/// absolute VRAM coordinates (the drawing offset already applied).
+pub fn quantize(color: [u8; 3], x: u16, y: u16, dither: bool) -> u16 {
+    let offset =
+        if dither {
+            DITHER_MATRIX[(y & 3) as usize][(x & 3) as usize] as i16
+        } else {
+            0
+        };
+
+    let component = |c: u8| {
+        let c = c as i16 + offset;
+
+        // The result saturates instead of wrapping around
+        let c = if c < 0 { 0 } else if c > 0xff { 0xff } else { c };
+
+        (c as u16) >> 3
+    };
+
+    component(color[0]) |
+    (component(color[1]) << 5) |
+    (component(color[2]) << 10)
+}
+
+/// Convert a pixel of a true color framebuffer (`0x0MBBGGRR`, see
+/// `Renderer::store_image_true_color`) to the 15bit value the real
+/// GPU would have stored in VRAM
+pub fn read_back(pixel: u32) -> u16 {
+    let color = [pixel as u8, (pixel >> 8) as u8, (pixel >> 16) as u8];
+    let mask = ((pixel >> 24) & 1) as u16;
+
+    quantize(color, 0, 0, false) | (mask << 15)
+}
+
+/// Apply the semi-transparency equation `mode` at 8bit per
+/// component precision. `back` is the color already in the
+/// framebuffer and `front` the color of the primitive. The results
+/// saturate like on the real GPU.
+pub fn blend(mode: SemiTransparencyMode,
+             back: [u8; 3],
+             front: [u8; 3]) -> [u8; 3] {
+    let mut out = [0; 3];
+
+    for i in 0..3 {
+        let b = back[i] as i32;
+        let f = front[i] as i32;
+
+        let v =
+            match mode {
+                SemiTransparencyMode::Average => (b + f) / 2,
+                SemiTransparencyMode::Add => b + f,
+                SemiTransparencyMode::SubstractSource => b - f,
+                SemiTransparencyMode::AddQuarterSource => b + f / 4,
+            };
+
+        out[i] = if v < 0 { 0 } else if v > 0xff { 0xff } else { v as u8 };
+    }
+
+    out
+}
+
+#[test]
+fn dithering() {
+    let white = [0xff; 3];
+    let black = [0; 3];
+    let grey = [0x83, 0x83, 0x83];
+
+    // Saturation
+    assert_eq!(quantize(white, 2, 1, true), 0x7fff);
+    assert_eq!(quantize(black, 0, 0, true), 0);
+
+    // 0x83 is 16.375 in 5 bits, the matrix rounds it either way
+    assert_eq!(quantize(grey, 0, 0, false), 0x4210);
+    assert_eq!(quantize(grey, 0, 0, true), 0x3def);
+    assert_eq!(quantize(grey, 0, 1, true), 0x4210);
+    assert_eq!(quantize(grey, 4, 4, true), 0x3def);
+}
+
+#[test]
+fn bob() {
+    let mut frame = [0, 0, 1, 1, 2, 2, 3, 3];
+
+    deinterlace(Deinterlacing::Bob, InterlacedField::Odd, &mut frame, 2);
+
+    assert_eq!(frame, [1, 1, 1, 1, 3, 3, 3, 3]);
+
+    deinterlace(Deinterlacing::Bob, InterlacedField::Even, &mut frame, 2);
+
+    assert_eq!(frame, [1, 1, 1, 1, 3, 3, 3, 3]);
+
+    assert_eq!(InterlacedField::Odd.vram_line(0x1ff, 0), 0);
+}
+
+#[test]
+fn cropped_aspect_ratio() {
+    let area = DisplayArea {
+        top_left: (0, 0),
+        visible: (320, 224),
+        frame_offset: (0, 8),
+        frame: (320, 240),
+    };
+
+    let ratio = area.cropped_aspect_ratio();
+
+    assert!((ratio - 4. / 3. * 240. / 224.).abs() < 0.0001);
+}
+
+#[test]
+fn true_color() {
+    assert_eq!(read_back(0x00ffffff), 0x7fff);
+    assert_eq!(read_back(0x01000000), 0x8000);
+    // Truncated like a non-dithered primitive
+    assert_eq!(read_back(0x000f0807), 0x0420);
+
+    let back = [0x80, 0x10, 0xf0];
+    let front = [0x81, 0x20, 0x40];
+
+    assert_eq!(blend(SemiTransparencyMode::Average, back, front),
+               [0x80, 0x18, 0x98]);
+    assert_eq!(blend(SemiTransparencyMode::Add, back, front),
+               [0xff, 0x30, 0xff]);
+    assert_eq!(blend(SemiTransparencyMode::SubstractSource, back, front),
+               [0, 0, 0xb0]);
+    assert_eq!(blend(SemiTransparencyMode::AddQuarterSource, back, front),
+               [0xa0, 0x18, 0xff]);
+}
+
+#[test]
+fn primitive_buffer() {
+    let mut attributes = PrimitiveAttributes {
+        semi_transparent: false,
+        semi_transparency_mode: SemiTransparencyMode::Average,
+        blend_mode: BlendMode::None,
+        texture_page: [0, 0],
+        texture_depth: TextureDepth::T4Bpp,
+        clut: [0, 0],
+        dither: false,
+        true_color: false,
+        flip_x: false,
+        flip_y: false,
+    };
+
+    let v = |x| Vertex::new([x, 0], [0; 3]);
+
+    let mut buffer = PrimitiveBuffer::with_capacity(4);
+
+    buffer.push(PrimitiveKind::Triangle, &attributes, &[v(0), v(1), v(2)]);
+    buffer.push(PrimitiveKind::Line, &attributes, &[v(3), v(4)]);
+
+    attributes.dither = true;
+
+    buffer.push(PrimitiveKind::Quad, &attributes, &[v(5), v(6), v(7), v(8)]);
+
+    assert_eq!(buffer.len(), 3);
+    assert_eq!(buffer.vertices().len(), 9);
+    // The first two primitives share their attributes
+    assert_eq!(buffer.attributes.len(), 2);
+
+    let primitives: Vec<_> = buffer.iter().collect();
+
+    assert_eq!(primitives[1].kind, PrimitiveKind::Line);
+    assert_eq!(primitives[1].vertices[0].position, [3, 0]);
+    assert!(!primitives[1].attributes.dither);
+    assert_eq!(primitives[2].vertices[3].position, [8, 0]);
+    assert!(primitives[2].attributes.dither);
+
+    buffer.clear();
+
+    assert!(buffer.is_empty());
+    assert_eq!(buffer.iter().count(), 0);
+}
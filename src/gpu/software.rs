@@ -0,0 +1,508 @@
+//! Minimal software renderer.
+//!
+//! Draws the primitives on the CPU in a 16bit VRAM buffer, the same
+//! way the real GPU does. It's meant for the frontends which don't
+//! have a hardware accelerated renderer (the SDL2 example, the web
+//! frontend) so it favours simplicity over speed and accuracy: the
+//! mask bit and the texture window are ignored and the textured
+//! rectangle flip bits aren't supported.
+//!
+//! The frontend retrieves the picture with `frame` once per frame.
+
+use super::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT, VRAM_SIZE_PIXELS};
+use super::clut::ClutCache;
+use super::renderer::{Renderer, PrimitiveAttributes, Vertex, BlendMode,
+                      TextureDepth, quantize, blend};
+
+pub struct SoftwareRenderer {
+    /// VRAM contents, line by line
+    vram: Vec<u16>,
+    /// Offset added to all the vertex coordinates
+    draw_offset: (i16, i16),
+    /// Drawing area, both corners are inclusive
+    draw_area: ((u16, u16), (u16, u16)),
+    /// Top left corner of the displayed area in VRAM
+    display_top_left: (u16, u16),
+    /// Resolution of the displayed area
+    display_resolution: (u16, u16),
+    /// True if the display area uses the 24bpp format
+    display_24bpp: bool,
+    clut_cache: ClutCache,
+}
+
+impl SoftwareRenderer {
+    pub fn new() -> SoftwareRenderer {
+        SoftwareRenderer {
+            vram: vec![0; VRAM_SIZE_PIXELS],
+            draw_offset: (0, 0),
+            draw_area: ((0, 0), (0, 0)),
+            display_top_left: (0, 0),
+            display_resolution: (0, 0),
+            display_24bpp: false,
+            clut_cache: ClutCache::new(),
+        }
+    }
+
+    /// Return the VRAM contents, line by line
+    pub fn vram(&self) -> &[u16] {
+        &self.vram
+    }
+
+    /// Convert the displayed area into `0x00RRGGBB` pixels, line by
+    /// line. Returns the resolution of the picture.
+    pub fn frame(&self, out: &mut Vec<u32>) -> (u16, u16) {
+        let (left, top) = self.display_top_left;
+        let (width, height) = self.display_resolution;
+
+        out.clear();
+        out.reserve(width as usize * height as usize);
+
+        for y in 0..height {
+            let y = top.wrapping_add(y);
+
+            for x in 0..width {
+                let rgb =
+                    if self.display_24bpp {
+                        // Three bytes per pixel, packed in the 16bit
+                        // VRAM words
+                        let byte = |n: u16| {
+                            let w = self.pixel(left.wrapping_add(n / 2), y);
+
+                            (w >> ((n & 1) * 8)) as u8
+                        };
+
+                        [byte(x * 3), byte(x * 3 + 1), byte(x * 3 + 2)]
+                    } else {
+                        to_rgb(self.pixel(left.wrapping_add(x), y))
+                    };
+
+                out.push(((rgb[0] as u32) << 16) |
+                         ((rgb[1] as u32) << 8) |
+                         (rgb[2] as u32));
+            }
+        }
+
+        (width, height)
+    }
+
+    fn pixel(&self, x: u16, y: u16) -> u16 {
+        self.vram[vram_index(x, y)]
+    }
+
+    fn set_pixel(&mut self, x: u16, y: u16, val: u16) {
+        self.vram[vram_index(x, y)] = val;
+    }
+
+    /// Return `(x, y)` as VRAM coordinates if it's within
+    /// the drawing area
+    fn clip(&self, x: i32, y: i32) -> Option<(u16, u16)> {
+        let ((left, top), (right, bottom)) = self.draw_area;
+
+        if x < left as i32 || x > right as i32 ||
+            y < top as i32 || y > bottom as i32 {
+            None
+        } else {
+            Some((x as u16, y as u16))
+        }
+    }
+
+    fn position(&self, v: &Vertex) -> (i32, i32) {
+        (v.position[0] as i32 + self.draw_offset.0 as i32,
+         v.position[1] as i32 + self.draw_offset.1 as i32)
+    }
+
+    fn draw_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     v: [&Vertex; 3]) {
+        let mut v = v;
+        let mut p = [self.position(v[0]),
+                     self.position(v[1]),
+                     self.position(v[2])];
+
+        let mut area = edge(p[0], p[1], p[2]);
+
+        if area == 0 {
+            return;
+        }
+
+        // Use a consistent winding so that the inside of the triangle
+        // is always on the positive side of the edges
+        if area < 0 {
+            v.swap(1, 2);
+            p.swap(1, 2);
+            area = -area;
+        }
+
+        let ((left, top), (right, bottom)) = self.draw_area;
+
+        let x_min = p.iter().map(|p| p.0).min().unwrap();
+        let x_max = p.iter().map(|p| p.0).max().unwrap();
+        let y_min = p.iter().map(|p| p.1).min().unwrap();
+        let y_max = p.iter().map(|p| p.1).max().unwrap();
+
+        let x_min = x_min.max(left as i32);
+        let x_max = x_max.min(right as i32);
+        let y_min = y_min.max(top as i32);
+        let y_max = y_max.min(bottom as i32);
+
+        let edges = [(p[1], p[2]), (p[2], p[0]), (p[0], p[1])];
+
+        for y in y_min..(y_max + 1) {
+            for x in x_min..(x_max + 1) {
+                let mut w = [0; 3];
+                let mut inside = true;
+
+                for (i, &(a, b)) in edges.iter().enumerate() {
+                    w[i] = edge(a, b, (x, y));
+
+                    // The GPU doesn't draw the pixels on the bottom
+                    // and right edges
+                    if w[i] < 0 || (w[i] == 0 && !is_top_left(a, b)) {
+                        inside = false;
+                    }
+                }
+
+                if !inside {
+                    continue;
+                }
+
+                let interpolate = |c: [i32; 3]| {
+                    (w[0] * c[0] + w[1] * c[1] + w[2] * c[2]) / area
+                };
+
+                let mut color = [0; 3];
+
+                for i in 0..3 {
+                    let c = [v[0].color[i] as i32,
+                             v[1].color[i] as i32,
+                             v[2].color[i] as i32];
+
+                    color[i] = interpolate(c) as u8;
+                }
+
+                let uv = |i: usize| {
+                    interpolate([v[0].texture_coord[i] as i32,
+                                 v[1].texture_coord[i] as i32,
+                                 v[2].texture_coord[i] as i32]) as u8
+                };
+
+                let uv = [uv(0), uv(1)];
+
+                self.shade(attributes, x as u16, y as u16, color, uv);
+            }
+        }
+    }
+
+    fn draw_line(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 v: [&Vertex; 2]) {
+        let (x0, y0) = self.position(v[0]);
+        let (x1, y1) = self.position(v[1]);
+
+        let steps = ::std::cmp::max((x1 - x0).abs(), (y1 - y0).abs());
+
+        for s in 0..(steps + 1) {
+            let lerp = |a: i32, b: i32| {
+                if steps == 0 {
+                    a
+                } else {
+                    a + (b - a) * s / steps
+                }
+            };
+
+            let mut color = [0; 3];
+
+            for i in 0..3 {
+                color[i] = lerp(v[0].color[i] as i32,
+                                v[1].color[i] as i32) as u8;
+            }
+
+            if let Some((x, y)) = self.clip(lerp(x0, x1), lerp(y0, y1)) {
+                self.shade(attributes, x, y, color, [0, 0]);
+            }
+        }
+    }
+
+    /// Draw the pixel at `(x, y)` with the shading `color` and
+    /// texture coordinates `uv`
+    fn shade(&mut self,
+             attributes: &PrimitiveAttributes,
+             x: u16,
+             y: u16,
+             color: [u8; 3],
+             uv: [u8; 2]) {
+        let (color, semi_transparent) =
+            match attributes.blend_mode {
+                BlendMode::None => (color, attributes.semi_transparent),
+                mode => {
+                    let texel = self.texel(attributes, uv);
+
+                    // Fully transparent texel
+                    if texel == 0 {
+                        return;
+                    }
+
+                    let t = to_rgb(texel);
+
+                    let color =
+                        if mode == BlendMode::Raw {
+                            t
+                        } else {
+                            // 0x80 is the neutral shading color
+                            let modulate = |i: usize| {
+                                let c = (t[i] as u32 * color[i] as u32) >> 7;
+
+                                ::std::cmp::min(c, 0xff) as u8
+                            };
+
+                            [modulate(0), modulate(1), modulate(2)]
+                        };
+
+                    // Bit 15 of the texel enables the
+                    // semi-transparency
+                    (color,
+                     attributes.semi_transparent && texel & 0x8000 != 0)
+                }
+            };
+
+        let color =
+            if semi_transparent {
+                let back = to_rgb(self.pixel(x, y));
+
+                blend(attributes.semi_transparency_mode, back, color)
+            } else {
+                color
+            };
+
+        let pixel = quantize(color, x, y, attributes.dither);
+
+        self.set_pixel(x, y, pixel);
+    }
+
+    /// Fetch the texel at `uv` in the texture page of `attributes`
+    fn texel(&mut self, attributes: &PrimitiveAttributes, uv: [u8; 2]) -> u16 {
+        let px = attributes.texture_page[0];
+        let py = attributes.texture_page[1];
+        let u = uv[0] as u16;
+        let y = py + uv[1] as u16;
+
+        let (x, shift, mask) =
+            match attributes.texture_depth {
+                TextureDepth::T4Bpp => (px + u / 4, (u & 3) * 4, 0xf),
+                TextureDepth::T8Bpp => (px + u / 2, (u & 1) * 8, 0xff),
+                TextureDepth::T16Bpp => return self.pixel(px + u, y),
+            };
+
+        let index = (self.pixel(x, y) >> shift) & mask;
+
+        // Paletted texture, the cache always returns a palette
+        let palette = self.clut_cache.palette(&self.vram, attributes);
+
+        palette.unwrap()[index as usize]
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn set_draw_offset(&mut self, x: i16, y: i16) {
+        self.draw_offset = (x, y);
+    }
+
+    fn set_draw_area(&mut self,
+                     top_left: (u16, u16),
+                     bottom_right: (u16, u16)) {
+        self.draw_area = (top_left, bottom_right);
+    }
+
+    fn set_display_mode(&mut self,
+                        top_left: (u16, u16),
+                        resolution: (u16, u16),
+                        depth_24bpp: bool) {
+        self.display_top_left = top_left;
+        self.display_resolution = resolution;
+        self.display_24bpp = depth_24bpp;
+    }
+
+    fn push_line(&mut self, attributes: &PrimitiveAttributes, v: &[Vertex; 2]) {
+        self.draw_line(attributes, [&v[0], &v[1]]);
+    }
+
+    fn push_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     v: &[Vertex; 3]) {
+        self.draw_triangle(attributes, [&v[0], &v[1], &v[2]]);
+    }
+
+    fn push_quad(&mut self, attributes: &PrimitiveAttributes, v: &[Vertex; 4]) {
+        // The GPU splits the quads along the 1-2 diagonal
+        self.draw_triangle(attributes, [&v[0], &v[1], &v[2]]);
+        self.draw_triangle(attributes, [&v[1], &v[2], &v[3]]);
+    }
+
+    fn fill_rect(&mut self,
+                 color: [u8; 3],
+                 top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        // The fill color is never dithered
+        let pixel = quantize(color, 0, 0, false);
+
+        for y in 0..dimensions.1 {
+            for x in 0..dimensions.0 {
+                self.set_pixel(top_left.0.wrapping_add(x),
+                               top_left.1.wrapping_add(y),
+                               pixel);
+            }
+        }
+    }
+
+    fn load_image(&mut self,
+                  top_left: (u16, u16),
+                  dimensions: (u16, u16),
+                  pixel_buffer: &[u16]) {
+        let width = dimensions.0 as usize;
+
+        if width == 0 {
+            return;
+        }
+
+        let len = width * dimensions.1 as usize;
+
+        for (i, &p) in pixel_buffer[..len].iter().enumerate() {
+            let x = top_left.0.wrapping_add((i % width) as u16);
+            let y = top_left.1.wrapping_add((i / width) as u16);
+
+            self.set_pixel(x, y, p);
+        }
+    }
+
+    fn copy_rect(&mut self,
+                 src_top_left: (u16, u16),
+                 dst_top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        let len = dimensions.0 as usize * dimensions.1 as usize;
+        let mut pixels = vec![0; len];
+
+        // Go through a buffer in case the rectangles overlap
+        self.store_image(src_top_left, dimensions, &mut pixels);
+        self.load_image(dst_top_left, dimensions, &pixels);
+    }
+
+    fn store_image(&mut self,
+                   top_left: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]) {
+        let width = dimensions.0 as usize;
+
+        if width == 0 {
+            return;
+        }
+
+        let len = width * dimensions.1 as usize;
+
+        for (i, p) in pixel_buffer[..len].iter_mut().enumerate() {
+            let x = top_left.0.wrapping_add((i % width) as u16);
+            let y = top_left.1.wrapping_add((i / width) as u16);
+
+            *p = self.pixel(x, y);
+        }
+    }
+
+    fn clear_cache(&mut self) {
+        self.clut_cache.invalidate();
+    }
+
+    fn set_clut_cache(&mut self, enabled: bool) {
+        self.clut_cache.set_enabled(enabled);
+    }
+}
+
+/// Return the index of the pixel at `(x, y)` in the VRAM, the
+/// coordinates wrap around
+fn vram_index(x: u16, y: u16) -> usize {
+    let x = (x % VRAM_WIDTH_PIXELS) as usize;
+    let y = (y % VRAM_HEIGHT) as usize;
+
+    y * VRAM_WIDTH_PIXELS as usize + x
+}
+
+/// Convert a 15bit VRAM pixel into 8bit components
+fn to_rgb(pixel: u16) -> [u8; 3] {
+    let component = |shift: u16| (((pixel >> shift) & 0x1f) << 3) as u8;
+
+    [component(0), component(5), component(10)]
+}
+
+/// Edge function: positive if `p` is on the inner side of the edge
+/// going from `a` to `b`
+fn edge(a: (i32, i32), b: (i32, i32), p: (i32, i32)) -> i32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+/// Return true if the edge going from `a` to `b` is a top or left
+/// edge of a triangle with a positive area
+fn is_top_left(a: (i32, i32), b: (i32, i32)) -> bool {
+    let dx = b.0 - a.0;
+    let dy = b.1 - a.1;
+
+    (dy == 0 && dx > 0) || dy < 0
+}
+
+#[test]
+fn software_rendering() {
+    use super::renderer::SemiTransparencyMode;
+
+    let mut r = SoftwareRenderer::new();
+
+    r.set_draw_area((0, 0), (1023, 511));
+
+    r.fill_rect([0xff, 0, 0], (0, 0), (16, 16));
+
+    let mut pixels = [0; 4];
+
+    r.store_image((14, 15), (2, 2), &mut pixels);
+    assert_eq!(pixels, [0x001f, 0x001f, 0, 0]);
+
+    // The transfers wrap around the VRAM
+    r.load_image((1022, 0), (4, 1), &[1, 2, 3, 4]);
+    r.store_image((0, 0), (2, 1), &mut pixels);
+    assert_eq!(&pixels[..2], &[3, 4]);
+
+    r.copy_rect((1022, 0), (100, 100), (4, 1));
+    r.store_image((100, 100), (4, 1), &mut pixels);
+    assert_eq!(pixels, [1, 2, 3, 4]);
+
+    let attributes = PrimitiveAttributes {
+        semi_transparent: false,
+        semi_transparency_mode: SemiTransparencyMode::Average,
+        blend_mode: BlendMode::None,
+        texture_page: [0, 0],
+        texture_depth: TextureDepth::T4Bpp,
+        clut: [0, 0],
+        dither: false,
+        true_color: false,
+        flip_x: false,
+        flip_y: false,
+    };
+
+    let green = [0, 0xff, 0];
+
+    r.set_draw_offset(2, 2);
+    r.push_triangle(&attributes,
+                    &[Vertex::new([0, 0], green),
+                      Vertex::new([8, 0], green),
+                      Vertex::new([0, 8], green)]);
+
+    // Top left edge is drawn, the other edge isn't
+    assert_eq!(r.pixel(2, 2), 0x03e0);
+    assert_eq!(r.pixel(5, 5), 0x03e0);
+    assert_eq!(r.pixel(10, 2), 0x001f);
+    assert_eq!(r.pixel(9, 9), 0x001f);
+
+    r.set_display_mode((0, 0), (16, 16), false);
+
+    let mut frame = Vec::new();
+
+    assert_eq!(r.frame(&mut frame), (16, 16));
+    assert_eq!(frame.len(), 16 * 16);
+    assert_eq!(frame[0], 0xf80000);
+    assert_eq!(frame[2 * 16 + 2], 0x00f800);
+}
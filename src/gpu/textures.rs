@@ -0,0 +1,399 @@
+//! Texture dumping and replacement.
+//!
+//! Like the rendered frames the VRAM lives in the renderer, so the
+//! `TextureCache` works on the VRAM contents provided by the renderer
+//! when it samples a texture. Textures are identified by a hash of
+//! their texture page and CLUT contents, which lets us recognize them
+//! wherever the game uploads them. The renderer must report every
+//! VRAM write through `invalidate` so that we rehash the textures
+//! which might have changed.
+//!
+//! Dumped textures are saved as `<hash>.png` and replacements are
+//! looked up using the same naming scheme. Since the core doesn't
+//! have an image decoder the frontend is responsible for decoding the
+//! replacement files listed by `replacement_files` and registering
+//! them with `add_replacement`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use logging;
+
+use super::{vram_wrap_spans, VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+use super::dump::write_png;
+use super::renderer::{PrimitiveAttributes, BlendMode, TextureDepth};
+
+/// High resolution replacement texture
+pub struct Replacement {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA8888 pixels, line by line
+    pub rgba: Vec<u8>,
+}
+
+pub struct TextureCache {
+    /// Directory where new textures are dumped. Dumping is disabled
+    /// if it's `None`.
+    dump_dir: Option<PathBuf>,
+    /// Hashes of the textures already dumped
+    dumped: HashSet<u64>,
+    /// Replacement textures, indexed by hash
+    replacements: HashMap<u64, Rc<Replacement>>,
+    /// Hashes of the textures used since they were last invalidated
+    entries: HashMap<TextureKey, u64>,
+}
+
+impl TextureCache {
+    pub fn new() -> TextureCache {
+        TextureCache {
+            dump_dir: None,
+            dumped: HashSet::new(),
+            replacements: HashMap::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Dump every new texture sampled by the renderer into `dir`
+    pub fn set_dump_dir(&mut self, dir: Option<PathBuf>) {
+        self.dump_dir = dir;
+    }
+
+    /// Register the replacement for the texture with hash `hash`
+    pub fn add_replacement(&mut self, hash: u64, replacement: Replacement) {
+        self.replacements.insert(hash, Rc::new(replacement));
+    }
+
+    pub fn clear_replacements(&mut self) {
+        self.replacements.clear();
+    }
+
+    /// Must be called by the renderer for every VRAM write
+    /// (`load_image`, `fill_rect`, `copy_rect` destination and
+    /// rendering into a texture page)
+    pub fn invalidate(&mut self,
+                      top_left: (u16, u16),
+                      dimensions: (u16, u16)) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        let rect = Rect::new(top_left, dimensions);
+
+        self.entries.retain(|key, _| !key.overlaps(&rect));
+    }
+
+    /// Return the hash of the texture used by a primitive with
+    /// `attributes`, `None` if the primitive isn't textured. `vram`
+    /// is the current content of the VRAM, line by line.
+    pub fn hash(&mut self,
+                vram: &[u16],
+                attributes: &PrimitiveAttributes) -> Option<u64> {
+        let key =
+            match TextureKey::from_attributes(attributes) {
+                Some(k) => k,
+                None => return None,
+            };
+
+        if let Some(&hash) = self.entries.get(&key) {
+            return Some(hash);
+        }
+
+        let hash = key.hash(vram);
+
+        self.entries.insert(key, hash);
+
+        if self.dump_dir.is_some() && self.dumped.insert(hash) {
+            self.dump(vram, key, hash);
+        }
+
+        Some(hash)
+    }
+
+    /// Return the replacement for the texture used by a primitive
+    /// with `attributes`, if any. New textures are dumped if dumping
+    /// is enabled.
+    pub fn lookup(&mut self,
+                  vram: &[u16],
+                  attributes: &PrimitiveAttributes)
+                  -> Option<Rc<Replacement>> {
+        self.hash(vram, attributes)
+            .and_then(|h| self.replacements.get(&h).cloned())
+    }
+
+    fn dump(&self, vram: &[u16], key: TextureKey, hash: u64) {
+        let dir =
+            match self.dump_dir {
+                Some(ref d) => d,
+                None => return,
+            };
+
+        let path = dir.join(file_name(hash));
+
+        let rgb = key.decode(vram);
+
+        match write_png(&path, 256, 256, &rgb) {
+            Ok(()) => info!(target: logging::GPU,
+                            "Dumped texture {}", path.display()),
+            Err(e) => warn!(target: logging::GPU,
+                            "Couldn't dump texture {}: {}",
+                            path.display(), e),
+        }
+    }
+}
+
+/// Return the file name used to dump and replace the texture with
+/// hash `hash`
+pub fn file_name(hash: u64) -> String {
+    format!("{:016x}.png", hash)
+}
+
+/// List the replacement textures in `dir` along with their hash
+pub fn replacement_files(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut files = Vec::new();
+
+    for entry in try!(fs::read_dir(dir)) {
+        let path = try!(entry).path();
+
+        let hash =
+            match path.file_stem().and_then(|s| s.to_str()) {
+                Some(stem) if stem.len() == 16 =>
+                    u64::from_str_radix(stem, 16).ok(),
+                _ => None,
+            };
+
+        let is_png =
+            path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.eq_ignore_ascii_case("png"))
+            .unwrap_or(false);
+
+        if let (Some(hash), true) = (hash, is_png) {
+            files.push((hash, path));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Texture page and CLUT combination
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct TextureKey {
+    page: (u16, u16),
+    clut: (u16, u16),
+    /// Bits per pixel
+    bpp: u8,
+}
+
+impl TextureKey {
+    fn from_attributes(attributes: &PrimitiveAttributes)
+                       -> Option<TextureKey> {
+        if attributes.blend_mode == BlendMode::None {
+            return None;
+        }
+
+        let bpp =
+            match attributes.texture_depth {
+                TextureDepth::T4Bpp => 4,
+                TextureDepth::T8Bpp => 8,
+                TextureDepth::T16Bpp => 16,
+            };
+
+        let clut =
+            match bpp {
+                // No CLUT for truecolor textures
+                16 => (0, 0),
+                _ => (attributes.clut[0], attributes.clut[1]),
+            };
+
+        Some(TextureKey {
+            page: (attributes.texture_page[0], attributes.texture_page[1]),
+            clut: clut,
+            bpp: bpp,
+        })
+    }
+
+    /// VRAM area containing the texels
+    fn page_rect(&self) -> Rect {
+        // Texture pages are 256 texels wide
+        let width = 256 * self.bpp as u16 / 16;
+
+        Rect::new(self.page, (width, 256))
+    }
+
+    /// VRAM area containing the CLUT, if any
+    fn clut_rect(&self) -> Option<Rect> {
+        match self.bpp {
+            4 => Some(Rect::new(self.clut, (16, 1))),
+            8 => Some(Rect::new(self.clut, (256, 1))),
+            _ => None,
+        }
+    }
+
+    fn overlaps(&self, rect: &Rect) -> bool {
+        self.page_rect().overlaps(rect) ||
+            self.clut_rect().map(|c| c.overlaps(rect)).unwrap_or(false)
+    }
+
+    fn hash(&self, vram: &[u16]) -> u64 {
+        // FNV-1a
+        let mut hash = 0xcbf29ce484222325u64;
+
+        {
+            let mut feed = |v: u16| {
+                for &b in &[v as u8, (v >> 8) as u8] {
+                    hash ^= b as u64;
+                    hash = hash.wrapping_mul(0x100000001b3);
+                }
+            };
+
+            feed(self.bpp as u16);
+
+            self.page_rect().for_each_pixel(vram, &mut feed);
+
+            if let Some(clut) = self.clut_rect() {
+                clut.for_each_pixel(vram, &mut feed);
+            }
+        }
+
+        hash
+    }
+
+    /// Decode the 256x256 texture page into an RGB888 buffer
+    fn decode(&self, vram: &[u16]) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(256 * 256 * 3);
+
+        let texels_per_pixel = 16 / self.bpp as u16;
+
+        for y in 0..256 {
+            for x in 0..256 {
+                let vx = self.page.0 + x / texels_per_pixel;
+                let v = vram_pixel(vram, vx, self.page.1 + y);
+
+                let color =
+                    match self.bpp {
+                        16 => v,
+                        bpp => {
+                            let mask = (1 << bpp) - 1;
+                            let shift = (x % texels_per_pixel) * bpp as u16;
+                            let index = (v >> shift) & mask;
+
+                            vram_pixel(vram, self.clut.0 + index, self.clut.1)
+                        }
+                    };
+
+                rgb.push(((color & 0x1f) << 3) as u8);
+                rgb.push((((color >> 5) & 0x1f) << 3) as u8);
+                rgb.push((((color >> 10) & 0x1f) << 3) as u8);
+            }
+        }
+
+        rgb
+    }
+}
+
+/// Read the pixel at `x`, `y` wrapping around the VRAM
+fn vram_pixel(vram: &[u16], x: u16, y: u16) -> u16 {
+    let x = (x % VRAM_WIDTH_PIXELS) as usize;
+    let y = (y % VRAM_HEIGHT) as usize;
+
+    vram[y * VRAM_WIDTH_PIXELS as usize + x]
+}
+
+/// VRAM rectangle, split in up to four parts when it wraps around
+struct Rect {
+    x_spans: [(u16, u16); 2],
+    y_spans: [(u16, u16); 2],
+}
+
+impl Rect {
+    fn new(top_left: (u16, u16), dimensions: (u16, u16)) -> Rect {
+        let (x, y) = top_left;
+        let (w, h) = dimensions;
+
+        Rect {
+            x_spans: vram_wrap_spans(x % VRAM_WIDTH_PIXELS,
+                                     w.min(VRAM_WIDTH_PIXELS),
+                                     VRAM_WIDTH_PIXELS),
+            y_spans: vram_wrap_spans(y % VRAM_HEIGHT,
+                                     h.min(VRAM_HEIGHT),
+                                     VRAM_HEIGHT),
+        }
+    }
+
+    fn overlaps(&self, other: &Rect) -> bool {
+        fn spans_overlap(a: &[(u16, u16); 2], b: &[(u16, u16); 2]) -> bool {
+            a.iter().any(|&(sa, la)| {
+                b.iter().any(|&(sb, lb)| {
+                    la > 0 && lb > 0 && sa < sb + lb && sb < sa + la
+                })
+            })
+        }
+
+        spans_overlap(&self.x_spans, &other.x_spans) &&
+            spans_overlap(&self.y_spans, &other.y_spans)
+    }
+
+    fn for_each_pixel<F>(&self, vram: &[u16], f: &mut F)
+        where F: FnMut(u16) {
+        for &(y, h) in &self.y_spans {
+            for line in y..(y + h) {
+                for &(x, w) in &self.x_spans {
+                    for col in x..(x + w) {
+                        f(vram_pixel(vram, col, line));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn invalidation() {
+    use super::renderer::SemiTransparencyMode;
+
+    let mut vram = vec![0u16; VRAM_WIDTH_PIXELS as usize *
+                              VRAM_HEIGHT as usize];
+
+    let attributes = PrimitiveAttributes {
+        semi_transparent: false,
+        semi_transparency_mode: SemiTransparencyMode::Average,
+        blend_mode: BlendMode::Raw,
+        texture_page: [960, 256],
+        texture_depth: TextureDepth::T8Bpp,
+        clut: [0, 480],
+        dither: false,
+        true_color: false,
+        flip_x: false,
+        flip_y: false,
+    };
+
+    let mut cache = TextureCache::new();
+
+    let h0 = cache.hash(&vram, &attributes).unwrap();
+
+    // The page wraps around, this is in the second half
+    vram[300 * 1024 + 10] = 0x7fff;
+
+    // Not invalidated yet, we get the stale hash
+    assert_eq!(cache.hash(&vram, &attributes), Some(h0));
+
+    cache.invalidate((10, 300), (1, 1));
+
+    let h1 = cache.hash(&vram, &attributes).unwrap();
+
+    assert!(h1 != h0);
+
+    // Unrelated write
+    cache.invalidate((500, 0), (16, 16));
+
+    vram[480 * 1024 + 5] = 0x1234;
+
+    assert_eq!(cache.hash(&vram, &attributes), Some(h1));
+
+    cache.invalidate((5, 480), (1, 1));
+
+    assert!(cache.hash(&vram, &attributes) != Some(h1));
+}
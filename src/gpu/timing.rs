@@ -0,0 +1,147 @@
+//! Video timings as seen by the rest of the console: the horizontal
+//! and vertical blanking signals used by the timers for their
+//! synchronization modes.
+
+use std::cmp;
+
+use timekeeper::{Cycles, FracCycles};
+
+/// Periodic blanking signal (HBLANK or VBLANK). All the durations are
+/// in CPU clock periods so that the timers can follow the signal
+/// without having to synchronize the GPU.
+#[derive(Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub struct Blanking {
+    /// Duration of a full period (a line for HBLANK, a frame for
+    /// VBLANK)
+    period: FracCycles,
+    /// Start of the active (not blanking) part of the period
+    active_start: FracCycles,
+    /// End of the active part of the period, blanking starts here
+    active_end: FracCycles,
+    /// Current position within the period
+    position: FracCycles,
+}
+
+impl Blanking {
+    /// Build a blanking signal from GPU clock tick counts. `position`
+    /// is the current position within the period (in fractional GPU
+    /// ticks) and `gpu_to_cpu` the GPU to CPU clock ratio.
+    pub fn new(period: Cycles,
+               active_start: Cycles,
+               active_end: Cycles,
+               position: FracCycles,
+               gpu_to_cpu: FracCycles) -> Blanking {
+        let to_cpu = |ticks: Cycles| {
+            FracCycles::from_cycles(ticks).divide(gpu_to_cpu)
+        };
+
+        let period = to_cpu(period);
+        let position = position.divide(gpu_to_cpu);
+
+        Blanking {
+            period: period,
+            active_start: to_cpu(active_start),
+            active_end: to_cpu(active_end),
+            // The conversion can round the position past the end of
+            // the period
+            position: FracCycles::from_fp(position.get_fp() %
+                                          period.get_fp()),
+        }
+    }
+
+    /// Return true if the signal is currently active
+    pub fn in_blank(&self) -> bool {
+        let pos = self.position.get_fp();
+
+        pos < self.active_start.get_fp() || pos >= self.active_end.get_fp()
+    }
+
+    /// Return true if the signal never changes state
+    fn is_constant(&self) -> bool {
+        let start = self.active_start.get_fp();
+        let end = cmp::min(self.active_end.get_fp(), self.period.get_fp());
+
+        start >= end || (start == 0 && end == self.period.get_fp())
+    }
+
+    /// Return the time (in fractional CPU cycles) until the signal
+    /// changes state, `None` if it never does.
+    pub fn next_edge(&self) -> Option<Cycles> {
+        if self.is_constant() {
+            return None;
+        }
+
+        let pos = self.position.get_fp();
+        let period = self.period.get_fp();
+        let start = self.active_start.get_fp();
+        let end = cmp::min(self.active_end.get_fp(), period);
+
+        let edge =
+            if pos < start {
+                start
+            } else if pos < end {
+                end
+            } else {
+                period + start
+            };
+
+        Some(edge - pos)
+    }
+
+    /// Move forward by at most `max` fractional CPU cycles, stopping
+    /// at the next edge of the signal. Returns the duration actually
+    /// elapsed.
+    pub fn advance(&mut self, max: Cycles) -> Cycles {
+        let elapsed =
+            match self.next_edge() {
+                Some(edge) => cmp::min(edge, max),
+                None => max,
+            };
+
+        let pos = (self.position.get_fp() + elapsed) % self.period.get_fp();
+
+        self.position = FracCycles::from_fp(pos);
+
+        elapsed
+    }
+}
+
+/// First and last GPU clock tick of the active part of a line for
+/// the NTSC and PAL video modes. Outside of this window the HBLANK
+/// signal is asserted.
+pub const NTSC_HBLANK_END: Cycles = 488;
+pub const NTSC_HBLANK_START: Cycles = 3288;
+pub const PAL_HBLANK_END: Cycles = 487;
+pub const PAL_HBLANK_START: Cycles = 3282;
+
+#[test]
+fn blanking_edges() {
+    let ratio = FracCycles::from_cycles(1);
+
+    // 10 cycle period, blanking outside of [2, 8)
+    let mut b = Blanking::new(10, 2, 8, FracCycles::from_cycles(0), ratio);
+
+    let cycles = |c: Cycles| FracCycles::from_cycles(c).get_fp();
+
+    assert!(b.in_blank());
+    assert_eq!(b.next_edge(), Some(cycles(2)));
+
+    // Stops at the end of the blanking
+    assert_eq!(b.advance(cycles(5)), cycles(2));
+    assert!(!b.in_blank());
+
+    assert_eq!(b.advance(cycles(4)), cycles(4));
+    assert!(!b.in_blank());
+
+    assert_eq!(b.advance(cycles(5)), cycles(2));
+    assert!(b.in_blank());
+
+    // Blanking lasts until the active part of the next period
+    assert_eq!(b.next_edge(), Some(cycles(4)));
+
+    // Always blanking
+    let b = Blanking::new(10, 8, 2, FracCycles::from_cycles(0), ratio);
+
+    assert!(b.in_blank());
+    assert_eq!(b.next_edge(), None);
+}
@@ -0,0 +1,151 @@
+//! Interfaces to the host system.
+//!
+//! The emulator core doesn't access the filesystem or the host clock
+//! directly, it goes through the traits below. Frontends running
+//! without a filesystem or a usable clock (web browsers, embedded
+//! targets) can provide the BIOS and disc images or the current date
+//! some other way. `FileSource`, `MemoryCardFiles` and `SystemClock`
+//! implement them on top of the standard library for the usual
+//! desktop frontends.
+
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bios::BIOS_SIZE;
+use cdrom::disc::Disc;
+
+/// Provides the BIOS image
+pub trait BiosSource {
+    /// Return the raw contents of the BIOS ROM. The image is
+    /// validated by `Bios::from_source`.
+    fn read_bios(&mut self) -> Result<Vec<u8>, String>;
+}
+
+/// Provides the disc images
+pub trait DiscSource {
+    fn open_disc(&mut self) -> Result<Disc, String>;
+}
+
+/// Stores the memory card images when the game modifies them
+pub trait MemoryCardSink {
+    /// Save the contents of the memory card in `port` (0 or 1)
+    fn write_memory_card(&mut self,
+                         port: usize,
+                         data: &[u8]) -> Result<(), String>;
+}
+
+/// Host wall clock, used by the RTC when it follows the host time
+pub trait Clock {
+    /// Return the current UNIX timestamp in seconds
+    fn unix_time(&self) -> u64;
+}
+
+/// BIOS image already in memory
+impl<'a> BiosSource for &'a [u8] {
+    fn read_bios(&mut self) -> Result<Vec<u8>, String> {
+        Ok(self.to_vec())
+    }
+}
+
+/// Load the images from the filesystem. BIOS images are read
+/// directly, discs are expected to be CUE sheets.
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new<P: AsRef<Path>>(path: P) -> FileSource {
+        FileSource {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl BiosSource for FileSource {
+    fn read_bios(&mut self) -> Result<Vec<u8>, String> {
+        let mut f =
+            match File::open(&self.path) {
+                Ok(f) => f,
+                Err(e) => return Err(format!("Can't open {}: {}",
+                                             self.path.display(), e)),
+            };
+
+        // Read one more byte than needed to detect oversized images
+        let mut binary = Vec::with_capacity(BIOS_SIZE + 1);
+
+        let r = f.by_ref().take(BIOS_SIZE as u64 + 1).read_to_end(&mut binary);
+
+        match r {
+            Ok(_) => Ok(binary),
+            Err(e) => Err(format!("Can't read {}: {}",
+                                  self.path.display(), e)),
+        }
+    }
+}
+
+impl DiscSource for FileSource {
+    fn open_disc(&mut self) -> Result<Disc, String> {
+        Disc::from_path(&self.path)
+    }
+}
+
+/// Save the memory cards as raw 128KB images. The image is written
+/// to a temporary file first and then renamed so that a crash in the
+/// middle of the write doesn't corrupt the previous save.
+pub struct MemoryCardFiles {
+    paths: [Option<PathBuf>; 2],
+}
+
+impl MemoryCardFiles {
+    /// Save the cards in ports 1 and 2 at the given locations. The
+    /// cards without a path are not saved.
+    pub fn new(port1: Option<PathBuf>,
+               port2: Option<PathBuf>) -> MemoryCardFiles {
+        MemoryCardFiles {
+            paths: [port1, port2],
+        }
+    }
+}
+
+impl MemoryCardSink for MemoryCardFiles {
+    fn write_memory_card(&mut self,
+                         port: usize,
+                         data: &[u8]) -> Result<(), String> {
+        let path =
+            match self.paths.get(port) {
+                Some(&Some(ref p)) => p,
+                _ => return Ok(()),
+            };
+
+        let tmp = path.with_extension("tmp");
+
+        let r = File::create(&tmp)
+            .and_then(|mut f| f.write_all(data).and_then(|_| f.sync_all()))
+            .and_then(|_| fs::rename(&tmp, path));
+
+        r.map_err(|e| format!("Can't write {}: {}", path.display(), e))
+    }
+}
+
+/// Host clock from the standard library
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn unix_time(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// Clock stuck at a given UNIX timestamp, for hosts without a clock
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn unix_time(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,120 @@
+//! Policy used to initialize the state whose power-on value is
+//! undefined on the real hardware (CPU registers, RAM, caches...).
+//!
+//! Filling it with recognizable patterns or pseudo-random values
+//! helps catching software reading uninitialized state, while using
+//! a seed keeps the runs reproducible.
+
+/// How to fill the uninitialized state
+#[derive(Clone, Copy, Debug, PartialEq, Eq, RustcDecodable, RustcEncodable)]
+pub enum InitPolicy {
+    /// Historical values used by rustation: 0xdeadbeef in the CPU
+    /// registers, 0xca in RAM, 0xdb in the ScratchPad and an
+    /// instruction cache with zeroed words and invalid lines.
+    Legacy,
+    /// Everything set to 0
+    Zero,
+    /// Repeat a 32bit little endian pattern everywhere
+    Pattern(u32),
+    /// Pseudo-random values generated from the given seed
+    Random(u64),
+}
+
+impl InitPolicy {
+    /// Return a new `Filler` generating the values for this policy.
+    /// For `Random` two fillers created from the same policy generate
+    /// the same sequence.
+    pub fn filler(self) -> Filler {
+        let state =
+            match self {
+                // Xorshift doesn't work with a 0 state
+                InitPolicy::Random(0) => 0x9e3779b97f4a7c15,
+                InitPolicy::Random(seed) => seed,
+                _ => 0,
+            };
+
+        Filler {
+            policy: self,
+            state: state,
+        }
+    }
+}
+
+impl Default for InitPolicy {
+    fn default() -> InitPolicy {
+        InitPolicy::Legacy
+    }
+}
+
+/// Value generator for a given `InitPolicy`
+pub struct Filler {
+    policy: InitPolicy,
+    /// Xorshift64* state for the `Random` policy
+    state: u64,
+}
+
+impl Filler {
+    /// Return the next 32bit value. `legacy` is the value returned
+    /// for the `Legacy` policy.
+    pub fn word(&mut self, legacy: u32) -> u32 {
+        match self.policy {
+            InitPolicy::Legacy => legacy,
+            InitPolicy::Zero => 0,
+            InitPolicy::Pattern(p) => p,
+            InitPolicy::Random(_) => self.next_random(),
+        }
+    }
+
+    /// Fill `buf`. `legacy` is the byte value used for the `Legacy`
+    /// policy.
+    pub fn fill(&mut self, buf: &mut [u8], legacy: u8) {
+        let legacy = legacy as u32 * 0x01010101;
+
+        for chunk in buf.chunks_mut(4) {
+            let w = self.word(legacy);
+
+            for (i, b) in chunk.iter_mut().enumerate() {
+                *b = (w >> (i * 8)) as u8;
+            }
+        }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.state;
+
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+
+        self.state = x;
+
+        (x.wrapping_mul(0x2545f4914f6cdd1d) >> 32) as u32
+    }
+}
+
+#[test]
+fn reproducible() {
+    let mut a = InitPolicy::Random(1234).filler();
+    let mut b = InitPolicy::Random(1234).filler();
+
+    let mut buf_a = [0u8; 64];
+    let mut buf_b = [0u8; 64];
+
+    a.fill(&mut buf_a, 0);
+    b.fill(&mut buf_b, 0);
+
+    assert!(buf_a[..] == buf_b[..]);
+    assert!(buf_a.iter().any(|&v| v != buf_a[0]));
+
+    let mut legacy = InitPolicy::Legacy.filler();
+
+    legacy.fill(&mut buf_a, 0xca);
+
+    assert!(buf_a.iter().all(|&v| v == 0xca));
+
+    let mut pattern = InitPolicy::Pattern(0x12345678).filler();
+
+    pattern.fill(&mut buf_a, 0);
+
+    assert_eq!(&buf_a[0..4], &[0x78, 0x56, 0x34, 0x12]);
+}
@@ -0,0 +1,209 @@
+//! Instrumentation hooks for external tools.
+//!
+//! When the crate is built with the "instrumentation" feature an
+//! `Instrument` can be attached to the CPU with
+//! `Cpu::set_instrumentation`. It's then called for every instruction
+//! executed and every memory access, which is enough to compute the
+//! code coverage of a game or test ROM run or to map the data it
+//! touches. Without the feature the hooks are empty inline functions
+//! and the compiler removes them entirely.
+//!
+//! Unlike the `Debugger` the instrument can't modify the state of the
+//! CPU, it only observes it.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+/// Receives the CPU activity. All the methods do nothing by default.
+pub trait Instrument {
+    /// Called before the instruction at `pc` is executed
+    fn instruction(&mut self, _pc: u32) {
+    }
+
+    /// Called when the instruction at `pc` reads `size` bytes at
+    /// `addr`
+    fn memory_read(&mut self, _pc: u32, _addr: u32, _size: u8) {
+    }
+
+    /// Called when the instruction at `pc` writes `val` (`size`
+    /// bytes) at `addr`
+    fn memory_write(&mut self, _pc: u32, _addr: u32, _size: u8, _val: u32) {
+    }
+}
+
+/// Holds the instrument attached to the CPU, if any
+pub struct Instrumentation {
+    #[cfg(feature = "instrumentation")]
+    instrument: Option<Box<Instrument>>,
+}
+
+#[cfg(feature = "instrumentation")]
+impl Instrumentation {
+    pub fn new() -> Instrumentation {
+        Instrumentation {
+            instrument: None,
+        }
+    }
+
+    /// Replace the instrument, returns the previous one
+    pub fn set(&mut self,
+               instrument: Option<Box<Instrument>>) -> Option<Box<Instrument>> {
+        ::std::mem::replace(&mut self.instrument, instrument)
+    }
+
+    #[inline(always)]
+    pub fn instruction(&mut self, pc: u32) {
+        if let Some(ref mut i) = self.instrument {
+            i.instruction(pc);
+        }
+    }
+
+    #[inline(always)]
+    pub fn memory_read(&mut self, pc: u32, addr: u32, size: u8) {
+        if let Some(ref mut i) = self.instrument {
+            i.memory_read(pc, addr, size);
+        }
+    }
+
+    #[inline(always)]
+    pub fn memory_write(&mut self, pc: u32, addr: u32, size: u8, val: u32) {
+        if let Some(ref mut i) = self.instrument {
+            i.memory_write(pc, addr, size, val);
+        }
+    }
+}
+
+#[cfg(not(feature = "instrumentation"))]
+impl Instrumentation {
+    pub fn new() -> Instrumentation {
+        Instrumentation {}
+    }
+
+    #[inline(always)]
+    pub fn instruction(&mut self, _pc: u32) {
+        // NOP
+    }
+
+    #[inline(always)]
+    pub fn memory_read(&mut self, _pc: u32, _addr: u32, _size: u8) {
+        // NOP
+    }
+
+    #[inline(always)]
+    pub fn memory_write(&mut self,
+                        _pc: u32,
+                        _addr: u32,
+                        _size: u8,
+                        _val: u32) {
+        // NOP
+    }
+}
+
+impl Encodable for Instrumentation {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Tooling, not part of the console state
+        s.emit_nil()
+    }
+}
+
+impl Decodable for Instrumentation {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Instrumentation, D::Error> {
+        try!(d.read_nil());
+
+        Ok(Instrumentation::new())
+    }
+}
+
+/// Simple `Instrument` counting how many times each instruction was
+/// executed and each word of memory was accessed
+pub struct Coverage {
+    /// Execution count for each instruction address
+    executed: HashMap<u32, u64>,
+    /// Read and write counts for each (word aligned) address
+    accessed: HashMap<u32, (u64, u64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: HashMap::new(),
+            accessed: HashMap::new(),
+        }
+    }
+
+    /// Number of times the instruction at `pc` was executed
+    pub fn executed(&self, pc: u32) -> u64 {
+        self.executed.get(&pc).cloned().unwrap_or(0)
+    }
+
+    /// Number of (reads, writes) to the word containing `addr`
+    pub fn accessed(&self, addr: u32) -> (u64, u64) {
+        self.accessed.get(&(addr & !3)).cloned().unwrap_or((0, 0))
+    }
+
+    /// Addresses of all the instructions executed, sorted
+    pub fn executed_addresses(&self) -> Vec<u32> {
+        let mut v: Vec<u32> = self.executed.keys().cloned().collect();
+
+        v.sort();
+
+        v
+    }
+
+    /// Write the coverage report: one line per instruction executed
+    /// with its address and execution count, followed by one line
+    /// per memory word accessed with its read and write counts.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        try!(writeln!(w, "# Executed instructions: address count"));
+
+        for pc in self.executed_addresses() {
+            try!(writeln!(w, "0x{:08x} {}", pc, self.executed(pc)));
+        }
+
+        try!(writeln!(w, "# Memory accesses: address reads writes"));
+
+        let mut addresses: Vec<u32> = self.accessed.keys().cloned().collect();
+
+        addresses.sort();
+
+        for addr in addresses {
+            let (r, w_count) = self.accessed(addr);
+
+            try!(writeln!(w, "0x{:08x} {} {}", addr, r, w_count));
+        }
+
+        Ok(())
+    }
+}
+
+impl Instrument for Coverage {
+    fn instruction(&mut self, pc: u32) {
+        *self.executed.entry(pc).or_insert(0) += 1;
+    }
+
+    fn memory_read(&mut self, _pc: u32, addr: u32, _size: u8) {
+        self.accessed.entry(addr & !3).or_insert((0, 0)).0 += 1;
+    }
+
+    fn memory_write(&mut self, _pc: u32, addr: u32, _size: u8, _val: u32) {
+        self.accessed.entry(addr & !3).or_insert((0, 0)).1 += 1;
+    }
+}
+
+#[test]
+fn coverage() {
+    let mut coverage = Coverage::new();
+
+    coverage.instruction(0x80010000);
+    coverage.instruction(0x80010004);
+    coverage.instruction(0x80010000);
+    coverage.memory_read(0x80010000, 0x1f801812, 2);
+    coverage.memory_write(0x80010004, 0x1f801810, 4, 0);
+
+    assert_eq!(coverage.executed(0x80010000), 2);
+    assert_eq!(coverage.executed(0x80010008), 0);
+    assert_eq!(coverage.executed_addresses(), vec![0x80010000, 0x80010004]);
+    assert_eq!(coverage.accessed(0x1f801810), (1, 1));
+}
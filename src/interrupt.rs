@@ -16,6 +16,11 @@ pub enum Interrupt {
     Timer2 = 6,
     /// Gamepad and Memory Card controller interrupt
     PadMemCard = 7,
+    /// Serial port 1
+    Sio = 8,
+    /// Lightpen input on the controller ports, used by some
+    /// lightguns
+    LightPen = 10,
 }
 
 #[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
@@ -63,7 +68,9 @@ impl InterruptState {
                           Interrupt::Timer0,
                           Interrupt::Timer1,
                           Interrupt::Timer2,
-                          Interrupt::PadMemCard];
+                          Interrupt::PadMemCard,
+                          Interrupt::Sio,
+                          Interrupt::LightPen];
 
         let rem = supported.iter().fold(mask,
                                         |mask, &it| mask & !(1 << it as u16));
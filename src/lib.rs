@@ -4,10 +4,9 @@ extern crate shaman;
 extern crate cdimage;
 extern crate arrayvec;
 extern crate rustc_serialize;
-
-#[cfg(feature = "trace")]
-#[macro_use]
-extern crate lazy_static;
+extern crate toml;
+extern crate bincode;
+extern crate flate2;
 
 #[macro_use]
 mod box_array;
@@ -23,10 +22,27 @@ pub mod memory;
 pub mod cpu;
 pub mod shared;
 pub mod padmemcard;
+pub mod sio1;
 pub mod debugger;
 pub mod assembler;
 pub mod parallel_io;
 pub mod debug_uart;
+pub mod logging;
+pub mod machine;
+pub mod audio;
+pub mod config;
+pub mod init_policy;
+pub mod pgxp;
+pub mod rtc;
+pub mod host;
+pub mod stats;
+pub mod savestate;
+pub mod crash;
+pub mod rng;
+pub mod instrumentation;
+pub mod events;
+pub mod report;
+pub mod web;
 
 mod interrupt;
 mod timekeeper;
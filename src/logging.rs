@@ -0,0 +1,137 @@
+//! Log targets used by the emulator's subsystems and a simple
+//! per-target level filter frontends can use in their logger
+//! implementation.
+//!
+//! All the log messages emitted by rustation use one of the targets
+//! defined here instead of the default module path, that way it's
+//! possible to enable verbose logging for a single subsystem (say
+//! the CDROM controller) while keeping the rest of the emulator quiet.
+
+use log::{LogLevel, LogLevelFilter, LogMetadata};
+
+/// CPU and coprocessor 0
+pub const CPU: &'static str = "cpu";
+/// Geometry Transform Engine
+pub const GTE: &'static str = "gte";
+/// Graphics Processing Unit
+pub const GPU: &'static str = "gpu";
+/// DMA controller
+pub const DMA: &'static str = "dma";
+/// CDROM controller and disc handling
+pub const CDROM: &'static str = "cdrom";
+/// Sound Processing Unit
+pub const SPU: &'static str = "spu";
+/// Timers
+pub const TIMERS: &'static str = "timers";
+/// Motion decoder
+pub const MDEC: &'static str = "mdec";
+/// Gamepad and memory card controller
+pub const PAD_MEMCARD: &'static str = "padmemcard";
+/// Serial port 1 and link cable
+pub const SIO1: &'static str = "sio1";
+/// Memory bus and memory control registers
+pub const MEMORY: &'static str = "memory";
+/// Parallel I/O port and the modules plugged into it
+pub const PARALLEL_IO: &'static str = "parallel_io";
+/// Debug UART (BIOS TTY output)
+pub const DEBUG_UART: &'static str = "debug_uart";
+/// High-level emulation of the BIOS
+pub const HLE: &'static str = "hle";
+/// Hardware register accesses, see `memory::io_trace`
+pub const IO_TRACE: &'static str = "io_trace";
+/// Savestate loading and saving
+pub const SAVESTATE: &'static str = "savestate";
+
+/// List of all the targets used by the emulator
+pub const TARGETS: [&'static str; 16] = [
+    CPU,
+    GTE,
+    GPU,
+    DMA,
+    CDROM,
+    SPU,
+    TIMERS,
+    MDEC,
+    PAD_MEMCARD,
+    SIO1,
+    MEMORY,
+    PARALLEL_IO,
+    DEBUG_UART,
+    HLE,
+    IO_TRACE,
+    SAVESTATE,
+];
+
+/// Per-target log level filter. Targets that haven't been configured
+/// explicitly use the default level.
+pub struct LogFilter {
+    /// Level used for targets without an explicit configuration
+    default: LogLevelFilter,
+    /// Per-target level overrides
+    levels: Vec<(String, LogLevelFilter)>,
+}
+
+impl LogFilter {
+    /// Create a new filter using `default` for all targets
+    pub fn new(default: LogLevelFilter) -> LogFilter {
+        LogFilter {
+            default: default,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Change the level used for targets without an explicit
+    /// configuration
+    pub fn set_default(&mut self, level: LogLevelFilter) {
+        self.default = level;
+    }
+
+    /// Set the log level for `target`, overriding the default
+    pub fn set_level(&mut self, target: &str, level: LogLevelFilter) {
+        for &mut (ref t, ref mut l) in self.levels.iter_mut() {
+            if t == target {
+                *l = level;
+                return;
+            }
+        }
+
+        self.levels.push((target.into(), level));
+    }
+
+    /// Remove the override for `target`, it will use the default
+    /// level again
+    pub fn reset_level(&mut self, target: &str) {
+        self.levels.retain(|&(ref t, _)| t != target);
+    }
+
+    /// Return the level filter for `target`
+    pub fn level(&self, target: &str) -> LogLevelFilter {
+        for &(ref t, l) in &self.levels {
+            if t == target {
+                return l;
+            }
+        }
+
+        self.default
+    }
+
+    /// Return the most verbose level enabled for any target. Can be
+    /// used to configure the global maximum log level.
+    pub fn max_level(&self) -> LogLevelFilter {
+        self.levels.iter().fold(self.default, |max, &(_, l)| {
+            if l > max { l } else { max }
+        })
+    }
+
+    /// Return true if a message at `level` for `target` should be
+    /// logged
+    pub fn enabled_for(&self, target: &str, level: LogLevel) -> bool {
+        level <= self.level(target)
+    }
+
+    /// Return true if a message described by `metadata` should be
+    /// logged. Meant to be called from `Log::enabled`.
+    pub fn enabled(&self, metadata: &LogMetadata) -> bool {
+        self.enabled_for(metadata.target(), metadata.level())
+    }
+}
@@ -0,0 +1,734 @@
+//! Top-level emulator handle owning all the pieces needed to run the
+//! console.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use bios::Bios;
+use cpu::Cpu;
+use gpu::{Gpu, VideoClock};
+use gpu::renderer::Renderer;
+use memory::Interconnect;
+use cdrom::disc::Disc;
+use cdrom::metadata::GameInfo;
+use config::Database;
+use shared::SharedState;
+use debugger::Debugger;
+use rtc::{TimeSource, DateTime};
+use host::{Clock, DiscSource, MemoryCardSink};
+use savestate::{self, SaveState};
+use crash::{self, AutoSave};
+use memory::busy_wait::BusyWait;
+use memory::layout::MappedRegion;
+use events::Event;
+use padmemcard::memcard::MemoryCard;
+use logging;
+
+/// A complete PlayStation: the CPU (which in turn owns the
+/// interconnect and all the peripherals), the state shared between
+/// the various modules, the debugger and the renderer used to draw
+/// the GPU's output.
+pub struct Machine<D: Debugger, R: Renderer> {
+    cpu: Cpu,
+    shared: SharedState,
+    debugger: D,
+    renderer: R,
+    /// Game database used to identify the discs
+    game_db: Database,
+    /// Periodic savestates, if enabled
+    auto_save: Option<AutoSave>,
+    /// Directory where crash reports are written, if enabled
+    crash_dump_dir: Option<PathBuf>,
+    /// Delayed saving of the memory cards, if enabled
+    memory_card_flush: Option<MemoryCardFlush>,
+    /// True while the reset button of the console is held down
+    reset_button: bool,
+}
+
+impl<D: Debugger, R: Renderer> Machine<D, R> {
+    /// Build a new machine from its components
+    pub fn new(bios: Bios,
+               video_clock: VideoClock,
+               disc: Option<Disc>,
+               debugger: D,
+               renderer: R) -> Machine<D, R> {
+        let gpu = Gpu::new(video_clock);
+        let inter = Interconnect::new(bios, gpu, disc);
+
+        Machine::from_cpu(Cpu::new(inter),
+                          SharedState::new(),
+                          debugger,
+                          renderer)
+    }
+
+    /// Build a machine around an existing `Cpu` and `SharedState`,
+    /// for instance after loading a savestate.
+    pub fn from_cpu(cpu: Cpu,
+                    shared: SharedState,
+                    debugger: D,
+                    renderer: R) -> Machine<D, R> {
+        Machine {
+            cpu: cpu,
+            shared: shared,
+            debugger: debugger,
+            renderer: renderer,
+            game_db: Database::new(),
+            auto_save: None,
+            crash_dump_dir: None,
+            memory_card_flush: None,
+            reset_button: false,
+        }
+    }
+
+    /// Execute a single CPU instruction
+    pub fn step(&mut self) {
+        if self.reset_button {
+            // The CPU doesn't run while the reset line is asserted
+            return;
+        }
+
+        self.cpu.run_next_instruction(&mut self.debugger,
+                                      &mut self.shared,
+                                      &mut self.renderer);
+
+        // When stepping through the code we want to see the
+        // primitives right away
+        self.cpu.interconnect_mut().gpu_mut().flush(&mut self.renderer);
+    }
+
+    /// Run the emulator until the start of the next frame
+    pub fn run_frame(&mut self) {
+        if self.reset_button {
+            return;
+        }
+
+        if self.crash_dump_dir.is_some() {
+            self.run_frame_catch_panic();
+        } else {
+            self.cpu.run_until_next_frame(&mut self.debugger,
+                                          &mut self.shared,
+                                          &mut self.renderer);
+        }
+
+        self.update_memory_cards();
+
+        let save = match self.auto_save {
+            Some(ref mut a) => a.frame_complete(),
+            None => false,
+        };
+
+        if save {
+            // Make sure the memory cards on disk match the state
+            self.flush_memory_cards_or_warn();
+
+            let r = self.save_state().and_then(|s| {
+                self.auto_save.as_mut().unwrap().save(&s)
+            });
+
+            match r {
+                Ok(p) => {
+                    info!(target: logging::SAVESTATE,
+                          "Auto-saved state to {}", p.display());
+
+                    self.shared.push_event(Event::SaveStateComplete(p));
+                }
+                Err(e) => warn!(target: logging::SAVESTATE,
+                                "Auto-save failed: {}", e),
+            }
+        }
+    }
+
+    /// Run a frame, writing a crash report if the emulator panics.
+    /// The panic is then resumed.
+    fn run_frame_catch_panic(&mut self) {
+        let r = {
+            let cpu = &mut self.cpu;
+            let debugger = &mut self.debugger;
+            let shared = &mut self.shared;
+            let renderer = &mut self.renderer;
+
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                cpu.run_until_next_frame(debugger, shared, renderer)
+            }))
+        };
+
+        if let Err(e) = r {
+            let message = panic_message(&*e);
+
+            // The state might be inconsistent if we panicked in the
+            // middle of an instruction but it's still useful to see
+            // what led there
+            let state = self.save_state().ok();
+
+            let dir = self.crash_dump_dir.as_ref().unwrap();
+
+            match crash::write_crash_dump(dir,
+                                          &message,
+                                          state.as_ref(),
+                                          self.cpu.pc_ring()) {
+                Ok(p) => error!(target: logging::SAVESTATE,
+                                "Crash report written to {}", p.display()),
+                Err(err) => error!(target: logging::SAVESTATE,
+                                   "Can't write crash report: {}", err),
+            }
+
+            panic::resume_unwind(e);
+        }
+    }
+
+    /// Return the hardware register the CPU has been polling in a
+    /// tight loop since the last call, if any. That usually means
+    /// that the game waits for a peripheral feature we don't
+    /// emulate. The threshold can be changed through
+    /// `Interconnect::busy_wait_mut`.
+    pub fn take_busy_wait(&mut self) -> Option<BusyWait> {
+        self.cpu.interconnect_mut().take_busy_wait()
+    }
+
+    /// Save the state every few frames in rotating slots, `None`
+    /// disables the automatic saves
+    pub fn set_auto_save(&mut self, auto_save: Option<AutoSave>) {
+        self.auto_save = auto_save;
+    }
+
+    /// Write a crash report and the last state in `dir` if the
+    /// emulator panics in `run_frame`. `None` disables the reports.
+    pub fn set_crash_dump_dir(&mut self, dir: Option<PathBuf>) {
+        self.crash_dump_dir = dir;
+    }
+
+    /// Save the memory cards through `sink` once the game stops
+    /// writing to them for `grace_frames` frames. Games write a save
+    /// one sector at a time, waiting a bit avoids writing the image
+    /// for each of them and saving a half-written file. `None`
+    /// disables the saving, the frontend is then responsible for it.
+    pub fn set_memory_card_sink(&mut self,
+                                sink: Option<Box<MemoryCardSink>>,
+                                grace_frames: u32) {
+        self.memory_card_flush = sink.map(|s| MemoryCardFlush {
+            sink: s,
+            grace_frames: grace_frames,
+            write_counts: [None; 2],
+            idle_frames: [0; 2],
+        });
+    }
+
+    /// Return true if the memory card in `port` (0 or 1) has been
+    /// modified and not saved yet. Frontends should warn the user
+    /// before quitting.
+    pub fn memory_card_busy(&mut self, port: usize) -> bool {
+        self.cpu.interconnect_mut()
+            .pad_memcard_mut()
+            .memory_card_mut(port)
+            .map(|card| card.dirty())
+            .unwrap_or(false)
+    }
+
+    /// Save all the modified memory cards right away, without waiting
+    /// for the grace period. Should be called before exiting.
+    pub fn flush_memory_cards(&mut self) -> Result<(), String> {
+        let flush =
+            match self.memory_card_flush {
+                Some(ref mut f) => f,
+                None => return Ok(()),
+            };
+
+        let pad_memcard = self.cpu.interconnect_mut().pad_memcard_mut();
+
+        let mut result = Ok(());
+
+        for port in 0..2 {
+            if let Some(card) = pad_memcard.memory_card_mut(port) {
+                if card.dirty() {
+                    let r = flush.save(port, card, &mut self.shared);
+
+                    if result.is_ok() {
+                        result = r;
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    fn flush_memory_cards_or_warn(&mut self) {
+        if let Err(e) = self.flush_memory_cards() {
+            warn!(target: logging::PAD_MEMCARD,
+                  "Memory card save failed: {}", e);
+        }
+    }
+
+    /// Called at the end of each frame to save the memory cards which
+    /// haven't been written to during the grace period
+    fn update_memory_cards(&mut self) {
+        let flush =
+            match self.memory_card_flush {
+                Some(ref mut f) => f,
+                None => return,
+            };
+
+        let pad_memcard = self.cpu.interconnect_mut().pad_memcard_mut();
+
+        for port in 0..2 {
+            let card =
+                match pad_memcard.memory_card_mut(port) {
+                    Some(c) => c,
+                    None => continue,
+                };
+
+            let count = Some(card.write_count());
+
+            if count != flush.write_counts[port] {
+                // The game is still writing
+                flush.write_counts[port] = count;
+                flush.idle_frames[port] = 0;
+                continue;
+            }
+
+            if !card.dirty() {
+                continue;
+            }
+
+            flush.idle_frames[port] += 1;
+
+            if flush.idle_frames[port] >= flush.grace_frames {
+                flush.idle_frames[port] = 0;
+
+                if let Err(e) = flush.save(port, card, &mut self.shared) {
+                    // We'll try again after an other grace period
+                    warn!(target: logging::PAD_MEMCARD,
+                          "Memory card save failed: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Soft reset: put the CPU and all the peripherals back in their
+    /// power-on state and restart execution at the beginning of the
+    /// BIOS, like pressing the reset button on the console. The BIOS,
+    /// disc, controllers and RAM contents are kept.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.cpu.interconnect_mut().reset();
+        self.shared.reset();
+    }
+
+    /// Press (`true`) or release the reset button on the front of the
+    /// console. The button drives the reset line directly: the
+    /// console is reset when it's pressed and stays halted until the
+    /// button is released, execution then resumes at the start of
+    /// the BIOS.
+    pub fn set_reset_button(&mut self, pressed: bool) {
+        if pressed && !self.reset_button {
+            info!(target: logging::CPU, "Reset button pressed");
+            self.reset();
+        }
+
+        self.reset_button = pressed;
+    }
+
+    pub fn reset_button(&self) -> bool {
+        self.reset_button
+    }
+
+    /// Capture the complete console state. See `savestate` for the
+    /// format.
+    pub fn save_state(&self) -> Result<SaveState, savestate::Error> {
+        let mut state = SaveState::new();
+
+        try!(self.cpu.save_state(&mut state));
+        try!(self.shared.save_state(&mut state));
+
+        Ok(state)
+    }
+
+    /// Restore a state captured by `save_state`, possibly by an
+    /// other version of the emulator. The machine is reset first so
+    /// that the subsystems missing from `state` end up in their
+    /// power-on state. The BIOS must be the one used to create the
+    /// state, the current disc is kept.
+    pub fn load_state(&mut self,
+                      state: &SaveState) -> Result<(), savestate::Error> {
+        // The state replaces the contents of the memory cards, save
+        // what the game wrote since the last flush first
+        self.flush_memory_cards_or_warn();
+
+        self.reset();
+
+        try!(self.cpu.load_state(state));
+        self.shared.load_state(state)
+    }
+
+    /// Swap the disc for the one at `path` (a CUE sheet). The lid
+    /// is opened immediately and closed again with the new disc
+    /// inside after a short delay, the way multi-disc games expect
+    /// it. Returns the previous disc.
+    pub fn swap_disc(&mut self, path: &Path) -> Result<Option<Disc>, String> {
+        let disc = try!(Disc::from_path(path));
+
+        Ok(self.change_disc(Some(disc)))
+    }
+
+    /// Same as `swap_disc` with a disc provided by `source`
+    pub fn swap_disc_from(&mut self,
+                          source: &mut DiscSource)
+                          -> Result<Option<Disc>, String> {
+        let disc = try!(source.open_disc());
+
+        Ok(self.change_disc(Some(disc)))
+    }
+
+    /// Same as `swap_disc` using an already loaded `disc`. Use `None`
+    /// to eject the current disc and leave the drive empty.
+    pub fn change_disc(&mut self, mut disc: Option<Disc>) -> Option<Disc> {
+        let info = disc.as_mut().map(|d| GameInfo::from_disc(d, &self.game_db));
+
+        self.shared.game_changed(info.as_ref());
+
+        let cdrom = self.cpu.interconnect_mut().cdrom_mut();
+
+        cdrom.swap_disc(&mut self.shared, disc)
+    }
+
+    /// Set the database used to look up the game titles
+    pub fn set_game_database(&mut self, db: Database) {
+        self.game_db = db;
+    }
+
+    /// Identify the game in the drive (or about to be inserted if a
+    /// disc swap is in progress). Frontends can call this after
+    /// building the machine, later changes are reported through the
+    /// `on_game_changed` callback in `SharedState`.
+    pub fn game_info(&mut self) -> Option<GameInfo> {
+        let db = &self.game_db;
+
+        self.cpu.interconnect_mut().cdrom_mut()
+            .disc_mut()
+            .map(|d| GameInfo::from_disc(d, db))
+    }
+
+    /// Overclock or underclock the CPU. `ratio` is the CPU clock
+    /// multiplier (1.0 for the native 33.8685MHz), the GPU, SPU and
+    /// other peripherals keep running at their normal speed. Higher
+    /// values reduce the slowdown in games that can't keep up with
+    /// their target framerate but can break games relying on precise
+    /// timings.
+    pub fn set_cpu_clock_ratio(&mut self, ratio: f32) {
+        self.shared.tk().set_cpu_clock_ratio(ratio);
+    }
+
+    pub fn cpu_clock_ratio(&mut self) -> f32 {
+        self.shared.tk().cpu_clock_ratio()
+    }
+
+    /// Switch between the fast and accurate timing models. Can be
+    /// called at any point between two steps, the pending pipeline
+    /// stalls and GPU draw time are flushed so the switch takes
+    /// effect immediately.
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        let accurate = accuracy == Accuracy::Accurate;
+
+        self.cpu.set_pipeline_timing(accurate);
+        self.cpu.set_write_buffer(accurate);
+
+        self.cpu.interconnect_mut()
+            .pad_memcard_mut()
+            .set_accurate_timing(accurate);
+
+        let gpu = self.cpu.interconnect_mut().gpu_mut();
+
+        // Catch up with the previous line timing before switching,
+        // the next sync date depends on it
+        gpu.sync(&mut self.shared);
+
+        gpu.set_draw_timing(accurate);
+        gpu.set_scanline_timing(accurate);
+        gpu.set_clut_cache(accurate);
+
+        gpu.predict_next_sync(&mut self.shared);
+    }
+
+    /// Return the current timing model. A machine with the pipeline
+    /// timing disabled (the default) is considered to run in fast
+    /// mode even though the GPU draw timing might still be enabled.
+    pub fn accuracy(&self) -> Accuracy {
+        if self.cpu.pipeline_timing() {
+            Accuracy::Accurate
+        } else {
+            Accuracy::Fast
+        }
+    }
+
+    /// Select where the emulated real time clock gets the date
+    /// from. Use `TimeSource::Fixed` or `TimeSource::Accelerated` for
+    /// reproducible runs.
+    pub fn set_time_source(&mut self, source: TimeSource) {
+        let now = self.shared.tk().now();
+
+        self.shared.rtc_mut().set_source(source, now);
+    }
+
+    /// Replace the host clock used by `TimeSource::Host`, for
+    /// frontends running on hosts without a system clock
+    pub fn set_host_clock(&mut self, clock: Box<Clock>) {
+        self.shared.rtc_mut().set_host_clock(clock);
+    }
+
+    /// Seed the generator used for the non-deterministic hardware
+    /// behaviours (CD controller timings...). Machines sharing the
+    /// same seed and inputs run identically, use it for replays and
+    /// netplay.
+    pub fn set_rng_seed(&mut self, seed: u32) {
+        self.shared.set_rng_seed(seed);
+    }
+
+    /// Return the current date of the emulated real time clock
+    pub fn date_time(&mut self) -> DateTime {
+        let now = self.shared.tk().now();
+
+        self.shared.rtc().date_time(now)
+    }
+
+    /// Return the regions currently mapped in the physical address
+    /// space, for the frontends' memory map views
+    pub fn memory_map(&self) -> Vec<MappedRegion> {
+        self.cpu.interconnect().memory_map()
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn shared(&self) -> &SharedState {
+        &self.shared
+    }
+
+    pub fn shared_mut(&mut self) -> &mut SharedState {
+        &mut self.shared
+    }
+
+    pub fn debugger(&self) -> &D {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut D {
+        &mut self.debugger
+    }
+
+    pub fn renderer(&self) -> &R {
+        &self.renderer
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut R {
+        &mut self.renderer
+    }
+
+    /// Destroy the machine and return its components. The memory
+    /// cards are flushed first.
+    pub fn into_parts(mut self) -> (Cpu, SharedState, D, R) {
+        self.flush_memory_cards_or_warn();
+
+        (self.cpu, self.shared, self.debugger, self.renderer)
+    }
+}
+
+/// Memory card saving state
+struct MemoryCardFlush {
+    sink: Box<MemoryCardSink>,
+    /// Number of frames without writes before a card is saved
+    grace_frames: u32,
+    /// `write_count` of the card in each port at the previous frame
+    write_counts: [Option<u32>; 2],
+    /// Number of frames since the last write for each port
+    idle_frames: [u32; 2],
+}
+
+impl MemoryCardFlush {
+    fn save(&mut self,
+            port: usize,
+            card: &mut MemoryCard,
+            shared: &mut SharedState) -> Result<(), String> {
+        try!(self.sink.write_memory_card(port, card.data()));
+
+        card.clear_dirty();
+
+        info!(target: logging::PAD_MEMCARD,
+              "Memory card {} saved", port + 1);
+
+        shared.push_event(Event::MemoryCardFlushed(port));
+
+        Ok(())
+    }
+}
+
+/// Default number of frames to wait after the last memory card write
+/// before saving it (about one second)
+pub const DEFAULT_MEMORY_CARD_GRACE_FRAMES: u32 = 60;
+
+/// Extract the message from a panic payload
+pub fn panic_message(payload: &(Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Unknown panic".into()
+    }
+}
+
+/// Timing models selectable at runtime with `Machine::set_accuracy`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Accuracy {
+    /// No CPU pipeline timing or write buffer, instantaneous GPU draw
+    /// commands and palettes always read from VRAM
+    Fast,
+    /// Emulate the CPU pipeline stalls and write buffer, the GPU
+    /// drawing time and the CLUT cache
+    Accurate,
+}
+
+#[test]
+fn concurrent_machines() {
+    use std::thread;
+    use cpu::test_machine::TestMachine;
+
+    // Each machine counts down from a different value in its own
+    // thread, they mustn't share any state
+    let threads: Vec<_> = (1..5).map(|n| {
+        thread::spawn(move || {
+            let mut m = TestMachine::new()
+                .with_reg(1, n * 1000)
+                .with_blob(0x80100000, &[0x2421ffff, // addiu $1, $1, -1
+                                         0x1420fffe, // bne   $1, $0, -2
+                                         0x24420001, // addiu $2, $2, 1
+                                         0x0bab6fb8, // j     0xeadbee0
+                                         0x00000000]);
+
+            // The first step sets the PC to the start of the blob
+            m.step();
+
+            let mut machine =
+                Machine::from_cpu(m.cpu, m.shared, (), m.renderer);
+
+            while machine.cpu().pc() & 0x0fffffff != 0xeadbee0 {
+                machine.step();
+            }
+
+            let regs = machine.cpu().regs();
+
+            (n, regs[1], regs[2])
+        })
+    }).collect();
+
+    for t in threads {
+        let (n, r1, r2) = t.join().unwrap();
+
+        assert_eq!(r1, 0);
+        assert_eq!(r2, n * 1000);
+    }
+}
+
+#[test]
+fn memory_card_flush() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use cpu::test_machine::TestMachine;
+    use padmemcard::memcard::MemoryCardKind;
+
+    struct Sink(Rc<RefCell<Vec<usize>>>);
+
+    impl MemoryCardSink for Sink {
+        fn write_memory_card(&mut self,
+                             port: usize,
+                             _: &[u8]) -> Result<(), String> {
+            self.0.borrow_mut().push(port);
+            Ok(())
+        }
+    }
+
+    let saved = Rc::new(RefCell::new(Vec::new()));
+
+    let m = TestMachine::new();
+    let mut machine = Machine::from_cpu(m.cpu, m.shared, (), m.renderer);
+
+    machine.set_memory_card_sink(Some(Box::new(Sink(saved.clone()))), 2);
+
+    {
+        let pad_memcard =
+            machine.cpu_mut().interconnect_mut().pad_memcard_mut();
+
+        let card = MemoryCard::new(MemoryCardKind::Standard);
+
+        pad_memcard.set_memory_card(0, Some(card));
+
+        // Write an empty sector 0x123
+        let card = pad_memcard.memory_card_mut(0).unwrap();
+
+        let mut cmds = vec![0x81, 0x57, 0, 0, 0x01, 0x23];
+        cmds.extend_from_slice(&[0; 128]);
+        cmds.extend_from_slice(&[0x01 ^ 0x23, 0, 0, 0]);
+
+        card.select();
+
+        for c in cmds {
+            card.send_command(c);
+        }
+    }
+
+    assert!(machine.memory_card_busy(0));
+
+    // The card is only saved once the grace period has elapsed
+    // without any new write
+    machine.update_memory_cards();
+    machine.update_memory_cards();
+
+    assert!(saved.borrow().is_empty());
+
+    machine.update_memory_cards();
+
+    assert_eq!(*saved.borrow(), vec![0]);
+    assert!(!machine.memory_card_busy(0));
+    assert_eq!(machine.shared_mut().poll_event(),
+               Some(Event::MemoryCardFlushed(0)));
+}
+
+#[test]
+fn reset_button() {
+    use cpu::test_machine::TestMachine;
+    use memory::Word;
+
+    let mut t = TestMachine::new()
+        .with_blob(0x80100000, &[0x24010001, // addiu $1, $0, 1
+                                 0x24020002]); // addiu $2, $0, 2
+
+    // The first step sets the PC to the start of the blob
+    t.step();
+
+    let mut m = Machine::from_cpu(t.cpu, t.shared, (), t.renderer);
+
+    m.step();
+    assert_eq!(m.cpu().regs()[2], 2);
+
+    m.set_reset_button(true);
+    assert!(m.reset_button());
+    assert_eq!(m.cpu().pc(), 0xbfc00000);
+
+    // The console is halted while the button is held
+    m.step();
+    m.run_frame();
+    assert_eq!(m.cpu().pc(), 0xbfc00000);
+
+    // The RAM isn't cleared by the reset
+    assert_eq!(m.cpu().interconnect().ram().load::<Word>(0x100000),
+               0x24010001);
+
+    m.set_reset_button(false);
+    m.step();
+    assert!(m.cpu().pc() != 0xbfc00000);
+}
@@ -1,6 +1,7 @@
 use memory::Addressable;
 use shared::SharedState;
 use tracer::module_tracer;
+use logging;
 
 /// Motion Decoder (sometimes called macroblock or movie decoder).
 #[derive(RustcDecodable, RustcEncodable)]
@@ -155,7 +156,8 @@ impl MDec {
                 },
                 3 => (32, MDec::handle_idct_matrix),
                 n => {
-                    warn!("Unsupported MDEC opcode {} ({:08x})", n, cmd);
+                    warn!(target: logging::MDEC,
+                          "Unsupported MDEC opcode {} ({:08x})", n, cmd);
                     (1, MDec::handle_command)
                 }
             };
@@ -0,0 +1,137 @@
+//! Busy-wait detection heuristic.
+//!
+//! Games often poll a hardware register in a tight loop while waiting
+//! for a peripheral (GPU ready, CDROM response, DMA completion...).
+//! If the feature they wait for isn't emulated the loop never ends
+//! and the emulator just spins silently. `BusyWaitDetector` counts
+//! the consecutive loads from the same I/O register, with no other
+//! I/O access in between, and reports the register once the count
+//! exceeds a threshold so that frontends can warn the user and the
+//! compatibility database can flag the missing feature.
+
+use std::fmt;
+
+use super::io_trace::{register_name, IO_START, IO_END};
+
+/// Default number of consecutive polls before reporting a busy-wait.
+/// Legitimate waits (for instance for the vertical blanking) don't
+/// last more than a few tens of thousands of polls.
+pub const DEFAULT_THRESHOLD: u32 = 500_000;
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct BusyWaitDetector {
+    /// Address of the register being polled
+    addr: u32,
+    /// Number of consecutive loads from `addr`
+    polls: u32,
+    /// Number of polls before we report the busy-wait
+    threshold: u32,
+    /// Busy-wait detected since the last call to `take`
+    detected: Option<BusyWait>,
+}
+
+impl BusyWaitDetector {
+    pub fn new() -> BusyWaitDetector {
+        BusyWaitDetector {
+            addr: 0,
+            polls: 0,
+            threshold: DEFAULT_THRESHOLD,
+            detected: None,
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: u32) {
+        self.threshold = threshold;
+    }
+
+    /// Called for every CPU load. `cdrom_index` is used to name the
+    /// CDROM registers.
+    pub fn load(&mut self, abs_addr: u32, cdrom_index: u8) {
+        if abs_addr < IO_START || abs_addr >= IO_END {
+            return;
+        }
+
+        if abs_addr != self.addr {
+            self.addr = abs_addr;
+            self.polls = 0;
+        }
+
+        self.polls = self.polls.saturating_add(1);
+
+        if self.polls >= self.threshold {
+            self.detected = Some(BusyWait {
+                addr: abs_addr,
+                polls: self.polls,
+                cdrom_index: cdrom_index,
+            });
+        }
+    }
+
+    /// Called for every CPU store: writing to a register means that
+    /// the code is making progress
+    pub fn store(&mut self, abs_addr: u32) {
+        if abs_addr >= IO_START && abs_addr < IO_END {
+            self.polls = 0;
+        }
+    }
+
+    /// Return the busy-wait detected since the last call, if any
+    pub fn take(&mut self) -> Option<BusyWait> {
+        self.detected.take()
+    }
+}
+
+/// Register polled by the CPU
+#[derive(Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub struct BusyWait {
+    /// Absolute address of the register
+    pub addr: u32,
+    /// Number of consecutive polls so far
+    pub polls: u32,
+    /// CDROM index register when the busy-wait was detected
+    cdrom_index: u8,
+}
+
+impl BusyWait {
+    /// Return the peripheral (as a log target) and the name of the
+    /// register
+    pub fn register(&self) -> (&'static str, String) {
+        register_name(self.addr, false, self.cdrom_index)
+    }
+}
+
+impl fmt::Display for BusyWait {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (target, name) = self.register();
+
+        write!(f, "CPU busy-waiting on {} {} (0x{:08x}), {} polls",
+               target, name, self.addr, self.polls)
+    }
+}
+
+#[test]
+fn busy_wait() {
+    let gpustat = 0x1f801814;
+
+    let mut d = BusyWaitDetector::new();
+
+    d.set_threshold(3);
+
+    d.load(gpustat, 0);
+    d.load(gpustat, 0);
+    // RAM accesses don't count
+    d.load(0x100, 0);
+    assert!(d.take().is_none());
+
+    d.load(gpustat, 0);
+
+    let w = d.take().unwrap();
+
+    assert_eq!(w.addr, gpustat);
+    assert_eq!(w.polls, 3);
+
+    // Writing to a register resets the count
+    d.store(0x1f801810);
+    d.load(gpustat, 0);
+    assert!(d.take().is_none());
+}
@@ -1,5 +1,6 @@
 use shared::SharedState;
 use interrupt::Interrupt;
+use timekeeper::Cycles;
 
 use tracer::SizedValue;
 
@@ -81,14 +82,14 @@ impl Dma {
 
         self.force_irq = (val >> 15) & 1 != 0;
 
-        // XXX I don't think disabling the channel IRQ clears the
-        // interrupt in channel_irq_flags but I should check that.
+        // Disabling a channel's IRQ doesn't clear its flag, it has to
+        // be acknowledged explicitly
         self.channel_irq_en = ((val >> 16) & 0x7f) as u8;
 
         self.irq_en = (val >> 23) & 1 != 0;
 
         // Writing 1 to a flag resets it
-        let ack = ((val >> 24) & 0x3f) as u8;
+        let ack = ((val >> 24) & 0x7f) as u8;
         self.channel_irq_flags &= !ack;
 
         if !prev_irq && self.irq() {
@@ -97,6 +98,59 @@ impl Dma {
         }
     }
 
+    /// Return true if the channel for `port` is enabled in the
+    /// control register. Disabled channels don't transfer anything.
+    pub fn port_enabled(&self, port: Port) -> bool {
+        let shift = (port as u32) * 4;
+
+        (self.control >> shift) & 8 != 0
+    }
+
+    /// Return the priority of `port` as configured in the control
+    /// register. Lower values have higher priority.
+    pub fn port_priority(&self, port: Port) -> u32 {
+        let shift = (port as u32) * 4;
+
+        (self.control >> shift) & 7
+    }
+
+    /// Return the port with the highest priority amongst the ones
+    /// with a chopped transfer ready to resume at date `now`. When
+    /// two channels have the same priority the one with the highest
+    /// port number wins.
+    pub fn next_ready(&self, now: Cycles) -> Option<Port> {
+        let mut best: Option<(Port, u32)> = None;
+
+        for i in 0..7 {
+            let port = Port::from_index(i);
+            let channel = self.channel(port);
+
+            if !channel.in_progress()
+                || !self.port_enabled(port)
+                || channel.resume > now {
+                continue;
+            }
+
+            let priority = self.port_priority(port);
+
+            best = match best {
+                Some((_, p)) if p < priority => best,
+                _ => Some((port, priority)),
+            };
+        }
+
+        best.map(|(port, _)| port)
+    }
+
+    /// Return the date at which the next chopped transfer should
+    /// resume or `None` if no transfer is pending.
+    pub fn next_resume(&self) -> Option<Cycles> {
+        self.channels.iter()
+            .filter(|c| c.in_progress())
+            .map(|c| c.resume)
+            .min()
+    }
+
     /// Return a reference to a channel by port number.
     pub fn channel(&self, port: Port) -> &Channel {
         &self.channels[port as usize]
@@ -157,6 +211,16 @@ pub struct Channel {
     block_count: u16,
     /// Unkown 2 RW bits in configuration register
     dummy: u8,
+    /// Address of the next word for block transfers in progress
+    cursor: u32,
+    /// Number of words left to transfer in the current block
+    /// transfer. 0 if no transfer is in progress.
+    remaining: u32,
+    /// Date at which a chopped transfer can resume
+    resume: Cycles,
+    /// Set while a linked list transfer is paused waiting for the
+    /// GPU's DMA request
+    list_pending: bool,
 }
 
 impl Channel {
@@ -174,6 +238,10 @@ impl Channel {
             block_size: 0,
             block_count: 0,
             dummy: 0,
+            cursor: 0,
+            remaining: 0,
+            resume: 0,
+            list_pending: false,
         }
     }
 
@@ -236,6 +304,14 @@ impl Channel {
         self.dummy = ((val >> 29) & 3) as u8;
     }
 
+    /// Set the value of the control register of the OTC channel
+    /// (port 6). Only the enable, trigger and one of the unknown bits
+    /// are writeable, the transfer always goes backwards to RAM in
+    /// manual sync mode without chopping.
+    pub fn set_otc_control(&mut self, val: u32) {
+        self.set_control((val & OTC_CONTROL_MASK) | OTC_CONTROL_FIXED);
+    }
+
     /// Retrieve value of the Block Control register
     pub fn block_control(&self) -> u32 {
         let bs = self.block_size as u32;
@@ -266,6 +342,77 @@ impl Channel {
     fn done(&mut self) {
         self.enable = false;
         self.trigger = false;
+        self.remaining = 0;
+        self.list_pending = false;
+    }
+
+    /// Return true if a block transfer has been started and hasn't
+    /// completed yet, or if a linked list transfer is paused
+    pub fn in_progress(&self) -> bool {
+        self.remaining > 0 || self.list_pending
+    }
+
+    /// Start a new block transfer. Does nothing in linked list mode.
+    pub fn start_block(&mut self) {
+        self.cursor = self.base;
+        self.remaining = self.transfer_size().unwrap_or(0);
+        self.resume = 0;
+    }
+
+    /// Abort any transfer in progress
+    pub fn stop(&mut self) {
+        self.remaining = 0;
+        self.list_pending = false;
+    }
+
+    /// Pause a linked list transfer until `date`. `addr` is the
+    /// address of the next header, like on the real hardware it's
+    /// stored in the base address register.
+    pub fn pause_linked_list(&mut self, addr: u32, date: Cycles) {
+        self.set_base(addr);
+        self.list_pending = true;
+        self.resume = date;
+    }
+
+    /// Called when a linked list transfer starts or resumes
+    pub fn start_linked_list(&mut self) {
+        self.list_pending = false;
+    }
+
+    /// Address of the next word to be transferred
+    pub fn cursor(&self) -> u32 {
+        self.cursor
+    }
+
+    /// Number of words left in the current block transfer
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Move the transfer forward by one word
+    pub fn advance(&mut self) {
+        let increment = match self.step {
+            Step::Increment =>  4,
+            Step::Decrement => -4i32 as u32,
+        };
+
+        self.cursor = self.cursor.wrapping_add(increment);
+        self.remaining -= 1;
+    }
+
+    /// Set the date at which a chopped transfer can resume
+    pub fn set_resume(&mut self, date: Cycles) {
+        self.resume = date;
+    }
+
+    /// If chopping is enabled return the size of the DMA window (in
+    /// words) and the size of the CPU window (in cycles).
+    pub fn chop_windows(&self) -> Option<(u32, Cycles)> {
+        if self.chop {
+            Some((1 << self.chop_dma_sz, 1 << self.chop_cpu_sz))
+        } else {
+            None
+        }
     }
 
     pub fn direction(&self) -> Direction {
@@ -287,8 +434,9 @@ impl Channel {
         let bc = self.block_count as u32;
 
         match self.sync {
-            // For manual mode only the block size is used
-            Sync::Manual => Some(bs),
+            // For manual mode only the block size is used, 0 means
+            // 0x10000 words
+            Sync::Manual => Some(if bs == 0 { 0x10000 } else { bs }),
             // In DMA request mode we must transfer `bc` blocks
             Sync::Request => Some(bc * bs),
             // In linked list mode the size is not known ahead of
@@ -299,6 +447,13 @@ impl Channel {
     }
 }
 
+/// Writeable bits in the control register of the OTC channel
+const OTC_CONTROL_MASK: u32 = 0x51000000;
+
+/// Bits always set in the control register of the OTC channel: the
+/// RAM address is decremented after each word
+const OTC_CONTROL_FIXED: u32 = 0x00000002;
+
 /// DMA transfer direction
 #[derive(Clone, Copy, PartialEq, Eq, RustcDecodable, RustcEncodable)]
 pub enum Direction {
@@ -370,3 +525,72 @@ impl From<Port> for SizedValue {
         SizedValue(v as u32, 3)
     }
 }
+
+#[test]
+fn otc_control() {
+    let mut channel = Channel::new();
+
+    // Try to setup a chopped, incrementing linked list transfer from
+    // RAM
+    channel.set_otc_control(0x11000701);
+
+    assert_eq!(channel.control(), 0x11000002);
+    assert!(channel.direction() == Direction::ToRam);
+
+    channel.set_block_control(0);
+    channel.start_block();
+
+    assert_eq!(channel.remaining(), 0x10000);
+}
+
+#[test]
+fn interrupt_register() {
+    let mut shared = SharedState::new();
+    let mut dma = Dma::new();
+
+    // Enable the OTC and GPU channel interrupts without the master
+    // enable
+    dma.set_interrupt(&mut shared, 0x00440000);
+    dma.done(&mut shared, Port::Otc);
+
+    assert_eq!(dma.interrupt(), 0x40440000);
+    assert_eq!(shared.irq_state().status(), 0);
+
+    // Setting the master enable with a flag already set is a rising
+    // edge
+    dma.set_interrupt(&mut shared, 0x00c40000);
+
+    assert_eq!(dma.interrupt(), 0xc0c40000);
+    assert_eq!(shared.irq_state().status(), 1 << (Interrupt::Dma as u16));
+
+    shared.irq_state_mut().ack(0);
+
+    // Another channel completing doesn't generate a new edge while
+    // the master flag is still set
+    dma.done(&mut shared, Port::Gpu);
+
+    assert_eq!(dma.interrupt(), 0xc4c40000);
+    assert_eq!(shared.irq_state().status(), 0);
+
+    // Acknowledge the OTC flag, the GPU flag keeps the master flag
+    // up
+    dma.set_interrupt(&mut shared, 0x40c40000);
+
+    assert_eq!(dma.interrupt(), 0x84c40000);
+
+    dma.set_interrupt(&mut shared, 0x04c40000);
+
+    assert_eq!(dma.interrupt(), 0x00c40000);
+
+    // Channels without IRQ enable don't set their flag
+    dma.done(&mut shared, Port::Spu);
+
+    assert_eq!(dma.interrupt(), 0x00c40000);
+    assert_eq!(shared.irq_state().status(), 0);
+
+    // Force IRQ
+    dma.set_interrupt(&mut shared, 0x00008000);
+
+    assert_eq!(dma.interrupt(), 0x80008000);
+    assert_eq!(shared.irq_state().status(), 1 << (Interrupt::Dma as u16));
+}
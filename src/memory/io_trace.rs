@@ -0,0 +1,338 @@
+//! I/O register trace: logs the CPU accesses to the hardware
+//! registers (0x1f801000 to 0x1f803000) with decoded register
+//! names. Meant to make sense of the initialization sequences of
+//! games and of the BIOS.
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+use logging;
+
+pub struct IoTrace {
+    enabled: bool,
+    /// Peripherals (identified by their log target) to trace. If it's
+    /// empty everything is traced.
+    filter: Vec<&'static str>,
+}
+
+impl IoTrace {
+    pub fn new() -> IoTrace {
+        IoTrace {
+            enabled: false,
+            filter: Vec::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Only trace the peripherals in `targets` (using the log targets
+    /// in `logging`, for instance `logging::GPU`). An empty list
+    /// traces everything.
+    pub fn set_filter(&mut self, targets: &[&'static str]) {
+        self.filter = targets.to_vec();
+    }
+
+    /// Trace a load of `size` bytes from `addr` which returned `val`.
+    /// `cdrom_index` is the current value of the CDROM index register
+    /// used to decode the other CDROM registers.
+    pub fn load(&self, addr: u32, size: u8, val: u32, cdrom_index: u8) {
+        self.trace(addr, size, val, cdrom_index, false);
+    }
+
+    /// Trace a store of `val` (`size` bytes) to `addr`
+    pub fn store(&self, addr: u32, size: u8, val: u32, cdrom_index: u8) {
+        self.trace(addr, size, val, cdrom_index, true);
+    }
+
+    fn trace(&self,
+             addr: u32,
+             size: u8,
+             val: u32,
+             cdrom_index: u8,
+             store: bool) {
+        let abs_addr = super::map::mask_region(addr);
+
+        if abs_addr < IO_START || abs_addr >= IO_END {
+            return;
+        }
+
+        let (peripheral, name) =
+            register_name(abs_addr, store, cdrom_index);
+
+        if !self.filter.is_empty() && !self.filter.contains(&peripheral) {
+            return;
+        }
+
+        let (dir, arrow) = if store { ('W', "<-") } else { ('R', "->") };
+
+        info!(target: logging::IO_TRACE,
+              "{}{:<2} {:<16} [0x{:08x}] {} 0x{:0width$x}",
+              dir, size * 8, name, abs_addr, arrow, val,
+              width = size as usize * 2);
+    }
+}
+
+impl Encodable for IoTrace {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Debugging configuration, not part of the console state
+        s.emit_nil()
+    }
+}
+
+impl Decodable for IoTrace {
+    fn decode<D: Decoder>(d: &mut D) -> Result<IoTrace, D::Error> {
+        try!(d.read_nil());
+
+        Ok(IoTrace::new())
+    }
+}
+
+/// Start of the traced I/O area
+pub const IO_START: u32 = 0x1f801000;
+/// End of the traced I/O area
+pub const IO_END: u32 = 0x1f803000;
+
+/// Return the peripheral (as a log target) and the name of the
+/// register at absolute address `addr`. Some registers have a
+/// different meaning when written or read.
+pub fn register_name(addr: u32,
+                     store: bool,
+                     cdrom_index: u8) -> (&'static str, String) {
+    use super::map;
+
+    let addr = map::PhysAddr::from_cpu(addr);
+
+    if let Some(off) = map::MEM_CONTROL.contains(addr) {
+        let names = ["EXP1_BASE", "EXP2_BASE", "EXP1_DELAY",
+                     "EXP3_DELAY", "BIOS_DELAY", "SPU_DELAY",
+                     "CDROM_DELAY", "EXP2_DELAY", "COM_DELAY"];
+
+        return (logging::MEMORY, indexed(&names, off, 4, "MEMCTRL"));
+    }
+
+    if let Some(off) = map::PAD_MEMCARD.contains(addr) {
+        let name =
+            match off {
+                0 => "JOY_DATA",
+                4 => "JOY_STAT",
+                8 => "JOY_MODE",
+                0xa => "JOY_CTRL",
+                0xe => "JOY_BAUD",
+                _ => return (logging::PAD_MEMCARD, raw("JOY", off)),
+            };
+
+        return (logging::PAD_MEMCARD, name.into());
+    }
+
+    if let Some(off) = map::SIO1.contains(addr) {
+        let name =
+            match off {
+                0 => "SIO_DATA",
+                4 => "SIO_STAT",
+                8 => "SIO_MODE",
+                0xa => "SIO_CTRL",
+                0xc => "SIO_MISC",
+                0xe => "SIO_BAUD",
+                _ => return (logging::SIO1, raw("SIO", off)),
+            };
+
+        return (logging::SIO1, name.into());
+    }
+
+    if let Some(_) = map::RAM_SIZE.contains(addr) {
+        return (logging::MEMORY, "RAM_SIZE".into());
+    }
+
+    if let Some(off) = map::IRQ_CONTROL.contains(addr) {
+        return (logging::CPU, indexed(&["I_STAT", "I_MASK"], off, 4, "IRQ"));
+    }
+
+    if let Some(off) = map::DMA.contains(addr) {
+        let name =
+            match off {
+                0x70 => "DPCR".into(),
+                0x74 => "DICR".into(),
+                _ if off < 0x70 => {
+                    let reg = ["MADR", "BCR", "CHCR"];
+
+                    match reg.get(((off & 0xf) / 4) as usize) {
+                        Some(r) => format!("D{}_{}", off >> 4, r),
+                        None => raw("DMA", off),
+                    }
+                }
+                _ => raw("DMA", off),
+            };
+
+        return (logging::DMA, name);
+    }
+
+    if let Some(off) = map::TIMERS.contains(addr) {
+        let reg = ["CNT", "MODE", "TGT"];
+
+        let name =
+            match reg.get(((off & 0xf) / 4) as usize) {
+                Some(r) => format!("T{}_{}", off >> 4, r),
+                None => raw("TIMER", off),
+            };
+
+        return (logging::TIMERS, name);
+    }
+
+    if let Some(off) = map::CDROM.contains(addr) {
+        return (logging::CDROM, cdrom_register(off, store, cdrom_index));
+    }
+
+    if let Some(off) = map::GPU.contains(addr) {
+        let name =
+            match (off, store) {
+                (0, false) => "GPUREAD",
+                (0, true) => "GP0",
+                (4, false) => "GPUSTAT",
+                (4, true) => "GP1",
+                _ => return (logging::GPU, raw("GPU", off)),
+            };
+
+        return (logging::GPU, name.into());
+    }
+
+    if let Some(off) = map::MDEC.contains(addr) {
+        let name =
+            match (off, store) {
+                (0, false) => "MDEC_DATA",
+                (0, true) => "MDEC_CMD",
+                (4, false) => "MDEC_STAT",
+                (4, true) => "MDEC_CTRL",
+                _ => return (logging::MDEC, raw("MDEC", off)),
+            };
+
+        return (logging::MDEC, name.into());
+    }
+
+    if let Some(off) = map::SPU.contains(addr) {
+        return (logging::SPU, spu_register(off));
+    }
+
+    if let Some(off) = map::EXPANSION_2.contains(addr) {
+        let name =
+            match off {
+                0x20...0x2f => format!("DUART_{:02X}", off),
+                0x41 => "POST".into(),
+                _ => raw("EXP2", off),
+            };
+
+        return (logging::DEBUG_UART, name);
+    }
+
+    (logging::MEMORY, format!("IO_{:08X}", addr.get()))
+}
+
+/// Name for the register at `off` in a bank of registers `stride`
+/// bytes apart
+fn indexed(names: &[&str], off: u32, stride: u32, prefix: &str) -> String {
+    if off % stride == 0 {
+        if let Some(n) = names.get((off / stride) as usize) {
+            return (*n).into();
+        }
+    }
+
+    raw(prefix, off)
+}
+
+/// Fallback name for registers we don't know about
+fn raw(prefix: &str, off: u32) -> String {
+    format!("{}+0x{:x}", prefix, off)
+}
+
+/// The meaning of the CDROM registers 1 to 3 depends on the value of
+/// the index register and on the direction of the access
+fn cdrom_register(off: u32, store: bool, index: u8) -> String {
+    let name =
+        match (off, store, index & 3) {
+            (0, _, _) => "CDROM_INDEX",
+            (1, false, _) => "CDROM_RESPONSE",
+            (2, false, _) => "CDROM_DATA",
+            (3, false, 0) | (3, false, 2) => "CDROM_IE",
+            (3, false, _) => "CDROM_IF",
+            (1, true, 0) => "CDROM_CMD",
+            (1, true, 1) => "CDROM_SMDOUT",
+            (1, true, 2) => "CDROM_SMCI",
+            (1, true, _) => "CDROM_VOL_RR",
+            (2, true, 0) => "CDROM_PARAM",
+            (2, true, 1) => "CDROM_IE",
+            (2, true, 2) => "CDROM_VOL_LL",
+            (2, true, _) => "CDROM_VOL_RL",
+            (3, true, 0) => "CDROM_REQUEST",
+            (3, true, 1) => "CDROM_IF",
+            (3, true, 2) => "CDROM_VOL_LR",
+            (3, true, _) => "CDROM_VOL_APPLY",
+            _ => return raw("CDROM", off),
+        };
+
+    name.into()
+}
+
+fn spu_register(off: u32) -> String {
+    if off < 0x180 {
+        let reg = ["VOL_L", "VOL_R", "PITCH", "START",
+                   "ADSR_LO", "ADSR_HI", "ADSR_VOL", "REPEAT"];
+
+        return match reg.get(((off & 0xf) / 2) as usize) {
+            Some(r) if off & 1 == 0 => format!("SPU_V{}_{}", off >> 4, r),
+            _ => raw("SPU", off),
+        };
+    }
+
+    let name =
+        match off {
+            0x180 => "SPU_MAIN_VOL_L",
+            0x182 => "SPU_MAIN_VOL_R",
+            0x184 => "SPU_REVERB_VOL_L",
+            0x186 => "SPU_REVERB_VOL_R",
+            0x188 => "SPU_KON_LO",
+            0x18a => "SPU_KON_HI",
+            0x18c => "SPU_KOFF_LO",
+            0x18e => "SPU_KOFF_HI",
+            0x190 => "SPU_PMON_LO",
+            0x192 => "SPU_PMON_HI",
+            0x194 => "SPU_NON_LO",
+            0x196 => "SPU_NON_HI",
+            0x198 => "SPU_EON_LO",
+            0x19a => "SPU_EON_HI",
+            0x19c => "SPU_ENDX_LO",
+            0x19e => "SPU_ENDX_HI",
+            0x1a2 => "SPU_REVERB_BASE",
+            0x1a4 => "SPU_IRQ_ADDR",
+            0x1a6 => "SPU_TRANSFER_ADDR",
+            0x1a8 => "SPU_TRANSFER_FIFO",
+            0x1aa => "SPUCNT",
+            0x1ac => "SPU_TRANSFER_CTRL",
+            0x1ae => "SPUSTAT",
+            0x1b0 => "SPU_CD_VOL_L",
+            0x1b2 => "SPU_CD_VOL_R",
+            0x1b4 => "SPU_EXT_VOL_L",
+            0x1b6 => "SPU_EXT_VOL_R",
+            0x1b8 => "SPU_CUR_VOL_L",
+            0x1ba => "SPU_CUR_VOL_R",
+            0x1c0...0x1ff => return format!("SPU_REVERB_{:02X}", off - 0x1c0),
+            _ => return raw("SPU", off),
+        };
+
+    name.into()
+}
+
+#[test]
+fn names() {
+    assert_eq!(register_name(0x1f801814, false, 0).1, "GPUSTAT");
+    assert_eq!(register_name(0x1f801814, true, 0).1, "GP1");
+    assert_eq!(register_name(0x1f8010a8, true, 0).1, "D2_CHCR");
+    assert_eq!(register_name(0x1f801800, true, 0).1, "CDROM_INDEX");
+    assert_eq!(register_name(0x1f801801, true, 0).1, "CDROM_CMD");
+    assert_eq!(register_name(0x1f801803, true, 1).1, "CDROM_IF");
+    assert_eq!(register_name(0x1f801124, false, 0).1, "T2_MODE");
+    assert_eq!(register_name(0x1f801c1c, true, 0).1, "SPU_V1_ADSR_VOL");
+}
@@ -0,0 +1,147 @@
+//! Description of the physical address space, meant for the
+//! frontends' memory map views and for the tests checking that every
+//! region decoded by the hardware is handled.
+//!
+//! The list is built from the same constants as the address decoder
+//! in `Interconnect`. The expansion and BIOS regions are reported with
+//! the size of the window currently configured in the memory control
+//! registers.
+
+use std::cmp;
+
+use super::map::{self, Range};
+
+/// Type of handler behind a region
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Handler {
+    /// Plain read/write memory
+    Ram,
+    /// Read-only memory
+    Rom,
+    /// Peripheral registers, accesses can have side effects
+    Io,
+    /// Device plugged in an expansion port
+    Expansion,
+    /// Decoded by the hardware but not emulated, accesses are
+    /// treated as unhandled
+    Unimplemented,
+}
+
+/// A region of the physical address space
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MappedRegion {
+    pub name: &'static str,
+    /// Physical address of the first byte
+    pub base: u32,
+    /// Size in bytes
+    pub size: u32,
+    /// Access widths supported by the emulated handler, in bytes
+    pub widths: &'static [u8],
+    pub handler: Handler,
+}
+
+impl MappedRegion {
+    fn new(name: &'static str,
+           range: Range,
+           widths: &'static [u8],
+           handler: Handler) -> MappedRegion {
+        MappedRegion {
+            name: name,
+            base: range.0,
+            size: range.1,
+            widths: widths,
+            handler: handler,
+        }
+    }
+
+    /// Return true if `abs_addr` is within this region
+    pub fn contains(&self, abs_addr: u32) -> bool {
+        abs_addr >= self.base && abs_addr - self.base < self.size
+    }
+}
+
+/// Build the list of regions sorted by address. `exp1_window`,
+/// `exp2_window` and `bios_window` are the sizes of the windows
+/// configured in the memory control registers. The BIOS is mirrored
+/// over the whole window.
+pub fn regions(exp1_window: u32,
+               exp2_window: u32,
+               bios_window: u32) -> Vec<MappedRegion> {
+    use self::Handler::*;
+
+    let exp1 = Range(map::EXPANSION_1.0,
+                     cmp::min(exp1_window, map::EXPANSION_1.1));
+    let exp2 = Range(map::EXPANSION_2.0,
+                     cmp::min(exp2_window, map::EXPANSION_2.1));
+    let bios = Range(map::BIOS_MIRRORS.0,
+                     cmp::min(bios_window, map::BIOS_MIRRORS.1));
+
+    let mut regions = vec![
+        MappedRegion::new("RAM", map::RAM, ALL, Ram),
+        MappedRegion::new("Expansion 1", exp1, ALL, Expansion),
+        MappedRegion::new("ScratchPad", map::SCRATCH_PAD, ALL, Ram),
+        MappedRegion::new("Memory control", map::MEM_CONTROL, WORD, Io),
+        MappedRegion::new("Pad/memory card", map::PAD_MEMCARD, ALL, Io),
+        MappedRegion::new("SIO1", map::SIO1, ALL, Io),
+        MappedRegion::new("RAM size", map::RAM_SIZE, WORD, Io),
+        MappedRegion::new("IRQ control", map::IRQ_CONTROL, ALL, Io),
+        MappedRegion::new("DMA", map::DMA, ALL, Io),
+        MappedRegion::new("Timers", map::TIMERS, HALFWORD_WORD, Io),
+        MappedRegion::new("CD-ROM", map::CDROM, BYTE, Io),
+        MappedRegion::new("GPU", map::GPU, WORD, Io),
+        MappedRegion::new("MDEC", map::MDEC, WORD, Io),
+        MappedRegion::new("SPU", map::SPU, HALFWORD, Io),
+        MappedRegion::new("Expansion 2", exp2, BYTE, Expansion),
+        MappedRegion::new("Expansion 3", map::EXPANSION_3, ALL,
+                          Unimplemented),
+        MappedRegion::new("BIOS", bios, ALL, Rom),
+        // The cache control register lives in this window
+        MappedRegion::new("KSEG2 I/O", map::KSEG2_IO, WORD, Io),
+    ];
+
+    // An empty window isn't mapped at all
+    regions.retain(|r| r.size > 0);
+
+    regions
+}
+
+const ALL: &'static [u8] = &[1, 2, 4];
+const BYTE: &'static [u8] = &[1];
+const HALFWORD: &'static [u8] = &[2];
+const HALFWORD_WORD: &'static [u8] = &[2, 4];
+const WORD: &'static [u8] = &[4];
+
+#[test]
+fn layout_consistency() {
+    let all = regions(!0, !0, map::BIOS.1);
+
+    for r in &all {
+        assert!(!r.widths.is_empty());
+
+        // Everything we list must be decoded by the bus
+        for &addr in &[r.base, r.base + r.size - 1] {
+            assert!(map::is_mapped(map::PhysAddr::from_cpu(addr)),
+                    "{} isn't on the bus", r.name);
+        }
+    }
+
+    // Sorted and non-overlapping
+    for pair in all.windows(2) {
+        assert!(pair[0].base + pair[0].size <= pair[1].base,
+                "{} overlaps {}", pair[0].name, pair[1].name);
+    }
+
+    // Disabled expansion windows are left out
+    assert!(regions(0, 0, map::BIOS.1)
+            .iter()
+            .all(|r| r.handler != Handler::Expansion));
+
+    // The BIOS follows its window, up to 4MB
+    let bios = |window| {
+        regions(0, 0, window).into_iter().find(|r| r.name == "BIOS")
+    };
+
+    assert_eq!(bios(map::BIOS.1).unwrap().size, map::BIOS.1);
+    assert_eq!(bios(1 << 20).unwrap().size, 1 << 20);
+    assert_eq!(bios(!0).unwrap().size, map::BIOS_MIRRORS.1);
+}
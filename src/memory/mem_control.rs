@@ -0,0 +1,253 @@
+//! Memory control registers at 0x1f801000. They configure the base
+//! address of the expansion regions as well as the size and access
+//! timings of the devices connected to the CPU's secondary bus
+//! (BIOS, SPU, CDROM and expansions).
+
+use std::cmp;
+
+use timekeeper::Cycles;
+use logging;
+
+use super::map;
+
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+pub struct MemControl {
+    /// Expansion 1 base address
+    expansion_1_base: u32,
+    /// Expansion 2 base address
+    expansion_2_base: u32,
+    /// Delay/size registers, indexed by `Region`
+    delay_size: [DelaySize; 6],
+    /// Common delays shared by all the regions (COM_DELAY)
+    com_delay: u32,
+}
+
+impl MemControl {
+    /// The registers' contents at power-on is unknown, use the values
+    /// the BIOS programs at boot so that code running before it (or
+    /// without it) sees a sensible configuration.
+    pub fn new() -> MemControl {
+        MemControl {
+            expansion_1_base: 0x1f000000,
+            expansion_2_base: 0x1f802000,
+            delay_size: [
+                DelaySize(0x0013243f),
+                DelaySize(0x00003022),
+                DelaySize(0x0013243f),
+                DelaySize(0x200931e1),
+                DelaySize(0x00020843),
+                DelaySize(0x00070777),
+                ],
+            com_delay: 0x00031125,
+        }
+    }
+
+    /// Register read, `offset` is relative to the start of the
+    /// register window
+    pub fn load(&self, offset: u32) -> u32 {
+        match offset {
+            0x00 => self.expansion_1_base,
+            0x04 => self.expansion_2_base,
+            0x08...0x1c => {
+                let region = Region::from_offset(offset);
+
+                self.delay_size[region as usize].0
+            }
+            0x20 => self.com_delay,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Register write. Returns an error message if the value is not
+    /// supported by the emulator, in which case it's still stored.
+    pub fn store(&mut self, offset: u32, val: u32) -> Result<(), String> {
+        match offset {
+            0x00 => {
+                // The top 8 bits are hardwired
+                self.expansion_1_base = (val & 0xffffff) | 0x1f000000;
+
+                if self.expansion_1_base != map::EXPANSION_1.0 {
+                    return Err(format!("Bad expansion 1 base address: \
+                                        0x{:08x}", val));
+                }
+            }
+            0x04 => {
+                self.expansion_2_base = (val & 0xffffff) | 0x1f000000;
+
+                if self.expansion_2_base != map::EXPANSION_2.0 {
+                    return Err(format!("Bad expansion 2 base address: \
+                                        0x{:08x}", val));
+                }
+            }
+            0x08...0x1c => {
+                let region = Region::from_offset(offset);
+                let delay_size = DelaySize(val & DELAY_SIZE_MASK);
+
+                debug!(target: logging::MEMORY,
+                       "{:?} delay/size: 0x{:08x} ({} bytes)",
+                       region, val, delay_size.size());
+
+                self.delay_size[region as usize] = delay_size;
+            }
+            0x20 => self.com_delay = val & COM_DELAY_MASK,
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// Size of the window configured for `region` in bytes
+    pub fn size(&self, region: Region) -> u32 {
+        self.delay_size[region as usize].size()
+    }
+
+    /// Return true if `offset` is within the window configured for
+    /// `region`
+    pub fn in_window(&self, region: Region, offset: u32) -> bool {
+        offset < self.size(region)
+    }
+
+    /// Number of CPU cycles taken by a read of `size` bytes in
+    /// `region`. This is the formula given in the Nocash PSX spec.
+    pub fn read_cycles(&self, region: Region, size: u8) -> Cycles {
+        let delay = self.delay_size[region as usize];
+
+        let com0 = (self.com_delay & 0xf) as i32;
+        let com2 = ((self.com_delay >> 8) & 0xf) as i32;
+        let com3 = ((self.com_delay >> 12) & 0xf) as i32;
+
+        let mut first = 0;
+        let mut seq = 0;
+        let mut min = 0;
+
+        if delay.use_com0() {
+            first += com0 - 1;
+            seq += com0 - 1;
+        }
+
+        if delay.use_com2() {
+            first += com2;
+            seq += com2;
+        }
+
+        if delay.use_com3() {
+            min = com3;
+        }
+
+        if first < 6 {
+            first += 1;
+        }
+
+        first += delay.read_delay() + 2;
+        seq += delay.read_delay() + 2;
+
+        first = cmp::max(first, min + 6);
+        seq = cmp::max(seq, min + 2);
+
+        // Number of bus accesses needed for this read
+        let accesses =
+            if delay.bus_16bits() {
+                (size as i32 + 1) / 2
+            } else {
+                size as i32
+            };
+
+        (first + seq * (accesses - 1)) as Cycles
+    }
+}
+
+/// Regions configured by the delay/size registers
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Region {
+    Expansion1 = 0,
+    Expansion3 = 1,
+    Bios = 2,
+    Spu = 3,
+    CdRom = 4,
+    Expansion2 = 5,
+}
+
+impl Region {
+    fn from_offset(offset: u32) -> Region {
+        match offset {
+            0x08 => Region::Expansion1,
+            0x0c => Region::Expansion3,
+            0x10 => Region::Bios,
+            0x14 => Region::Spu,
+            0x18 => Region::CdRom,
+            0x1c => Region::Expansion2,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return the region containing `abs_addr`, if any
+    pub fn from_address(abs_addr: map::PhysAddr) -> Option<Region> {
+        if map::EXPANSION_1.contains(abs_addr).is_some() {
+            Some(Region::Expansion1)
+        } else if map::EXPANSION_3.contains(abs_addr).is_some() {
+            Some(Region::Expansion3)
+        } else if map::BIOS.contains(abs_addr).is_some() {
+            Some(Region::Bios)
+        } else if map::SPU.contains(abs_addr).is_some() {
+            Some(Region::Spu)
+        } else if map::CDROM.contains(abs_addr).is_some() {
+            Some(Region::CdRom)
+        } else if map::EXPANSION_2.contains(abs_addr).is_some() {
+            Some(Region::Expansion2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Delay/size register
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+struct DelaySize(u32);
+
+impl DelaySize {
+    fn read_delay(self) -> i32 {
+        ((self.0 >> 4) & 0xf) as i32
+    }
+
+    fn use_com0(self) -> bool {
+        self.0 & (1 << 8) != 0
+    }
+
+    fn use_com2(self) -> bool {
+        self.0 & (1 << 10) != 0
+    }
+
+    fn use_com3(self) -> bool {
+        self.0 & (1 << 11) != 0
+    }
+
+    /// True if the device uses a 16bit data bus, otherwise it's 8bit
+    fn bus_16bits(self) -> bool {
+        self.0 & (1 << 12) != 0
+    }
+
+    fn size(self) -> u32 {
+        1 << ((self.0 >> 16) & 0x1f)
+    }
+}
+
+/// Writable bits in the delay/size registers
+const DELAY_SIZE_MASK: u32 = 0xaf1fffff;
+
+/// Writable bits in the COM_DELAY register
+const COM_DELAY_MASK: u32 = 0x3ffff;
+
+#[test]
+fn bios_timings() {
+    let mc = MemControl::new();
+
+    // 8bit bus, each byte costs a full access
+    assert_eq!(mc.read_cycles(Region::Bios, 1), 7);
+    assert_eq!(mc.read_cycles(Region::Bios, 4), 7 + 6 * 3);
+    // 16bit bus
+    assert_eq!(mc.read_cycles(Region::Spu, 2), 21);
+    assert_eq!(mc.read_cycles(Region::Spu, 4), 21 + 20);
+
+    assert_eq!(mc.size(Region::Bios), 512 * 1024);
+    assert_eq!(mc.size(Region::Expansion2), 128);
+}
@@ -1,24 +1,45 @@
 pub mod timers;
-mod ram;
+pub mod ram;
+pub mod io_trace;
+pub mod busy_wait;
+pub mod unhandled_log;
+pub mod layout;
 mod dma;
+mod mem_control;
+mod page_table;
+
+use std::cmp;
 
 use self::ram::{Ram, ScratchPad};
-use self::dma::{Dma, Port, Direction, Step, Sync};
+use self::dma::{Dma, Port, Direction, Sync};
 use self::timers::Timers;
+use self::io_trace::IoTrace;
+use self::busy_wait::{BusyWaitDetector, BusyWait};
+use self::mem_control::{MemControl, Region};
+use self::map::{PhysAddr, Segment};
+use self::page_table::{PageTable, Page};
+use self::unhandled_log::{UnhandledLog, UnhandledAccess};
+use self::layout::MappedRegion;
 
 use shared::SharedState;
+use events::Event;
+use init_policy::Filler;
+use pgxp::Pgxp;
 use bios::Bios;
-use timekeeper::Peripheral;
+use timekeeper::{Peripheral, Cycles};
 use gpu::Gpu;
 use gpu::renderer::Renderer;
 use spu::Spu;
 use cdrom::CdRom;
 use cdrom::disc::Disc;
 use padmemcard::PadMemCard;
+use sio1::Sio1;
 use mdec::MDec;
 use parallel_io::ParallelIo;
 use debug_uart::DebugUart;
 use tracer::module_tracer;
+use savestate::{self, SaveState};
+use logging;
 
 /// Global interconnect
 #[derive(RustcDecodable, RustcEncodable)]
@@ -39,21 +60,49 @@ pub struct Interconnect {
     timers: Timers,
     /// Cache Control register
     cache_control: CacheControl,
+    /// Other registers in the KSEG2 I/O window
+    kseg2_regs: Kseg2Registers,
     /// CDROM controller
     cdrom: CdRom,
     /// Gamepad and memory card controller
     pad_memcard: PadMemCard,
+    /// Serial port 1
+    sio1: Sio1,
     /// Motion decoder
     mdec: MDec,
     /// Contents of the RAM_SIZE register which is probably a
     /// configuration register for the memory controller.
     ram_size: u32,
     /// Memory control registers
-    mem_control: [u32; 9],
+    mem_control: MemControl,
     /// Parallel I/O
     parallel_io: ParallelIo,
     /// Debug UART
     debug_uart: DebugUart,
+    /// What to do when the CPU accesses an address we don't handle
+    unhandled_access: UnhandledAccessPolicy,
+    /// Set when an access resulted in a bus error, the CPU is
+    /// responsible for triggering the exception and clearing the flag.
+    bus_error: bool,
+    /// Last instruction word fetched by the CPU, returned when
+    /// reading from an open bus
+    last_fetch: u32,
+    /// Hardware register access trace
+    io_trace: IoTrace,
+    /// Precise vertex tracking
+    pgxp: Pgxp,
+    /// Detection of the loops polling a hardware register
+    busy_wait: BusyWaitDetector,
+    /// Lookup table for the RAM, ScratchPad and BIOS fast paths
+    page_table: PageTable,
+    /// Unhandled accesses encountered so far
+    unhandled_log: UnhandledLog,
+    /// If true the CPU can modify the BIOS image, otherwise the
+    /// writes are ignored
+    bios_writable: bool,
+    /// Set once a write to the BIOS ROM has been reported, to avoid
+    /// flooding the frontend
+    bios_write_reported: bool,
 }
 
 impl Interconnect {
@@ -69,25 +118,187 @@ impl Interconnect {
             spu: Spu::new(),
             timers: Timers::new(),
             cache_control: CacheControl(0),
+            kseg2_regs: Kseg2Registers::new(),
             cdrom: CdRom::new(disc),
             pad_memcard: PadMemCard::new(),
+            sio1: Sio1::new(),
             mdec: MDec::new(),
             ram_size: 0,
-            mem_control: [0; 9],
+            mem_control: MemControl::new(),
             parallel_io: ParallelIo::disconnected(),
             debug_uart: DebugUart::new(),
+            unhandled_access: UnhandledAccessPolicy::Panic,
+            bus_error: false,
+            last_fetch: 0,
+            io_trace: IoTrace::new(),
+            pgxp: Pgxp::new(),
+            busy_wait: BusyWaitDetector::new(),
+            page_table: PageTable::new(),
+            unhandled_log: UnhandledLog::new(),
+            bios_writable: false,
+            bios_write_reported: false,
         }
     }
 
-    pub fn sync(&mut self, shared: &mut SharedState) {
+    /// Reset all the peripherals to their power-on state. The BIOS,
+    /// disc and external modules are kept, the RAM and ScratchPad
+    /// contents are left untouched like on the real hardware.
+    pub fn reset(&mut self) {
+        let video_clock = self.gpu.video_clock();
+
+        self.dma = Dma::new();
+        self.gpu = Gpu::new(video_clock);
+        self.spu = Spu::new();
+        self.timers = Timers::new();
+        self.cache_control = CacheControl(0);
+        self.kseg2_regs = Kseg2Registers::new();
+        self.cdrom.reset();
+        self.pad_memcard.reset();
+        self.sio1.reset();
+        self.mdec = MDec::new();
+        self.ram_size = 0;
+        self.mem_control = MemControl::new();
+        self.bus_error = false;
+        self.pgxp.clear();
+        self.page_table.unmap_bios_mirrors();
+        self.bios_write_reported = false;
+    }
+
+    /// Store the peripherals' state in `state`. The parallel I/O
+    /// module, the I/O trace and the PGXP state are not included.
+    pub fn save_state(&self,
+                      state: &mut SaveState) -> Result<(), savestate::Error> {
+        try!(state.put(&savestate::BIOS, &self.bios));
+        try!(state.put(&savestate::RAM,
+                       &(&self.ram, &self.scratch_pad, self.ram_size)));
+        try!(state.put(&savestate::BUS,
+                       &(&self.cache_control,
+                         &self.kseg2_regs,
+                         &self.mem_control,
+                         self.bus_error,
+                         self.last_fetch)));
+        try!(state.put(&savestate::DMA, &self.dma));
+        try!(state.put(&savestate::GPU, &self.gpu));
+        try!(state.put(&savestate::SPU, &self.spu));
+        try!(state.put(&savestate::TIMERS, &self.timers));
+        try!(state.put(&savestate::CDROM, &self.cdrom));
+        try!(state.put(&savestate::PAD_MEMCARD, &self.pad_memcard));
+        try!(state.put(&savestate::SIO1, &self.sio1));
+        try!(state.put(&savestate::MDEC, &self.mdec));
+        state.put(&savestate::DEBUG_UART, &self.debug_uart)
+    }
+
+    /// Restore the peripherals' state from `state`. Missing chunks
+    /// leave the corresponding peripheral untouched. The BIOS and the
+    /// disc image are kept, the gamepad profiles and the link cable
+    /// have to be reconnected by the frontend.
+    pub fn load_state(&mut self,
+                      state: &SaveState) -> Result<(), savestate::Error> {
+        if let Some(bios) = try!(state.get::<Bios>(&savestate::BIOS)) {
+            let expected = bios.metadata();
+            let current = self.bios.metadata();
+
+            if expected.sha256 != current.sha256 {
+                return Err(savestate::Error::Mismatch(
+                    format!("state created with BIOS {:?}, using {:?}",
+                            expected, current)));
+            }
+        }
+
+        if let Some((ram, scratch_pad, ram_size)) =
+            try!(state.get(&savestate::RAM)) {
+            self.ram = ram;
+            self.scratch_pad = scratch_pad;
+            self.ram_size = ram_size;
+        }
+
+        if let Some((cache_control, kseg2_regs, mem_control,
+                     bus_error, last_fetch)) =
+            try!(state.get(&savestate::BUS)) {
+            self.cache_control = cache_control;
+            self.kseg2_regs = kseg2_regs;
+            self.mem_control = mem_control;
+            self.bus_error = bus_error;
+            self.last_fetch = last_fetch;
+
+            self.page_table.unmap_bios_mirrors();
+        }
+
+        if let Some(dma) = try!(state.get(&savestate::DMA)) {
+            self.dma = dma;
+        }
+
+        if let Some(gpu) = try!(state.get(&savestate::GPU)) {
+            self.gpu = gpu;
+        }
+
+        if let Some(spu) = try!(state.get(&savestate::SPU)) {
+            self.spu = spu;
+        }
+
+        if let Some(timers) = try!(state.get(&savestate::TIMERS)) {
+            self.timers = timers;
+        }
+
+        if let Some(mut cdrom) = try!(state.get::<CdRom>(&savestate::CDROM)) {
+            // The decoded controller only contains a placeholder for
+            // the disc, keep the real one
+            let disc = self.cdrom.remove_disc();
+
+            cdrom.set_disc(disc);
+
+            self.cdrom = cdrom;
+        }
+
+        if let Some(pad_memcard) = try!(state.get(&savestate::PAD_MEMCARD)) {
+            self.pad_memcard = pad_memcard;
+        }
+
+        if let Some(sio1) = try!(state.get(&savestate::SIO1)) {
+            self.sio1 = sio1;
+        }
+
+        if let Some(mdec) = try!(state.get(&savestate::MDEC)) {
+            self.mdec = mdec;
+        }
+
+        if let Some(debug_uart) = try!(state.get(&savestate::DEBUG_UART)) {
+            self.debug_uart = debug_uart;
+        }
+
+        self.pgxp.clear();
+
+        Ok(())
+    }
+
+    /// Fill the RAM and ScratchPad with the values generated by
+    /// `filler` to simulate their undefined power-on state
+    pub fn fill_uninitialized(&mut self, filler: &mut Filler) {
+        self.ram.fill(filler);
+        self.scratch_pad.fill(filler);
+    }
+
+    pub fn sync(&mut self,
+                shared: &mut SharedState,
+                renderer: &mut Renderer) {
+        if shared.tk().needs_sync(Peripheral::Dma) {
+            self.dma_sync(shared, renderer);
+        }
+
         if shared.tk().needs_sync(Peripheral::Gpu) {
             self.gpu.sync(shared);
+            self.gpu.update_field(renderer);
+            self.gpu.update_display_lines(renderer);
         }
 
         if shared.tk().needs_sync(Peripheral::PadMemCard) {
             self.pad_memcard.sync(shared);
         }
 
+        if shared.tk().needs_sync(Peripheral::Sio1) {
+            self.sio1.sync(shared);
+        }
+
         self.timers.sync(shared);
 
         if shared.tk().needs_sync(Peripheral::CdRom) {
@@ -104,6 +315,11 @@ impl Interconnect {
         &self.gpu
     }
 
+    /// Return a mutable reference to the GPU instance
+    pub fn gpu_mut(&mut self) -> &mut Gpu {
+        &mut self.gpu
+    }
+
     /// Return a reference to the BIOS instance
     pub fn bios(&self) -> &Bios {
         &self.bios
@@ -130,11 +346,43 @@ impl Interconnect {
         &mut self.ram
     }
 
+    pub fn io_trace(&self) -> &IoTrace {
+        &self.io_trace
+    }
+
+    pub fn io_trace_mut(&mut self) -> &mut IoTrace {
+        &mut self.io_trace
+    }
+
+    pub fn busy_wait_mut(&mut self) -> &mut BusyWaitDetector {
+        &mut self.busy_wait
+    }
+
+    /// Return the register the CPU has been polling in a loop since
+    /// the last call, if any
+    pub fn take_busy_wait(&mut self) -> Option<BusyWait> {
+        self.busy_wait.take()
+    }
+
+    pub fn pgxp(&self) -> &Pgxp {
+        &self.pgxp
+    }
+
+    pub fn pgxp_mut(&mut self) -> &mut Pgxp {
+        &mut self.pgxp
+    }
+
     /// Return a mutable reference to the PadMemCard instance
     pub fn pad_memcard_mut(&mut self) -> &mut PadMemCard {
         &mut self.pad_memcard
     }
 
+    /// Return a mutable reference to the serial port, used to plug
+    /// in a link cable
+    pub fn sio1_mut(&mut self) -> &mut Sio1 {
+        &mut self.sio1
+    }
+
     /// Return a mutable reference to the CdRom controller
     pub fn cdrom_mut(&mut self) -> &mut CdRom {
         &mut self.cdrom
@@ -145,13 +393,164 @@ impl Interconnect {
         &mut self.parallel_io
     }
 
+    /// Return the current policy for unhandled memory accesses
+    pub fn unhandled_access_policy(&self) -> UnhandledAccessPolicy {
+        self.unhandled_access
+    }
+
+    /// Select what happens when the CPU accesses an address we don't
+    /// handle
+    pub fn set_unhandled_access_policy(&mut self,
+                                       policy: UnhandledAccessPolicy) {
+        self.unhandled_access = policy;
+    }
+
+    /// Allow the CPU to modify the BIOS image. Writes to the ROM are
+    /// normally ignored but some fast boot patches modify the BIOS
+    /// code at runtime. The modifications are not saved in the
+    /// savestates.
+    pub fn set_bios_writable(&mut self, writable: bool) {
+        self.bios_writable = writable;
+    }
+
+    pub fn bios_writable(&self) -> bool {
+        self.bios_writable
+    }
+
+    /// Return the distinct unhandled accesses encountered since the
+    /// machine was created or `clear_unhandled_accesses` was called
+    pub fn unhandled_accesses(&self) -> &[UnhandledAccess] {
+        self.unhandled_log.accesses()
+    }
+
+    pub fn clear_unhandled_accesses(&mut self) {
+        self.unhandled_log.clear();
+    }
+
+    /// Return the regions currently mapped in the physical address
+    /// space, sorted by address
+    pub fn memory_map(&self) -> Vec<MappedRegion> {
+        layout::regions(self.mem_control.size(Region::Expansion1),
+                        self.mem_control.size(Region::Expansion2),
+                        self.mem_control.size(Region::Bios))
+    }
+
+    /// Return true if the last access resulted in a bus error and
+    /// clear the flag
+    pub fn take_bus_error(&mut self) -> bool {
+        let bus_error = self.bus_error;
+
+        self.bus_error = false;
+
+        bus_error
+    }
+
+    /// Return the policy to use for an unhandled access at `addr`.
+    /// Addresses where no device is mapped always raise a bus error
+    /// like on the real hardware, some games probe them on purpose.
+    fn access_policy(&self, addr: u32) -> UnhandledAccessPolicy {
+        if map::is_mapped(PhysAddr::from_cpu(addr)) {
+            self.unhandled_access
+        } else {
+            UnhandledAccessPolicy::BusError
+        }
+    }
+
+    /// Value read when nothing drives the data bus. The bus
+    /// capacitance tends to retain the last value transferred, which
+    /// is usually the last instruction fetched by the CPU.
+    fn open_bus(&self) -> u32 {
+        self.last_fetch
+    }
+
+    /// Called when the CPU attempts to read from an address we don't
+    /// handle. Returns the value to be put on the bus.
+    fn unhandled_load(&mut self, addr: u32, size: u8) -> u32 {
+        self.unhandled_log.record(UnhandledAccess {
+            addr: addr,
+            size: size,
+            store: false,
+        });
+
+        match self.access_policy(addr) {
+            UnhandledAccessPolicy::Panic =>
+                panic!("unhandled load{} at address {:08x}", size * 8, addr),
+            UnhandledAccessPolicy::OpenBus => {
+                warn!(target: logging::MEMORY,
+                      "unhandled load{} at address {:08x}", size * 8, addr);
+                self.open_bus()
+            }
+            UnhandledAccessPolicy::BusError => {
+                warn!(target: logging::MEMORY,
+                      "bus error on load{} at address {:08x}", size * 8, addr);
+                self.bus_error = true;
+                self.open_bus()
+            }
+        }
+    }
+
+    /// Called when a register we emulate is accessed in a way we
+    /// don't support (bad access width, unexpected value...). Aborts
+    /// the emulation with the `Panic` policy, otherwise the access is
+    /// logged and ignored (loads return the open bus value).
+    fn unsupported_io(&self, msg: String) {
+        match self.unhandled_access {
+            UnhandledAccessPolicy::Panic => panic!("{}", msg),
+            _ => warn!(target: logging::MEMORY, "{}", msg),
+        }
+    }
+
+    /// Called when the CPU attempts to write to an address we don't
+    /// handle
+    fn unhandled_store(&mut self, addr: u32, size: u8, val: u32) {
+        self.unhandled_log.record(UnhandledAccess {
+            addr: addr,
+            size: size,
+            store: true,
+        });
+
+        match self.access_policy(addr) {
+            UnhandledAccessPolicy::Panic =>
+                panic!("unhandled store{} into address {:08x}: {:08x}",
+                       size * 8, addr, val),
+            UnhandledAccessPolicy::OpenBus =>
+                warn!(target: logging::MEMORY,
+                      "unhandled store{} into address {:08x}: {:08x}",
+                      size * 8, addr, val),
+            UnhandledAccessPolicy::BusError => {
+                warn!(target: logging::MEMORY,
+                      "bus error on store{} into address {:08x}: {:08x}",
+                      size * 8, addr, val);
+                self.bus_error = true;
+            }
+        }
+    }
+
     /// Interconnect: load instruction at `PC`. Only the RAM and BIOS
     /// are supported, would it make sense to fetch instructions from
     /// anything else?
     pub fn load_instruction(&mut self,
                             shared: &mut SharedState,
                             pc: u32) -> u32 {
-        let abs_addr = map::mask_region(pc);
+        let instruction = self.do_load_instruction(shared, pc);
+
+        self.last_fetch = instruction;
+
+        instruction
+    }
+
+    fn do_load_instruction(&mut self,
+                           shared: &mut SharedState,
+                           pc: u32) -> u32 {
+        let abs_addr = PhysAddr::from_cpu(pc);
+
+        match self.page_table.lookup(abs_addr) {
+            Page::Ram =>
+                return self.ram.load::<Word>(abs_addr.get() - map::RAM.0),
+            Page::Bios =>
+                return self.bios.load::<Word>(bios_offset(abs_addr)),
+            _ => (),
+        }
 
         if let Some(offset) = map::RAM.contains(abs_addr) {
             return self.ram.load::<Word>(offset);
@@ -161,31 +560,113 @@ impl Interconnect {
             return self.bios.load::<Word>(offset);
         }
 
+        if self.is_bios_mirror(abs_addr) {
+            self.page_table.map_bios_mirror(abs_addr);
+
+            return self.bios.load::<Word>(bios_offset(abs_addr));
+        }
+
         if let Some(offset) = map::EXPANSION_1.contains(abs_addr) {
             return self.parallel_io.load::<Word>(shared, offset);
         }
 
-        panic!("unhandled instruction load at address {:08x}", pc);
+        self.unhandled_load(pc, 4)
     }
 
     /// Interconnect: load value at `addr`
     pub fn load<A: Addressable>(&mut self,
                                 shared: &mut SharedState,
                                 addr: u32) -> u32 {
-        // XXX Since I don't implement CPU pipelining correctly for
-        // now I just pretend the memory is pretty fast. In reality it
-        // will depend on the device being accessed and then it could
-        // be pipelined in the CPU to reduce stalling.
-        shared.tk().tick(2);
+        let v = self.do_load::<A>(shared, addr);
 
-        let abs_addr = map::mask_region(addr);
+        self.busy_wait.load(map::mask_region(addr), self.cdrom.index());
+
+        if self.io_trace.enabled() {
+            self.io_trace.load(addr, A::size(), v, self.cdrom.index());
+        }
+
+        v
+    }
+
+    /// Read plain memory (RAM, ScratchPad and BIOS) without any side
+    /// effect: no timing, no trace and no bus error. Returns `None`
+    /// for everything else since reading the I/O registers can modify
+    /// the state of the devices (FIFOs...).
+    pub fn peek<A: Addressable>(&self, addr: u32) -> Option<u32> {
+        let abs_addr = PhysAddr::from_cpu(addr);
+
+        if let Some(offset) = map::RAM.contains(abs_addr) {
+            return Some(self.ram.load::<A>(offset));
+        }
+
+        if let Some(offset) = map::SCRATCH_PAD.contains(abs_addr) {
+            // Not reachable through the uncached segment
+            if !Segment::from_address(addr).is_cached() {
+                return None;
+            }
+
+            return Some(self.scratch_pad.load::<A>(offset));
+        }
+
+        if let Some(offset) = map::BIOS.contains(abs_addr) {
+            return Some(self.bios.load::<A>(offset));
+        }
+
+        if self.is_bios_mirror(abs_addr) {
+            return Some(self.bios.load::<A>(bios_offset(abs_addr)));
+        }
+
+        None
+    }
+
+    fn do_load<A: Addressable>(&mut self,
+                               shared: &mut SharedState,
+                               addr: u32) -> u32 {
+        let abs_addr = PhysAddr::from_cpu(addr);
+
+        // Fast path for the accesses to plain memory, the rest goes
+        // through the big address decoder below
+        match self.page_table.lookup(abs_addr) {
+            Page::Ram => {
+                shared.tk().tick(FAST_ACCESS_CYCLES);
+
+                return self.ram.load::<A>(abs_addr.get() - map::RAM.0);
+            }
+            Page::ScratchPad if Segment::from_address(addr).is_cached() => {
+                let offset = abs_addr.get() - map::SCRATCH_PAD.0;
+
+                shared.tk().tick(FAST_ACCESS_CYCLES);
+
+                return self.scratch_pad.load::<A>(offset);
+            }
+            Page::Bios => {
+                let cycles =
+                    self.mem_control.read_cycles(Region::Bios, A::size());
+
+                shared.tk().tick(cycles);
+
+                return self.bios.load::<A>(bios_offset(abs_addr));
+            }
+            _ => (),
+        }
+
+        // The devices on the secondary bus have their access time
+        // configured in the memory control registers. For everything
+        // else I just pretend the memory is pretty fast.
+        let cycles =
+            match Region::from_address(abs_addr) {
+                Some(r) => self.mem_control.read_cycles(r, A::size()),
+                None => FAST_ACCESS_CYCLES,
+            };
+
+        shared.tk().tick(cycles);
 
         if let Some(offset) = map::RAM.contains(abs_addr) {
             return self.ram.load::<A>(offset);
         }
 
         if let Some(offset) = map::SCRATCH_PAD.contains(abs_addr) {
-            if addr > 0xa0000000 {
+            if !Segment::from_address(addr).is_cached() {
                 panic!("ScratchPad access through uncached memory");
             }
 
@@ -196,12 +677,18 @@ impl Interconnect {
             return self.bios.load::<A>(offset);
         }
 
+        if self.is_bios_mirror(abs_addr) {
+            self.page_table.map_bios_mirror(abs_addr);
+
+            return self.bios.load::<A>(bios_offset(abs_addr));
+        }
+
         if let Some(offset) = map::IRQ_CONTROL.contains(abs_addr) {
             return
                 match offset {
                     0 => shared.irq_state().status() as u32,
                     4 => shared.irq_state().mask() as u32,
-                    _ => panic!("Unhandled IRQ load at address {:08x}", addr),
+                    _ => self.unhandled_load(addr, A::size()),
                 };
         }
 
@@ -233,7 +720,15 @@ impl Interconnect {
             return self.pad_memcard.load::<A>(shared, offset);
         }
 
+        if let Some(offset) = map::SIO1.contains(abs_addr) {
+            return self.sio1.load::<A>(shared, offset);
+        }
+
         if let Some(offset) = map::EXPANSION_1.contains(abs_addr) {
+            if !self.mem_control.in_window(Region::Expansion1, offset) {
+                return self.unhandled_load(addr, A::size());
+            }
+
             return self.parallel_io.load::<A>(shared, offset);
         }
 
@@ -244,27 +739,43 @@ impl Interconnect {
         if let Some(offset) = map::MEM_CONTROL.contains(abs_addr) {
 
             if A::size() != 4 {
-                panic!("Unhandled MEM_CONTROL access ({})", A::size());
+                self.unsupported_io(format!("MEM_CONTROL load{}",
+                                            A::size() * 8));
+                return self.open_bus();
             }
 
-            let index = (offset >> 2) as usize;
-
-            return self.mem_control[index];
+            return self.mem_control.load(offset);
         }
 
         if let Some(_) = map::CACHE_CONTROL.contains(abs_addr) {
             if A::size() != 4 {
-                panic!("Unhandled cache control access ({})", A::size());
+                self.unsupported_io(format!("Cache control load{}",
+                                            A::size() * 8));
+                return self.open_bus();
             }
 
             return self.cache_control.0;
         }
 
+        if let Some(offset) = map::KSEG2_IO.contains(abs_addr) {
+            if A::size() != 4 {
+                self.unsupported_io(format!("KSEG2 load{} at {:08x}",
+                                            A::size() * 8, addr));
+                return self.open_bus();
+            }
+
+            return self.kseg2_regs[(offset >> 2) as usize];
+        }
+
         if let Some(offset) = map::EXPANSION_2.contains(abs_addr) {
+            if !self.mem_control.in_window(Region::Expansion2, offset) {
+                return self.unhandled_load(addr, A::size());
+            }
+
             return self.debug_uart.load::<A>(shared, offset);
         }
 
-        panic!("unhandled load at address {:08x}", addr);
+        self.unhandled_load(addr, A::size())
     }
 
     /// Interconnect: store `val` into `addr`
@@ -273,8 +784,38 @@ impl Interconnect {
                                  renderer: &mut Renderer,
                                  addr: u32,
                                  val: u32) {
+        if self.io_trace.enabled() {
+            // Traced before the store so that we decode the CDROM
+            // registers with the index in effect for the access
+            self.io_trace.store(addr, A::size(), val, self.cdrom.index());
+        }
 
-        let abs_addr = map::mask_region(addr);
+        self.busy_wait.store(map::mask_region(addr));
+
+        self.do_store::<A>(shared, renderer, addr, val);
+    }
+
+    fn do_store<A: Addressable>(&mut self,
+                                shared: &mut SharedState,
+                                renderer: &mut Renderer,
+                                addr: u32,
+                                val: u32) {
+
+        let abs_addr = PhysAddr::from_cpu(addr);
+
+        match self.page_table.lookup(abs_addr) {
+            Page::Ram => {
+                self.ram.store::<A>(abs_addr.get() - map::RAM.0, val);
+                return;
+            }
+            Page::ScratchPad if Segment::from_address(addr).is_cached() => {
+                let offset = abs_addr.get() - map::SCRATCH_PAD.0;
+
+                self.scratch_pad.store::<A>(offset, val);
+                return;
+            }
+            _ => (),
+        }
 
         if let Some(offset) = map::RAM.contains(abs_addr) {
             self.ram.store::<A>(offset, val);
@@ -282,7 +823,7 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::SCRATCH_PAD.contains(abs_addr) {
-            if addr > 0xa0000000 {
+            if !Segment::from_address(addr).is_cached() {
                 panic!("ScratchPad access through uncached memory");
             }
 
@@ -293,7 +834,7 @@ impl Interconnect {
             match offset {
                 0 => shared.irq_state_mut().ack(val as u16),
                 4 => shared.irq_state_mut().set_mask(val as u16),
-                _ => panic!("Unhandled IRQ store at address {:08x}"),
+                _ => self.unhandled_store(addr, A::size(), val),
             }
             return;
         }
@@ -304,6 +845,10 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::GPU.contains(abs_addr) {
+            if offset == 0 {
+                self.gpu.pgxp_prepare(&self.pgxp, abs_addr.get(), val);
+            }
+
             self.gpu.store::<A>(shared,
                                 renderer,
                                 &mut self.timers,
@@ -334,13 +879,20 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::PAD_MEMCARD.contains(abs_addr) {
-            self.pad_memcard.store::<A>(shared, offset, val);
+            self.pad_memcard.store::<A>(shared, &mut self.gpu, offset, val);
+            return;
+        }
+
+        if let Some(offset) = map::SIO1.contains(abs_addr) {
+            self.sio1.store::<A>(shared, offset, val);
             return;
         }
 
         if let Some(_) = map::CACHE_CONTROL.contains(abs_addr) {
             if A::size() != 4 {
-                panic!("Unhandled cache control access");
+                self.unsupported_io(format!("Cache control store{}",
+                                            A::size() * 8));
+                return;
             }
 
             self.cache_control = CacheControl(val);
@@ -348,32 +900,38 @@ impl Interconnect {
             return;
         }
 
+        if let Some(offset) = map::KSEG2_IO.contains(abs_addr) {
+            if A::size() != 4 {
+                self.unsupported_io(format!("KSEG2 store{} at {:08x}",
+                                            A::size() * 8, addr));
+                return;
+            }
+
+            debug!(target: logging::MEMORY,
+                   "KSEG2 register {:08x} = {:08x}", addr, val);
+
+            self.kseg2_regs[(offset >> 2) as usize] = val;
+
+            return;
+        }
+
         if let Some(offset) = map::MEM_CONTROL.contains(abs_addr) {
 
             if A::size() != 4 {
-                panic!("Unhandled MEM_CONTROL access ({})", A::size());
+                self.unsupported_io(format!("MEM_CONTROL store{}",
+                                            A::size() * 8));
+                return;
             }
 
-            let val = val;
+            let bios_window = self.mem_control.size(Region::Bios);
 
-            match offset {
-                0 => // Expansion 1 base address
-                    if val != 0x1f000000 {
-                        panic!("Bad expansion 1 base address: 0x{:08x}", val);
-                    },
-                4 => // Expansion 2 base address
-                    if val != 0x1f802000 {
-                        panic!("Bad expansion 2 base address: 0x{:08x}", val);
-                    },
-                _ =>
-                    warn!("Unhandled write to MEM_CONTROL register {:x}: \
-                           0x{:08x}",
-                          offset, val),
+            if let Err(msg) = self.mem_control.store(offset, val) {
+                self.unsupported_io(msg);
             }
 
-            let index = (offset >> 2) as usize;
-
-            self.mem_control[index] = val;
+            if self.mem_control.size(Region::Bios) != bios_window {
+                self.page_table.unmap_bios_mirrors();
+            }
 
             return;
         }
@@ -381,20 +939,79 @@ impl Interconnect {
         if let Some(_) = map::RAM_SIZE.contains(abs_addr) {
 
             if A::size() != 4 {
-                panic!("Unhandled RAM_SIZE access");
+                self.unsupported_io(format!("RAM_SIZE store{}",
+                                            A::size() * 8));
+                return;
             }
 
             self.ram_size = val;
             return;
         }
 
+        if let Some(offset) = map::EXPANSION_1.contains(abs_addr) {
+            if !self.mem_control.in_window(Region::Expansion1, offset) {
+                self.unhandled_store(addr, A::size(), val);
+                return;
+            }
+
+            self.parallel_io.store::<A>(shared, offset, val);
+            return;
+        }
+
         if let Some(offset) = map::EXPANSION_2.contains(abs_addr) {
+            if !self.mem_control.in_window(Region::Expansion2, offset) {
+                self.unhandled_store(addr, A::size(), val);
+                return;
+            }
+
             self.debug_uart.store::<A>(shared, offset, val);
             return;
         }
 
-        panic!("unhandled store into address {:08x}: {:08x}",
-               addr, val);
+        if self.is_bios_mirror(abs_addr) {
+            self.bios_store::<A>(shared, addr, val);
+            return;
+        }
+
+        self.unhandled_store(addr, A::size(), val);
+    }
+
+    /// Return true if `abs_addr` targets the BIOS or one of its
+    /// mirrors within the configured BIOS window
+    fn is_bios_mirror(&self, abs_addr: PhysAddr) -> bool {
+        match map::BIOS_MIRRORS.contains(abs_addr) {
+            Some(offset) => self.mem_control.in_window(Region::Bios, offset),
+            None => false,
+        }
+    }
+
+    /// Write to the BIOS ROM. Some games do that by accident, the
+    /// write is ignored unless `bios_writable` is set.
+    fn bios_store<A: Addressable>(&mut self,
+                                  shared: &mut SharedState,
+                                  addr: u32,
+                                  val: u32) {
+        let abs_addr = PhysAddr::from_cpu(addr);
+
+        if self.bios_writable {
+            self.bios.store::<A>(bios_offset(abs_addr), val);
+            return;
+        }
+
+        // Some games write to the BIOS in a loop, only warn about the
+        // first one
+        if !self.bios_write_reported {
+            warn!(target: logging::MEMORY,
+                  "Ignored store{} into BIOS ROM at {:08x}: {:08x}",
+                  A::size() * 8, addr, val);
+
+            self.bios_write_reported = true;
+            shared.push_event(Event::BiosWrite(addr));
+        } else {
+            debug!(target: logging::MEMORY,
+                   "Ignored store{} into BIOS ROM at {:08x}: {:08x}",
+                   A::size() * 8, addr, val);
+        }
     }
 
     /// DMA register read
@@ -458,21 +1075,35 @@ impl Interconnect {
                     match minor {
                         0 => channel.set_base(val),
                         4 => channel.set_block_control(val),
-                        8 => channel.set_control(val),
+                        8 => match port {
+                            Port::Otc => channel.set_otc_control(val),
+                            _ => channel.set_control(val),
+                        },
                         _ => panic!("Unhandled DMA write {:x}: {:08x}",
                                     offset, val)
                     }
 
-                    if channel.active() {
-                        Some(port)
-                    } else {
+                    if !channel.active() {
+                        // XXX The hardware probably pauses the
+                        // transfer instead of aborting it
+                        channel.stop();
+                        None
+                    } else if channel.in_progress() {
+                        // Already running
                         None
+                    } else {
+                        Some(port)
                     }
                 },
                 // Common DMA registers
                 7 => {
                     match minor {
-                        0 => self.dma.set_control(val),
+                        0 => {
+                            self.dma.set_control(val);
+                            // Channels enabled in DPCR might have been
+                            // waiting to start
+                            self.dma_start_pending(shared, renderer);
+                        }
                         4 => self.dma.set_interrupt(shared, val),
                         _ => panic!("Unhandled DMA write {:x}: {:08x}",
                                     offset, val),
@@ -485,19 +1116,77 @@ impl Interconnect {
             };
 
         if let Some(port) = active_port {
+            if self.dma.port_enabled(port) {
+                self.do_dma(shared, renderer, port);
+            }
+        }
+    }
+
+    /// Start the transfers for all the active channels which are
+    /// enabled in DPCR, in priority order
+    fn dma_start_pending(&mut self,
+                         shared: &mut SharedState,
+                         renderer: &mut Renderer) {
+        let mut ports: Vec<Port> =
+            (0..7).map(Port::from_index)
+            .filter(|&p| {
+                let channel = self.dma.channel(p);
+
+                self.dma.port_enabled(p)
+                    && channel.active()
+                    && !channel.in_progress()
+            })
+            .collect();
+
+        // Highest priority first. For equal priorities the highest
+        // port number wins.
+        ports.sort_by_key(|&p| (self.dma.port_priority(p), 7 - p as u32));
+
+        for port in ports {
             self.do_dma(shared, renderer, port);
         }
     }
 
-    /// Execute DMA transfer for a port
+    /// Resume the chopped DMA transfers whose CPU window has elapsed
+    fn dma_sync(&mut self,
+                shared: &mut SharedState,
+                renderer: &mut Renderer) {
+        shared.tk().sync(Peripheral::Dma);
+
+        loop {
+            let now = shared.tk().now();
+
+            match self.dma.next_ready(now) {
+                Some(port) => self.dma_resume(shared, renderer, port),
+                None => break,
+            }
+        }
+
+        self.dma_schedule(shared);
+    }
+
+    /// Schedule the next DMA sync for the pending chopped transfers
+    fn dma_schedule(&mut self, shared: &mut SharedState) {
+        let tk = shared.tk();
+
+        match self.dma.next_resume() {
+            Some(date) => {
+                let delta = date.saturating_sub(tk.now());
+
+                tk.set_next_sync_delta(Peripheral::Dma, delta);
+            }
+            None => tk.no_sync_needed(Peripheral::Dma),
+        }
+    }
+
+    /// Start the DMA transfer for a port. Linked list transfers and
+    /// non-chopped block transfers complete immediately (the CPU is
+    /// stalled while they run), chopped block transfers only run
+    /// their first DMA window and are resumed from `dma_sync`.
     fn do_dma(&mut self,
               shared: &mut SharedState,
               renderer: &mut Renderer,
               port: Port) {
-        // DMA transfer has been started, for now let's
-        // process everything in one pass (i.e. no
-        // chopping or priority handling)
-
         let sync = self.dma.channel(port).sync();
 
         module_tracer("DMA", |m| {
@@ -519,23 +1208,107 @@ impl Interconnect {
         });
 
         match sync {
-                Sync::LinkedList => self.do_dma_linked_list(renderer, port),
-                _                => self.do_dma_block(shared, renderer, port),
+            Sync::LinkedList => self.dma_linked_list(shared, renderer, port),
+            _ => {
+                self.dma.channel_mut(port).start_block();
+
+                if self.dma.channel(port).in_progress() {
+                    self.dma_block_window(shared, renderer, port);
+                } else {
+                    // Empty transfer
+                    self.dma.done(shared, port);
+                }
+            }
+        }
+    }
+
+    /// Resume a paused transfer
+    fn dma_resume(&mut self,
+                  shared: &mut SharedState,
+                  renderer: &mut Renderer,
+                  port: Port) {
+        match self.dma.channel(port).sync() {
+            Sync::LinkedList => self.dma_linked_list(shared, renderer, port),
+            _ => self.dma_block_window(shared, renderer, port),
+        }
+    }
+
+    /// Run or resume a linked list transfer. It pauses when the GPU
+    /// stops requesting data and is resumed from `dma_sync`.
+    fn dma_linked_list(&mut self,
+                       shared: &mut SharedState,
+                       renderer: &mut Renderer,
+                       port: Port) {
+        if self.do_dma_linked_list(shared, renderer, port) {
+            self.dma.done(shared, port);
         }
 
-        self.dma.done(shared, port);
+        self.dma_schedule(shared);
     }
 
-    /// Emulate DMA transfer for linked list synchronization mode.
-    fn do_dma_linked_list(&mut self, renderer: &mut Renderer, port: Port) {
-        let channel = self.dma.channel_mut(port);
+    /// Run a block transfer until completion or, when chopping is
+    /// enabled, for a single DMA window. In the latter case the
+    /// transfer is resumed once the CPU window has elapsed. Transfers
+    /// to the GPU in request mode also pause while the GPU's command
+    /// FIFO is full.
+    fn dma_block_window(&mut self,
+                        shared: &mut SharedState,
+                        renderer: &mut Renderer,
+                        port: Port) {
+        let (remaining, chop) = {
+            let channel = self.dma.channel(port);
+
+            (channel.remaining(), channel.chop_windows())
+        };
+
+        let words =
+            match chop {
+                Some((dma_window, _)) => cmp::min(dma_window, remaining),
+                None => remaining,
+            };
+
+        let stalled = self.do_dma_block(shared, renderer, port, words);
+
+        if self.dma.channel(port).in_progress() {
+            let delay =
+                if stalled {
+                    self.gpu.dma_request_delay()
+                } else {
+                    match chop {
+                        Some((_, w)) => w,
+                        None => unreachable!(),
+                    }
+                };
 
-        let mut addr = channel.base() & 0x1ffffc;
+            let now = shared.tk().now();
 
-        if channel.direction() == Direction::ToRam {
-            panic!("Invalid DMA direction for linked list mode");
+            self.dma.channel_mut(port).set_resume(now + delay);
+        } else {
+            self.dma.done(shared, port);
         }
 
+        self.dma_schedule(shared);
+    }
+
+    /// Emulate DMA transfer for linked list synchronization mode.
+    /// Returns `false` if the transfer has been paused because the
+    /// GPU's command FIFO is full, `true` once it's complete.
+    fn do_dma_linked_list(&mut self,
+                          shared: &mut SharedState,
+                          renderer: &mut Renderer,
+                          port: Port) -> bool {
+        let mut addr = {
+            let channel = self.dma.channel_mut(port);
+
+            if channel.direction() == Direction::ToRam {
+                panic!("Invalid DMA direction for linked list mode");
+            }
+
+            channel.start_linked_list();
+
+            PhysAddr::from_dma(channel.base()).get()
+        };
+
         // I don't know if the DMA even supports linked list mode for
         // anything besides the GPU
         if port != Port::Gpu {
@@ -543,6 +1316,17 @@ impl Interconnect {
         }
 
         loop {
+            // Only send the next packet if the GPU requests it
+            self.gpu.sync(shared);
+
+            if !self.gpu.dma_request() {
+                let resume = shared.tk().now() + self.gpu.dma_request_delay();
+
+                self.dma.channel_mut(port).pause_linked_list(addr, resume);
+
+                return false;
+            }
+
             // In linked list mode, each entry starts with a "header"
             // word. The high byte contains the number of words in the
             // "packet" (not counting the header word)
@@ -550,15 +1334,23 @@ impl Interconnect {
 
             let mut remsz = header >> 24;
 
+            // XXX Header fetch cost, taken from mednafen
+            shared.tk().tick(LINKED_LIST_HEADER_CYCLES);
+
+            shared.stats_mut().dma_words += 1 + remsz;
+
             while remsz > 0 {
-                addr = (addr + 4) & 0x1ffffc;
+                addr = PhysAddr::from_dma(addr + 4).get();
 
                 let command = self.ram.load::<Word>(addr);
 
+                self.gpu.pgxp_prepare(&self.pgxp, addr, command);
+
                 // Send command to the GPU
                 self.gpu.gp0(renderer, command);
 
                 remsz -= 1;
+                shared.tk().tick(1);
             }
 
             // The end-of-table marker is usually 0xffffff but
@@ -567,50 +1359,61 @@ impl Interconnect {
             // valid address it makes some sense. I'll have to test
             // that at some point...
             if header & 0x800000 != 0 {
-                break;
+                return true;
             }
 
-            addr = header & 0x1ffffc;
+            addr = PhysAddr::from_dma(header).get();
         }
     }
 
-    /// Emulate DMA transfer for Manual and Request synchronization
-    /// modes.
+    /// Emulate `words` words of a DMA transfer for Manual and
+    /// Request synchronization modes. Returns `true` if the transfer
+    /// stalled because the GPU stopped requesting data.
     fn do_dma_block(&mut self,
                     shared: &mut SharedState,
                     renderer: &mut Renderer,
-                    port: Port) {
+                    port: Port,
+                    words: u32) -> bool {
         let channel = self.dma.channel_mut(port);
 
-        let increment = match channel.step() {
-            Step::Increment =>  4,
-            Step::Decrement => -4i32 as u32,
-        };
+        // In request mode the transfers to the GPU wait for its DMA
+        // request signal
+        let gpu_request =
+            match (port, channel.sync(), channel.direction()) {
+                (Port::Gpu, Sync::Request, Direction::FromRam) => true,
+                _ => false,
+            };
 
-        let mut addr = channel.base();
+        for _ in 0..words {
+            if gpu_request {
+                self.gpu.sync(shared);
 
-        // Transfer size in words
-        let mut remsz = match channel.transfer_size() {
-            Some(n) => n,
-            // Shouldn't happen since we shouldn't be reaching this code
-            // in linked list mode
-            None    => panic!("Couldn't figure out DMA block transfer size"),
-        };
+                if !self.gpu.dma_request() {
+                    return true;
+                }
+            }
+
+            let addr = channel.cursor();
+            let remsz = channel.remaining();
 
-        while remsz > 0 {
             // Not sure what happens if address is
             // bogus... Mednafen just masks addr this way, maybe
             // that's how the hardware behaves (i.e. the RAM
             // address wraps and the two LSB are ignored, seems
             // reasonable enough
-            let cur_addr = addr & 0x1ffffc;
+            let cur_addr = PhysAddr::from_dma(addr).get();
 
             match channel.direction() {
                 Direction::FromRam => {
                     let src_word = self.ram.load::<Word>(cur_addr);
 
                     match port {
-                        Port::Gpu => self.gpu.gp0(renderer, src_word),
+                        Port::Gpu => {
+                            self.gpu.pgxp_prepare(&self.pgxp,
+                                                  cur_addr,
+                                                  src_word);
+                            self.gpu.gp0(renderer, src_word)
+                        }
                         Port::MDecIn => self.mdec.command(shared, src_word),
                         // XXX ignre transfers to the SPU for now
                         Port::Spu => (),
@@ -620,19 +1423,16 @@ impl Interconnect {
                 }
                 Direction::ToRam => {
                     let src_word = match port {
-                        // Clear ordering table
+                        // Clear ordering table: build an empty linked
+                        // list going backwards from the base address
                         Port::Otc => match remsz {
                             // Last entry contains the end
                             // of table marker
-                            1 => 0xffffff,
+                            1 => OTC_TERMINATOR,
                             // Pointer to the previous entry
                             _ => addr.wrapping_sub(4) & 0x1fffff,
                         },
-                        Port::Gpu => {
-                            // XXX to be implemented
-                            debug!("DMA GPU READ");
-                            0
-                        }
+                        Port::Gpu => self.gpu.load::<Word>(shared, 0),
                         Port::CdRom => self.cdrom.dma_read_word(),
                         Port::MDecOut => 0,
                         _ => panic!("Unhandled DMA source port {:?}", port),
@@ -642,14 +1442,22 @@ impl Interconnect {
                 }
             }
 
-            addr = addr.wrapping_add(increment);
-            remsz -= 1;
+            channel.advance();
             // XXX Probably completely inaccurate
             shared.tk().tick(1);
+
+            shared.stats_mut().dma_words += 1;
         }
+
+        false
     }
 }
 
+/// KSEG2 I/O registers besides the cache control. We don't know what
+/// they do, they're only latched so that the values written by the
+/// BIOS can be read back.
+buffer!(struct Kseg2Registers([u32; 0x80]));
+
 #[derive(Clone,Copy, RustcDecodable, RustcEncodable)]
 pub struct CacheControl(u32);
 
@@ -665,6 +1473,41 @@ impl CacheControl {
     }
 }
 
+/// Offset in the BIOS ROM for `abs_addr`, which must be within
+/// `map::BIOS_MIRRORS`
+fn bios_offset(abs_addr: PhysAddr) -> u32 {
+    (abs_addr.get() - map::BIOS.0) & (map::BIOS.1 - 1)
+}
+
+/// Number of cycles taken by an access to the RAM, the ScratchPad
+/// and the devices without a configurable delay
+const FAST_ACCESS_CYCLES: Cycles = 2;
+
+/// Number of cycles taken by the DMA to fetch a linked list header
+const LINKED_LIST_HEADER_CYCLES: Cycles = 10;
+
+/// Value written by the OTC channel in the last entry of the ordering
+/// table (the one at the lowest address). It's the "end of list"
+/// marker for the GPU linked list transfers.
+const OTC_TERMINATOR: u32 = 0xffffff;
+
+/// Policy applied when the CPU accesses an address within a mapped
+/// region that isn't emulated (because we don't support it yet).
+/// Accesses to addresses where nothing is mapped on the real hardware
+/// always trigger a bus error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcDecodable, RustcEncodable)]
+pub enum UnhandledAccessPolicy {
+    /// Abort the emulation. Useful when developing the emulator to
+    /// catch missing features early.
+    Panic,
+    /// Log the access, ignore writes and return the open bus value
+    /// (the last instruction fetched) for reads. Unsupported accesses
+    /// to known registers are ignored instead of aborting.
+    OpenBus,
+    /// Log the access and trigger a bus error exception in the CPU.
+    BusError,
+}
+
 /// Trait representing the attributes of a memory access
 pub trait Addressable {
     /// Retreive the size of the access in bytes
@@ -703,8 +1546,9 @@ pub mod map {
 
     impl Range {
         /// Return `Some(offset)` if addr is contained in `self`
-        pub fn contains(self, addr: u32) -> Option<u32> {
+        pub fn contains(self, addr: PhysAddr) -> Option<u32> {
             let Range(start, length) = self;
+            let PhysAddr(addr) = addr;
 
             if addr >= start && addr < start + length {
                 Some(addr - start)
@@ -714,6 +1558,64 @@ pub mod map {
         }
     }
 
+    /// Address as seen on the bus, once the CPU region bits have been
+    /// stripped. The device ranges below only match physical
+    /// addresses so every access has to be translated first.
+    #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+    pub struct PhysAddr(u32);
+
+    impl PhysAddr {
+        /// Translate the CPU address `addr`
+        pub fn from_cpu(addr: u32) -> PhysAddr {
+            PhysAddr(mask_region(addr))
+        }
+
+        /// Translate an address used by a DMA channel. The DMA can
+        /// only reach the RAM: the address wraps around the 2MB and
+        /// the two LSBs are ignored.
+        pub fn from_dma(addr: u32) -> PhysAddr {
+            PhysAddr(addr & 0x1ffffc)
+        }
+
+        pub fn get(self) -> u32 {
+            self.0
+        }
+    }
+
+    /// The four segments of the CPU address space
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum Segment {
+        /// User segment, 2GB
+        Kuseg,
+        /// Cached kernel mirror of the first 512MB of KUSEG
+        Kseg0,
+        /// Uncached kernel mirror of the first 512MB of KUSEG
+        Kseg1,
+        /// Kernel segment containing the cache control register
+        Kseg2,
+    }
+
+    impl Segment {
+        /// Return the segment containing the CPU address `addr`
+        pub fn from_address(addr: u32) -> Segment {
+            match addr >> 29 {
+                0...3 => Segment::Kuseg,
+                4 => Segment::Kseg0,
+                5 => Segment::Kseg1,
+                _ => Segment::Kseg2,
+            }
+        }
+
+        /// True if accesses through this segment go through the
+        /// instruction cache and the ScratchPad
+        pub fn is_cached(self) -> bool {
+            match self {
+                Segment::Kuseg | Segment::Kseg0 => true,
+                Segment::Kseg1 | Segment::Kseg2 => false,
+            }
+        }
+    }
+
     /// Mask array used to strip the region bits of the address. The
     /// mask is selected using the 3 MSBs of the address so each entry
     /// effectively matches 512kB of the address space. KSEG2 is not
@@ -743,6 +1645,11 @@ pub mod map {
 
     pub const BIOS: Range = Range(0x1fc00000, 512 * 1024);
 
+    /// Region where the BIOS ROM is mirrored if the BIOS window
+    /// configured in the memory control registers is bigger than the
+    /// ROM
+    pub const BIOS_MIRRORS: Range = Range(0x1fc00000, 4 * 1024 * 1024);
+
     /// ScratchPad: data cache used as a fast 1kB RAM
     pub const SCRATCH_PAD: Range = Range(0x1f800000, 1024);
 
@@ -750,7 +1657,10 @@ pub mod map {
     pub const MEM_CONTROL: Range = Range(0x1f801000, 36);
 
     /// Gamepad and memory card controller
-    pub const PAD_MEMCARD: Range = Range(0x1f801040, 32);
+    pub const PAD_MEMCARD: Range = Range(0x1f801040, 16);
+
+    /// Serial port 1
+    pub const SIO1: Range = Range(0x1f801050, 16);
 
     /// Register that has something to do with RAM configuration,
     /// configured by the BIOS
@@ -777,6 +1687,213 @@ pub mod map {
     /// Expansion region 2
     pub const EXPANSION_2: Range = Range(0x1f802000, 66);
 
+    /// Expansion region 3
+    pub const EXPANSION_3: Range = Range(0x1fa00000, 2 * 1024 * 1024);
+
     /// Cache control register. Full address since it's in KSEG2
     pub const CACHE_CONTROL: Range = Range(0xfffe0130, 4);
+
+    /// KSEG2 I/O window containing the cache control register and a
+    /// few other registers of unknown use. Must be tested after
+    /// `CACHE_CONTROL`.
+    pub const KSEG2_IO: Range = Range(0xfffe0000, 0x200);
+
+    /// Regions decoded by the hardware, whether or not we emulate
+    /// what's behind them. Accessing anything else results in a bus
+    /// error.
+    const BUS_REGIONS: [(u32, u32); 6] = [
+        // Main RAM
+        (0x00000000, 8 * 1024 * 1024),
+        // Expansion 1 (including the ScratchPad)
+        (0x1f000000, 8 * 1024 * 1024),
+        // I/O ports and expansion 2
+        (0x1f801000, 0x2000),
+        // Expansion 3
+        (0x1fa00000, 2 * 1024 * 1024),
+        // BIOS
+        (0x1fc00000, 512 * 1024),
+        // KSEG2 cache control
+        (0xfffe0000, 0x200),
+    ];
+
+    /// Return true if something answers at the physical address
+    /// `abs_addr`
+    pub fn is_mapped(abs_addr: PhysAddr) -> bool {
+        BUS_REGIONS.iter().any(|&(start, len)| {
+            Range(start, len).contains(abs_addr).is_some()
+        })
+    }
+}
+
+#[test]
+fn address_translation() {
+    // KUSEG, KSEG0 and KSEG1 all mirror the same 512MB
+    for &base in &[0x00000000, 0x80000000, 0xa0000000] {
+        for &offset in &[0, 0x1234, 0x1f801810, 0x1fc00000, 0x1fffffff] {
+            let phys = PhysAddr::from_cpu(base + offset);
+
+            assert_eq!(phys.get(), offset);
+        }
+    }
+
+    // KSEG2 isn't translated
+    assert_eq!(PhysAddr::from_cpu(0xfffe0130).get(), 0xfffe0130);
+    assert!(map::CACHE_CONTROL.contains(PhysAddr::from_cpu(0xfffe0130))
+            .is_some());
+
+    assert_eq!(map::GPU.contains(PhysAddr::from_cpu(0xbf801814)), Some(4));
+    assert_eq!(map::RAM.contains(PhysAddr::from_cpu(0x1f800000)), None);
+
+    // The DMA wraps around the RAM and ignores the LSBs
+    assert_eq!(PhysAddr::from_dma(0x00fffffe).get(), 0x001ffffc);
+
+    assert!(Segment::from_address(0x7fffffff).is_cached());
+    assert!(Segment::from_address(0x80010000).is_cached());
+    assert!(!Segment::from_address(0xbfc00000).is_cached());
+    assert_eq!(Segment::from_address(0xfffe0130), Segment::Kseg2);
+}
+
+#[test]
+fn bios_mirrors_and_writes() {
+    use cpu::test_machine::NullRenderer;
+    use gpu::VideoClock;
+
+    let mut shared = SharedState::new();
+    let mut renderer = NullRenderer;
+    let mut inter = Interconnect::new(Bios::dummy(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+
+    let rom = inter.load::<Word>(&mut shared, 0xbfc00100);
+
+    // Ignored by default, only the first write is reported
+    inter.store::<Word>(&mut shared, &mut renderer, 0xbfc00100, 0);
+    inter.store::<Word>(&mut shared, &mut renderer, 0xbfc00104, 0);
+
+    assert_eq!(inter.load::<Word>(&mut shared, 0xbfc00100), rom);
+    assert_eq!(shared.poll_event(), Some(Event::BiosWrite(0xbfc00100)));
+    assert_eq!(shared.poll_event(), None);
+
+    // Extend the BIOS window to 1MB to get a mirror
+    inter.store::<Word>(&mut shared, &mut renderer, 0x1f801010, 0x0014243f);
+
+    assert_eq!(inter.load::<Word>(&mut shared, 0xbfc80100), rom);
+    // Fast path
+    assert_eq!(inter.load::<Word>(&mut shared, 0xbfc80100), rom);
+
+    inter.set_bios_writable(true);
+    inter.store::<Word>(&mut shared, &mut renderer, 0xbfc80100, 0x1234);
+
+    assert_eq!(inter.load::<Word>(&mut shared, 0xbfc00100), 0x1234);
+}
+
+#[test]
+fn unhandled_access_policies() {
+    use cpu::test_machine::NullRenderer;
+    use gpu::VideoClock;
+
+    let mut shared = SharedState::new();
+    let mut renderer = NullRenderer;
+    let mut inter = Interconnect::new(Bios::dummy(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+
+    // Expansion 3 is decoded by the hardware but we don't emulate
+    // anything there
+    let unhandled = 0xbfa00000;
+    // Nothing is mapped there
+    let unmapped = 0xbf900000;
+
+    let fetch = inter.load_instruction(&mut shared, 0xbfc00000);
+
+    inter.set_unhandled_access_policy(UnhandledAccessPolicy::OpenBus);
+
+    assert_eq!(inter.load::<Word>(&mut shared, unhandled), fetch);
+    assert!(!inter.take_bus_error());
+    inter.store::<Word>(&mut shared, &mut renderer, unhandled, 0);
+    assert!(!inter.take_bus_error());
+
+    // Unmapped addresses raise a bus error regardless of the policy
+    inter.load::<Word>(&mut shared, unmapped);
+    assert!(inter.take_bus_error());
+    // The flag is cleared when read
+    assert!(!inter.take_bus_error());
+
+    inter.set_unhandled_access_policy(UnhandledAccessPolicy::BusError);
+
+    assert_eq!(inter.load::<Byte>(&mut shared, unhandled), fetch);
+    assert!(inter.take_bus_error());
+    inter.store::<Word>(&mut shared, &mut renderer, unhandled, 0);
+    assert!(inter.take_bus_error());
+
+    // Each distinct access is only logged once
+    assert_eq!(inter.unhandled_accesses().len(), 4);
+}
+
+#[test]
+#[should_panic]
+fn unhandled_access_panic() {
+    use gpu::VideoClock;
+
+    let mut shared = SharedState::new();
+    let mut inter = Interconnect::new(Bios::dummy(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+
+    // Panic is the default policy
+    inter.load::<Word>(&mut shared, 0xbfa00000);
+}
+
+#[test]
+fn dma_chopping() {
+    use cpu::test_machine::NullRenderer;
+    use gpu::VideoClock;
+
+    let mut shared = SharedState::new();
+    let mut renderer = NullRenderer;
+    let mut inter = Interconnect::new(Bios::dummy(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+
+    for i in 0..64 {
+        inter.ram.store::<Word>(0x1000 + i * 4, 0xffffffff);
+    }
+
+    // Enable the MDEC out channel in DPCR
+    inter.store::<Word>(&mut shared, &mut renderer, 0x1f8010f0, 0x076543a1);
+
+    // 64 word manual transfer to RAM with 8 word DMA windows and 16
+    // cycle CPU windows
+    inter.store::<Word>(&mut shared, &mut renderer, 0x1f801090, 0x1000);
+    inter.store::<Word>(&mut shared, &mut renderer, 0x1f801094, 64);
+
+    let start = shared.tk().now();
+
+    inter.store::<Word>(&mut shared, &mut renderer, 0x1f801098, 0x11430100);
+
+    // Only the first window has been transferred, one cycle per word
+    assert_eq!(inter.dma.channel(Port::MDecOut).remaining(), 56);
+    assert_eq!(shared.tk().now(), start + 8);
+    assert_eq!(inter.ram.load::<Word>(0x101c), 0);
+    assert_eq!(inter.ram.load::<Word>(0x1020), 0xffffffff);
+
+    // The CPU runs until the end of its window
+    shared.tk().tick(15);
+    inter.sync(&mut shared, &mut renderer);
+    assert_eq!(inter.dma.channel(Port::MDecOut).remaining(), 56);
+
+    shared.tk().tick(1);
+    inter.sync(&mut shared, &mut renderer);
+    assert_eq!(inter.dma.channel(Port::MDecOut).remaining(), 48);
+
+    for _ in 0..6 {
+        shared.tk().tick(16);
+        inter.sync(&mut shared, &mut renderer);
+    }
+
+    let channel = inter.dma.channel(Port::MDecOut);
+
+    assert!(!channel.in_progress());
+    assert!(!channel.active());
+    assert_eq!(inter.ram.load::<Word>(0x10fc), 0);
 }
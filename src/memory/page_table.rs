@@ -0,0 +1,176 @@
+//! Page table used to speed up the memory accesses.
+//!
+//! The vast majority of the CPU accesses target the RAM, the
+//! ScratchPad or the BIOS. Instead of testing the address against
+//! the range of every device the physical address space is split in
+//! 4KB pages and a single lookup tells us if the page is backed by
+//! one of those memories. Everything else (I/O registers, expansion
+//! regions, KSEG2...) takes the slow path.
+//!
+//! The BIOS mirrors are only valid if the BIOS window configured in
+//! the memory control registers is bigger than the ROM so they're
+//! mapped lazily, the first time the slow path resolves an access to
+//! them.
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+use super::map::{self, PhysAddr};
+
+/// Memory directly accessible in a page
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Page {
+    /// Not a plain memory, the access must go through the slow path
+    Slow,
+    Ram,
+    Bios,
+    ScratchPad,
+}
+
+pub struct PageTable {
+    /// One entry per page of the first 512MB of the physical address
+    /// space, the KSEG2 registers always take the slow path
+    pages: Box<[Page]>,
+}
+
+impl PageTable {
+    pub fn new() -> PageTable {
+        let mut pages = vec![Page::Slow; PAGE_COUNT];
+
+        let memories = [
+            (map::RAM, Page::Ram),
+            (map::BIOS, Page::Bios),
+            (map::SCRATCH_PAD, Page::ScratchPad),
+            ];
+
+        for &(ref range, page) in &memories {
+            let map::Range(start, len) = *range;
+
+            let first = (start >> PAGE_SHIFT) as usize;
+            let last = ((start + len - 1) >> PAGE_SHIFT) as usize;
+
+            for p in &mut pages[first..last + 1] {
+                *p = page;
+            }
+        }
+
+        PageTable {
+            pages: pages.into_boxed_slice(),
+        }
+    }
+
+    /// Map the BIOS mirror page containing `addr`. Should only be
+    /// called once the address has been checked against the BIOS
+    /// window.
+    pub fn map_bios_mirror(&mut self, addr: PhysAddr) {
+        if map::BIOS_MIRRORS.contains(addr).is_some() {
+            self.pages[(addr.get() >> PAGE_SHIFT) as usize] = Page::Bios;
+        }
+    }
+
+    /// Unmap all the BIOS mirrors, used when the BIOS window changes
+    pub fn unmap_bios_mirrors(&mut self) {
+        let map::Range(start, len) = map::BIOS_MIRRORS;
+
+        // The ROM itself is always mapped
+        let first = ((start + map::BIOS.1) >> PAGE_SHIFT) as usize;
+        let last = ((start + len - 1) >> PAGE_SHIFT) as usize;
+
+        for p in &mut self.pages[first..last + 1] {
+            *p = Page::Slow;
+        }
+    }
+
+    /// Return the memory mapped at `addr`
+    #[inline(always)]
+    pub fn lookup(&self, addr: PhysAddr) -> Page {
+        let addr = addr.get();
+
+        match self.pages.get((addr >> PAGE_SHIFT) as usize) {
+            // The ScratchPad is smaller than a page
+            Some(&Page::ScratchPad) if addr & PAGE_MASK >= SCRATCH_PAD_SIZE =>
+                Page::Slow,
+            Some(&p) => p,
+            None => Page::Slow,
+        }
+    }
+}
+
+impl Encodable for PageTable {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // The table never changes, it's rebuilt when loading
+        s.emit_nil()
+    }
+}
+
+impl Decodable for PageTable {
+    fn decode<D: Decoder>(d: &mut D) -> Result<PageTable, D::Error> {
+        try!(d.read_nil());
+
+        Ok(PageTable::new())
+    }
+}
+
+/// log2 of the page size
+const PAGE_SHIFT: u32 = 12;
+
+const PAGE_MASK: u32 = (1 << PAGE_SHIFT) - 1;
+
+/// Number of pages needed to cover 512MB
+const PAGE_COUNT: usize = 1 << (29 - PAGE_SHIFT);
+
+const SCRATCH_PAD_SIZE: u32 = map::SCRATCH_PAD.1;
+
+#[test]
+fn page_lookup() {
+    let table = PageTable::new();
+
+    let lookup = |addr| table.lookup(PhysAddr::from_cpu(addr));
+
+    assert_eq!(lookup(0x80000000), Page::Ram);
+    assert_eq!(lookup(0x007ffffc), Page::Ram);
+    assert_eq!(lookup(0x00800000), Page::Slow);
+    assert_eq!(lookup(0xbfc00180), Page::Bios);
+    assert_eq!(lookup(0x1fc7fffc), Page::Bios);
+    assert_eq!(lookup(0x1fc80000), Page::Slow);
+    assert_eq!(lookup(0x1f8003fc), Page::ScratchPad);
+    assert_eq!(lookup(0x1f800400), Page::Slow);
+    assert_eq!(lookup(0x1f801810), Page::Slow);
+    assert_eq!(lookup(0xfffe0130), Page::Slow);
+
+    // Make sure the table agrees with the memory map for every page
+    for page in 0..PAGE_COUNT as u32 {
+        let addr = PhysAddr::from_cpu(page << PAGE_SHIFT);
+
+        let expected =
+            if map::RAM.contains(addr).is_some() {
+                Page::Ram
+            } else if map::BIOS.contains(addr).is_some() {
+                Page::Bios
+            } else if map::SCRATCH_PAD.contains(addr).is_some() {
+                Page::ScratchPad
+            } else {
+                Page::Slow
+            };
+
+        assert_eq!(table.lookup(addr), expected);
+    }
+}
+
+#[test]
+fn bios_mirrors() {
+    let mut table = PageTable::new();
+
+    let mirror = PhysAddr::from_cpu(0xbfe00100);
+
+    assert_eq!(table.lookup(mirror), Page::Slow);
+
+    table.map_bios_mirror(mirror);
+
+    assert_eq!(table.lookup(mirror), Page::Bios);
+    assert_eq!(table.lookup(PhysAddr::from_cpu(0xbfe01000)), Page::Slow);
+
+    table.unmap_bios_mirrors();
+
+    assert_eq!(table.lookup(mirror), Page::Slow);
+    assert_eq!(table.lookup(PhysAddr::from_cpu(0xbfc00000)), Page::Bios);
+}
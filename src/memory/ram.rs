@@ -1,6 +1,7 @@
 use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
 use super::Addressable;
+use init_policy::Filler;
 
 /// RAM
 pub struct Ram {
@@ -43,6 +44,16 @@ impl Ram {
             self.data[offset + i] = (val >> (i * 8)) as u8;
         }
     }
+
+    /// Overwrite the entire RAM with values generated by `filler`
+    pub fn fill(&mut self, filler: &mut Filler) {
+        filler.fill(&mut self.data[..], 0xca);
+    }
+
+    /// Return the raw contents of the RAM. Used by the debugger.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..]
+    }
 }
 
 impl Encodable for Ram {
@@ -86,6 +97,12 @@ impl ScratchPad {
         ScratchPad { data: [0xdb; SCRATCH_PAD_SIZE] }
     }
 
+    /// Overwrite the entire ScratchPad with values generated by
+    /// `filler`
+    pub fn fill(&mut self, filler: &mut Filler) {
+        filler.fill(&mut self.data, 0xdb);
+    }
+
     /// Fetch the little endian value at `offset`
     pub fn load<T: Addressable>(&self, offset: u32) -> u32 {
         let offset = offset as usize;
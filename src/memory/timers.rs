@@ -1,8 +1,12 @@
+use std::cmp;
+
 use timekeeper::{Cycles, FracCycles, Peripheral};
 use gpu::Gpu;
+use gpu::timing::Blanking;
 use super::Addressable;
 use interrupt::Interrupt;
 use shared::SharedState;
+use logging;
 
 #[derive(Debug, RustcDecodable, RustcEncodable)]
 pub struct Timers {
@@ -156,6 +160,15 @@ struct Timer {
     phase: FracCycles,
     /// True if interrupt signal is active
     interrupt: bool,
+    /// Set when an interrupt condition has been reached since the
+    /// last mode write. In one-shot mode (`repeat_irq` false) no
+    /// other interrupt is generated until the mode is written again.
+    irq_done: bool,
+    /// Blanking signal used for the synchronization modes of timers
+    /// 0 (HBLANK) and 1 (VBLANK)
+    blanking: Option<Blanking>,
+    /// Set in `WaitForSync` mode until the first blanking starts
+    waiting_for_sync: bool,
 }
 
 impl Timer {
@@ -177,6 +190,9 @@ impl Timer {
             period: FracCycles::from_cycles(1),
             phase: FracCycles::from_cycles(0),
             interrupt: false,
+            irq_done: false,
+            blanking: None,
+            waiting_for_sync: false,
         }
     }
 
@@ -209,6 +225,13 @@ impl Timer {
             }
         }
 
+        self.blanking =
+            match (self.use_sync, self.instance) {
+                (true, Peripheral::Timer0) => Some(gpu.hblank()),
+                (true, Peripheral::Timer1) => Some(gpu.vblank()),
+                _ => None,
+            };
+
         self.predict_next_sync(shared);
     }
 
@@ -225,9 +248,73 @@ impl Timer {
             return;
         }
 
-        let delta_frac = FracCycles::from_cycles(delta);
+        let mut remaining = FracCycles::from_cycles(delta).get_fp();
+
+        // Split the elapsed time at each edge of the blanking signal
+        // (if any) since the counter might behave differently on
+        // each side.
+        while remaining > 0 {
+            let (elapsed, blank, blank_start) =
+                match self.blanking {
+                    Some(ref mut b) => {
+                        let blank = b.in_blank();
+                        let elapsed = b.advance(remaining);
+
+                        (elapsed, blank, !blank && b.in_blank())
+                    }
+                    None => (remaining, false, false),
+                };
+
+            remaining -= elapsed;
+
+            if self.counting(blank) {
+                self.count(shared, FracCycles::from_fp(elapsed));
+            }
+
+            if blank_start {
+                self.blanking_started();
+            }
+        }
+
+        self.predict_next_sync(shared)
+    }
+
+    /// Return true if the counter is running given the current state
+    /// of the blanking signal
+    fn counting(&self, blank: bool) -> bool {
+        if !self.use_sync {
+            return true;
+        }
+
+        match (self.instance, self.sync) {
+            // Timer 2 doesn't use the blanking signals: modes 0 and
+            // 3 stop the counter until the mode is changed, 1 and 2
+            // free-run as if the synchronization was disabled.
+            (Peripheral::Timer2, Sync::Pause) => false,
+            (Peripheral::Timer2, Sync::WaitForSync) => false,
+            (Peripheral::Timer2, _) => true,
+            (_, Sync::Pause) => !blank,
+            (_, Sync::Reset) => true,
+            (_, Sync::ResetAndPause) => blank,
+            (_, Sync::WaitForSync) => !self.waiting_for_sync,
+        }
+    }
+
+    /// Called at the beginning of the blanking period in sync mode
+    fn blanking_started(&mut self) {
+        match self.sync {
+            Sync::Reset | Sync::ResetAndPause => {
+                self.counter = 0;
+                self.phase = FracCycles::from_cycles(0);
+            }
+            Sync::WaitForSync => self.waiting_for_sync = false,
+            Sync::Pause => (),
+        }
+    }
 
-        let ticks = delta_frac.add(self.phase);
+    /// Advance the counter by `duration`
+    fn count(&mut self, shared: &mut SharedState, duration: FracCycles) {
+        let ticks = duration.add(self.phase);
 
         let mut count = ticks.get_fp() / self.period.get_fp();
         let phase     = ticks.get_fp() % self.period.get_fp();
@@ -276,7 +363,37 @@ impl Timer {
         }
 
         self.counter = count as u16;
+
+        // Once the target is reached the counter keeps going (up to
+        // 0xffff if `target_wrap` is false), only the interrupt
+        // generation depends on the IRQ mode
         if (self.wrap_irq && overflow) || (self.target_irq && target_passed) {
+            self.irq_condition(shared);
+        } else if !self.negate_irq {
+            // Pulse is over
+            self.interrupt = false;
+        }
+    }
+
+    /// Called when one of the interrupt conditions is reached
+    fn irq_condition(&mut self, shared: &mut SharedState) {
+        if self.irq_done && !self.repeat_irq {
+            // One-shot mode, the interrupt already fired
+            return;
+        }
+
+        self.irq_done = true;
+
+        if self.negate_irq {
+            // Toggle mode: the signal changes state each time, the
+            // interrupt is only triggered when it becomes active
+            self.interrupt = !self.interrupt;
+        } else {
+            // Pulse mode
+            self.interrupt = true;
+        }
+
+        if self.interrupt {
             let interrupt =
                 match self.instance {
                     Peripheral::Timer0 => Interrupt::Timer0,
@@ -285,41 +402,62 @@ impl Timer {
                     _ => unreachable!(),
                 };
 
-            if self.negate_irq {
-                panic!("Unhandled negate IRQ!");
-            } else {
-                // Pulse interrupt
-                shared.irq_state_mut().assert(interrupt);
-                self.interrupt = true;
-            }
-        } else if !self.negate_irq {
-            // Pulse is over
-            self.interrupt = false;
+            shared.irq_state_mut().assert(interrupt);
         }
+    }
 
-        self.predict_next_sync(shared)
+    /// Return true if the counter is stopped until the mode changes
+    fn stopped(&self) -> bool {
+        match (self.use_sync, self.instance, self.sync) {
+            (true, Peripheral::Timer2, Sync::Pause) => true,
+            (true, Peripheral::Timer2, Sync::WaitForSync) => true,
+            _ => false,
+        }
     }
 
     fn predict_next_sync(&mut self, shared: &mut SharedState) {
-        // XXX add support for wrap IRQ
+        let irq_enabled = self.target_irq || self.wrap_irq;
+        let one_shot_done = self.irq_done && !self.repeat_irq;
 
-        if !self.target_irq {
-            // No IRQ enabled, we don't need to be called back.
+        if !irq_enabled || one_shot_done || self.stopped() {
+            // No IRQ can fire, we don't need to be called back.
             shared.tk().no_sync_needed(self.instance);
             return;
         }
 
-        let countdown =
+        // Number of ticks before the counter reaches the target
+        let target_countdown =
             if self.counter <= self.target {
-                self.target - self.counter
+                self.target as Cycles - self.counter as Cycles
             } else {
-                0xffff - self.counter + self.target
+                0xffff - self.counter as Cycles + self.target as Cycles
+            };
+
+        // Number of ticks before the counter reaches 0xffff (and
+        // overflows on the next tick), if it ever does
+        let overflow_countdown =
+            if self.target_wrap && self.target != 0xffff {
+                None
+            } else {
+                Some(0xffff - self.counter as Cycles)
+            };
+
+        let countdown =
+            match (self.target_irq, self.wrap_irq, overflow_countdown) {
+                (true, true, Some(o)) => cmp::min(target_countdown, o),
+                (false, true, Some(o)) => o,
+                (true, _, _) => target_countdown,
+                // Wrap IRQ only but the counter never overflows
+                _ => {
+                    shared.tk().no_sync_needed(self.instance);
+                    return;
+                }
             };
 
         // Convert from timer count to CPU cycles. I add + 1 to the
         // countdown because the interrupt is generated on the
         // following cycle (I think?)
-        let mut delta = self.period.get_fp() * (countdown as Cycles + 1);
+        let mut delta = self.period.get_fp() * (countdown + 1);
         delta -= self.phase.get_fp();
 
         // Round up to the next CPU cycle
@@ -331,11 +469,13 @@ impl Timer {
     /// Return true if the timer relies on the GPU for the clock
     /// source or synchronization
     pub fn needs_gpu(&self) -> bool {
-        if self.use_sync {
-            warn!("Sync mode not supported!");
-        }
+        let gpu_sync =
+            match self.instance {
+                Peripheral::Timer0 | Peripheral::Timer1 => self.use_sync,
+                _ => false,
+            };
 
-        self.clock_source.clock(self.instance).needs_gpu()
+        gpu_sync || self.clock_source.clock(self.instance).needs_gpu()
     }
 
     fn mode(&mut self) -> u16 {
@@ -372,26 +512,22 @@ impl Timer {
         self.negate_irq = (val >> 7) & 1 != 0;
         self.clock_source = ClockSource::from_field((val >> 8) & 3);
 
-        // Writing to mode resets the interrupt flag
+        // Writing to mode resets the interrupt flag and re-arms the
+        // one-shot interrupts
         self.interrupt = false;
+        self.irq_done = false;
 
         // Writing to mode resets the counter
         self.counter = 0;
 
-        if self.wrap_irq {
-            panic!("Wrap IRQ not supported");
-        }
-
-        if (self.wrap_irq || self.target_irq) && !self.repeat_irq {
-            panic!("One shot timer interrupts are not supported: {:?}", self);
-        }
-
-        if self.negate_irq {
-            panic!("Only pulse interrupts are supported: {:?}", self);
-        }
+        self.waiting_for_sync = match self.sync {
+            Sync::WaitForSync => self.use_sync,
+            _ => false,
+        };
 
         if self.use_sync {
-            warn!("Sync mode is not supported: {:?}", self);
+            debug!(target: logging::TIMERS,
+                   "Sync mode {:?}: {:?}", self.sync, self);
         }
     }
 
@@ -416,15 +552,15 @@ impl Timer {
 /// free-run.
 #[derive(Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
 enum Sync {
-    /// For timer 1/2: Pause during H/VBlank. For timer 3: Stop counter
+    /// For timer 0/1: Pause during H/VBlank. For timer 2: Stop counter
     Pause = 0,
-    /// For timer 1/2: Reset counter at H/VBlank. For timer 3: Free run
+    /// For timer 0/1: Reset counter at H/VBlank. For timer 2: Free run
     Reset = 1,
-    /// For timer 1/2: Reset counter at H/VBlank and pause outside of
-    /// it. For timer 3: Free run
+    /// For timer 0/1: Reset counter at H/VBlank and pause outside of
+    /// it. For timer 2: Free run
     ResetAndPause = 2,
-    /// For timer 1/2: Wait for H/VBlank and then free-run. For timer
-    /// 3: Stop counter
+    /// For timer 0/1: Wait for H/VBlank and then free-run. For timer
+    /// 2: Stop counter
     WaitForSync = 3,
 }
 
@@ -507,3 +643,52 @@ impl Clock {
         }
     }
 }
+
+#[test]
+fn timer2_irq_modes() {
+    let mut shared = SharedState::new();
+    let mut timer = Timer::new(Peripheral::Timer2);
+
+    let irq = 1 << Interrupt::Timer2 as u16;
+
+    // One-shot pulse on target, the counter keeps going past it
+    timer.set_target(10);
+    timer.set_mode(0x10);
+
+    timer.count(&mut shared, FracCycles::from_cycles(11));
+    assert_eq!(shared.irq_state().status(), irq);
+    assert_eq!(timer.counter(), 11);
+
+    shared.irq_state_mut().ack(0);
+
+    // The target is reached again but the interrupt already fired
+    timer.set_counter(0);
+    timer.count(&mut shared, FracCycles::from_cycles(11));
+    assert_eq!(shared.irq_state().status(), 0);
+
+    // Repeat + toggle mode: only every other condition triggers
+    timer.set_mode(0x10 | 0x40 | 0x80);
+
+    timer.count(&mut shared, FracCycles::from_cycles(11));
+    assert_eq!(shared.irq_state().status(), irq);
+    assert!(timer.mode() & 0x400 == 0);
+
+    shared.irq_state_mut().ack(0);
+
+    timer.set_counter(0);
+    timer.count(&mut shared, FracCycles::from_cycles(11));
+    assert_eq!(shared.irq_state().status(), 0);
+    assert!(timer.mode() & 0x400 != 0);
+
+    // Sync modes 0 and 3 stop the counter
+    timer.set_mode(0x1);
+    assert!(!timer.counting(false));
+    timer.set_mode(0x7);
+    assert!(!timer.counting(false));
+
+    // Sync modes 1 and 2 free-run
+    timer.set_mode(0x3);
+    assert!(timer.counting(false));
+    timer.set_mode(0x5);
+    assert!(timer.counting(false));
+}
@@ -0,0 +1,86 @@
+//! Record of the unhandled memory accesses. When the emulator is
+//! configured to keep running after an unhandled access (see
+//! `UnhandledAccessPolicy`) the log messages are easy to miss, this
+//! keeps a list of the distinct accesses so that they can be reported
+//! at the end of a run.
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+/// An unhandled memory access
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UnhandledAccess {
+    /// Address targeted by the CPU
+    pub addr: u32,
+    /// Access width in bytes
+    pub size: u8,
+    /// True for stores, false for loads
+    pub store: bool,
+}
+
+pub struct UnhandledLog {
+    /// Distinct accesses, in the order they were first encountered
+    accesses: Vec<UnhandledAccess>,
+}
+
+impl UnhandledLog {
+    pub fn new() -> UnhandledLog {
+        UnhandledLog {
+            accesses: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, access: UnhandledAccess) {
+        if self.accesses.len() < UNHANDLED_LOG_CAPACITY &&
+            !self.accesses.contains(&access) {
+            self.accesses.push(access);
+        }
+    }
+
+    pub fn accesses(&self) -> &[UnhandledAccess] {
+        &self.accesses
+    }
+
+    pub fn clear(&mut self) {
+        self.accesses.clear();
+    }
+}
+
+impl Encodable for UnhandledLog {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Diagnostics, not part of the console state
+        s.emit_nil()
+    }
+}
+
+impl Decodable for UnhandledLog {
+    fn decode<D: Decoder>(d: &mut D) -> Result<UnhandledLog, D::Error> {
+        try!(d.read_nil());
+
+        Ok(UnhandledLog::new())
+    }
+}
+
+/// Maximum number of distinct accesses recorded. A game stuck in a
+/// loop hitting a different address each time could otherwise fill
+/// the memory.
+pub const UNHANDLED_LOG_CAPACITY: usize = 256;
+
+#[test]
+fn distinct_accesses() {
+    let mut log = UnhandledLog::new();
+
+    let a = UnhandledAccess { addr: 0x1f802041, size: 1, store: true };
+    let b = UnhandledAccess { addr: 0x1f802041, size: 1, store: false };
+
+    log.record(a);
+    log.record(b);
+    log.record(a);
+
+    assert_eq!(log.accesses(), &[a, b]);
+
+    for addr in 0..(UNHANDLED_LOG_CAPACITY as u32 * 2) {
+        log.record(UnhandledAccess { addr: addr, size: 4, store: false });
+    }
+
+    assert_eq!(log.accesses().len(), UNHANDLED_LOG_CAPACITY);
+}
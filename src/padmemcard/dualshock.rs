@@ -0,0 +1,235 @@
+//! SCPH-1200: DualShock analog controller with force feedback.
+//!
+//! The controller starts in digital mode and behaves like a
+//! SCPH-1080. Games switch it to analog mode and configure the
+//! rumble motors through the "config mode" commands (0x43 to
+//! 0x4d). Once configured the motor values are sent alongside the
+//! regular 0x42 "read buttons" command, we just keep track of them
+//! and let the frontend poll them with `Profile::rumble`.
+
+use super::gamepad::{Profile, Button, ButtonState, Axis, Rumble};
+
+pub struct DualShockProfile {
+    /// Active low button state
+    buttons: u16,
+    /// Analog sticks: right X, right Y, left X, left Y
+    axes: [u8; 4],
+    /// True if the controller is in analog mode
+    analog: bool,
+    /// True if the controller is in config mode
+    config: bool,
+    /// Command being processed
+    command: u8,
+    /// Parameter of the current command for the commands taking an
+    /// index
+    index: u8,
+    /// Motor addressed by each byte of the 0x42 command payload: 0x00
+    /// for the small motor, 0x01 for the big one and 0xff for none.
+    rumble_config: [u8; 6],
+    rumble: Rumble,
+}
+
+impl DualShockProfile {
+    pub fn new() -> DualShockProfile {
+        DualShockProfile {
+            buttons: 0xffff,
+            axes: [0x80; 4],
+            analog: false,
+            config: false,
+            command: 0,
+            index: 0,
+            rumble_config: [0xff; 6],
+            rumble: Rumble::off(),
+        }
+    }
+
+    fn id(&self) -> u8 {
+        if self.config {
+            0xf3
+        } else if self.analog {
+            0x73
+        } else {
+            0x41
+        }
+    }
+
+    /// Length of the payload following the 0x5a byte
+    fn payload_len(&self) -> u8 {
+        if self.config || self.analog {
+            6
+        } else {
+            2
+        }
+    }
+
+    /// Return the `i`th byte of the button and axes state
+    fn state_byte(&self, i: u8) -> u8 {
+        match i {
+            0 => self.buttons as u8,
+            1 => (self.buttons >> 8) as u8,
+            _ => self.axes[i as usize - 2],
+        }
+    }
+
+    fn set_motor(&mut self, i: u8, val: u8) {
+        match self.rumble_config[i as usize] {
+            0x00 => self.rumble.small = if val & 1 != 0 { 0xff } else { 0 },
+            0x01 => self.rumble.big = val,
+            _ => (),
+        }
+    }
+
+    /// Handle the payload byte `i` of the current command
+    fn payload(&mut self, i: u8, cmd: u8) -> u8 {
+        if i == 0 {
+            self.index = cmd;
+        }
+
+        match self.command {
+            0x42 => {
+                self.set_motor(i, cmd);
+                self.state_byte(i)
+            }
+            0x43 => {
+                let r = if self.config { 0 } else { self.state_byte(i) };
+
+                // The new mode takes effect at the end of the
+                // command
+                if i == self.payload_len() - 1 {
+                    self.config = self.index == 0x01;
+                    self.rumble = Rumble::off();
+                }
+
+                r
+            }
+            // Set mode. The second byte locks the mode switch button
+            // which we don't emulate.
+            0x44 => {
+                if i == 0 {
+                    self.analog = cmd == 0x01;
+                }
+
+                0
+            }
+            // Get status: controller type and current mode
+            0x45 => {
+                let status = [0x01, 0x02, self.analog as u8, 0x02, 0x01, 0x00];
+
+                status[i as usize]
+            }
+            // Unknown constants used to identify the controller
+            0x46 => match (self.index, i) {
+                (0, 2) => 0x01,
+                (0, 3) => 0x02,
+                (0, 5) => 0x0a,
+                (1, 2) => 0x01,
+                (1, 3) => 0x01,
+                (1, 4) => 0x01,
+                (1, 5) => 0x14,
+                _ => 0,
+            },
+            0x47 => [0x00, 0x00, 0x02, 0x00, 0x01, 0x00][i as usize],
+            0x4c => match (self.index, i) {
+                (0, 3) => 0x04,
+                (1, 3) => 0x07,
+                _ => 0,
+            },
+            // Rumble configuration, returns the previous one
+            0x4d => {
+                let prev = self.rumble_config[i as usize];
+
+                self.rumble_config[i as usize] = cmd;
+                self.rumble = Rumble::off();
+
+                prev
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl Profile for DualShockProfile {
+    fn handle_command(&mut self, seq: u8, cmd: u8) -> (u8, bool) {
+        match seq {
+            0 => (0xff, (cmd == 0x01)),
+            1 => {
+                let valid =
+                    match cmd {
+                        0x42 | 0x43 => true,
+                        0x44...0x4f => self.config,
+                        _ => false,
+                    };
+
+                self.command = cmd;
+
+                (self.id(), valid)
+            }
+            2 => (0x5a, true),
+            _ => {
+                let i = seq - 3;
+                let len = self.payload_len();
+
+                if i >= len {
+                    return (0xff, false);
+                }
+
+                // Must be computed before the payload in case the
+                // mode changes
+                let last = i == len - 1;
+
+                (self.payload(i, cmd), !last)
+            }
+        }
+    }
+
+    fn set_button_state(&mut self, button: Button, state: ButtonState) {
+        let mask = 1 << (button as usize);
+
+        self.buttons =
+            match state {
+                ButtonState::Pressed  => self.buttons & !mask,
+                ButtonState::Released => self.buttons | mask,
+            };
+    }
+
+    fn set_axis_state(&mut self, axis: Axis, val: u8) {
+        self.axes[axis as usize] = val;
+    }
+
+    fn rumble(&self) -> Rumble {
+        self.rumble
+    }
+}
+
+#[test]
+fn rumble_config() {
+    let mut pad = DualShockProfile::new();
+
+    fn transfer(pad: &mut DualShockProfile, cmds: &[u8]) -> Vec<u8> {
+        cmds.iter()
+            .enumerate()
+            .map(|(seq, &c)| pad.handle_command(seq as u8, c).0)
+            .collect()
+    }
+
+    // Enter config mode
+    transfer(&mut pad, &[0x01, 0x43, 0x00, 0x01, 0x00]);
+    assert_eq!(pad.id(), 0xf3);
+
+    // Analog mode
+    transfer(&mut pad, &[0x01, 0x44, 0x00, 0x01, 0x03, 0, 0, 0, 0]);
+    // Small motor on the first byte, big motor on the second
+    let r = transfer(&mut pad, &[0x01, 0x4d, 0x00, 0x00, 0x01,
+                                 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(&r[3..], &[0xff; 6]);
+
+    // Exit config mode
+    transfer(&mut pad, &[0x01, 0x43, 0x00, 0x00, 0, 0, 0, 0, 0]);
+    assert_eq!(pad.id(), 0x73);
+
+    let r = transfer(&mut pad, &[0x01, 0x42, 0x00, 0x01, 0x80,
+                                 0, 0, 0, 0]);
+    assert_eq!(r, [0xff, 0x73, 0x5a, 0xff, 0xff, 0x80, 0x80, 0x80, 0x80]);
+    assert_eq!(pad.rumble(), Rumble { small: 0xff, big: 0x80 });
+    assert_eq!(pad.rumble().scaled(0.5), (0x7fff, 0x4040));
+}
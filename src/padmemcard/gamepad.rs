@@ -1,5 +1,7 @@
 use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
+use gpu::BeamPosition;
+
 pub struct GamePad {
     /// Gamepad profile. *Not* stored in the savestate.
     profile: Box<Profile>,
@@ -8,6 +10,15 @@ pub struct GamePad {
     seq:    u8,
     /// False if the pad is done processing the current command
     active: bool,
+    /// Latest state of the buttons set through `set_button_state`, one
+    /// bit per button, set if pressed. *Not* stored in the savestate
+    /// (neither are the other input fields).
+    buttons: u16,
+    /// Buttons modified since the last `latch_input`
+    buttons_changed: u16,
+    /// Axis positions set through `set_axis_state` since the last
+    /// `latch_input`
+    axes: [Option<u8>; 4],
 }
 
 impl GamePad {
@@ -17,6 +28,57 @@ impl GamePad {
             seq: 0,
             active: true,
             profile: Box::new(DisconnectedProfile),
+            buttons: 0,
+            buttons_changed: 0,
+            axes: [None; 4],
+        }
+    }
+
+    /// Set a button state, applied to the profile when the controller
+    /// latches its input (see `InputLatch`). Only the latest state is
+    /// kept, a button pressed and released between two latches is
+    /// never seen by the game.
+    pub fn set_button_state(&mut self, button: Button, state: ButtonState) {
+        let mask = 1 << (button as usize);
+
+        match state {
+            ButtonState::Pressed => self.buttons |= mask,
+            ButtonState::Released => self.buttons &= !mask,
+        }
+
+        self.buttons_changed |= mask;
+    }
+
+    /// Set an axis position, applied like `set_button_state`
+    pub fn set_axis_state(&mut self, axis: Axis, val: u8) {
+        self.axes[axis as usize] = Some(val);
+    }
+
+    /// Apply the input changes to the profile
+    pub fn latch_input(&mut self) {
+        for &button in BUTTONS.iter() {
+            let mask = 1 << (button as usize);
+
+            if self.buttons_changed & mask == 0 {
+                continue;
+            }
+
+            let state =
+                if self.buttons & mask != 0 {
+                    ButtonState::Pressed
+                } else {
+                    ButtonState::Released
+                };
+
+            self.profile.set_button_state(button, state);
+        }
+
+        self.buttons_changed = 0;
+
+        for &axis in AXES.iter() {
+            if let Some(v) = self.axes[axis as usize].take() {
+                self.profile.set_axis_state(axis, v);
+            }
         }
     }
 
@@ -98,6 +160,10 @@ impl Decodable for GamePad {
 #[derive(Clone,Copy,Debug)]
 pub enum Button {
     Select = 0,
+    /// Left stick button, analog controllers only
+    L3 = 1,
+    /// Right stick button, analog controllers only
+    R3 = 2,
     Start = 3,
     DUp = 4,
     DRight = 5,
@@ -119,6 +185,59 @@ pub enum ButtonState {
     Released,
 }
 
+/// Analog stick axes
+#[derive(Clone,Copy,Debug)]
+pub enum Axis {
+    RightX,
+    RightY,
+    LeftX,
+    LeftY,
+}
+
+/// All the buttons, in bit order
+const BUTTONS: [Button; 16] = [
+    Button::Select, Button::L3, Button::R3, Button::Start,
+    Button::DUp, Button::DRight, Button::DDown, Button::DLeft,
+    Button::L2, Button::R2, Button::L1, Button::R1,
+    Button::Triangle, Button::Circle, Button::Cross, Button::Square,
+];
+
+/// All the axes, in index order
+const AXES: [Axis; 4] = [Axis::RightX, Axis::RightY, Axis::LeftX, Axis::LeftY];
+
+/// State of the force feedback motors of a controller
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Rumble {
+    /// The small motor can only be turned on or off: it's either 0 or
+    /// 0xff
+    pub small: u8,
+    /// Big motor strength
+    pub big: u8,
+}
+
+impl Rumble {
+    pub fn off() -> Rumble {
+        Rumble {
+            small: 0,
+            big: 0,
+        }
+    }
+
+    /// Return the strength of the small and big motors scaled by
+    /// `intensity` (1.0 for the original strength) in the range
+    /// `[0, 0xffff]` used by most host force feedback APIs (SDL2
+    /// haptics, XInput...)
+    pub fn scaled(self, intensity: f32) -> (u16, u16) {
+        let scale = |v: u8| {
+            let v = (v as f32 / 255.) * intensity * 65535.;
+
+            v.max(0.).min(65535.) as u16
+        };
+
+        (scale(self.small), scale(self.big))
+    }
+}
+
 /// Trait used to abstract away the various controller types.
 pub trait Profile {
     /// Handle a command byte sent by the console. `seq` is the byte
@@ -135,6 +254,41 @@ pub trait Profile {
     /// in a row with the same button and the same state, it should be
     /// idempotent.
     fn set_button_state(&mut self, button: Button, state: ButtonState);
+
+    /// For analog controllers: set the position of an axis. 0x80 is
+    /// the center position.
+    fn set_axis_state(&mut self, _: Axis, _: u8) {
+    }
+
+    /// Return the current state of the force feedback motors. The
+    /// frontend is expected to poll it once per frame and forward it
+    /// to the host controller.
+    fn rumble(&self) -> Rumble {
+        Rumble::off()
+    }
+
+    /// For lightguns: set the point the gun is aiming at relative to
+    /// the displayed picture, from `(0.0, 0.0)` for the top-left
+    /// corner to `(1.0, 1.0)` for the bottom-right. `None` if the gun
+    /// isn't pointing at the screen.
+    fn set_aim(&mut self, _: Option<(f32, f32)>) {
+    }
+
+    /// For lightguns: return the point set by `set_aim`
+    fn aim(&self) -> Option<(f32, f32)> {
+        None
+    }
+
+    /// For lightguns: called at the beginning of each transfer with
+    /// the beam position for the current aim
+    fn latch_beam(&mut self, _: Option<BeamPosition>) {
+    }
+
+    /// For lightguns using the lightpen interrupt: return the beam
+    /// position where the interrupt must trigger
+    fn lightpen_target(&self) -> Option<BeamPosition> {
+        None
+    }
 }
 
 /// Dummy profile emulating an empty pad slot
@@ -0,0 +1,145 @@
+//! Lightgun controllers.
+//!
+//! A lightgun works by detecting the video beam when it passes in
+//! front of it. The frontend gives us the point the gun is aiming at
+//! with `Profile::set_aim` (typically from the mouse position) and we
+//! ask the GPU when the beam reaches it at the beginning of each
+//! controller transfer.
+//!
+//! The buttons are mapped on the regular `Button` values matching
+//! their bit position in the serial protocol: `Circle` is the
+//! trigger, `Start` is the A button and `Cross` is the B button (aux
+//! button for the Justifier).
+
+use gpu::BeamPosition;
+
+use super::gamepad::{Profile, Button, ButtonState};
+
+/// Namco GunCon (NPC-103). It latches the beam position itself and
+/// reports it in the serial reply.
+pub struct GunConProfile {
+    buttons: u16,
+    aim: Option<(f32, f32)>,
+    /// Latched beam position, `None` if the gun is pointing
+    /// off-screen
+    beam: Option<BeamPosition>,
+}
+
+impl GunConProfile {
+    pub fn new() -> GunConProfile {
+        GunConProfile {
+            buttons: 0xffff,
+            aim: None,
+            beam: None,
+        }
+    }
+
+    /// Return the X and Y counters reported to the console
+    fn position(&self) -> (u16, u16) {
+        match self.beam {
+            Some(b) => (b.x_8mhz, b.line),
+            // Values returned by the real hardware when the gun
+            // doesn't see the beam
+            None => (0x0001, 0x000a),
+        }
+    }
+}
+
+impl Profile for GunConProfile {
+    fn handle_command(&mut self, seq: u8, cmd: u8) -> (u8, bool) {
+        let (x, y) = self.position();
+
+        match seq {
+            0 => (0xff, (cmd == 0x01)),
+            // Response 0x63: GunCon ID
+            1 => (0x63, (cmd == 0x42)),
+            2 => (0x5a, true),
+            3 => (self.buttons as u8, true),
+            4 => ((self.buttons >> 8) as u8, true),
+            5 => (x as u8, true),
+            6 => ((x >> 8) as u8, true),
+            7 => (y as u8, true),
+            8 => ((y >> 8) as u8, false),
+            _ => (0xff, false),
+        }
+    }
+
+    fn set_button_state(&mut self, button: Button, state: ButtonState) {
+        self.buttons = update_buttons(self.buttons, button, state);
+    }
+
+    fn set_aim(&mut self, aim: Option<(f32, f32)>) {
+        self.aim = aim;
+    }
+
+    fn aim(&self) -> Option<(f32, f32)> {
+        self.aim
+    }
+
+    fn latch_beam(&mut self, beam: Option<BeamPosition>) {
+        self.beam = beam;
+    }
+}
+
+/// Konami Justifier (SLUH-00017). It only reports the buttons over
+/// the serial link, the position is found by the software by
+/// latching the timers when the gun triggers the lightpen interrupt.
+pub struct JustifierProfile {
+    buttons: u16,
+    aim: Option<(f32, f32)>,
+    beam: Option<BeamPosition>,
+}
+
+impl JustifierProfile {
+    pub fn new() -> JustifierProfile {
+        JustifierProfile {
+            buttons: 0xffff,
+            aim: None,
+            beam: None,
+        }
+    }
+}
+
+impl Profile for JustifierProfile {
+    fn handle_command(&mut self, seq: u8, cmd: u8) -> (u8, bool) {
+        match seq {
+            0 => (0xff, (cmd == 0x01)),
+            // Response 0x31: Justifier ID
+            1 => (0x31, (cmd == 0x42)),
+            2 => (0x5a, true),
+            3 => (self.buttons as u8, true),
+            4 => ((self.buttons >> 8) as u8, false),
+            _ => (0xff, false),
+        }
+    }
+
+    fn set_button_state(&mut self, button: Button, state: ButtonState) {
+        self.buttons = update_buttons(self.buttons, button, state);
+    }
+
+    fn set_aim(&mut self, aim: Option<(f32, f32)>) {
+        self.aim = aim;
+    }
+
+    fn aim(&self) -> Option<(f32, f32)> {
+        self.aim
+    }
+
+    fn latch_beam(&mut self, beam: Option<BeamPosition>) {
+        self.beam = beam;
+    }
+
+    fn lightpen_target(&self) -> Option<BeamPosition> {
+        self.beam
+    }
+}
+
+/// Update the active low button bitfield `buttons`
+fn update_buttons(buttons: u16, button: Button, state: ButtonState) -> u16 {
+    let mask = 1 << (button as usize);
+
+    match state {
+        ButtonState::Pressed  => buttons & !mask,
+        ButtonState::Released => buttons | mask,
+    }
+}
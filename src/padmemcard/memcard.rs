@@ -0,0 +1,337 @@
+//! Memory card emulation, including a partial PocketStation.
+//!
+//! Memory cards share the controller ports with the gamepads, they're
+//! addressed by sending 0x81 instead of 0x01 as the first byte of the
+//! transfer. A card holds 1024 sectors of 128 bytes.
+//!
+//! The PocketStation is a memory card with an ARM7 CPU and an LCD
+//! screen. Games detect it and download minigames using extended
+//! commands 0x50 to 0x5f which a regular card rejects. We only
+//! acknowledge those commands so that games detect the device, their
+//! payload is stubbed and the ARM7 side isn't emulated.
+
+/// Type of memory card
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcDecodable, RustcEncodable)]
+pub enum MemoryCardKind {
+    /// Regular 128KB memory card (SCPH-1020)
+    Standard,
+    /// PocketStation (SCPH-4000)
+    PocketStation,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct MemoryCard {
+    kind: MemoryCardKind,
+    /// Card contents
+    data: Vec<u8>,
+    /// Status flag returned with each command. Bit 3 is set when the
+    /// card is inserted and cleared by the first write.
+    flag: u8,
+    /// Position in the current transfer
+    seq: u16,
+    /// Current command
+    command: u8,
+    /// Sector address of the current read or write
+    address: u16,
+    /// Checksum of the current read or write
+    checksum: u8,
+    /// Previous byte received, some replies echo it
+    prev: u8,
+    /// Sector being written
+    buffer: Vec<u8>,
+    /// Set when the contents are modified, the frontend can use it to
+    /// know when to flush the card to disk
+    dirty: bool,
+    /// Number of sectors written so far (wrapping), used to follow
+    /// the write activity
+    write_count: u32,
+}
+
+impl MemoryCard {
+    /// Create a new unformatted memory card
+    pub fn new(kind: MemoryCardKind) -> MemoryCard {
+        MemoryCard {
+            kind: kind,
+            data: vec![0; MEMORY_CARD_SIZE],
+            flag: FLAG_NEW_CARD,
+            seq: 0,
+            command: 0,
+            address: 0,
+            checksum: 0,
+            prev: 0,
+            buffer: Vec::with_capacity(SECTOR_SIZE),
+            dirty: false,
+            write_count: 0,
+        }
+    }
+
+    /// Create a memory card from an existing 128KB image
+    pub fn from_data(kind: MemoryCardKind,
+                     data: Vec<u8>) -> Result<MemoryCard, &'static str> {
+        if data.len() != MEMORY_CARD_SIZE {
+            return Err("Invalid memory card image size");
+        }
+
+        let mut card = MemoryCard::new(kind);
+
+        card.data = data;
+
+        Ok(card)
+    }
+
+    pub fn kind(&self) -> MemoryCardKind {
+        self.kind
+    }
+
+    /// Return the raw contents of the card
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// True if the card has been written to since the last call to
+    /// `clear_dirty`
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    /// Number of sectors written since the card was created. It
+    /// wraps around, compare it with a previous value to detect new
+    /// writes.
+    pub fn write_count(&self) -> u32 {
+        self.write_count
+    }
+
+    /// Called when the "select" line goes down
+    pub fn select(&mut self) {
+        self.seq = 0;
+    }
+
+    /// Handle a command byte, returns the response byte and DSR like
+    /// `GamePad::send_command`
+    pub fn send_command(&mut self, cmd: u8) -> (u8, bool) {
+        let seq = self.seq;
+
+        self.seq = self.seq.saturating_add(1);
+
+        let reply =
+            match seq {
+                0 => (0xff, cmd == 0x81),
+                1 => {
+                    self.command = cmd;
+
+                    let valid =
+                        match cmd {
+                            0x52 | 0x53 | 0x57 => true,
+                            0x50...0x5f =>
+                                self.kind == MemoryCardKind::PocketStation,
+                            _ => false,
+                        };
+
+                    (self.flag, valid)
+                }
+                // Memory card ID
+                2 => (0x5a, true),
+                3 => (0x5d, true),
+                _ => {
+                    let i = seq - 4;
+
+                    match self.command {
+                        0x52 => self.read(i, cmd),
+                        0x53 => self.get_id(i),
+                        0x57 => self.write(i, cmd),
+                        _ => self.pocketstation(i),
+                    }
+                }
+            };
+
+        self.prev = cmd;
+
+        reply
+    }
+
+    fn sector_valid(&self) -> bool {
+        (self.address as usize) < MEMORY_CARD_SIZE / SECTOR_SIZE
+    }
+
+    fn sector_offset(&self) -> usize {
+        self.address as usize * SECTOR_SIZE
+    }
+
+    /// Read command (0x52, 'R')
+    fn read(&mut self, i: u16, cmd: u8) -> (u8, bool) {
+        match i {
+            0 => {
+                self.address = (cmd as u16) << 8;
+                (0x00, true)
+            }
+            1 => {
+                self.address |= cmd as u16;
+                (self.prev, true)
+            }
+            // Command acknowledge
+            2 => (0x5c, true),
+            3 => (0x5d, true),
+            4 => {
+                if !self.sector_valid() {
+                    // Invalid sector, abort
+                    return (0xff, false);
+                }
+
+                let msb = (self.address >> 8) as u8;
+
+                self.checksum = msb;
+
+                (msb, true)
+            }
+            5 => {
+                let lsb = self.address as u8;
+
+                self.checksum ^= lsb;
+
+                (lsb, true)
+            }
+            6...133 => {
+                let b = self.data[self.sector_offset() + (i - 6) as usize];
+
+                self.checksum ^= b;
+
+                (b, true)
+            }
+            134 => (self.checksum, true),
+            // End byte: 'G' for good
+            135 => (0x47, false),
+            _ => (0xff, false),
+        }
+    }
+
+    /// Write command (0x57, 'W')
+    fn write(&mut self, i: u16, cmd: u8) -> (u8, bool) {
+        match i {
+            0 => {
+                self.address = (cmd as u16) << 8;
+                self.checksum = cmd;
+                self.buffer.clear();
+                (0x00, true)
+            }
+            1 => {
+                self.address |= cmd as u16;
+                self.checksum ^= cmd;
+                (self.prev, true)
+            }
+            2...129 => {
+                self.buffer.push(cmd);
+                self.checksum ^= cmd;
+                (self.prev, true)
+            }
+            // Checksum sent by the console
+            130 => {
+                self.checksum ^= cmd;
+                (self.prev, true)
+            }
+            131 => (0x5c, true),
+            132 => (0x5d, true),
+            133 => {
+                let status =
+                    if !self.sector_valid() {
+                        0xff
+                    } else if self.checksum != 0 {
+                        // 'N': bad checksum
+                        0x4e
+                    } else {
+                        let offset = self.sector_offset();
+
+                        self.data[offset..offset + SECTOR_SIZE]
+                            .copy_from_slice(&self.buffer);
+
+                        self.flag &= !FLAG_NEW_CARD;
+                        self.dirty = true;
+                        self.write_count = self.write_count.wrapping_add(1);
+
+                        // 'G': good
+                        0x47
+                    };
+
+                (status, false)
+            }
+            _ => (0xff, false),
+        }
+    }
+
+    /// Get ID command (0x53, 'S'), returns the card geometry
+    fn get_id(&mut self, i: u16) -> (u8, bool) {
+        let id = [0x5c, 0x5d, 0x04, 0x00, 0x00, 0x80];
+
+        match id.get(i as usize) {
+            Some(&b) => (b, (i as usize) < id.len() - 1),
+            None => (0xff, false),
+        }
+    }
+
+    /// Stub for the PocketStation extended commands, we just
+    /// acknowledge the bytes sent by the console
+    fn pocketstation(&mut self, i: u16) -> (u8, bool) {
+        if i < POCKETSTATION_MAX_PAYLOAD {
+            (0x00, i < POCKETSTATION_MAX_PAYLOAD - 1)
+        } else {
+            (0xff, false)
+        }
+    }
+}
+
+/// Size of a memory card in bytes
+pub const MEMORY_CARD_SIZE: usize = 128 * 1024;
+
+/// Size of a memory card sector in bytes
+const SECTOR_SIZE: usize = 128;
+
+/// Flag bit set until the first write to the card
+const FLAG_NEW_CARD: u8 = 0x08;
+
+/// Maximum number of bytes we acknowledge for the stubbed
+/// PocketStation commands
+const POCKETSTATION_MAX_PAYLOAD: u16 = 0x90;
+
+#[test]
+fn write_read() {
+    let mut card = MemoryCard::new(MemoryCardKind::Standard);
+
+    fn transfer(card: &mut MemoryCard, cmds: &[u8]) -> Vec<u8> {
+        card.select();
+
+        cmds.iter().map(|&c| card.send_command(c).0).collect()
+    }
+
+    let sector: Vec<u8> = (0..128).map(|v| v as u8).collect();
+
+    let checksum = sector.iter().fold(0x01 ^ 0x23, |c, &b| c ^ b);
+
+    let mut cmds = vec![0x81, 0x57, 0, 0, 0x01, 0x23];
+    cmds.extend_from_slice(&sector);
+    cmds.extend_from_slice(&[checksum, 0, 0, 0]);
+
+    let r = transfer(&mut card, &cmds);
+
+    assert_eq!(r[1], FLAG_NEW_CARD);
+    assert_eq!(&r[r.len() - 3..], &[0x5c, 0x5d, 0x47]);
+    assert!(card.dirty());
+    assert_eq!(card.write_count(), 1);
+
+    let mut cmds = vec![0x81, 0x52, 0, 0, 0x01, 0x23];
+    cmds.extend_from_slice(&[0; 134]);
+
+    let r = transfer(&mut card, &cmds);
+
+    assert_eq!(r[1], 0);
+    assert_eq!(&r[6..10], &[0x5c, 0x5d, 0x01, 0x23]);
+    assert_eq!(&r[10..138], &sector[..]);
+    assert_eq!(&r[138..], &[checksum, 0x47]);
+
+    // Regular cards don't acknowledge the PocketStation commands
+    card.select();
+    assert_eq!(card.send_command(0x81), (0xff, true));
+    assert_eq!(card.send_command(0x58).1, false);
+}
@@ -5,10 +5,19 @@ use interrupt::Interrupt;
 use timekeeper::{Peripheral, Cycles};
 use shared::SharedState;
 use tracer::module_tracer;
+use gpu::Gpu;
+use logging;
+use events::Event;
 
 use self::gamepad::GamePad;
+use self::multitap::Multitap;
+use self::memcard::MemoryCard;
 
 pub mod gamepad;
+pub mod multitap;
+pub mod lightgun;
+pub mod dualshock;
+pub mod memcard;
 
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct PadMemCard {
@@ -52,12 +61,22 @@ pub struct PadMemCard {
     response: u8,
     /// True when we the RX FIFO is not empty.
     rx_not_empty: bool,
-    /// Gamepad in slot 1
-    pad1: GamePad,
-    /// Gamepad in slot 2
-    pad2: GamePad,
+    /// Controller port 1, possibly with a multitap
+    port1: Multitap,
+    /// Controller port 2, possibly with a multitap
+    port2: Multitap,
     /// Bus state machine
     bus: BusState,
+    /// If true the /ACK pulse follows each byte with the delay and
+    /// duration of the real hardware instead of immediately
+    accurate_timing: bool,
+    /// Delay between the end of the current byte and the /ACK pulse
+    /// when `accurate_timing` is set
+    ack_delay: Cycles,
+    /// When the gamepads apply the input changes
+    input_latch: InputLatch,
+    /// Frame during which the input was last latched
+    latched_frame: Option<u32>,
 }
 
 impl PadMemCard {
@@ -75,14 +94,37 @@ impl PadMemCard {
             dsr_it: false,
             response: 0xff,
             rx_not_empty: false,
-            pad1: GamePad::disconnected(),
-            pad2: GamePad::disconnected(),
+            port1: Multitap::new(),
+            port2: Multitap::new(),
             bus: BusState::Idle,
+            accurate_timing: false,
+            ack_delay: 0,
+            input_latch: InputLatch::Poll,
+            latched_frame: None,
         }
     }
 
+    /// Enable or disable the accurate /ACK timing. Some games are
+    /// sensitive to the delay between the bytes of a transfer.
+    pub fn set_accurate_timing(&mut self, accurate: bool) {
+        self.accurate_timing = accurate;
+    }
+
+    pub fn accurate_timing(&self) -> bool {
+        self.accurate_timing
+    }
+
+    pub fn set_input_latch(&mut self, latch: InputLatch) {
+        self.input_latch = latch;
+    }
+
+    pub fn input_latch(&self) -> InputLatch {
+        self.input_latch
+    }
+
     pub fn store<T: Addressable>(&mut self,
                                  shared: &mut SharedState,
+                                 gpu: &mut Gpu,
                                  offset: u32,
                                  val: u32) {
 
@@ -111,7 +153,7 @@ impl PadMemCard {
                     // Byte access behaves like a halfword
                     panic!("Unhandled byte gamepad control access");
                 }
-                self.set_control(shared, val as u16);
+                self.set_control(shared, gpu, val as u16);
             }
             14 => self.baud_div = val as u16,
             _ => panic!("Unhandled write to gamepad register {} {:04x}",
@@ -187,43 +229,33 @@ impl PadMemCard {
 
                     self.response = r;
                     self.rx_not_empty = true;
-                    self.dsr = dsr;
-
-                    if self.dsr {
-                        if self.dsr_it {
-                            if !self.interrupt {
-                                // Rising edge of the interrupt
-                                let irq_state = shared.irq_state_mut();
-
-                                irq_state.assert(Interrupt::PadMemCard);
-                            }
-
-                            self.interrupt = true;
-                        }
-
-                        // The DSR pulse is generated purely by the
-                        // controller without any input from the
-                        // console. Therefore the actual length of the
-                        // pulse changes from controller to
-                        // controller. I have two seemingly identical
-                        // SCPH-1080 controllers, one pulses the DSR
-                        // line for ~100CPU cycles while the other one
-                        // is slightly faster at around ~90 CPU
-                        // cycles.
-
-                        // XXX Because of timing inaccuracies
-                        // throughout the emulator I can't use the
-                        // proper timing otherwise the BIOS attempts
-                        // to ack the interrupt while DSR is still
-                        // active.
-                        let dsr_duration = 10;
-                        self.bus = BusState::Dsr(dsr_duration);
-                    } else {
+
+                    // Time elapsed since the end of the byte
+                    let elapsed = delta - delay;
+
+                    if !dsr {
                         // We're done with this transaction
                         self.bus = BusState::Idle;
+                        shared.tk().no_sync_needed(Peripheral::PadMemCard);
+                    } else if self.accurate_timing &&
+                        elapsed < self.ack_delay {
+                        let delay = self.ack_delay - elapsed;
+
+                        self.bus = BusState::AckDelay(delay);
+                        self.schedule_ack(shared, delay);
+                    } else {
+                        self.start_ack(shared);
                     }
+                }
+            }
+            BusState::AckDelay(delay) => {
+                if delta < delay {
+                    let delay = delay - delta;
 
-                    shared.tk().no_sync_needed(Peripheral::PadMemCard);
+                    self.bus = BusState::AckDelay(delay);
+                    self.schedule_ack(shared, delay);
+                } else {
+                    self.start_ack(shared);
                 }
             }
             BusState::Dsr(delay) => {
@@ -240,9 +272,160 @@ impl PadMemCard {
         }
     }
 
-    /// Return a mutable reference to the gamepad profiles being used.
+    /// Sync at the start of the /ACK pulse if it triggers an interrupt
+    fn schedule_ack(&mut self, shared: &mut SharedState, delay: Cycles) {
+        if self.dsr_it {
+            shared.tk().set_next_sync_delta(Peripheral::PadMemCard, delay);
+        } else {
+            shared.tk().no_sync_needed(Peripheral::PadMemCard);
+        }
+    }
+
+    /// The targeted device asserts /ACK (DSR)
+    fn start_ack(&mut self, shared: &mut SharedState) {
+        self.dsr = true;
+
+        if self.dsr_it {
+            if !self.interrupt {
+                // Rising edge of the interrupt
+                let irq_state = shared.irq_state_mut();
+
+                irq_state.assert(Interrupt::PadMemCard);
+            }
+
+            self.interrupt = true;
+        }
+
+        // The DSR pulse is generated purely by the controller without
+        // any input from the console. Therefore the actual length of
+        // the pulse changes from controller to controller. I have two
+        // seemingly identical SCPH-1080 controllers, one pulses the
+        // DSR line for ~100CPU cycles while the other one is slightly
+        // faster at around ~90 CPU cycles.
+        let dsr_duration =
+            if self.accurate_timing {
+                ACK_PULSE_CYCLES
+            } else {
+                // XXX Because of timing inaccuracies throughout the
+                // emulator I can't use the proper timing otherwise
+                // the BIOS attempts to ack the interrupt while DSR is
+                // still active.
+                10
+            };
+
+        self.bus = BusState::Dsr(dsr_duration);
+
+        shared.tk().no_sync_needed(Peripheral::PadMemCard);
+    }
+
+    /// Apply the queued input changes of the gamepads connected to
+    /// `port` at the start of a transfer, depending on `input_latch`
+    fn latch_input(&mut self, shared: &SharedState, port: usize) {
+        match self.input_latch {
+            InputLatch::Poll => self.port_mut(port).latch_input(),
+            InputLatch::Frame => {
+                let frame = shared.frame();
+
+                if self.latched_frame != Some(frame) {
+                    self.latched_frame = Some(frame);
+                    self.port1.latch_input();
+                    self.port2.latch_input();
+                }
+            }
+        }
+    }
+
+    /// Reset the controller to its power-on state. The gamepads
+    /// remain connected.
+    pub fn reset(&mut self) {
+        let mut fresh = PadMemCard::new();
+
+        ::std::mem::swap(&mut fresh.port1, &mut self.port1);
+        ::std::mem::swap(&mut fresh.port2, &mut self.port2);
+
+        fresh.port1.reset();
+        fresh.port2.reset();
+
+        *self = fresh;
+    }
+
+    /// Return a mutable reference to the gamepads connected directly
+    /// to the ports (or in slot A if a multitap is connected)
     pub fn gamepads_mut(&mut self) -> [&mut GamePad; 2] {
-        [ &mut self.pad1, &mut self.pad2 ]
+        [ self.port1.gamepad_mut(0), self.port2.gamepad_mut(0) ]
+    }
+
+    /// Return the gamepad in `slot` (0 to 3 for multitap slots A to
+    /// D) of `port` (0 or 1)
+    pub fn gamepad_mut(&mut self, port: usize, slot: usize) -> &mut GamePad {
+        self.port_mut(port).gamepad_mut(slot)
+    }
+
+    /// Insert or remove a memory card in `port` (0 or 1)
+    pub fn set_memory_card(&mut self,
+                           port: usize,
+                           card: Option<MemoryCard>) {
+        self.port_mut(port).set_memory_card(card)
+    }
+
+    pub fn memory_card_mut(&mut self, port: usize) -> Option<&mut MemoryCard> {
+        self.port_mut(port).memory_card_mut()
+    }
+
+    /// Return the `write_count` of the memory card in `port`, if any
+    fn memory_card_writes(&mut self, port: usize) -> Option<u32> {
+        self.memory_card_mut(port).map(|card| card.write_count())
+    }
+
+    /// Plug or unplug a multitap in `port` (0 or 1)
+    pub fn set_multitap(&mut self, port: usize, connected: bool) {
+        self.port_mut(port).set_connected(connected)
+    }
+
+    pub fn multitap(&self, port: usize) -> bool {
+        match port {
+            0 => self.port1.connected(),
+            1 => self.port2.connected(),
+            _ => panic!("Invalid controller port {}", port),
+        }
+    }
+
+    /// Latch the beam position for the lightguns connected to the
+    /// targeted port at the beginning of a transfer and update the
+    /// lightpen interrupt target
+    fn latch_lightguns(&mut self, shared: &mut SharedState, gpu: &mut Gpu) {
+        let port = self.target as usize;
+
+        for slot in 0..4 {
+            let profile = self.gamepad_mut(port, slot).profile_mut();
+
+            let beam = profile.aim().and_then(|(x, y)| gpu.beam_position(x, y));
+
+            profile.latch_beam(beam);
+        }
+
+        let mut lightpen = None;
+
+        for port in 0..2 {
+            for slot in 0..4 {
+                if lightpen.is_none() {
+                    lightpen =
+                        self.gamepad_mut(port, slot)
+                        .profile_mut()
+                        .lightpen_target();
+                }
+            }
+        }
+
+        gpu.set_lightpen(shared, lightpen);
+    }
+
+    fn port_mut(&mut self, port: usize) -> &mut Multitap {
+        match port {
+            0 => &mut self.port1,
+            1 => &mut self.port2,
+            _ => panic!("Invalid controller port {}", port),
+        }
     }
 
     fn send_command(&mut self, shared: &mut SharedState, cmd: u8) {
@@ -254,15 +437,34 @@ impl PadMemCard {
 
         if self.bus.is_busy() {
             // I suppose the transfer should be queued in the TX FIFO?
-            warn!("Gamepad command {:x} while bus is busy!", cmd);
+            warn!(target: logging::PAD_MEMCARD,
+                  "Gamepad command {:x} while bus is busy!", cmd);
         }
 
         let (response, dsr) =
             if self.select {
-                match self.target {
-                    Target::PadMemCard1 => self.pad1.send_command(cmd),
-                    Target::PadMemCard2 => self.pad2.send_command(cmd),
+                let port =
+                    match self.target {
+                        Target::PadMemCard1 => 0,
+                        Target::PadMemCard2 => 1,
+                    };
+
+                let writes = self.memory_card_writes(port);
+
+                let reply = self.port_mut(port).send_command(cmd);
+
+                if self.memory_card_writes(port) != writes {
+                    shared.push_event(Event::MemoryCardWrite(port));
                 }
+
+                self.ack_delay =
+                    if self.port_mut(port).memory_card_transfer() {
+                        MEMORY_CARD_ACK_DELAY
+                    } else {
+                        CONTROLLER_ACK_DELAY
+                    };
+
+                reply
             } else {
                 // No response
                 (0xff, false)
@@ -275,8 +477,8 @@ impl PadMemCard {
 
         self.bus = BusState::Transfer(response, dsr, tx_duration);
 
-        // XXX For now pretend that the DSR pulse follows
-        // immediately after the last byte, probably not accurate.
+        // Without `accurate_timing` the DSR pulse follows immediately
+        // after the last bit
         shared.tk().set_next_sync_delta(Peripheral::PadMemCard, tx_duration);
     }
 
@@ -316,7 +518,10 @@ impl PadMemCard {
         ctrl
     }
 
-    fn set_control(&mut self, shared: &mut SharedState, ctrl: u16) {
+    fn set_control(&mut self,
+                   shared: &mut SharedState,
+                   gpu: &mut Gpu,
+                   ctrl: u16) {
         if ctrl & 0x40 != 0 {
             // Soft reset
             self.baud_div = 0;
@@ -348,7 +553,8 @@ impl PadMemCard {
                     // which will be seen by the edge-triggered top
                     // level interrupt controller. So I guess this
                     // shouldn't happen?
-                    warn!("Gamepad interrupt acknowledge while DSR is active");
+                    warn!(target: logging::PAD_MEMCARD,
+                          "Gamepad interrupt acknowledge while DSR is active");
 
                     self.interrupt = true;
                     shared.irq_state_mut().assert(Interrupt::PadMemCard);
@@ -382,10 +588,17 @@ impl PadMemCard {
             }
 
             if !prev_select && self.select {
-                // XXX Should probably also check self.target, not
-                // sure how it influences the select line. I assume
-                // only the targeted slot is selected?
-                self.pad1.select();
+                let port = self.target as usize;
+
+                self.latch_input(shared, port);
+
+                // XXX I assume only the targeted port is selected?
+                match self.target {
+                    Target::PadMemCard1 => self.port1.select(),
+                    Target::PadMemCard2 => self.port2.select(),
+                }
+
+                self.latch_lightguns(shared, gpu);
             }
         }
     }
@@ -408,6 +621,20 @@ impl Target {
     }
 }
 
+/// When the gamepads apply the input changes queued with
+/// `GamePad::set_button_state` and `GamePad::set_axis_state`. Changes
+/// made directly on the profile bypass the latch.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcDecodable, RustcEncodable)]
+pub enum InputLatch {
+    /// At the start of every transfer, like the real controllers:
+    /// the game sees the latest input every time it polls
+    Poll,
+    /// At the start of the first transfer of each frame: all the polls
+    /// within a frame see the same input, which makes the replays
+    /// independent of how often a game polls
+    Frame,
+}
+
 /// Controller transaction state machine
 #[derive(Debug, RustcDecodable, RustcEncodable)]
 enum BusState {
@@ -417,6 +644,8 @@ enum BusState {
     /// response and the number of Cycles remaining until we reach the
     /// DSR pulse (if any)
     Transfer(u8, bool, Cycles),
+    /// Transfer done, waiting for the device to assert DSR (/ACK)
+    AckDelay(Cycles),
     /// DSR is asserted, count the number of cycles remaining.
     Dsr(Cycles),
 }
@@ -429,3 +658,105 @@ impl BusState {
         }
     }
 }
+
+/// Approximate delay between the end of a byte and the /ACK pulse of
+/// a controller, in CPU cycles
+const CONTROLLER_ACK_DELAY: Cycles = 450;
+
+/// Memory cards reply faster than the controllers
+const MEMORY_CARD_ACK_DELAY: Cycles = 170;
+
+/// Duration of the /ACK pulse, see `start_ack`
+const ACK_PULSE_CYCLES: Cycles = 100;
+
+#[test]
+fn ack_timing() {
+    use memory::{Byte, HalfWord};
+    use gpu::VideoClock;
+    use self::gamepad::DigitalProfile;
+
+    let mut shared = SharedState::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut pad = PadMemCard::new();
+
+    pad.gamepad_mut(0, 0).set_profile(Box::new(DigitalProfile::new()));
+    pad.set_accurate_timing(true);
+
+    pad.store::<HalfWord>(&mut shared, &mut gpu, 14, 0x88);
+    // TX enable, select, DSR interrupt
+    pad.store::<HalfWord>(&mut shared, &mut gpu, 10, 0x1003);
+    pad.store::<Byte>(&mut shared, &mut gpu, 0, 0x01);
+
+    // The byte has been transferred but the pad hasn't acknowledged
+    // it yet
+    shared.tk().tick(8 * 0x88);
+    pad.sync(&mut shared);
+
+    assert!(pad.stat() & 0x2 != 0);
+    assert!(pad.stat() & 0x80 == 0);
+
+    shared.tk().tick(CONTROLLER_ACK_DELAY);
+    pad.sync(&mut shared);
+
+    assert!(pad.stat() & 0x80 != 0);
+    assert!(shared.irq_state().status() & (1 << 7) != 0);
+
+    // End of the pulse
+    shared.tk().tick(ACK_PULSE_CYCLES);
+    pad.sync(&mut shared);
+
+    assert!(pad.stat() & 0x80 == 0);
+}
+
+#[test]
+fn input_latch() {
+    use memory::HalfWord;
+    use gpu::VideoClock;
+    use self::gamepad::{DigitalProfile, Button, ButtonState};
+
+    /// Select the pad in port 1 (which latches its input) and read
+    /// the button state. The bits are active low.
+    fn poll(pad: &mut PadMemCard,
+            shared: &mut SharedState,
+            gpu: &mut Gpu) -> u16 {
+        pad.store::<HalfWord>(shared, gpu, 10, 0);
+        pad.store::<HalfWord>(shared, gpu, 10, 2);
+
+        let gamepad = pad.gamepad_mut(0, 0);
+
+        gamepad.select();
+
+        let r: Vec<u8> =
+            [0x01, 0x42, 0, 0, 0].iter()
+            .map(|&c| gamepad.send_command(c).0)
+            .collect();
+
+        r[3] as u16 | (r[4] as u16) << 8
+    }
+
+    let cross = 1 << (Button::Cross as usize);
+
+    for &latch in &[InputLatch::Poll, InputLatch::Frame] {
+        let mut shared = SharedState::new();
+        let mut gpu = Gpu::new(VideoClock::Ntsc);
+        let mut pad = PadMemCard::new();
+
+        pad.gamepad_mut(0, 0).set_profile(Box::new(DigitalProfile::new()));
+        pad.set_input_latch(latch);
+
+        assert_eq!(poll(&mut pad, &mut shared, &mut gpu) & cross, cross);
+
+        // Mid-frame change, only seen right away when polling
+        pad.gamepad_mut(0, 0)
+            .set_button_state(Button::Cross, ButtonState::Pressed);
+
+        let pressed = poll(&mut pad, &mut shared, &mut gpu) & cross == 0;
+
+        assert_eq!(pressed, latch == InputLatch::Poll);
+
+        // Both see it in the next frame
+        shared.counters_mut().frame.increment();
+
+        assert_eq!(poll(&mut pad, &mut shared, &mut gpu) & cross, 0);
+    }
+}
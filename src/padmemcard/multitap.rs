@@ -0,0 +1,244 @@
+//! SCPH-1070 Multitap emulation.
+//!
+//! The multitap plugs into a controller port and connects up to four
+//! controllers to it. By default it's transparent: transfers are
+//! forwarded to the controller in slot A. If the third byte of a
+//! controller transfer is 0x01 the *next* transfer returns the state
+//! of the four controllers in a single long frame:
+//!
+//! ```text
+//! Send  Reply
+//! 01h   Hi-Z   Controller address
+//! 42h   80h    Multitap ID lo
+//! xxh   5Ah    Multitap ID hi, 01h to stay in multitap mode
+//! ...          4 x 8 bytes: the reply of each controller to the
+//!              command bytes sent by the console, padded with FFh
+//! ```
+
+use super::gamepad::GamePad;
+use super::memcard::MemoryCard;
+
+/// A controller port, with or without a multitap
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Multitap {
+    /// True if the multitap is plugged in. Otherwise only slot A is
+    /// used and it's connected directly to the port.
+    connected: bool,
+    /// Controllers in slots A to D
+    pads: Vec<GamePad>,
+    /// Memory card in slot A. The multitap slots B to D don't support
+    /// memory cards for now.
+    memory_card: Option<MemoryCard>,
+    /// True if the current transfer addresses the memory card
+    memory_card_transfer: bool,
+    /// True if the current transfer returns the four slots
+    full_mode: bool,
+    /// Value of `full_mode` for the next transfer
+    next_full_mode: bool,
+    /// Position in the current transfer
+    seq: u8,
+    /// False if we're done processing the current transfer
+    active: bool,
+}
+
+impl Multitap {
+    /// Create a port without multitap and with four disconnected
+    /// gamepads
+    pub fn new() -> Multitap {
+        Multitap {
+            connected: false,
+            pads: (0..4).map(|_| GamePad::disconnected()).collect(),
+            memory_card: None,
+            memory_card_transfer: false,
+            full_mode: false,
+            next_full_mode: false,
+            seq: 0,
+            active: true,
+        }
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Plug or unplug the multitap. When the multitap is unplugged
+    /// the gamepad in slot A is connected directly to the port.
+    pub fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+        self.reset();
+    }
+
+    /// Return the gamepad in `slot` (0 to 3 for slots A to D)
+    pub fn gamepad_mut(&mut self, slot: usize) -> &mut GamePad {
+        &mut self.pads[slot]
+    }
+
+    /// Apply the queued input changes of the four gamepads
+    pub fn latch_input(&mut self) {
+        for pad in &mut self.pads {
+            pad.latch_input();
+        }
+    }
+
+    /// True if the current transfer addresses the memory card
+    pub fn memory_card_transfer(&self) -> bool {
+        self.memory_card_transfer
+    }
+
+    /// Insert or remove a memory card
+    pub fn set_memory_card(&mut self, card: Option<MemoryCard>) {
+        self.memory_card = card;
+    }
+
+    pub fn memory_card_mut(&mut self) -> Option<&mut MemoryCard> {
+        self.memory_card.as_mut()
+    }
+
+    /// Remove the memory card and return it
+    pub fn take_memory_card(&mut self) -> Option<MemoryCard> {
+        self.memory_card.take()
+    }
+
+    /// Reset the protocol state, the gamepads remain connected
+    pub fn reset(&mut self) {
+        self.full_mode = false;
+        self.next_full_mode = false;
+        self.seq = 0;
+        self.active = true;
+    }
+
+    /// Called when the "select" line goes down
+    pub fn select(&mut self) {
+        self.seq = 0;
+        self.active = true;
+        self.full_mode = self.next_full_mode;
+        self.memory_card_transfer = false;
+
+        self.pads[0].select();
+
+        if let Some(ref mut card) = self.memory_card {
+            card.select();
+        }
+    }
+
+    /// Handle a command byte, returns the response byte and DSR like
+    /// `GamePad::send_command`
+    pub fn send_command(&mut self, cmd: u8) -> (u8, bool) {
+        if !self.active {
+            return (0xff, false);
+        }
+
+        let seq = self.seq;
+
+        self.seq = self.seq.saturating_add(1);
+
+        if seq == 0 {
+            // 0x81 addresses the memory card
+            self.memory_card_transfer = cmd == 0x81;
+        }
+
+        if seq == 2 && self.connected && !self.memory_card_transfer {
+            self.next_full_mode = cmd == 0x01;
+        }
+
+        let (resp, dsr) =
+            if self.memory_card_transfer {
+                match self.memory_card {
+                    Some(ref mut card) => card.send_command(cmd),
+                    None => (0xff, false),
+                }
+            } else if self.connected && self.full_mode {
+                self.full_command(seq, cmd)
+            } else {
+                self.pads[0].send_command(cmd)
+            };
+
+        self.active = dsr;
+
+        (resp, dsr)
+    }
+
+    /// Handle a byte of a transfer returning the four slots
+    fn full_command(&mut self, seq: u8, cmd: u8) -> (u8, bool) {
+        match seq {
+            // We only handle controllers, not memory cards
+            0 => (0xff, cmd == 0x01),
+            1 => (0x80, cmd == 0x42),
+            2 => (0x5a, true),
+            _ => {
+                let pos = seq as usize - 3;
+                let slot = pos / SLOT_FRAME_LEN;
+                let byte = pos % SLOT_FRAME_LEN;
+
+                let last = slot == 3 && byte == SLOT_FRAME_LEN - 1;
+
+                if slot > 3 {
+                    return (0xff, false);
+                }
+
+                let pad = &mut self.pads[slot];
+
+                if byte == 0 {
+                    // Address the controller in this slot
+                    pad.select();
+                    pad.send_command(0x01);
+                }
+
+                // Controllers with shorter replies are padded with
+                // 0xff
+                let (resp, _) = pad.send_command(cmd);
+
+                (resp, !last)
+            }
+        }
+    }
+}
+
+/// Number of bytes returned for each slot in multitap mode
+const SLOT_FRAME_LEN: usize = 8;
+
+#[test]
+fn four_slots() {
+    use super::gamepad::{DigitalProfile, Profile, Button, ButtonState};
+
+    let mut tap = Multitap::new();
+
+    tap.set_connected(true);
+
+    for slot in 0..3 {
+        let mut profile = DigitalProfile::new();
+
+        if slot == 1 {
+            profile.set_button_state(Button::Cross, ButtonState::Pressed);
+        }
+
+        tap.gamepad_mut(slot).set_profile(Box::new(profile));
+    }
+
+    fn transfer(tap: &mut Multitap, cmds: &[u8]) -> Vec<u8> {
+        tap.select();
+
+        cmds.iter().map(|&c| tap.send_command(c).0).collect()
+    }
+
+    // Direct access to slot A, request multitap mode for the next
+    // transfer
+    let r = transfer(&mut tap, &[0x01, 0x42, 0x01, 0x00, 0x00]);
+    assert_eq!(r, [0xff, 0x41, 0x5a, 0xff, 0xff]);
+
+    let mut cmds = vec![0x01, 0x42, 0x01];
+
+    for _ in 0..4 {
+        cmds.extend_from_slice(&[0x42, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    let r = transfer(&mut tap, &cmds);
+
+    assert_eq!(&r[0..3], &[0xff, 0x80, 0x5a]);
+    // Slot A
+    assert_eq!(&r[3..11], &[0x41, 0x5a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    // Slot B, cross pressed
+    assert_eq!(&r[11..15], &[0x41, 0x5a, 0xff, 0xbf]);
+    // Slot D is disconnected
+    assert_eq!(&r[27..35], &[0xff; 8]);
+}
@@ -0,0 +1,215 @@
+//! Emulation of the Action Replay/GameShark/Xplorer family of cheat
+//! cartridges plugged in the Parallel I/O port.
+//!
+//! The cartridge maps its ROM at the start of the expansion 1 region.
+//! The ROM starts with a header the BIOS looks for during boot: if
+//! the "Licensed by Sony Computer Entertainment Inc." string is found
+//! the BIOS calls the pre-boot entry point at 0x1f000080 (before the
+//! kernel is initialized) and the post-boot entry point at 0x1f000000
+//! (just before the boot animation). The cartridge code uses those to
+//! install its own exception handler and sets up the COP0 hardware
+//! breakpoints to regain control periodically once the game runs,
+//! that's where the cheat codes are applied. The COP0 breakpoints are
+//! emulated by the CPU so we only need to provide the ROM and the few
+//! I/O registers of the cartridge.
+//!
+//! The switch on top of the cartridge selects between booting into
+//! the cartridge's menu (on) or straight into the game with the
+//! cheats enabled (off), the ROM code reads its state through a
+//! register.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use bios::Bios;
+use assembler::Assembler;
+use assembler::syntax::*;
+use shared::SharedState;
+use logging;
+
+use super::ParallelIoModule;
+
+pub struct ActionReplay {
+    /// Cartridge ROM contents
+    rom: Vec<u8>,
+    /// State of the switch on the cartridge
+    switch_on: bool,
+}
+
+impl ActionReplay {
+    /// Create a cartridge using `rom` as its ROM image. The switch is
+    /// initially on.
+    pub fn new(rom: Vec<u8>) -> Result<ActionReplay, Error> {
+        let len = rom.len();
+
+        if len < ROM_HEADER_LEN || len > ROM_MAX_LEN {
+            return Err(Error::BadRomSize(len));
+        }
+
+        let has_id = [POST_BOOT_ID_OFFSET, PRE_BOOT_ID_OFFSET].iter()
+            .any(|&o| rom[o..].starts_with(LICENSE_ID));
+
+        if !has_id {
+            // Some dumps or homebrew ROMs might only be meant to be
+            // called through `patch_bios`
+            warn!(target: logging::PARALLEL_IO,
+                  "No license string in the cartridge ROM header, \
+                   the BIOS won't call the entry points");
+        }
+
+        Ok(ActionReplay {
+            rom: rom,
+            switch_on: true,
+        })
+    }
+
+    /// Load the cartridge ROM from the file at `path`
+    pub fn load_file(path: &Path) -> Result<ActionReplay, Error> {
+        let mut file = try!(File::open(path));
+
+        let mut rom = Vec::new();
+
+        // Read one byte more than the max size to detect oversized
+        // images
+        try!(file.take(ROM_MAX_LEN as u64 + 1).read_to_end(&mut rom));
+
+        ActionReplay::new(rom)
+    }
+
+    /// Change the position of the switch on the cartridge. The ROM
+    /// code only reads it during boot so it has to be set before the
+    /// console is started (or reset).
+    pub fn set_switch(&mut self, on: bool) {
+        self.switch_on = on;
+    }
+
+    pub fn switch(&self) -> bool {
+        self.switch_on
+    }
+
+    /// Patch the BIOS animation jump to call the cartridge's pre-boot
+    /// entry point instead. Not needed with an unmodified BIOS which
+    /// calls the entry points itself, but some patched "fast boot"
+    /// BIOS images skip the expansion check altogether. Returns an
+    /// error if the patching failed.
+    pub fn patch_bios(&self, bios: &mut Bios) -> Result<(), ()> {
+        let mut asm = Assembler::from_base(0);
+
+        let instruction = Jal(Label::Absolute(PRE_BOOT_ENTRY_ADDRESS));
+
+        asm.assemble(&[instruction]).unwrap();
+
+        let (mc, _) = asm.machine_code();
+
+        assert!(mc.len() == 4);
+
+        let instruction = mc[0] as u32
+            | ((mc[1] as u32) << 8)
+            | ((mc[2] as u32) << 16)
+            | ((mc[3] as u32) << 24);
+
+        bios.patch_animation_jump_hook(instruction)
+    }
+}
+
+impl ParallelIoModule for ActionReplay {
+    fn load(&mut self, _: &mut SharedState, offset: u32) -> u8 {
+        match offset {
+            SWITCH_OFFSET => self.switch_on as u8,
+            // The PC communication link isn't emulated, pretend that
+            // nothing is connected
+            COMMS_DATA_IN_OFFSET => 0,
+            _ => *self.rom.get(offset as usize).unwrap_or(&!0),
+        }
+    }
+
+    fn store(&mut self, _: &mut SharedState, offset: u32, val: u8) {
+        match offset {
+            // Strobe and data out for the PC communication link
+            STROBE_OFFSET | COMMS_DATA_OUT_OFFSET => (),
+            _ => {
+                // Writes to the flash ROM (the cartridges store the
+                // user's codes there). We don't support flashing, the
+                // codes are lost at power off.
+                debug!(target: logging::PARALLEL_IO,
+                       "Ignored write to cartridge ROM: {:05x} = {:02x}",
+                       offset, val);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error while reading the ROM file
+    IoError(io::Error),
+    /// The ROM image has an unexpected size
+    BadRomSize(usize),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+/// String the BIOS looks for in the expansion ROM header
+const LICENSE_ID: &'static [u8] =
+    b"Licensed by Sony Computer Entertainment Inc.";
+
+/// Offset of the license string following the post-boot entry point
+const POST_BOOT_ID_OFFSET: usize = 0x04;
+
+/// Offset of the license string following the pre-boot entry point
+const PRE_BOOT_ID_OFFSET: usize = 0x84;
+
+/// The ROM must at least contain both entry points and their license
+/// strings
+const ROM_HEADER_LEN: usize = 0x100;
+
+/// Largest ROM size used by the cartridges (256KB)
+const ROM_MAX_LEN: usize = 256 * 1024;
+
+/// Absolute address of the pre-boot entry point
+const PRE_BOOT_ENTRY_ADDRESS: u32 = ::memory::map::EXPANSION_1.0 + 0x80;
+
+/// Strobe for the PC communication link (write only)
+const STROBE_OFFSET: u32 = 0x20010;
+
+/// Switch state in bit 0 (read only)
+const SWITCH_OFFSET: u32 = 0x20018;
+
+/// PC communication link data in (read only)
+const COMMS_DATA_IN_OFFSET: u32 = 0x60000;
+
+/// PC communication link data out (write only)
+const COMMS_DATA_OUT_OFFSET: u32 = 0x60008;
+
+#[test]
+fn cartridge_registers() {
+    let mut shared = SharedState::new();
+
+    let mut rom = vec![0; 0x20000];
+
+    rom[0] = 0x12;
+    rom[PRE_BOOT_ID_OFFSET..PRE_BOOT_ID_OFFSET + LICENSE_ID.len()]
+        .copy_from_slice(LICENSE_ID);
+
+    let mut ar = ActionReplay::new(rom).unwrap();
+
+    assert_eq!(ar.load(&mut shared, 0), 0x12);
+    assert_eq!(ar.load(&mut shared, PRE_BOOT_ID_OFFSET as u32), b'L');
+    // Past the end of the ROM
+    assert_eq!(ar.load(&mut shared, 0x30000), 0xff);
+
+    assert_eq!(ar.load(&mut shared, SWITCH_OFFSET), 1);
+    ar.set_switch(false);
+    assert_eq!(ar.load(&mut shared, SWITCH_OFFSET), 0);
+
+    // The ROM isn't writable
+    ar.store(&mut shared, 0, 0x34);
+    assert_eq!(ar.load(&mut shared, 0), 0x12);
+
+    assert!(ActionReplay::new(vec![0; 0x10]).is_err());
+}
@@ -0,0 +1,184 @@
+//! Minimal parser for the 32bit little endian MIPS ELF executables
+//! produced by homebrew toolchains (PSn00bSDK, GCC...)
+
+use debugger::symbols::SymbolTable;
+
+/// Loadable segment
+pub struct Segment {
+    /// Load address
+    pub vaddr: u32,
+    /// Contents of the segment present in the file
+    pub data: Vec<u8>,
+    /// Size of the segment in memory. If it's bigger than
+    /// `data.len()` the rest must be filled with zeroes (.bss).
+    pub mem_size: u32,
+}
+
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    /// Function and object symbols
+    pub symbols: SymbolTable,
+    /// Value of the `_gp` symbol if there's one
+    pub gp: Option<u32>,
+}
+
+impl Elf {
+    /// Return true if `data` starts with the ELF magic
+    pub fn is_elf(data: &[u8]) -> bool {
+        data.starts_with(b"\x7fELF")
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Elf, &'static str> {
+        if !Elf::is_elf(data) {
+            return Err("bad magic");
+        }
+
+        if try!(read_u8(data, 4)) != 1 {
+            return Err("not a 32bit ELF");
+        }
+
+        if try!(read_u8(data, 5)) != 1 {
+            return Err("not a little endian ELF");
+        }
+
+        // EM_MIPS
+        if try!(read_u16(data, 18)) != 8 {
+            return Err("not a MIPS executable");
+        }
+
+        let entry = try!(read_u32(data, 24));
+
+        let mut elf = Elf {
+            entry: entry,
+            segments: Vec::new(),
+            symbols: SymbolTable::new(),
+            gp: None,
+        };
+
+        try!(elf.parse_segments(data));
+        try!(elf.parse_symbols(data));
+
+        Ok(elf)
+    }
+
+    fn parse_segments(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let phoff = try!(read_u32(data, 28)) as usize;
+        let phentsize = try!(read_u16(data, 42)) as usize;
+        let phnum = try!(read_u16(data, 44)) as usize;
+
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+
+            // PT_LOAD
+            if try!(read_u32(data, ph)) != 1 {
+                continue;
+            }
+
+            let offset = try!(read_u32(data, ph + 4)) as usize;
+            let vaddr = try!(read_u32(data, ph + 8));
+            let file_size = try!(read_u32(data, ph + 16)) as usize;
+            let mem_size = try!(read_u32(data, ph + 20));
+
+            let contents =
+                match data.get(offset..offset + file_size) {
+                    Some(c) => c,
+                    None => return Err("segment out of bounds"),
+                };
+
+            self.segments.push(Segment {
+                vaddr: vaddr,
+                data: contents.into(),
+                mem_size: mem_size,
+            });
+        }
+
+        if self.segments.is_empty() {
+            return Err("no loadable segment");
+        }
+
+        Ok(())
+    }
+
+    fn parse_symbols(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let shoff = try!(read_u32(data, 32)) as usize;
+        let shentsize = try!(read_u16(data, 46)) as usize;
+        let shnum = try!(read_u16(data, 48)) as usize;
+
+        for i in 0..shnum {
+            let sh = shoff + i * shentsize;
+
+            // SHT_SYMTAB
+            if try!(read_u32(data, sh + 4)) != 2 {
+                continue;
+            }
+
+            let offset = try!(read_u32(data, sh + 16)) as usize;
+            let size = try!(read_u32(data, sh + 20)) as usize;
+            let link = try!(read_u32(data, sh + 24)) as usize;
+
+            // Associated string table
+            let strtab_sh = shoff + link * shentsize;
+            let strtab = try!(read_u32(data, strtab_sh + 16)) as usize;
+
+            for s in 0..size / 16 {
+                let sym = offset + s * 16;
+
+                let name = try!(read_u32(data, sym)) as usize;
+                let value = try!(read_u32(data, sym + 4));
+                let sym_size = try!(read_u32(data, sym + 8));
+                let info = try!(read_u8(data, sym + 12));
+
+                let name = try!(read_str(data, strtab + name));
+
+                if name == "_gp" {
+                    self.gp = Some(value);
+                }
+
+                match info & 0xf {
+                    // STT_OBJECT | STT_FUNC
+                    1 | 2 if !name.is_empty() =>
+                        self.symbols.add(value, sym_size, name),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_u8(data: &[u8], off: usize) -> Result<u8, &'static str> {
+    match data.get(off) {
+        Some(&b) => Ok(b),
+        None => Err("truncated file"),
+    }
+}
+
+fn read_u16(data: &[u8], off: usize) -> Result<u16, &'static str> {
+    let lo = try!(read_u8(data, off)) as u16;
+    let hi = try!(read_u8(data, off + 1)) as u16;
+
+    Ok(lo | (hi << 8))
+}
+
+fn read_u32(data: &[u8], off: usize) -> Result<u32, &'static str> {
+    let lo = try!(read_u16(data, off)) as u32;
+    let hi = try!(read_u16(data, off + 2)) as u32;
+
+    Ok(lo | (hi << 16))
+}
+
+/// Read a NUL-terminated string
+fn read_str(data: &[u8], off: usize) -> Result<String, &'static str> {
+    let s =
+        match data.get(off..) {
+            Some(s) => s,
+            None => return Err("string out of bounds"),
+        };
+
+    match s.iter().position(|&b| b == 0) {
+        Some(len) => Ok(String::from_utf8_lossy(&s[0..len]).into_owned()),
+        None => Err("unterminated string"),
+    }
+}
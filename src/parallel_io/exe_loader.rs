@@ -4,15 +4,18 @@
 
 use std::path::Path;
 use std::fs::File;
-use std::io;
+use std::io::{self, Read};
 
 use cdrom::disc::Region;
 use bios::Bios;
 use assembler::Assembler;
 use assembler::syntax::*;
 use shared::SharedState;
+use debugger::symbols::SymbolTable;
+use logging;
 
 use super::ParallelIoModule;
+use super::elf::Elf;
 
 pub struct ExeLoader {
     /// Base address for the executable
@@ -36,6 +39,8 @@ pub struct ExeLoader {
     text_index: usize,
     /// Code of the loader
     loader: Vec<u8>,
+    /// Symbols found in the executable (only available for ELF files)
+    symbols: SymbolTable,
 }
 
 impl ExeLoader {
@@ -114,9 +119,11 @@ impl ExeLoader {
             text: text,
             text_index: 0,
             loader: Vec::new(),
+            symbols: SymbolTable::new(),
         };
 
-        info!("Loaded PS-EXE: BASE=0x{:08x} ENTRY=0x{:08x} LEN={}",
+        info!(target: logging::PARALLEL_IO,
+              "Loaded PS-EXE: BASE=0x{:08x} ENTRY=0x{:08x} LEN={}",
               base, entry, text_len);
 
         loader.assemble_loader();
@@ -124,6 +131,73 @@ impl ExeLoader {
         Ok(loader)
     }
 
+    /// Load a MIPS ELF executable. All the loadable segments are
+    /// merged into a single image, the holes between them are filled
+    /// with zeroes.
+    pub fn load_elf(data: &[u8]) -> Result<ExeLoader, Error> {
+        let elf =
+            match Elf::parse(data) {
+                Ok(e) => e,
+                Err(e) => return Err(Error::BadElf(e)),
+            };
+
+        let base = elf.segments.iter().map(|s| s.vaddr).min().unwrap();
+
+        let text_end =
+            elf.segments.iter()
+            .map(|s| s.vaddr + s.data.len() as u32)
+            .max()
+            .unwrap();
+
+        let text_len = text_end - base;
+
+        if text_len > 2 * 1024 * 1024 {
+            return Err(Error::TooBig(text_len));
+        }
+
+        let mut text = vec![0; text_len as usize];
+
+        for s in &elf.segments {
+            let start = (s.vaddr - base) as usize;
+
+            text[start..start + s.data.len()].copy_from_slice(&s.data);
+        }
+
+        // The loader fills the memfill area before copying the text so
+        // we can use a single range covering all the .bss sections,
+        // anything in between will be overwritten by the text.
+        let bss: Vec<(u32, u32)> =
+            elf.segments.iter()
+            .filter(|s| s.mem_size as usize > s.data.len())
+            .map(|s| (s.vaddr + s.data.len() as u32, s.vaddr + s.mem_size))
+            .collect();
+
+        let memfill_base = bss.iter().map(|b| b.0).min().unwrap_or(0);
+        let memfill_end = bss.iter().map(|b| b.1).max().unwrap_or(0);
+
+        let mut loader = ExeLoader {
+            base: base,
+            entry: elf.entry,
+            initial_gp: elf.gp.unwrap_or(0),
+            initial_sp: ELF_INITIAL_SP,
+            memfill_base: memfill_base,
+            memfill_len: memfill_end - memfill_base,
+            region: None,
+            text: text,
+            text_index: 0,
+            loader: Vec::new(),
+            symbols: elf.symbols,
+        };
+
+        info!(target: logging::PARALLEL_IO,
+              "Loaded ELF: BASE=0x{:08x} ENTRY=0x{:08x} LEN={} SYMBOLS={}",
+              base, elf.entry, text_len, loader.symbols.len());
+
+        loader.assemble_loader();
+
+        Ok(loader)
+    }
+
     /// Assemble the code for the native loader whose purpose is to
     /// load the executable in RAM
     fn assemble_loader(&mut self) {
@@ -291,16 +365,59 @@ impl ExeLoader {
         self.loader = mc;
     }
 
+    /// Load a PS-X EXE or ELF executable
     pub fn load_file(path: &Path) -> Result<ExeLoader, Error> {
         let mut f = try!(File::open(path));
 
-        ExeLoader::load(&mut f)
+        let mut data = Vec::new();
+
+        try!(f.read_to_end(&mut data));
+
+        if Elf::is_elf(&data) {
+            ExeLoader::load_elf(&data)
+        } else {
+            ExeLoader::load(&mut &data[..])
+        }
     }
 
     pub fn region(&self) -> Option<Region> {
         self.region
     }
 
+    pub fn entry(&self) -> u32 {
+        self.entry
+    }
+
+    pub fn initial_gp(&self) -> u32 {
+        self.initial_gp
+    }
+
+    pub fn initial_sp(&self) -> u32 {
+        self.initial_sp
+    }
+
+    /// Return the load address and contents of the "text" section
+    pub fn text(&self) -> (u32, &[u8]) {
+        (self.base, &self.text)
+    }
+
+    /// Return the base address and length of the 0-filled area
+    pub fn memfill(&self) -> (u32, u32) {
+        (self.memfill_base, self.memfill_len)
+    }
+
+    /// Return the symbols found in the executable. Should be retrieved
+    /// before the loader is handed to the parallel port since it's
+    /// consumed by `ParallelIo::set_module`.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Take ownership of the symbol table, leaving an empty one behind
+    pub fn take_symbols(&mut self) -> SymbolTable {
+        ::std::mem::replace(&mut self.symbols, SymbolTable::new())
+    }
+
     /// Patch the BIOS animation jump to run the loader code
     /// instead. Returns an error if the patching failed.
     pub fn patch_bios(&self, bios: &mut Bios) -> Result<(), ()> {
@@ -336,7 +453,8 @@ impl ParallelIoModule for ExeLoader {
                     b
                 }
                 None => {
-                    warn!("Load from EXE FIFO when empty");
+                    warn!(target: logging::PARALLEL_IO,
+                          "Load from EXE FIFO when empty");
                     !0
                 }
             }
@@ -364,6 +482,8 @@ pub enum Error {
     UnknownFormat,
     /// The program is anormaly large
     TooBig(u32),
+    /// The ELF file is invalid or unsupported
+    BadElf(&'static str),
 }
 
 impl From<io::Error> for Error {
@@ -391,6 +511,10 @@ const EXE_FIFO_OFFSET: u32 = 0x100;
 /// for loading the EXE
 const EXE_FIFO_ADDRESS: u32 = ::memory::map::EXPANSION_1.0 + EXE_FIFO_OFFSET;
 
+/// ELF files don't specify a stack pointer, use the same value as the
+/// BIOS
+const ELF_INITIAL_SP: u32 = 0x801ffff0;
+
 /// Offset of the entry point for the loader code in the EXPANSION 1
 /// memory range
 const LOADER_ENTRY_OFFSET: u32 = 0x200;
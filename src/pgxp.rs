@@ -0,0 +1,182 @@
+//! PGXP-style precise vertex tracking.
+//!
+//! The GTE outputs screen coordinates as 11bit integers which causes
+//! the infamous "wobbly" polygons of PlayStation games. When enabled
+//! this module keeps the full precision coordinates computed by the
+//! GTE alongside the 16bit values as they travel through the CPU
+//! registers and memory up to the GPU. The hardware renderer can then
+//! use them instead of the integer positions.
+//!
+//! Every precise value is tagged with the 32bit word it shadows and
+//! is only used as long as the emulated value still matches. This way
+//! we don't have to track every instruction that could modify a
+//! register or a memory location: any modification simply
+//! invalidates the precise value.
+//!
+//! Only the most common paths are tracked: MFC2/SWC2 out of the GTE
+//! SXY registers, LW/SW and register moves through `or` and `addu`
+//! with R0, then CPU writes to GP0 and GPU DMA. Values going through
+//! any other path fall back to the integer coordinates.
+
+use std::collections::HashMap;
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+use memory::map::mask_region;
+
+/// Full precision screen coordinates of a vertex
+#[derive(Clone, Copy, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct PreciseVertex {
+    /// Screen X coordinate
+    pub x: f32,
+    /// Screen Y coordinate
+    pub y: f32,
+    /// Depth, before saturation to 16bits
+    pub z: f32,
+    /// Packed 16bit "YYYYXXXX" value shadowed by this vertex
+    pub value: u32,
+}
+
+impl PreciseVertex {
+    /// Return `self` if it still shadows `value`
+    pub fn validate(self, value: u32) -> Option<PreciseVertex> {
+        if self.value == value {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Pgxp {
+    enabled: bool,
+    /// Precise values associated with the CPU general purpose
+    /// registers
+    regs: [Option<PreciseVertex>; 32],
+    /// Precise values stored in memory, indexed by word address
+    memory: HashMap<u32, PreciseVertex>,
+}
+
+impl Pgxp {
+    pub fn new() -> Pgxp {
+        Pgxp {
+            enabled: false,
+            regs: [None; 32],
+            memory: HashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enable or disable the precise vertex tracking. Disabling it
+    /// discards all the values tracked so far.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+
+        if !enabled {
+            self.clear();
+        }
+    }
+
+    /// Discard all the values tracked so far
+    pub fn clear(&mut self) {
+        self.regs = [None; 32];
+        self.memory.clear();
+    }
+
+    /// Return the precise value of CPU register `reg` if it still
+    /// shadows `value`
+    pub fn reg(&self, reg: u32, value: u32) -> Option<PreciseVertex> {
+        self.regs[reg as usize].and_then(|p| p.validate(value))
+    }
+
+    /// Set the precise value of CPU register `reg`
+    pub fn set_reg(&mut self, reg: u32, precise: Option<PreciseVertex>) {
+        // R0 is always 0, no need to track it
+        if reg != 0 {
+            self.regs[reg as usize] = precise;
+        }
+    }
+
+    /// Return the precise value stored at `addr` if it still shadows
+    /// `value`
+    pub fn load(&self, addr: u32, value: u32) -> Option<PreciseVertex> {
+        self.memory.get(&word_address(addr))
+            .and_then(|p| p.validate(value))
+    }
+
+    /// Called when the word `value` is stored at `addr`
+    pub fn store(&mut self,
+                 addr: u32,
+                 value: u32,
+                 precise: Option<PreciseVertex>) {
+        let addr = word_address(addr);
+
+        match precise.and_then(|p| p.validate(value)) {
+            Some(p) => { self.memory.insert(addr, p); }
+            None => { self.memory.remove(&addr); }
+        }
+    }
+}
+
+impl Encodable for Pgxp {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // The precise values are not saved, they're rebuilt as the
+        // game runs
+        s.emit_bool(self.enabled)
+    }
+}
+
+impl Decodable for Pgxp {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Pgxp, D::Error> {
+        let mut pgxp = Pgxp::new();
+
+        pgxp.enabled = try!(d.read_bool());
+
+        Ok(pgxp)
+    }
+}
+
+/// Normalize `addr` into an aligned physical address. The RAM is
+/// mirrored four times so we use the same index for all the mirrors.
+fn word_address(addr: u32) -> u32 {
+    let addr = mask_region(addr);
+
+    if addr < 0x800000 {
+        addr & 0x1ffffc
+    } else {
+        addr & !3
+    }
+}
+
+#[test]
+fn validation() {
+    let p = PreciseVertex {
+        x: 12.25,
+        y: -3.5,
+        z: 1000.,
+        value: 0xfffc000c,
+    };
+
+    let mut pgxp = Pgxp::new();
+
+    pgxp.set_enabled(true);
+
+    pgxp.store(0x80001000, p.value, Some(p));
+
+    // RAM mirror
+    assert_eq!(pgxp.load(0x00201000, p.value), Some(p));
+    // Overwritten by the emulated code
+    assert_eq!(pgxp.load(0x80001000, 0x1234), None);
+
+    pgxp.store(0xa0001000, 0x1234, None);
+    assert_eq!(pgxp.load(0x80001000, p.value), None);
+
+    pgxp.set_reg(0, Some(p));
+    assert_eq!(pgxp.reg(0, p.value), None);
+
+    pgxp.set_reg(8, Some(p));
+    assert_eq!(pgxp.reg(8, p.value), Some(p));
+}
@@ -0,0 +1,180 @@
+//! Machine-readable compatibility reports.
+//!
+//! `run` boots the machine for a given number of frames without any
+//! user interaction and returns a `Report` saying how far the boot
+//! went: did the BIOS reach its shell, did it start the game's
+//! executable, how many frames did the game render and which
+//! unhandled addresses did it access along the way. The report can
+//! be serialized to JSON to track the compatibility of a game
+//! collection automatically.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use rustc_serialize::json;
+
+use cpu::Cpu;
+use debugger::Debugger;
+use gpu::renderer::Renderer;
+use machine::{self, Machine};
+use memory::UnhandledAccessPolicy;
+
+/// Debugger watching the boot process
+pub struct BootTracker {
+    /// Entry point of the game's executable, if known
+    exe_entry: Option<u32>,
+    /// Set when the CPU reaches the BIOS shell
+    reached_shell: bool,
+    /// Set when the CPU reaches the game's entry point
+    executed_exe: bool,
+}
+
+impl BootTracker {
+    /// Create a tracker for a game starting at `exe_entry` (see
+    /// `Disc::boot_entry_point`). With `None` we can't tell if the
+    /// game started.
+    pub fn new(exe_entry: Option<u32>) -> BootTracker {
+        BootTracker {
+            exe_entry: exe_entry,
+            reached_shell: false,
+            executed_exe: false,
+        }
+    }
+
+    pub fn reached_shell(&self) -> bool {
+        self.reached_shell
+    }
+
+    pub fn executed_exe(&self) -> bool {
+        self.executed_exe
+    }
+}
+
+impl Debugger for BootTracker {
+    fn trigger_break(&mut self) {
+    }
+
+    fn pc_change(&mut self, cpu: &mut Cpu) {
+        let pc = cpu.pc();
+
+        if pc == SHELL_ENTRY {
+            self.reached_shell = true;
+        }
+
+        if Some(pc) == self.exe_entry {
+            self.executed_exe = true;
+        }
+    }
+
+    fn memory_read(&mut self, _: &mut Cpu, _: u32) {
+    }
+
+    fn memory_write(&mut self, _: &mut Cpu, _: u32) {
+    }
+}
+
+/// Outcome of a headless run
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct Report {
+    /// Number of frames emulated. Lower than requested if the
+    /// emulator panicked.
+    pub frames: u32,
+    /// True if the BIOS reached its shell (the boot logo)
+    pub reached_shell: bool,
+    /// True if the CPU jumped to the game's entry point
+    pub executed_exe: bool,
+    /// Entry point of the game's executable as `0xXXXXXXXX`, if it
+    /// could be read from the disc
+    pub exe_entry: Option<String>,
+    /// Number of frames the game rendered (display area changes)
+    pub frames_rendered: u32,
+    /// Distinct unhandled memory accesses
+    pub unhandled_accesses: Vec<ReportAccess>,
+    /// Panic message if the emulator crashed
+    pub panic: Option<String>,
+}
+
+impl Report {
+    pub fn to_json(&self) -> String {
+        json::as_pretty_json(self).to_string()
+    }
+}
+
+/// Unhandled access as it appears in the report
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct ReportAccess {
+    /// Address formatted as `0xXXXXXXXX`
+    pub address: String,
+    /// Access width in bytes
+    pub size: u8,
+    /// "load" or "store"
+    pub kind: &'static str,
+}
+
+/// Run `machine` for `frames` frames and build the report. Unhandled
+/// accesses don't stop the emulation (the `OpenBus` policy is used)
+/// and panics are caught and reported.
+pub fn run<R: Renderer>(machine: &mut Machine<BootTracker, R>,
+                        frames: u32) -> Report {
+    machine.cpu_mut()
+        .interconnect_mut()
+        .set_unhandled_access_policy(UnhandledAccessPolicy::OpenBus);
+
+    let mut frames_run = 0;
+    let mut panic_message = None;
+
+    while frames_run < frames {
+        let r = panic::catch_unwind(AssertUnwindSafe(|| machine.run_frame()));
+
+        if let Err(e) = r {
+            panic_message = Some(machine::panic_message(&*e));
+            break;
+        }
+
+        frames_run += 1;
+    }
+
+    let tracker = machine.debugger();
+
+    let unhandled_accesses =
+        machine.cpu().interconnect().unhandled_accesses().iter()
+        .map(|a| ReportAccess {
+            address: format!("0x{:08x}", a.addr),
+            size: a.size,
+            kind: if a.store { "store" } else { "load" },
+        })
+        .collect();
+
+    Report {
+        frames: frames_run,
+        reached_shell: tracker.reached_shell(),
+        executed_exe: tracker.executed_exe(),
+        exe_entry: tracker.exe_entry.map(|e| format!("0x{:08x}", e)),
+        frames_rendered: machine.shared().counters().framebuffer_swap.get(),
+        unhandled_accesses: unhandled_accesses,
+        panic: panic_message,
+    }
+}
+
+/// Address of the BIOS shell once copied to RAM. The BIOS runs it at
+/// every boot to display the logo (or the memory card manager when
+/// there's no disc).
+const SHELL_ENTRY: u32 = 0x80030000;
+
+#[test]
+fn boot_tracker() {
+    use cpu::test_machine::TestMachine;
+
+    let mut m = TestMachine::new();
+    let mut tracker = BootTracker::new(Some(0x80010000));
+
+    m.cpu.set_pc(SHELL_ENTRY);
+    tracker.pc_change(&mut m.cpu);
+
+    assert!(tracker.reached_shell());
+    assert!(!tracker.executed_exe());
+
+    m.cpu.set_pc(0x80010000);
+    tracker.pc_change(&mut m.cpu);
+
+    assert!(tracker.executed_exe());
+}
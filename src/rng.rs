@@ -0,0 +1,98 @@
+//! Deterministic pseudo-random numbers.
+//!
+//! Some hardware behaviours aren't exactly reproducible on the real
+//! console: the CD controller doesn't always answer after the same
+//! delay, seeks don't always take the same time... In order to keep
+//! replays and netplay sessions bit-exact all these choices go through
+//! the `Rng` held in the `SharedState` instead of using the host's
+//! randomness.
+//!
+//! The generator is re-seeded at the beginning of each frame from the
+//! global seed and the frame number, so two machines using the same
+//! seed get the same sequence for a given frame even if one of them
+//! was loaded from a savestate.
+
+/// Seed used when the frontend doesn't set one
+pub const DEFAULT_SEED: u32 = 1;
+
+/// Fast non-cryptographically secure RNG. The implementation is
+/// XorShift with a period of (2**31)-1. This is more than sufficient
+/// for our use case.
+///
+/// See http://www.jstatsoft.org/v08/i14/paper for more details on the
+/// algorithm.
+///
+/// One of the pitfalls of this algorithm is that if the output is
+/// used as a raw 32bit random number (without modulo) it'll never
+/// return 0.
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    /// Create a new Rng instance using the default seed
+    pub fn new() -> Rng {
+        Rng::with_seed(DEFAULT_SEED)
+    }
+
+    /// Create a new Rng instance seeded with `seed`
+    pub fn with_seed(seed: u32) -> Rng {
+        Rng {
+            // The state must be non-0
+            state: if seed == 0 { DEFAULT_SEED } else { seed },
+        }
+    }
+
+    /// Create the generator used during frame `frame` for `seed`
+    pub fn for_frame(seed: u32, frame: u32) -> Rng {
+        // Mix the frame number so that consecutive frames don't
+        // start with correlated states
+        let mut rng = Rng::with_seed(seed ^ frame.wrapping_mul(0x9e3779b9));
+
+        // Get rid of the first few, poorly mixed values
+        for _ in 0..4 {
+            rng.next();
+        }
+
+        rng
+    }
+
+    /// Run through one cycle of XorShift and return the internal
+    /// pseudo-random state. It will *never* return 0.
+    pub fn next(&mut self) -> u32 {
+        // The XorShift paper lists a bunch of valid shift triplets, I
+        // picked one at random.
+        self.state ^= self.state << 6;
+        self.state ^= self.state >> 1;
+        self.state ^= self.state << 11;
+
+        self.state
+    }
+
+    /// Return a value in the range `[0, n)`. Returns 0 if `n` is 0.
+    pub fn below(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            0
+        } else {
+            self.next() % n
+        }
+    }
+}
+
+#[test]
+fn per_frame_sequence() {
+    let mut a = Rng::for_frame(1234, 10);
+    let mut b = Rng::for_frame(1234, 10);
+
+    for _ in 0..16 {
+        assert_eq!(a.next(), b.next());
+    }
+
+    let mut c = Rng::for_frame(1234, 11);
+
+    assert!(Rng::for_frame(1234, 10).next() != c.next());
+
+    assert!(a.below(5) < 5);
+    assert_eq!(a.below(0), 0);
+}
@@ -0,0 +1,218 @@
+//! Virtual real time clock.
+//!
+//! The PlayStation itself doesn't have a battery backed clock but
+//! some peripherals (the PocketStation, some arcade boards) and a few
+//! games want to know the current date. `Rtc` centralizes the time
+//! source so that it can either follow the host clock or be derived
+//! from the emulated time, which keeps replays and TAS runs
+//! deterministic.
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+use timekeeper::Cycles;
+use cpu::CPU_FREQ_HZ;
+use host::Clock;
+
+/// Where the RTC gets the current date from
+#[derive(Clone, Copy, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum TimeSource {
+    /// Use the host clock. Not deterministic.
+    Host,
+    /// Start at the given UNIX timestamp (in seconds) when the
+    /// emulator starts and follow the emulated time from there.
+    Fixed(u64),
+    /// Like `Fixed` but the emulated time is multiplied by the given
+    /// ratio, for instance to let a day go by in a few minutes.
+    Accelerated(u64, f32),
+}
+
+impl Default for TimeSource {
+    fn default() -> TimeSource {
+        TimeSource::Host
+    }
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Rtc {
+    source: TimeSource,
+    /// Emulated date when the `source` was last changed, used to
+    /// start counting from the configured timestamp
+    origin: Cycles,
+    /// Clock used for `TimeSource::Host`
+    host_clock: HostClock,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            source: TimeSource::default(),
+            origin: 0,
+            host_clock: HostClock::default(),
+        }
+    }
+
+    /// Replace the clock used for `TimeSource::Host`. The clock is
+    /// not part of the savestates, the default clock is used after a
+    /// reload.
+    pub fn set_host_clock(&mut self, clock: Box<Clock>) {
+        self.host_clock = HostClock(clock);
+    }
+
+    /// Replace the emulated state with `state` (loaded from a
+    /// savestate), keeping our host clock
+    pub fn restore(&mut self, state: Rtc) {
+        self.source = state.source;
+        self.origin = state.origin;
+    }
+
+    pub fn source(&self) -> TimeSource {
+        self.source
+    }
+
+    /// Change the time source. `now` is the current emulated date,
+    /// the `Fixed` and `Accelerated` timestamps apply from there.
+    pub fn set_source(&mut self, source: TimeSource, now: Cycles) {
+        self.source = source;
+        self.origin = now;
+    }
+
+    /// Return the current UNIX timestamp in seconds. `now` is the
+    /// current emulated date.
+    pub fn timestamp(&self, now: Cycles) -> u64 {
+        let elapsed = now.saturating_sub(self.origin);
+
+        match self.source {
+            TimeSource::Host => self.host_clock.0.unix_time(),
+            TimeSource::Fixed(start) =>
+                start + elapsed / CPU_FREQ_HZ as Cycles,
+            TimeSource::Accelerated(start, ratio) => {
+                let secs = elapsed as f64 / CPU_FREQ_HZ as f64;
+
+                start + (secs * ratio as f64) as u64
+            }
+        }
+    }
+
+    /// Return the current date and time in UTC. `now` is the current
+    /// emulated date.
+    pub fn date_time(&self, now: Cycles) -> DateTime {
+        DateTime::from_timestamp(self.timestamp(now))
+    }
+}
+
+struct HostClock(Box<Clock>);
+
+impl Default for HostClock {
+    /// `SystemTime::now` panics on `wasm32-unknown-unknown`, web
+    /// frontends have to provide the date through `set_host_clock`
+    #[cfg(target_arch = "wasm32")]
+    fn default() -> HostClock {
+        HostClock(Box::new(::host::FixedClock(0)))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn default() -> HostClock {
+        HostClock(Box::new(::host::SystemClock))
+    }
+}
+
+impl Encodable for HostClock {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Provided by the frontend, not part of the console state
+        s.emit_nil()
+    }
+}
+
+impl Decodable for HostClock {
+    fn decode<D: Decoder>(d: &mut D) -> Result<HostClock, D::Error> {
+        try!(d.read_nil());
+
+        Ok(HostClock::default())
+    }
+}
+
+/// Broken down UTC date
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    /// 0 (Sunday) to 6 (Saturday)
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Convert a UNIX timestamp to a date in the proleptic Gregorian
+    /// calendar
+    pub fn from_timestamp(timestamp: u64) -> DateTime {
+        let days = timestamp / 86400;
+        let secs = timestamp % 86400;
+
+        // 1970-01-01 was a Thursday
+        let weekday = ((days + 4) % 7) as u8;
+
+        // Shift the epoch to 0000-03-01 so that the leap day ends the
+        // year, then split into 400 year eras
+        let days = days + 719468;
+        let era = days / 146097;
+        let doe = days % 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: year as u16,
+            month: month,
+            day: day,
+            weekday: weekday,
+            hour: (secs / 3600) as u8,
+            minute: ((secs / 60) % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+}
+
+/// Convert `v` (0 to 99) to BCD, the format used by most RTC chips
+pub fn to_bcd(v: u8) -> u8 {
+    ((v / 10) << 4) | (v % 10)
+}
+
+#[test]
+fn deterministic_time() {
+    let mut rtc = Rtc::new();
+
+    // 2000-02-29 23:59:59, a Tuesday
+    rtc.set_source(TimeSource::Fixed(951868799), 1000);
+
+    let dt = rtc.date_time(1000);
+
+    assert_eq!(dt, DateTime {
+        year: 2000,
+        month: 2,
+        day: 29,
+        weekday: 2,
+        hour: 23,
+        minute: 59,
+        second: 59,
+    });
+
+    let dt = rtc.date_time(1000 + CPU_FREQ_HZ as Cycles);
+
+    assert_eq!((dt.month, dt.day, dt.hour, dt.second), (3, 1, 0, 0));
+
+    // One emulated second is one hour
+    rtc.set_source(TimeSource::Accelerated(0, 3600.), 0);
+
+    assert_eq!(rtc.timestamp(CPU_FREQ_HZ as Cycles * 2), 7200);
+
+    assert_eq!(to_bcd(59), 0x59);
+}
@@ -0,0 +1,369 @@
+//! Savestate container format.
+//!
+//! A savestate is split into chunks, one per subsystem (CPU, GPU,
+//! SPU...). Each chunk has a four character tag, its own version
+//! number and is encoded separately, so that changing the layout of
+//! one subsystem doesn't invalidate the rest of the state:
+//!
+//! * Chunks with an unknown tag (written by a newer emulator) are
+//!   skipped.
+//! * Chunks with an unsupported version are skipped as well, the
+//!   subsystem is left in its power-on state.
+//! * Missing chunks leave the subsystem in its power-on state.
+//!
+//! The file layout is:
+//!
+//! ```text
+//! magic "RSXSTATE"
+//! format version (u32)
+//! number of chunks (u32)
+//! for each chunk:
+//!     tag ([u8; 4])
+//!     chunk version (u32)
+//!     payload length in bytes (u32)
+//!     payload
+//! ```
+//!
+//! All the integers are little endian. The payloads are the
+//! `rustc_serialize` encoding of the subsystem state using `bincode`.
+//! Saving and loading the complete console is done through
+//! `Machine::save_state` and `Machine::load_state`.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize as bincode_rs;
+use rustc_serialize::{Decodable, Encodable};
+
+use logging;
+
+/// Magic string at the beginning of savestate files
+pub const MAGIC: [u8; 8] = *b"RSXSTATE";
+
+/// Version of the container layout itself. Only changes if the chunk
+/// headers change, new subsystem layouts just bump the chunk version.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Description of a chunk known to this version of the emulator
+pub struct ChunkKind {
+    pub tag: [u8; 4],
+    /// Current version of the chunk's payload
+    pub version: u32,
+    pub description: &'static str,
+}
+
+impl ChunkKind {
+    pub fn name(&self) -> &str {
+        tag_name(&self.tag)
+    }
+}
+
+macro_rules! chunks {
+    ($($name:ident: $tag:expr, $version:expr, $desc:expr;)+) => (
+        $(pub const $name: ChunkKind = ChunkKind {
+            tag: *$tag,
+            version: $version,
+            description: $desc,
+        };)+
+
+        /// All the chunks known to this version of the emulator
+        pub const KNOWN_CHUNKS: &'static [ChunkKind] = &[$($name),+];
+    )
+}
+
+chunks! {
+    CPU: b"CPU ", 2, "CPU registers";
+    PIPELINE: b"PIPE", 2, "CPU pipeline timing model";
+    WRITE_BUFFER: b"WBUF", 1, "CPU write buffer model";
+    ICACHE: b"ICAC", 2, "CPU instruction cache";
+    COP0: b"COP0", 1, "System control coprocessor";
+    GTE: b"GTE ", 1, "Geometry Transform Engine";
+    HLE: b"HLE ", 2, "High-level BIOS emulation";
+    BIOS: b"BIOS", 1, "BIOS checksum";
+    RAM: b"RAM ", 1, "Main RAM and ScratchPad";
+    BUS: b"BUS ", 1, "Memory control and bus registers";
+    DMA: b"DMA ", 1, "DMA controller";
+    GPU: b"GPU ", 4, "Graphics Processing Unit";
+    SPU: b"SPU ", 1, "Sound Processing Unit";
+    TIMERS: b"TIMR", 2, "Timers";
+    CDROM: b"CDRM", 2, "CDROM controller";
+    PAD_MEMCARD: b"PAD ", 3, "Gamepad and memory card controller";
+    SIO1: b"SIO1", 1, "Serial port 1";
+    MDEC: b"MDEC", 1, "Motion decoder";
+    DEBUG_UART: b"UART", 1, "Debug UART";
+    TIMEKEEPER: b"TIME", 1, "Peripheral synchronization";
+    IRQ: b"IRQ ", 1, "Interrupt controller";
+    COUNTERS: b"CNTR", 1, "Event counters";
+    RTC: b"RTC ", 1, "Real time clock settings";
+    RNG: b"RNG ", 1, "Pseudo-random generator";
+}
+
+/// Return the description of the chunk with tag `tag`, if it's known
+pub fn lookup(tag: &[u8; 4]) -> Option<&'static ChunkKind> {
+    KNOWN_CHUNKS.iter().find(|k| &k.tag == tag)
+}
+
+fn tag_name(tag: &[u8; 4]) -> &str {
+    str::from_utf8(tag).unwrap_or("????")
+}
+
+/// Serialized state of a single subsystem
+pub struct Chunk {
+    pub tag: [u8; 4],
+    pub version: u32,
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn name(&self) -> &str {
+        tag_name(&self.tag)
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} v{} {:>9} bytes", self.name(), self.version,
+                    self.data.len()));
+
+        match lookup(&self.tag) {
+            Some(k) if k.version == self.version =>
+                write!(f, "  {}", k.description),
+            Some(k) =>
+                write!(f, "  {} (unsupported version, expected v{})",
+                       k.description, k.version),
+            None => write!(f, "  (unknown chunk)"),
+        }
+    }
+}
+
+pub struct SaveState {
+    chunks: Vec<Chunk>,
+}
+
+impl SaveState {
+    pub fn new() -> SaveState {
+        SaveState {
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Encode `val` in a new chunk of type `kind`, replacing any
+    /// previous chunk with the same tag
+    pub fn put<T: Encodable>(&mut self,
+                             kind: &ChunkKind,
+                             val: &T) -> Result<(), Error> {
+        let data =
+            match bincode_rs::encode(val, SizeLimit::Infinite) {
+                Ok(d) => d,
+                Err(e) => return Err(Error::Encoding(kind.name().into(),
+                                                     e.to_string())),
+            };
+
+        self.chunks.retain(|c| c.tag != kind.tag);
+
+        self.chunks.push(Chunk {
+            tag: kind.tag,
+            version: kind.version,
+            data: data,
+        });
+
+        Ok(())
+    }
+
+    /// Decode the chunk of type `kind`. Returns `None` if the chunk
+    /// is missing or if its version isn't supported.
+    pub fn get<T: Decodable>(&self,
+                             kind: &ChunkKind) -> Result<Option<T>, Error> {
+        let chunk =
+            match self.chunks.iter().find(|c| c.tag == kind.tag) {
+                Some(c) => c,
+                None => {
+                    warn!(target: logging::SAVESTATE,
+                          "Missing {} chunk, using the default state",
+                          kind.name());
+                    return Ok(None);
+                }
+            };
+
+        if chunk.version != kind.version {
+            warn!(target: logging::SAVESTATE,
+                  "Unsupported {} chunk version {} (expected {}), \
+                   using the default state",
+                  kind.name(), chunk.version, kind.version);
+            return Ok(None);
+        }
+
+        match bincode_rs::decode(&chunk.data) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => Err(Error::Decoding(kind.name().into(),
+                                          e.to_string())),
+        }
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Serialize the state to `w`
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        try!(w.write_all(&MAGIC));
+        try!(write_u32(w, FORMAT_VERSION));
+        try!(write_u32(w, self.chunks.len() as u32));
+
+        for c in &self.chunks {
+            try!(w.write_all(&c.tag));
+            try!(write_u32(w, c.version));
+            try!(write_u32(w, c.data.len() as u32));
+            try!(w.write_all(&c.data));
+        }
+
+        Ok(())
+    }
+
+    /// Parse a state serialized by `write`. Unknown chunks are kept
+    /// (so that they show up in the inventory) but never decoded.
+    pub fn read<R: Read>(r: &mut R) -> Result<SaveState, Error> {
+        let mut magic = [0; 8];
+
+        try!(r.read_exact(&mut magic));
+
+        if magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let version = try!(read_u32(r));
+
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedFormat(version));
+        }
+
+        let count = try!(read_u32(r));
+
+        let mut chunks = Vec::new();
+
+        for _ in 0..count {
+            let mut tag = [0; 4];
+
+            try!(r.read_exact(&mut tag));
+
+            let version = try!(read_u32(r));
+            let len = try!(read_u32(r));
+
+            let mut data = Vec::new();
+
+            try!(r.by_ref().take(len as u64).read_to_end(&mut data));
+
+            if data.len() != len as usize {
+                return Err(Error::Truncated);
+            }
+
+            if lookup(&tag).is_none() {
+                debug!(target: logging::SAVESTATE,
+                       "Skipping unknown chunk {}", tag_name(&tag));
+            }
+
+            chunks.push(Chunk {
+                tag: tag,
+                version: version,
+                data: data,
+            });
+        }
+
+        Ok(SaveState {
+            chunks: chunks,
+        })
+    }
+}
+
+fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    let b = [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8];
+
+    w.write_all(&b)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+
+    try!(r.read_exact(&mut b));
+
+    Ok((b[0] as u32) |
+       ((b[1] as u32) << 8) |
+       ((b[2] as u32) << 16) |
+       ((b[3] as u32) << 24))
+}
+
+/// Error while saving or loading a state
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file doesn't start with `MAGIC`
+    BadMagic,
+    /// The container format version isn't supported
+    UnsupportedFormat(u32),
+    /// The file ends in the middle of a chunk
+    Truncated,
+    /// A chunk couldn't be encoded
+    Encoding(String, String),
+    /// A chunk couldn't be decoded
+    Decoding(String, String),
+    /// The state is not compatible with the running machine
+    Mismatch(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            Error::Truncated
+        } else {
+            Error::Io(e)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::BadMagic => write!(f, "Not a savestate"),
+            Error::UnsupportedFormat(v) =>
+                write!(f, "Unsupported savestate format version {}", v),
+            Error::Truncated => write!(f, "Truncated savestate"),
+            Error::Encoding(ref c, ref e) =>
+                write!(f, "Can't encode {} chunk: {}", c, e),
+            Error::Decoding(ref c, ref e) =>
+                write!(f, "Can't decode {} chunk: {}", c, e),
+            Error::Mismatch(ref e) => write!(f, "Incompatible state: {}", e),
+        }
+    }
+}
+
+#[test]
+fn round_trip() {
+    let mut state = SaveState::new();
+
+    state.put(&GPU, &(1u32, 2u8)).unwrap();
+    state.put(&SPU, &vec![1u16, 2, 3]).unwrap();
+
+    let mut raw = Vec::new();
+
+    state.write(&mut raw).unwrap();
+
+    // Append an unknown chunk, as if written by a newer version
+    raw[12] = 3;
+    raw.extend_from_slice(b"NEW ");
+    raw.extend_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0, 0xab, 0xcd]);
+
+    let state = SaveState::read(&mut &raw[..]).unwrap();
+
+    assert_eq!(state.chunks().len(), 3);
+    assert_eq!(state.get::<(u32, u8)>(&GPU).unwrap(), Some((1, 2)));
+    assert_eq!(state.get::<Vec<u16>>(&SPU).unwrap(), Some(vec![1, 2, 3]));
+    assert_eq!(state.get::<u32>(&CDROM).unwrap(), None);
+
+    match SaveState::read(&mut &raw[..raw.len() - 1]) {
+        Err(Error::Truncated) => (),
+        _ => panic!("Truncated state accepted"),
+    }
+}
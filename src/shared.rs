@@ -1,5 +1,21 @@
+//! State shared between the CPU and the peripherals.
+//!
+//! Besides the scheduler and the interrupt controller it's also the
+//! place where frontends can follow the emulation: frame counters,
+//! dropped frame statistics, pending interrupts, the event queue and
+//! callbacks invoked on vertical blanking, at the end of each frame
+//! and when the game disc changes.
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
 use timekeeper::TimeKeeper;
 use interrupt::InterruptState;
+use rtc::Rtc;
+use cdrom::metadata::GameInfo;
+use stats::FrameStats;
+use rng::{self, Rng};
+use savestate::{self, SaveState};
+use events::{Event, EventQueue, IRQ_STORM_THRESHOLD};
 
 /// State shared between various modules
 #[derive(RustcDecodable, RustcEncodable)]
@@ -7,6 +23,23 @@ pub struct SharedState {
     tk: TimeKeeper,
     irq_state: InterruptState,
     counters: Counters,
+    rtc: Rtc,
+    /// Seed of the pseudo-random generator
+    rng_seed: u32,
+    /// Source of all the pseudo-random hardware behaviours,
+    /// re-seeded at each frame
+    rng: Rng,
+    /// Value of `counters.framebuffer_swap` at the end of the
+    /// previous frame, used to detect dropped frames
+    last_framebuffer_swap: u32,
+    /// Statistics for the current frame
+    stats: FrameStats,
+    /// Statistics for the previous frame
+    last_frame_stats: FrameStats,
+    /// Notifications waiting to be polled by the frontend
+    events: EventQueue,
+    /// Frontend callbacks
+    callbacks: Callbacks,
 }
 
 impl SharedState {
@@ -15,9 +48,65 @@ impl SharedState {
             tk: TimeKeeper::new(),
             irq_state: InterruptState::new(),
             counters: Counters::new(),
+            rtc: Rtc::new(),
+            rng_seed: rng::DEFAULT_SEED,
+            rng: Rng::new(),
+            last_framebuffer_swap: 0,
+            stats: FrameStats::new(),
+            last_frame_stats: FrameStats::new(),
+            events: EventQueue::new(),
+            callbacks: Callbacks::new(),
         }
     }
 
+    /// Reset the interrupt controller and reschedule all the
+    /// peripherals. The time and counters keep running.
+    pub fn reset(&mut self) {
+        self.irq_state = InterruptState::new();
+        self.tk.reset_timesheets();
+    }
+
+    /// Store the emulated state in `state`. The statistics and the
+    /// callbacks are not part of it.
+    pub fn save_state(&self,
+                      state: &mut SaveState) -> Result<(), savestate::Error> {
+        try!(state.put(&savestate::TIMEKEEPER, &self.tk));
+        try!(state.put(&savestate::IRQ, &self.irq_state));
+        try!(state.put(&savestate::COUNTERS,
+                       &(&self.counters, self.last_framebuffer_swap)));
+        try!(state.put(&savestate::RNG, &(self.rng_seed, &self.rng)));
+        state.put(&savestate::RTC, &self.rtc)
+    }
+
+    /// Restore the emulated state from `state`. Missing chunks leave
+    /// the corresponding state untouched.
+    pub fn load_state(&mut self,
+                      state: &SaveState) -> Result<(), savestate::Error> {
+        if let Some(tk) = try!(state.get(&savestate::TIMEKEEPER)) {
+            self.tk = tk;
+        }
+
+        if let Some(irq_state) = try!(state.get(&savestate::IRQ)) {
+            self.irq_state = irq_state;
+        }
+
+        if let Some((counters, swap)) = try!(state.get(&savestate::COUNTERS)) {
+            self.counters = counters;
+            self.last_framebuffer_swap = swap;
+        }
+
+        if let Some((seed, rng)) = try!(state.get(&savestate::RNG)) {
+            self.rng_seed = seed;
+            self.rng = rng;
+        }
+
+        if let Some(rtc) = try!(state.get(&savestate::RTC)) {
+            self.rtc.restore(rtc);
+        }
+
+        Ok(())
+    }
+
     pub fn tk(&mut self) -> &mut TimeKeeper {
         &mut self.tk
     }
@@ -30,6 +119,24 @@ impl SharedState {
         &mut self.irq_state
     }
 
+    /// Return the pseudo-random generator. Peripherals must use it
+    /// for any non-deterministic behaviour.
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Change the seed of the pseudo-random generator, the new seed
+    /// takes effect immediately. Use the same seed on all the
+    /// machines taking part in a replay or netplay session.
+    pub fn set_rng_seed(&mut self, seed: u32) {
+        self.rng_seed = seed;
+        self.rng = Rng::for_frame(seed, self.frame());
+    }
+
+    pub fn rng_seed(&self) -> u32 {
+        self.rng_seed
+    }
+
     pub fn counters(&self) -> &Counters {
         &self.counters
     }
@@ -37,6 +144,137 @@ impl SharedState {
     pub fn counters_mut(&mut self) -> &mut Counters {
         &mut self.counters
     }
+
+    /// Return the number of frames displayed so far
+    pub fn frame(&self) -> u32 {
+        self.counters.frame.get()
+    }
+
+    /// Return the number of frames during which the game didn't
+    /// display a new image, typically because it couldn't keep up
+    /// with the refresh rate
+    pub fn dropped_frames(&self) -> u32 {
+        self.counters.dropped_frame.get()
+    }
+
+    /// Statistics accumulated since the start of the current frame
+    pub fn stats_mut(&mut self) -> &mut FrameStats {
+        &mut self.stats
+    }
+
+    /// Return the statistics of the last complete frame
+    pub fn last_frame_stats(&self) -> &FrameStats {
+        &self.last_frame_stats
+    }
+
+    /// Return the interrupts currently pending and not masked, one
+    /// bit per `Interrupt`
+    pub fn pending_irqs(&self) -> u16 {
+        self.irq_state.status() & self.irq_state.mask()
+    }
+
+    /// Register a callback called at the start of each vertical
+    /// blanking period with the current frame number
+    pub fn set_on_vblank(&mut self, callback: Option<Box<FnMut(u32)>>) {
+        self.callbacks.on_vblank = callback;
+    }
+
+    /// Register a callback called at the end of each frame
+    pub fn set_on_frame_complete(&mut self,
+                                 callback: Option<Box<FnMut(FrameInfo)>>) {
+        self.callbacks.on_frame_complete = callback;
+    }
+
+    /// Register a callback called when the disc changes with the
+    /// identification of the new game (`None` if the drive is left
+    /// empty), for instance to update the window title
+    pub fn set_on_game_changed(&mut self,
+                               cb: Option<Box<FnMut(Option<&GameInfo>)>>) {
+        self.callbacks.on_game_changed = cb;
+    }
+
+    /// Register a callback receiving the events as soon as they're
+    /// emitted. While a callback is registered the events are no
+    /// longer queued for `poll_event`.
+    pub fn set_on_event(&mut self, callback: Option<Box<FnMut(&Event)>>) {
+        self.callbacks.on_event = callback;
+    }
+
+    /// Notify the frontend
+    pub fn push_event(&mut self, event: Event) {
+        match self.callbacks.on_event {
+            Some(ref mut f) => f(&event),
+            None => self.events.push(event),
+        }
+    }
+
+    /// Return the oldest event not yet polled, if any
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop()
+    }
+
+    /// Called when a new disc is inserted or the current one removed
+    pub fn game_changed(&mut self, info: Option<&GameInfo>) {
+        if let Some(ref mut f) = self.callbacks.on_game_changed {
+            f(info);
+        }
+    }
+
+    /// Called by the GPU at the start of the vertical blanking
+    pub fn vblank(&mut self) {
+        let frame = self.frame();
+
+        if let Some(ref mut f) = self.callbacks.on_vblank {
+            f(frame);
+        }
+
+        self.push_event(Event::VBlank(frame));
+    }
+
+    /// Called by the GPU at the end of the vertical blanking, when a
+    /// new frame starts
+    pub fn end_frame(&mut self) {
+        let swap = self.counters.framebuffer_swap.get();
+        let dropped = swap == self.last_framebuffer_swap;
+
+        self.last_framebuffer_swap = swap;
+
+        if dropped {
+            self.counters.dropped_frame.increment();
+        }
+
+        self.counters.frame.increment();
+
+        self.rng = Rng::for_frame(self.rng_seed, self.frame());
+
+        self.last_frame_stats = self.stats;
+        self.stats = FrameStats::new();
+
+        let interrupts = self.last_frame_stats.interrupts;
+
+        if interrupts > IRQ_STORM_THRESHOLD {
+            self.push_event(Event::IrqStorm(interrupts));
+        }
+
+        let info = FrameInfo {
+            frame: self.frame(),
+            dropped: dropped,
+            dropped_frames: self.dropped_frames(),
+            stats: self.last_frame_stats,
+        };
+
+        if let Some(ref mut f) = self.callbacks.on_frame_complete {
+            f(info);
+        }
+    }
+
+    pub fn rtc(&self) -> &Rtc {
+        &self.rtc
+    }
+
+    pub fn rtc_mut(&mut self) -> &mut Rtc {
+        &mut self.rtc
+    }
 }
 
 /// Struct holding various counters for debugging and profiling
@@ -52,6 +290,9 @@ pub struct Counters {
     /// Incremented when the CPU is preempted by an external
     /// interrupt.
     pub cpu_interrupt: Counter,
+    /// Incremented at the end of each frame during which
+    /// `framebuffer_swap` didn't change.
+    pub dropped_frame: Counter,
 }
 
 impl Counters {
@@ -60,6 +301,7 @@ impl Counters {
             frame: Counter(0),
             framebuffer_swap: Counter(0),
             cpu_interrupt: Counter(0),
+            dropped_frame: Counter(0),
         }
     }
 }
@@ -81,3 +323,79 @@ impl Counter {
         self.0
     }
 }
+
+/// Information passed to the `on_frame_complete` callback
+#[derive(Clone, Copy, Debug)]
+pub struct FrameInfo {
+    /// Number of the frame that just started
+    pub frame: u32,
+    /// True if the game didn't display a new image during the frame
+    pub dropped: bool,
+    /// Total number of dropped frames
+    pub dropped_frames: u32,
+    /// Statistics for the frame
+    pub stats: FrameStats,
+}
+
+/// Callbacks registered by the frontend
+struct Callbacks {
+    on_vblank: Option<Box<FnMut(u32)>>,
+    on_frame_complete: Option<Box<FnMut(FrameInfo)>>,
+    on_game_changed: Option<Box<FnMut(Option<&GameInfo>)>>,
+    on_event: Option<Box<FnMut(&Event)>>,
+}
+
+impl Callbacks {
+    fn new() -> Callbacks {
+        Callbacks {
+            on_vblank: None,
+            on_frame_complete: None,
+            on_game_changed: None,
+            on_event: None,
+        }
+    }
+}
+
+impl Encodable for Callbacks {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Frontend hooks, not part of the console state
+        s.emit_nil()
+    }
+}
+
+impl Decodable for Callbacks {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Callbacks, D::Error> {
+        try!(d.read_nil());
+
+        Ok(Callbacks::new())
+    }
+}
+
+#[test]
+fn dropped_frames() {
+    let mut shared = SharedState::new();
+
+    shared.counters_mut().framebuffer_swap.increment();
+    shared.end_frame();
+
+    assert_eq!(shared.dropped_frames(), 0);
+
+    shared.end_frame();
+
+    assert_eq!(shared.frame(), 2);
+    assert_eq!(shared.dropped_frames(), 1);
+}
+
+#[test]
+fn irq_storm() {
+    let mut shared = SharedState::new();
+
+    shared.stats_mut().interrupts = IRQ_STORM_THRESHOLD + 1;
+    shared.end_frame();
+    shared.vblank();
+
+    assert_eq!(shared.poll_event(),
+               Some(Event::IrqStorm(IRQ_STORM_THRESHOLD + 1)));
+    assert_eq!(shared.poll_event(), Some(Event::VBlank(1)));
+    assert_eq!(shared.poll_event(), None);
+}
@@ -0,0 +1,264 @@
+//! Link cable implementations for the SIO1 serial port
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{self, Read, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+use logging;
+
+/// Other end of the serial cable
+pub trait Link {
+    /// Send a byte to the remote end
+    fn send(&mut self, b: u8);
+    /// Receive a byte from the remote end, if one is available. Must
+    /// not block.
+    fn recv(&mut self) -> Option<u8>;
+    /// Update the state of our handshake outputs (DTR and RTS)
+    fn set_lines(&mut self, dtr: bool, rts: bool);
+    /// Return the state of the remote end's handshake outputs as seen
+    /// by us, that is our (DSR, CTS) inputs
+    fn remote_lines(&mut self) -> (bool, bool);
+}
+
+/// Messages exchanged between the two ends of the link
+enum Message {
+    /// Data byte
+    Data(u8),
+    /// Handshake line state change: (DTR, RTS)
+    Lines(bool, bool),
+}
+
+// Only the TCP link serializes the messages
+#[cfg(not(target_arch = "wasm32"))]
+impl Message {
+    fn encode(&self) -> [u8; 2] {
+        match *self {
+            Message::Data(b) => [0, b],
+            Message::Lines(dtr, rts) => [1, dtr as u8 | (rts as u8) << 1],
+        }
+    }
+
+    fn decode(raw: [u8; 2]) -> Option<Message> {
+        match raw[0] {
+            0 => Some(Message::Data(raw[1])),
+            1 => Some(Message::Lines(raw[1] & 1 != 0, raw[1] & 2 != 0)),
+            _ => None,
+        }
+    }
+}
+
+/// Link over a TCP socket, used to connect two emulator instances
+/// (possibly on different machines). Not available on wasm32, the
+/// browsers don't give access to raw sockets.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct TcpLink {
+    stream: TcpStream,
+    /// Partially received message
+    partial: Vec<u8>,
+    /// Remote DTR (our DSR)
+    remote_dtr: bool,
+    /// Remote RTS (our CTS)
+    remote_rts: bool,
+    /// Received data bytes waiting to be read
+    rx: Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TcpLink {
+    /// Wait for the other instance to connect on `addr`
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        let listener = try!(TcpListener::bind(addr));
+
+        let (stream, remote) = try!(listener.accept());
+
+        info!(target: logging::SIO1, "Link cable connected to {}", remote);
+
+        TcpLink::from_stream(stream)
+    }
+
+    /// Connect to an instance listening on `addr`
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        let stream = try!(TcpStream::connect(addr));
+
+        TcpLink::from_stream(stream)
+    }
+
+    fn from_stream(stream: TcpStream) -> io::Result<TcpLink> {
+        try!(stream.set_nonblocking(true));
+        try!(stream.set_nodelay(true));
+
+        Ok(TcpLink {
+            stream: stream,
+            partial: Vec::with_capacity(2),
+            remote_dtr: false,
+            remote_rts: false,
+            rx: Vec::new(),
+        })
+    }
+
+    fn send_message(&mut self, m: Message) {
+        // XXX the socket is non-blocking, we could lose data if the
+        // send buffer is full. It shouldn't happen given the tiny
+        // bandwidth of the serial link.
+        if let Err(e) = self.stream.write_all(&m.encode()) {
+            warn!(target: logging::SIO1, "Link cable send failed: {}", e);
+        }
+    }
+
+    /// Process all the messages available in the socket
+    fn poll(&mut self) {
+        let mut buf = [0; 256];
+
+        loop {
+            let n =
+                match self.stream.read(&mut buf) {
+                    // Connection closed
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock =>
+                        break,
+                    Err(e) => {
+                        warn!(target: logging::SIO1,
+                              "Link cable receive failed: {}", e);
+                        break;
+                    }
+                };
+
+            for &b in &buf[0..n] {
+                self.partial.push(b);
+
+                if self.partial.len() < 2 {
+                    continue;
+                }
+
+                let raw = [self.partial[0], self.partial[1]];
+
+                self.partial.clear();
+
+                match Message::decode(raw) {
+                    Some(Message::Data(b)) => self.rx.push(b),
+                    Some(Message::Lines(dtr, rts)) => {
+                        self.remote_dtr = dtr;
+                        self.remote_rts = rts;
+                    }
+                    None => warn!(target: logging::SIO1,
+                                  "Invalid link cable message {:?}", raw),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Link for TcpLink {
+    fn send(&mut self, b: u8) {
+        self.send_message(Message::Data(b));
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        self.poll();
+
+        if self.rx.is_empty() {
+            None
+        } else {
+            Some(self.rx.remove(0))
+        }
+    }
+
+    fn set_lines(&mut self, dtr: bool, rts: bool) {
+        self.send_message(Message::Lines(dtr, rts));
+    }
+
+    fn remote_lines(&mut self) -> (bool, bool) {
+        self.poll();
+
+        (self.remote_dtr, self.remote_rts)
+    }
+}
+
+/// Link between two instances running in the same process
+pub struct LocalLink {
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+    remote_dtr: bool,
+    remote_rts: bool,
+    /// Data bytes received while looking for line changes
+    pending: Vec<u8>,
+}
+
+impl LocalLink {
+    /// Create both ends of a cable
+    pub fn pair() -> (LocalLink, LocalLink) {
+        let (tx_a, rx_b) = channel();
+        let (tx_b, rx_a) = channel();
+
+        (LocalLink::new(tx_a, rx_a), LocalLink::new(tx_b, rx_b))
+    }
+
+    fn new(tx: Sender<Message>, rx: Receiver<Message>) -> LocalLink {
+        LocalLink {
+            tx: tx,
+            rx: rx,
+            remote_dtr: false,
+            remote_rts: false,
+            pending: Vec::new(),
+        }
+    }
+
+    fn poll(&mut self) {
+        while let Ok(m) = self.rx.try_recv() {
+            match m {
+                Message::Data(b) => self.pending.push(b),
+                Message::Lines(dtr, rts) => {
+                    self.remote_dtr = dtr;
+                    self.remote_rts = rts;
+                }
+            }
+        }
+    }
+}
+
+impl Link for LocalLink {
+    fn send(&mut self, b: u8) {
+        // If the other end is gone the byte is lost, like with a real
+        // unplugged cable
+        let _ = self.tx.send(Message::Data(b));
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        self.poll();
+
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(self.pending.remove(0))
+        }
+    }
+
+    fn set_lines(&mut self, dtr: bool, rts: bool) {
+        let _ = self.tx.send(Message::Lines(dtr, rts));
+    }
+
+    fn remote_lines(&mut self) -> (bool, bool) {
+        self.poll();
+
+        (self.remote_dtr, self.remote_rts)
+    }
+}
+
+#[test]
+fn local_link() {
+    let (mut a, mut b) = LocalLink::pair();
+
+    a.set_lines(true, false);
+    a.send(0x42);
+    a.send(0x13);
+
+    assert_eq!(b.remote_lines(), (true, false));
+    assert_eq!(b.recv(), Some(0x42));
+    assert_eq!(b.recv(), Some(0x13));
+    assert_eq!(b.recv(), None);
+    assert_eq!(a.recv(), None);
+}
@@ -0,0 +1,412 @@
+//! Serial port 1 (SIO1) emulation.
+//!
+//! SIO1 is a simple UART available on the serial port at the back of
+//! the console. It's mainly used by link cable multiplayer games. The
+//! other end of the cable is abstracted by the `Link` trait, see the
+//! `link` module for implementations over TCP or between two
+//! instances in the same process.
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+use memory::Addressable;
+use interrupt::Interrupt;
+use timekeeper::{Peripheral, Cycles};
+use shared::SharedState;
+use logging;
+
+pub mod link;
+
+use self::link::Link;
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Sio1 {
+    /// Baudrate timer reload value
+    baud_reload: u16,
+    /// Mode register: baudrate factor, character length, parity and
+    /// stop bits
+    mode: u16,
+    /// TX enable
+    tx_en: bool,
+    /// Data Terminal Ready output
+    dtr: bool,
+    /// RX enable
+    rx_en: bool,
+    /// Request To Send output
+    rts: bool,
+    /// Number of bytes in the RX FIFO before an interrupt is
+    /// generated (log2)
+    rx_irq_mode: u8,
+    /// Interrupt when the TX is ready
+    tx_irq_en: bool,
+    /// Interrupt when data is received
+    rx_irq_en: bool,
+    /// Interrupt when DSR goes active
+    dsr_irq_en: bool,
+    /// Control register bits we store but don't emulate
+    control_unknown: u16,
+    /// Current interrupt level
+    interrupt: bool,
+    /// Set when a byte was received while the RX FIFO was full
+    rx_overrun: bool,
+    /// RX FIFO
+    rx_fifo: [u8; 8],
+    /// Number of bytes in `rx_fifo`
+    rx_len: u8,
+    /// Byte written by the CPU and waiting to be sent
+    tx_data: Option<u8>,
+    /// Byte currently being shifted out and number of cycles left
+    /// before the transfer completes
+    tx_shift: Option<(u8, Cycles)>,
+    /// Last known state of the remote end's handshake lines
+    dsr: bool,
+    cts: bool,
+    /// Other end of the cable
+    link: LinkSlot,
+}
+
+impl Sio1 {
+    pub fn new() -> Sio1 {
+        Sio1 {
+            baud_reload: 0,
+            mode: 0,
+            tx_en: false,
+            dtr: false,
+            rx_en: false,
+            rts: false,
+            rx_irq_mode: 0,
+            tx_irq_en: false,
+            rx_irq_en: false,
+            dsr_irq_en: false,
+            control_unknown: 0,
+            interrupt: false,
+            rx_overrun: false,
+            rx_fifo: [0; 8],
+            rx_len: 0,
+            tx_data: None,
+            tx_shift: None,
+            dsr: false,
+            cts: false,
+            link: LinkSlot(None),
+        }
+    }
+
+    /// Reset the UART, the link (if any) remains connected
+    pub fn reset(&mut self) {
+        let link = self.disconnect();
+
+        *self = Sio1::new();
+
+        self.link = LinkSlot(link);
+    }
+
+    /// Plug a link cable into the serial port
+    pub fn connect(&mut self, link: Box<Link>) {
+        self.link = LinkSlot(Some(link));
+
+        self.update_lines();
+    }
+
+    /// Unplug the link cable, returning it
+    pub fn disconnect(&mut self) -> Option<Box<Link>> {
+        self.link.0.take()
+    }
+
+    pub fn load<A: Addressable>(&mut self,
+                                shared: &mut SharedState,
+                                offset: u32) -> u32 {
+        self.sync(shared);
+
+        match offset {
+            0 => self.pop_rx() as u32,
+            4 => self.stat(),
+            8 => self.mode as u32,
+            10 => self.control() as u32,
+            14 => self.baud_reload as u32,
+            _ => {
+                warn!(target: logging::SIO1,
+                      "Unhandled SIO1 read {:?} 0x{:x}", A::size(), offset);
+                0
+            }
+        }
+    }
+
+    pub fn store<A: Addressable>(&mut self,
+                                 shared: &mut SharedState,
+                                 offset: u32,
+                                 val: u32) {
+        self.sync(shared);
+
+        match offset {
+            0 => self.tx_data = Some(val as u8),
+            8 => self.mode = val as u16,
+            10 => self.set_control(shared, val as u16),
+            14 => self.baud_reload = val as u16,
+            _ => warn!(target: logging::SIO1,
+                       "Unhandled SIO1 write {:?} 0x{:x}: {:x}",
+                       A::size(), offset, val),
+        }
+
+        self.maybe_start_tx();
+        self.predict_next_sync(shared);
+    }
+
+    pub fn sync(&mut self, shared: &mut SharedState) {
+        let delta = shared.tk().sync(Peripheral::Sio1);
+
+        if let Some((b, delay)) = self.tx_shift {
+            if delta < delay {
+                self.tx_shift = Some((b, delay - delta));
+            } else {
+                self.tx_shift = None;
+
+                if let Some(ref mut link) = self.link.0 {
+                    link.send(b);
+                }
+
+                self.maybe_start_tx();
+
+                if self.tx_irq_en && self.tx_ready() {
+                    self.set_interrupt(shared);
+                }
+            }
+        }
+
+        self.poll_link(shared);
+
+        self.predict_next_sync(shared);
+    }
+
+    /// Fetch the incoming bytes and line changes from the link
+    fn poll_link(&mut self, shared: &mut SharedState) {
+        let prev_dsr = self.dsr;
+
+        if let Some(ref mut link) = self.link.0 {
+            let (dsr, cts) = link.remote_lines();
+
+            self.dsr = dsr;
+            self.cts = cts;
+        } else {
+            self.dsr = false;
+            self.cts = false;
+        }
+
+        if self.dsr_irq_en && self.dsr && !prev_dsr {
+            self.set_interrupt(shared);
+        }
+
+        loop {
+            let b =
+                match self.link.0 {
+                    Some(ref mut link) => match link.recv() {
+                        Some(b) => b,
+                        None => break,
+                    },
+                    None => break,
+                };
+
+            if !self.rx_en {
+                // Receiver disabled, drop the byte
+                continue;
+            }
+
+            if self.rx_len as usize == self.rx_fifo.len() {
+                self.rx_overrun = true;
+            } else {
+                self.rx_fifo[self.rx_len as usize] = b;
+                self.rx_len += 1;
+            }
+
+            if self.rx_irq_en && self.rx_len >= (1 << self.rx_irq_mode) {
+                self.set_interrupt(shared);
+            }
+        }
+    }
+
+    fn predict_next_sync(&mut self, shared: &mut SharedState) {
+        match self.tx_shift {
+            Some((_, delay)) =>
+                shared.tk().set_next_sync_delta(Peripheral::Sio1, delay),
+            None if self.link.0.is_some() => {
+                // We have to poll the link for incoming data, there's
+                // no point in doing it more often than the time it
+                // takes to transfer a single character.
+                let period = self.char_cycles();
+
+                shared.tk().set_next_sync_delta(Peripheral::Sio1, period)
+            }
+            None => shared.tk().no_sync_needed(Peripheral::Sio1),
+        }
+    }
+
+    /// Start sending the next byte if the conditions are met
+    fn maybe_start_tx(&mut self) {
+        if self.tx_shift.is_some() || !self.tx_en || !self.cts {
+            return;
+        }
+
+        if let Some(b) = self.tx_data.take() {
+            let duration = self.char_cycles();
+
+            self.tx_shift = Some((b, duration));
+        }
+    }
+
+    /// Pop a byte from the RX FIFO
+    fn pop_rx(&mut self) -> u8 {
+        if self.rx_len == 0 {
+            return 0;
+        }
+
+        let b = self.rx_fifo[0];
+
+        for i in 1..self.rx_len as usize {
+            self.rx_fifo[i - 1] = self.rx_fifo[i];
+        }
+
+        self.rx_len -= 1;
+
+        b
+    }
+
+    /// True if a new byte can be written in the TX register
+    fn tx_ready(&self) -> bool {
+        self.tx_data.is_none()
+    }
+
+    /// Duration of a single bit in CPU cycles
+    fn bit_cycles(&self) -> Cycles {
+        let factor =
+            match self.mode & 3 {
+                // 0 is supposed to stop the baudrate generator
+                0 | 1 => 1,
+                2 => 16,
+                _ => 64,
+            };
+
+        let period = (self.baud_reload as Cycles * factor) & !1;
+
+        if period < factor {
+            factor
+        } else {
+            period
+        }
+    }
+
+    /// Duration of a full character (start bit, data, parity and
+    /// stop bits) in CPU cycles
+    fn char_cycles(&self) -> Cycles {
+        let data_bits = 5 + ((self.mode >> 2) & 3) as Cycles;
+        let parity_bits = ((self.mode >> 4) & 1) as Cycles;
+        // 1.5 stop bits is rounded up
+        let stop_bits =
+            match (self.mode >> 6) & 3 {
+                3 => 2,
+                2 => 2,
+                _ => 1,
+            };
+
+        (1 + data_bits + parity_bits + stop_bits) * self.bit_cycles()
+    }
+
+    fn stat(&self) -> u32 {
+        let mut r = 0u32;
+
+        r |= self.tx_ready() as u32;
+        r |= ((self.rx_len > 0) as u32) << 1;
+        r |= ((self.tx_shift.is_none() && self.tx_data.is_none()) as u32)
+            << 2;
+        r |= (self.rx_overrun as u32) << 4;
+        r |= (self.dsr as u32) << 7;
+        r |= (self.cts as u32) << 8;
+        r |= (self.interrupt as u32) << 9;
+
+        r
+    }
+
+    fn control(&self) -> u16 {
+        let mut r = self.control_unknown;
+
+        r |= self.tx_en as u16;
+        r |= (self.dtr as u16) << 1;
+        r |= (self.rx_en as u16) << 2;
+        r |= (self.rts as u16) << 5;
+        r |= (self.rx_irq_mode as u16) << 8;
+        r |= (self.tx_irq_en as u16) << 10;
+        r |= (self.rx_irq_en as u16) << 11;
+        r |= (self.dsr_irq_en as u16) << 12;
+
+        r
+    }
+
+    fn set_control(&mut self, shared: &mut SharedState, ctrl: u16) {
+        if ctrl & 0x40 != 0 {
+            // Reset
+            let link = self.disconnect();
+            let baud_reload = self.baud_reload;
+
+            *self = Sio1::new();
+
+            self.baud_reload = baud_reload;
+            self.link = LinkSlot(link);
+        } else {
+            if ctrl & 0x10 != 0 {
+                // Acknowledge
+                self.interrupt = false;
+                self.rx_overrun = false;
+            }
+
+            self.tx_en = ctrl & 1 != 0;
+            self.dtr = ctrl & 2 != 0;
+            self.rx_en = ctrl & 4 != 0;
+            self.rts = ctrl & 0x20 != 0;
+            self.rx_irq_mode = ((ctrl >> 8) & 3) as u8;
+            self.tx_irq_en = ctrl & 0x400 != 0;
+            self.rx_irq_en = ctrl & 0x800 != 0;
+            self.dsr_irq_en = ctrl & 0x1000 != 0;
+            self.control_unknown = ctrl & 0xe008;
+        }
+
+        self.update_lines();
+
+        if self.tx_irq_en && self.tx_ready() {
+            self.set_interrupt(shared);
+        }
+    }
+
+    /// Send our handshake lines to the other end of the cable
+    fn update_lines(&mut self) {
+        let (dtr, rts) = (self.dtr, self.rts);
+
+        if let Some(ref mut link) = self.link.0 {
+            link.set_lines(dtr, rts);
+        }
+    }
+
+    fn set_interrupt(&mut self, shared: &mut SharedState) {
+        if !self.interrupt {
+            // Rising edge of the interrupt
+            shared.irq_state_mut().assert(Interrupt::Sio);
+        }
+
+        self.interrupt = true;
+    }
+}
+
+/// Wrapper around the link so that `Sio1` can be serialized. The
+/// link itself is not part of the savestate, it's disconnected when
+/// the state is loaded and has to be reconnected by the frontend.
+struct LinkSlot(Option<Box<Link>>);
+
+impl Encodable for LinkSlot {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_nil()
+    }
+}
+
+impl Decodable for LinkSlot {
+    fn decode<D: Decoder>(d: &mut D) -> Result<LinkSlot, D::Error> {
+        try!(d.read_nil());
+
+        Ok(LinkSlot(None))
+    }
+}
@@ -1,6 +1,7 @@
 use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
 use memory::Addressable;
+use logging;
 
 /// Sound Processing Unit
 pub struct Spu {
@@ -215,7 +216,8 @@ impl Spu {
         // XXX handle FIFO overflow?
         let index = self.ram_index;
 
-        debug!("SPU RAM store {:05x}: {:04x}", index, val);
+        debug!(target: logging::SPU,
+               "SPU RAM store {:05x}: {:04x}", index, val);
 
         self.ram[index as usize] = val;
         self.ram_index = (index + 1) & 0x3ffff;
@@ -0,0 +1,78 @@
+//! Per-frame performance statistics.
+//!
+//! The counters are accumulated during each emulated frame and
+//! reported through `SharedState::last_frame_stats` and the
+//! `on_frame_complete` callback. Frontends can display them as an
+//! on-screen HUD using `FrameStats::hud`, adding the host side frame
+//! time they measure themselves (the core doesn't look at the host
+//! clock).
+
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Default, Debug, RustcDecodable, RustcEncodable)]
+pub struct FrameStats {
+    /// Number of drawing commands (polygons, lines, rectangles and
+    /// fills) received by the GPU
+    pub draw_calls: u32,
+    /// Number of triangles drawn, quads count for two
+    pub triangles: u32,
+    /// Number of line segments drawn
+    pub lines: u32,
+    /// Number of rectangles (sprites) drawn
+    pub rects: u32,
+    /// Number of 32bit words uploaded to the VRAM by image loads
+    pub vram_upload_words: u32,
+    /// Number of 32bit words moved by the DMA
+    pub dma_words: u32,
+    /// Number of CPU instructions executed
+    pub cpu_instructions: u32,
+    /// Number of interrupts taken by the CPU
+    pub interrupts: u32,
+}
+
+impl FrameStats {
+    pub fn new() -> FrameStats {
+        FrameStats::default()
+    }
+
+    /// Add the counters in `other` to ours
+    pub fn merge(&mut self, other: &FrameStats) {
+        self.draw_calls += other.draw_calls;
+        self.triangles += other.triangles;
+        self.lines += other.lines;
+        self.rects += other.rects;
+        self.vram_upload_words += other.vram_upload_words;
+        self.dma_words += other.dma_words;
+        self.cpu_instructions += other.cpu_instructions;
+        self.interrupts += other.interrupts;
+    }
+
+    /// Return the HUD text for these statistics, one item per line.
+    /// `host_frame_time` is the time the frontend spent emulating
+    /// and rendering the frame, if it's measured.
+    pub fn hud(&self, host_frame_time: Option<Duration>) -> String {
+        let mut hud = self.to_string();
+
+        if let Some(t) = host_frame_time {
+            let ms = t.as_secs() as f64 * 1000. +
+                     t.subsec_nanos() as f64 / 1_000_000.;
+
+            hud.push_str(&format!("\nHost: {:.2}ms", ms));
+        }
+
+        hud
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "Draw calls: {}", self.draw_calls));
+        try!(writeln!(f, "Triangles: {} Lines: {} Rects: {}",
+                      self.triangles, self.lines, self.rects));
+        try!(writeln!(f, "VRAM upload: {}B", self.vram_upload_words * 4));
+        try!(writeln!(f, "DMA: {} words", self.dma_words));
+        write!(f, "CPU: {} instructions, {} IRQs",
+               self.cpu_instructions, self.interrupts)
+    }
+}
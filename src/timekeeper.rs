@@ -19,6 +19,10 @@ pub enum Peripheral {
     PadMemCard,
     /// CD-ROM controller
     CdRom,
+    /// DMA controller (chopped transfers)
+    Dma,
+    /// Serial port 1
+    Sio1,
 }
 
 
@@ -31,7 +35,14 @@ pub struct TimeKeeper {
     /// Next time a peripheral needs an update
     next_sync: Cycles,
     /// Time sheets for keeping track of the various peripherals
-    timesheets: [TimeSheet; 6],
+    timesheets: [TimeSheet; 8],
+    /// CPU clock multiplier in 16.16 fixed point. The CPU timings are
+    /// divided by this ratio while the peripherals keep running at
+    /// their normal speed.
+    cpu_clock_ratio: u32,
+    /// Fractional part of the CPU cycles accumulated when the ratio
+    /// is not 1, in 1/0x10000th of a cycle
+    cpu_frac: u32,
 }
 
 impl TimeKeeper {
@@ -40,7 +51,9 @@ impl TimeKeeper {
             now: 0,
             // Force a sync at the start to initialize evrything
             next_sync: 0,
-            timesheets: [TimeSheet::new(); 6],
+            timesheets: [TimeSheet::new(); 8],
+            cpu_clock_ratio: CPU_CLOCK_RATIO_ONE,
+            cpu_frac: 0,
         }
     }
 
@@ -52,6 +65,53 @@ impl TimeKeeper {
         self.now += cycles;
     }
 
+    /// Advance time by `cycles` CPU cycles, scaled by the CPU clock
+    /// multiplier
+    pub fn cpu_tick(&mut self, cycles: Cycles) {
+        if self.cpu_clock_ratio == CPU_CLOCK_RATIO_ONE {
+            self.now += cycles;
+            return;
+        }
+
+        let elapsed = (cycles << 32) / self.cpu_clock_ratio as Cycles
+            + self.cpu_frac as Cycles;
+
+        self.now += elapsed >> 16;
+        self.cpu_frac = (elapsed & 0xffff) as u32;
+    }
+
+    /// Convert a duration in CPU cycles to the peripheral clock,
+    /// scaled by the CPU clock multiplier. The fractional part is
+    /// discarded.
+    pub fn cpu_cycles(&self, cycles: Cycles) -> Cycles {
+        (cycles << 16) / self.cpu_clock_ratio as Cycles
+    }
+
+    /// Return the CPU clock multiplier
+    pub fn cpu_clock_ratio(&self) -> f32 {
+        self.cpu_clock_ratio as f32 / CPU_CLOCK_RATIO_ONE as f32
+    }
+
+    /// Overclock (`ratio` > 1.0) or underclock (`ratio` < 1.0) the
+    /// CPU. The ratio is clamped to the range [0.25, 8.0], NaN and
+    /// infinite values reset it to 1.0. Can be changed at any time.
+    pub fn set_cpu_clock_ratio(&mut self, ratio: f32) {
+        let ratio =
+            if !ratio.is_finite() {
+                1.
+            } else if ratio < 0.25 {
+                0.25
+            } else if ratio > 8. {
+                8.
+            } else {
+                ratio
+            };
+
+        self.cpu_clock_ratio =
+            (ratio * CPU_CLOCK_RATIO_ONE as f32).round() as u32;
+        self.cpu_frac = 0;
+    }
+
     /// Synchronize the timesheet for the given peripheral and return
     /// the elapsed time synce the last sync.
     pub fn sync(&mut self, who: Peripheral) -> Cycles {
@@ -101,6 +161,20 @@ impl TimeKeeper {
         self.timesheets[who as usize].set_next_sync(Cycles::max_value());
     }
 
+    /// Mark all peripherals as freshly synchronized and force a sync
+    /// at the next step so that they can reschedule their events.
+    /// Used when the peripherals are reset.
+    pub fn reset_timesheets(&mut self) {
+        let now = self.now;
+
+        for t in self.timesheets.iter_mut() {
+            t.sync(now);
+            t.set_next_sync(now);
+        }
+
+        self.next_sync = now;
+    }
+
     pub fn sync_pending(&self) -> bool{
         self.next_sync <= self.now
     }
@@ -171,6 +245,9 @@ impl TimeSheet {
     }
 }
 
+/// Native CPU clock ratio in 16.16 fixed point
+const CPU_CLOCK_RATIO_ONE: u32 = 0x10000;
+
 /// 64bit timestamps will wrap in roughly 17271 years with a CPU clock
 /// at 33.8685MHz so it should be plenty enough.
 pub type Cycles = u64;
@@ -2,6 +2,8 @@
 //! traces
 
 use std::collections::HashMap;
+#[cfg(feature = "trace")]
+use std::cell::RefCell;
 
 pub type ValueType  = u32;
 pub type ValueSize  = u8;
@@ -133,19 +135,18 @@ impl Tracer {
     }
 }
 
-/// Global logger instance
+// Logger instance. There's one per thread so that several emulator
+// instances running in parallel don't mix their traces, which means
+// that `remove_trace` must be called from the thread running the
+// emulator.
 #[cfg(feature = "trace")]
-lazy_static! {
-    static ref LOGGER: ::std::sync::Mutex<Tracer> = {
-        ::std::sync::Mutex::new(Tracer::new())
-    };
+thread_local! {
+    static LOGGER: RefCell<Tracer> = RefCell::new(Tracer::new())
 }
 
 #[cfg(feature = "trace")]
 pub fn remove_trace() -> HashMap<&'static str, Module> {
-    let mut logger = LOGGER.lock().unwrap();
-
-    logger.remove_trace()
+    LOGGER.with(|logger| logger.borrow_mut().remove_trace())
 }
 
 #[cfg(not(feature = "trace"))]
@@ -157,11 +158,11 @@ pub fn remove_trace() -> HashMap<&'static str, Module> {
 pub fn module_tracer<F>(name: &'static str, f: F)
     where F: FnOnce(&mut Module) {
 
-    let mut logger = LOGGER.lock().unwrap();
-
-    let module = logger.module_mut(name);
+    LOGGER.with(|logger| {
+        let mut logger = logger.borrow_mut();
 
-    f(module);
+        f(logger.module_mut(name));
+    })
 }
 
 #[cfg(not(feature = "trace"))]
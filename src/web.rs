@@ -0,0 +1,223 @@
+//! Minimal web frontend layer.
+//!
+//! Glue between the emulator and the browser APIs, kept free of any
+//! JavaScript binding so that it can be wrapped by `wasm-bindgen`,
+//! `stdweb` or plain `extern "C"` exports (see the `web` example):
+//!
+//! * The video output is drawn by the `SoftwareRenderer` and
+//!   converted to the RGBA layout expected by a canvas `ImageData`.
+//! * The audio is resampled to the rate of the `AudioContext` and
+//!   returned as interleaved `f32` samples, ready to be copied into
+//!   an `AudioBuffer`.
+//! * The keyboard is mapped to a digital pad in port 1 using the DOM
+//!   `KeyboardEvent.code` values.
+//!
+//! Browsers don't give access to the filesystem so the BIOS and disc
+//! must be provided by the page, see the `host` module.
+
+use audio::{SPU_SAMPLE_RATE, Frame};
+use audio::resampler::AudioOutput;
+use bios::Bios;
+use cdrom::disc::Disc;
+use gpu::VideoClock;
+use gpu::software::SoftwareRenderer;
+use host::FixedClock;
+use machine::Machine;
+use padmemcard::gamepad::{Button, ButtonState, DigitalProfile};
+
+/// Audio latency in milliseconds
+const AUDIO_LATENCY: u32 = 100;
+
+pub struct WebFrontend {
+    machine: Machine<(), SoftwareRenderer>,
+    /// Resampler from the SPU rate to the `AudioContext` rate
+    output: AudioOutput,
+    /// Number of SPU samples generated per video frame
+    frame_samples: usize,
+    /// Current picture as `0x00RRGGBB` pixels
+    pixels: Vec<u32>,
+    /// Current picture in the canvas layout
+    rgba: Vec<u8>,
+    /// Resolution of the current picture
+    resolution: (u16, u16),
+    /// Interleaved stereo samples for the current frame
+    audio: Vec<f32>,
+}
+
+impl WebFrontend {
+    /// Create a frontend playing the audio at `sample_rate`Hz (the
+    /// `sampleRate` of the page's `AudioContext`)
+    pub fn new(bios: Bios,
+               disc: Option<Disc>,
+               sample_rate: u32) -> WebFrontend {
+        let video_clock = bios.video_clock();
+
+        let mut machine = Machine::new(bios,
+                                       video_clock,
+                                       disc,
+                                       (),
+                                       SoftwareRenderer::new());
+
+        machine.cpu_mut()
+            .interconnect_mut()
+            .pad_memcard_mut()
+            .gamepad_mut(0, 0)
+            .set_profile(Box::new(DigitalProfile::new()));
+
+        let fps =
+            match video_clock {
+                VideoClock::Ntsc => 60,
+                VideoClock::Pal => 50,
+            };
+
+        WebFrontend {
+            machine: machine,
+            output: AudioOutput::new(sample_rate, AUDIO_LATENCY),
+            frame_samples: (SPU_SAMPLE_RATE / fps) as usize,
+            pixels: Vec::new(),
+            rgba: Vec::new(),
+            resolution: (0, 0),
+            audio: Vec::new(),
+        }
+    }
+
+    /// Set the date seen by the emulated RTC, `unix_time` is usually
+    /// `Date.now() / 1000`. The browser has no system clock usable by
+    /// the core.
+    pub fn set_date(&mut self, unix_time: u64) {
+        self.machine.set_host_clock(Box::new(FixedClock(unix_time)));
+    }
+
+    /// Run the emulator for one frame, meant to be called from the
+    /// `requestAnimationFrame` callback
+    pub fn run_frame(&mut self) {
+        self.machine.run_frame();
+
+        self.resolution = self.machine.renderer().frame(&mut self.pixels);
+
+        self.rgba.clear();
+
+        for &p in &self.pixels {
+            self.rgba.extend_from_slice(&[(p >> 16) as u8,
+                                          (p >> 8) as u8,
+                                          p as u8,
+                                          0xff]);
+        }
+
+        // The SPU doesn't produce any sound yet, send silence
+        let silence = vec![(0, 0); self.frame_samples];
+
+        self.output.push(&silence);
+
+        let mut frames: Vec<Frame> = vec![(0, 0); self.frame_samples];
+        let n = self.output.pull(&mut frames);
+
+        self.audio.clear();
+
+        for &(l, r) in &frames[..n] {
+            self.audio.push(l as f32 / 32768.);
+            self.audio.push(r as f32 / 32768.);
+        }
+    }
+
+    /// Return the resolution of the current picture
+    pub fn resolution(&self) -> (u16, u16) {
+        self.resolution
+    }
+
+    /// Return the current picture, 4 bytes per pixel (R, G, B, A)
+    /// line by line
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    /// Return the audio generated by the last frame, interleaved
+    /// stereo samples between -1.0 and 1.0
+    pub fn audio(&self) -> &[f32] {
+        &self.audio
+    }
+
+    /// Handle a `keydown` or `keyup` event. `code` is the
+    /// `KeyboardEvent.code` value. Returns false if the key isn't
+    /// mapped, in which case the page shouldn't prevent the default
+    /// action.
+    pub fn key_event(&mut self, code: &str, pressed: bool) -> bool {
+        if code == "F5" {
+            self.machine.set_reset_button(pressed);
+            return true;
+        }
+
+        let button =
+            match key_button(code) {
+                Some(b) => b,
+                None => return false,
+            };
+
+        let state =
+            if pressed {
+                ButtonState::Pressed
+            } else {
+                ButtonState::Released
+            };
+
+        self.machine.cpu_mut()
+            .interconnect_mut()
+            .pad_memcard_mut()
+            .gamepad_mut(0, 0)
+            .set_button_state(button, state);
+
+        true
+    }
+
+    pub fn machine(&self) -> &Machine<(), SoftwareRenderer> {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine<(), SoftwareRenderer> {
+        &mut self.machine
+    }
+}
+
+/// Same mapping as the SDL2 example
+fn key_button(code: &str) -> Option<Button> {
+    let b =
+        match code {
+            "ArrowUp" => Button::DUp,
+            "ArrowDown" => Button::DDown,
+            "ArrowLeft" => Button::DLeft,
+            "ArrowRight" => Button::DRight,
+            "KeyX" => Button::Cross,
+            "KeyC" => Button::Circle,
+            "KeyS" => Button::Square,
+            "KeyD" => Button::Triangle,
+            "KeyQ" => Button::L1,
+            "KeyW" => Button::R1,
+            "KeyA" => Button::L2,
+            "KeyE" => Button::R2,
+            "Enter" => Button::Start,
+            "Backspace" => Button::Select,
+            _ => return None,
+        };
+
+    Some(b)
+}
+
+#[test]
+fn web_frame() {
+    let mut web = WebFrontend::new(Bios::dummy(), None, 48_000);
+
+    assert!(web.key_event("ArrowUp", true));
+    assert!(!web.key_event("KeyZ", true));
+
+    web.run_frame();
+
+    let (width, height) = web.resolution();
+
+    assert_eq!(web.rgba().len(), width as usize * height as usize * 4);
+    assert!(web.rgba().chunks(4).all(|p| p[3] == 0xff));
+
+    // Interleaved stereo samples
+    assert_eq!(web.audio().len() % 2, 0);
+    assert!(web.audio().len() <= 2 * web.frame_samples);
+    assert!(web.audio().iter().all(|&s| s == 0.));
+}
@@ -0,0 +1,177 @@
+//! Integration test harness running amidog's CPU and GTE test
+//! executables and checking their TTY output.
+//!
+//! The tests need the test executables which can't be distributed
+//! with rustation, so they're ignored by default. Run them with:
+//!
+//! ```text
+//! RUSTATION_BIOS=/path/to/SCPH1001.BIN \
+//! RUSTATION_AMIDOG=/path/to/amidog/tests \
+//! cargo test --test amidog -- --ignored
+//! ```
+//!
+//! Each test runs until the executable prints its completion message
+//! or the frame budget (`RUSTATION_TEST_FRAMES`, 7200 by default) is
+//! exhausted. Any line containing "FAIL" or "ERROR" fails the test.
+//!
+//! If `RUSTATION_BIOS` isn't set the executables run on top of the
+//! built-in kernel shim (`Bios::shim`) instead of a real BIOS. The
+//! shim's `printf` doesn't expand the conversions so the output is
+//! less readable but the tests run without any copyrighted file.
+
+extern crate rustation;
+
+use std::env;
+use std::path::PathBuf;
+
+use rustation::bios::Bios;
+use rustation::cpu::Cpu;
+use rustation::debugger::Debugger;
+use rustation::debugger::tty::TtyCapture;
+use rustation::gpu::renderer::{Renderer, PrimitiveAttributes, Vertex};
+use rustation::machine::Machine;
+use rustation::parallel_io::exe_loader::ExeLoader;
+
+/// Debugger capturing the TTY output
+struct Harness {
+    tty: TtyCapture,
+}
+
+impl Debugger for Harness {
+    fn trigger_break(&mut self) {
+    }
+
+    fn pc_change(&mut self, cpu: &mut Cpu) {
+        self.tty.pc_change(cpu);
+    }
+
+    fn memory_read(&mut self, _: &mut Cpu, _: u32) {
+    }
+
+    fn memory_write(&mut self, _: &mut Cpu, _: u32) {
+    }
+}
+
+/// Renderer discarding everything, we only care about the TTY
+struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn set_draw_offset(&mut self, _: i16, _: i16) {
+    }
+
+    fn set_draw_area(&mut self, _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn set_display_mode(&mut self, _: (u16, u16), _: (u16, u16), _: bool) {
+    }
+
+    fn push_line(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 2]) {
+    }
+
+    fn push_triangle(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 3]) {
+    }
+
+    fn push_quad(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 4]) {
+    }
+
+    fn fill_rect(&mut self, _: [u8; 3], _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn load_image(&mut self, _: (u16, u16), _: (u16, u16), _: &[u16]) {
+    }
+
+    fn copy_rect(&mut self, _: (u16, u16), _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn store_image(&mut self,
+                   _: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]) {
+        let len = dimensions.0 as usize * dimensions.1 as usize;
+
+        for p in pixel_buffer[..len].iter_mut() {
+            *p = 0;
+        }
+    }
+}
+
+fn env_path(var: &str) -> PathBuf {
+    match env::var_os(var) {
+        Some(p) => PathBuf::from(p),
+        None => panic!("{} is not set", var),
+    }
+}
+
+/// Sideload `exe` and run it until it prints `done_marker`. Returns
+/// the complete TTY output.
+fn run_test(exe: &str, done_marker: &str) -> String {
+    let mut bios =
+        match env::var_os("RUSTATION_BIOS") {
+            Some(p) => Bios::load_file(&PathBuf::from(p)).unwrap(),
+            None => Bios::shim(),
+        };
+
+    let exe = env_path("RUSTATION_AMIDOG").join(exe);
+
+    let loader = ExeLoader::load_file(&exe).unwrap();
+
+    loader.patch_bios(&mut bios).unwrap();
+
+    let frames =
+        env::var("RUSTATION_TEST_FRAMES").ok()
+        .and_then(|f| f.parse::<u32>().ok())
+        .unwrap_or(7200);
+
+    let video_clock = bios.video_clock();
+
+    let harness = Harness { tty: TtyCapture::new() };
+
+    let mut machine = Machine::new(bios,
+                                   video_clock,
+                                   None,
+                                   harness,
+                                   NullRenderer);
+
+    machine.cpu_mut()
+        .interconnect_mut()
+        .parallel_io_mut()
+        .set_module(Box::new(loader));
+
+    for _ in 0..frames {
+        machine.run_frame();
+
+        for line in machine.debugger_mut().tty.take_lines() {
+            println!("{}", line);
+
+            let upper = line.to_uppercase();
+
+            if upper.contains("FAIL") || upper.contains("ERROR") {
+                panic!("Test failure: {}", line);
+            }
+
+            if line.contains(done_marker) {
+                return machine.debugger().tty.output().into();
+            }
+        }
+    }
+
+    panic!("{} didn't complete after {} frames", exe.display(), frames);
+}
+
+#[test]
+#[ignore]
+fn amidog_cpu() {
+    run_test("psxtest_cpu.exe", "Done");
+}
+
+#[test]
+#[ignore]
+fn amidog_cpx() {
+    run_test("psxtest_cpx.exe", "Done");
+}
+
+#[test]
+#[ignore]
+fn amidog_gte() {
+    run_test("psxtest_gte.exe", "Done");
+}
@@ -0,0 +1,179 @@
+//! Golden trace scenario: boot the BIOS (optionally sideloading an
+//! executable), sample the CPU state at a fixed interval and compare
+//! the result with a trace captured from a reference emulator.
+//!
+//! Needs a BIOS image so it's ignored by default:
+//!
+//! ```text
+//! RUSTATION_BIOS=/path/to/SCPH1001.BIN \
+//! RUSTATION_GOLDEN_REF=/path/to/reference.trace \
+//! cargo test --test golden -- --ignored
+//! ```
+//!
+//! Optional variables:
+//!
+//! * `RUSTATION_GOLDEN_EXE`: executable to sideload
+//! * `RUSTATION_GOLDEN_FRAMES`: number of frames to run (60)
+//! * `RUSTATION_GOLDEN_INTERVAL`: instructions between samples (1000)
+//! * `RUSTATION_GOLDEN_OUT`: where to write the generated trace
+//!
+//! Without `RUSTATION_GOLDEN_REF` the trace is only generated. If the
+//! reference is shorter than the generated trace only the common part
+//! is compared.
+
+extern crate rustation;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use rustation::bios::Bios;
+use rustation::cpu::Cpu;
+use rustation::debugger::Debugger;
+use rustation::debugger::golden::{GoldenTrace, parse_trace, first_divergence};
+use rustation::gpu::renderer::{Renderer, PrimitiveAttributes, Vertex};
+use rustation::machine::Machine;
+use rustation::parallel_io::exe_loader::ExeLoader;
+
+struct Harness {
+    trace: GoldenTrace,
+}
+
+impl Debugger for Harness {
+    fn trigger_break(&mut self) {
+    }
+
+    fn pc_change(&mut self, cpu: &mut Cpu) {
+        self.trace.pc_change(cpu);
+    }
+
+    fn memory_read(&mut self, _: &mut Cpu, _: u32) {
+    }
+
+    fn memory_write(&mut self, _: &mut Cpu, _: u32) {
+    }
+}
+
+/// Renderer discarding everything, only the CPU state is traced
+struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn set_draw_offset(&mut self, _: i16, _: i16) {
+    }
+
+    fn set_draw_area(&mut self, _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn set_display_mode(&mut self, _: (u16, u16), _: (u16, u16), _: bool) {
+    }
+
+    fn push_line(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 2]) {
+    }
+
+    fn push_triangle(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 3]) {
+    }
+
+    fn push_quad(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 4]) {
+    }
+
+    fn fill_rect(&mut self, _: [u8; 3], _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn load_image(&mut self, _: (u16, u16), _: (u16, u16), _: &[u16]) {
+    }
+
+    fn copy_rect(&mut self, _: (u16, u16), _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn store_image(&mut self,
+                   _: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]) {
+        let len = dimensions.0 as usize * dimensions.1 as usize;
+
+        for p in pixel_buffer[..len].iter_mut() {
+            *p = 0;
+        }
+    }
+}
+
+fn env_number<T: FromStr>(var: &str, default: T) -> T {
+    env::var(var).ok()
+        .and_then(|v| v.parse::<T>().ok())
+        .unwrap_or(default)
+}
+
+#[test]
+#[ignore]
+fn golden_trace() {
+    let bios_path =
+        match env::var_os("RUSTATION_BIOS") {
+            Some(p) => PathBuf::from(p),
+            None => panic!("RUSTATION_BIOS is not set"),
+        };
+
+    let mut bios = Bios::load_file(&bios_path).unwrap();
+
+    let loader =
+        env::var_os("RUSTATION_GOLDEN_EXE").map(|exe| {
+            let loader = ExeLoader::load_file(&PathBuf::from(exe)).unwrap();
+
+            loader.patch_bios(&mut bios).unwrap();
+
+            loader
+        });
+
+    let frames = env_number("RUSTATION_GOLDEN_FRAMES", 60u32);
+    let interval = env_number("RUSTATION_GOLDEN_INTERVAL", 1000u64);
+
+    let video_clock = bios.video_clock();
+
+    let harness = Harness { trace: GoldenTrace::new(interval) };
+
+    let mut machine = Machine::new(bios,
+                                   video_clock,
+                                   None,
+                                   harness,
+                                   NullRenderer);
+
+    if let Some(loader) = loader {
+        machine.cpu_mut()
+            .interconnect_mut()
+            .parallel_io_mut()
+            .set_module(Box::new(loader));
+    }
+
+    for _ in 0..frames {
+        machine.run_frame();
+    }
+
+    let trace = &machine.debugger().trace;
+
+    if let Some(out) = env::var_os("RUSTATION_GOLDEN_OUT") {
+        let mut f = File::create(PathBuf::from(out)).unwrap();
+
+        trace.write(&mut f).unwrap();
+    }
+
+    if let Some(reference) = env::var_os("RUSTATION_GOLDEN_REF") {
+        let mut s = String::new();
+
+        File::open(PathBuf::from(reference)).unwrap()
+            .read_to_string(&mut s).unwrap();
+
+        let expected = parse_trace(&s).unwrap();
+
+        // The reference may have been captured over a shorter run
+        let mut actual = trace.entries();
+
+        if actual.len() > expected.len() {
+            actual = &actual[..expected.len()];
+        }
+
+        if let Some(d) = first_divergence(&expected, actual) {
+            panic!("{}", d);
+        }
+    }
+}